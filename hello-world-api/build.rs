@@ -0,0 +1,13 @@
+// Generates the gRPC service of `proto/todo.proto`, compiled with a vendored
+// protoc so building doesn't need one installed.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure().build_client(false).compile(
+        &["proto/todo.proto"],
+        &[
+            "proto",
+            &protoc_bin_vendored::include_path()?.to_string_lossy(),
+        ],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,17 @@
+//! The query string of `GET /todos`: its filters, `sort`, `fields`, `ids`
+//! and `after` cursor, turned into the listing's SQL.
+
+#![no_main]
+
+use hello_world_api::{models::ListTodos, repository::todo_query::TodoQuery};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(params) = serde_urlencoded::from_bytes::<ListTodos>(data) else {
+        return;
+    };
+    if let Ok(query) = TodoQuery::try_from(params) {
+        query.build(uuid::Uuid::nil()).sql();
+        query.build_count(uuid::Uuid::nil()).sql();
+    }
+});
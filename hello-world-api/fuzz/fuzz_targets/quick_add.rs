@@ -0,0 +1,13 @@
+//! Quick-add lines, parsed as `POST /todos/quick` does.
+
+#![no_main]
+
+use chrono::{TimeZone, Utc};
+use hello_world_api::fuzz::quick_add;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    // late on a leap day, so that days ahead cross into March
+    let now = Utc.with_ymd_and_hms(2024, 2, 29, 23, 30, 0).unwrap();
+    quick_add(input, now);
+});
@@ -0,0 +1,15 @@
+//! Recurrence rules, in words or as an `RRULE`, and the due dates they
+//! lead to.
+
+#![no_main]
+
+use chrono::{TimeZone, Utc};
+use hello_world_api::fuzz::recurrence;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    if let Some(rule) = recurrence(input) {
+        let from = Utc.with_ymd_and_hms(2024, 1, 31, 9, 0, 0).unwrap();
+        rule.occurrences(from, 5);
+    }
+});
@@ -0,0 +1,16 @@
+//! The bodies of `POST /todos` and `PATCH /todos/{id}`, as the JSON
+//! extractor reads them.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use todo_api_types::{CreateTodo, PatchTodo};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(body) = serde_json::from_slice::<CreateTodo>(data) {
+        serde_json::to_vec(&body).unwrap();
+    }
+    if let Ok(body) = serde_json::from_slice::<PatchTodo>(data) {
+        serde_json::to_vec(&body).unwrap();
+    }
+});
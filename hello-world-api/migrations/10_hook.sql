@@ -0,0 +1,9 @@
+create table "hook"
+(
+    id                uuid primary key default gen_random_uuid(),
+    token_hash        bytea unique not null,
+    text_template     text not null,
+    is_done_path      text,
+    external_id_path  text,
+    created_at        timestamptz not null default now()
+);
@@ -0,0 +1,33 @@
+alter table "todo"
+    add column field_modified jsonb not null default '{}';
+
+-- stamps every API-visible field a statement changes, whichever code path
+-- (API, import, CalDAV, webhooks) the change came through
+create function todo_field_modified() returns trigger as $$
+declare
+    changed text[];
+begin
+    if tg_op = 'INSERT' then
+        changed := array['text', 'is_done', 'location'];
+    else
+        changed := array[]::text[];
+        if new.todo_text is distinct from old.todo_text then
+            changed := array_append(changed, 'text');
+        end if;
+        if new.is_done is distinct from old.is_done then
+            changed := array_append(changed, 'is_done');
+        end if;
+        if (new.latitude, new.longitude, new.radius_m)
+            is distinct from (old.latitude, old.longitude, old.radius_m) then
+            changed := array_append(changed, 'location');
+        end if;
+    end if;
+    new.field_modified := new.field_modified
+        || (select coalesce(jsonb_object_agg(field, now()), '{}') from unnest(changed) as field);
+    return new;
+end;
+$$ language plpgsql;
+
+create trigger todo_field_modified
+    before insert or update on "todo"
+    for each row execute function todo_field_modified();
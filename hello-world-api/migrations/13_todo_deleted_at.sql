@@ -0,0 +1,7 @@
+alter table "todo"
+    add column deleted_at timestamptz;
+
+-- a deleted todo's text can be used again
+alter table "todo"
+    drop constraint todo_todo_text_key;
+create unique index todo_todo_text_key on "todo" (todo_text) where deleted_at is null;
@@ -0,0 +1,8 @@
+-- text-search configuration matching the language detected in todo_text,
+-- 'simple' (no stemming or stop words) when it wasn't recognised
+alter table "todo"
+    add column search_config regconfig not null default 'simple';
+alter table "todo"
+    add column search_vector tsvector
+        generated always as (to_tsvector(search_config, todo_text)) stored;
+create index todo_search_vector on "todo" using gin (search_vector);
@@ -0,0 +1,20 @@
+alter table "user"
+    add column password_hash text,
+    add column created_at timestamptz not null default now();
+
+-- todos from before accounts existed have no owner and are listed to nobody
+alter table "todo"
+    add column user_id uuid references "user" (user_id);
+create index todo_user_id on "todo" (user_id);
+
+-- texts and external ids only have to be unique among one user's todos
+drop index todo_todo_text_key;
+create unique index todo_todo_text_key on "todo" (user_id, todo_text) where deleted_at is null;
+alter table "todo"
+    drop constraint todo_external_id_key;
+alter table "todo"
+    add constraint todo_external_id_key unique (user_id, external_id);
+
+-- hooks from before accounts existed have no owner and accept no deliveries
+alter table "hook"
+    add column user_id uuid references "user" (user_id);
@@ -0,0 +1,13 @@
+alter table "user"
+    add column is_admin boolean not null default false;
+
+create table "audit_log"
+(
+    id          uuid primary key default gen_random_uuid(),
+    at          timestamptz not null default now(),
+    admin_id    uuid not null references "user" (user_id),
+    user_id     uuid not null references "user" (user_id),
+    method      text not null,
+    path        text not null
+);
+create index audit_log_at on "audit_log" (at);
@@ -0,0 +1,17 @@
+-- completions per user and UTC day, read by the stats endpoints instead of
+-- the todo table and refreshed periodically by the server
+create materialized view "todo_daily_completions" as
+select user_id, (completed_at at time zone 'UTC')::date as day, count(*) as completed
+from "todo"
+where completed_at is not null and merged_into is null and deleted_at is null
+group by user_id, day;
+-- refreshing concurrently needs a unique index
+create unique index todo_daily_completions_user_day on "todo_daily_completions" (user_id, day);
+
+-- when each materialized view was last refreshed
+create table "view_refresh"
+(
+    view_name    text primary key,
+    refreshed_at timestamptz not null
+);
+insert into "view_refresh" (view_name, refreshed_at) values ('todo_daily_completions', now());
@@ -0,0 +1,37 @@
+alter table "todo"
+    add column due_at timestamptz;
+
+-- overdue listings and `?sort=due_at` only look at todos with a due date
+create index todo_user_id_due_at on "todo" (user_id, due_at) where due_at is not null;
+
+-- same as in 12_todo_start_at, with due_at stamped as well
+create or replace function todo_field_modified() returns trigger as $$
+declare
+    changed text[];
+begin
+    if tg_op = 'INSERT' then
+        changed := array['text', 'is_done', 'location', 'start_at', 'due_at'];
+    else
+        changed := array[]::text[];
+        if new.todo_text is distinct from old.todo_text then
+            changed := array_append(changed, 'text');
+        end if;
+        if new.is_done is distinct from old.is_done then
+            changed := array_append(changed, 'is_done');
+        end if;
+        if (new.latitude, new.longitude, new.radius_m)
+            is distinct from (old.latitude, old.longitude, old.radius_m) then
+            changed := array_append(changed, 'location');
+        end if;
+        if new.start_at is distinct from old.start_at then
+            changed := array_append(changed, 'start_at');
+        end if;
+        if new.due_at is distinct from old.due_at then
+            changed := array_append(changed, 'due_at');
+        end if;
+    end if;
+    new.field_modified := new.field_modified
+        || (select coalesce(jsonb_object_agg(field, now()), '{}') from unnest(changed) as field);
+    return new;
+end;
+$$ language plpgsql;
@@ -0,0 +1,25 @@
+create table "tag"
+(
+    id          uuid primary key default gen_random_uuid(),
+    user_id     uuid not null references "user" (user_id),
+    name        text not null,
+    unique (user_id, name)
+);
+
+create table "todo_tag"
+(
+    todo_id     uuid not null references "todo" (id),
+    tag_id      uuid not null references "tag" (id),
+    primary key (todo_id, tag_id)
+);
+-- for listing the todos with a tag
+create index todo_tag_tag_id on "todo_tag" (tag_id);
+
+-- a todo's tags as a json array ordered by name, selected alongside the todo
+-- so listings embed them in the same query
+create function todo_tags(todo_id uuid) returns json as $$
+    select coalesce(json_agg(json_build_object('id', g.id, 'name', g.name) order by g.name), '[]')
+    from "todo_tag" tt
+    join "tag" g on g.id = tt.tag_id
+    where tt.todo_id = $1
+$$ language sql stable;
@@ -0,0 +1,42 @@
+alter table "todo"
+    add column expires_at timestamptz,
+    add column expired_at timestamptz;
+
+-- what the expiry job scans for
+create index todo_expires_at on "todo" (expires_at)
+    where expired_at is null and not is_done and merged_into is null and deleted_at is null;
+
+-- same as in 18_todo_due_at, with expires_at stamped as well
+create or replace function todo_field_modified() returns trigger as $$
+declare
+    changed text[];
+begin
+    if tg_op = 'INSERT' then
+        changed := array['text', 'is_done', 'location', 'start_at', 'due_at', 'expires_at'];
+    else
+        changed := array[]::text[];
+        if new.todo_text is distinct from old.todo_text then
+            changed := array_append(changed, 'text');
+        end if;
+        if new.is_done is distinct from old.is_done then
+            changed := array_append(changed, 'is_done');
+        end if;
+        if (new.latitude, new.longitude, new.radius_m)
+            is distinct from (old.latitude, old.longitude, old.radius_m) then
+            changed := array_append(changed, 'location');
+        end if;
+        if new.start_at is distinct from old.start_at then
+            changed := array_append(changed, 'start_at');
+        end if;
+        if new.due_at is distinct from old.due_at then
+            changed := array_append(changed, 'due_at');
+        end if;
+        if new.expires_at is distinct from old.expires_at then
+            changed := array_append(changed, 'expires_at');
+        end if;
+    end if;
+    new.field_modified := new.field_modified
+        || (select coalesce(jsonb_object_agg(field, now()), '{}') from unnest(changed) as field);
+    return new;
+end;
+$$ language plpgsql;
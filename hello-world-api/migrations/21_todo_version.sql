@@ -0,0 +1,16 @@
+alter table "todo"
+    add column version bigint not null default 1;
+
+-- bumped by every statement updating a todo, whichever code path (API,
+-- import, CalDAV, the expiry job) it came through, so a write conditional
+-- on the version fails after any of them
+create function todo_version() returns trigger as $$
+begin
+    new.version := old.version + 1;
+    return new;
+end;
+$$ language plpgsql;
+
+create trigger todo_version
+    before update on "todo"
+    for each row execute function todo_version();
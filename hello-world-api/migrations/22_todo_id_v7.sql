@@ -0,0 +1,6 @@
+-- new todos get a UUIDv7 from the application, so a statement forgetting
+-- the id fails instead of inserting a random one. Existing todos keep their
+-- random ids: API clients and CalDAV hrefs refer to them, so they are
+-- never rewritten. Ordering by id stays total, which is all keyset
+-- pagination needs, it just isn't by creation time for them.
+alter table "todo" alter column id drop default;
@@ -0,0 +1,17 @@
+-- typed links between two todos of the same user, read as "from <kind> to"
+create table "todo_link"
+(
+    id          uuid primary key default gen_random_uuid(),
+    user_id     uuid not null references "user" (user_id),
+    from_id     uuid not null references "todo" (id),
+    to_id       uuid not null references "todo" (id),
+    kind        text not null check (kind in ('relates_to', 'duplicates', 'caused_by')),
+    created_at  timestamptz not null default now(),
+    check (from_id <> to_id),
+    unique (from_id, to_id, kind)
+);
+-- for the links pointing at a todo
+create index todo_link_to_id on "todo_link" (to_id);
+-- relating a todo to another relates the other to it, so only one may exist
+create unique index todo_link_relates_to on "todo_link" (least(from_id, to_id), greatest(from_id, to_id))
+    where kind = 'relates_to';
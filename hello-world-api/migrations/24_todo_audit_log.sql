@@ -0,0 +1,67 @@
+create table "todo_audit_log"
+(
+    id       uuid primary key default gen_random_uuid(),
+    at       timestamptz not null default now(),
+    todo_id  uuid not null references "todo" (id) on delete cascade,
+    -- whoever sent the request making the change, an admin acting as the
+    -- owner included; null for changes made by the server itself
+    actor_id uuid references "user" (user_id) on delete set null,
+    action   text not null,
+    -- the todo's version once changed
+    version  bigint not null,
+    before   jsonb,
+    after    jsonb not null
+);
+create index todo_audit_log_todo_id on "todo_audit_log" (todo_id, at);
+
+-- the API-visible fields of a todo, as recorded in its audit log
+create function todo_snapshot(t "todo") returns jsonb as $$
+    select jsonb_build_object(
+        'text', t.todo_text,
+        'is_done', t.is_done,
+        'start_at', t.start_at,
+        'due_at', t.due_at,
+        'expires_at', t.expires_at,
+        'expired_at', t.expired_at,
+        'deleted_at', t.deleted_at,
+        'merged_into', t.merged_into,
+        'latitude', t.latitude,
+        'longitude', t.longitude,
+        'radius_m', t.radius_m
+    )
+$$ language sql immutable;
+
+-- records every statement creating or changing a todo, whichever code path
+-- it came through, in the statement's transaction; the actor is the
+-- `app.actor_id` the transaction set, if any
+create function todo_audit_log() returns trigger as $$
+declare
+    action text;
+begin
+    if tg_op = 'INSERT' then
+        action := 'created';
+    elsif todo_snapshot(new) = todo_snapshot(old) then
+        return null;
+    elsif new.merged_into is not null and old.merged_into is null then
+        action := 'merged';
+    elsif new.deleted_at is not null and old.deleted_at is null then
+        action := 'deleted';
+    else
+        action := 'updated';
+    end if;
+    insert into "todo_audit_log" (todo_id, actor_id, action, version, before, after)
+    values (
+        new.id,
+        nullif(current_setting('app.actor_id', true), '')::uuid,
+        action,
+        new.version,
+        case when tg_op = 'UPDATE' then todo_snapshot(old) end,
+        todo_snapshot(new)
+    );
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger todo_audit_log
+    after insert or update on "todo"
+    for each row execute function todo_audit_log();
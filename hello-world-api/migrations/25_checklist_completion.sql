@@ -0,0 +1,13 @@
+-- a todo's checklist goes with it
+alter table "checklist_item"
+    drop constraint checklist_item_todo_id_fkey,
+    add constraint checklist_item_todo_id_fkey
+        foreign key (todo_id) references "todo" (id) on delete cascade;
+
+-- how much of a todo's checklist is done, in percent rounded down, null for
+-- a todo without one; selected alongside the todo like its tags
+create function todo_completion(todo_id uuid) returns integer as $$
+    select (100 * count(*) filter (where is_done) / nullif(count(*), 0))::integer
+    from "checklist_item"
+    where todo_id = $1
+$$ language sql stable;
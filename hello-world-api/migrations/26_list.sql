@@ -0,0 +1,44 @@
+create table "list"
+(
+    id          uuid primary key default gen_random_uuid(),
+    user_id     uuid not null references "user" (user_id),
+    name        text not null,
+    created_at  timestamptz not null default now(),
+    unique (user_id, name),
+    -- referenced with the owner, so a todo can only be in its user's lists
+    unique (id, user_id)
+);
+
+alter table "todo"
+    add column list_id uuid,
+    add constraint todo_list_id_fkey
+        foreign key (list_id, user_id) references "list" (id, user_id);
+-- for listing the todos of a list
+create index todo_list_id on "todo" (list_id);
+
+-- how many of a list's todos are neither done nor expired, selected
+-- alongside the list
+create function list_open_todos(list_id uuid) returns bigint as $$
+    select count(*)
+    from "todo"
+    where list_id = $1 and merged_into is null and deleted_at is null
+        and not is_done and expired_at is null
+$$ language sql stable;
+
+-- moving a todo between lists is a change in its audit log
+create or replace function todo_snapshot(t "todo") returns jsonb as $$
+    select jsonb_build_object(
+        'text', t.todo_text,
+        'is_done', t.is_done,
+        'start_at', t.start_at,
+        'due_at', t.due_at,
+        'expires_at', t.expires_at,
+        'expired_at', t.expired_at,
+        'deleted_at', t.deleted_at,
+        'merged_into', t.merged_into,
+        'latitude', t.latitude,
+        'longitude', t.longitude,
+        'radius_m', t.radius_m,
+        'list_id', t.list_id
+    )
+$$ language sql immutable;
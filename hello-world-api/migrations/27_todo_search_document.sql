@@ -0,0 +1,10 @@
+-- the words of todo_text both stemmed in its language and as written, the
+-- latter for prefix matches; unlike with search_vector, a search against it
+-- is one tsquery for every row, which the index can be used for
+alter table "todo"
+    add column search_document tsvector
+        generated always as (
+            setweight(to_tsvector(search_config, todo_text), 'A')
+                || setweight(to_tsvector('simple', todo_text), 'B')
+        ) stored;
+create index todo_search_document on "todo" using gin (search_document);
@@ -0,0 +1,63 @@
+-- declared from lowest to highest, so todos sort by it in that order
+create type "priority" as enum ('low', 'medium', 'high', 'urgent');
+
+alter table "todo"
+    add column priority "priority";
+
+-- same as in 20_todo_expires_at, with priority stamped as well
+create or replace function todo_field_modified() returns trigger as $$
+declare
+    changed text[];
+begin
+    if tg_op = 'INSERT' then
+        changed := array['text', 'is_done', 'location', 'start_at', 'due_at', 'expires_at',
+            'priority'];
+    else
+        changed := array[]::text[];
+        if new.todo_text is distinct from old.todo_text then
+            changed := array_append(changed, 'text');
+        end if;
+        if new.is_done is distinct from old.is_done then
+            changed := array_append(changed, 'is_done');
+        end if;
+        if (new.latitude, new.longitude, new.radius_m)
+            is distinct from (old.latitude, old.longitude, old.radius_m) then
+            changed := array_append(changed, 'location');
+        end if;
+        if new.start_at is distinct from old.start_at then
+            changed := array_append(changed, 'start_at');
+        end if;
+        if new.due_at is distinct from old.due_at then
+            changed := array_append(changed, 'due_at');
+        end if;
+        if new.expires_at is distinct from old.expires_at then
+            changed := array_append(changed, 'expires_at');
+        end if;
+        if new.priority is distinct from old.priority then
+            changed := array_append(changed, 'priority');
+        end if;
+    end if;
+    new.field_modified := new.field_modified
+        || (select coalesce(jsonb_object_agg(field, now()), '{}') from unnest(changed) as field);
+    return new;
+end;
+$$ language plpgsql;
+
+-- same as in 26_list, with the priority recorded as well
+create or replace function todo_snapshot(t "todo") returns jsonb as $$
+    select jsonb_build_object(
+        'text', t.todo_text,
+        'is_done', t.is_done,
+        'start_at', t.start_at,
+        'due_at', t.due_at,
+        'expires_at', t.expires_at,
+        'expired_at', t.expired_at,
+        'deleted_at', t.deleted_at,
+        'merged_into', t.merged_into,
+        'latitude', t.latitude,
+        'longitude', t.longitude,
+        'radius_m', t.radius_m,
+        'list_id', t.list_id,
+        'priority', t.priority
+    )
+$$ language sql immutable;
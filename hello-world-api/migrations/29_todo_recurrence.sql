@@ -0,0 +1,77 @@
+-- the rule in words or as an RRULE, as the API checked and was given it
+alter table "todo"
+    add column recurrence text,
+    -- when the recurrence job created the next occurrence of the done todo,
+    -- or found its rule has none
+    add column recurred_at timestamptz;
+-- for the recurrence job to find the done todos it hasn't handled yet
+create index todo_recurrence_due on "todo" (completed_at)
+    where recurrence is not null and is_done and recurred_at is null;
+
+-- the next occurrence of a recurring todo takes over its text, which the
+-- done one no longer holds
+drop index todo_todo_text_key;
+create unique index todo_todo_text_key on "todo" (user_id, todo_text)
+    where deleted_at is null and recurred_at is null;
+
+-- same as in 28_todo_priority, with the recurrence stamped as well
+create or replace function todo_field_modified() returns trigger as $$
+declare
+    changed text[];
+begin
+    if tg_op = 'INSERT' then
+        changed := array['text', 'is_done', 'location', 'start_at', 'due_at', 'expires_at',
+            'priority', 'recurrence'];
+    else
+        changed := array[]::text[];
+        if new.todo_text is distinct from old.todo_text then
+            changed := array_append(changed, 'text');
+        end if;
+        if new.is_done is distinct from old.is_done then
+            changed := array_append(changed, 'is_done');
+        end if;
+        if (new.latitude, new.longitude, new.radius_m)
+            is distinct from (old.latitude, old.longitude, old.radius_m) then
+            changed := array_append(changed, 'location');
+        end if;
+        if new.start_at is distinct from old.start_at then
+            changed := array_append(changed, 'start_at');
+        end if;
+        if new.due_at is distinct from old.due_at then
+            changed := array_append(changed, 'due_at');
+        end if;
+        if new.expires_at is distinct from old.expires_at then
+            changed := array_append(changed, 'expires_at');
+        end if;
+        if new.priority is distinct from old.priority then
+            changed := array_append(changed, 'priority');
+        end if;
+        if new.recurrence is distinct from old.recurrence then
+            changed := array_append(changed, 'recurrence');
+        end if;
+    end if;
+    new.field_modified := new.field_modified
+        || (select coalesce(jsonb_object_agg(field, now()), '{}') from unnest(changed) as field);
+    return new;
+end;
+$$ language plpgsql;
+
+-- same as in 28_todo_priority, with the recurrence recorded as well
+create or replace function todo_snapshot(t "todo") returns jsonb as $$
+    select jsonb_build_object(
+        'text', t.todo_text,
+        'is_done', t.is_done,
+        'start_at', t.start_at,
+        'due_at', t.due_at,
+        'expires_at', t.expires_at,
+        'expired_at', t.expired_at,
+        'deleted_at', t.deleted_at,
+        'merged_into', t.merged_into,
+        'latitude', t.latitude,
+        'longitude', t.longitude,
+        'radius_m', t.radius_m,
+        'list_id', t.list_id,
+        'priority', t.priority,
+        'recurrence', t.recurrence
+    )
+$$ language sql immutable;
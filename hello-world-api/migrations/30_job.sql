@@ -0,0 +1,45 @@
+-- the schedule and last outcome of each background job, shared by every
+-- server on the database so each run happens on one of them
+create table "job"
+(
+    name             text primary key,
+    every_secs       bigint not null,
+    next_run_at      timestamptz not null default now(),
+    -- set while a server runs the job; once past, that run is taken to have
+    -- died with its server
+    running_until    timestamptz,
+    last_started_at  timestamptz,
+    last_finished_at timestamptz,
+    -- of the last run, null if it succeeded
+    last_error       text,
+    -- how many items the last successful run handled
+    last_handled     bigint,
+    runs             bigint not null default 0,
+    failures         bigint not null default 0
+);
+
+-- the reminders sent of upcoming due dates, one per due date of a todo;
+-- kept apart from the todo so sending one doesn't change its version
+create table "todo_reminder"
+(
+    todo_id uuid not null references "todo" (id) on delete cascade,
+    due_at  timestamptz not null,
+    primary key (todo_id, due_at)
+);
+
+-- purging a deleted todo takes its tags, links and share links with it
+alter table "todo_tag"
+    drop constraint todo_tag_todo_id_fkey,
+    add constraint todo_tag_todo_id_fkey
+        foreign key (todo_id) references "todo" (id) on delete cascade;
+alter table "todo_link"
+    drop constraint todo_link_from_id_fkey,
+    add constraint todo_link_from_id_fkey
+        foreign key (from_id) references "todo" (id) on delete cascade,
+    drop constraint todo_link_to_id_fkey,
+    add constraint todo_link_to_id_fkey
+        foreign key (to_id) references "todo" (id) on delete cascade;
+alter table "share_link"
+    drop constraint share_link_todo_id_fkey,
+    add constraint share_link_todo_id_fkey
+        foreign key (todo_id) references "todo" (id) on delete cascade;
@@ -0,0 +1,16 @@
+-- listings page through the todos in creation order, by (created_at, id)
+alter table "todo"
+    add column created_at timestamptz not null default now();
+
+-- todos with a version 7 id were created at the millisecond it starts with;
+-- the older ones all get the time of this migration, and their ids order them.
+-- Not a change of the todos, so their versions and etags stay as they are.
+alter table "todo" disable trigger todo_version;
+update "todo"
+    set created_at = to_timestamp(
+        ('x' || substr(replace(id::text, '-', ''), 1, 12))::bit(48)::bigint / 1000.0
+    )
+    where substr(id::text, 15, 1) = '7';
+alter table "todo" enable trigger todo_version;
+
+create index todo_user_id_created_at on "todo" (user_id, created_at, id);
@@ -0,0 +1,24 @@
+alter table "todo"
+    add column updated_at timestamptz not null default now();
+
+-- the last change field_modified or deleted_at tell of, keeping the versions
+alter table "todo" disable trigger todo_version;
+update "todo"
+    set updated_at = greatest(
+        created_at,
+        deleted_at,
+        (select max((value #>> '{}')::timestamptz) from jsonb_each(field_modified))
+    );
+alter table "todo" enable trigger todo_version;
+
+-- for listings of the todos changed since a time
+create index todo_user_id_updated_at on "todo" (user_id, updated_at);
+
+-- same as in 21_todo_version, with the time of the update stamped as well
+create or replace function todo_version() returns trigger as $$
+begin
+    new.version := old.version + 1;
+    new.updated_at := now();
+    return new;
+end;
+$$ language plpgsql;
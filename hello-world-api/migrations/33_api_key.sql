@@ -0,0 +1,18 @@
+-- keys machine clients authenticate with through `X-Api-Key` instead of a
+-- bearer token; only a hash of the key is kept
+create table "api_key"
+(
+    id            uuid primary key default gen_random_uuid(),
+    user_id       uuid not null references "user" (user_id) on delete cascade,
+    name          text not null,
+    key_hash      bytea unique not null,
+    -- the start of the key, for users to tell their keys apart
+    prefix        text not null,
+    -- space-separated, as in a token's `scope` claim
+    scope         text not null,
+    created_at    timestamptz not null default now(),
+    expires_at    timestamptz,
+    last_used_at  timestamptz
+);
+
+create index api_key_user_id on "api_key" (user_id);
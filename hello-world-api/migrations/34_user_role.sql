@@ -0,0 +1,8 @@
+-- what a user may do: admins see every user's data and the /admin endpoints
+create type "role" as enum ('user', 'admin');
+
+alter table "user"
+    add column role "role" not null default 'user';
+update "user" set role = 'admin' where is_admin;
+alter table "user"
+    drop column is_admin;
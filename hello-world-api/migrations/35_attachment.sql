@@ -0,0 +1,15 @@
+-- files attached to todos; their contents are in the attachment storage
+-- under `storage_key`, not in the database
+create table "attachment"
+(
+    id            uuid primary key,
+    todo_id       uuid not null references "todo" (id) on delete cascade,
+    user_id       uuid not null references "user" (user_id) on delete cascade,
+    filename      text not null,
+    content_type  text not null,
+    size          bigint not null,
+    storage_key   text unique not null,
+    created_at    timestamptz not null default now()
+);
+
+create index attachment_todo_id on "attachment" (todo_id);
@@ -0,0 +1,42 @@
+-- workspaces share their todos, lists, tags and the rest between their
+-- members. A workspace's data belongs to an account of its own, the "user"
+-- row of the same id, which has no password to log in with: members act as
+-- it, so everything scoped to a user is scoped to the workspace
+create type "workspace_role" as enum ('owner', 'member');
+
+create table "workspace"
+(
+    id          uuid primary key references "user" (user_id) on delete cascade,
+    -- in `X-Workspace` and the subdomains of `WORKSPACE_DOMAIN`
+    slug        text unique not null,
+    name        text not null,
+    created_at  timestamptz not null default now()
+);
+
+create table "workspace_member"
+(
+    workspace_id  uuid not null references "workspace" (id) on delete cascade,
+    user_id       uuid not null references "user" (user_id) on delete cascade,
+    role          "workspace_role" not null default 'member',
+    joined_at     timestamptz not null default now(),
+    primary key (workspace_id, user_id)
+);
+
+-- for listing a user's workspaces
+create index workspace_member_user_id on "workspace_member" (user_id);
+
+-- single-use invitations, accepted with a token of which only a hash is kept
+create table "workspace_invitation"
+(
+    id            uuid primary key default gen_random_uuid(),
+    workspace_id  uuid not null references "workspace" (id) on delete cascade,
+    token_hash    bytea unique not null,
+    -- who may accept it, anyone with the token if null
+    username      text,
+    role          "workspace_role" not null,
+    invited_by    uuid not null references "user" (user_id) on delete cascade,
+    created_at    timestamptz not null default now(),
+    expires_at    timestamptz not null,
+    accepted_by   uuid references "user" (user_id) on delete set null,
+    accepted_at   timestamptz
+);
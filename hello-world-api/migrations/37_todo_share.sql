@@ -0,0 +1,40 @@
+-- todos and lists their owner shares with other users, who see them among
+-- their own todos. Viewers only read them, editors also change them
+-- (never delete them); ordered so that `max` is the most a user may do
+create type "share_permission" as enum ('viewer', 'editor');
+
+create table "todo_share"
+(
+    id          uuid primary key default gen_random_uuid(),
+    -- one of the two, a list's share covering the todos in it
+    todo_id     uuid references "todo" (id) on delete cascade,
+    list_id     uuid references "list" (id) on delete cascade,
+    -- whom it is shared with
+    user_id     uuid not null references "user" (user_id) on delete cascade,
+    permission  "share_permission" not null,
+    created_at  timestamptz not null default now(),
+    check ((todo_id is null) <> (list_id is null)),
+    unique (todo_id, user_id),
+    unique (list_id, user_id)
+);
+
+-- for listing what is shared with a user
+create index todo_share_user_id on "todo_share" (user_id);
+
+-- what `user_id` may do with someone else's todo, null if it isn't shared
+-- with them by itself or through its list
+create function todo_permission(todo_id uuid, user_id uuid) returns "share_permission" as $$
+    select max(s.permission)
+    from "todo" t
+    join "todo_share" s on s.todo_id = t.id or s.list_id = t.list_id
+    where t.id = $1 and s.user_id = $2
+$$ language sql stable;
+
+-- who shared a todo of `owner_id`'s with `user_id`: the owner's username,
+-- or the slug of the workspace it belongs to; null for the owner
+create function todo_shared_by(owner_id uuid, user_id uuid) returns text as $$
+    select coalesce(w.slug, u.username)
+    from "user" u
+    left join "workspace" w on w.id = u.user_id
+    where u.user_id = $1 and $1 <> $2
+$$ language sql stable;
@@ -0,0 +1,41 @@
+-- the `type`s of todo events, as published to live clients
+create type "todo_event_kind" as enum ('created', 'updated', 'deleted', 'due');
+
+-- URLs a user has the events of their todos posted to
+create table "webhook"
+(
+    id          uuid primary key default gen_random_uuid(),
+    user_id     uuid not null references "user" (user_id) on delete cascade,
+    url         text not null,
+    -- signs the deliveries; shown once when the webhook is created
+    secret      text not null,
+    -- the kinds of events delivered, all of them if empty
+    events      "todo_event_kind"[] not null default '{}',
+    created_at  timestamptz not null default now()
+);
+
+create index webhook_user_id on "webhook" (user_id);
+
+-- each event to post to a webhook, kept after it is delivered or given up
+-- on for the webhook's delivery log
+create table "webhook_delivery"
+(
+    id               uuid primary key default gen_random_uuid(),
+    webhook_id       uuid not null references "webhook" (id) on delete cascade,
+    event            "todo_event_kind" not null,
+    -- the body posted, the event as live clients get it
+    payload          jsonb not null,
+    created_at       timestamptz not null default now(),
+    attempts         integer not null default 0,
+    -- null once delivered or given up on; pushed ahead while an attempt
+    -- is under way, so no other server makes it too
+    next_attempt_at  timestamptz default now(),
+    delivered_at     timestamptz,
+    -- the answer to the last attempt, or why it got none
+    response_status  integer,
+    last_error       text
+);
+
+create index webhook_delivery_next_attempt_at on "webhook_delivery" (next_attempt_at)
+    where next_attempt_at is not null;
+create index webhook_delivery_webhook_id on "webhook_delivery" (webhook_id, created_at);
@@ -0,0 +1,25 @@
+-- todo events, recorded in the transaction making the change they tell of
+-- and published from here once it commits, then kept a while
+create table "todo_outbox"
+(
+    id            bigserial primary key,
+    user_id       uuid not null references "user" (user_id) on delete cascade,
+    -- the event as live clients get it, kept as serialized
+    payload       json not null,
+    created_at    timestamptz not null default now(),
+    published_at  timestamptz
+);
+
+create index todo_outbox_unpublished on "todo_outbox" (id) where published_at is null;
+
+-- wakes up the relays as the transaction recording events commits
+create function todo_outbox_notify() returns trigger as $$
+begin
+    perform pg_notify('todo_outbox', '');
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger todo_outbox_notify
+    after insert on "todo_outbox"
+    for each statement execute function todo_outbox_notify();
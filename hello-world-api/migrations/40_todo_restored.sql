@@ -0,0 +1,31 @@
+-- same as in 24_todo_audit_log, with todos taken out of the trash recorded
+-- as restored rather than updated
+create or replace function todo_audit_log() returns trigger as $$
+declare
+    action text;
+begin
+    if tg_op = 'INSERT' then
+        action := 'created';
+    elsif todo_snapshot(new) = todo_snapshot(old) then
+        return null;
+    elsif new.merged_into is not null and old.merged_into is null then
+        action := 'merged';
+    elsif new.deleted_at is not null and old.deleted_at is null then
+        action := 'deleted';
+    elsif new.deleted_at is null and old.deleted_at is not null then
+        action := 'restored';
+    else
+        action := 'updated';
+    end if;
+    insert into "todo_audit_log" (todo_id, actor_id, action, version, before, after)
+    values (
+        new.id,
+        nullif(current_setting('app.actor_id', true), '')::uuid,
+        action,
+        new.version,
+        case when tg_op = 'UPDATE' then todo_snapshot(old) end,
+        todo_snapshot(new)
+    );
+    return null;
+end;
+$$ language plpgsql;
@@ -0,0 +1,6 @@
+-- back to listings in creation order; the column's index goes with it
+drop trigger todo_position on "todo";
+drop function todo_position();
+
+alter table "todo"
+    drop column position;
@@ -0,0 +1,41 @@
+-- each user's todos in the order they arrange them, listings without `sort`
+-- paging through them by (position, id). New todos go last, 1024 after the
+-- user's last one; moving a todo puts it halfway between its new neighbours,
+-- so only its row changes until a gap runs out and the user's todos are
+-- spread out again.
+alter table "todo"
+    add column position double precision;
+
+-- the todos so far in the order they were created. Not a change of the
+-- todos, so their versions and etags stay as they are.
+alter table "todo" disable trigger todo_version;
+update "todo"
+    set position = ranked.position
+    from (
+        select id, (row_number() over (partition by user_id order by created_at, id) * 1024)::float8
+            as position
+        from "todo"
+    ) ranked
+    where "todo".id = ranked.id;
+alter table "todo" enable trigger todo_version;
+
+alter table "todo"
+    alter column position set not null;
+
+create index todo_user_id_position on "todo" (user_id, position, id);
+
+-- whichever code path inserts the todo, unless it places it itself
+create function todo_position() returns trigger as $$
+begin
+    if new.position is null then
+        new.position := coalesce(
+            (select max(position) from "todo" where user_id = new.user_id), 0
+        ) + 1024;
+    end if;
+    return new;
+end;
+$$ language plpgsql;
+
+create trigger todo_position
+    before insert on "todo"
+    for each row execute function todo_position();
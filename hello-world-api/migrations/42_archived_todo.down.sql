@@ -0,0 +1,2 @@
+-- the archived todos are lost, they can't be put back into "todo"
+drop table "archived_todo";
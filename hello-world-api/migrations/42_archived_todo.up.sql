@@ -0,0 +1,19 @@
+-- done todos moved out of "todo" by `POST /todos/archive-completed`, with
+-- the fields they are listed with and the names of their tags. Anything
+-- else they had, such as checklists, links and history, went with them.
+create table "archived_todo"
+(
+    id           uuid primary key,
+    user_id      uuid not null references "user" (user_id),
+    todo_text    text not null,
+    priority     "priority",
+    due_at       timestamptz,
+    completed_at timestamptz,
+    created_at   timestamptz not null,
+    list_id      uuid,
+    tags         text[] not null default '{}',
+    archived_at  timestamptz not null default now()
+);
+
+-- for listing a user's archive, the last archived first
+create index archived_todo_user_id_archived_at on "archived_todo" (user_id, archived_at desc, id);
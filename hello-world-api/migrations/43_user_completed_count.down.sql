@@ -0,0 +1,5 @@
+drop trigger todo_completed_count on "todo";
+drop function todo_completed_count();
+
+alter table "user"
+    drop column completed_count;
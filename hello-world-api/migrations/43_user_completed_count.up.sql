@@ -0,0 +1,42 @@
+-- each user's done todos, neither deleted nor merged, archived ones
+-- included, for `GET /me/summary` to read without counting them. Kept by a
+-- trigger in the statement that changes the todos, whichever code path runs
+-- it, so toggling a todo and its user's count are one atomic change.
+alter table "user"
+    add column completed_count bigint not null default 0;
+
+update "user" u
+    set completed_count = (
+        select count(*) from "todo" t
+        where t.user_id = u.user_id and t.is_done
+            and t.deleted_at is null and t.merged_into is null
+    ) + (
+        select count(*) from "archived_todo" a where a.user_id = u.user_id
+    );
+
+create function todo_completed_count() returns trigger as $$
+declare
+    -- null for the row an insert or delete doesn't have
+    counted_before boolean := old.is_done and old.deleted_at is null and old.merged_into is null;
+    counted_after boolean := new.is_done and new.deleted_at is null and new.merged_into is null;
+begin
+    -- archiving moves done todos out of "todo", they stay done
+    if tg_op = 'DELETE' and current_setting('app.archiving', true) = 'on' then
+        return null;
+    end if;
+    if tg_op = 'UPDATE' and counted_before = counted_after and old.user_id = new.user_id then
+        return null;
+    end if;
+    if counted_before then
+        update "user" set completed_count = completed_count - 1 where user_id = old.user_id;
+    end if;
+    if counted_after then
+        update "user" set completed_count = completed_count + 1 where user_id = new.user_id;
+    end if;
+    return null;
+end;
+$$ language plpgsql;
+
+create trigger todo_completed_count
+    after insert or delete or update of is_done, deleted_at, merged_into, user_id on "todo"
+    for each row execute function todo_completed_count();
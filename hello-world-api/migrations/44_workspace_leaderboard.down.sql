@@ -0,0 +1,4 @@
+drop index todo_audit_log_actor_id;
+
+alter table "workspace_member"
+    drop column on_leaderboard;
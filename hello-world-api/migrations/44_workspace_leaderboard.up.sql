@@ -0,0 +1,7 @@
+-- whether a member is ranked on their workspace's leaderboard, which they
+-- can opt out of
+alter table "workspace_member"
+    add column on_leaderboard boolean not null default true;
+
+-- the leaderboard counts each member's completions from the audit log
+create index todo_audit_log_actor_id on "todo_audit_log" (actor_id, at);
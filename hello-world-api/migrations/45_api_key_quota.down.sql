@@ -0,0 +1,4 @@
+alter table "api_key"
+    drop column daily_quota,
+    drop column used_on,
+    drop column used_today;
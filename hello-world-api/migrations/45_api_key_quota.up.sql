@@ -0,0 +1,6 @@
+-- how many requests a key may send per UTC day, any number if null, and
+-- how many it sent on the day it was last used
+alter table "api_key"
+    add column daily_quota integer check (daily_quota > 0),
+    add column used_on date,
+    add column used_today integer not null default 0;
@@ -0,0 +1,20 @@
+-- enum values can't be dropped, the type is made anew without them
+delete from "webhook_delivery" where event::text in ('assigned', 'due_changed');
+delete from "todo_outbox" where payload->>'type' in ('assigned', 'due_changed');
+update "webhook"
+set events = array_remove(array_remove(events, 'assigned'), 'due_changed');
+
+alter type "todo_event_kind" rename to "todo_event_kind_old";
+create type "todo_event_kind" as enum ('created', 'updated', 'deleted', 'due');
+alter table "webhook"
+    alter column events drop default,
+    alter column events type "todo_event_kind"[] using events::text[]::"todo_event_kind"[],
+    alter column events set default '{}';
+alter table "webhook_delivery"
+    alter column event type "todo_event_kind" using event::text::"todo_event_kind";
+drop type "todo_event_kind_old";
+
+drop index todo_assignee_id;
+
+alter table "todo"
+    drop column assignee_id;
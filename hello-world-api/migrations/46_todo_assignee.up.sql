@@ -0,0 +1,9 @@
+-- the workspace member a todo is assigned to, who is told when it is
+-- assigned to them and when its due date changes
+alter table "todo"
+    add column assignee_id uuid references "user" (user_id) on delete set null;
+
+create index todo_assignee_id on "todo" (assignee_id) where assignee_id is not null;
+
+alter type "todo_event_kind" add value 'assigned';
+alter type "todo_event_kind" add value 'due_changed';
@@ -0,0 +1 @@
+drop table "workspace_rate_limit";
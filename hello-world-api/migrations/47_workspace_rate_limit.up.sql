@@ -0,0 +1,9 @@
+-- the request rate admins allow a workspace's clients, instead of
+-- RATE_LIMIT_PER_MINUTE and RATE_LIMIT_BURST
+create table "workspace_rate_limit"
+(
+    workspace_id  uuid primary key references "workspace" (id) on delete cascade,
+    per_minute    integer not null check (per_minute > 0),
+    burst         integer not null check (burst > 0),
+    updated_at    timestamptz not null default now()
+);
@@ -0,0 +1 @@
+alter table "workspace" drop column settings;
@@ -0,0 +1,6 @@
+-- what owners set for their workspace: the priority and the reminder offset
+-- of its todos, and the week start and timezone of its leaderboard. The API
+-- checks the fields, an absent one is the default
+alter table "workspace"
+    add column settings jsonb not null default '{}'
+        check (jsonb_typeof(settings) = 'object');
@@ -0,0 +1,9 @@
+-- the view settings clients keep for a user, replaced as a whole and only
+-- from the version the client read
+create table "user_preferences"
+(
+    user_id      uuid primary key references "user" (user_id) on delete cascade,
+    preferences  jsonb not null check (jsonb_typeof(preferences) = 'object'),
+    version      bigint not null,
+    updated_at   timestamptz not null default now()
+);
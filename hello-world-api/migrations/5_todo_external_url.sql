@@ -0,0 +1,2 @@
+alter table "todo"
+    add column external_url text;
@@ -0,0 +1,2 @@
+alter table "todo"
+    add column merged_into uuid references "todo" (id);
@@ -0,0 +1,9 @@
+create table "checklist_item"
+(
+    id          uuid primary key default gen_random_uuid(),
+    todo_id     uuid not null references "todo" (id),
+    position    integer not null,
+    item_text   text not null,
+    is_done     boolean not null default false
+);
+create index checklist_item_todo_id on "checklist_item" (todo_id, position);
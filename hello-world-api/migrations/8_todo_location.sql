@@ -0,0 +1,9 @@
+create extension if not exists cube;
+create extension if not exists earthdistance;
+alter table "todo"
+    add column latitude double precision check (latitude between -90 and 90),
+    add column longitude double precision check (longitude between -180 and 180),
+    add column radius_m double precision check (radius_m > 0),
+    add constraint todo_location_complete check ((latitude is null) = (longitude is null));
+create index todo_location on "todo" using gist (ll_to_earth(latitude, longitude))
+    where latitude is not null;
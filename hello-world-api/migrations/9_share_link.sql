@@ -0,0 +1,9 @@
+create table "share_link"
+(
+    id          uuid primary key default gen_random_uuid(),
+    todo_id     uuid not null references "todo" (id),
+    token_hash  bytea unique not null,
+    created_at  timestamptz not null default now(),
+    expires_at  timestamptz not null,
+    revoked_at  timestamptz
+);
@@ -0,0 +1,130 @@
+use std::{net::SocketAddr, time::Instant};
+
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, MatchedPath, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use tracing::info;
+
+use crate::logs::LogFormat;
+
+/// Line format of the `access_log` tracing target.
+#[derive(Clone, Copy)]
+pub enum AccessLogFormat {
+    /// Apache Common Log Format extended with latency and request id.
+    Common,
+    Json,
+}
+
+impl std::str::FromStr for AccessLogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "common" => Ok(AccessLogFormat::Common),
+            "json" => Ok(AccessLogFormat::Json),
+            other => anyhow::bail!("ACCESS_LOG_FORMAT must be common or json, got {other}"),
+        }
+    }
+}
+
+/// The route a request matched, handed out to [`access_log`] on the
+/// response by [`matched_route`].
+#[derive(Clone)]
+struct Route(String);
+
+/// Emits one line per request to the `access_log` target, independently of
+/// the span-based `TraceLayer` output. With JSON logs, the line's values are
+/// also fields of the event.
+pub async fn access_log<B>(
+    State((format, log_format)): State<(AccessLogFormat, LogFormat)>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let started = Instant::now();
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let version = req.version();
+    let remote = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string());
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+
+    let response = next.run(req).await;
+
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let status = response.status().as_u16();
+    let bytes = response.body().size_hint().exact();
+
+    let line = match format {
+        AccessLogFormat::Common => format!(
+            // no authentication yet, so the user field is always `-`
+            r#"{} - - [{}] "{} {} {:?}" {} {} {:.3}ms {}"#,
+            remote.as_deref().unwrap_or("-"),
+            chrono::Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+            method,
+            path,
+            version,
+            status,
+            bytes.map_or_else(|| "-".to_owned(), |bytes| bytes.to_string()),
+            latency_ms,
+            request_id.as_deref().unwrap_or("-"),
+        ),
+        AccessLogFormat::Json => serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "remote": remote,
+            "method": method.as_str(),
+            "path": path,
+            "status": status,
+            "bytes": bytes,
+            "latency_ms": latency_ms,
+            "user": null,
+            "request_id": request_id,
+        })
+        .to_string(),
+    };
+    match log_format {
+        LogFormat::Text => info!(target: "access_log", "{line}"),
+        LogFormat::Json => {
+            let route = response
+                .extensions()
+                .get::<Route>()
+                .map(|Route(route)| route.as_str());
+            info!(
+                target: "access_log",
+                remote = remote.as_deref(),
+                method = %method,
+                path,
+                route,
+                status,
+                bytes,
+                latency_ms,
+                request_id = request_id.as_deref(),
+                "{line}"
+            )
+        }
+    }
+
+    response
+}
+
+/// Passes the matched route on to [`access_log`], which runs before routing.
+/// Layered inside the router.
+pub async fn matched_route<B>(req: Request<B>, next: Next<B>) -> Response {
+    let route = req.extensions().get::<MatchedPath>().cloned();
+    let mut response = next.run(req).await;
+    if let Some(route) = route {
+        response
+            .extensions_mut()
+            .insert(Route(route.as_str().to_owned()));
+    }
+    response
+}
@@ -0,0 +1,164 @@
+//! `GET /admin/stats`: how the instance is used, with todo counts across
+//! all users and per user.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::ApiError,
+    extract::{Json, Query},
+};
+
+/// Days `created_per_day` goes back, today included.
+const DAYS: i32 = 30;
+
+const MAX_USERS: i64 = 1000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsQuery {
+    /// How many users to count the todos of, those with the most first;
+    /// 1 to 1000, 100 by default.
+    users: Option<i64>,
+}
+
+/// Counts of live todos, those neither deleted nor merged into another.
+#[derive(Serialize, ToSchema)]
+pub struct AdminStats {
+    total: i64,
+    completed: i64,
+    /// Neither done nor expired.
+    open: i64,
+    expired: i64,
+    /// Every day of the last 30, oldest first, UTC.
+    created_per_day: Vec<DayCount>,
+    users: Vec<UserCounts>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DayCount {
+    day: NaiveDate,
+    created: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserCounts {
+    user_id: uuid::Uuid,
+    username: String,
+    total: i64,
+    completed: i64,
+    open: i64,
+}
+
+struct Totals {
+    total: i64,
+    completed: i64,
+    open: i64,
+    expired: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    tag = "admin",
+    params(
+        StatsQuery,
+    ),
+    responses(
+        (status = 200, description = "Todo counts across all users", body = AdminStats),
+        (status = 400, description = "`users` out of range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn get(
+    pg: Extension<PgPool>,
+    Query(params): Query<StatsQuery>,
+) -> axum::response::Response {
+    let users = params.users.unwrap_or(100);
+    if !(1..=MAX_USERS).contains(&users) {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("users must be between 1 and {MAX_USERS}"),
+        )
+        .into_response();
+    }
+    let result = tokio::try_join!(totals(&pg), created_per_day(&pg), user_counts(&pg, users));
+    match result {
+        Ok((totals, created_per_day, users)) => Json(AdminStats {
+            total: totals.total,
+            completed: totals.completed,
+            open: totals.open,
+            expired: totals.expired,
+            created_per_day,
+            users,
+        })
+        .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+async fn totals(pg: &PgPool) -> Result<Totals, sqlx::Error> {
+    sqlx::query_as!(
+        Totals,
+        r#"select count(*) as "total!",
+            count(*) filter (where is_done) as "completed!",
+            count(*) filter (where not is_done and expired_at is null) as "open!",
+            count(*) filter (where not is_done and expired_at is not null) as "expired!"
+        from "todo"
+        where merged_into is null and deleted_at is null"#,
+    )
+    .fetch_one(pg)
+    .await
+}
+
+/// Grouped before the join, so each todo is only looked at once.
+async fn created_per_day(pg: &PgPool) -> Result<Vec<DayCount>, sqlx::Error> {
+    sqlx::query_as!(
+        DayCount,
+        r#"with created as (
+            select (created_at at time zone 'UTC')::date as day, count(*) as created
+            from "todo"
+            where created_at >= (now() at time zone 'UTC')::date - ($1::int - 1)
+                and merged_into is null and deleted_at is null
+            group by 1
+        )
+        select d.day::date as "day!", coalesce(c.created, 0) as "created!"
+        from generate_series(
+            (now() at time zone 'UTC')::date - ($1::int - 1),
+            (now() at time zone 'UTC')::date,
+            interval '1 day'
+        ) d(day)
+        left join created c on c.day = d.day::date
+        order by 1"#,
+        DAYS,
+    )
+    .fetch_all(pg)
+    .await
+}
+
+async fn user_counts(pg: &PgPool, limit: i64) -> Result<Vec<UserCounts>, sqlx::Error> {
+    sqlx::query_as!(
+        UserCounts,
+        r#"select u.user_id, u.username,
+            coalesce(c.total, 0) as "total!",
+            coalesce(c.completed, 0) as "completed!",
+            coalesce(c.open, 0) as "open!"
+        from "user" u
+        left join (
+            select user_id,
+                count(*) as total,
+                count(*) filter (where is_done) as completed,
+                count(*) filter (where not is_done and expired_at is null) as open
+            from "todo"
+            where merged_into is null and deleted_at is null
+            group by user_id
+        ) c using (user_id)
+        order by 3 desc, u.username
+        limit $1"#,
+        limit,
+    )
+    .fetch_all(pg)
+    .await
+}
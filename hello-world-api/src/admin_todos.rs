@@ -0,0 +1,111 @@
+//! `GET /admin/todos`: every user's todos, for admins looking into a
+//! report without acting as the user.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::ApiError,
+    extract::{Json, Query},
+    models::Priority,
+};
+
+const MAX_LIMIT: i64 = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListAllTodos {
+    /// Only this user's todos, by username.
+    user: Option<String>,
+    is_done: Option<bool>,
+    /// 1 to 100, 10 by default.
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminTodo {
+    id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    username: String,
+    text: String,
+    is_done: bool,
+    due_at: Option<DateTime<Utc>>,
+    priority: Option<Priority>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminTodoPage {
+    /// Todos matching the filters, over all pages.
+    total: i64,
+    items: Vec<AdminTodo>,
+}
+
+/// Live todos, those neither deleted nor merged into another, newest first.
+#[utoipa::path(
+    get,
+    path = "/admin/todos",
+    tag = "admin",
+    params(
+        ListAllTodos,
+    ),
+    responses(
+        (status = 200, description = "A page of todos across all users", body = AdminTodoPage),
+        (status = 400, description = "`limit` out of range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn list(
+    pg: Extension<PgPool>,
+    Query(params): Query<ListAllTodos>,
+) -> axum::response::Response {
+    let limit = params.limit.unwrap_or(10);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_LIMIT}"),
+        )
+        .into_response();
+    }
+    let query = sqlx::query!(
+        r#"select t.id, t.user_id as "user_id!", u.username, t.todo_text, t.is_done, t.due_at,
+            t.priority as "priority: Priority", t.created_at, t.updated_at,
+            count(*) over () as "total!"
+        from "todo" t
+        join "user" u using (user_id)
+        where t.merged_into is null and t.deleted_at is null
+            and ($1::text is null or u.username = $1)
+            and ($2::bool is null or t.is_done = $2)
+        order by t.created_at desc, t.id
+        limit $3
+        offset $4"#,
+        params.user,
+        params.is_done,
+        limit,
+        params.offset.unwrap_or(0).max(0),
+    );
+    let rows = match query.fetch_all(&*pg).await {
+        Ok(rows) => rows,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let total = rows.first().map_or(0, |row| row.total);
+    let items = rows
+        .into_iter()
+        .map(|row| AdminTodo {
+            id: row.id,
+            user_id: row.user_id,
+            username: row.username,
+            text: row.todo_text,
+            is_done: row.is_done,
+            due_at: row.due_at,
+            priority: row.priority,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })
+        .collect();
+    Json(AdminTodoPage { total, items }).into_response()
+}
@@ -0,0 +1,287 @@
+//! Opt-in, anonymized product analytics. With `ANALYTICS_SINK` set, handlers
+//! emit the events registered in [`schema`] to a queue that a background
+//! worker sends on in batches, so a slow or failing sink never holds up a
+//! request. Users are only identified by a keyed hash of their id, and
+//! events are dropped rather than queued when the sink falls behind.
+
+pub mod schema;
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Method;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::outbound::{self, Outbound};
+
+/// Events sent to the sink in one request at most.
+const BATCH_SIZE: usize = 100;
+
+#[derive(Serialize)]
+pub struct Event {
+    event: &'static str,
+    /// Keyed hash of the user id, stable for as long as `ANALYTICS_SALT` is.
+    distinct_id: String,
+    timestamp: DateTime<Utc>,
+    schema_version: u32,
+    properties: Map<String, Value>,
+}
+
+/// Where emitted events end up.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn send(&self, events: &[Event]) -> anyhow::Result<()>;
+}
+
+/// One JSON line per event on standard output, for a log shipper to pick up.
+struct Stdout;
+
+#[async_trait]
+impl EventSink for Stdout {
+    async fn send(&self, events: &[Event]) -> anyhow::Result<()> {
+        for event in events {
+            println!("{}", serde_json::to_string(event)?);
+        }
+        Ok(())
+    }
+}
+
+/// Produces to a Kafka topic through a Confluent-compatible REST proxy,
+/// configured with `ANALYTICS_KAFKA_URL` and `ANALYTICS_KAFKA_TOPIC`.
+struct Kafka {
+    http: Outbound,
+    topic_url: String,
+}
+
+#[async_trait]
+impl EventSink for Kafka {
+    async fn send(&self, events: &[Event]) -> anyhow::Result<()> {
+        let records: Vec<_> = events
+            .iter()
+            .map(|event| json!({ "value": event }))
+            .collect();
+        let body = json!({ "records": records });
+        self.http
+            .send(
+                &outbound::ANALYTICS,
+                Method::POST,
+                &self.topic_url,
+                |request| {
+                    request
+                        .header("content-type", "application/vnd.kafka.json.v2+json")
+                        .json(&body)
+                },
+            )
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// PostHog's batch capture API, configured with `POSTHOG_API_KEY` and
+/// `POSTHOG_HOST`. Events are sent without person profiles or GeoIP lookup.
+struct PostHog {
+    http: Outbound,
+    batch_url: String,
+    api_key: String,
+}
+
+#[async_trait]
+impl EventSink for PostHog {
+    async fn send(&self, events: &[Event]) -> anyhow::Result<()> {
+        let batch: Vec<_> = events
+            .iter()
+            .map(|event| {
+                let mut properties = event.properties.clone();
+                properties.insert("schema_version".to_owned(), event.schema_version.into());
+                properties.insert("$process_person_profile".to_owned(), false.into());
+                properties.insert("$geoip_disable".to_owned(), true.into());
+                json!({
+                    "event": event.event,
+                    "distinct_id": event.distinct_id,
+                    "timestamp": event.timestamp,
+                    "properties": properties,
+                })
+            })
+            .collect();
+        let body = json!({ "api_key": self.api_key, "batch": batch });
+        self.http
+            .send(
+                &outbound::ANALYTICS,
+                Method::POST,
+                &self.batch_url,
+                |request| request.json(&body),
+            )
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Queue of events, drained by the worker started in [`Analytics::spawn`].
+#[derive(Clone)]
+pub struct Analytics {
+    events: mpsc::Sender<Event>,
+    salt: Arc<[u8]>,
+}
+
+impl Analytics {
+    /// `None` unless `ANALYTICS_SINK` is set.
+    pub fn from_env(http: Outbound) -> anyhow::Result<Option<Self>> {
+        let Ok(sink) = std::env::var("ANALYTICS_SINK") else {
+            return Ok(None);
+        };
+        let salt = std::env::var("ANALYTICS_SALT")
+            .ok()
+            .filter(|salt| salt.len() >= 16)
+            .context("ANALYTICS_SALT of at least 16 bytes is needed with ANALYTICS_SINK")?;
+        let sink: Arc<dyn EventSink> = match sink.as_str() {
+            "stdout" => Arc::new(Stdout),
+            "kafka" => {
+                let url = std::env::var("ANALYTICS_KAFKA_URL")
+                    .context("ANALYTICS_KAFKA_URL is needed with ANALYTICS_SINK=kafka")?;
+                let topic = std::env::var("ANALYTICS_KAFKA_TOPIC")
+                    .unwrap_or_else(|_| "product-events".to_owned());
+                Arc::new(Kafka {
+                    http: http.clone(),
+                    topic_url: format!("{}/topics/{topic}", url.trim_end_matches('/')),
+                })
+            }
+            "posthog" => {
+                let api_key = std::env::var("POSTHOG_API_KEY")
+                    .context("POSTHOG_API_KEY is needed with ANALYTICS_SINK=posthog")?;
+                let host = std::env::var("POSTHOG_HOST")
+                    .unwrap_or_else(|_| "https://us.i.posthog.com".to_owned());
+                Arc::new(PostHog {
+                    http: http.clone(),
+                    batch_url: format!("{}/batch/", host.trim_end_matches('/')),
+                    api_key,
+                })
+            }
+            other => anyhow::bail!("ANALYTICS_SINK must be stdout, kafka or posthog, got {other}"),
+        };
+        Ok(Some(Analytics::spawn(sink, salt.as_bytes())))
+    }
+
+    fn spawn(sink: Arc<dyn EventSink>, salt: &[u8]) -> Self {
+        let (tx, mut rx) = mpsc::channel::<Event>(1024);
+        tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            while rx.recv_many(&mut batch, BATCH_SIZE).await > 0 {
+                if let Err(err) = sink.send(&batch).await {
+                    warn!(
+                        events = batch.len(),
+                        "Fail to send analytics events {:?}", err
+                    );
+                }
+                batch.clear();
+            }
+        });
+        Analytics {
+            events: tx,
+            salt: salt.into(),
+        }
+    }
+
+    /// Queues `event` of `user_id`; `properties` must be a JSON object
+    /// matching the event's [`schema`].
+    pub fn emit(&self, user_id: uuid::Uuid, event: &'static str, properties: Value) {
+        let Value::Object(properties) = properties else {
+            warn!(
+                event,
+                "Analytics properties must be an object, dropping event"
+            );
+            return;
+        };
+        if let Err(err) = schema::validate(event, &properties) {
+            warn!("Dropping analytics event: {err}");
+            return;
+        }
+        let event = Event {
+            event,
+            distinct_id: self.anonymize(user_id),
+            timestamp: Utc::now(),
+            schema_version: schema::VERSION,
+            properties,
+        };
+        if self.events.try_send(event).is_err() {
+            warn!("Analytics queue is full, dropping event");
+        }
+    }
+
+    fn anonymize(&self, user_id: uuid::Uuid) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.salt).expect("HMAC takes keys of any length");
+        mac.update(user_id.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use super::*;
+
+    /// Keeps what it is sent.
+    #[derive(Default)]
+    struct Recorded(Mutex<Vec<Value>>);
+
+    #[async_trait]
+    impl EventSink for Recorded {
+        async fn send(&self, events: &[Event]) -> anyhow::Result<()> {
+            let mut recorded = self.0.lock().unwrap();
+            for event in events {
+                recorded.push(serde_json::to_value(event)?);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn only_events_matching_their_schema_are_sent() {
+        let sink = Arc::new(Recorded::default());
+        let analytics = Analytics::spawn(sink.clone(), b"0123456789abcdef");
+        let user_id = uuid::Uuid::new_v4();
+        analytics.emit(
+            user_id,
+            "todo_created",
+            json!({"via": "rss", "has_start_at": true}),
+        );
+        analytics.emit(user_id, "todo_deleted", json!({}));
+        analytics.emit(user_id, "todo_created", json!(["api"]));
+        analytics.emit(
+            user_id,
+            "todo_created",
+            json!({"via": "api", "has_start_at": false}),
+        );
+
+        let sent = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(event) = sink.0.lock().unwrap().first() {
+                    return event.clone();
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the event was never sent");
+        assert_eq!(sent["event"], "todo_created");
+        assert_eq!(sent["schema_version"], schema::VERSION);
+        assert_eq!(
+            sent["properties"],
+            json!({"via": "api", "has_start_at": false})
+        );
+        let distinct_id = sent["distinct_id"].as_str().unwrap();
+        assert_eq!(distinct_id, analytics.anonymize(user_id));
+        assert!(!distinct_id.contains(&user_id.simple().to_string()));
+        assert_eq!(sink.0.lock().unwrap().len(), 1);
+    }
+}
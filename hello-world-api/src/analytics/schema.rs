@@ -0,0 +1,192 @@
+//! Registry of the analytics events: every event name with the properties
+//! it carries. Properties are booleans, counts or one of a fixed set of
+//! strings, so no free text such as a todo's text or a search term can end up
+//! in an event. Events are checked against it before they are queued.
+
+use serde_json::{Map, Value};
+
+/// Sent with every event; bumped whenever an event changes incompatibly.
+pub const VERSION: u32 = 1;
+
+#[derive(Clone, Copy)]
+pub enum Property {
+    Bool,
+    Count,
+    OneOf(&'static [&'static str]),
+}
+
+pub struct EventSchema {
+    pub name: &'static str,
+    pub properties: &'static [(&'static str, Property)],
+}
+
+pub const EVENTS: &[EventSchema] = &[
+    EventSchema {
+        name: "todo_created",
+        properties: &[
+            ("via", Property::OneOf(&["api", "quick_add"])),
+            ("has_start_at", Property::Bool),
+        ],
+    },
+    EventSchema {
+        name: "search_performed",
+        properties: &[
+            (
+                "kind",
+                Property::OneOf(&["substring", "full_text", "ranked"]),
+            ),
+            ("filtered", Property::Bool),
+            ("sorted", Property::Bool),
+            ("results", Property::Count),
+        ],
+    },
+];
+
+/// Checks that `name` is registered and `properties` are exactly its
+/// properties, each of the registered kind.
+pub fn validate(name: &str, properties: &Map<String, Value>) -> Result<(), String> {
+    validate_in(EVENTS, name, properties)
+}
+
+fn validate_in(
+    events: &[EventSchema],
+    name: &str,
+    properties: &Map<String, Value>,
+) -> Result<(), String> {
+    let schema = events
+        .iter()
+        .find(|schema| schema.name == name)
+        .ok_or_else(|| format!("{name} is not a registered event"))?;
+    if properties.len() != schema.properties.len() {
+        return Err(format!(
+            "{name} takes {} properties, got {}",
+            schema.properties.len(),
+            properties.len()
+        ));
+    }
+    for (key, kind) in schema.properties {
+        let valid = match (kind, properties.get(*key)) {
+            (Property::Bool, Some(value)) => value.is_boolean(),
+            (Property::Count, Some(value)) => value.is_u64(),
+            (Property::OneOf(allowed), Some(Value::String(value))) => allowed.contains(&&**value),
+            _ => false,
+        };
+        if !valid {
+            return Err(format!("{name} has a missing or invalid {key}"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use serde_json::json;
+
+    use super::*;
+
+    const REGISTRY: &[EventSchema] = &[EventSchema {
+        name: "list_shared",
+        properties: &[
+            ("role", Property::OneOf(&["viewer", "editor"])),
+            ("with_link", Property::Bool),
+            ("members", Property::Count),
+        ],
+    }];
+
+    fn validate(name: &str, properties: Value) -> Result<(), String> {
+        let Value::Object(properties) = properties else {
+            panic!("properties must be an object");
+        };
+        validate_in(REGISTRY, name, &properties)
+    }
+
+    #[test]
+    fn registered_events_validate() {
+        assert_eq!(
+            validate(
+                "list_shared",
+                json!({"role": "editor", "with_link": false, "members": 3})
+            ),
+            Ok(())
+        );
+        // the registry the handlers emit with
+        let created = json!({"via": "quick_add", "has_start_at": true});
+        let Value::Object(created) = created else {
+            unreachable!()
+        };
+        assert_eq!(super::validate("todo_created", &created), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unknown_events() {
+        assert_eq!(
+            validate("list_deleted", json!({})),
+            Err("list_deleted is not a registered event".to_owned())
+        );
+        assert_eq!(
+            super::validate("list_shared", &Map::new()),
+            Err("list_shared is not a registered event".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_events_of_another_shape() {
+        for (properties, error) in [
+            (
+                json!({"role": "editor", "with_link": false}),
+                "list_shared takes 3 properties, got 2",
+            ),
+            (
+                json!({"role": "editor", "with_link": false, "members": 3, "text": "x"}),
+                "list_shared takes 3 properties, got 4",
+            ),
+            (
+                json!({"role": "editor", "with_link": false, "users": 3}),
+                "list_shared has a missing or invalid members",
+            ),
+            (
+                json!({"role": "owner", "with_link": false, "members": 3}),
+                "list_shared has a missing or invalid role",
+            ),
+            (
+                json!({"role": "editor", "with_link": "no", "members": 3}),
+                "list_shared has a missing or invalid with_link",
+            ),
+            (
+                json!({"role": "editor", "with_link": false, "members": -1}),
+                "list_shared has a missing or invalid members",
+            ),
+            (
+                json!({"role": "editor", "with_link": false, "members": 1.5}),
+                "list_shared has a missing or invalid members",
+            ),
+            (
+                json!({"role": null, "with_link": false, "members": 3}),
+                "list_shared has a missing or invalid role",
+            ),
+        ] {
+            assert_eq!(
+                validate("list_shared", properties.clone()),
+                Err(error.to_owned()),
+                "{properties}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_registry_is_unambiguous() {
+        let mut names = HashSet::new();
+        for schema in EVENTS {
+            assert!(names.insert(schema.name), "{} twice", schema.name);
+            let mut properties = HashSet::new();
+            for (name, kind) in schema.properties {
+                assert!(properties.insert(name), "{}.{name} twice", schema.name);
+                if let Property::OneOf(allowed) = kind {
+                    assert!(!allowed.is_empty(), "{}.{name}", schema.name);
+                }
+            }
+        }
+    }
+}
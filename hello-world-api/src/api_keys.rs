@@ -0,0 +1,388 @@
+//! API keys for machine clients, such as scripts and CI jobs, which send
+//! `X-Api-Key: <key>` instead of logging in for a bearer token. A key
+//! belongs to the user who created it, acts as them with the scopes it was
+//! created with and works until it is revoked or expires. Only its hash is
+//! stored, so the key itself is returned once, when it is created.
+//!
+//! Keys can't be given the `admin` scope, and requests authenticated with a
+//! key can't manage keys: a leaked key can't be used to mint others that
+//! outlive its revocation.
+//!
+//! A key created with a `daily_quota` can send that many requests per UTC
+//! day. Every request it authenticates is counted, and the responses carry
+//! `X-Quota-Remaining`, the requests left for the day, and `X-Quota-Reset`,
+//! the Unix time the count starts over. Requests over the quota are answered
+//! with a 429 until then.
+
+use std::sync::Arc;
+
+use axum::{
+    http::{HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tokio::sync::OnceCell;
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{self, AuthUser, Scope},
+    error::ApiError,
+    extract::{Json, Path},
+};
+
+/// Every key starts with this, so it is recognizable in a leaked config.
+const KEY_PREFIX: &str = "tdk_";
+
+/// How much of the key [`ApiKeyView::prefix`] shows, [`KEY_PREFIX`] included.
+const SHOWN_CHARS: usize = 12;
+
+const MAX_NAME_CHARS: usize = 100;
+
+/// `last_used_at` is only brought up to date this often, rather than with
+/// every request.
+const USE_RESOLUTION: Duration = Duration::minutes(1);
+
+const X_QUOTA_REMAINING: &str = "x-quota-remaining";
+const X_QUOTA_RESET: &str = "x-quota-reset";
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateApiKey {
+    /// What the key is for, stored trimmed; 1 to 100 characters.
+    #[schema(example = "nightly backup")]
+    name: String,
+    /// Space-separated scopes, `todos:read` for a read-only key. By default
+    /// `todos:read todos:write`.
+    #[serde(default)]
+    #[schema(example = "todos:read")]
+    scope: Option<String>,
+    /// When the key stops working; never by default.
+    expires_at: Option<DateTime<Utc>>,
+    /// Requests the key may send per UTC day; any number by default.
+    #[schema(minimum = 1, example = 1000)]
+    daily_quota: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ApiKeyView {
+    id: uuid::Uuid,
+    name: String,
+    /// The start of the key, to tell keys apart.
+    #[schema(example = "tdk_3f9a0c1b")]
+    prefix: String,
+    scope: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    daily_quota: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedApiKey {
+    /// The key to send as `X-Api-Key`. Only returned here, it can't be
+    /// looked up later.
+    key: String,
+    #[serde(flatten)]
+    view: ApiKeyView,
+}
+
+/// A live API key, as [`verify`] found it.
+pub struct VerifiedKey {
+    pub id: uuid::Uuid,
+    /// The user the key acts as.
+    pub user_id: uuid::Uuid,
+    pub scopes: Vec<Scope>,
+    pub daily_quota: Option<i32>,
+}
+
+/// The user an API key acts as and the scopes it has, if it is a live key.
+pub async fn verify(pg: &PgPool, key: &str) -> Result<Option<VerifiedKey>, sqlx::Error> {
+    let hash = Sha256::digest(key.trim().as_bytes()).to_vec();
+    let found = sqlx::query!(
+        r#"select id, user_id, scope, last_used_at, daily_quota from "api_key"
+        where key_hash = $1 and (expires_at is null or expires_at > now())"#,
+        hash,
+    )
+    .fetch_optional(pg)
+    .await?;
+    let Some(found) = found else {
+        return Ok(None);
+    };
+    let stale = found
+        .last_used_at
+        .is_none_or(|used| Utc::now() - used > USE_RESOLUTION);
+    if stale {
+        // not worth failing or delaying the request for, and read-only
+        // replicas can't record it anyway
+        let pg = pg.clone();
+        tokio::spawn(async move {
+            let result = sqlx::query!(
+                r#"update "api_key" set last_used_at = now() where id = $1"#,
+                found.id,
+            )
+            .execute(&pg)
+            .await;
+            if let Err(err) = result {
+                debug!("Fail to record the use of API key {}: {:?}", found.id, err);
+            }
+        });
+    }
+    let scopes = found
+        .scope
+        .split_whitespace()
+        .filter_map(Scope::parse)
+        .collect();
+    Ok(Some(VerifiedKey {
+        id: found.id,
+        user_id: found.user_id,
+        scopes,
+        daily_quota: found.daily_quota,
+    }))
+}
+
+/// A key's use of its daily quota, counting the request at hand.
+#[derive(Clone, Copy)]
+pub struct QuotaUse {
+    quota: i32,
+    used: i32,
+    /// When the count starts over, the next UTC midnight.
+    pub reset_at: DateTime<Utc>,
+}
+
+impl QuotaUse {
+    pub fn exceeded(&self) -> bool {
+        self.used > self.quota
+    }
+
+    fn remaining(&self) -> i32 {
+        (self.quota - self.used).max(0)
+    }
+}
+
+/// Counts a request of the key against the day it is sent on, the quota's
+/// use if the key has one.
+pub async fn count_use(pg: &PgPool, key: &VerifiedKey) -> Result<Option<QuotaUse>, sqlx::Error> {
+    // the right-hand sides read the row as it was, so the count starts
+    // over on the key's first request of a day
+    let counted = sqlx::query!(
+        r#"update "api_key"
+        set used_on = (now() at time zone 'UTC')::date,
+            used_today = case when used_on = (now() at time zone 'UTC')::date
+                then used_today + 1 else 1 end
+        where id = $1
+        returning used_today, used_on as "used_on!""#,
+        key.id,
+    )
+    .fetch_one(pg)
+    .await?;
+    Ok(key.daily_quota.map(|quota| QuotaUse {
+        quota,
+        used: counted.used_today,
+        reset_at: next_day(counted.used_on),
+    }))
+}
+
+fn next_day(day: NaiveDate) -> DateTime<Utc> {
+    (day + Duration::days(1))
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight exists")
+        .and_utc()
+}
+
+/// Handed down with requests sent with an API key, for the authentication
+/// to count the request once, however many extractors authenticate it, and
+/// for [`quota_headers`] to report the quota's use.
+#[derive(Clone, Default)]
+pub struct KeyUsage(Arc<OnceCell<Option<QuotaUse>>>);
+
+impl KeyUsage {
+    pub async fn count(
+        &self,
+        pg: &PgPool,
+        key: &VerifiedKey,
+    ) -> Result<Option<QuotaUse>, sqlx::Error> {
+        self.0.get_or_try_init(|| count_use(pg, key)).await.copied()
+    }
+}
+
+/// Adds `X-Quota-Remaining` and `X-Quota-Reset` to the responses to
+/// requests sent with an API key that has a daily quota.
+pub async fn quota_headers<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    if !auth::uses_api_key(req.headers()) {
+        return next.run(req).await;
+    }
+    let usage = KeyUsage::default();
+    req.extensions_mut().insert(usage.clone());
+    let mut response = next.run(req).await;
+    if let Some(Some(quota)) = usage.0.get() {
+        let headers = response.headers_mut();
+        headers.insert(X_QUOTA_REMAINING, HeaderValue::from(quota.remaining()));
+        headers.insert(X_QUOTA_RESET, HeaderValue::from(quota.reset_at.timestamp()));
+    }
+    response
+}
+
+/// Keys are managed with a bearer token only, see the module docs.
+fn forbid_keys(headers: &HeaderMap) -> Result<(), ApiError> {
+    match auth::uses_api_key(headers) {
+        true => Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "API keys are managed with a bearer token, not an API key",
+        )),
+        false => Ok(()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/api-keys",
+    tag = "auth",
+    request_body = CreateApiKey,
+    responses(
+        (status = 201, description = "The key, shown this once", body = CreatedApiKey),
+        (status = 400, description = "Empty or too long name, an unknown or the `admin` scope, `expires_at` in the past, or a `daily_quota` below 1", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Sent with an API key rather than a bearer token", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create(
+    pg: Extension<PgPool>,
+    headers: HeaderMap,
+    AuthUser(user_id): AuthUser,
+    Json(body): Json<CreateApiKey>,
+) -> axum::response::Response {
+    if let Err(err) = forbid_keys(&headers) {
+        return err.into_response();
+    }
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("name must be 1 to {MAX_NAME_CHARS} characters"),
+        )
+        .into_response();
+    }
+    let scopes = match body.scope.as_deref().map(auth::parse_scopes).transpose() {
+        Ok(Some(scopes)) if scopes.contains(&Scope::Admin) => {
+            return ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "API keys can't have the admin scope",
+            )
+            .into_response()
+        }
+        Ok(scopes) => scopes.unwrap_or_else(|| vec![Scope::TodosRead, Scope::TodosWrite]),
+        Err(err) => return err.into_response(),
+    };
+    if body.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return ApiError::new(StatusCode::BAD_REQUEST, "expires_at must be in the future")
+            .into_response();
+    }
+    if body.daily_quota.is_some_and(|quota| quota < 1) {
+        return ApiError::new(StatusCode::BAD_REQUEST, "daily_quota must be at least 1")
+            .into_response();
+    }
+
+    let key = format!(
+        "{KEY_PREFIX}{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let result = sqlx::query_as!(
+        ApiKeyView,
+        r#"insert into "api_key" (user_id, name, key_hash, prefix, scope, expires_at, daily_quota)
+        values ($1, $2, $3, $4, $5, $6, $7)
+        returning id, name, prefix, scope, created_at, expires_at, last_used_at, daily_quota"#,
+        user_id,
+        name,
+        Sha256::digest(key.as_bytes()).to_vec(),
+        &key[..SHOWN_CHARS],
+        auth::join(&scopes),
+        body.expires_at,
+        body.daily_quota,
+    )
+    .fetch_one(&*pg)
+    .await;
+    match result {
+        Ok(view) => (StatusCode::CREATED, Json(CreatedApiKey { key, view })).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/api-keys",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The user's keys, newest first, without the keys themselves", body = Vec<ApiKeyView>),
+        (status = 403, description = "Sent with an API key rather than a bearer token", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list(
+    pg: Extension<PgPool>,
+    headers: HeaderMap,
+    AuthUser(user_id): AuthUser,
+) -> axum::response::Response {
+    if let Err(err) = forbid_keys(&headers) {
+        return err.into_response();
+    }
+    let result = sqlx::query_as!(
+        ApiKeyView,
+        r#"select id, name, prefix, scope, created_at, expires_at, last_used_at, daily_quota
+        from "api_key"
+        where user_id = $1
+        order by created_at desc, id"#,
+        user_id,
+    )
+    .fetch_all(&*pg)
+    .await;
+    match result {
+        Ok(keys) => Json(keys).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Revokes the key at once: requests sending it are refused from now on.
+#[utoipa::path(
+    delete,
+    path = "/auth/api-keys/{id}",
+    tag = "auth",
+    params(
+        ("id" = uuid::Uuid, Path, description = "API key id"),
+    ),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 403, description = "Sent with an API key rather than a bearer token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such key", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn revoke(
+    pg: Extension<PgPool>,
+    headers: HeaderMap,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    if let Err(err) = forbid_keys(&headers) {
+        return err.into_response();
+    }
+    let result = sqlx::query!(
+        r#"delete from "api_key" where id = $1 and user_id = $2"#,
+        id,
+        user_id,
+    )
+    .execute(&*pg)
+    .await;
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            ApiError::from(sqlx::Error::RowNotFound).into_response()
+        }
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
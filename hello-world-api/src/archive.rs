@@ -0,0 +1,268 @@
+//! Archive of done todos. `POST /todos/archive-completed` moves the user's
+//! todos done for long enough out of the `todo` table into `archived_todo`,
+//! keeping their text, dates, priority, list and tag names; their
+//! checklists, links, shares, history and attachments are removed as when
+//! they are purged. Archived todos are only listed, with
+//! `GET /todos/archived`, and can't be brought back.
+
+use std::sync::Arc;
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use tracing::{info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    attachments::storage::Storage,
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{Json, Query},
+    models::Priority,
+};
+
+/// Most todos archived in one transaction; the next ones are archived in
+/// the next, so archiving a large backlog doesn't hold its locks for long.
+const BATCH_SIZE: i64 = 500;
+
+const DEFAULT_OLDER_THAN_DAYS: i32 = 30;
+
+const MAX_LIMIT: i64 = 100;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ArchiveCompleted {
+    /// Only the todos done more than this many days ago, 30 by default.
+    older_than_days: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Archived {
+    /// Todos moved to the archive.
+    archived: i64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListArchived {
+    /// 1 to 100, 20 by default.
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ArchivedTodo {
+    id: uuid::Uuid,
+    text: String,
+    priority: Option<Priority>,
+    due_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    list_id: Option<uuid::Uuid>,
+    /// The names of the tags it had.
+    tags: Vec<String>,
+    archived_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ArchivedTodoPage {
+    /// Archived todos, over all pages.
+    total: i64,
+    items: Vec<ArchivedTodo>,
+}
+
+/// Archives the user's todos done more than `older_than_days` ago, in
+/// batches of their own transaction. They are published as deleted.
+#[utoipa::path(
+    post,
+    path = "/todos/archive-completed",
+    tag = "todos",
+    request_body = ArchiveCompleted,
+    responses(
+        (status = 200, description = "How many todos were archived", body = Archived),
+        (status = 400, description = "`older_than_days` out of range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn archive_completed(
+    AuthUser(user_id): AuthUser,
+    pg: Extension<PgPool>,
+    Extension(events): Extension<Events>,
+    Extension(storage): Extension<Arc<dyn Storage>>,
+    Json(body): Json<ArchiveCompleted>,
+) -> axum::response::Response {
+    let older_than_days = body.older_than_days.unwrap_or(DEFAULT_OLDER_THAN_DAYS);
+    if !(0..=36500).contains(&older_than_days) {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "older_than_days must be between 0 and 36500",
+        )
+        .into_response();
+    }
+    let mut archived = 0;
+    loop {
+        // a failed batch leaves those archived before it archived
+        let batch = async {
+            let mut tx = pg.begin().await?;
+            let batch = archive(&mut tx, user_id, older_than_days).await?;
+            for id in &batch.ids {
+                events.deleted(&mut tx, user_id, *id).await?;
+            }
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>(batch)
+        }
+        .await;
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(err) => return ApiError::from(err).into_response(),
+        };
+        archived += batch.ids.len() as i64;
+        // the rows went with their todos, a blob left behind is only
+        // wasted space
+        for key in &batch.storage_keys {
+            if let Err(err) = storage.delete(key).await {
+                warn!("Fail to delete attachment {key}: {:?}", err);
+            }
+        }
+        if (batch.ids.len() as i64) < BATCH_SIZE {
+            break;
+        }
+    }
+    if archived > 0 {
+        info!("Archived {archived} done todos of user {user_id}");
+    }
+    Json(Archived { archived }).into_response()
+}
+
+struct Batch {
+    /// The todos archived.
+    ids: Vec<uuid::Uuid>,
+    /// Of the attachments they had.
+    storage_keys: Vec<String>,
+}
+
+/// Moves a batch of the user's todos done longer than `older_than_days`
+/// ago to the archive. Todos merged into them, which point at them, are
+/// removed with them.
+async fn archive(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    older_than_days: i32,
+) -> Result<Batch, sqlx::Error> {
+    // todos done before completed_at was kept have their last change
+    let ids = sqlx::query_scalar!(
+        r#"select id from "todo"
+        where user_id = $1 and is_done and merged_into is null and deleted_at is null
+            and coalesce(completed_at, updated_at) < now() - make_interval(days => $2)
+        order by id
+        limit $3
+        for update skip locked"#,
+        user_id,
+        older_than_days,
+        BATCH_SIZE,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    if ids.is_empty() {
+        return Ok(Batch {
+            ids,
+            storage_keys: Vec::new(),
+        });
+    }
+    sqlx::query!(
+        r#"insert into "archived_todo"
+            (id, user_id, todo_text, priority, due_at, completed_at, created_at, list_id, tags)
+        select t.id, t.user_id, t.todo_text, t.priority, t.due_at, t.completed_at, t.created_at,
+            t.list_id,
+            array(
+                select g.name from "todo_tag" tt join "tag" g on g.id = tt.tag_id
+                where tt.todo_id = t.id
+                order by g.name
+            )
+        from "todo" t
+        where t.id = any($1)"#,
+        &ids,
+    )
+    .execute(&mut *conn)
+    .await?;
+    let storage_keys = sqlx::query_scalar!(
+        r#"select storage_key from "attachment" where todo_id = any($1)"#,
+        &ids,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    // archived todos stay in their user's completed_count
+    sqlx::query("select set_config('app.archiving', 'on', true)")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!(
+        r#"delete from "todo" where id = any($1) or merged_into = any($1)"#,
+        &ids,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(Batch { ids, storage_keys })
+}
+
+/// The user's archived todos, the last archived first.
+#[utoipa::path(
+    get,
+    path = "/todos/archived",
+    tag = "todos",
+    params(
+        ListArchived,
+    ),
+    responses(
+        (status = 200, description = "A page of archived todos", body = ArchivedTodoPage),
+        (status = 400, description = "`limit` out of range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list(
+    AuthUser(user_id): AuthUser,
+    pg: Extension<PgPool>,
+    Query(params): Query<ListArchived>,
+) -> axum::response::Response {
+    let limit = params.limit.unwrap_or(20);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_LIMIT}"),
+        )
+        .into_response();
+    }
+    let query = sqlx::query!(
+        r#"select id, todo_text, priority as "priority: Priority", due_at, completed_at,
+            created_at, list_id, tags, archived_at,
+            count(*) over () as "total!"
+        from "archived_todo"
+        where user_id = $1
+        order by archived_at desc, id
+        limit $2
+        offset $3"#,
+        user_id,
+        limit,
+        params.offset.unwrap_or(0).max(0),
+    );
+    let rows = match query.fetch_all(&*pg).await {
+        Ok(rows) => rows,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let total = rows.first().map_or(0, |row| row.total);
+    let items = rows
+        .into_iter()
+        .map(|row| ArchivedTodo {
+            id: row.id,
+            text: row.todo_text,
+            priority: row.priority,
+            due_at: row.due_at,
+            completed_at: row.completed_at,
+            created_at: row.created_at,
+            list_id: row.list_id,
+            tags: row.tags,
+            archived_at: row.archived_at,
+        })
+        .collect();
+    Json(ArchivedTodoPage { total, items }).into_response()
+}
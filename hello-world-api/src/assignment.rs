@@ -0,0 +1,110 @@
+//! Assigning todos. `POST /todos/:id/assign` gives a todo to a member of
+//! the [workspace](crate::workspaces) it belongs to, or to the user for a
+//! todo of their own account, and `GET /todos?assignee=me` lists those
+//! assigned to the requester. The assignee is sent an `assigned` event, and
+//! a `due_changed` one whenever the todo's due date is changed.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{Json, Path},
+    models::{Priority, ToDoView, Todo},
+    tags::Tag,
+    tx::Tx,
+};
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Assign {
+    /// The member to assign the todo to, null to unassign it.
+    assignee_id: Option<uuid::Uuid>,
+}
+
+/// The user `?assignee=` names: `me` for `requester`, or a user id.
+pub fn assignee(value: &str, requester: uuid::Uuid) -> Result<uuid::Uuid, ApiError> {
+    match value {
+        "me" => Ok(requester),
+        id => id.parse().map_err(|_| {
+            ApiError::new(StatusCode::BAD_REQUEST, "assignee must be me or a user id")
+        }),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/assign",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    request_body = Assign,
+    responses(
+        (status = 200, description = "The assigned todo", body = ToDoView),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "The assignee isn't a member of the todo's workspace, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn assign(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+    Json(body): Json<Assign>,
+) -> axum::response::Response {
+    if let Some(assignee_id) = body.assignee_id {
+        let member = sqlx::query_scalar!(
+            r#"select $1::uuid = $2 or exists (
+                select from "workspace_member" where workspace_id = $2 and user_id = $1
+            ) as "member!""#,
+            assignee_id,
+            user_id,
+        )
+        .fetch_one(&mut *tx)
+        .await;
+        match member {
+            Ok(true) => {}
+            Ok(false) => {
+                return ApiError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "The assignee isn't a member of the workspace",
+                )
+                .into_response()
+            }
+            Err(err) => return ApiError::from(err).into_response(),
+        }
+    }
+    let result = sqlx::query_as!(
+        Todo,
+        r#"update "todo" set assignee_id = $1
+        where id = $2 and user_id = $3 and merged_into is null and deleted_at is null
+        returning id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, null::timestamptz as deleted_at, null::jsonb as field_modified,
+            todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+            todo_completion(id) as completion_percent, null::text as shared_by"#,
+        body.assignee_id,
+        id,
+        user_id,
+    )
+    .fetch_one(&mut *tx)
+    .await;
+    let todo = match result {
+        Ok(todo) => todo,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    if let Err(err) = events.updated(&mut tx, user_id, &todo).await {
+        return ApiError::from(err).into_response();
+    }
+    if let Some(assignee_id) = todo.assignee_id {
+        if let Err(err) = events.assigned(&mut tx, assignee_id, &todo).await {
+            return ApiError::from(err).into_response();
+        }
+    }
+    (StatusCode::OK, Json(ToDoView::from(todo))).into_response()
+}
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{Json, Path},
+    outbound::{self, Outbound},
+};
+
+/// Something that can propose how to split a task into smaller steps.
+#[async_trait]
+pub trait TaskAssistant: Send + Sync {
+    async fn suggest_subtasks(&self, task: &str) -> anyhow::Result<Vec<String>>;
+}
+
+/// Provider speaking the OpenAI chat completions protocol, which most hosted
+/// and self-hosted LLM servers implement. Configured with `LLM_API_KEY`,
+/// `LLM_API_URL` and `LLM_MODEL`.
+pub struct ChatCompletionsAssistant {
+    http: Outbound,
+    url: String,
+    api_key: String,
+    model: String,
+}
+
+impl ChatCompletionsAssistant {
+    /// `None` unless `LLM_API_KEY` is set.
+    pub fn from_env(http: Outbound) -> anyhow::Result<Option<Self>> {
+        let Ok(api_key) = std::env::var("LLM_API_KEY") else {
+            return Ok(None);
+        };
+        Ok(Some(ChatCompletionsAssistant {
+            http,
+            url: std::env::var("LLM_API_URL")
+                .unwrap_or_else(|_| "https://api.openai.com/v1/chat/completions".to_owned()),
+            api_key,
+            model: std::env::var("LLM_MODEL").unwrap_or_else(|_| "gpt-4o-mini".to_owned()),
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+struct Completion {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: Message,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    content: String,
+}
+
+#[async_trait]
+impl TaskAssistant for ChatCompletionsAssistant {
+    async fn suggest_subtasks(&self, task: &str) -> anyhow::Result<Vec<String>> {
+        let body = serde_json::json!({
+            "model": self.model,
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "Break the user's task into 3 to 7 short, concrete subtasks. \
+                                Answer with a JSON array of strings and nothing else.",
+                },
+                { "role": "user", "content": task },
+            ],
+        });
+        let completion: Completion = self
+            .http
+            .send(&outbound::LLM, Method::POST, &self.url, |request| {
+                request.bearer_auth(&self.api_key).json(&body)
+            })
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let content = completion
+            .choices
+            .into_iter()
+            .next()
+            .context("completion has no choices")?
+            .message
+            .content;
+        // models like to wrap JSON in a markdown code fence
+        let json = content
+            .trim()
+            .trim_start_matches("```json")
+            .trim_start_matches("```")
+            .trim_end_matches("```");
+        let suggestions: Vec<String> =
+            serde_json::from_str(json).context("completion is not a JSON array of strings")?;
+        Ok(suggestions
+            .into_iter()
+            .map(|suggestion| suggestion.trim().to_owned())
+            .filter(|suggestion| !suggestion.is_empty())
+            .collect())
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BreakdownView {
+    todo_id: uuid::Uuid,
+    /// Proposed subtasks; nothing is created until the client confirms them.
+    suggestions: Vec<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/breakdown",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    responses(
+        (status = 200, description = "Suggested subtasks", body = BreakdownView),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "No task assistant is configured or it failed", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn breakdown(
+    pg: Extension<PgPool>,
+    Extension(assistant): Extension<Option<Arc<dyn TaskAssistant>>>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    let Some(assistant) = assistant else {
+        return ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No task assistant is configured",
+        )
+        .into_response();
+    };
+    let text = sqlx::query_scalar::<_, String>(
+        r#"select todo_text from "todo" where id = $1 and user_id = $2 and deleted_at is null"#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_one(&*pg)
+    .await;
+    let text = match text {
+        Ok(text) => text,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    match assistant.suggest_subtasks(&text).await {
+        Ok(suggestions) => (
+            StatusCode::OK,
+            Json(BreakdownView {
+                todo_id: id,
+                suggestions,
+            }),
+        )
+            .into_response(),
+        Err(err) => {
+            error!("Fail to get subtask suggestions {:?}", err);
+            ApiError::new(StatusCode::BAD_GATEWAY, "Task assistant failed").into_response()
+        }
+    }
+}
@@ -0,0 +1,461 @@
+//! Files attached to a todo, such as a receipt or a screenshot, uploaded as
+//! `multipart/form-data`. Their metadata is kept in the `attachment` table
+//! and their contents in [`storage`], so large files stay out of Postgres.
+//!
+//! Only the types in [`ALLOWED_TYPES`] are taken, up to
+//! [`MAX_ATTACHMENT_BYTES`], and the content must look like the type it is
+//! declared as: downloads are served with that type, so an HTML page posing
+//! as a PNG can't run in the API's origin. Attachments are removed from the
+//! storage with their todo when it is purged or archived.
+
+pub mod storage;
+
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgExecutor, PgPool};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{Json, Multipart, Path},
+};
+
+use self::storage::Storage;
+
+/// Largest file taken.
+pub const MAX_ATTACHMENT_BYTES: usize = 10 * 1024 * 1024;
+
+/// Largest upload body, the file and its multipart framing.
+pub const MAX_UPLOAD_BYTES: usize = MAX_ATTACHMENT_BYTES + 64 * 1024;
+
+/// Most attachments a todo can have.
+const MAX_PER_TODO: i64 = 20;
+
+const MAX_FILENAME_CHARS: usize = 255;
+
+/// The types attachments may have, each with the bytes its files start
+/// with, if it has such a signature. Text types must be UTF-8 instead.
+const ALLOWED_TYPES: &[(&str, Option<&[u8]>)] = &[
+    ("image/png", Some(b"\x89PNG\r\n\x1a\n")),
+    ("image/jpeg", Some(b"\xff\xd8\xff")),
+    ("image/gif", Some(b"GIF8")),
+    ("image/webp", Some(b"RIFF")),
+    ("application/pdf", Some(b"%PDF-")),
+    ("application/zip", Some(b"PK\x03\x04")),
+    ("text/plain", None),
+    ("text/csv", None),
+    ("text/markdown", None),
+];
+
+/// The multipart form of an upload.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct UploadAttachment {
+    /// The file, with its name and type in the part's headers.
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Attachment {
+    id: uuid::Uuid,
+    todo_id: uuid::Uuid,
+    #[schema(example = "receipt.pdf")]
+    filename: String,
+    #[schema(example = "application/pdf")]
+    content_type: String,
+    /// In bytes.
+    size: i64,
+    created_at: DateTime<Utc>,
+}
+
+/// Whether the todo is the user's and neither deleted nor merged.
+async fn has_todo(pg: &PgPool, user_id: uuid::Uuid, todo_id: uuid::Uuid) -> Result<(), ApiError> {
+    let found = sqlx::query_scalar!(
+        r#"select exists (
+            select 1 from "todo"
+            where id = $1 and user_id = $2 and merged_into is null and deleted_at is null
+        ) as "found!""#,
+        todo_id,
+        user_id,
+    )
+    .fetch_one(pg)
+    .await?;
+    match found {
+        true => Ok(()),
+        false => Err(ApiError::from(sqlx::Error::RowNotFound)),
+    }
+}
+
+/// Refuses another attachment to a todo that has as many as it may.
+async fn has_room(db: impl PgExecutor<'_>, todo_id: uuid::Uuid) -> Result<(), ApiError> {
+    let count = sqlx::query_scalar!(
+        r#"select count(*) as "count!" from "attachment" where todo_id = $1"#,
+        todo_id,
+    )
+    .fetch_one(db)
+    .await?;
+    if count >= MAX_PER_TODO {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("A todo can have at most {MAX_PER_TODO} attachments"),
+        ));
+    }
+    Ok(())
+}
+
+/// The last component of the name the client sent, without control
+/// characters.
+fn clean_filename(name: Option<&str>) -> String {
+    let name = name
+        .unwrap_or_default()
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_FILENAME_CHARS)
+        .collect();
+    match name.trim() {
+        "" => "attachment".to_owned(),
+        name => name.to_owned(),
+    }
+}
+
+/// Why `bytes` can't be attached as `content_type`, if they can't.
+fn check_content(content_type: &str, bytes: &[u8]) -> Result<(), ApiError> {
+    let Some((_, signature)) = ALLOWED_TYPES
+        .iter()
+        .find(|(allowed, _)| *allowed == content_type)
+    else {
+        let allowed: Vec<_> = ALLOWED_TYPES.iter().map(|(allowed, _)| *allowed).collect();
+        return Err(ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!(
+                "Files of type {content_type} can't be attached, only {}",
+                allowed.join(", ")
+            ),
+        ));
+    };
+    let looks_right = match signature {
+        // a RIFF container holds all sorts, WebP says so at byte 8
+        Some(_) if content_type == "image/webp" => {
+            bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP")
+        }
+        Some(signature) => bytes.starts_with(signature),
+        None => std::str::from_utf8(bytes).is_ok(),
+    };
+    match looks_right {
+        true => Ok(()),
+        false => Err(ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("The file's content isn't {content_type}"),
+        )),
+    }
+}
+
+/// `Content-Disposition` of a download, with the name percent-encoded for
+/// clients that take UTF-8 names and an ASCII one for the others.
+fn content_disposition(filename: &str) -> String {
+    let ascii: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = filename
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect();
+    format!("attachment; filename=\"{ascii}\"; filename*=UTF-8''{encoded}")
+}
+
+/// Attaches the `file` part of the form to the todo.
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/attachments",
+    tag = "attachments",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    request_body(content = UploadAttachment, content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "The attachment's metadata", body = Attachment),
+        (status = 400, description = "No `file` part, or an empty file", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "The todo has as many attachments as it may", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 413, description = "The file is larger than 10 MiB", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 415, description = "A type that isn't allowed, or content that isn't of the declared type", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn upload(
+    pg: Extension<PgPool>,
+    Extension(storage): Extension<Arc<dyn Storage>>,
+    AuthUser(user_id): AuthUser,
+    Path(todo_id): Path<uuid::Uuid>,
+    Multipart(mut form): Multipart,
+) -> axum::response::Response {
+    let result = async {
+        has_todo(&pg, user_id, todo_id).await?;
+        // refused before the upload, and again once the todo is locked
+        has_room(&*pg, todo_id).await?;
+
+        let mut field = loop {
+            match form.next_field().await? {
+                Some(field) if field.name() == Some("file") => break field,
+                Some(_) => continue,
+                None => {
+                    return Err(ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        "The form has no file part",
+                    ))
+                }
+            }
+        };
+        let filename = clean_filename(field.file_name());
+        let content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.chunk().await? {
+            if bytes.len() + chunk.len() > MAX_ATTACHMENT_BYTES {
+                return Err(ApiError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Files can be at most {MAX_ATTACHMENT_BYTES} bytes"),
+                ));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        if bytes.is_empty() {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "The file is empty"));
+        }
+        check_content(&content_type, &bytes)?;
+
+        let id = uuid::Uuid::new_v4();
+        let key = format!("{todo_id}/{id}");
+        let size = bytes.len() as i64;
+        storage
+            .put(&key, &content_type, Bytes::from(bytes))
+            .await
+            .map_err(|err| {
+                warn!("Fail to store attachment {key}: {:?}", err);
+                ApiError::new(StatusCode::BAD_GATEWAY, "The file could not be stored")
+            })?;
+        let inserted = async {
+            let mut tx = pg.begin().await?;
+            // concurrent uploads to the todo wait for each other here, so
+            // each counts the attachments the others inserted
+            sqlx::query_scalar!(
+                r#"select id from "todo"
+                where id = $1 and user_id = $2 and merged_into is null and deleted_at is null
+                for update"#,
+                todo_id,
+                user_id,
+            )
+            .fetch_one(&mut tx)
+            .await?;
+            has_room(&mut tx, todo_id).await?;
+            let attachment = sqlx::query_as!(
+                Attachment,
+                r#"insert into "attachment"
+                    (id, todo_id, user_id, filename, content_type, size, storage_key)
+                values ($1, $2, $3, $4, $5, $6, $7)
+                returning id, todo_id, filename, content_type, size, created_at"#,
+                id,
+                todo_id,
+                user_id,
+                filename,
+                content_type,
+                size,
+                key,
+            )
+            .fetch_one(&mut tx)
+            .await?;
+            tx.commit().await?;
+            Ok(attachment)
+        }
+        .await;
+        if inserted.is_err() {
+            // nothing refers to the blob
+            if let Err(err) = storage.delete(&key).await {
+                warn!("Fail to delete unrecorded attachment {key}: {:?}", err);
+            }
+        }
+        inserted
+    }
+    .await;
+    match result {
+        Ok(attachment) => (StatusCode::CREATED, Json(attachment)).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// The todo's attachments, oldest first.
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/attachments",
+    tag = "attachments",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    responses(
+        (status = 200, description = "Oldest first", body = Vec<Attachment>),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Path(todo_id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    if let Err(err) = has_todo(&pg, user_id, todo_id).await {
+        return err.into_response();
+    }
+    let result = sqlx::query_as!(
+        Attachment,
+        r#"select id, todo_id, filename, content_type, size, created_at
+        from "attachment"
+        where todo_id = $1
+        order by created_at, id"#,
+        todo_id,
+    )
+    .fetch_all(&*pg)
+    .await;
+    match result {
+        Ok(attachments) => Json(attachments).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// The attachment's content, with the type it was uploaded as.
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/attachments/{attachment_id}",
+    tag = "attachments",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("attachment_id" = uuid::Uuid, Path, description = "Attachment id"),
+    ),
+    responses(
+        (status = 200, description = "The file, to be saved rather than shown", body = String, content_type = "application/octet-stream"),
+        (status = 404, description = "No such todo or attachment", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn download(
+    pg: Extension<PgPool>,
+    Extension(storage): Extension<Arc<dyn Storage>>,
+    AuthUser(user_id): AuthUser,
+    Path((todo_id, id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> axum::response::Response {
+    let result = async {
+        has_todo(&pg, user_id, todo_id).await?;
+        let found = sqlx::query!(
+            r#"select filename, content_type, storage_key from "attachment"
+            where id = $1 and todo_id = $2"#,
+            id,
+            todo_id,
+        )
+        .fetch_optional(&*pg)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+        let bytes = storage.get(&found.storage_key).await.map_err(|err| {
+            warn!("Fail to read attachment {}: {:?}", found.storage_key, err);
+            ApiError::new(StatusCode::BAD_GATEWAY, "The file could not be read")
+        })?;
+        let Some(bytes) = bytes else {
+            warn!(
+                "Attachment {} is missing from the storage",
+                found.storage_key
+            );
+            return Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "The file is missing",
+            ));
+        };
+        Ok((found.filename, found.content_type, bytes))
+    }
+    .await;
+    match result {
+        Ok((filename, content_type, bytes)) => (
+            [
+                (header::CONTENT_TYPE, content_type),
+                (header::CONTENT_DISPOSITION, content_disposition(&filename)),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_owned()),
+                (header::CACHE_CONTROL, "private".to_owned()),
+            ],
+            bytes,
+        )
+            .into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// Removes the attachment and its content.
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/attachments/{attachment_id}",
+    tag = "attachments",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("attachment_id" = uuid::Uuid, Path, description = "Attachment id"),
+    ),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = 404, description = "No such todo or attachment", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete(
+    pg: Extension<PgPool>,
+    Extension(storage): Extension<Arc<dyn Storage>>,
+    AuthUser(user_id): AuthUser,
+    Path((todo_id, id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> axum::response::Response {
+    let result = async {
+        has_todo(&pg, user_id, todo_id).await?;
+        let key = sqlx::query_scalar!(
+            r#"delete from "attachment" where id = $1 and todo_id = $2 returning storage_key"#,
+            id,
+            todo_id,
+        )
+        .fetch_optional(&*pg)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+        // the row is gone, so the attachment is; a blob left behind is only
+        // wasted space
+        if let Err(err) = storage.delete(&key).await {
+            warn!("Fail to delete attachment {key}: {:?}", err);
+        }
+        Ok::<_, ApiError>(())
+    }
+    .await;
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => err.into_response(),
+    }
+}
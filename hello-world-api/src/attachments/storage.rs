@@ -0,0 +1,249 @@
+//! Where attachments' contents are kept, picked with `ATTACHMENT_STORAGE`:
+//! files under `ATTACHMENT_DIR` (`disk`, the default) or objects in an S3
+//! bucket (`s3`). Their metadata stays in Postgres either way.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+use crate::outbound::{self, Outbound};
+
+/// Stores blobs by key. Keys are made of the ids of the todo and the
+/// attachment, `<todo id>/<attachment id>`, safe as paths and object names.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, content_type: &str, bytes: Bytes) -> anyhow::Result<()>;
+
+    /// `None` if there is no blob with this key.
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>>;
+
+    /// Succeeds if the blob is already gone.
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+/// `ATTACHMENT_STORAGE` and the variables of the chosen backend.
+pub fn from_env(http: Outbound) -> anyhow::Result<Arc<dyn Storage>> {
+    let storage = std::env::var("ATTACHMENT_STORAGE").unwrap_or_else(|_| "disk".to_owned());
+    match storage.as_str() {
+        "disk" => {
+            let dir = std::env::var("ATTACHMENT_DIR").unwrap_or_else(|_| "attachments".to_owned());
+            Ok(Arc::new(LocalDisk::new(dir)))
+        }
+        "s3" => {
+            let bucket = std::env::var("ATTACHMENT_S3_BUCKET")
+                .context("ATTACHMENT_S3_BUCKET is needed with ATTACHMENT_STORAGE=s3")?;
+            let region =
+                std::env::var("ATTACHMENT_S3_REGION").unwrap_or_else(|_| "us-east-1".to_owned());
+            let endpoint = std::env::var("ATTACHMENT_S3_ENDPOINT")
+                .unwrap_or_else(|_| format!("https://s3.{region}.amazonaws.com"));
+            let endpoint = Url::parse(&endpoint).context("ATTACHMENT_S3_ENDPOINT is not a URL")?;
+            Ok(Arc::new(S3 {
+                http,
+                endpoint,
+                bucket,
+                region,
+                access_key: std::env::var("AWS_ACCESS_KEY_ID")
+                    .context("AWS_ACCESS_KEY_ID is needed with ATTACHMENT_STORAGE=s3")?,
+                secret_key: std::env::var("AWS_SECRET_ACCESS_KEY")
+                    .context("AWS_SECRET_ACCESS_KEY is needed with ATTACHMENT_STORAGE=s3")?,
+                session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            }))
+        }
+        other => anyhow::bail!("ATTACHMENT_STORAGE must be disk or s3, got {other}"),
+    }
+}
+
+/// One file per blob, under a directory created when the first one is
+/// stored.
+pub struct LocalDisk {
+    root: PathBuf,
+}
+
+impl LocalDisk {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalDisk { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(Path::new(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalDisk {
+    async fn put(&self, key: &str, _content_type: &str, bytes: Bytes) -> anyhow::Result<()> {
+        let path = self.path(key);
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        // renamed into place, so a reader never sees half a file
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, &bytes)
+            .await
+            .with_context(|| format!("failed to write {}", partial.display()))?;
+        tokio::fs::rename(&partial, &path)
+            .await
+            .with_context(|| format!("failed to move {} into place", path.display()))
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        let path = self.path(key);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(Some(bytes.into())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let path = self.path(key);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("failed to delete {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An S3 bucket, or one of a compatible store such as MinIO, addressed by
+/// path (`<endpoint>/<bucket>/<key>`) and requested with SigV4-signed
+/// requests.
+struct S3 {
+    http: Outbound,
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl S3 {
+    /// Sends `method` for the object, signed over `body`.
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        content_type: Option<&str>,
+        body: Bytes,
+    ) -> anyhow::Result<reqwest::Response> {
+        let path = format!(
+            "{}/{}/{key}",
+            self.endpoint.path().trim_end_matches('/'),
+            self.bucket
+        );
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        };
+
+        let now = Utc::now();
+        let date_time = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        // signed headers in the order SigV4 wants them, sorted by name
+        let mut headers = vec![("host", host)];
+        if let Some(content_type) = content_type {
+            headers.insert(0, ("content-type", content_type.to_owned()));
+        }
+        headers.push(("x-amz-content-sha256", payload_hash.clone()));
+        headers.push(("x-amz-date", date_time.clone()));
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{method}\n{}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+            url.path()
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{date_time}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signing_key = [
+            date.as_bytes(),
+            self.region.as_bytes(),
+            b"s3",
+            b"aws4_request",
+        ]
+        .into_iter()
+        .fold(
+            format!("AWS4{}", self.secret_key).into_bytes(),
+            |key, part| hmac(&key, part),
+        );
+        let signature = hex::encode(hmac(&signing_key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key
+        );
+
+        self.http
+            .send(&outbound::STORAGE, method, url.as_str(), |request| {
+                let request = headers
+                    .iter()
+                    .filter(|(name, _)| *name != "host")
+                    .fold(request, |request, (name, value)| {
+                        request.header(*name, value)
+                    });
+                request
+                    .header("authorization", &authorization)
+                    .body(body.clone())
+            })
+            .await
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[async_trait]
+impl Storage for S3 {
+    async fn put(&self, key: &str, content_type: &str, bytes: Bytes) -> anyhow::Result<()> {
+        self.send(Method::PUT, key, Some(content_type), bytes)
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        let response = self.send(Method::GET, key, None, Bytes::new()).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.bytes().await?))
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let response = self.send(Method::DELETE, key, None, Bytes::new()).await?;
+        if response.status() != StatusCode::NOT_FOUND {
+            response.error_for_status()?;
+        }
+        Ok(())
+    }
+}
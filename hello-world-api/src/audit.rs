@@ -0,0 +1,100 @@
+//! Audit log of admins acting on behalf of other users. Every request an
+//! admin sends with `X-Act-As` is recorded before it is handled, and one that
+//! can't be recorded is refused.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::ApiError,
+    extract::{Json, Query},
+};
+
+const MAX_ENTRIES: i64 = 1000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListAuditLog {
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuditEntry {
+    id: uuid::Uuid,
+    at: DateTime<Utc>,
+    admin_id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    /// `admin <name> acting as user <name>`.
+    action: String,
+    method: String,
+    path: String,
+}
+
+/// Records that `admin_id` is sending `method path` as `user_id`.
+pub async fn record(
+    pg: &PgPool,
+    admin_id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    method: &str,
+    path: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"insert into "audit_log" (admin_id, user_id, method, path) values ($1, $2, $3, $4)"#,
+    )
+    .bind(admin_id)
+    .bind(user_id)
+    .bind(method)
+    .bind(path)
+    .execute(pg)
+    .await?;
+    Ok(())
+}
+
+/// `GET /admin/audit-log`: the latest `limit` (default 100) entries, newest
+/// first.
+#[utoipa::path(
+    get,
+    path = "/admin/audit-log",
+    tag = "admin",
+    params(
+        ListAuditLog,
+    ),
+    responses(
+        (status = 200, description = "Newest entries first", body = Vec<AuditEntry>),
+        (status = 400, description = "`limit` out of range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn list(
+    pg: Extension<PgPool>,
+    Query(params): Query<ListAuditLog>,
+) -> axum::response::Response {
+    let limit = params.limit.unwrap_or(100);
+    if !(1..=MAX_ENTRIES).contains(&limit) {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_ENTRIES}"),
+        )
+        .into_response();
+    }
+    let result = sqlx::query_as!(
+        AuditEntry,
+        r#"select l.id, l.at, l.admin_id, l.user_id,
+            'admin ' || a.username || ' acting as user ' || u.username as "action!",
+            l.method, l.path
+        from "audit_log" l
+        join "user" a on a.user_id = l.admin_id
+        join "user" u on u.user_id = l.user_id
+        order by l.at desc, l.id
+        limit $1"#,
+        limit,
+    )
+    .fetch_all(&*pg)
+    .await;
+    match result {
+        Ok(entries) => Json(entries).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
@@ -0,0 +1,775 @@
+//! Accounts and bearer tokens. `POST /auth/register` creates a user,
+//! `POST /auth/login` exchanges its password for a JWT, and handlers taking
+//! an [`AuthUser`] only run for requests carrying a valid one. Every todo
+//! belongs to the user who created it and is only visible to them, and to
+//! admins.
+//!
+//! Users have a [`Role`]. Admins may call the `/admin` endpoints, which
+//! [`require_admin`], and with `ADMIN_IMPERSONATION` on they can send
+//! `X-Act-As: <username>` to act as that user for support. Each such request
+//! is recorded in the [`audit`](crate::audit) log.
+//!
+//! Tokens carry [`Scope`]s: reads need `todos:read`, writes `todos:write`
+//! and `X-Act-As` also `admin`, as do the endpoints taking an [`AdminUser`].
+//! Machine clients can send an [API key](crate::api_keys) with scopes of its
+//! own instead of a token.
+//! `POST /auth/introspect` tells integrators whether a token is valid and
+//! what it may do.
+
+use std::{sync::Arc, time::Duration};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+use crate::{
+    api_keys::{self, KeyUsage, VerifiedKey},
+    audit,
+    error::{ApiError, ErrorCode},
+    extract::Json,
+    maintenance,
+    rate_limit::KeyCheck,
+    recording::RecordedUser,
+    workspaces,
+};
+
+const MIN_PASSWORD_CHARS: usize = 8;
+const MAX_USERNAME_CHARS: usize = 64;
+const X_ACT_AS: &str = "x-act-as";
+pub(crate) const X_API_KEY: &str = "x-api-key";
+
+/// Keys tokens are signed and checked with (`JWT_SECRET`).
+#[derive(Clone)]
+pub struct Auth(Arc<Keys>);
+
+struct Keys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    lifetime: Duration,
+    impersonation: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: uuid::Uuid,
+    iat: i64,
+    exp: i64,
+    /// Space-separated [`Scope`]s; tokens issued before scopes existed have
+    /// none and may do everything.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+}
+
+impl Claims {
+    fn scopes(&self) -> Vec<Scope> {
+        match &self.scope {
+            Some(scope) => scope.split_whitespace().filter_map(Scope::parse).collect(),
+            None => Scope::ALL.to_vec(),
+        }
+    }
+}
+
+/// What a user may do. Everyone owns their data and only sees it; admins
+/// also see everyone else's through the `/admin` endpoints, may be granted
+/// the [`Scope::Admin`] scope and act as other users.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, sqlx::Type)]
+#[sqlx(type_name = "role", rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+/// What a token may be used for. Which one a request needs follows from its
+/// method, as for [`maintenance`] mode: `GET` and the like are reads, anything
+/// else is a write.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scope {
+    /// Read the user's todos, tags and stats.
+    TodosRead,
+    /// Create, change and delete them.
+    TodosWrite,
+    /// Call the `/admin` endpoints and act as other users with `X-Act-As`;
+    /// only granted to admins.
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::TodosRead, Scope::TodosWrite, Scope::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::TodosRead => "todos:read",
+            Scope::TodosWrite => "todos:write",
+            Scope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Scope::ALL.into_iter().find(|scope| scope.as_str() == name)
+    }
+
+    /// The scope a request with `method` needs.
+    fn for_method(method: &Method) -> Self {
+        if maintenance::is_read(method) {
+            Scope::TodosRead
+        } else {
+            Scope::TodosWrite
+        }
+    }
+}
+
+/// `scopes` in the space-separated form of the `scope` claim.
+pub fn join(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 403 for a token without `scope`, naming it as RFC 6750 describes.
+fn insufficient_scope(scope: Scope) -> Response {
+    let challenge = format!(
+        r#"Bearer error="insufficient_scope", scope="{}""#,
+        scope.as_str()
+    );
+    (
+        [(header::WWW_AUTHENTICATE, challenge)],
+        ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("The token lacks the {} scope", scope.as_str()),
+        )
+        .with_code(ErrorCode::InsufficientScope),
+    )
+        .into_response()
+}
+
+impl Auth {
+    /// `impersonation` lets admins use `X-Act-As`.
+    pub fn new(secret: &[u8], lifetime: Duration, impersonation: bool) -> Self {
+        Auth(Arc::new(Keys {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            lifetime,
+            impersonation,
+        }))
+    }
+
+    /// Signs with a random secret, so tokens stop working when the process
+    /// exits and are only accepted by the instance that issued them.
+    pub fn ephemeral(lifetime: Duration, impersonation: bool) -> Self {
+        let secret = format!(
+            "{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        Auth::new(secret.as_bytes(), lifetime, impersonation)
+    }
+
+    pub fn issue(&self, user_id: uuid::Uuid, scopes: &[Scope]) -> Result<TokenView, ApiError> {
+        let now = Utc::now();
+        let expires_at = now + chrono::Duration::from_std(self.0.lifetime).unwrap_or_default();
+        let claims = Claims {
+            sub: user_id,
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+            scope: Some(join(scopes)),
+        };
+        let access_token = jsonwebtoken::encode(&Header::default(), &claims, &self.0.encoding)
+            .map_err(|err| {
+                error!("Fail to sign token {:?}", err);
+                ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Fail to sign token")
+            })?;
+        Ok(TokenView {
+            access_token,
+            token_type: "Bearer",
+            expires_at,
+            scope: join(scopes),
+        })
+    }
+
+    fn verify(&self, token: &str) -> Option<Claims> {
+        jsonwebtoken::decode::<Claims>(token, &self.0.decoding, &Validation::default())
+            .ok()
+            .map(|data| data.claims)
+    }
+
+    /// The claims of the request's bearer token, if it carries a valid one.
+    fn bearer(&self, headers: &HeaderMap) -> Option<Claims> {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| self.verify(token.trim()))
+    }
+
+    /// The user the request's bearer token was issued to, without checking
+    /// its scopes.
+    pub fn bearer_user(&self, headers: &HeaderMap) -> Option<uuid::Uuid> {
+        self.bearer(headers).map(|claims| claims.sub)
+    }
+
+    /// The user the bearer token in `headers` was issued to, if it has
+    /// `scope`: [`AuthUser`] for transports other than the router, whose
+    /// callers can't act as other users.
+    pub fn authorize(&self, headers: &HeaderMap, scope: Scope) -> Result<uuid::Uuid, ApiError> {
+        let Some(claims) = self.bearer(headers) else {
+            return Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "Missing or invalid bearer token",
+            ));
+        };
+        if !claims.scopes().contains(&scope) {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("The token lacks the {} scope", scope.as_str()),
+            )
+            .with_code(ErrorCode::InsufficientScope));
+        }
+        Ok(claims.sub)
+    }
+}
+
+/// The user a request's bearer token was issued to, or the one an admin acts
+/// as. Rejects tokens without the [`Scope`] the request needs. Requests
+/// without a bearer token may send an [API key](crate::api_keys) instead.
+/// In a [workspace](crate::workspaces) the user is a member of, it is the
+/// workspace.
+pub struct AuthUser(pub uuid::Uuid);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(auth) = parts.extensions.get::<Auth>() else {
+            error!("Auth extension is missing");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        };
+        if uses_api_key(&parts.headers) {
+            let AuthUser(user_id) = api_key_user(parts).await?;
+            return in_workspace(parts, user_id).await;
+        }
+        let Some(claims) = auth.bearer(&parts.headers) else {
+            return Err((
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                ApiError::new(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token"),
+            )
+                .into_response());
+        };
+        let scopes = claims.scopes();
+        let needed = Scope::for_method(&parts.method);
+        if !scopes.contains(&needed) {
+            return Err(insufficient_scope(needed));
+        }
+        let user_id = if !parts.headers.contains_key(X_ACT_AS) {
+            claims.sub
+        } else if !scopes.contains(&Scope::Admin) {
+            return Err(insufficient_scope(Scope::Admin));
+        } else {
+            act_as(parts, auth.0.impersonation, claims.sub)
+                .await
+                .map_err(IntoResponse::into_response)?
+        };
+        parts.extensions.insert(Actor(claims.sub));
+        in_workspace(parts, user_id).await
+    }
+}
+
+async fn in_workspace(parts: &mut Parts, user_id: uuid::Uuid) -> Result<AuthUser, Response> {
+    parts.extensions.insert(Requester(user_id));
+    if let Some(recorded) = parts.extensions.get::<RecordedUser>() {
+        recorded.found(user_id);
+    }
+    workspaces::scope(parts, user_id)
+        .await
+        .map(AuthUser)
+        .map_err(IntoResponse::into_response)
+}
+
+/// Authorization layer of the `/admin` routes: refuses requests other than
+/// an [`AdminUser`]'s with a 401 or 403.
+pub async fn require_admin<B>(_: AdminUser, request: Request<B>, next: Next<B>) -> Response {
+    next.run(request).await
+}
+
+/// Whether the request authenticates with `X-Api-Key`, sent without a bearer
+/// token, which takes precedence.
+pub fn uses_api_key(headers: &HeaderMap) -> bool {
+    headers.contains_key(X_API_KEY) && !headers.contains_key(header::AUTHORIZATION)
+}
+
+/// [`AuthUser`] by the request's API key. Keys never have the `admin` scope,
+/// so they can't act as other users either.
+async fn api_key_user(parts: &mut Parts) -> Result<AuthUser, Response> {
+    let Some(pg) = parts.extensions.get::<PgPool>() else {
+        error!("PgPool extension is missing");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    };
+    let key = parts
+        .headers
+        .get(X_API_KEY)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let verified = api_keys::verify(pg, key).await;
+    if let (Some(check), Ok(found)) = (parts.extensions.get::<KeyCheck>(), &verified) {
+        check.found(found.is_some());
+    }
+    let key = match verified {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            return Err(
+                ApiError::new(StatusCode::UNAUTHORIZED, "Invalid or expired API key")
+                    .into_response(),
+            )
+        }
+        Err(err) => return Err(ApiError::from(err).into_response()),
+    };
+    let counted = match parts.extensions.get::<KeyUsage>() {
+        Some(usage) => usage.count(pg, &key).await,
+        None => api_keys::count_use(pg, &key).await,
+    };
+    match counted {
+        Ok(Some(quota)) if quota.exceeded() => {
+            let retry_after = (quota.reset_at - Utc::now()).num_seconds().max(1) as u64;
+            return Err(ApiError {
+                retry_after: Some(retry_after),
+                ..ApiError::new(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "The API key's daily quota is used up",
+                )
+            }
+            .into_response());
+        }
+        Ok(_) => {}
+        Err(err) => return Err(ApiError::from(err).into_response()),
+    }
+    let VerifiedKey {
+        user_id, scopes, ..
+    } = key;
+    let needed = Scope::for_method(&parts.method);
+    if !scopes.contains(&needed) {
+        return Err(insufficient_scope(needed));
+    }
+    if parts.headers.contains_key(X_ACT_AS) {
+        return Err(insufficient_scope(Scope::Admin));
+    }
+    parts.extensions.insert(Actor(user_id));
+    Ok(AuthUser(user_id))
+}
+
+/// An admin: the bearer token has the `admin` scope and was issued to a user
+/// who still is one. For operator endpoints that see every user's data;
+/// `X-Act-As` doesn't apply to them.
+pub struct AdminUser;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AdminUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let (Some(auth), Some(pg)) = (
+            parts.extensions.get::<Auth>(),
+            parts.extensions.get::<PgPool>(),
+        ) else {
+            error!("Auth or PgPool extension is missing");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        };
+        let Some(claims) = auth.bearer(&parts.headers) else {
+            return Err((
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                ApiError::new(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token"),
+            )
+                .into_response());
+        };
+        if !claims.scopes().contains(&Scope::Admin) {
+            return Err(insufficient_scope(Scope::Admin));
+        }
+        match is_admin(pg, claims.sub).await {
+            Ok(true) => Ok(AdminUser),
+            Ok(false) => {
+                Err(ApiError::new(StatusCode::FORBIDDEN, "No longer an admin").into_response())
+            }
+            Err(err) => Err(ApiError::from(err).into_response()),
+        }
+    }
+}
+
+/// Who sent the request: the user its bearer token was issued to, the admin
+/// rather than the user acted as. Set once [`AuthUser`] is taken.
+#[derive(Clone, Copy)]
+pub struct Actor(pub uuid::Uuid);
+
+/// The user the request acts as, the one an admin acts as included, rather
+/// than the [workspace](crate::workspaces) [`AuthUser`] is in one. Set once
+/// [`AuthUser`] is taken.
+#[derive(Clone, Copy)]
+pub struct Requester(pub uuid::Uuid);
+
+/// The user `admin_id` names in `X-Act-As`, once the request is recorded.
+async fn act_as(
+    parts: &Parts,
+    enabled: bool,
+    admin_id: uuid::Uuid,
+) -> Result<uuid::Uuid, ApiError> {
+    let forbidden = |error: &str| ApiError::new(StatusCode::FORBIDDEN, error);
+    if !enabled {
+        return Err(forbidden("Impersonation is disabled"));
+    }
+    let Some(pg) = parts.extensions.get::<PgPool>() else {
+        error!("PgPool extension is missing");
+        return Err(ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error",
+        ));
+    };
+    let Some(username) = parts
+        .headers
+        .get(X_ACT_AS)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "X-Act-As is not a username",
+        ));
+    };
+    if !is_admin(pg, admin_id).await? {
+        return Err(forbidden("Only admins can act as other users"));
+    }
+    let user_id =
+        sqlx::query_scalar::<_, uuid::Uuid>(r#"select user_id from "user" where username = $1"#)
+            .bind(username.trim())
+            .fetch_optional(pg)
+            .await?;
+    let Some(user_id) = user_id else {
+        return Err(forbidden("X-Act-As names no user"));
+    };
+    let path = parts
+        .uri
+        .path_and_query()
+        .map_or(parts.uri.path(), |path| path.as_str());
+    audit::record(pg, admin_id, user_id, parts.method.as_str(), path).await?;
+    info!(%admin_id, %user_id, method = %parts.method, path, "Admin acting as user");
+    Ok(user_id)
+}
+
+async fn is_admin(pg: &PgPool, user_id: uuid::Uuid) -> Result<bool, sqlx::Error> {
+    let role = sqlx::query_scalar!(
+        r#"select role as "role: Role" from "user" where user_id = $1"#,
+        user_id,
+    )
+    .fetch_optional(pg)
+    .await?;
+    Ok(role == Some(Role::Admin))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct Credentials {
+    username: String,
+    password: String,
+    /// Login only: space-separated scopes to limit the token to, by default
+    /// all the user may have (`todos:read todos:write`, and `admin` for
+    /// admins).
+    #[serde(default)]
+    #[schema(example = "todos:read")]
+    scope: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserView {
+    user_id: uuid::Uuid,
+    username: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TokenView {
+    pub(crate) access_token: String,
+    token_type: &'static str,
+    expires_at: DateTime<Utc>,
+    /// Space-separated scopes the token has.
+    scope: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct Introspect {
+    token: String,
+}
+
+/// RFC 7662 style answer: only `active` for tokens that are invalid or
+/// expired.
+#[derive(Serialize, ToSchema)]
+pub struct Introspection {
+    active: bool,
+    /// Space-separated scopes the token has.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    /// The user the token was issued to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<uuid::Uuid>,
+    /// Seconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iat: Option<i64>,
+    /// Seconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<i64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/register",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 201, description = "The new user", body = UserView),
+        (status = 400, description = "Username or password too short or long", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Username is taken", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn register(
+    pg: Extension<PgPool>,
+    Json(credentials): Json<Credentials>,
+) -> axum::response::Response {
+    let (username, hash) = match new_account(credentials).await {
+        Ok(account) => account,
+        Err(err) => return err.into_response(),
+    };
+    let username = username.as_str();
+    let result = sqlx::query_scalar::<_, uuid::Uuid>(
+        r#"insert into "user" (username, password_hash) values ($1, $2) returning user_id"#,
+    )
+    .bind(username)
+    .bind(hash)
+    .fetch_one(&*pg)
+    .await;
+    match result {
+        Ok(user_id) => (
+            StatusCode::CREATED,
+            Json(UserView {
+                user_id,
+                username: username.to_owned(),
+            }),
+        )
+            .into_response(),
+        Err(sqlx::Error::Database(err)) if err.code().as_deref() == Some("23505") => {
+            ApiError::new(StatusCode::CONFLICT, "Username is taken").into_response()
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "A bearer token", body = TokenView),
+        (status = 400, description = "`scope` is empty or names an unknown scope", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Invalid username or password", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "`admin` was asked for by a user who isn't one", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn login(
+    pg: Extension<PgPool>,
+    Extension(auth): Extension<Auth>,
+    Json(credentials): Json<Credentials>,
+) -> axum::response::Response {
+    let requested = match credentials.scope.as_deref().map(parse_scopes).transpose() {
+        Ok(requested) => requested,
+        Err(err) => return err.into_response(),
+    };
+    let user_id = match verify_password(&pg, &credentials.username, credentials.password).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            return ApiError::new(StatusCode::UNAUTHORIZED, "Invalid username or password")
+                .into_response()
+        }
+        Err(err) => return err.into_response(),
+    };
+    let mut granted = vec![Scope::TodosRead, Scope::TodosWrite];
+    match is_admin(&pg, user_id).await {
+        Ok(true) => granted.push(Scope::Admin),
+        Ok(false) => {}
+        Err(err) => return ApiError::from(err).into_response(),
+    }
+    let scopes = match requested {
+        Some(requested) if requested.iter().any(|scope| !granted.contains(scope)) => {
+            return ApiError::new(
+                StatusCode::FORBIDDEN,
+                "Only admins can be granted the admin scope",
+            )
+            .into_response()
+        }
+        Some(requested) => requested,
+        None => granted,
+    };
+    match auth.issue(user_id, &scopes) {
+        Ok(token) => Json(token).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// The scopes of a login's or an API key's `scope`, without duplicates.
+pub fn parse_scopes(scope: &str) -> Result<Vec<Scope>, ApiError> {
+    let mut scopes = Vec::new();
+    for name in scope.split_whitespace() {
+        let Some(scope) = Scope::parse(name) else {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("Unknown scope {name}, expected todos:read, todos:write or admin"),
+            ));
+        };
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    if scopes.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "scope must name at least one scope",
+        ));
+    }
+    Ok(scopes)
+}
+
+/// Whether a token is valid and, if so, whose it is and what it may do. Knowing
+/// the token is all it takes, so this needs no authentication itself.
+#[utoipa::path(
+    post,
+    path = "/auth/introspect",
+    tag = "auth",
+    request_body = Introspect,
+    responses(
+        (status = 200, description = "The token's claims, or `active: false`", body = Introspection),
+    ),
+)]
+pub async fn introspect(
+    Extension(auth): Extension<Auth>,
+    Json(body): Json<Introspect>,
+) -> Json<Introspection> {
+    Json(match auth.verify(body.token.trim()) {
+        Some(claims) => Introspection {
+            active: true,
+            scope: Some(join(&claims.scopes())),
+            sub: Some(claims.sub),
+            iat: Some(claims.iat),
+            exp: Some(claims.exp),
+        },
+        None => Introspection {
+            active: false,
+            scope: None,
+            sub: None,
+            iat: None,
+            exp: None,
+        },
+    })
+}
+
+/// The trimmed username and the password hash of an account to create, once
+/// both are long enough.
+pub async fn new_account(credentials: Credentials) -> Result<(String, String), ApiError> {
+    let username = credentials.username.trim();
+    if username.is_empty() || username.chars().count() > MAX_USERNAME_CHARS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("username must be 1 to {MAX_USERNAME_CHARS} characters"),
+        ));
+    }
+    if credentials.password.chars().count() < MIN_PASSWORD_CHARS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("password must be at least {MIN_PASSWORD_CHARS} characters"),
+        ));
+    }
+    Ok((
+        username.to_owned(),
+        hash_password(credentials.password).await?,
+    ))
+}
+
+/// The Argon2 hash `password` is stored as.
+pub async fn hash_password(password: String) -> Result<String, ApiError> {
+    // hashing takes tens of milliseconds of CPU, keep it off the runtime
+    let hash = tokio::task::spawn_blocking(move || {
+        Argon2::default()
+            .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+            .map(|hash| hash.to_string())
+    })
+    .await;
+    match hash {
+        Ok(Ok(hash)) => Ok(hash),
+        _ => {
+            error!("Fail to hash password");
+            Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Fail to hash password",
+            ))
+        }
+    }
+}
+
+/// The user named `username` if `password` is theirs.
+pub async fn verify_password(
+    pg: &PgPool,
+    username: &str,
+    password: String,
+) -> Result<Option<uuid::Uuid>, ApiError> {
+    let user = sqlx::query!(
+        r#"select user_id, password_hash from "user" where username = $1"#,
+        username.trim(),
+    )
+    .fetch_optional(pg)
+    .await?;
+    // users from before accounts existed have no password to log in with
+    let Some((user_id, Some(hash))) = user.map(|user| (user.user_id, user.password_hash)) else {
+        return Ok(None);
+    };
+    let matches = tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .unwrap_or(false);
+    Ok(matches.then_some(user_id))
+}
+
+/// The user of a request with HTTP Basic credentials, for clients such as
+/// CalDAV apps that can't obtain bearer tokens.
+pub async fn basic_user(pg: &PgPool, headers: &HeaderMap) -> Result<Option<uuid::Uuid>, ApiError> {
+    let credentials = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| {
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim())
+                .ok()
+        })
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    let Some((username, password)) = credentials
+        .as_deref()
+        .and_then(|credentials| credentials.split_once(':'))
+    else {
+        return Ok(None);
+    };
+    verify_password(pg, username, password.to_owned()).await
+}
@@ -0,0 +1,232 @@
+//! Todo events published to a message broker, for other services to react
+//! to changes without polling the API. With `BROKER` set, the outbox relay
+//! sends each batch of events to the broker before marking it published, so
+//! every recorded event reaches the broker at least once; while the broker
+//! can't be reached, the events wait for it, and so do the live clients and
+//! webhooks. The Kafka and NATS clients are compiled in with the `kafka` and
+//! `nats` features.
+//!
+//! Each message is the event as live clients get it, with the recorded
+//! event's id in `Todo-Event-Id` and the user's in `Todo-User-Id`.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_trait::async_trait;
+
+use crate::outbox::Recorded;
+
+/// Where the relay publishes the events it relays.
+#[async_trait]
+pub trait Broker: Send + Sync {
+    /// Publishes `events` in order, done once the broker has them.
+    async fn publish(&self, events: &[Recorded]) -> anyhow::Result<()>;
+}
+
+/// `None` unless `BROKER` is set.
+pub fn from_env() -> anyhow::Result<Option<Arc<dyn Broker>>> {
+    let Ok(broker) = std::env::var("BROKER") else {
+        return Ok(None);
+    };
+    let url = std::env::var("BROKER_URL")
+        .with_context(|| format!("BROKER_URL is needed with BROKER={broker}"))?;
+    let topic = std::env::var("BROKER_TOPIC").unwrap_or_else(|_| "todo-events".to_owned());
+    match broker.as_str() {
+        "kafka" => kafka(url, topic).map(Some),
+        "nats" => nats(url, topic).map(Some),
+        other => anyhow::bail!("BROKER must be kafka or nats, got {other}"),
+    }
+}
+
+#[cfg(feature = "kafka")]
+fn kafka(url: String, topic: String) -> anyhow::Result<Arc<dyn Broker>> {
+    Ok(Arc::new(kafka::Kafka::new(&url, topic)))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn kafka(_url: String, _topic: String) -> anyhow::Result<Arc<dyn Broker>> {
+    anyhow::bail!("BROKER=kafka needs the server built with the kafka feature")
+}
+
+#[cfg(feature = "nats")]
+fn nats(url: String, subject: String) -> anyhow::Result<Arc<dyn Broker>> {
+    Ok(Arc::new(nats::Nats::new(url, subject)))
+}
+
+#[cfg(not(feature = "nats"))]
+fn nats(_url: String, _subject: String) -> anyhow::Result<Arc<dyn Broker>> {
+    anyhow::bail!("BROKER=nats needs the server built with the nats feature")
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use std::time::Duration;
+
+    use anyhow::Context;
+    use async_trait::async_trait;
+    use chrono::Utc;
+    use rskafka::{
+        client::{
+            partition::{Compression, PartitionClient, UnknownTopicHandling},
+            ClientBuilder,
+        },
+        record::Record,
+        BackoffConfig,
+    };
+    use tokio::sync::Mutex;
+
+    use super::Broker;
+    use crate::outbox::Recorded;
+
+    /// How long a request is retried for before the batch is given up on,
+    /// to be sent again with the next one.
+    const DEADLINE: Duration = Duration::from_secs(30);
+
+    /// Produces to a Kafka topic, `BROKER_URL` being its comma-separated
+    /// bootstrap brokers. The events of a user go to one partition, keyed by
+    /// the user's id, so they are consumed in order.
+    pub struct Kafka {
+        brokers: Vec<String>,
+        topic: String,
+        /// A client for each of the topic's partitions, once connected.
+        partitions: Mutex<Vec<PartitionClient>>,
+    }
+
+    impl Kafka {
+        pub fn new(url: &str, topic: String) -> Self {
+            Kafka {
+                brokers: url
+                    .split(',')
+                    .map(|broker| broker.trim().to_owned())
+                    .collect(),
+                topic,
+                partitions: Mutex::new(Vec::new()),
+            }
+        }
+
+        async fn connect(&self) -> anyhow::Result<Vec<PartitionClient>> {
+            let backoff = BackoffConfig {
+                deadline: Some(DEADLINE),
+                ..BackoffConfig::default()
+            };
+            let client = ClientBuilder::new(self.brokers.clone())
+                .client_id("hello-world-api")
+                .backoff_config(backoff)
+                .build()
+                .await?;
+            let mut indexes = client
+                .list_topics()
+                .await?
+                .into_iter()
+                .find(|topic| topic.name == self.topic)
+                .with_context(|| format!("No Kafka topic {}", self.topic))?
+                .partitions
+                .into_iter()
+                .collect::<Vec<_>>();
+            indexes.sort_unstable();
+            let mut partitions = Vec::with_capacity(indexes.len());
+            for index in indexes {
+                partitions.push(
+                    client
+                        .partition_client(self.topic.clone(), index, UnknownTopicHandling::Retry)
+                        .await?,
+                );
+            }
+            Ok(partitions)
+        }
+    }
+
+    #[async_trait]
+    impl Broker for Kafka {
+        async fn publish(&self, events: &[Recorded]) -> anyhow::Result<()> {
+            let mut partitions = self.partitions.lock().await;
+            if partitions.is_empty() {
+                *partitions = self.connect().await?;
+            }
+            let mut records: Vec<Vec<Record>> = vec![Vec::new(); partitions.len()];
+            for event in events {
+                let partition = (event.user_id.as_u128() % partitions.len() as u128) as usize;
+                records[partition].push(Record {
+                    key: Some(event.user_id.to_string().into_bytes()),
+                    value: Some(event.payload.clone().into_bytes()),
+                    headers: [
+                        (
+                            "Todo-Event-Id".to_owned(),
+                            event.id.to_string().into_bytes(),
+                        ),
+                        (
+                            "Todo-User-Id".to_owned(),
+                            event.user_id.to_string().into_bytes(),
+                        ),
+                    ]
+                    .into(),
+                    timestamp: Utc::now(),
+                });
+            }
+            for (client, records) in partitions.iter().zip(records) {
+                if let Err(err) = client.produce(records, Compression::NoCompression).await {
+                    // the partitions may have moved, or the topic changed
+                    partitions.clear();
+                    return Err(err.into());
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use async_nats::{header::NATS_MESSAGE_ID, Client, HeaderMap};
+    use async_trait::async_trait;
+    use tokio::sync::Mutex;
+
+    use super::Broker;
+    use crate::outbox::Recorded;
+
+    /// Publishes on a NATS subject, `BROKER_URL` being the server's URL. The
+    /// recorded event's id is also the `Nats-Msg-Id`, so a JetStream stream
+    /// on the subject drops the events published again.
+    pub struct Nats {
+        url: String,
+        subject: String,
+        /// Once connected; it reconnects by itself from then on.
+        client: Mutex<Option<Client>>,
+    }
+
+    impl Nats {
+        pub fn new(url: String, subject: String) -> Self {
+            Nats {
+                url,
+                subject,
+                client: Mutex::new(None),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Broker for Nats {
+        async fn publish(&self, events: &[Recorded]) -> anyhow::Result<()> {
+            let mut client = self.client.lock().await;
+            if client.is_none() {
+                *client = Some(async_nats::connect(&self.url).await?);
+            }
+            let client = client.as_ref().expect("connected");
+            for event in events {
+                let mut headers = HeaderMap::new();
+                headers.insert(NATS_MESSAGE_ID, event.id.to_string().as_str());
+                headers.insert("Todo-Event-Id", event.id.to_string().as_str());
+                headers.insert("Todo-User-Id", event.user_id.to_string().as_str());
+                client
+                    .publish_with_headers(
+                        self.subject.clone(),
+                        headers,
+                        event.payload.clone().into_bytes().into(),
+                    )
+                    .await?;
+            }
+            client.flush().await?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Just enough CalDAV (RFC 4791) for Apple Reminders and Thunderbird to use
+//! the todo list as a task calendar: one collection at `/caldav` holding a
+//! VTODO resource per todo.
+//!
+//! A todo's start date is its `DTSTART`. Todos have no due date, so `DUE`
+//! is neither sent nor stored.
+//!
+//! Clients log in with HTTP Basic auth and only see the todos of that user.
+
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::{auth, error::ApiError, events::Events, extract::Path, language, models::Todo};
+
+const COLLECTION: &str = "/caldav";
+
+struct CalTodo {
+    id: uuid::Uuid,
+    todo_text: String,
+    is_done: bool,
+    start_at: Option<DateTime<Utc>>,
+    external_id: Option<String>,
+}
+
+/// The properties of a VTODO that map onto a todo.
+struct VTodo {
+    summary: String,
+    completed: bool,
+    start_at: Option<DateTime<Utc>>,
+}
+
+impl CalTodo {
+    /// Resource name under the collection. Todos created over CalDAV keep
+    /// the name the client chose, everything else is addressed by id.
+    fn name(&self) -> String {
+        match self
+            .external_id
+            .as_deref()
+            .and_then(|external_id| external_id.strip_prefix("caldav:"))
+        {
+            Some(name) => name.to_owned(),
+            None => format!("{}.ics", self.id),
+        }
+    }
+
+    fn href(&self) -> String {
+        format!("{COLLECTION}/{}", self.name())
+    }
+
+    fn etag(&self) -> String {
+        let digest = Sha256::new()
+            .chain_update(self.todo_text.as_bytes())
+            .chain_update([self.is_done as u8])
+            .chain_update(
+                self.start_at
+                    .map(|at| at.timestamp())
+                    .unwrap_or_default()
+                    .to_be_bytes(),
+            )
+            .finalize();
+        format!("\"{}\"", hex::encode(&digest[..8]))
+    }
+
+    fn ical(&self) -> String {
+        let uid = self
+            .name()
+            .strip_suffix(".ics")
+            .map(str::to_owned)
+            .unwrap_or_else(|| self.name());
+        let status = if self.is_done {
+            "COMPLETED"
+        } else {
+            "NEEDS-ACTION"
+        };
+        let start = self
+            .start_at
+            .map(|at| format!("DTSTART:{}\r\n", at.format("%Y%m%dT%H%M%SZ")))
+            .unwrap_or_default();
+        format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//hello-world-api//EN\r\n\
+             BEGIN:VTODO\r\nUID:{}\r\nSUMMARY:{}\r\nSTATUS:{}\r\n{}END:VTODO\r\nEND:VCALENDAR\r\n",
+            uid,
+            escape_text(&self.todo_text),
+            status,
+            start
+        )
+    }
+}
+
+pub async fn well_known() -> Redirect {
+    Redirect::permanent(COLLECTION)
+}
+
+/// `/caldav`: the task collection itself.
+pub async fn collection(
+    pg: Extension<PgPool>,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if method == Method::OPTIONS {
+        return options();
+    }
+    let user_id = match authenticate(&pg, &headers).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    match method.as_str() {
+        "PROPFIND" => {
+            let depth_one = headers
+                .get("depth")
+                .is_some_and(|depth| depth.as_bytes() != b"0");
+            let mut responses = vec![collection_response(&pg, user_id).await];
+            if depth_one {
+                match all_todos(&pg, user_id).await {
+                    Ok(todos) => {
+                        responses.extend(todos.iter().map(|todo| todo_response(todo, false)))
+                    }
+                    Err(err) => return ApiError::from(err).into_response(),
+                }
+            }
+            multistatus(responses)
+        }
+        "REPORT" => {
+            let todos = match all_todos(&pg, user_id).await {
+                Ok(todos) => todos,
+                Err(err) => return ApiError::from(err).into_response(),
+            };
+            let body = String::from_utf8_lossy(&body);
+            // calendar-multiget names the resources it wants; calendar-query
+            // filters aren't evaluated, the collection only holds VTODOs
+            let wanted = hrefs(&body);
+            let responses = todos
+                .iter()
+                .filter(|todo| !body.contains("calendar-multiget") || wanted.contains(&todo.href()))
+                .map(|todo| todo_response(todo, true))
+                .collect();
+            multistatus(responses)
+        }
+        _ => method_not_allowed(),
+    }
+}
+
+/// `/caldav/:name`: a single VTODO.
+pub async fn resource(
+    pg: Extension<PgPool>,
+    Extension(events): Extension<Events>,
+    method: Method,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    body: Bytes,
+) -> Response {
+    if method == Method::OPTIONS {
+        return options();
+    }
+    let user_id = match authenticate(&pg, &headers).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    match method.as_str() {
+        "GET" | "HEAD" => match find_todo(&pg, user_id, &name).await {
+            Ok(Some(todo)) => (
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        "text/calendar; charset=utf-8".to_owned(),
+                    ),
+                    (header::ETAG, todo.etag()),
+                ],
+                todo.ical(),
+            )
+                .into_response(),
+            Ok(None) => StatusCode::NOT_FOUND.into_response(),
+            Err(err) => ApiError::from(err).into_response(),
+        },
+        "PROPFIND" => match find_todo(&pg, user_id, &name).await {
+            Ok(Some(todo)) => multistatus(vec![todo_response(&todo, false)]),
+            Ok(None) => StatusCode::NOT_FOUND.into_response(),
+            Err(err) => ApiError::from(err).into_response(),
+        },
+        "PUT" => {
+            let body = String::from_utf8_lossy(&body);
+            put(&pg, &events, user_id, &name, &body).await
+        }
+        _ => method_not_allowed(),
+    }
+}
+
+/// The user of the request's Basic credentials, or the `401` asking the
+/// client for them.
+async fn authenticate(pg: &PgPool, headers: &HeaderMap) -> Result<uuid::Uuid, Response> {
+    match auth::basic_user(pg, headers).await {
+        Ok(Some(user_id)) => Ok(user_id),
+        Ok(None) => Err((
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"todos\"")],
+        )
+            .into_response()),
+        Err(err) => Err(err.into_response()),
+    }
+}
+
+async fn put(
+    pg: &PgPool,
+    events: &Events,
+    user_id: uuid::Uuid,
+    name: &str,
+    body: &str,
+) -> Response {
+    let Some(vtodo) = parse_vtodo(body) else {
+        return (StatusCode::BAD_REQUEST, "Expected a VCALENDAR with a VTODO").into_response();
+    };
+    let existing = match find_todo(pg, user_id, name).await {
+        Ok(existing) => existing,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let mut tx = match pg.begin().await {
+        Ok(tx) => tx,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let result = match existing {
+        Some(todo) => sqlx::query_as!(
+            CalTodo,
+            r#"update "todo"
+            set todo_text = $1, is_done = $2,
+                completed_at = case when $2 then coalesce(completed_at, now()) end,
+                start_at = $3, search_config = $5::text::regconfig
+            where id = $4
+            returning id, todo_text, is_done, start_at, external_id"#,
+            &vtodo.summary,
+            vtodo.completed,
+            vtodo.start_at,
+            todo.id,
+            language::search_config(&vtodo.summary),
+        )
+        .fetch_one(&mut tx)
+        .await
+        .map(|todo| (StatusCode::NO_CONTENT, todo)),
+        None => sqlx::query_as!(
+            CalTodo,
+            r#"insert into "todo"
+                (user_id, todo_text, is_done, completed_at, start_at, external_id, search_config, id)
+            values ($6, $1, $2, case when $2 then now() end, $3, $4, $5::text::regconfig, $7)
+            returning id, todo_text, is_done, start_at, external_id"#,
+            &vtodo.summary,
+            vtodo.completed,
+            vtodo.start_at,
+            format!("caldav:{name}"),
+            language::search_config(&vtodo.summary),
+            user_id,
+            Todo::new_id(),
+        )
+        .fetch_one(&mut tx)
+        .await
+        .map(|todo| (StatusCode::CREATED, todo)),
+    };
+    let (status, todo) = match result {
+        Ok(result) => result,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let recorded = if status == StatusCode::CREATED {
+        events.created_id(&mut tx, user_id, todo.id).await
+    } else {
+        events.changed(&mut tx, user_id, todo.id).await
+    };
+    if let Err(err) = recorded {
+        return ApiError::from(err).into_response();
+    }
+    match tx.commit().await {
+        Ok(()) => (status, [(header::ETAG, todo.etag())]).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+async fn all_todos(pg: &PgPool, user_id: uuid::Uuid) -> Result<Vec<CalTodo>, sqlx::Error> {
+    sqlx::query_as!(
+        CalTodo,
+        r#"select id, todo_text, is_done, start_at, external_id from "todo"
+        where user_id = $1 and merged_into is null and deleted_at is null
+        order by id"#,
+        user_id,
+    )
+    .fetch_all(pg)
+    .await
+}
+
+async fn find_todo(
+    pg: &PgPool,
+    user_id: uuid::Uuid,
+    name: &str,
+) -> Result<Option<CalTodo>, sqlx::Error> {
+    let id = name
+        .strip_suffix(".ics")
+        .and_then(|id| id.parse::<uuid::Uuid>().ok());
+    sqlx::query_as!(
+        CalTodo,
+        r#"select id, todo_text, is_done, start_at, external_id from "todo"
+        where (external_id = $1 or id = $2)
+            and user_id = $3 and merged_into is null and deleted_at is null"#,
+        format!("caldav:{name}"),
+        id,
+        user_id,
+    )
+    .fetch_optional(pg)
+    .await
+}
+
+/// Collection properties, including a ctag that changes with any of the
+/// user's todos.
+async fn collection_response(pg: &PgPool, user_id: uuid::Uuid) -> String {
+    let ctag = all_todos(pg, user_id)
+        .await
+        .map(|todos| {
+            let mut hasher = Sha256::new();
+            for todo in &todos {
+                hasher.update(todo.id.as_bytes());
+                hasher.update(todo.etag().as_bytes());
+            }
+            hex::encode(&hasher.finalize()[..8])
+        })
+        .unwrap_or_default();
+    format!(
+        "<D:response><D:href>{COLLECTION}/</D:href><D:propstat><D:prop>\
+         <D:resourcetype><D:collection/><C:calendar/></D:resourcetype>\
+         <D:displayname>Todos</D:displayname>\
+         <C:supported-calendar-component-set><C:comp name=\"VTODO\"/></C:supported-calendar-component-set>\
+         <CS:getctag>{ctag}</CS:getctag>\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>"
+    )
+}
+
+fn todo_response(todo: &CalTodo, with_data: bool) -> String {
+    let data = if with_data {
+        format!(
+            "<C:calendar-data>{}</C:calendar-data>",
+            escape_xml(&todo.ical())
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:getetag>{}</D:getetag>\
+         <D:getcontenttype>text/calendar; component=vtodo</D:getcontenttype>{}\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        escape_xml(&todo.href()),
+        escape_xml(&todo.etag()),
+        data
+    )
+}
+
+fn multistatus(responses: Vec<String>) -> Response {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <D:multistatus xmlns:D=\"DAV:\" xmlns:C=\"urn:ietf:params:xml:ns:caldav\" \
+         xmlns:CS=\"http://calendarserver.org/ns/\">{}</D:multistatus>",
+        responses.concat()
+    );
+    (
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
+
+fn options() -> Response {
+    (
+        StatusCode::OK,
+        [
+            ("dav", "1, calendar-access"),
+            ("allow", "OPTIONS, GET, HEAD, PUT, PROPFIND, REPORT"),
+        ],
+    )
+        .into_response()
+}
+
+fn method_not_allowed() -> Response {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [("allow", "OPTIONS, GET, HEAD, PUT, PROPFIND, REPORT")],
+    )
+        .into_response()
+}
+
+/// Contents of every `<...href>` element, whatever namespace prefix the
+/// client used.
+fn hrefs(body: &str) -> Vec<String> {
+    let mut hrefs = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("href>") {
+        rest = &rest[start + "href>".len()..];
+        if let Some(end) = rest.find('<') {
+            if !rest[..end].trim().is_empty() {
+                hrefs.push(rest[..end].trim().to_owned());
+            }
+            rest = &rest[end..];
+        }
+    }
+    hrefs
+}
+
+/// The first VTODO in an iCalendar body.
+fn parse_vtodo(body: &str) -> Option<VTodo> {
+    // unfold continuation lines (RFC 5545 3.1) before looking at properties
+    let unfolded = body
+        .replace("\r\n ", "")
+        .replace("\r\n\t", "")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+    let mut in_vtodo = false;
+    let mut summary = None;
+    let mut completed = false;
+    let mut start_at = None;
+    for line in unfolded.lines() {
+        let line = line.trim_end_matches('\r');
+        match line {
+            "BEGIN:VTODO" => in_vtodo = true,
+            "END:VTODO" => break,
+            _ if in_vtodo => {
+                let Some((name, value)) = line.split_once(':') else {
+                    continue;
+                };
+                // drop parameters such as SUMMARY;LANGUAGE=en
+                match name.split(';').next() {
+                    Some("SUMMARY") => summary = Some(unescape_text(value)),
+                    Some("STATUS") => completed = value == "COMPLETED",
+                    Some("DTSTART") => start_at = parse_date_time(value),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    summary
+        .filter(|summary| !summary.trim().is_empty())
+        .map(|summary| VTodo {
+            summary,
+            completed,
+            start_at,
+        })
+}
+
+/// A `DATE-TIME` or `DATE` value. Floating and `TZID` times are taken as
+/// UTC, as there are no time zone definitions to resolve them with; a bare
+/// date starts at midnight.
+fn parse_date_time(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y%m%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .map(|at| at.and_utc())
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape_text(text: &str) -> String {
+    text.replace("\\n", "\n")
+        .replace("\\N", "\n")
+        .replace("\\;", ";")
+        .replace("\\,", ",")
+        .replace("\\\\", "\\")
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,91 @@
+//! Fault injection for resilience testing, compiled in with the `chaos`
+//! feature. Delays a share of requests and fails another share with a
+//! random 5xx before they reach a handler, so clients' timeouts and retries
+//! can be exercised against a flaky server.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rand::Rng;
+use tracing::warn;
+
+use crate::error::{ApiError, ErrorCode};
+
+#[derive(Clone, Copy, Default)]
+pub struct Chaos {
+    /// Percentage of requests answered with a 500, 502 or 503.
+    error_percent: f64,
+    /// Percentage of requests delayed by up to `max_latency`.
+    latency_percent: f64,
+    max_latency: Duration,
+}
+
+impl Chaos {
+    /// Reads `CHAOS_ERROR_PERCENT`, `CHAOS_LATENCY_PERCENT` and
+    /// `CHAOS_LATENCY_MS`, all of which default to zero.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let percent = |name: &str| -> anyhow::Result<f64> {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|percent| (0.0..=100.0).contains(percent))
+                    .with_context(|| format!("{name} must be a percentage between 0 and 100")),
+                Err(_) => Ok(0.0),
+            }
+        };
+        let max_latency = match std::env::var("CHAOS_LATENCY_MS") {
+            Ok(ms) => Duration::from_millis(
+                ms.parse()
+                    .context("CHAOS_LATENCY_MS must be a non-negative integer")?,
+            ),
+            Err(_) => Duration::ZERO,
+        };
+        let chaos = Chaos {
+            error_percent: percent("CHAOS_ERROR_PERCENT")?,
+            latency_percent: percent("CHAOS_LATENCY_PERCENT")?,
+            max_latency,
+        };
+        if chaos.error_percent > 0.0 || chaos.latency_percent > 0.0 {
+            warn!(
+                error_percent = chaos.error_percent,
+                latency_percent = chaos.latency_percent,
+                max_latency_ms = chaos.max_latency.as_millis() as u64,
+                "Fault injection is enabled"
+            );
+        }
+        Ok(chaos)
+    }
+}
+
+pub async fn inject<B>(State(chaos): State<Chaos>, req: Request<B>, next: Next<B>) -> Response {
+    // the thread rng isn't Send, so roll the dice before the first await
+    let (delay, failure) = {
+        let mut rng = rand::thread_rng();
+        let delay = (rng.gen::<f64>() * 100.0 < chaos.latency_percent)
+            .then(|| chaos.max_latency.mul_f64(rng.gen()));
+        let failure = (rng.gen::<f64>() * 100.0 < chaos.error_percent).then(|| {
+            [
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ][rng.gen_range(0..3)]
+        });
+        (delay, failure)
+    };
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+    if let Some(status) = failure {
+        return ApiError::new(status, "Injected fault")
+            .with_code(ErrorCode::InjectedFault)
+            .into_response();
+    }
+    next.run(req).await
+}
@@ -0,0 +1,335 @@
+//! Ordered checklist items embedded in a todo. Unlike todos they have no
+//! life of their own: they are only addressed through the todo they belong
+//! to and can't be completed, imported or synced separately. They go where
+//! the todo goes: out of sight once it is deleted, onto the target of a
+//! merge, and away with its row. The todo shows how much of its checklist is
+//! done as `completion_percent`, so changing an item is an update of it for
+//! event subscribers.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgQueryResult, PgConnection, PgExecutor, PgPool};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{Json, Path},
+    tx::Tx,
+};
+
+#[derive(Serialize, ToSchema)]
+struct ChecklistItem {
+    id: uuid::Uuid,
+    #[serde(rename = "text")]
+    item_text: String,
+    is_done: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ChecklistView {
+    todo_id: uuid::Uuid,
+    completed_count: usize,
+    #[schema(inline)]
+    items: Vec<ChecklistItem>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AddItem {
+    text: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PutItem {
+    is_done: bool,
+    /// Renames the item.
+    text: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct Reorder {
+    /// Every item of the checklist, in the new order.
+    order: Vec<uuid::Uuid>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/checklist",
+    tag = "checklists",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    responses(
+        (status = 200, description = "The checklist", body = ChecklistView),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_checklist(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Path(todo_id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    let exists = sqlx::query_scalar::<_, bool>(
+        r#"select exists(select 1 from "todo"
+        where id = $1 and user_id = $2 and merged_into is null and deleted_at is null)"#,
+    )
+    .bind(todo_id)
+    .bind(user_id)
+    .fetch_one(&*pg)
+    .await;
+    match exists {
+        Ok(true) => respond(StatusCode::OK, checklist(&*pg, todo_id).await),
+        Ok(false) => ApiError::from(sqlx::Error::RowNotFound).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Appends an item to the end of the checklist.
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/checklist",
+    tag = "checklists",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    request_body = AddItem,
+    responses(
+        (status = 201, description = "The checklist with the item appended", body = ChecklistView),
+        (status = 400, description = "Empty item text", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn add_item(
+    AuthUser(user_id): AuthUser,
+    Extension(events): Extension<Events>,
+    Path(todo_id): Path<uuid::Uuid>,
+    mut tx: Tx,
+    Json(body): Json<AddItem>,
+) -> axum::response::Response {
+    let text = body.text.trim();
+    if text.is_empty() {
+        return ApiError::new(StatusCode::BAD_REQUEST, "Checklist item text is empty")
+            .into_response();
+    }
+    let result = async {
+        lock_todo(&mut tx, user_id, todo_id).await?;
+        sqlx::query(
+            r#"insert into "checklist_item" (todo_id, position, item_text)
+            select $1, coalesce(max(position) + 1, 0), $2 from "checklist_item" where todo_id = $1"#,
+        )
+        .bind(todo_id)
+        .bind(text)
+        .execute(&mut *tx)
+        .await?;
+        events.changed(&mut tx, user_id, todo_id).await?;
+        checklist(&mut *tx, todo_id).await
+    }
+    .await;
+    respond(StatusCode::CREATED, result)
+}
+
+/// Checks or unchecks one item, and renames it if `text` is given.
+#[utoipa::path(
+    put,
+    path = "/todos/{id}/checklist/{item_id}",
+    tag = "checklists",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("item_id" = uuid::Uuid, Path, description = "Checklist item id"),
+    ),
+    request_body = PutItem,
+    responses(
+        (status = 200, description = "The updated checklist", body = ChecklistView),
+        (status = 400, description = "Empty item text", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo or item", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn put_item(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Path((todo_id, item_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    Json(body): Json<PutItem>,
+) -> axum::response::Response {
+    let text = body.text.as_deref().map(str::trim);
+    if text.is_some_and(str::is_empty) {
+        return ApiError::new(StatusCode::BAD_REQUEST, "Checklist item text is empty")
+            .into_response();
+    }
+    let result = sqlx::query(
+        r#"update "checklist_item" set is_done = $1, item_text = coalesce($5, item_text)
+        where id = $2 and todo_id = (select id from "todo"
+            where id = $3 and user_id = $4 and merged_into is null and deleted_at is null)"#,
+    )
+    .bind(body.is_done)
+    .bind(item_id)
+    .bind(todo_id)
+    .bind(user_id)
+    .bind(text)
+    .execute(&mut *tx)
+    .await;
+    changed(&mut tx, &events, user_id, todo_id, result).await
+}
+
+/// Removes one item from the checklist.
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/checklist/{item_id}",
+    tag = "checklists",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("item_id" = uuid::Uuid, Path, description = "Checklist item id"),
+    ),
+    responses(
+        (status = 200, description = "The checklist without the item", body = ChecklistView),
+        (status = 404, description = "No such todo or item", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_item(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Path((todo_id, item_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> axum::response::Response {
+    let result = sqlx::query(
+        r#"delete from "checklist_item"
+        where id = $1 and todo_id = (select id from "todo"
+            where id = $2 and user_id = $3 and merged_into is null and deleted_at is null)"#,
+    )
+    .bind(item_id)
+    .bind(todo_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await;
+    changed(&mut tx, &events, user_id, todo_id, result).await
+}
+
+/// The checklist once `result` changed one of its items, or a 404 if it
+/// changed none.
+async fn changed(
+    conn: &mut PgConnection,
+    events: &Events,
+    user_id: uuid::Uuid,
+    todo_id: uuid::Uuid,
+    result: Result<PgQueryResult, sqlx::Error>,
+) -> axum::response::Response {
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            ApiError::from(sqlx::Error::RowNotFound).into_response()
+        }
+        Ok(_) => {
+            if let Err(err) = events.changed(&mut *conn, user_id, todo_id).await {
+                return ApiError::from(err).into_response();
+            }
+            respond(StatusCode::OK, checklist(conn, todo_id).await)
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Puts the items in the order given, which has to name each of them once.
+#[utoipa::path(
+    put,
+    path = "/todos/{id}/checklist",
+    tag = "checklists",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    request_body = Reorder,
+    responses(
+        (status = 200, description = "The reordered checklist", body = ChecklistView),
+        (status = 400, description = "`order` isn't exactly the checklist's items", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn reorder(
+    AuthUser(user_id): AuthUser,
+    Path(todo_id): Path<uuid::Uuid>,
+    mut tx: Tx,
+    Json(body): Json<Reorder>,
+) -> axum::response::Response {
+    let result = async {
+        lock_todo(&mut tx, user_id, todo_id).await?;
+        let mut current = sqlx::query_scalar::<_, uuid::Uuid>(
+            r#"select id from "checklist_item" where todo_id = $1"#,
+        )
+        .bind(todo_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut wanted = body.order.clone();
+        current.sort();
+        wanted.sort();
+        if current != wanted {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "order must list every checklist item exactly once",
+            ));
+        }
+        sqlx::query(
+            r#"update "checklist_item" set position = o.position - 1
+            from unnest($1::uuid[]) with ordinality as o (id, position)
+            where "checklist_item".id = o.id"#,
+        )
+        .bind(&body.order)
+        .execute(&mut *tx)
+        .await?;
+        checklist(&mut *tx, todo_id).await
+    }
+    .await;
+    respond(StatusCode::OK, result)
+}
+
+/// Takes the row lock of `user_id`'s todo for the rest of the request's
+/// transaction, which serializes appends and reorders of its checklist.
+async fn lock_todo(
+    tx: &mut Tx,
+    user_id: uuid::Uuid,
+    todo_id: uuid::Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"select id from "todo"
+        where id = $1 and user_id = $2 and merged_into is null and deleted_at is null
+        for update"#,
+    )
+    .bind(todo_id)
+    .bind(user_id)
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+async fn checklist(
+    executor: impl PgExecutor<'_>,
+    todo_id: uuid::Uuid,
+) -> Result<ChecklistView, ApiError> {
+    let items = sqlx::query_as!(
+        ChecklistItem,
+        r#"select id, item_text, is_done from "checklist_item"
+        where todo_id = $1
+        order by position"#,
+        todo_id,
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(ChecklistView {
+        todo_id,
+        completed_count: items.iter().filter(|item| item.is_done).count(),
+        items,
+    })
+}
+
+fn respond(
+    status: StatusCode,
+    result: Result<ChecklistView, ApiError>,
+) -> axum::response::Response {
+    match result {
+        Ok(view) => (status, Json(view)).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
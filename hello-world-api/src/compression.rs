@@ -0,0 +1,29 @@
+//! Compression of response bodies (`RESPONSE_COMPRESSION`), with Brotli or
+//! gzip, whichever the client's `Accept-Encoding` prefers. Tiny bodies,
+//! images and event streams are sent as they are: the encoder would hold
+//! back each event until it had a block's worth of them.
+
+use axum::{
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use tower_http::compression::{
+    predicate::{And, DefaultPredicate, NotForContentType, Predicate},
+    CompressionLayer,
+};
+
+pub fn layer() -> CompressionLayer<And<DefaultPredicate, NotForContentType>> {
+    CompressionLayer::new().br(true).gzip(true).compress_when(
+        DefaultPredicate::new().and(NotForContentType::const_new("text/event-stream")),
+    )
+}
+
+/// Tells caches the response depends on `Accept-Encoding`, whether or not
+/// this one got compressed.
+pub fn vary(response: impl IntoResponse) -> Response {
+    let mut response = response.into_response();
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    response
+}
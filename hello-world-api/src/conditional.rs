@@ -0,0 +1,107 @@
+//! Conditional `GET`s of todos, for clients polling them. The responses
+//! carry an `ETag`, and a `Last-Modified` the handler sets from the todos'
+//! `updated_at`; a request whose `If-None-Match` lists the `ETag`, or
+//! without one, whose `If-Modified-Since` isn't older than `Last-Modified`,
+//! gets an empty 304 instead.
+//!
+//! Responses without an `ETag` of their own, such as listings, get a weak
+//! one hashed from their body, so it changes with anything in them.
+//! `Last-Modified` only changes with the todos themselves, not with their
+//! tags or checklists, nor with todos leaving a listing.
+
+use axum::{
+    body,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+/// The `Last-Modified` header of something last changed `at`.
+pub fn last_modified(at: DateTime<Utc>) -> (header::HeaderName, HeaderValue) {
+    let value = at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    let value = HeaderValue::from_str(&value).expect("an HTTP date is a header value");
+    (header::LAST_MODIFIED, value)
+}
+
+/// Answers `GET`s with a 304 when the client has the response already, see
+/// the module docs.
+pub async fn respond<B>(req: Request<B>, next: Next<B>) -> Response {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return next.run(req).await;
+    }
+    let conditions = req.headers().clone();
+    let response = next.run(req).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let (mut parts, response_body) = response.into_parts();
+    let mut response = if parts.headers.contains_key(header::ETAG) {
+        Response::from_parts(parts, response_body)
+    } else {
+        let Ok(bytes) = hyper::body::to_bytes(response_body).await else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        let digest = hex::encode(&Sha256::digest(&bytes)[..16]);
+        let etag = HeaderValue::from_str(&format!("W/\"{digest}\""))
+            .expect("hex digits are a header value");
+        parts.headers.insert(header::ETAG, etag);
+        Response::from_parts(parts, body::boxed(body::Full::new(bytes)))
+    };
+    if !not_modified(&conditions, response.headers()) {
+        return response;
+    }
+    unchanged(std::mem::take(response.headers_mut()))
+}
+
+/// The empty 304 of a response with `headers`: the validators and caching
+/// headers stay, what describes the body goes.
+pub fn unchanged(mut headers: HeaderMap) -> Response {
+    headers.remove(header::CONTENT_TYPE);
+    headers.remove(header::CONTENT_LENGTH);
+    (StatusCode::NOT_MODIFIED, headers).into_response()
+}
+
+/// Whether the request's conditions say the client has the response with
+/// these headers: its `If-None-Match` if it has one, its
+/// `If-Modified-Since` otherwise.
+pub fn not_modified(conditions: &HeaderMap, response: &HeaderMap) -> bool {
+    if conditions.contains_key(header::IF_NONE_MATCH) {
+        return response
+            .get(header::ETAG)
+            .is_some_and(|etag| none_match(conditions, etag));
+    }
+    let since = conditions
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(http_date);
+    let last_modified = response.get(header::LAST_MODIFIED).and_then(http_date);
+    match (since, last_modified) {
+        (Some(since), Some(last_modified)) => last_modified <= since,
+        _ => false,
+    }
+}
+
+/// Whether the request's `If-None-Match` lists `etag`, or is `*`.
+pub fn none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let etag = etag.as_bytes();
+    let etag = etag.strip_prefix(b"W/").unwrap_or(etag);
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        // weak comparison, as If-None-Match has
+        .map(|tag| tag.strip_prefix("W/").unwrap_or(tag))
+        .any(|tag| tag == "*" || tag.as_bytes() == etag)
+}
+
+/// An HTTP date such as `Sun, 06 Nov 1994 08:49:37 GMT`, `None` for a
+/// header that isn't one.
+fn http_date(value: &HeaderValue) -> Option<DateTime<Utc>> {
+    let value = value.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|at| at.with_timezone(&Utc))
+}
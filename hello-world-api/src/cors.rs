@@ -0,0 +1,129 @@
+//! Cross-origin requests from browsers. Off unless `CORS_ALLOWED_ORIGINS`
+//! lists the origins allowed, or is `*` for any. Preflight requests from those
+//! origins are answered right away with the allowed methods and headers,
+//! which browsers may cache for [`MAX_AGE`]; the other responses carry the
+//! headers letting the page read them, including the ones the API answers
+//! with that browsers hide by default.
+
+use std::{str::FromStr, time::Duration};
+
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use reqwest::Url;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::Config;
+
+/// How long browsers may reuse a preflight's answer.
+const MAX_AGE: Duration = Duration::from_secs(600);
+
+/// The pages that may call the API.
+#[derive(Clone)]
+pub enum Origins {
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+impl FromStr for Origins {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.trim() == "*" {
+            return Ok(Origins::Any);
+        }
+        let origins = List::<String>::from_str(value)?.0;
+        origins
+            .into_iter()
+            .map(|origin| {
+                // as browsers send it: scheme, host and port if not the default
+                let serialized = Url::parse(&origin).map(|url| url.origin().ascii_serialization());
+                anyhow::ensure!(
+                    serialized.is_ok_and(|serialized| serialized == origin),
+                    "{origin} is not an origin, such as https://app.example.com"
+                );
+                Ok(HeaderValue::try_from(origin)?)
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Origins::List)
+    }
+}
+
+/// A comma-separated list.
+#[derive(Clone)]
+pub struct List<T>(pub Vec<T>);
+
+impl<T> FromStr for List<T>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let items = value
+            .split(',')
+            .map(str::trim)
+            .map(|item| {
+                anyhow::ensure!(!item.is_empty(), "the list has an empty entry");
+                item.parse().map_err(Into::into)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(List(items))
+    }
+}
+
+/// Every method the API has a route for.
+pub fn default_methods() -> List<Method> {
+    List(vec![
+        Method::GET,
+        Method::HEAD,
+        Method::POST,
+        Method::PUT,
+        Method::PATCH,
+        Method::DELETE,
+    ])
+}
+
+/// The request headers the API reads that browsers don't allow by default.
+pub fn default_headers() -> List<HeaderName> {
+    List(vec![
+        header::AUTHORIZATION,
+        header::CONTENT_TYPE,
+        header::IF_MATCH,
+        HeaderName::from_static("last-event-id"),
+        HeaderName::from_static("x-act-as"),
+        HeaderName::from_static("x-api-key"),
+        HeaderName::from_static("x-http-method-override"),
+        HeaderName::from_static("x-request-id"),
+        HeaderName::from_static("x-workspace"),
+    ])
+}
+
+pub fn layer(origins: &Origins, config: &Config) -> CorsLayer {
+    let origins = match origins {
+        Origins::Any => AllowOrigin::any(),
+        Origins::List(origins) => AllowOrigin::list(origins.clone()),
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(config.cors_allowed_methods.0.clone())
+        .allow_headers(config.cors_allowed_headers.0.clone())
+        .allow_credentials(config.cors_allow_credentials)
+        // the response headers the API sets that pages may need
+        .expose_headers([
+            header::ETAG,
+            header::LOCATION,
+            header::RETRY_AFTER,
+            header::CONTENT_LANGUAGE,
+            header::LINK,
+            HeaderName::from_static("deprecation"),
+            HeaderName::from_static("sunset"),
+            HeaderName::from_static("x-warning"),
+            HeaderName::from_static("x-total-count"),
+            HeaderName::from_static("x-total-estimated"),
+            HeaderName::from_static("x-next-cursor"),
+            HeaderName::from_static("x-request-id"),
+            HeaderName::from_static("x-quota-remaining"),
+            HeaderName::from_static("x-quota-reset"),
+        ])
+        .max_age(MAX_AGE)
+}
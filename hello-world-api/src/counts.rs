@@ -0,0 +1,109 @@
+//! Badge counts for clients polling every few seconds. `GET /todos/counts`
+//! counts the user's open todos in one query over their rows, and keeps the
+//! answer for [`COUNTS_TTL`], which clients may also cache it for. Its
+//! `ETag` lets a client ask with `If-None-Match` and get an empty 304 while
+//! the counts are the same.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use serde::Serialize;
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+use crate::{auth::AuthUser, conditional, error::ApiError, extract::Json};
+
+/// How long counts are served without counting again, so they can lag
+/// behind writes by as much.
+const COUNTS_TTL: Duration = Duration::from_secs(5);
+
+/// The latest counts of each user who asked within [`COUNTS_TTL`].
+#[derive(Clone, Default)]
+pub struct CountsCache(Arc<Mutex<HashMap<uuid::Uuid, (Instant, TodoCounts)>>>);
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TodoCounts {
+    /// Todos neither done nor expired.
+    open: i64,
+    /// Open todos that have started, as listed by `GET /todos/today`.
+    today: i64,
+    /// Open todos past their due date.
+    overdue: i64,
+}
+
+impl TodoCounts {
+    fn etag(&self) -> HeaderValue {
+        HeaderValue::try_from(format!("\"{}-{}-{}\"", self.open, self.today, self.overdue))
+            .expect("digits and dashes are a valid header value")
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/counts",
+    tag = "todos",
+    params(
+        ("If-None-Match" = Option<String>, Header, description = "The `etag` of counts the client has"),
+    ),
+    responses(
+        (status = 200, description = "The counts", body = TodoCounts, headers(("etag" = String, description = "Changes with the counts"))),
+        (status = 304, description = "The counts are still those of `If-None-Match`"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get(
+    pg: Extension<PgPool>,
+    Extension(cache): Extension<CountsCache>,
+    AuthUser(user_id): AuthUser,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    let cached = cache.0.lock().unwrap().get(&user_id).copied();
+    let counts = match cached.filter(|(at, _)| at.elapsed() < COUNTS_TTL) {
+        Some((_, counts)) => counts,
+        None => match count(&pg, user_id).await {
+            Ok(counts) => {
+                let mut cache = cache.0.lock().unwrap();
+                cache.retain(|_, (at, _)| at.elapsed() < COUNTS_TTL);
+                cache.insert(user_id, (Instant::now(), counts));
+                counts
+            }
+            Err(err) => return ApiError::from(err).into_response(),
+        },
+    };
+
+    let etag = counts.etag();
+    let cache_control =
+        HeaderValue::from_str(&format!("private, max-age={}", COUNTS_TTL.as_secs()))
+            .expect("a valid header value");
+    let headers_out = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, cache_control),
+    ];
+    if conditional::none_match(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, headers_out).into_response();
+    }
+    (StatusCode::OK, headers_out, Json(counts)).into_response()
+}
+
+async fn count(pg: &PgPool, user_id: uuid::Uuid) -> Result<TodoCounts, sqlx::Error> {
+    sqlx::query_as!(
+        TodoCounts,
+        r#"select count(*) as "open!",
+            count(*) filter (where start_at is null or start_at <= now()) as "today!",
+            count(*) filter (where due_at < now()) as "overdue!"
+        from "todo"
+        where user_id = $1 and merged_into is null and deleted_at is null
+            and not is_done and expired_at is null"#,
+        user_id,
+    )
+    .fetch_one(pg)
+    .await
+}
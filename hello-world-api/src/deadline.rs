@@ -0,0 +1,84 @@
+//! Request deadlines. With `REQUEST_TIMEOUT_SECS` set, a request whose
+//! response isn't ready by then is answered with a 503 and what its handler
+//! was doing is dropped. Its queries are bounded too, so they don't keep
+//! running for nobody, holding a pool connection all the while: the
+//! request's transaction gets a `statement_timeout` of the time left, and the
+//! pool's connections the whole timeout, for queries made outside of one.
+//!
+//! Only the response's head counts, streamed bodies such as event streams
+//! and exports are not cut short.
+
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::PgConnection;
+use tracing::warn;
+
+use crate::error::{ApiError, ErrorCode};
+
+tokio::task_local! {
+    /// When the request being handled has to be answered by.
+    static DEADLINE: Instant;
+}
+
+/// Time left until the request being handled has to be answered, `None`
+/// outside of [`enforce`].
+pub fn remaining() -> Option<Duration> {
+    DEADLINE
+        .try_with(|deadline| deadline.saturating_duration_since(Instant::now()))
+        .ok()
+}
+
+/// The `statement_timeout` bounding a query to `timeout`, in milliseconds as
+/// Postgres reads it. Never 0, which would turn the timeout off.
+pub fn statement_timeout(timeout: Duration) -> String {
+    timeout.as_millis().max(1).to_string()
+}
+
+/// What a request out of time is answered with.
+pub fn timed_out() -> ApiError {
+    ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "The request took too long, try again later",
+    )
+    .with_code(ErrorCode::TimedOut)
+}
+
+/// Bounds the queries of the transaction on `conn` to the time left for the
+/// request being handled, or fails if there is none left.
+pub async fn apply(conn: &mut PgConnection) -> Result<(), ApiError> {
+    let Some(remaining) = remaining() else {
+        return Ok(());
+    };
+    if remaining.is_zero() {
+        return Err(timed_out());
+    }
+    sqlx::query("select set_config('statement_timeout', $1, true)")
+        .bind(statement_timeout(remaining))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Answers requests not answered within `timeout` with a 503.
+pub async fn enforce<B>(
+    State(timeout): State<Duration>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let deadline = Instant::now() + timeout;
+    match tokio::time::timeout(timeout, DEADLINE.scope(deadline, next.run(req))).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!(%method, path, ?timeout, "Request timed out");
+            timed_out().into_response()
+        }
+    }
+}
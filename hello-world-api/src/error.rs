@@ -0,0 +1,297 @@
+//! Error responses. Everything a handler fails with is rendered as an
+//! RFC 7807 problem details body, with an [`ErrorCode`] as `code` for clients
+//! to branch on and malformed requests described in `details`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::error::DatabaseError;
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    deadline,
+    extract::{invalid_fields, FieldError},
+    repository::RepositoryError,
+    request_id, resilience,
+};
+
+/// Pool acquisitions that timed out since startup, i.e. how often the pool
+/// was saturated.
+static POOL_ACQUIRE_TIMEOUTS: AtomicU64 = AtomicU64::new(0);
+
+pub fn pool_acquire_timeouts() -> u64 {
+    POOL_ACQUIRE_TIMEOUTS.load(Ordering::Relaxed)
+}
+
+/// Machine-readable kind of an error. The `detail` text may change between
+/// releases; these don't.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The body, query or path is malformed or fails validation.
+    InvalidRequest,
+    UnsupportedMediaType,
+    Unauthorized,
+    Forbidden,
+    /// The bearer token lacks the scope the request needs.
+    InsufficientScope,
+    QuotaExceeded,
+    /// The client sent more requests than `RATE_LIMIT_PER_MINUTE` allows.
+    RateLimited,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    Conflict,
+    /// The body is larger than `MAX_BODY_BYTES`, or the route allows.
+    PayloadTooLarge,
+    /// The resource changed since the client's `If-Match` version.
+    PreconditionFailed,
+    /// A write that has to be conditional came without `If-Match`.
+    PreconditionRequired,
+    Internal,
+    /// A dependency (the LLM, GitHub, ...) failed or isn't configured.
+    Unavailable,
+    Maintenance,
+    /// A write sent to a read-only replica, to be sent to the primary.
+    ReadOnly,
+    Overloaded,
+    PoolExhausted,
+    /// The request took longer than `REQUEST_TIMEOUT_SECS`, or its body
+    /// stalled for longer than `REQUEST_BODY_TIMEOUT_SECS` (a 408).
+    TimedOut,
+    InjectedFault,
+}
+
+impl ErrorCode {
+    /// The code of errors that have no more specific one.
+    pub fn for_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+            StatusCode::NOT_ACCEPTABLE => ErrorCode::NotAcceptable,
+            StatusCode::CONFLICT => ErrorCode::Conflict,
+            StatusCode::REQUEST_TIMEOUT => ErrorCode::TimedOut,
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+            StatusCode::PRECONDITION_FAILED => ErrorCode::PreconditionFailed,
+            StatusCode::PRECONDITION_REQUIRED => ErrorCode::PreconditionRequired,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => ErrorCode::UnsupportedMediaType,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::RateLimited,
+            StatusCode::SERVICE_UNAVAILABLE | StatusCode::BAD_GATEWAY => ErrorCode::Unavailable,
+            status if status.is_client_error() => ErrorCode::InvalidRequest,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+pub struct ApiError {
+    pub code: StatusCode,
+    pub error: String,
+    pub error_code: ErrorCode,
+    /// What exactly is wrong with the request, sent as `details`.
+    pub details: Option<Value>,
+    pub retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(code: StatusCode, error: impl Into<String>) -> Self {
+        ApiError {
+            code,
+            error: error.into(),
+            error_code: ErrorCode::for_status(code),
+            details: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn with_code(self, error_code: ErrorCode) -> Self {
+        ApiError { error_code, ..self }
+    }
+
+    /// The problem details body this error is answered with, for embedding
+    /// in responses reporting several outcomes.
+    pub fn body(&self) -> Value {
+        Problem {
+            status: self.code,
+            detail: self.error.clone(),
+            code: self.error_code,
+            details: self.details.clone(),
+        }
+        .body()
+    }
+}
+
+impl From<Box<dyn DatabaseError>> for ApiError {
+    fn from(value: Box<dyn DatabaseError>) -> Self {
+        match value.code().as_deref() {
+            Some("23505") => ApiError::new(StatusCode::CONFLICT, "Duplicate entity"),
+            // canceled by the request's statement_timeout
+            Some("57014") => deadline::timed_out(),
+            // data exceptions, such as a value too long for its column
+            Some(code) if code.starts_with("22") => {
+                ApiError::new(StatusCode::BAD_REQUEST, value.message())
+            }
+            Some(code) if resilience::is_transient_code(code) => {
+                warn!("Transient database error {:?}", value);
+                resilience::DATABASE.record_failure();
+                unavailable()
+            }
+            _ => {
+                error!("Database error {:?}", value);
+                ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+            }
+        }
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::Database(db_err) => db_err.into(),
+            sqlx::Error::RowNotFound => ApiError::new(StatusCode::NOT_FOUND, "Not found"),
+            sqlx::Error::PoolTimedOut => {
+                let timeouts = POOL_ACQUIRE_TIMEOUTS.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(timeouts, "Timed out acquiring a database connection");
+                ApiError {
+                    retry_after: Some(1),
+                    ..ApiError::new(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Database is saturated, try again later",
+                    )
+                    .with_code(ErrorCode::PoolExhausted)
+                }
+            }
+            _ if resilience::is_transient(&err) => {
+                warn!("Lost the database connection {:?}", err);
+                resilience::DATABASE.record_failure();
+                unavailable()
+            }
+            _ => {
+                error!("Fail to insert into database {:?}", err);
+                ApiError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Fail to insert into database",
+                )
+            }
+        }
+    }
+}
+
+/// A failure that may pass, which [`resilience::retry`] already had a go at
+/// where the query could be sent again.
+pub(crate) fn unavailable() -> ApiError {
+    ApiError {
+        retry_after: Some(1),
+        ..ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The database is unavailable, try again later",
+        )
+        .with_code(ErrorCode::Unavailable)
+    }
+}
+
+impl From<RepositoryError> for ApiError {
+    fn from(err: RepositoryError) -> Self {
+        match err {
+            RepositoryError::NotFound => ApiError::from(sqlx::Error::RowNotFound),
+            RepositoryError::Duplicate => ApiError::new(StatusCode::CONFLICT, "Duplicate entity"),
+            RepositoryError::VersionMismatch => ApiError::new(
+                StatusCode::PRECONDITION_FAILED,
+                "The todo changed since the version in If-Match, fetch it again",
+            ),
+            RepositoryError::NoSuchList => invalid_fields(vec![FieldError {
+                field: "list_id",
+                reason: "is not one of your lists".to_owned(),
+            }]),
+            RepositoryError::NotPermitted => ApiError::new(
+                StatusCode::FORBIDDEN,
+                "The todo is shared with you, but not for this",
+            ),
+            RepositoryError::Database(err) => ApiError::from(err),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = Problem {
+            status: self.code,
+            detail: self.error,
+            code: self.error_code,
+            details: self.details,
+        }
+        .into_response();
+        if let Some(seconds) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, seconds.into());
+        }
+        response
+    }
+}
+
+/// RFC 7807 problem details body.
+pub struct Problem {
+    pub status: StatusCode,
+    pub detail: String,
+    /// Rendered as a `code` extension member.
+    pub code: ErrorCode,
+    /// Rendered as a `details` extension member if present.
+    pub details: Option<Value>,
+}
+
+impl Problem {
+    pub fn body(&self) -> Value {
+        let mut body = serde_json::json!({
+            "type": "about:blank",
+            "title": self.status.canonical_reason().unwrap_or_default(),
+            "status": self.status.as_u16(),
+            "detail": self.detail,
+            "code": self.code,
+        });
+        if let Some(details) = &self.details {
+            body["details"] = details.clone();
+        }
+        if let Some(request_id) = request_id::current() {
+            body["request_id"] = request_id.into();
+        }
+        body
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        (
+            self.status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            self.body().to_string(),
+        )
+            .into_response()
+    }
+}
+
+/// Body of every error response, as rendered by [`Problem`].
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ProblemDetails {
+    /// Always `about:blank`.
+    r#type: String,
+    /// Reason phrase of the status.
+    title: String,
+    status: u16,
+    detail: String,
+    code: ErrorCode,
+    /// Where and why a malformed request failed, such as
+    /// `{"source": "body", "reason": "missing field `text`"}`.
+    #[schema(value_type = Option<Object>)]
+    details: Option<Value>,
+    /// The request's `X-Request-Id`, to quote when reporting the problem.
+    request_id: Option<String>,
+}
@@ -0,0 +1,462 @@
+//! Live todo changes. The handlers creating, changing and deleting todos
+//! record a [`TodoEvent`] in the [outbox](crate::outbox), in the transaction
+//! making the change, from which it is published on the process' [`Events`]
+//! bus, and `GET /ws/todos` forwards the events of the connected user over a
+//! WebSocket as JSON text messages, `GET /todos/events` as Server-Sent Events.
+//! With several instances, only the events relayed by the same instance are
+//! seen.
+//!
+//! The last [`CAPACITY`] events are kept so an SSE client reconnecting with
+//! `Last-Event-ID` is sent the ones it missed. Event ids are prefixed with an
+//! id of the process, so ids handed out before a restart or by another
+//! instance are recognized as unknown rather than taken for recent ones.
+
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+use async_graphql::{Enum, SimpleObject};
+use axum::{
+    extract::ws::{close_code, CloseFrame, Message, WebSocket},
+    http::{header, HeaderMap, HeaderValue},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Extension,
+};
+use futures_util::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    extract::WebSocketUpgrade,
+    models::{ToDoView, Todo},
+    outbox::Outbox,
+};
+
+/// Events a connection may fall behind by before it is closed, and events
+/// kept for SSE clients to resume from.
+const CAPACITY: usize = 1024;
+
+/// Name of the SSE event telling the client it may have missed changes and
+/// has to fetch its todos again.
+const RESYNC: &str = "resync";
+
+#[derive(Clone)]
+pub struct Events(Arc<Bus>);
+
+struct Bus {
+    sender: broadcast::Sender<Published>,
+    /// Random for each process, the prefix of its event ids.
+    instance: String,
+    recent: Mutex<Recent>,
+}
+
+/// The last events published, oldest first. Sent under the same lock they
+/// are recorded under, so a subscriber taking a snapshot of them gets every
+/// later event from its receiver.
+#[derive(Default)]
+struct Recent {
+    events: VecDeque<Published>,
+    /// Sequence number of the next event.
+    next: u64,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        let instance = uuid::Uuid::new_v4().simple().to_string()[..8].to_owned();
+        Events(Arc::new(Bus {
+            sender: broadcast::channel(CAPACITY).0,
+            instance,
+            recent: Mutex::default(),
+        }))
+    }
+}
+
+/// An event serialized once for every connection of its user.
+#[derive(Clone)]
+struct Published {
+    seq: u64,
+    user_id: uuid::Uuid,
+    json: Arc<str>,
+    event: Arc<TodoEvent>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, SimpleObject)]
+pub struct TodoEvent {
+    #[serde(rename = "type")]
+    #[graphql(name = "type")]
+    pub kind: EventKind,
+    pub id: uuid::Uuid,
+    /// The todo as changed, left out for deletions and for changes made by
+    /// endpoints that don't answer with the todo, after which it has to be
+    /// fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo: Option<ToDoView>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, ToSchema, Enum, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "todo_event_kind", rename_all = "snake_case")]
+pub enum EventKind {
+    Created,
+    Updated,
+    Deleted,
+    /// The todo falls due soon, sent once per due date; see
+    /// `REMINDER_LEAD_SECS`.
+    Due,
+    /// The todo was assigned to the user, sent to the assignee.
+    Assigned,
+    /// The due date of a todo assigned to the user changed, sent to the
+    /// assignee.
+    DueChanged,
+}
+
+impl PgHasArrayType for EventKind {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_todo_event_kind")
+    }
+}
+
+/// Recording an event fails with the statement recording it, which leaves
+/// the transaction making the change to be rolled back.
+impl Events {
+    pub async fn created<'c>(
+        &self,
+        outbox: impl Into<Outbox<'c>>,
+        user_id: uuid::Uuid,
+        todo: &Todo,
+    ) -> Result<(), sqlx::Error> {
+        let event = Self::event(EventKind::Created, todo.id, Some(todo));
+        self.record(outbox.into(), user_id, event).await
+    }
+
+    pub async fn updated<'c>(
+        &self,
+        outbox: impl Into<Outbox<'c>>,
+        user_id: uuid::Uuid,
+        todo: &Todo,
+    ) -> Result<(), sqlx::Error> {
+        let event = Self::event(EventKind::Updated, todo.id, Some(todo));
+        self.record(outbox.into(), user_id, event).await
+    }
+
+    /// An update of a todo the handler doesn't have at hand.
+    pub async fn changed<'c>(
+        &self,
+        outbox: impl Into<Outbox<'c>>,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<(), sqlx::Error> {
+        let event = Self::event(EventKind::Updated, id, None);
+        self.record(outbox.into(), user_id, event).await
+    }
+
+    pub async fn created_id<'c>(
+        &self,
+        outbox: impl Into<Outbox<'c>>,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<(), sqlx::Error> {
+        let event = Self::event(EventKind::Created, id, None);
+        self.record(outbox.into(), user_id, event).await
+    }
+
+    pub async fn deleted<'c>(
+        &self,
+        outbox: impl Into<Outbox<'c>>,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<(), sqlx::Error> {
+        let event = Self::event(EventKind::Deleted, id, None);
+        self.record(outbox.into(), user_id, event).await
+    }
+
+    pub async fn due<'c>(
+        &self,
+        outbox: impl Into<Outbox<'c>>,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<(), sqlx::Error> {
+        let event = Self::event(EventKind::Due, id, None);
+        self.record(outbox.into(), user_id, event).await
+    }
+
+    pub async fn assigned<'c>(
+        &self,
+        outbox: impl Into<Outbox<'c>>,
+        assignee_id: uuid::Uuid,
+        todo: &Todo,
+    ) -> Result<(), sqlx::Error> {
+        let event = Self::event(EventKind::Assigned, todo.id, Some(todo));
+        self.record(outbox.into(), assignee_id, event).await
+    }
+
+    pub async fn due_changed<'c>(
+        &self,
+        outbox: impl Into<Outbox<'c>>,
+        assignee_id: uuid::Uuid,
+        todo: &Todo,
+    ) -> Result<(), sqlx::Error> {
+        let event = Self::event(EventKind::DueChanged, todo.id, Some(todo));
+        self.record(outbox.into(), assignee_id, event).await
+    }
+
+    fn event(kind: EventKind, id: uuid::Uuid, todo: Option<&Todo>) -> TodoEvent {
+        TodoEvent {
+            kind,
+            id,
+            todo: todo.cloned().map(ToDoView::from),
+        }
+    }
+
+    /// Records the event in `outbox`, or publishes it right away without a
+    /// database.
+    async fn record(
+        &self,
+        outbox: Outbox<'_>,
+        user_id: uuid::Uuid,
+        event: TodoEvent,
+    ) -> Result<(), sqlx::Error> {
+        let json = match serde_json::to_string(&event) {
+            Ok(json) => json,
+            Err(err) => {
+                error!("Fail to serialize todo event {:?}", err);
+                return Ok(());
+            }
+        };
+        if !outbox.record(user_id, &json).await? {
+            self.publish(user_id, json.into(), event);
+        }
+        Ok(())
+    }
+
+    /// Publishes `json`, an event recorded in the outbox.
+    pub fn relay(&self, user_id: uuid::Uuid, json: &str) -> serde_json::Result<()> {
+        let event = serde_json::from_str(json)?;
+        self.publish(user_id, json.into(), event);
+        Ok(())
+    }
+
+    fn publish(&self, user_id: uuid::Uuid, json: Arc<str>, event: TodoEvent) {
+        let mut recent = self.recent();
+        let published = Published {
+            seq: recent.next,
+            user_id,
+            json,
+            event: Arc::new(event),
+        };
+        recent.next += 1;
+        if recent.events.len() == CAPACITY {
+            recent.events.pop_front();
+        }
+        recent.events.push_back(published.clone());
+        // fails when nobody is listening, which is the common case
+        let _ = self.0.sender.send(published);
+    }
+
+    /// The user's events as they are published, for transports other than
+    /// the WebSocket and SSE. Ends with `Err` and the number of events missed
+    /// once the subscriber falls too far behind.
+    pub fn watch(
+        &self,
+        user_id: uuid::Uuid,
+    ) -> impl Stream<Item = Result<Arc<TodoEvent>, u64>> + Send + 'static {
+        let receiver = Some(self.0.sender.subscribe());
+        stream::unfold(receiver, move |receiver| async move {
+            let mut receiver = receiver?;
+            loop {
+                match receiver.recv().await {
+                    Ok(published) if published.user_id == user_id => {
+                        return Some((Ok(published.event), Some(receiver)));
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => return Some((Err(missed), None)),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    fn recent(&self) -> std::sync::MutexGuard<'_, Recent> {
+        self.0.recent.lock().unwrap()
+    }
+
+    fn event_id(&self, seq: u64) -> String {
+        format!("{}:{seq}", self.0.instance)
+    }
+
+    /// Subscribes, with the user's recorded events after `last_event_id`.
+    /// `None` for the events if the id isn't one of this process or is older
+    /// than the events kept, so events may have been missed.
+    fn resume(
+        &self,
+        user_id: uuid::Uuid,
+        last_event_id: Option<&str>,
+    ) -> (broadcast::Receiver<Published>, Option<Vec<Published>>) {
+        let recent = self.recent();
+        let receiver = self.0.sender.subscribe();
+        let Some(last_event_id) = last_event_id else {
+            return (receiver, Some(Vec::new()));
+        };
+        let last_seq = last_event_id
+            .split_once(':')
+            .filter(|(instance, _)| *instance == self.0.instance)
+            .and_then(|(_, seq)| seq.parse::<u64>().ok())
+            .filter(|seq| *seq < recent.next);
+        let oldest = recent.events.front().map_or(recent.next, |event| event.seq);
+        let missed = last_seq.filter(|seq| seq + 1 >= oldest).map(|last_seq| {
+            recent
+                .events
+                .iter()
+                .filter(|event| event.seq > last_seq && event.user_id == user_id)
+                .cloned()
+                .collect()
+        });
+        (receiver, missed)
+    }
+}
+
+/// Streams the user's todo changes as [`TodoEvent`] text messages. A client
+/// too slow to keep up is disconnected with close code 1013 and has to
+/// reconnect and fetch its todos again.
+#[utoipa::path(
+    get,
+    path = "/ws/todos",
+    tag = "todos",
+    responses(
+        (status = 101, description = "Upgraded to a WebSocket carrying a `TodoEvent` per change"),
+        (status = 400, description = "Not a WebSocket upgrade request", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid bearer token", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn stream(
+    AuthUser(user_id): AuthUser,
+    Extension(events): Extension<Events>,
+    WebSocketUpgrade(ws): WebSocketUpgrade,
+) -> axum::response::Response {
+    // subscribed before upgrading so no change after this request is missed
+    let receiver = events.0.sender.subscribe();
+    ws.on_upgrade(move |socket| forward(socket, receiver, user_id))
+        .into_response()
+}
+
+async fn forward(
+    mut socket: WebSocket,
+    mut receiver: broadcast::Receiver<Published>,
+    user_id: uuid::Uuid,
+) {
+    loop {
+        tokio::select! {
+            published = receiver.recv() => match published {
+                Ok(published) if published.user_id == user_id => {
+                    if socket.send(Message::Text(published.json.to_string())).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(%user_id, missed, "WebSocket client fell behind the todo events");
+                    let close = CloseFrame {
+                        code: close_code::AGAIN,
+                        reason: "Missed events, fetch the todos again".into(),
+                    };
+                    let _ = socket.send(Message::Close(Some(close))).await;
+                    return;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            // clients only listen, pings are answered by the socket itself
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Streams the user's todo changes as Server-Sent Events, for clients that
+/// can't use the WebSocket. Each event carries a [`TodoEvent`] as its data
+/// and an id to resume from with `Last-Event-ID`. A `resync` event, without
+/// data, replaces the changes the client may have missed: when its
+/// `Last-Event-ID` is too old or from before a restart, or when it falls too
+/// far behind.
+#[utoipa::path(
+    get,
+    path = "/todos/events",
+    tag = "todos",
+    params(
+        ("Last-Event-ID" = Option<String>, Header, description = "Id of the last event received, to be sent the later ones"),
+    ),
+    responses(
+        (status = 200, description = "An event stream with a `TodoEvent` per change", content_type = "text/event-stream"),
+        (status = 401, description = "Missing or invalid bearer token", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn sse(
+    AuthUser(user_id): AuthUser,
+    Extension(events): Extension<Events>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok());
+    let (receiver, missed) = events.resume(user_id, last_event_id);
+    let first = match missed {
+        Some(missed) => missed.iter().map(|event| to_sse(&events, event)).collect(),
+        None => vec![Event::default().event(RESYNC).data("")],
+    };
+    let mut response = Sse::new(sse_stream(events, first, receiver, user_id))
+        .keep_alive(KeepAlive::default())
+        .into_response();
+    // keeps the response cache from adding a max-age to a live stream
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+fn to_sse(events: &Events, published: &Published) -> Event {
+    Event::default()
+        .id(events.event_id(published.seq))
+        .data(&*published.json)
+}
+
+/// `first`, then the user's events as they are published.
+fn sse_stream(
+    events: Events,
+    first: Vec<Event>,
+    receiver: broadcast::Receiver<Published>,
+    user_id: uuid::Uuid,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let state = (events, first.into_iter(), receiver);
+    stream::unfold(state, move |(events, mut first, mut receiver)| async move {
+        if let Some(event) = first.next() {
+            return Some((Ok(event), (events, first, receiver)));
+        }
+        loop {
+            match receiver.recv().await {
+                Ok(published) if published.user_id == user_id => {
+                    let event = to_sse(&events, &published);
+                    return Some((Ok(event), (events, first, receiver)));
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(%user_id, missed, "SSE client fell behind the todo events");
+                    let event = Event::default().event(RESYNC).data("");
+                    return Some((Ok(event), (events, first, receiver)));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
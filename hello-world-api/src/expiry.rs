@@ -0,0 +1,59 @@
+//! Expiry of time-boxed todos. A todo can have an `expires_at`; once it
+//! passes with the todo still open, a job running every `EXPIRY_CHECK_SECS`
+//! cancels it, which gives it the `expired` status, and announces each one
+//! on the `todo_expired` notification channel and to the live clients.
+
+use async_trait::async_trait;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::{events::Events, jobs::Job};
+
+/// Channel of the `NOTIFY` sent for each expired todo, with a JSON payload
+/// of its `id` and `user_id`.
+pub const CHANNEL: &str = "todo_expired";
+
+pub struct ExpireTodos {
+    pub events: Events,
+}
+
+#[async_trait]
+impl Job for ExpireTodos {
+    fn name(&self) -> &'static str {
+        "expire_todos"
+    }
+
+    async fn run(&self, pg: &PgPool) -> anyhow::Result<i64> {
+        let expired = expire(pg, &self.events).await?;
+        if expired > 0 {
+            info!("Expired {expired} todos");
+        }
+        Ok(expired as i64)
+    }
+}
+
+/// Cancels the open todos past their expiry, notifying in the same
+/// transaction so listeners only hear of todos that did expire; so are
+/// their events recorded. Returns how many todos expired.
+async fn expire(pg: &PgPool, events: &Events) -> Result<usize, sqlx::Error> {
+    let mut tx = pg.begin().await?;
+    let expired = sqlx::query!(
+        r#"with expired as (
+            update "todo" set expired_at = now()
+            where expires_at <= now() and expired_at is null and not is_done
+                and merged_into is null and deleted_at is null
+            returning id, user_id
+        )
+        select id as "id!", user_id as "user_id!",
+            pg_notify($1, json_build_object('id', id, 'user_id', user_id)::text)::text
+        from expired"#,
+        CHANNEL,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    for row in &expired {
+        events.changed(&mut tx, row.user_id, row.id).await?;
+    }
+    tx.commit().await?;
+    Ok(expired.len())
+}
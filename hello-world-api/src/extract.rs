@@ -0,0 +1,304 @@
+//! Axum's extractors, answering requests they can't parse, or that aren't the
+//! WebSocket upgrade or multipart body expected, with a problem details body
+//! saying which part is malformed instead of axum's plain-text rejections. [`Valid`] also
+//! checks the parsed body's fields, [`IfMatch`] makes a write conditional
+//! on the version the client has, and [`ListFormat`] picks what a listing
+//! answers with by `Accept`.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{
+        multipart::{self, MultipartError, MultipartRejection},
+        rejection::{FormRejection, JsonRejection, PathRejection, QueryRejection},
+        ws::{self, rejection::WebSocketUpgradeRejection},
+        FromRequest, FromRequestParts,
+    },
+    http::{header, request::Parts, HeaderMap, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::error::ApiError;
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct Query<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct Path<T>(pub T);
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Form), rejection(ApiError))]
+pub struct Form<T>(pub T);
+
+/// An upgrade to a WebSocket, refusing requests that aren't one, such as a
+/// plain `GET`, like the other extractors.
+pub struct WebSocketUpgrade(pub ws::WebSocketUpgrade);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for WebSocketUpgrade {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, ApiError> {
+        Ok(WebSocketUpgrade(
+            ws::WebSocketUpgrade::from_request_parts(parts, state).await?,
+        ))
+    }
+}
+
+/// A `multipart/form-data` body, refusing requests that aren't one. Its
+/// fields' own errors convert to an [`ApiError`] too.
+pub struct Multipart(pub multipart::Multipart);
+
+#[async_trait]
+impl<S, B> FromRequest<S, B> for Multipart
+where
+    B: axum::body::HttpBody + Send + 'static,
+    B::Data: Into<axum::body::Bytes>,
+    B::Error: Into<axum::BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, ApiError> {
+        Ok(Multipart(
+            multipart::Multipart::from_request(req, state).await?,
+        ))
+    }
+}
+
+/// A JSON body whose fields are checked by its [`Validate`] impl, refused
+/// with a 422 listing every invalid field in `details`.
+pub struct Valid<T>(pub T);
+
+/// Checks on a request body beyond what deserializing it does.
+pub trait Validate {
+    /// Every invalid field, none if the body is valid.
+    fn validate(&self) -> Vec<FieldError>;
+}
+
+#[derive(Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub reason: String,
+}
+
+/// Why `text`, trimmed, isn't between 1 and `max_chars` characters long.
+pub fn check_text(field: &'static str, text: &str, max_chars: usize) -> Option<FieldError> {
+    let chars = text.trim().chars().count();
+    let reason = if chars == 0 {
+        "must not be empty".to_owned()
+    } else if chars > max_chars {
+        format!("must be at most {max_chars} characters")
+    } else {
+        return None;
+    };
+    Some(FieldError { field, reason })
+}
+
+#[async_trait]
+impl<S, B, T> FromRequest<S, B> for Valid<T>
+where
+    Json<T>: FromRequest<S, B, Rejection = ApiError>,
+    T: Validate,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, ApiError> {
+        let Json(body) = Json::<T>::from_request(req, state).await?;
+        let fields = body.validate();
+        if fields.is_empty() {
+            return Ok(Valid(body));
+        }
+        Err(invalid_fields(fields))
+    }
+}
+
+/// The versions of a todo a write is conditional on, from its required
+/// `If-Match` header; a request without one is refused with a 428. `None`
+/// for `If-Match: *`, which any version of an existing todo matches. Entity
+/// tags that aren't a todo's, weak ones included, match no version.
+pub struct IfMatch(pub Option<Vec<i64>>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfMatch {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, ApiError> {
+        IfMatch::from_headers(&parts.headers).ok_or_else(|| {
+            ApiError::new(
+                StatusCode::PRECONDITION_REQUIRED,
+                "If-Match is required, give the todo's etag",
+            )
+        })
+    }
+}
+
+impl IfMatch {
+    /// The versions `headers`' `If-Match` lists, of a todo or anything else
+    /// versioned the same way; `None` without one.
+    pub fn from_headers(headers: &HeaderMap) -> Option<IfMatch> {
+        let mut values = headers.get_all(header::IF_MATCH).iter().peekable();
+        values.peek()?;
+        let mut versions = Vec::new();
+        for value in values {
+            let value = value.to_str().unwrap_or_default();
+            for etag in value.split(',').map(str::trim) {
+                if etag == "*" {
+                    return Some(IfMatch(None));
+                }
+                versions.extend(etag_version(etag));
+            }
+        }
+        Some(IfMatch(Some(versions)))
+    }
+}
+
+/// The version of the todo a strong entity tag such as its `etag` stands
+/// for, `None` for any other tag.
+pub fn etag_version(etag: &str) -> Option<i64> {
+    etag.strip_prefix('"')
+        .and_then(|etag| etag.strip_suffix('"'))
+        .and_then(|version| version.parse().ok())
+}
+
+/// The representation of a todo listing the client prefers by its `Accept`
+/// header: JSON, unless it prefers CSV. A client accepting neither is
+/// refused with a 406.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    Json,
+    Csv,
+}
+
+impl ListFormat {
+    pub fn media_type(self) -> &'static str {
+        match self {
+            ListFormat::Json => "application/json",
+            ListFormat::Csv => "text/csv",
+        }
+    }
+
+    fn matches(self, range: &str) -> bool {
+        let media_type = self.media_type();
+        let (kind, _) = media_type.split_once('/').expect("a type and subtype");
+        range == "*/*"
+            || range.eq_ignore_ascii_case(media_type)
+            || range
+                .strip_suffix("/*")
+                .is_some_and(|range| range.eq_ignore_ascii_case(kind))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ListFormat {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, ApiError> {
+        let Some(accept) = parts.headers.get(header::ACCEPT) else {
+            return Ok(ListFormat::Json);
+        };
+        let mut ranges: Vec<(&str, f32)> = accept
+            .to_str()
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                let media_range = params.next()?.trim();
+                let q = params
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse().ok())?;
+                (!media_range.is_empty() && q > 0.0).then_some((media_range, q))
+            })
+            .collect();
+        if ranges.is_empty() {
+            return Ok(ListFormat::Json);
+        }
+        // stable, so ranges of equal quality keep the client's order
+        ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        ranges
+            .into_iter()
+            .find_map(|(range, _)| {
+                [ListFormat::Json, ListFormat::Csv]
+                    .into_iter()
+                    .find(|format| format.matches(range))
+            })
+            .ok_or_else(|| {
+                ApiError::new(
+                    StatusCode::NOT_ACCEPTABLE,
+                    "Todo listings are application/json or text/csv",
+                )
+            })
+    }
+}
+
+/// The 422 a body with these invalid fields is refused with.
+pub fn invalid_fields(fields: Vec<FieldError>) -> ApiError {
+    ApiError {
+        details: Some(json!({ "source": "body", "fields": fields })),
+        ..ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "Invalid request body")
+    }
+}
+
+fn invalid(status: StatusCode, source: &str, reason: String) -> ApiError {
+    ApiError {
+        details: Some(json!({ "source": source, "reason": reason })),
+        ..ApiError::new(status, format!("Invalid request {source}"))
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        invalid(rejection.status(), "body", rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        invalid(rejection.status(), "query", rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        invalid(rejection.status(), "path", rejection.body_text())
+    }
+}
+
+impl From<WebSocketUpgradeRejection> for ApiError {
+    fn from(rejection: WebSocketUpgradeRejection) -> Self {
+        invalid(rejection.status(), "headers", rejection.body_text())
+    }
+}
+
+impl From<FormRejection> for ApiError {
+    fn from(rejection: FormRejection) -> Self {
+        invalid(rejection.status(), "body", rejection.body_text())
+    }
+}
+
+impl From<MultipartRejection> for ApiError {
+    fn from(rejection: MultipartRejection) -> Self {
+        invalid(rejection.status(), "body", rejection.body_text())
+    }
+}
+
+impl From<MultipartError> for ApiError {
+    fn from(err: MultipartError) -> Self {
+        invalid(err.status(), "body", err.body_text())
+    }
+}
@@ -0,0 +1,301 @@
+//! Fake but plausible data for demos and load tests: users with lists, tags
+//! and todos spread over the past and coming weeks, some done, some not.
+//! The same `rng_seed` gives the same data, so a load test can be set up
+//! again exactly as it was.
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::{
+    auth,
+    lists::{self, ListName},
+    models::Priority,
+    repository::{NewTodo, PgTodoRepository, TodoRepository},
+    tags::{self, CreateTag},
+};
+
+/// The password of every seeded user.
+pub const PASSWORD: &str = "seeded password";
+
+const USERNAMES: &[&str] = &[
+    "alice", "bob", "carol", "dave", "erin", "frank", "grace", "heidi", "ivan", "judy", "mallory",
+    "niaj", "olivia", "peggy", "rupert", "sybil", "trent", "victor", "walter",
+];
+
+const LISTS: &[&str] = &["Home", "Work", "Groceries", "Errands", "Side project"];
+
+const TAGS: &[&str] = &[
+    "urgent", "waiting", "phone", "computer", "outside", "someday",
+];
+
+/// Each verb with the things it is done to.
+const TASKS: &[(&str, &[&str])] = &[
+    (
+        "Buy",
+        &[
+            "milk",
+            "train tickets",
+            "new running shoes",
+            "flowers",
+            "a birthday card for mum",
+        ],
+    ),
+    (
+        "Call",
+        &[
+            "the dentist",
+            "the plumber",
+            "the bank",
+            "grandma",
+            "the landlord",
+        ],
+    ),
+    (
+        "Email",
+        &[
+            "the accountant",
+            "the school",
+            "the recruiter",
+            "the team about Friday",
+        ],
+    ),
+    (
+        "Fix",
+        &[
+            "the leaking tap",
+            "the bike",
+            "the flaky test",
+            "the garden gate",
+        ],
+    ),
+    (
+        "Clean",
+        &["the garage", "the windows", "the fridge", "the gutters"],
+    ),
+    (
+        "Book",
+        &[
+            "a haircut",
+            "the summer trip",
+            "a table for Saturday",
+            "the car service",
+        ],
+    ),
+    (
+        "Return",
+        &["the library books", "the parcel", "the drill to Sam"],
+    ),
+    (
+        "Renew",
+        &[
+            "my passport",
+            "the car insurance",
+            "the gym membership",
+            "the domain",
+        ],
+    ),
+    (
+        "Pay",
+        &["the electricity bill", "the rent", "the parking fine"],
+    ),
+    (
+        "Review",
+        &["the pull request", "the quarterly report", "the contract"],
+    ),
+    (
+        "Plan",
+        &["the team offsite", "the sprint", "meals for the week"],
+    ),
+    (
+        "Check",
+        &["the smoke alarms", "the tyre pressure", "the tax return"],
+    ),
+];
+
+const PRIORITIES: &[Priority] = &[
+    Priority::Low,
+    Priority::Medium,
+    Priority::High,
+    Priority::Urgent,
+];
+
+/// How much to seed.
+#[derive(Clone, Copy, Debug)]
+pub struct Seed {
+    pub users: usize,
+    pub todos_per_user: usize,
+    pub rng_seed: u64,
+}
+
+impl Default for Seed {
+    fn default() -> Self {
+        Seed {
+            users: 5,
+            todos_per_user: 50,
+            rng_seed: 1,
+        }
+    }
+}
+
+/// What [`seed`] inserted.
+#[derive(Debug, Default)]
+pub struct Seeded {
+    pub users: usize,
+    /// Users already there, which are left alone.
+    pub existing_users: usize,
+    pub todos: usize,
+}
+
+/// Inserts `seed.users` users named after [`USERNAMES`], `alice`, `bob` and
+/// so on, with [`PASSWORD`], each with their lists, tags and todos. Users
+/// that already exist are skipped, so seeding again only adds the missing
+/// ones.
+pub async fn seed(pg: &PgPool, seed: Seed) -> anyhow::Result<Seeded> {
+    let mut rng = Rng::new(seed.rng_seed);
+    let todos = PgTodoRepository::new(pg.clone());
+    // one hash serves every user, hashing is slow on purpose
+    let hash = auth::hash_password(PASSWORD.to_owned())
+        .await
+        .map_err(|err| anyhow::anyhow!(err.error))?;
+    let now = Utc::now();
+    let mut seeded = Seeded::default();
+    for n in 0..seed.users {
+        let username = match n / USERNAMES.len() {
+            0 => USERNAMES[n].to_owned(),
+            round => format!("{}{}", USERNAMES[n % USERNAMES.len()], round + 1),
+        };
+        let user_id = sqlx::query_scalar::<_, uuid::Uuid>(
+            r#"insert into "user" (username, password_hash) values ($1, $2)
+            on conflict (username) do nothing
+            returning user_id"#,
+        )
+        .bind(&username)
+        .bind(&hash)
+        .fetch_optional(pg)
+        .await
+        .with_context(|| format!("failed to insert {username}"))?;
+        let Some(user_id) = user_id else {
+            seeded.existing_users += 1;
+            continue;
+        };
+        seeded.users += 1;
+        seeded.todos += seed_user(pg, &todos, &mut rng, user_id, seed.todos_per_user, now).await?;
+        info!("Seeded {username}");
+    }
+    Ok(seeded)
+}
+
+async fn seed_user(
+    pg: &PgPool,
+    todos: &PgTodoRepository<'_>,
+    rng: &mut Rng,
+    user_id: uuid::Uuid,
+    count: usize,
+    now: DateTime<Utc>,
+) -> anyhow::Result<usize> {
+    let mut list_ids = Vec::new();
+    for &name in rng.some_of(LISTS, 2) {
+        let list = ListName {
+            name: name.to_owned(),
+        };
+        list_ids.push(lists::insert_list(pg, user_id, &list).await?.id);
+    }
+    let mut tag_ids = Vec::new();
+    for &name in rng.some_of(TAGS, 3) {
+        let tag = CreateTag {
+            name: name.to_owned(),
+        };
+        tag_ids.push(tags::insert_tag(pg, user_id, &tag).await?.id);
+    }
+
+    // texts are unique among a user's todos
+    let mut texts = HashSet::new();
+    while texts.len() < count {
+        let (verb, objects) = rng.pick(TASKS);
+        let text = format!("{verb} {}", rng.pick(objects));
+        let text = match texts.contains(&text) {
+            false => text,
+            true => format!("{text} ({})", texts.len() + 1),
+        };
+        texts.insert(text);
+    }
+    let mut texts: Vec<_> = texts.into_iter().collect();
+    // a set's order isn't the seed's
+    texts.sort();
+    let new_todos: Vec<_> = texts
+        .iter()
+        .map(|text| {
+            // due from 20 days ago to 40 days from now
+            let due_at = rng
+                .chance(0.6)
+                .then(|| now + Duration::hours(rng.below(24 * 60) as i64 - 24 * 20));
+            NewTodo {
+                text,
+                start_at: None,
+                due_at,
+                expires_at: None,
+                list_id: rng.chance(0.7).then(|| *rng.pick(&list_ids)),
+                priority: rng.chance(0.5).then(|| *rng.pick(PRIORITIES)),
+                // recurring needs a due date to repeat from
+                recurrence: (due_at.is_some() && rng.chance(0.1)).then_some("every week"),
+            }
+        })
+        .collect();
+
+    let mut inserted = 0;
+    for todo in todos.insert_many(user_id, &new_todos).await? {
+        let todo = todo?;
+        inserted += 1;
+        if rng.chance(0.4) {
+            todos.set_done(user_id, todo.id, true, None).await?;
+        }
+        if rng.chance(0.3) {
+            tags::tag_todo(pg, user_id, todo.id, *rng.pick(&tag_ids)).await?;
+        }
+    }
+    Ok(inserted)
+}
+
+/// SplitMix64: good enough for fake data, and the same on every platform.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64) < p * (1u64 << 53) as f64
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+
+    /// At least `min` of `items`, in their order.
+    fn some_of<'a, T>(&mut self, items: &'a [T], min: usize) -> Vec<&'a T> {
+        let count = min + self.below((items.len() - min + 1) as u64) as usize;
+        let mut chosen: Vec<_> = items.iter().collect();
+        while chosen.len() > count {
+            let index = self.below(chosen.len() as u64) as usize;
+            chosen.remove(index);
+        }
+        chosen
+    }
+}
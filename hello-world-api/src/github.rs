@@ -0,0 +1,292 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use hmac::{Hmac, Mac};
+use reqwest::Method;
+use serde::Deserialize;
+use sha2::Sha256;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::{
+    error::ApiError,
+    events::Events,
+    import::{self, ImportRow},
+    outbound::{self, Outbound},
+};
+
+/// Access to one GitHub repository, configured with `GITHUB_TOKEN` and
+/// `GITHUB_REPO` (`owner/name`).
+pub struct GithubClient {
+    http: Outbound,
+    token: String,
+    repo: String,
+    /// Push done/undone changes of imported todos back to their issues
+    /// (`GITHUB_SYNC_ISSUES`).
+    pub sync_issues: bool,
+    /// Secret GitHub signs webhook deliveries with (`GITHUB_WEBHOOK_SECRET`).
+    webhook_secret: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Issue {
+    pub number: u64,
+    pub title: String,
+    pub state: String,
+    pub html_url: String,
+    /// Only present when the "issue" is actually a pull request.
+    pull_request: Option<serde_json::Value>,
+}
+
+impl GithubClient {
+    /// `None` unless both token and repository are configured.
+    pub fn from_env(http: Outbound) -> Option<Self> {
+        let (Ok(token), Ok(repo)) = (std::env::var("GITHUB_TOKEN"), std::env::var("GITHUB_REPO"))
+        else {
+            return None;
+        };
+        Some(GithubClient {
+            http,
+            token,
+            repo,
+            sync_issues: std::env::var("GITHUB_SYNC_ISSUES").is_ok_and(|v| v == "true"),
+            webhook_secret: std::env::var("GITHUB_WEBHOOK_SECRET").ok(),
+        })
+    }
+
+    /// `external_id` of the todo imported from issue `number`.
+    pub fn external_id(&self, number: u64) -> String {
+        format!("github:{}#{}", self.repo, number)
+    }
+
+    /// Issue number encoded in `external_id`, if it belongs to this repo.
+    pub fn issue_number(&self, external_id: &str) -> Option<u64> {
+        external_id
+            .strip_prefix("github:")?
+            .strip_prefix(self.repo.as_str())?
+            .strip_prefix('#')?
+            .parse()
+            .ok()
+    }
+
+    /// All open and closed issues of the repository, pull requests excluded.
+    pub async fn issues(&self) -> anyhow::Result<Vec<Issue>> {
+        let mut issues = Vec::new();
+        for page in 1.. {
+            let url = format!("https://api.github.com/repos/{}/issues", self.repo);
+            let batch: Vec<Issue> = self
+                .http
+                .send(&outbound::GITHUB, Method::GET, &url, |request| {
+                    request
+                        .bearer_auth(&self.token)
+                        .query(&[("state", "all"), ("per_page", "100")])
+                        .query(&[("page", page)])
+                })
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let last = batch.len() < 100;
+            issues.extend(
+                batch
+                    .into_iter()
+                    .filter(|issue| issue.pull_request.is_none()),
+            );
+            if last {
+                break;
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Brings the state of the issue `todo_id` was imported from in line
+    /// with the todo, if there is such an issue.
+    async fn sync_issue(&self, pg: &PgPool, todo_id: uuid::Uuid) -> anyhow::Result<()> {
+        let todo = sqlx::query!(
+            r#"select external_id, is_done from "todo" where id = $1"#,
+            todo_id,
+        )
+        .fetch_optional(pg)
+        .await?;
+        let Some((Some(external_id), is_done)) = todo.map(|todo| (todo.external_id, todo.is_done))
+        else {
+            return Ok(());
+        };
+        let Some(number) = self.issue_number(&external_id) else {
+            return Ok(());
+        };
+        let state = if is_done { "closed" } else { "open" };
+        self.set_issue_state(number, state).await?;
+        info!(number, state, "Synced GitHub issue state");
+        Ok(())
+    }
+
+    async fn set_issue_state(&self, number: u64, state: &str) -> anyhow::Result<()> {
+        let url = format!(
+            "https://api.github.com/repos/{}/issues/{}",
+            self.repo, number
+        );
+        self.http
+            .send(&outbound::GITHUB, Method::PATCH, &url, |request| {
+                request
+                    .bearer_auth(&self.token)
+                    .json(&serde_json::json!({ "state": state }))
+            })
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Queue of todos whose state has to be pushed to GitHub, drained by the
+/// worker started with [`GithubSync::spawn`].
+///
+/// Only changes made through the API are queued. Changes arriving through
+/// the webhook are applied directly, so an update never bounces back to the
+/// side it came from.
+#[derive(Clone)]
+pub struct GithubSync(mpsc::Sender<uuid::Uuid>);
+
+impl GithubSync {
+    pub fn spawn(github: Arc<GithubClient>, pg: PgPool) -> Self {
+        let (tx, mut rx) = mpsc::channel::<uuid::Uuid>(1024);
+        tokio::spawn(async move {
+            while let Some(todo_id) = rx.recv().await {
+                if let Err(err) = github.sync_issue(&pg, todo_id).await {
+                    error!("Fail to sync GitHub issue of {} {:?}", todo_id, err);
+                }
+            }
+        });
+        GithubSync(tx)
+    }
+
+    pub fn push(&self, todo_id: uuid::Uuid) {
+        if self.0.try_send(todo_id).is_err() {
+            warn!(%todo_id, "GitHub sync queue is full, dropping update");
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IssuesEvent {
+    action: String,
+    issue: Issue,
+    repository: Repository,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    full_name: String,
+}
+
+/// Receives GitHub `issues` webhook deliveries and mirrors opened, edited,
+/// closed and reopened issues onto their todos, for every user who has
+/// imported the repository.
+#[utoipa::path(
+    post,
+    path = "/integrations/github",
+    tag = "integrations",
+    request_body(content = Object, description = "GitHub webhook delivery, signed with `X-Hub-Signature-256`"),
+    responses(
+        (status = 204, description = "Handled or ignored"),
+        (status = 400, description = "Invalid `issues` event", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Invalid signature", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "The GitHub integration is off", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn webhook(
+    pg: Extension<PgPool>,
+    Extension(github): Extension<Option<Arc<GithubClient>>>,
+    Extension(events): Extension<Events>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> axum::response::Response {
+    let Some(github) = github else {
+        return ApiError::new(StatusCode::NOT_FOUND, "Not found").into_response();
+    };
+    let Some(secret) = &github.webhook_secret else {
+        return ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "GitHub webhook secret is not configured",
+        )
+        .into_response();
+    };
+    let signature = headers
+        .get("x-hub-signature-256")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !signature_matches(secret, &body, signature) {
+        return ApiError::new(StatusCode::UNAUTHORIZED, "Invalid webhook signature")
+            .into_response();
+    }
+    match headers.get("x-github-event").and_then(|v| v.to_str().ok()) {
+        Some("issues") => {}
+        // pings and events we don't mirror are acknowledged and ignored
+        _ => return StatusCode::NO_CONTENT.into_response(),
+    }
+
+    let event: IssuesEvent = match serde_json::from_slice(&body) {
+        Ok(event) => event,
+        Err(err) => {
+            return ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid issues event: {err}"),
+            )
+            .into_response()
+        }
+    };
+    if event.repository.full_name != github.repo || event.issue.pull_request.is_some() {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    if !matches!(
+        event.action.as_str(),
+        "opened" | "edited" | "closed" | "reopened"
+    ) {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    let row = ImportRow {
+        is_done: event.issue.state == "closed",
+        external_id: Some(github.external_id(event.issue.number)),
+        external_url: Some(event.issue.html_url),
+        text: event.issue.title,
+        due_at: None,
+        list: None,
+        tags: Vec::new(),
+    };
+    let owners = sqlx::query_scalar::<_, uuid::Uuid>(
+        r#"select distinct user_id from "todo"
+        where starts_with(external_id, $1) and user_id is not null"#,
+    )
+    .bind(format!("github:{}#", github.repo))
+    .fetch_all(&*pg)
+    .await;
+    let owners = match owners {
+        Ok(owners) => owners,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    for user_id in owners {
+        if let Err(err) = import::insert_row(&pg, &events, user_id, &row).await {
+            return ApiError::from(err).into_response();
+        }
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Checks a `sha256=<hex>` `X-Hub-Signature-256` header in constant time.
+fn signature_matches(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(Ok(signature)) = signature.strip_prefix("sha256=").map(hex::decode) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
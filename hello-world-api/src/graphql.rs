@@ -0,0 +1,641 @@
+//! A GraphQL API at `/graphql` over the same todos, tags and lists as the
+//! REST endpoints: queries with the listings' filters, mutations creating,
+//! changing and deleting each, and a `todoEvents` subscription streaming the
+//! events `GET /ws/todos` sends, over a WebSocket at `/graphql/ws`. Fields go
+//! through the same repository and queries as the handlers and publish the
+//! same events.
+//!
+//! Requests carry the REST API's bearer token, checked per field: queries
+//! need `todos:read`, mutations `todos:write`. Mutations are only run when
+//! POSTed, so GET requests, which maintenance mode and read-only replicas let
+//! through, stay reads. A subscription socket takes the token from the
+//! `Authorization` of its `connection_init` payload, or else from its upgrade
+//! request. Errors carry the REST API's `status`, `code` and `details` in
+//! their extensions.
+
+use std::sync::Arc;
+
+use async_graphql::{
+    http::ALL_WEBSOCKET_PROTOCOLS, Context, Data, ErrorExtensions, InputObject, MaybeUndefined,
+    Object, Schema, SimpleObject, Subscription,
+};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use futures_util::{Stream, StreamExt};
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::{
+    auth::{Auth, Scope},
+    error::ApiError,
+    events::{Events, TodoEvent},
+    extract::{etag_version, invalid_fields, Validate, WebSocketUpgrade},
+    github::GithubSync,
+    lists::{self, List, ListName},
+    models::{CreateTodo, PatchTodo, Priority, ToDoView},
+    quota::Quota,
+    repository::{
+        todo_query::{self, TodoQuery},
+        NewTodo, RepositoryError, TodoChanges, Todos,
+    },
+    tags::{self, CreateTag, Tag},
+};
+
+pub type TodoSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// Deepest selection accepted. Nothing in the schema nests deeper than a
+/// todo's tags, so this only turns away abusive queries.
+const MAX_DEPTH: usize = 8;
+
+pub fn schema() -> TodoSchema {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+/// What the resolvers work with: the handlers' state and extensions.
+struct Backend {
+    todos: Todos,
+    pg: PgPool,
+    auth: Auth,
+    events: Events,
+    quota: Option<Quota>,
+    github_sync: Option<GithubSync>,
+}
+
+/// Who sends a request, and whether it may write.
+struct Caller {
+    /// Of which only `Authorization` counts.
+    headers: HeaderMap,
+    /// Only POSTed requests run mutations.
+    writes: bool,
+}
+
+/// Runs a query or, POSTed, a mutation.
+#[allow(clippy::too_many_arguments)] // one per extractor
+pub async fn execute(
+    State(todos): State<Todos>,
+    Extension(schema): Extension<TodoSchema>,
+    Extension(pg): Extension<PgPool>,
+    Extension(auth): Extension<Auth>,
+    Extension(events): Extension<Events>,
+    Extension(quota): Extension<Option<Quota>>,
+    Extension(github_sync): Extension<Option<GithubSync>>,
+    method: Method,
+    headers: HeaderMap,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let backend = Backend {
+        todos,
+        pg,
+        auth,
+        events,
+        quota,
+        github_sync,
+    };
+    let caller = Caller {
+        headers,
+        writes: method == Method::POST,
+    };
+    let request = request.into_inner().data(backend).data(caller);
+    schema.execute(request).await.into()
+}
+
+/// Upgrades to a WebSocket speaking `graphql-transport-ws` or the older
+/// `graphql-ws`, for subscriptions. It doesn't run mutations.
+#[allow(clippy::too_many_arguments)] // one per extractor
+pub async fn subscribe(
+    State(todos): State<Todos>,
+    Extension(schema): Extension<TodoSchema>,
+    Extension(pg): Extension<PgPool>,
+    Extension(auth): Extension<Auth>,
+    Extension(events): Extension<Events>,
+    Extension(quota): Extension<Option<Quota>>,
+    Extension(github_sync): Extension<Option<GithubSync>>,
+    headers: HeaderMap,
+    protocol: GraphQLProtocol,
+    WebSocketUpgrade(ws): WebSocketUpgrade,
+) -> axum::response::Response {
+    let mut data = Data::default();
+    data.insert(Backend {
+        todos,
+        pg,
+        auth,
+        events,
+        quota,
+        github_sync,
+    });
+    ws.protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| {
+            GraphQLWebSocket::new(socket, schema, protocol)
+                .with_data(data)
+                .on_connection_init(|payload| async move {
+                    let mut data = Data::default();
+                    data.insert(Caller {
+                        headers: init_headers(&payload).unwrap_or(headers),
+                        writes: false,
+                    });
+                    Ok(data)
+                })
+                .serve()
+        })
+        .into_response()
+}
+
+/// The `Authorization` of a `connection_init` payload, as browsers can't
+/// send headers with the upgrade request.
+fn init_headers(payload: &serde_json::Value) -> Option<HeaderMap> {
+    let authorization = payload
+        .get("Authorization")
+        .or_else(|| payload.get("authorization"))?
+        .as_str()?;
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(authorization).ok()?,
+    );
+    Some(headers)
+}
+
+fn backend<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a Backend> {
+    ctx.data::<Backend>()
+}
+
+/// The caller, whose token must have `scope`.
+fn user(ctx: &Context<'_>, scope: Scope) -> async_graphql::Result<uuid::Uuid> {
+    let caller = ctx.data::<Caller>()?;
+    backend(ctx)?
+        .auth
+        .authorize(&caller.headers, scope)
+        .map_err(error)
+}
+
+/// The caller of a mutation, if the request may write.
+fn writer(ctx: &Context<'_>) -> async_graphql::Result<uuid::Uuid> {
+    if !ctx.data::<Caller>()?.writes {
+        return Err(error(ApiError::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Mutations have to be POSTed to /graphql",
+        )));
+    }
+    user(ctx, Scope::TodosWrite)
+}
+
+/// `err` as a GraphQL error, its problem details in the extensions.
+fn error(err: impl Into<ApiError>) -> async_graphql::Error {
+    let err = err.into();
+    let code = serde_json::to_value(err.error_code)
+        .ok()
+        .and_then(|code| async_graphql::Value::from_json(code).ok());
+    let details = err
+        .details
+        .and_then(|details| async_graphql::Value::from_json(details).ok());
+    async_graphql::Error::new(err.error).extend_with(|_, extensions| {
+        extensions.set("status", err.code.as_u16());
+        if let Some(code) = code {
+            extensions.set("code", code);
+        }
+        if let Some(details) = details {
+            extensions.set("details", details);
+        }
+    })
+}
+
+/// Fails with a 422 listing `body`'s invalid fields, if any.
+fn check(body: &impl Validate) -> async_graphql::Result<()> {
+    let fields = body.validate();
+    if fields.is_empty() {
+        return Ok(());
+    }
+    Err(error(invalid_fields(fields)))
+}
+
+/// The `todos` query's filters, those of `GET /todos`.
+#[derive(Default, InputObject)]
+pub struct TodoFilter {
+    is_done: Option<bool>,
+    /// Only todos whose start date has (or hasn't) been reached.
+    started: Option<bool>,
+    /// Only open todos past their due date, or only the others.
+    overdue: Option<bool>,
+    /// Only todos due before this time.
+    due_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only todos with the tag of this name.
+    tag: Option<String>,
+    list_id: Option<uuid::Uuid>,
+    priority: Option<Priority>,
+    /// Only todos that expired, or only the others.
+    expired: Option<bool>,
+    /// Also list soft-deleted todos.
+    #[graphql(default)]
+    include_deleted: bool,
+    /// Case-insensitive substring of the text.
+    q: Option<String>,
+    /// Full-text search in each todo's language, e.g. `"buy milk" -oat`.
+    search: Option<String>,
+    /// Only todos changed after this time; with `includeDeleted` that
+    /// includes the ones deleted since.
+    since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A page of a todo listing.
+#[derive(SimpleObject)]
+#[graphql(name = "TodoPage")]
+pub struct Page {
+    items: Vec<ToDoView>,
+    /// All todos matching the filters, across pages.
+    total: i64,
+    /// Whether `total` is estimated, as it is for listings matching more
+    /// todos than `EXACT_COUNT_LIMIT`.
+    total_estimated: bool,
+    /// `after` for the next page; only set for unsorted listings with more
+    /// todos.
+    next_cursor: Option<String>,
+}
+
+/// The fields `updateTodo` changes, those that are given; `null` clears
+/// them, as in `PATCH /todos/{id}`.
+#[derive(InputObject)]
+pub struct UpdateTodoInput {
+    text: Option<String>,
+    is_done: Option<bool>,
+    due_at: MaybeUndefined<chrono::DateTime<chrono::Utc>>,
+    /// Setting it either way revives an expired todo.
+    expires_at: MaybeUndefined<chrono::DateTime<chrono::Utc>>,
+    list_id: MaybeUndefined<uuid::Uuid>,
+    priority: MaybeUndefined<Priority>,
+    recurrence: MaybeUndefined<String>,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// One page of the caller's todos, filtered, sorted and paged like
+    /// `GET /todos`.
+    async fn todos(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] filter: TodoFilter,
+        #[graphql(desc = "As the `sort` of `GET /todos`, e.g. `-priority,due_at`")] sort: Option<
+            String,
+        >,
+        #[graphql(desc = "`nextCursor` of the previous page; only without `sort`")] after: Option<
+            String,
+        >,
+        #[graphql(desc = "Page size, 1 to 100 (default 10)")] limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> async_graphql::Result<Page> {
+        let user_id = user(ctx, Scope::TodosRead)?;
+        let backend = backend(ctx)?;
+        let defaults = TodoQuery::default();
+        let sort = match sort {
+            Some(spec) => todo_query::parse_sort(&spec)
+                .map_err(|err| error(ApiError::new(StatusCode::BAD_REQUEST, err)))?,
+            None => defaults.sort,
+        };
+        if after.is_some() && !sort.is_empty() {
+            return Err(error(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "after can only be used without sort, use offset instead",
+            )));
+        }
+        let after = match after {
+            Some(cursor) => Some(
+                cursor
+                    .parse()
+                    .map_err(|err| error(ApiError::new(StatusCode::BAD_REQUEST, err)))?,
+            ),
+            None => None,
+        };
+        let query = TodoQuery {
+            is_done: filter.is_done,
+            started: filter.started,
+            overdue: filter.overdue,
+            due_before: filter.due_before,
+            tag: filter.tag,
+            list_id: filter.list_id,
+            assignee_id: None,
+            priority: filter.priority,
+            expired: filter.expired,
+            include_deleted: filter.include_deleted,
+            include_shared: false,
+            text_contains: filter.q.filter(|q| !q.is_empty()),
+            search: filter.search.filter(|search| !search.is_empty()),
+            since: filter.since,
+            sort,
+            after,
+            ids: None,
+            fields: None,
+            limit: limit.unwrap_or(defaults.limit).clamp(1, 100),
+            offset: offset.unwrap_or(defaults.offset).max(0),
+        };
+        let total = backend
+            .todos
+            .count_for_listing(user_id, &query)
+            .await
+            .map_err(error)?;
+        let (todos, next_cursor) = backend
+            .todos
+            .list_page(user_id, &query)
+            .await
+            .map_err(error)?;
+        Ok(Page {
+            items: todos.into_iter().map(ToDoView::from).collect(),
+            total: total.count,
+            total_estimated: total.estimated,
+            next_cursor: next_cursor.map(|cursor| cursor.to_string()),
+        })
+    }
+
+    /// The todo, or the one it was merged into; `null` if the caller has no
+    /// such todo.
+    async fn todo(
+        &self,
+        ctx: &Context<'_>,
+        id: uuid::Uuid,
+    ) -> async_graphql::Result<Option<ToDoView>> {
+        let user_id = user(ctx, Scope::TodosRead)?;
+        let todos = &backend(ctx)?.todos;
+        let id = match todos.merged_into(user_id, id).await.map_err(error)? {
+            Some(target) => target,
+            None => id,
+        };
+        match todos.get(user_id, id).await {
+            Ok(todo) => Ok(Some(ToDoView::from(todo))),
+            Err(RepositoryError::NotFound) => Ok(None),
+            Err(err) => Err(error(err)),
+        }
+    }
+
+    /// The caller's tags, by name.
+    async fn tags(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Tag>> {
+        let user_id = user(ctx, Scope::TodosRead)?;
+        tags::user_tags(&backend(ctx)?.pg, user_id)
+            .await
+            .map_err(error)
+    }
+
+    /// The caller's lists, by name.
+    async fn lists(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<List>> {
+        let user_id = user(ctx, Scope::TodosRead)?;
+        lists::user_lists(&backend(ctx)?.pg, user_id)
+            .await
+            .map_err(error)
+    }
+
+    /// The list, `null` if the caller has no such list.
+    async fn list(&self, ctx: &Context<'_>, id: uuid::Uuid) -> async_graphql::Result<Option<List>> {
+        let user_id = user(ctx, Scope::TodosRead)?;
+        match lists::find_list(&backend(ctx)?.pg, user_id, id).await {
+            Ok(list) => Ok(Some(list)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(err) => Err(error(err)),
+        }
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Creates a todo, as `POST /todos` does.
+    async fn create_todo(
+        &self,
+        ctx: &Context<'_>,
+        input: CreateTodo,
+    ) -> async_graphql::Result<ToDoView> {
+        let user_id = writer(ctx)?;
+        let backend = backend(ctx)?;
+        check(&input)?;
+        // the quota is checked in the transaction inserting the todo
+        let mut tx = backend.pg.begin().await.map_err(error)?;
+        let todos = backend.todos.unit_of_work(&mut tx);
+        if let Some(quota) = backend.quota {
+            quota.check_create(&*todos, user_id).await.map_err(error)?;
+        }
+        let todo = todos
+            .insert(user_id, NewTodo::from(&input))
+            .await
+            .map_err(error)?;
+        drop(todos);
+        backend
+            .events
+            .created(&mut tx, user_id, &todo)
+            .await
+            .map_err(error)?;
+        tx.commit().await.map_err(error)?;
+        Ok(ToDoView::from(todo))
+    }
+
+    /// Changes the todo if it is still at the version of `etag`, or at any
+    /// version for `*`, as `PATCH /todos/{id}` does.
+    async fn update_todo(
+        &self,
+        ctx: &Context<'_>,
+        id: uuid::Uuid,
+        etag: String,
+        input: UpdateTodoInput,
+    ) -> async_graphql::Result<ToDoView> {
+        let user_id = writer(ctx)?;
+        let backend = backend(ctx)?;
+        let versions = match etag.trim() {
+            "*" => None,
+            etag => Some(etag_version(etag).into_iter().collect::<Vec<_>>()),
+        };
+        let body = PatchTodo {
+            text: input.text,
+            is_done: input.is_done,
+            due_at: input.due_at.into(),
+            expires_at: input.expires_at.into(),
+            list_id: input.list_id.into(),
+            priority: input.priority.into(),
+            recurrence: input.recurrence.into(),
+        };
+        if body.is_empty() {
+            return Err(error(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Nothing to update, give text, isDone, dueAt, expiresAt, listId, priority or \
+                 recurrence",
+            )));
+        }
+        check(&body)?;
+        let mut tx = backend.pg.begin().await.map_err(error)?;
+        let todo = backend
+            .todos
+            .unit_of_work(&mut tx)
+            .update(user_id, id, TodoChanges::from(&body), versions.as_deref())
+            .await
+            .map_err(error)?;
+        backend
+            .events
+            .updated(&mut tx, user_id, &todo)
+            .await
+            .map_err(error)?;
+        tx.commit().await.map_err(error)?;
+        // only the done state is synced to GitHub issues
+        if let Some(github_sync) = backend
+            .github_sync
+            .as_ref()
+            .filter(|_| body.is_done.is_some())
+        {
+            github_sync.push(id);
+        }
+        Ok(ToDoView::from(todo))
+    }
+
+    /// Soft-deletes the todo, as `DELETE /todos/{id}` does. Returns its id.
+    async fn delete_todo(
+        &self,
+        ctx: &Context<'_>,
+        id: uuid::Uuid,
+    ) -> async_graphql::Result<uuid::Uuid> {
+        let user_id = writer(ctx)?;
+        let backend = backend(ctx)?;
+        let mut tx = backend.pg.begin().await.map_err(error)?;
+        backend
+            .todos
+            .unit_of_work(&mut tx)
+            .soft_delete(user_id, id)
+            .await
+            .map_err(error)?;
+        backend
+            .events
+            .deleted(&mut tx, user_id, id)
+            .await
+            .map_err(error)?;
+        tx.commit().await.map_err(error)?;
+        Ok(id)
+    }
+
+    async fn create_tag(&self, ctx: &Context<'_>, name: String) -> async_graphql::Result<Tag> {
+        let user_id = writer(ctx)?;
+        let tag = CreateTag { name };
+        check(&tag)?;
+        tags::insert_tag(&backend(ctx)?.pg, user_id, &tag)
+            .await
+            .map_err(error)
+    }
+
+    /// Tags the todo; tagging it again changes nothing.
+    async fn tag_todo(
+        &self,
+        ctx: &Context<'_>,
+        todo_id: uuid::Uuid,
+        tag_id: uuid::Uuid,
+    ) -> async_graphql::Result<ToDoView> {
+        let user_id = writer(ctx)?;
+        let backend = backend(ctx)?;
+        let mut tx = backend.pg.begin().await.map_err(error)?;
+        let found = tags::tag_todo(&mut *tx, user_id, todo_id, tag_id).await;
+        retagged(backend, tx, user_id, todo_id, found).await
+    }
+
+    /// Untags the todo; untagging one without the tag changes nothing.
+    async fn untag_todo(
+        &self,
+        ctx: &Context<'_>,
+        todo_id: uuid::Uuid,
+        tag_id: uuid::Uuid,
+    ) -> async_graphql::Result<ToDoView> {
+        let user_id = writer(ctx)?;
+        let backend = backend(ctx)?;
+        let mut tx = backend.pg.begin().await.map_err(error)?;
+        let found = tags::untag_todo(&mut *tx, user_id, todo_id, tag_id).await;
+        retagged(backend, tx, user_id, todo_id, found).await
+    }
+
+    async fn create_list(&self, ctx: &Context<'_>, name: String) -> async_graphql::Result<List> {
+        let user_id = writer(ctx)?;
+        let list = ListName { name };
+        check(&list)?;
+        lists::insert_list(&backend(ctx)?.pg, user_id, &list)
+            .await
+            .map_err(error)
+    }
+
+    async fn rename_list(
+        &self,
+        ctx: &Context<'_>,
+        id: uuid::Uuid,
+        name: String,
+    ) -> async_graphql::Result<List> {
+        let user_id = writer(ctx)?;
+        let list = ListName { name };
+        check(&list)?;
+        lists::rename_list(&backend(ctx)?.pg, user_id, id, &list)
+            .await
+            .map_err(error)
+    }
+
+    /// Deletes the list, keeping its todos out of any list. Returns its id.
+    async fn delete_list(
+        &self,
+        ctx: &Context<'_>,
+        id: uuid::Uuid,
+    ) -> async_graphql::Result<uuid::Uuid> {
+        let user_id = writer(ctx)?;
+        let backend = backend(ctx)?;
+        let mut tx = backend.pg.begin().await.map_err(error)?;
+        let moved = lists::delete_list(&mut tx, user_id, id)
+            .await
+            .map_err(error)?;
+        for todo_id in moved {
+            backend
+                .events
+                .changed(&mut tx, user_id, todo_id)
+                .await
+                .map_err(error)?;
+        }
+        tx.commit().await.map_err(error)?;
+        Ok(id)
+    }
+}
+
+/// The todo after tagging or untagging it in `tx`, `found` telling whether
+/// the caller had the todo and tag.
+async fn retagged(
+    backend: &Backend,
+    mut tx: Transaction<'static, Postgres>,
+    user_id: uuid::Uuid,
+    todo_id: uuid::Uuid,
+    found: Result<bool, sqlx::Error>,
+) -> async_graphql::Result<ToDoView> {
+    if !found.map_err(error)? {
+        return Err(error(sqlx::Error::RowNotFound));
+    }
+    backend
+        .events
+        .changed(&mut tx, user_id, todo_id)
+        .await
+        .map_err(error)?;
+    tx.commit().await.map_err(error)?;
+    let todo = backend.todos.get(user_id, todo_id).await.map_err(error)?;
+    Ok(ToDoView::from(todo))
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// The caller's todo changes as they happen. Ends with an error when the
+    /// subscriber falls too far behind, after which it has to fetch its
+    /// todos again.
+    async fn todo_events(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<impl Stream<Item = async_graphql::Result<Arc<TodoEvent>>>> {
+        let user_id = user(ctx, Scope::TodosRead)?;
+        let events = backend(ctx)?.events.watch(user_id);
+        Ok(events.map(move |event| {
+            event.map_err(|missed| {
+                tracing::warn!(%user_id, missed, "GraphQL subscriber fell behind the todo events");
+                async_graphql::Error::new("Missed events, fetch the todos again")
+            })
+        }))
+    }
+}
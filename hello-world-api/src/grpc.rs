@@ -0,0 +1,458 @@
+//! The core todo operations over gRPC, as `proto/todo.proto` defines them,
+//! served on `GRPC_LISTEN` next to the REST API. Calls go through the same
+//! [`TodoRepository`] and publish the same [`Events`] as the handlers, so
+//! REST and gRPC clients see each other's changes, and `WatchTodos` streams
+//! them like `GET /ws/todos`.
+//!
+//! Callers send the REST API's bearer tokens as `authorization` metadata,
+//! and errors carry the gRPC status closest to the REST API's HTTP status.
+//! Writes are refused on read-only replicas and in maintenance mode, which
+//! the public listener's middlewares can't tell apart from reads here since
+//! every call is a `POST`.
+
+// calls fail with tonic's `Status`, however large it is
+#![allow(clippy::result_large_err)]
+
+use std::pin::Pin;
+
+use axum::{http::StatusCode, Router};
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::{Stream, StreamExt};
+use sqlx::PgPool;
+use tonic::{transport::server::Routes, Code, Request, Response, Status};
+use tracing::warn;
+
+use crate::{
+    auth::{Auth, Scope},
+    error::{ApiError, ErrorCode},
+    events::{EventKind, Events, TodoEvent},
+    extract::{etag_version, invalid_fields, Validate},
+    github::GithubSync,
+    maintenance::Maintenance,
+    models::{CreateTodo, PatchTodo, Priority, ToDoView, TodoStatus},
+    quota::Quota,
+    repository::{
+        todo_query::{self, TodoQuery},
+        NewTodo, RepositoryError, TodoChanges, Todos,
+    },
+};
+
+pub mod proto {
+    tonic::include_proto!("todo.v1");
+}
+
+use proto::todos_server::TodosServer;
+
+#[derive(Clone)]
+pub struct TodoService {
+    pub todos: Todos,
+    pub pool: PgPool,
+    pub auth: Auth,
+    pub events: Events,
+    pub quota: Option<Quota>,
+    pub github_sync: Option<GithubSync>,
+    pub maintenance: Maintenance,
+    /// `READ_ONLY`.
+    pub read_only: bool,
+}
+
+/// The service as a router, to be served over HTTP/2.
+pub fn router(service: TodoService) -> Router {
+    Routes::new(TodosServer::new(service)).into_router()
+}
+
+impl TodoService {
+    /// The caller, whose token must have `scope`.
+    fn user<T>(&self, request: &Request<T>, scope: Scope) -> Result<uuid::Uuid, Status> {
+        let headers = request.metadata().clone().into_headers();
+        Ok(self.auth.authorize(&headers, scope)?)
+    }
+
+    /// The caller of a write, if writes are accepted.
+    fn writer<T>(&self, request: &Request<T>) -> Result<uuid::Uuid, Status> {
+        if self.read_only {
+            return Err(Status::failed_precondition(
+                "This is a read-only replica, send writes to the primary",
+            ));
+        }
+        if self.maintenance.enabled() {
+            return Err(Status::unavailable(
+                "The service is undergoing maintenance and is read-only for now, please try \
+                 again in a few minutes",
+            ));
+        }
+        self.user(request, Scope::TodosWrite)
+    }
+}
+
+type WatchStream = Pin<Box<dyn Stream<Item = Result<proto::TodoEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl proto::todos_server::Todos for TodoService {
+    async fn list_todos(
+        &self,
+        request: Request<proto::ListTodosRequest>,
+    ) -> Result<Response<proto::ListTodosResponse>, Status> {
+        let user_id = self.user(&request, Scope::TodosRead)?;
+        let query = list_query(request.into_inner())?;
+        let total = self.todos.count_for_listing(user_id, &query).await?;
+        let (todos, next_cursor) = self.todos.list_page(user_id, &query).await?;
+        Ok(Response::new(proto::ListTodosResponse {
+            todos: todos
+                .into_iter()
+                .map(|todo| proto::Todo::from(&ToDoView::from(todo)))
+                .collect(),
+            total: total.count,
+            total_estimated: total.estimated,
+            next_page_token: next_cursor
+                .map(|cursor| cursor.to_string())
+                .unwrap_or_default(),
+        }))
+    }
+
+    async fn get_todo(
+        &self,
+        request: Request<proto::GetTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let user_id = self.user(&request, Scope::TodosRead)?;
+        let id = parse_id("id", &request.get_ref().id)?;
+        let todo = self.todos.get(user_id, id).await?;
+        Ok(Response::new(proto::Todo::from(&ToDoView::from(todo))))
+    }
+
+    async fn create_todo(
+        &self,
+        request: Request<proto::CreateTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let user_id = self.writer(&request)?;
+        let request = request.into_inner();
+        let body = CreateTodo {
+            priority: priority(request.priority()),
+            text: request.text,
+            start_at: optional_time("start_at", request.start_at)?,
+            due_at: optional_time("due_at", request.due_at)?,
+            expires_at: optional_time("expires_at", request.expires_at)?,
+            list_id: optional_id("list_id", request.list_id.as_deref())?,
+            recurrence: request.recurrence,
+        };
+        let fields = body.validate();
+        if !fields.is_empty() {
+            return Err(invalid_fields(fields).into());
+        }
+        // the quota is checked in the transaction inserting the todo, as
+        // `POST /todos` does
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let todos = self.todos.unit_of_work(&mut tx);
+        if let Some(quota) = self.quota {
+            quota.check_create(&*todos, user_id).await?;
+        }
+        let todo = todos.insert(user_id, NewTodo::from(&body)).await?;
+        drop(todos);
+        self.events
+            .created(&mut tx, user_id, &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+        Ok(Response::new(proto::Todo::from(&ToDoView::from(todo))))
+    }
+
+    async fn update_todo(
+        &self,
+        request: Request<proto::UpdateTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let user_id = self.writer(&request)?;
+        let request = request.into_inner();
+        let id = parse_id("id", &request.id)?;
+        let versions = versions(&request.etag)?;
+        let values = request.todo.unwrap_or_default();
+        let mut body = PatchTodo {
+            text: None,
+            is_done: None,
+            due_at: None,
+            expires_at: None,
+            list_id: None,
+            priority: None,
+            recurrence: None,
+        };
+        for path in request
+            .update_mask
+            .map(|mask| mask.paths)
+            .unwrap_or_default()
+        {
+            match path.as_str() {
+                "text" => body.text = Some(values.text.clone()),
+                "is_done" => body.is_done = Some(values.is_done),
+                "due_at" => body.due_at = Some(optional_time("due_at", values.due_at.clone())?),
+                "expires_at" => {
+                    body.expires_at = Some(optional_time("expires_at", values.expires_at.clone())?)
+                }
+                "list_id" => {
+                    body.list_id = Some(optional_id("list_id", values.list_id.as_deref())?)
+                }
+                "priority" => body.priority = Some(priority(values.priority())),
+                "recurrence" => body.recurrence = Some(values.recurrence.clone()),
+                other => {
+                    return Err(Status::invalid_argument(format!(
+                        "{other} can't be updated, give text, is_done, due_at, expires_at, \
+                         list_id, priority or recurrence"
+                    )))
+                }
+            }
+        }
+        if body.is_empty() {
+            return Err(Status::invalid_argument(
+                "Nothing to update, give text, is_done, due_at, expires_at, list_id, priority \
+                 or recurrence in update_mask",
+            ));
+        }
+        let fields = body.validate();
+        if !fields.is_empty() {
+            return Err(invalid_fields(fields).into());
+        }
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        let todo = self
+            .todos
+            .unit_of_work(&mut tx)
+            .update(user_id, id, TodoChanges::from(&body), versions.as_deref())
+            .await?;
+        self.events
+            .updated(&mut tx, user_id, &todo)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+        // only the done state is synced to GitHub issues
+        if let Some(github_sync) = self.github_sync.as_ref().filter(|_| body.is_done.is_some()) {
+            github_sync.push(id);
+        }
+        Ok(Response::new(proto::Todo::from(&ToDoView::from(todo))))
+    }
+
+    async fn delete_todo(
+        &self,
+        request: Request<proto::DeleteTodoRequest>,
+    ) -> Result<Response<()>, Status> {
+        let user_id = self.writer(&request)?;
+        let id = parse_id("id", &request.get_ref().id)?;
+        let mut tx = self.pool.begin().await.map_err(RepositoryError::from)?;
+        self.todos
+            .unit_of_work(&mut tx)
+            .soft_delete(user_id, id)
+            .await?;
+        self.events
+            .deleted(&mut tx, user_id, id)
+            .await
+            .map_err(RepositoryError::from)?;
+        tx.commit().await.map_err(RepositoryError::from)?;
+        Ok(Response::new(()))
+    }
+
+    type WatchTodosStream = WatchStream;
+
+    async fn watch_todos(
+        &self,
+        request: Request<proto::WatchTodosRequest>,
+    ) -> Result<Response<WatchStream>, Status> {
+        let user_id = self.user(&request, Scope::TodosRead)?;
+        let events = self.events.watch(user_id).map(move |event| match event {
+            Ok(event) => Ok(proto::TodoEvent::from(&*event)),
+            Err(missed) => {
+                warn!(%user_id, missed, "gRPC client fell behind the todo events");
+                Err(Status::aborted("Missed events, list the todos again"))
+            }
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// The listing `request` asks for, checked as `GET /todos` checks its query.
+fn list_query(request: proto::ListTodosRequest) -> Result<TodoQuery, Status> {
+    let defaults = TodoQuery::default();
+    let sort = todo_query::parse_sort(&request.sort).map_err(Status::invalid_argument)?;
+    let after = match request.page_token.as_str() {
+        "" => None,
+        token => Some(
+            token
+                .parse()
+                .map_err(|_| Status::invalid_argument("page_token must be a next_page_token"))?,
+        ),
+    };
+    if after.is_some() && !sort.is_empty() {
+        return Err(Status::invalid_argument(
+            "page_token can only be used without sort, use offset instead",
+        ));
+    }
+    Ok(TodoQuery {
+        priority: priority(request.priority()),
+        is_done: request.is_done,
+        started: request.started,
+        overdue: request.overdue,
+        due_before: optional_time("due_before", request.due_before)?,
+        tag: request.tag,
+        list_id: optional_id("list_id", request.list_id.as_deref())?,
+        assignee_id: None,
+        expired: request.expired,
+        include_deleted: request.include_deleted,
+        include_shared: false,
+        text_contains: Some(request.q).filter(|q| !q.is_empty()),
+        search: Some(request.search).filter(|search| !search.is_empty()),
+        since: optional_time("since", request.since)?,
+        sort,
+        after,
+        ids: None,
+        fields: None,
+        limit: match request.page_size {
+            0 => defaults.limit,
+            page_size => i64::from(page_size).clamp(1, 100),
+        },
+        offset: i64::from(request.offset).max(0),
+    })
+}
+
+/// The versions an `etag` matches, as `If-Match` takes them: `None` for
+/// `*`, and none at all for one that isn't a todo's.
+fn versions(etag: &str) -> Result<Option<Vec<i64>>, Status> {
+    match etag.trim() {
+        "" => Err(Status::failed_precondition(
+            "etag is required, give the todo's etag or *",
+        )),
+        "*" => Ok(None),
+        etag => Ok(Some(etag_version(etag).into_iter().collect())),
+    }
+}
+
+fn parse_id(field: &str, id: &str) -> Result<uuid::Uuid, Status> {
+    id.parse()
+        .map_err(|_| Status::invalid_argument(format!("{field} must be a UUID")))
+}
+
+fn optional_id(field: &str, id: Option<&str>) -> Result<Option<uuid::Uuid>, Status> {
+    id.map(|id| parse_id(field, id)).transpose()
+}
+
+fn optional_time(
+    field: &str,
+    at: Option<prost_types::Timestamp>,
+) -> Result<Option<DateTime<Utc>>, Status> {
+    at.map(|at| {
+        u32::try_from(at.nanos)
+            .ok()
+            .and_then(|nanos| Utc.timestamp_opt(at.seconds, nanos).single())
+            .ok_or_else(|| Status::invalid_argument(format!("{field} is out of range")))
+    })
+    .transpose()
+}
+
+fn timestamp(at: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: at.timestamp(),
+        nanos: at.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn priority(priority: proto::Priority) -> Option<Priority> {
+    match priority {
+        proto::Priority::Unspecified => None,
+        proto::Priority::Low => Some(Priority::Low),
+        proto::Priority::Medium => Some(Priority::Medium),
+        proto::Priority::High => Some(Priority::High),
+        proto::Priority::Urgent => Some(Priority::Urgent),
+    }
+}
+
+impl From<Option<Priority>> for proto::Priority {
+    fn from(priority: Option<Priority>) -> Self {
+        match priority {
+            None => proto::Priority::Unspecified,
+            Some(Priority::Low) => proto::Priority::Low,
+            Some(Priority::Medium) => proto::Priority::Medium,
+            Some(Priority::High) => proto::Priority::High,
+            Some(Priority::Urgent) => proto::Priority::Urgent,
+        }
+    }
+}
+
+impl From<&ToDoView> for proto::Todo {
+    fn from(todo: &ToDoView) -> Self {
+        let status = match todo.status {
+            TodoStatus::Open => proto::Status::Open,
+            TodoStatus::Done => proto::Status::Done,
+            TodoStatus::Expired => proto::Status::Expired,
+        };
+        proto::Todo {
+            id: todo.id.to_string(),
+            text: todo.text.clone(),
+            is_done: todo.is_done,
+            status: status.into(),
+            start_at: todo.start_at.map(timestamp),
+            due_at: todo.due_at.map(timestamp),
+            expires_at: todo.expires_at.map(timestamp),
+            list_id: todo.list_id.map(|id| id.to_string()),
+            priority: proto::Priority::from(todo.priority).into(),
+            recurrence: todo.recurrence.clone(),
+            tags: todo
+                .tags
+                .iter()
+                .map(|tag| proto::Tag {
+                    id: tag.id.to_string(),
+                    name: tag.name.clone(),
+                })
+                .collect(),
+            completion_percent: todo.completion_percent,
+            deleted_at: todo.deleted_at.map(timestamp),
+            etag: todo.etag.clone(),
+            created_at: Some(timestamp(todo.created_at)),
+            updated_at: Some(timestamp(todo.updated_at)),
+            assignee_id: todo.assignee_id.map(|id| id.to_string()),
+        }
+    }
+}
+
+impl From<&TodoEvent> for proto::TodoEvent {
+    fn from(event: &TodoEvent) -> Self {
+        let kind = match event.kind {
+            EventKind::Created => proto::todo_event::Kind::Created,
+            EventKind::Updated => proto::todo_event::Kind::Updated,
+            EventKind::Deleted => proto::todo_event::Kind::Deleted,
+            EventKind::Due => proto::todo_event::Kind::Due,
+            EventKind::Assigned => proto::todo_event::Kind::Assigned,
+            EventKind::DueChanged => proto::todo_event::Kind::DueChanged,
+        };
+        proto::TodoEvent {
+            kind: kind.into(),
+            id: event.id.to_string(),
+            todo: event.todo.as_ref().map(proto::Todo::from),
+        }
+    }
+}
+
+impl From<ApiError> for Status {
+    fn from(err: ApiError) -> Self {
+        let code = match err.code {
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => Code::InvalidArgument,
+            StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            StatusCode::FORBIDDEN if err.error_code == ErrorCode::QuotaExceeded => {
+                Code::ResourceExhausted
+            }
+            StatusCode::FORBIDDEN => Code::PermissionDenied,
+            StatusCode::NOT_FOUND => Code::NotFound,
+            StatusCode::CONFLICT => Code::AlreadyExists,
+            StatusCode::PRECONDITION_FAILED => Code::Aborted,
+            StatusCode::PRECONDITION_REQUIRED => Code::FailedPrecondition,
+            StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+            StatusCode::GATEWAY_TIMEOUT => Code::DeadlineExceeded,
+            _ => Code::Internal,
+        };
+        let message = match err.details {
+            Some(details) => format!("{}: {details}", err.error),
+            None => err.error,
+        };
+        Status::new(code, message)
+    }
+}
+
+impl From<RepositoryError> for Status {
+    fn from(err: RepositoryError) -> Self {
+        ApiError::from(err).into()
+    }
+}
@@ -0,0 +1,5 @@
+//! Handlers of the core todo endpoints, and the fallbacks shared by every
+//! router. Feature endpoints live in their own modules at the crate root.
+
+pub mod fallback;
+pub mod todos;
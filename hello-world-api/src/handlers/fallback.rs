@@ -0,0 +1,61 @@
+//! Responses for requests that never reach a handler.
+
+use axum::{
+    http::{header, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use tracing::error;
+
+use crate::error::{ErrorCode, Problem};
+
+pub async fn not_found(method: Method, uri: Uri) -> Problem {
+    Problem {
+        status: StatusCode::NOT_FOUND,
+        detail: format!("No route for {} {}", method, uri.path()),
+        code: ErrorCode::NotFound,
+        details: None,
+    }
+}
+
+/// Axum answers unsupported methods with a bare 405; keep its `Allow` header
+/// but give the response the same problem+json body as every other error.
+pub async fn method_not_allowed(response: Response) -> Response {
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return response;
+    }
+    let (parts, _) = response.into_parts();
+    let mut problem = Problem {
+        status: StatusCode::METHOD_NOT_ALLOWED,
+        detail: "Method not allowed".to_owned(),
+        code: ErrorCode::MethodNotAllowed,
+        details: None,
+    }
+    .into_response();
+    if let Some(allow) = parts.headers.get(header::ALLOW) {
+        problem.headers_mut().insert(header::ALLOW, allow.clone());
+    }
+    problem
+}
+
+pub async fn overloaded(err: tower::BoxError) -> Response {
+    if !err.is::<tower::load_shed::error::Overloaded>() {
+        error!("Unhandled middleware error {:?}", err);
+        return Problem {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            detail: "Internal server error".to_owned(),
+            code: ErrorCode::Internal,
+            details: None,
+        }
+        .into_response();
+    }
+    (
+        [(header::RETRY_AFTER, "1")],
+        Problem {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            detail: "Server is overloaded, try again later".to_owned(),
+            code: ErrorCode::Overloaded,
+            details: None,
+        },
+    )
+        .into_response()
+}
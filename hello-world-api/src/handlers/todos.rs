@@ -0,0 +1,977 @@
+use std::collections::HashMap;
+
+use axum::{
+    debug_handler,
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Redirect},
+    Extension,
+};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    analytics::Analytics,
+    assignment,
+    auth::{AuthUser, Requester},
+    conditional,
+    error::ApiError,
+    events::Events,
+    extract::{invalid_fields, IfMatch, Json, ListFormat, Path, Query, Valid, Validate},
+    github::GithubSync,
+    models::{
+        bulk_result, move_to, BulkComplete, BulkCreate, BulkResults, CreateTodo, GetTodo,
+        ListTodos, ListTrash, MergeTodo, MoveTodo, PatchTodo, PutTodo, Staleness, ToDoMetaView,
+        ToDoRow, ToDoView, Todo, TodoPage, ValidateTodos, MAX_TRASH_LIMIT, TODO_ROW_COLUMNS,
+    },
+    purge::Retention,
+    quick_add,
+    quota::{self, Quota},
+    repository::{
+        todo_query::{TodoField, TodoQuery},
+        MoveTo, NewTodo, RepositoryError, TodoChanges, TodoRepository, Todos, Total,
+    },
+    tags::MAX_TAG_CHARS,
+    tx::Tx,
+    versioning,
+};
+
+#[utoipa::path(
+    get,
+    path = "/todos",
+    tag = "todos",
+    params(
+        ListTodos,
+        ("If-None-Match" = Option<String>, Header, description = "The `ETag` of the page the client has"),
+        ("If-Modified-Since" = Option<String>, Header, description = "The `Last-Modified` of the page the client has"),
+    ),
+    responses(
+        (status = 200, description = "A page of todos, each with only its `fields` if given, a `TodoMetaListPage` with `?meta=true`, CSV rows with `Accept: text/csv`; carries quota `warnings` once near the limit", body = TodoListPage, headers(("x-warning" = String, description = "One per entry of `warnings`"), ("etag" = String, description = "Changes with the page"), ("last-modified" = String, description = "The newest `updated_at` of the page's todos"))),
+        (status = 304, description = "The page is still the one of `If-None-Match` or `If-Modified-Since`"),
+        (status = 400, description = "Invalid filter, sort, paging, ids or fields, or fields for CSV", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 406, description = "Accepts neither JSON nor CSV", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+#[allow(clippy::too_many_arguments)] // one per extractor
+pub async fn get_todos(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(Requester(requester)): Extension<Requester>,
+    Extension(quota): Extension<Option<Quota>>,
+    Extension(analytics): Extension<Option<Analytics>>,
+    format: ListFormat,
+    Query(mut params): Query<ListTodos>,
+) -> axum::response::Response {
+    let meta = params.meta;
+    let assignee_id = match params.assignee.take() {
+        Some(value) => match assignment::assignee(&value, requester) {
+            Ok(id) => Some(id),
+            Err(err) => return err.into_response(),
+        },
+        None => None,
+    };
+    match TodoQuery::try_from(params) {
+        Ok(query) => {
+            let query = TodoQuery {
+                include_shared: true,
+                assignee_id,
+                ..query
+            };
+            list_todos(
+                &*todos,
+                quota,
+                analytics.as_ref(),
+                user_id,
+                query,
+                meta,
+                format,
+            )
+            .await
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+/// One page of `user_id`'s todos matching `query`, in a [`TodoPage`]
+/// envelope with the warnings of their `quota`. Searches are reported to
+/// `analytics`.
+pub async fn list_todos(
+    repository: &dyn TodoRepository,
+    quota: Option<Quota>,
+    analytics: Option<&Analytics>,
+    user_id: uuid::Uuid,
+    query: TodoQuery,
+    meta: bool,
+    format: ListFormat,
+) -> axum::response::Response {
+    if query.fields.is_some() && format == ListFormat::Csv {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "fields can only be used for JSON listings",
+        )
+        .into_response();
+    }
+    let total = match repository.count_for_listing(user_id, &query).await {
+        Result::Ok(total) => total,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    if let Some(analytics) = analytics {
+        let kind = match (&query.search, &query.text_contains) {
+            (Some(_), _) => Some("full_text"),
+            (None, Some(_)) => Some("substring"),
+            (None, None) => None,
+        };
+        if let Some(kind) = kind {
+            let properties = json!({
+                "kind": kind,
+                "filtered": query.is_done.is_some() || query.started.is_some(),
+                "sorted": !query.sort.is_empty(),
+                "results": total.count,
+            });
+            analytics.emit(user_id, "search_performed", properties);
+        }
+    }
+    let (todos, next_cursor) = match repository.list_page(user_id, &query).await {
+        Result::Ok(page) => page,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let next_cursor = next_cursor.map(|cursor| cursor.to_string());
+    let last_modified = todos.iter().map(|todo| todo.updated_at).max();
+    let warnings = match quota::warnings(quota, repository, user_id).await {
+        Result::Ok(warnings) => warnings,
+        Err(err) => return err.into_response(),
+    };
+    let mut response = if format == ListFormat::Csv {
+        csv_page(todos, total, next_cursor, &warnings)
+    } else if let Some(fields) = &query.fields {
+        let items: Vec<_> = todos
+            .into_iter()
+            .map(|todo| {
+                if meta {
+                    select_fields(ToDoMetaView::from(todo), fields)
+                } else {
+                    select_fields(ToDoView::from(todo), fields)
+                }
+            })
+            .collect();
+        let page = TodoPage {
+            items,
+            total: total.count,
+            total_estimated: total.estimated,
+            next_cursor,
+        };
+        quota::respond(StatusCode::OK, page, warnings)
+    } else if meta {
+        let items: Vec<_> = todos.into_iter().map(ToDoMetaView::from).collect();
+        let page = TodoPage {
+            items,
+            total: total.count,
+            total_estimated: total.estimated,
+            next_cursor,
+        };
+        quota::respond(StatusCode::OK, page, warnings)
+    } else {
+        let items: Vec<_> = todos.into_iter().map(ToDoView::from).collect();
+        let page = TodoPage {
+            items,
+            total: total.count,
+            total_estimated: total.estimated,
+            next_cursor,
+        };
+        quota::respond(StatusCode::OK, page, warnings)
+    };
+    // the same URL answers in another format for another Accept
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("Accept"));
+    if let Some(last_modified) = last_modified {
+        let (name, value) = conditional::last_modified(last_modified);
+        response.headers_mut().insert(name, value);
+    }
+    response
+}
+
+/// The `fields` of a todo's view, and its `meta` if it has one; the others
+/// were only read as placeholders.
+fn select_fields(view: impl Serialize, fields: &[TodoField]) -> serde_json::Value {
+    let mut view = serde_json::to_value(view).expect("a view serializes to JSON");
+    if let serde_json::Value::Object(object) = &mut view {
+        object.retain(|key, _| key == "meta" || fields.iter().any(|field| field.name() == key));
+    }
+    view
+}
+
+/// A page of todos as CSV, with the rest of its [`TodoPage`] in headers:
+/// `X-Total-Count`, `X-Total-Estimated` and, if there is one,
+/// `X-Next-Cursor`.
+fn csv_page(
+    todos: Vec<Todo>,
+    total: Total,
+    next_cursor: Option<String>,
+    warnings: &[quota::Warning],
+) -> axum::response::Response {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer
+        .write_record(TODO_ROW_COLUMNS)
+        .expect("writing to a Vec succeeds");
+    for todo in todos {
+        writer
+            .serialize(ToDoRow::from(todo))
+            .expect("a todo serializes to a CSV row");
+    }
+    let body = writer.into_inner().expect("writing to a Vec succeeds");
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/csv; charset=utf-8"),
+    );
+    headers.insert("x-total-count", HeaderValue::from(total.count));
+    headers.insert(
+        "x-total-estimated",
+        HeaderValue::from_static(if total.estimated { "true" } else { "false" }),
+    );
+    if let Some(next_cursor) = next_cursor {
+        headers.insert(
+            "x-next-cursor",
+            HeaderValue::from_str(&next_cursor).expect("base64 is a header value"),
+        );
+    }
+    quota::add_headers(&mut headers, warnings);
+    (StatusCode::OK, headers, body).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        GetTodo,
+        ("If-None-Match" = Option<String>, Header, description = "The todo's `etag` the client has"),
+        ("If-Modified-Since" = Option<String>, Header, description = "The `Last-Modified` of the todo the client has"),
+    ),
+    responses(
+        (status = 200, description = "The todo with its links, a `ToDoMetaView` with `?meta=true`", body = ToDoView, headers(("etag" = String, description = "The todo's `etag`"), ("last-modified" = String, description = "The todo's `updated_at`"))),
+        (status = 304, description = "The todo is still the one of `If-None-Match` or `If-Modified-Since`"),
+        (status = 308, description = "The todo was merged into the one at `Location`"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_todo(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    Query(params): Query<GetTodo>,
+) -> axum::response::Response {
+    let todo = match todos.get(user_id, id).await {
+        Result::Ok(todo) => todo,
+        // merged todos live on as tombstones pointing at their target
+        Err(RepositoryError::NotFound) => {
+            return match todos.merged_into(user_id, id).await {
+                Ok(Some(target)) => {
+                    Redirect::permanent(&format!("{}/todos/{target}", versioning::V1))
+                        .into_response()
+                }
+                Ok(None) => ApiError::from(RepositoryError::NotFound).into_response(),
+                Err(err) => ApiError::from(err).into_response(),
+            };
+        }
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let links = match todos.links(user_id, id).await {
+        Result::Ok(links) => links,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let etag = [(header::ETAG, todo.etag())];
+    let last_modified = [conditional::last_modified(todo.updated_at)];
+    if params.meta {
+        let view = ToDoMetaView::from(todo).with_links(links);
+        (StatusCode::OK, etag, last_modified, Json(view)).into_response()
+    } else {
+        let view = ToDoView::from(todo).with_links(links);
+        (StatusCode::OK, etag, last_modified, Json(view)).into_response()
+    }
+}
+
+/// Which of the todos a client holds have changed or gone since it got
+/// their `etag`, so that after a long time offline it only refetches those.
+#[utoipa::path(
+    post,
+    path = "/todos/validate",
+    tag = "todos",
+    request_body = ValidateTodos,
+    responses(
+        (status = 200, description = "The todos to refetch or drop", body = Staleness),
+        (status = 422, description = "Too many todos, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn validate_todos(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Valid(body): Valid<ValidateTodos>,
+) -> axum::response::Response {
+    let ids: Vec<_> = body.etags.keys().copied().collect();
+    let current: HashMap<_, _> = match todos.get_many(user_id, &ids).await {
+        Result::Ok(found) => found.iter().map(|todo| (todo.id, todo.etag())).collect(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let mut staleness = Staleness {
+        stale: Vec::new(),
+        deleted: Vec::new(),
+    };
+    for (id, etag) in body.etags {
+        match current.get(&id) {
+            Some(current) if *current != etag => staleness.stale.push(id),
+            Some(_) => {}
+            None => staleness.deleted.push(id),
+        }
+    }
+    staleness.stale.sort();
+    staleness.deleted.sort();
+    (StatusCode::OK, Json(staleness)).into_response()
+}
+
+/// Soft-deletes the todo: it stays in the table with `deleted_at` set,
+/// but is only listed again with `?include_deleted=true` and in the trash.
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_todo(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+    mut tx: Tx,
+) -> axum::response::Response {
+    if let Err(err) = tx.todos(&todos).soft_delete(user_id, id).await {
+        return ApiError::from(err).into_response();
+    }
+    match events.deleted(&mut tx, user_id, id).await {
+        Result::Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// The user's deleted todos that can still be restored, the last deleted
+/// first.
+#[utoipa::path(
+    get,
+    path = "/todos/trash",
+    tag = "todos",
+    params(
+        ListTrash,
+    ),
+    responses(
+        (status = 200, description = "The deleted todos, with their `deleted_at`", body = Vec<ToDoView>),
+        (status = 400, description = "`limit` out of range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_trash(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(Retention(kept_for)): Extension<Retention>,
+    Query(params): Query<ListTrash>,
+) -> axum::response::Response {
+    let limit = params.limit.unwrap_or(100);
+    if !(1..=MAX_TRASH_LIMIT).contains(&limit) {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_TRASH_LIMIT}"),
+        )
+        .into_response();
+    }
+    match todos.trash(user_id, kept_for, limit).await {
+        Result::Ok(deleted) => {
+            let views: Vec<_> = deleted.into_iter().map(ToDoView::from).collect();
+            (StatusCode::OK, Json(views)).into_response()
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Undeletes a todo from the trash. It counts against the open-todo quota
+/// as a new one would, and is published as created.
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/restore",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    responses(
+        (status = 200, description = "The restored todo", body = ToDoView, headers(("x-warning" = String, description = "One per entry of `warnings`"))),
+        (status = 403, description = "The open-todo quota is reached", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo in the trash", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "An open todo with that text exists", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn restore_todo(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(quota): Extension<Option<Quota>>,
+    Extension(events): Extension<Events>,
+    Extension(Retention(kept_for)): Extension<Retention>,
+    Path(id): Path<uuid::Uuid>,
+    mut tx: Tx,
+) -> axum::response::Response {
+    let todos = tx.todos(&todos);
+    if let Some(quota) = quota {
+        if let Err(err) = quota.check_create(&*todos, user_id).await {
+            return err.into_response();
+        }
+    }
+    let todo = match todos.restore(user_id, id, kept_for).await {
+        Result::Ok(todo) => todo,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let warnings = match quota::warnings(quota, &*todos, user_id).await {
+        Result::Ok(warnings) => warnings,
+        Err(err) => return err.into_response(),
+    };
+    drop(todos);
+    match events.created(&mut tx, user_id, &todo).await {
+        Result::Ok(()) => quota::respond(StatusCode::OK, ToDoView::from(todo), warnings),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Moves the todo within the user's order, which listings without `sort`
+/// follow. Only the moved todo changes, and is published as updated.
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/move",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    request_body = MoveTodo,
+    responses(
+        (status = 200, description = "The moved todo, at its new `position`", body = ToDoView),
+        (status = 400, description = "Not exactly one of the fields, or moving a todo next to itself", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The todo is only shared with the user", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo, or none to move it next to", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "A negative `index`, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn move_todo(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+    mut tx: Tx,
+    Valid(body): Valid<MoveTodo>,
+) -> axum::response::Response {
+    let to = match move_to(&body) {
+        Some(MoveTo::Before(anchor) | MoveTo::After(anchor)) if anchor == id => {
+            return ApiError::new(StatusCode::BAD_REQUEST, "Cannot move a todo next to itself")
+                .into_response()
+        }
+        Some(to) => to,
+        None => {
+            return ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Give exactly one of index, before or after",
+            )
+            .into_response()
+        }
+    };
+    let todo = match tx.todos(&todos).move_to(user_id, id, to).await {
+        Result::Ok(todo) => todo,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    match events.updated(&mut tx, user_id, &todo).await {
+        Result::Ok(()) => (StatusCode::OK, Json(ToDoView::from(todo))).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Folds the duplicate `source_id` into the todo at `id`. The source keeps
+/// existing as a tombstone that `GET /todos/:id` redirects to the target. Its
+/// checklist items are appended to the target's, its tags added to the
+/// target's, and its external link (import or CalDAV identity) is handed over
+/// if the target has none.
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/merge",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    request_body = MergeTodo,
+    responses(
+        (status = 200, description = "The merged target", body = ToDoView),
+        (status = 400, description = "Merging a todo into itself", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such target or source", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn merge_todo(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+    mut tx: Tx,
+    Json(body): Json<MergeTodo>,
+) -> axum::response::Response {
+    if body.source_id == id {
+        return ApiError::new(StatusCode::BAD_REQUEST, "Cannot merge a todo into itself")
+            .into_response();
+    }
+    let todo = match tx.todos(&todos).merge(user_id, id, body.source_id).await {
+        Result::Ok(todo) => todo,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    // the source's tombstone is gone for clients
+    if let Err(err) = events.deleted(&mut tx, user_id, body.source_id).await {
+        return ApiError::from(err).into_response();
+    }
+    match events.updated(&mut tx, user_id, &todo).await {
+        Result::Ok(()) => (StatusCode::OK, Json(ToDoView::from(todo))).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Only updates the todo at the version in `If-Match`, so a client
+/// doesn't overwrite a change it hasn't seen; so does the PATCH.
+#[utoipa::path(
+    put,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("If-Match" = String, Header, description = "The todo's `etag`, or `*` for any version"),
+    ),
+    request_body = PutTodo,
+    responses(
+        (status = 200, description = "The updated todo", body = ToDoView, headers(("etag" = String, description = "The todo's new `etag`"))),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 412, description = "The todo changed since the version in If-Match", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 428, description = "No If-Match", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+#[debug_handler]
+#[allow(clippy::too_many_arguments)] // one per extractor
+pub async fn put_todo_done(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(github_sync): Extension<Option<GithubSync>>,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+    IfMatch(versions): IfMatch,
+    mut tx: Tx,
+    Json(body): Json<PutTodo>,
+) -> axum::response::Response {
+    let result = tx
+        .todos(&todos)
+        .set_done(user_id, id, body.is_done, versions.as_deref())
+        .await;
+    match result {
+        Result::Ok(todo) => {
+            if let Err(err) = events.updated(&mut tx, user_id, &todo).await {
+                return ApiError::from(err).into_response();
+            }
+            // the sync reads the todo back
+            if let Err(err) = tx.commit().await {
+                return ApiError::from(err).into_response();
+            }
+            if let Some(github_sync) = github_sync {
+                github_sync.push(id);
+            }
+            updated(todo)
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Flips whether the todo is done in one statement, so unlike a PUT it
+/// needs no `If-Match`: two clients toggling at once each flip it.
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/toggle",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    responses(
+        (status = 200, description = "The toggled todo", body = ToDoView, headers(("etag" = String, description = "The todo's new `etag`"))),
+        (status = 403, description = "The todo is only shared with the user as viewer", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn toggle_todo(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(github_sync): Extension<Option<GithubSync>>,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+    mut tx: Tx,
+) -> axum::response::Response {
+    let todo = match tx.todos(&todos).toggle_done(user_id, id).await {
+        Result::Ok(todo) => todo,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    if let Err(err) = events.updated(&mut tx, user_id, &todo).await {
+        return ApiError::from(err).into_response();
+    }
+    // the sync reads the todo back
+    if let Err(err) = tx.commit().await {
+        return ApiError::from(err).into_response();
+    }
+    if let Some(github_sync) = github_sync {
+        github_sync.push(id);
+    }
+    updated(todo)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("If-Match" = String, Header, description = "The todo's `etag`, or `*` for any version"),
+    ),
+    request_body = PatchTodo,
+    responses(
+        (status = 200, description = "The updated todo", body = ToDoView, headers(("etag" = String, description = "The todo's new `etag`"))),
+        (status = 400, description = "No field to change", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "An open todo with that text exists", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 412, description = "The todo changed since the version in If-Match", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 428, description = "No If-Match", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Empty or too long text, a `list_id` not among the user's lists, an invalid `recurrence`, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+#[allow(clippy::too_many_arguments)] // one per extractor
+pub async fn patch_todo(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(github_sync): Extension<Option<GithubSync>>,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+    IfMatch(versions): IfMatch,
+    mut tx: Tx,
+    Valid(body): Valid<PatchTodo>,
+) -> axum::response::Response {
+    if body.is_empty() {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Nothing to update, give text, is_done, due_at, expires_at, list_id, priority or \
+             recurrence",
+        )
+        .into_response();
+    }
+    let result = tx
+        .todos(&todos)
+        .update(user_id, id, TodoChanges::from(&body), versions.as_deref())
+        .await;
+    match result {
+        Result::Ok(todo) => {
+            if let Err(err) = events.updated(&mut tx, user_id, &todo).await {
+                return ApiError::from(err).into_response();
+            }
+            if let Some(assignee_id) = todo.assignee_id.filter(|_| body.due_at.is_some()) {
+                if let Err(err) = events.due_changed(&mut tx, assignee_id, &todo).await {
+                    return ApiError::from(err).into_response();
+                }
+            }
+            // the sync reads the todo back
+            if let Err(err) = tx.commit().await {
+                return ApiError::from(err).into_response();
+            }
+            // only the done state is synced to GitHub issues
+            if let Some(github_sync) = github_sync.filter(|_| body.is_done.is_some()) {
+                github_sync.push(id);
+            }
+            updated(todo)
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// The updated todo, with its new `ETag` for the next conditional write.
+fn updated(todo: Todo) -> axum::response::Response {
+    let etag = [(header::ETAG, todo.etag())];
+    (StatusCode::OK, etag, Json(ToDoView::from(todo))).into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos",
+    tag = "todos",
+    request_body = CreateTodo,
+    responses(
+        (status = 201, description = "The created todo", body = ToDoView, headers(("x-warning" = String, description = "One per entry of `warnings`"))),
+        (status = 403, description = "The open-todo quota is reached", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "An open todo with that text exists", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Empty or too long text, a `list_id` not among the user's lists, an invalid `recurrence`, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create_todo(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(quota): Extension<Option<Quota>>,
+    Extension(analytics): Extension<Option<Analytics>>,
+    Extension(events): Extension<Events>,
+    mut tx: Tx,
+    Valid(body): Valid<CreateTodo>,
+) -> axum::response::Response {
+    let todos = tx.todos(&todos);
+    if let Some(quota) = quota {
+        if let Err(err) = quota.check_create(&*todos, user_id).await {
+            return err.into_response();
+        }
+    }
+    let todo = match todos.insert(user_id, NewTodo::from(&body)).await {
+        Result::Ok(todo) => todo,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    if let Some(analytics) = analytics {
+        let properties = json!({ "via": "api", "has_start_at": body.start_at.is_some() });
+        analytics.emit(user_id, "todo_created", properties);
+    }
+    let warnings = match quota::warnings(quota, &*todos, user_id).await {
+        Result::Ok(warnings) => warnings,
+        Err(err) => return err.into_response(),
+    };
+    drop(todos);
+    match events.created(&mut tx, user_id, &todo).await {
+        Result::Ok(()) => quota::respond(StatusCode::CREATED, ToDoView::from(todo), warnings),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Creates several todos in one transaction. Each is checked and inserted
+/// on its own, so one that is invalid or a duplicate is reported in its
+/// result while the others are still created; only the quota applies to the
+/// batch as a whole.
+#[utoipa::path(
+    post,
+    path = "/todos/bulk",
+    tag = "todos",
+    request_body = BulkCreate,
+    responses(
+        (status = 200, description = "Each todo's result, `201` with the todo once created", body = BulkResults, headers(("x-warning" = String, description = "One per entry of `warnings`"))),
+        (status = 403, description = "The todos would exceed the open-todo quota", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "No or too many todos, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create_todos(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(quota): Extension<Option<Quota>>,
+    Extension(analytics): Extension<Option<Analytics>>,
+    Extension(events): Extension<Events>,
+    mut tx: Tx,
+    Valid(body): Valid<BulkCreate>,
+) -> axum::response::Response {
+    let todos = tx.todos(&todos);
+    let checked: Vec<_> = body
+        .todos
+        .iter()
+        .map(|todo| match todo.validate() {
+            fields if fields.is_empty() => Ok(NewTodo::from(todo)),
+            fields => Err(invalid_fields(fields)),
+        })
+        .collect();
+    let new_todos: Vec<_> = checked
+        .iter()
+        .filter_map(|todo| todo.as_ref().ok().copied())
+        .collect();
+    if let Some(quota) = quota {
+        if let Err(err) = quota
+            .check_create_many(&*todos, user_id, new_todos.len())
+            .await
+        {
+            return err.into_response();
+        }
+    }
+    let mut inserted = match todos.insert_many(user_id, &new_todos).await {
+        Result::Ok(inserted) => inserted.into_iter(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let created: Vec<_> = checked
+        .into_iter()
+        .map(|todo| {
+            todo.and_then(|_| {
+                inserted
+                    .next()
+                    .expect("a result per valid todo")
+                    .map_err(ApiError::from)
+            })
+        })
+        .collect();
+    let warnings = match quota::warnings(quota, &*todos, user_id).await {
+        Result::Ok(warnings) => warnings,
+        Err(err) => return err.into_response(),
+    };
+    drop(todos);
+    let mut results = Vec::with_capacity(created.len());
+    for result in created {
+        if let Result::Ok(todo) = &result {
+            if let Err(err) = events.created(&mut tx, user_id, todo).await {
+                return ApiError::from(err).into_response();
+            }
+            if let Some(analytics) = &analytics {
+                let properties = json!({ "via": "bulk", "has_start_at": todo.start_at.is_some() });
+                analytics.emit(user_id, "todo_created", properties);
+            }
+        }
+        results.push(bulk_result(StatusCode::CREATED, result));
+    }
+    quota::respond(StatusCode::OK, BulkResults { results }, warnings)
+}
+
+/// Marks several todos done in one statement, reporting the ones that
+/// aren't found in their results.
+#[utoipa::path(
+    post,
+    path = "/todos/bulk-complete",
+    tag = "todos",
+    request_body = BulkComplete,
+    responses(
+        (status = 200, description = "Each id's result, `200` with the todo once done or `404`", body = BulkResults),
+        (status = 422, description = "No or too many ids, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn complete_todos(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(github_sync): Extension<Option<GithubSync>>,
+    Extension(events): Extension<Events>,
+    mut tx: Tx,
+    Valid(body): Valid<BulkComplete>,
+) -> axum::response::Response {
+    let completed = tx.todos(&todos).complete_many(user_id, &body.ids).await;
+    let completed = match completed {
+        Result::Ok(completed) => completed,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    for todo in completed.iter().flatten() {
+        if let Err(err) = events.updated(&mut tx, user_id, todo).await {
+            return ApiError::from(err).into_response();
+        }
+    }
+    // the sync reads the todos back
+    if let Err(err) = tx.commit().await {
+        return ApiError::from(err).into_response();
+    }
+    let results = completed
+        .into_iter()
+        .map(|result| {
+            if let Result::Ok(todo) = &result {
+                if let Some(github_sync) = &github_sync {
+                    github_sync.push(todo.id);
+                }
+            }
+            bulk_result(StatusCode::OK, result.map_err(ApiError::from))
+        })
+        .collect();
+    (StatusCode::OK, Json(BulkResults { results })).into_response()
+}
+
+/// Creates a todo from a free-text line, see [`quick_add`] for the syntax.
+/// The due date and priority parsed are stored unless the body gives them,
+/// and the tags are added, creating the missing ones, in the same
+/// transaction: a todo whose tags can't be added isn't created either.
+#[utoipa::path(
+    post,
+    path = "/todos/quick",
+    tag = "todos",
+    request_body = CreateTodo,
+    responses(
+        (status = 201, description = "The created todo, with what was parsed from its text", body = ToDoView, headers(("x-warning" = String, description = "One per entry of `warnings`"))),
+        (status = 400, description = "Nothing but metadata in the text", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The open-todo quota is reached", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "An open todo with that text exists", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Empty or too long text, a `list_id` not among the user's lists, an invalid `recurrence`, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn quick_add_todo(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(quota): Extension<Option<Quota>>,
+    Extension(analytics): Extension<Option<Analytics>>,
+    Extension(events): Extension<Events>,
+    mut tx: Tx,
+    Valid(body): Valid<CreateTodo>,
+) -> axum::response::Response {
+    let todos = tx.todos(&todos);
+    let parsed = quick_add::parse(&body.text, chrono::Utc::now());
+    if parsed.text.is_empty() {
+        return ApiError::new(StatusCode::BAD_REQUEST, "Todo text is empty").into_response();
+    }
+    if parsed
+        .tags
+        .iter()
+        .any(|tag| tag.chars().count() > MAX_TAG_CHARS)
+    {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Tags must be at most {MAX_TAG_CHARS} characters"),
+        )
+        .into_response();
+    }
+    if let Some(quota) = quota {
+        if let Err(err) = quota.check_create(&*todos, user_id).await {
+            return err.into_response();
+        }
+    }
+    let new_todo = NewTodo {
+        text: &parsed.text,
+        start_at: body.start_at,
+        due_at: body.due_at.or(parsed.due_at),
+        expires_at: body.expires_at,
+        list_id: body.list_id,
+        priority: body.priority.or(parsed.priority),
+        recurrence: body.recurrence.as_deref().map(str::trim),
+    };
+    let mut todo = match todos.insert(user_id, new_todo).await {
+        Result::Ok(todo) => todo,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    if !parsed.tags.is_empty() {
+        todo = match todos.add_tags(user_id, todo.id, &parsed.tags).await {
+            Result::Ok(todo) => todo,
+            Err(err) => return ApiError::from(err).into_response(),
+        };
+    }
+    if let Some(analytics) = analytics {
+        let properties = json!({ "via": "quick_add", "has_start_at": body.start_at.is_some() });
+        analytics.emit(user_id, "todo_created", properties);
+    }
+    let warnings = match quota::warnings(quota, &*todos, user_id).await {
+        Result::Ok(warnings) => warnings,
+        Err(err) => return err.into_response(),
+    };
+    drop(todos);
+    match events.created(&mut tx, user_id, &todo).await {
+        Result::Ok(()) => quota::respond(StatusCode::CREATED, ToDoView::from(todo), warnings),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
@@ -0,0 +1,130 @@
+//! Probes for orchestrators: `/healthz` answers as long as the process
+//! serves requests at all, `/readyz` only once the database is reachable and
+//! every migration this build embeds has been applied. `/readyz` also tells
+//! how busy the connection pool is.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{
+    error::{ErrorCode, Problem},
+    extract::Json,
+    repository::{PoolSettings, MIGRATOR},
+};
+
+#[derive(Serialize, ToSchema)]
+pub struct Liveness {
+    status: &'static str,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Readiness {
+    status: &'static str,
+    /// Version of the newest migration applied.
+    migration: i64,
+    pool: PoolHealth,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PoolHealth {
+    /// Connections open, in use or idle.
+    size: u32,
+    idle: u32,
+    min: u32,
+    max: u32,
+    /// Every connection is in use, so requests wait for one to be released.
+    /// A busy instance is still ready, others would only get busier.
+    saturated: bool,
+}
+
+/// Liveness: never touches the database, so an outage doesn't get every
+/// instance restarted.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses(
+        (status = 200, description = "The process is up", body = Liveness),
+    ),
+)]
+pub async fn healthz() -> Json<Liveness> {
+    Json(Liveness { status: "ok" })
+}
+
+/// Readiness: runs `select 1` on the pool and compares the applied
+/// migrations with the embedded ones.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready for traffic", body = Readiness),
+        (status = 503, description = "The database is unreachable or migrations are pending", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn readyz(
+    pg: Extension<PgPool>,
+    Extension(settings): Extension<PoolSettings>,
+) -> axum::response::Response {
+    // read before the check takes a connection
+    let pool = pool_health(&pg, &settings);
+    let applied = match applied_migrations(&pg).await {
+        Ok(applied) => applied,
+        Err(err) => {
+            warn!("Readiness check failed {:?}", err);
+            return not_ready("Database is unreachable", json!({ "check": "database" }));
+        }
+    };
+    let pending: Vec<_> = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect();
+    if !pending.is_empty() {
+        return not_ready(
+            "Migrations are pending",
+            json!({ "check": "migrations", "pending": pending }),
+        );
+    }
+    let readiness = Readiness {
+        status: "ready",
+        migration: applied.iter().copied().max().unwrap_or(0),
+        pool,
+    };
+    (StatusCode::OK, Json(readiness)).into_response()
+}
+
+fn pool_health(pg: &PgPool, settings: &PoolSettings) -> PoolHealth {
+    let size = pg.size();
+    let idle = pg.num_idle() as u32;
+    PoolHealth {
+        size,
+        idle,
+        min: settings.min_connections,
+        max: settings.max_connections,
+        saturated: size >= settings.max_connections && idle == 0,
+    }
+}
+
+/// Versions of the migrations that were applied successfully.
+async fn applied_migrations(pg: &PgPool) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query("select 1").execute(pg).await?;
+    sqlx::query_scalar(r#"select version from "_sqlx_migrations" where success"#)
+        .fetch_all(pg)
+        .await
+}
+
+fn not_ready(detail: &str, details: serde_json::Value) -> axum::response::Response {
+    Problem {
+        status: StatusCode::SERVICE_UNAVAILABLE,
+        detail: detail.to_owned(),
+        code: ErrorCode::Unavailable,
+        details: Some(details),
+    }
+    .into_response()
+}
@@ -0,0 +1,118 @@
+//! Audit log of the changes to todos. Every statement creating, changing,
+//! deleting or merging a todo is recorded by a trigger, in the statement's
+//! transaction, with the todo's fields before and after it. The change is
+//! attributed to whoever sent the request if it was made in the request's
+//! [`Tx`](crate::tx::Tx), which [`set_actor`] marks with them; changes made
+//! outside of one, such as by the expiry job, have no actor.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{Json, Path, Query},
+};
+
+const MAX_ENTRIES: i64 = 1000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListHistory {
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HistoryEntry {
+    id: uuid::Uuid,
+    at: DateTime<Utc>,
+    /// `created`, `updated`, `deleted`, `restored` or `merged`.
+    action: String,
+    /// The todo's version once changed.
+    version: i64,
+    actor_id: Option<uuid::Uuid>,
+    /// The actor's username.
+    actor: Option<String>,
+    /// The todo's fields before the change, none when it was created.
+    #[schema(value_type = Option<Object>)]
+    before: Option<serde_json::Value>,
+    #[schema(value_type = Object)]
+    after: serde_json::Value,
+}
+
+/// Attributes the changes to todos the transaction on `conn` makes to
+/// `actor_id`.
+pub async fn set_actor(conn: &mut PgConnection, actor_id: uuid::Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("select set_config('app.actor_id', $1, true)")
+        .bind(actor_id.to_string())
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// `GET /todos/:id/history`: the latest `limit` (default 100) changes to
+/// the todo, newest first. Deleted and merged todos keep theirs.
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/history",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ListHistory,
+    ),
+    responses(
+        (status = 200, description = "Newest changes first", body = Vec<HistoryEntry>),
+        (status = 400, description = "`limit` out of range", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list(
+    AuthUser(user_id): AuthUser,
+    pg: Extension<PgPool>,
+    Path(id): Path<uuid::Uuid>,
+    Query(params): Query<ListHistory>,
+) -> axum::response::Response {
+    let limit = params.limit.unwrap_or(100);
+    if !(1..=MAX_ENTRIES).contains(&limit) {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_ENTRIES}"),
+        )
+        .into_response();
+    }
+    let result = async {
+        let exists = sqlx::query_scalar!(
+            r#"select exists(select from "todo" where id = $1 and user_id = $2) as "exists!""#,
+            id,
+            user_id,
+        )
+        .fetch_one(&*pg)
+        .await?;
+        if !exists {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        sqlx::query_as!(
+            HistoryEntry,
+            r#"select l.id, l.at, l.action, l.version, l.actor_id, u.username as "actor?",
+                l.before, l.after
+            from "todo_audit_log" l
+            left join "user" u on u.user_id = l.actor_id
+            where l.todo_id = $1
+            order by l.at desc, l.version desc
+            limit $2"#,
+            id,
+            limit,
+        )
+        .fetch_all(&*pg)
+        .await
+    }
+    .await;
+    match result {
+        Ok(entries) => Json(entries).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
@@ -0,0 +1,321 @@
+//! Generic inbound webhooks: any system that can POST JSON can create todos
+//! through `POST /hooks/:token`, with the payload mapped onto a todo by the
+//! hook's template.
+//!
+//! Paths are a JSONPath subset: `$` followed by `.key`, `['key']` and
+//! `[index]` steps, such as `$.issue.labels[0].name`. The text template
+//! replaces every `{<path>}` placeholder, so `{$.title}` alone picks a single
+//! field and `{$.repo}: {$.title}` combines several.
+//!
+//! Todos created by a hook belong to the user who created the hook.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{Json, Path},
+    import::{self, ImportRow},
+    versioning,
+};
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct HookMapping {
+    text_template: String,
+    /// Path to a boolean marking the todo done.
+    is_done_path: Option<String>,
+    /// Path to a value identifying the item in the sending system. Deliveries
+    /// with the same value update one todo instead of creating another.
+    external_id_path: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HookView {
+    id: uuid::Uuid,
+    /// Path to POST deliveries to, relative to the API's base URL. Only
+    /// returned when the hook is created.
+    url: String,
+    #[serde(flatten)]
+    mapping: HookMapping,
+}
+
+struct Hook {
+    id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    text_template: String,
+    is_done_path: Option<String>,
+    external_id_path: Option<String>,
+}
+
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+enum Part {
+    Literal(String),
+    Path(Vec<Step>),
+}
+
+#[utoipa::path(
+    post,
+    path = "/integrations/hooks",
+    tag = "integrations",
+    request_body = HookMapping,
+    responses(
+        (status = 201, description = "The hook and the URL to deliver to", body = HookView),
+        (status = 400, description = "Invalid template or path", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Json(mapping): Json<HookMapping>,
+) -> axum::response::Response {
+    let valid = parse_template(&mapping.text_template).and_then(|_| {
+        for path in [&mapping.is_done_path, &mapping.external_id_path]
+            .into_iter()
+            .flatten()
+        {
+            parse_path(path)?;
+        }
+        Ok(())
+    });
+    if let Err(err) = valid {
+        return ApiError::new(StatusCode::BAD_REQUEST, err).into_response();
+    }
+    let token = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let result = sqlx::query_scalar::<_, uuid::Uuid>(
+        r#"insert into "hook" (token_hash, text_template, is_done_path, external_id_path, user_id)
+        values ($1, $2, $3, $4, $5)
+        returning id"#,
+    )
+    .bind(Sha256::digest(token.as_bytes()).to_vec())
+    .bind(&mapping.text_template)
+    .bind(&mapping.is_done_path)
+    .bind(&mapping.external_id_path)
+    .bind(user_id)
+    .fetch_one(&*pg)
+    .await;
+    match result {
+        Ok(id) => (
+            StatusCode::CREATED,
+            Json(HookView {
+                id,
+                url: format!("{}/hooks/{token}", versioning::V1),
+                mapping,
+            }),
+        )
+            .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/integrations/hooks/{id}",
+    tag = "integrations",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Hook id"),
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "No such hook", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    let result = sqlx::query(r#"delete from "hook" where id = $1 and user_id = $2"#)
+        .bind(id)
+        .bind(user_id)
+        .execute(&*pg)
+        .await;
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            ApiError::from(sqlx::Error::RowNotFound).into_response()
+        }
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/hooks/{token}",
+    tag = "integrations",
+    params(
+        ("token" = String, Path, description = "Token from the hook's `url`"),
+    ),
+    request_body(content = Object, description = "Any JSON payload"),
+    responses(
+        (status = 204, description = "The todo was created or updated"),
+        (status = 404, description = "Unknown hook", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "The payload doesn't fit the mapping", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn deliver(
+    pg: Extension<PgPool>,
+    Extension(events): Extension<Events>,
+    Path(token): Path<String>,
+    Json(payload): Json<Value>,
+) -> axum::response::Response {
+    let hook = sqlx::query_as!(
+        Hook,
+        r#"select id, user_id as "user_id!", text_template, is_done_path, external_id_path
+        from "hook"
+        where token_hash = $1 and user_id is not null"#,
+        Sha256::digest(token.as_bytes()).to_vec(),
+    )
+    .fetch_one(&*pg)
+    .await;
+    let hook = match hook {
+        Ok(hook) => hook,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let row = match map_payload(&hook, &payload) {
+        Ok(row) => row,
+        Err(err) => return ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, err).into_response(),
+    };
+    match import::insert_row(&pg, &events, hook.user_id, &row).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+fn map_payload(hook: &Hook, payload: &Value) -> Result<ImportRow, String> {
+    let mut text = String::new();
+    for part in parse_template(&hook.text_template)? {
+        match part {
+            Part::Literal(literal) => text.push_str(&literal),
+            Part::Path(steps) => text.push_str(&scalar(payload, &steps)?),
+        }
+    }
+    let text = text.trim().to_owned();
+    if text.is_empty() {
+        return Err("Template produced an empty todo text".to_owned());
+    }
+    let is_done = match &hook.is_done_path {
+        Some(path) => match lookup(payload, &parse_path(path)?) {
+            None | Some(Value::Null) => false,
+            Some(Value::Bool(is_done)) => *is_done,
+            Some(_) => return Err(format!("{path} is not a boolean")),
+        },
+        None => false,
+    };
+    let external_id = match &hook.external_id_path {
+        Some(path) => Some(format!(
+            "hook:{}:{}",
+            hook.id,
+            scalar(payload, &parse_path(path)?)?
+        )),
+        None => None,
+    };
+    Ok(ImportRow {
+        text,
+        is_done,
+        external_id,
+        external_url: None,
+        due_at: None,
+        list: None,
+        tags: Vec::new(),
+    })
+}
+
+fn parse_template(template: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{$") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unterminated placeholder in {template:?}"))?;
+        if start > 0 {
+            parts.push(Part::Literal(rest[..start].to_owned()));
+        }
+        parts.push(Part::Path(parse_path(&rest[start + 1..start + end])?));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_owned()));
+    }
+    if !parts.iter().any(|part| matches!(part, Part::Path(_))) {
+        return Err("text_template needs at least one {$...} placeholder".to_owned());
+    }
+    Ok(parts)
+}
+
+fn parse_path(path: &str) -> Result<Vec<Step>, String> {
+    let invalid = |reason: &str| format!("Invalid path {path:?}: {reason}");
+    let mut rest = path
+        .strip_prefix('$')
+        .ok_or_else(|| invalid("has to start with $"))?;
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(invalid("empty key"));
+            }
+            steps.push(Step::Key(after[..end].to_owned()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix("['") {
+            let end = after.find("']").ok_or_else(|| invalid("unterminated ['"))?;
+            steps.push(Step::Key(after[..end].to_owned()));
+            rest = &after[end + 2..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(|| invalid("unterminated ["))?;
+            let index = after[..end]
+                .parse()
+                .map_err(|_| invalid("index is not a number"))?;
+            steps.push(Step::Index(index));
+            rest = &after[end + 1..];
+        } else {
+            return Err(invalid("expected . or ["));
+        }
+    }
+    Ok(steps)
+}
+
+fn lookup<'a>(value: &'a Value, steps: &[Step]) -> Option<&'a Value> {
+    steps.iter().try_fold(value, |value, step| match step {
+        Step::Key(key) => value.get(key.as_str()),
+        Step::Index(index) => value.get(*index),
+    })
+}
+
+/// The string, number or boolean at `steps`, as text.
+fn scalar(payload: &Value, steps: &[Step]) -> Result<String, String> {
+    match lookup(payload, steps) {
+        Some(Value::String(text)) => Ok(text.clone()),
+        Some(value @ (Value::Number(_) | Value::Bool(_))) => Ok(value.to_string()),
+        Some(Value::Array(_) | Value::Object(_)) => {
+            Err(format!("{} is not a scalar", display(steps)))
+        }
+        None | Some(Value::Null) => Err(format!("{} is missing", display(steps))),
+    }
+}
+
+fn display(steps: &[Step]) -> String {
+    let mut path = "$".to_owned();
+    for step in steps {
+        match step {
+            Step::Key(key) => path.push_str(&format!("['{key}']")),
+            Step::Index(index) => path.push_str(&format!("[{index}]")),
+        }
+    }
+    path
+}
@@ -0,0 +1,239 @@
+//! Dates in the server-rendered views, in the reader's language
+//! (`Accept-Language`) and timezone.
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use chrono_tz::Tz;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    De,
+    Fr,
+    Es,
+}
+
+impl Locale {
+    /// The supported language the client prefers most, English when it
+    /// accepts none of them.
+    pub fn from_accept_language(header: Option<&str>) -> Locale {
+        let mut ranges: Vec<(&str, f32)> = header
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                let tag = params.next()?.trim();
+                let q = params
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse().ok())?;
+                (!tag.is_empty() && q > 0.0).then_some((tag, q))
+            })
+            .collect();
+        // stable, so ranges of equal quality keep the client's order
+        ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        ranges
+            .into_iter()
+            .find_map(|(tag, _)| {
+                let language = tag.split('-').next().unwrap_or(tag);
+                match language.to_ascii_lowercase().as_str() {
+                    "en" => Some(Locale::En),
+                    "de" => Some(Locale::De),
+                    "fr" => Some(Locale::Fr),
+                    "es" => Some(Locale::Es),
+                    _ => None,
+                }
+            })
+            .unwrap_or(Locale::En)
+    }
+
+    /// Language tag for `lang` attributes and `Content-Language`.
+    pub fn tag(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::De => "de",
+            Locale::Fr => "fr",
+            Locale::Es => "es",
+        }
+    }
+
+    /// Looks up one of the fixed phrases of the views.
+    pub fn text(self, phrase: Phrase) -> &'static str {
+        use Phrase::*;
+        match (self, phrase) {
+            (Locale::En, Done) => "Done",
+            (Locale::En, Expired) => "Expired",
+            (Locale::En, Open) => "Open",
+            (Locale::En, Starts) => "Starts",
+            (Locale::De, Done) => "Erledigt",
+            (Locale::De, Expired) => "Abgelaufen",
+            (Locale::De, Open) => "Offen",
+            (Locale::De, Starts) => "Beginnt am",
+            (Locale::Fr, Done) => "Terminé",
+            (Locale::Fr, Expired) => "Expiré",
+            (Locale::Fr, Open) => "Ouvert",
+            (Locale::Fr, Starts) => "Commence le",
+            (Locale::Es, Done) => "Hecho",
+            (Locale::Es, Expired) => "Caducado",
+            (Locale::Es, Open) => "Pendiente",
+            (Locale::Es, Starts) => "Empieza el",
+        }
+    }
+
+    /// Date and time of day, e.g. `October 14, 2026 at 9:05 AM` or
+    /// `14. Oktober 2026, 09:05`.
+    pub fn format_date_time(self, at: DateTime<Tz>) -> String {
+        let (day, year, hour, minute) = (at.day(), at.year(), at.hour(), at.minute());
+        let month = self.month_name(at.month0() as usize);
+        match self {
+            Locale::En => {
+                let (pm, hour12) = at.hour12();
+                let meridiem = if pm { "PM" } else { "AM" };
+                format!("{month} {day}, {year} at {hour12}:{minute:02} {meridiem}")
+            }
+            Locale::De => format!("{day}. {month} {year}, {hour:02}:{minute:02}"),
+            Locale::Fr => format!("{day} {month} {year} à {hour:02}:{minute:02}"),
+            Locale::Es => format!("{day} de {month} de {year}, {hour:02}:{minute:02}"),
+        }
+    }
+
+    /// Distance from `now` in the largest whole unit, e.g. `in 3 days` or
+    /// `vor 2 Stunden`.
+    pub fn format_relative(self, at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+        let seconds = (at - now).num_seconds();
+        let (count, unit) = match seconds.unsigned_abs() {
+            s if s < 60 => return self.just_now().to_owned(),
+            s if s < 3600 => (s / 60, Unit::Minute),
+            s if s < 86400 => (s / 3600, Unit::Hour),
+            s => (s / 86400, Unit::Day),
+        };
+        let unit = self.unit_name(unit, count == 1);
+        match (self, seconds > 0) {
+            (Locale::En, true) => format!("in {count} {unit}"),
+            (Locale::En, false) => format!("{count} {unit} ago"),
+            (Locale::De, true) => format!("in {count} {unit}"),
+            (Locale::De, false) => format!("vor {count} {unit}"),
+            (Locale::Fr, true) => format!("dans {count} {unit}"),
+            (Locale::Fr, false) => format!("il y a {count} {unit}"),
+            (Locale::Es, true) => format!("dentro de {count} {unit}"),
+            (Locale::Es, false) => format!("hace {count} {unit}"),
+        }
+    }
+
+    fn just_now(self) -> &'static str {
+        match self {
+            Locale::En => "just now",
+            Locale::De => "gerade eben",
+            Locale::Fr => "à l'instant",
+            Locale::Es => "ahora mismo",
+        }
+    }
+
+    fn month_name(self, month0: usize) -> &'static str {
+        const EN: [&str; 12] = [
+            "January",
+            "February",
+            "March",
+            "April",
+            "May",
+            "June",
+            "July",
+            "August",
+            "September",
+            "October",
+            "November",
+            "December",
+        ];
+        const DE: [&str; 12] = [
+            "Januar",
+            "Februar",
+            "März",
+            "April",
+            "Mai",
+            "Juni",
+            "Juli",
+            "August",
+            "September",
+            "Oktober",
+            "November",
+            "Dezember",
+        ];
+        const FR: [&str; 12] = [
+            "janvier",
+            "février",
+            "mars",
+            "avril",
+            "mai",
+            "juin",
+            "juillet",
+            "août",
+            "septembre",
+            "octobre",
+            "novembre",
+            "décembre",
+        ];
+        const ES: [&str; 12] = [
+            "enero",
+            "febrero",
+            "marzo",
+            "abril",
+            "mayo",
+            "junio",
+            "julio",
+            "agosto",
+            "septiembre",
+            "octubre",
+            "noviembre",
+            "diciembre",
+        ];
+        match self {
+            Locale::En => EN[month0],
+            Locale::De => DE[month0],
+            Locale::Fr => FR[month0],
+            Locale::Es => ES[month0],
+        }
+    }
+
+    fn unit_name(self, unit: Unit, singular: bool) -> &'static str {
+        // German needs the dative plural after both "in" and "vor"
+        match (self, unit, singular) {
+            (Locale::En, Unit::Minute, true) => "minute",
+            (Locale::En, Unit::Minute, false) => "minutes",
+            (Locale::En, Unit::Hour, true) => "hour",
+            (Locale::En, Unit::Hour, false) => "hours",
+            (Locale::En, Unit::Day, true) => "day",
+            (Locale::En, Unit::Day, false) => "days",
+            (Locale::De, Unit::Minute, true) => "Minute",
+            (Locale::De, Unit::Minute, false) => "Minuten",
+            (Locale::De, Unit::Hour, true) => "Stunde",
+            (Locale::De, Unit::Hour, false) => "Stunden",
+            (Locale::De, Unit::Day, true) => "Tag",
+            (Locale::De, Unit::Day, false) => "Tagen",
+            (Locale::Fr, Unit::Minute, true) => "minute",
+            (Locale::Fr, Unit::Minute, false) => "minutes",
+            (Locale::Fr, Unit::Hour, true) => "heure",
+            (Locale::Fr, Unit::Hour, false) => "heures",
+            (Locale::Fr, Unit::Day, true) => "jour",
+            (Locale::Fr, Unit::Day, false) => "jours",
+            (Locale::Es, Unit::Minute, true) => "minuto",
+            (Locale::Es, Unit::Minute, false) => "minutos",
+            (Locale::Es, Unit::Hour, true) => "hora",
+            (Locale::Es, Unit::Hour, false) => "horas",
+            (Locale::Es, Unit::Day, true) => "día",
+            (Locale::Es, Unit::Day, false) => "días",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Phrase {
+    Done,
+    Expired,
+    Open,
+    Starts,
+}
+
+#[derive(Clone, Copy)]
+enum Unit {
+    Minute,
+    Hour,
+    Day,
+}
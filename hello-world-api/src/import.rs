@@ -0,0 +1,597 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use tracing::{error, info};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{Json, Path, Query},
+    github::GithubClient,
+    language,
+    lists::MAX_LIST_CHARS,
+    models::Todo,
+    quick_add,
+    tags::MAX_TAG_CHARS,
+    versioning,
+};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Finished,
+    Failed,
+}
+
+/// Progress and outcome of one import, polled by the client.
+#[derive(Serialize, Clone, ToSchema)]
+pub struct ImportReport {
+    id: uuid::Uuid,
+    /// User the todos are imported for, the only one seeing the report.
+    #[serde(skip)]
+    user_id: uuid::Uuid,
+    /// When the job stopped running, for evicting the report.
+    #[serde(skip)]
+    ended_at: Option<Instant>,
+    source: &'static str,
+    status: JobStatus,
+    inserted: u32,
+    /// Rows re-imported onto the todo created by an earlier import.
+    updated: u32,
+    /// Rows whose text already exists as a todo.
+    duplicates: u32,
+    /// Rows that don't map to a todo (sections, notes, ...).
+    ignored: u32,
+    failed: u32,
+    errors: Vec<String>,
+}
+
+/// How long the report of a finished or failed import can still be polled.
+const REPORT_TTL: Duration = Duration::from_secs(3600);
+
+/// Reports of running imports and of those that ended within `REPORT_TTL`.
+#[derive(Clone, Default)]
+pub struct ImportJobs(Arc<Mutex<HashMap<uuid::Uuid, ImportReport>>>);
+
+impl ImportJobs {
+    fn start(&self, user_id: uuid::Uuid, source: &'static str) -> uuid::Uuid {
+        let id = uuid::Uuid::new_v4();
+        let report = ImportReport {
+            id,
+            user_id,
+            ended_at: None,
+            source,
+            status: JobStatus::Running,
+            inserted: 0,
+            updated: 0,
+            duplicates: 0,
+            ignored: 0,
+            failed: 0,
+            errors: Vec::new(),
+        };
+        let mut reports = self.0.lock().unwrap();
+        // expired reports go whenever another import starts
+        reports.retain(|_, report| {
+            report
+                .ended_at
+                .is_none_or(|ended_at| ended_at.elapsed() < REPORT_TTL)
+        });
+        reports.insert(id, report);
+        id
+    }
+
+    fn end(&self, id: uuid::Uuid, status: JobStatus) {
+        self.update(id, |report| {
+            report.status = status;
+            report.ended_at = Some(Instant::now());
+        });
+    }
+
+    fn update(&self, id: uuid::Uuid, f: impl FnOnce(&mut ImportReport)) {
+        if let Some(report) = self.0.lock().unwrap().get_mut(&id) {
+            f(report);
+        }
+    }
+
+    fn get(&self, id: uuid::Uuid) -> Option<ImportReport> {
+        self.0.lock().unwrap().get(&id).cloned()
+    }
+}
+
+/// A todo to create, as extracted from an export file.
+pub struct ImportRow {
+    pub text: String,
+    pub is_done: bool,
+    /// Stable id in the source system, e.g. `trello:<card id>`. Rows with one
+    /// update the todo from a previous import instead of duplicating it.
+    pub external_id: Option<String>,
+    /// Link back to the item in the source system.
+    pub external_url: Option<String>,
+    pub due_at: Option<DateTime<Utc>>,
+    /// Name of the list to put the todo in, created if the user has none
+    /// by that name.
+    pub list: Option<String>,
+    /// Names of the tags to attach, created if missing.
+    pub tags: Vec<String>,
+}
+
+/// What became of an imported row, with the id of the todo it created or
+/// updated.
+pub enum RowOutcome {
+    Inserted(uuid::Uuid),
+    Updated(uuid::Uuid),
+    Duplicate,
+}
+
+type Rows = Vec<Result<Option<ImportRow>, String>>;
+
+/// Runs an import in the background and answers `202 Accepted` pointing at
+/// the job's report. `rows` may still have to fetch the data, failing the
+/// whole job if it can't.
+fn spawn_import(
+    pg: PgPool,
+    jobs: ImportJobs,
+    events: Events,
+    user_id: uuid::Uuid,
+    source: &'static str,
+    rows: impl Future<Output = anyhow::Result<Rows>> + Send + 'static,
+) -> axum::response::Response {
+    let id = jobs.start(user_id, source);
+    let report = jobs.get(id);
+    tokio::spawn(async move {
+        let rows = match rows.await {
+            Ok(rows) => rows,
+            Err(err) => {
+                error!("Fail to fetch {} import {:?}", source, err);
+                jobs.update(id, |report| report.errors.push(err.to_string()));
+                jobs.end(id, JobStatus::Failed);
+                return;
+            }
+        };
+        for row in rows {
+            match row {
+                Ok(Some(row)) => match insert_row(&pg, &events, user_id, &row).await {
+                    Ok(outcome) => match outcome {
+                        RowOutcome::Inserted(_) => jobs.update(id, |report| report.inserted += 1),
+                        RowOutcome::Updated(_) => jobs.update(id, |report| report.updated += 1),
+                        RowOutcome::Duplicate => jobs.update(id, |report| report.duplicates += 1),
+                    },
+                    Err(err) => {
+                        error!("Fail to import todo {:?}", err);
+                        jobs.update(id, |report| {
+                            report.failed += 1;
+                            report.errors.push(format!("{}: {}", row.text, err));
+                        })
+                    }
+                },
+                Ok(None) => jobs.update(id, |report| report.ignored += 1),
+                Err(err) => jobs.update(id, |report| {
+                    report.failed += 1;
+                    report.errors.push(err);
+                }),
+            }
+        }
+        jobs.end(id, JobStatus::Finished);
+        info!(%id, source, "Import finished");
+    });
+    (
+        StatusCode::ACCEPTED,
+        [(
+            header::LOCATION,
+            format!("{}/import/jobs/{id}", versioning::V1),
+        )],
+        Json(report),
+    )
+        .into_response()
+}
+
+/// Inserts the todo for `user_id` unless they already have one from the same
+/// source row or with the same text, recording the event of the todo it
+/// created or updated in the same transaction.
+pub async fn insert_row(
+    pg: &PgPool,
+    events: &Events,
+    user_id: uuid::Uuid,
+    row: &ImportRow,
+) -> Result<RowOutcome, sqlx::Error> {
+    let mut tx = pg.begin().await?;
+    let outcome = insert(&mut tx, user_id, row).await?;
+    match outcome {
+        RowOutcome::Inserted(id) => events.created_id(&mut tx, user_id, id).await?,
+        RowOutcome::Updated(id) => events.changed(&mut tx, user_id, id).await?,
+        // the failed insert may have aborted the transaction
+        RowOutcome::Duplicate => return Ok(outcome),
+    }
+    tx.commit().await?;
+    Ok(outcome)
+}
+
+async fn insert(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    row: &ImportRow,
+) -> Result<RowOutcome, sqlx::Error> {
+    let outcome = insert_todo(&mut *conn, user_id, row).await?;
+    if let RowOutcome::Inserted(id) | RowOutcome::Updated(id) = outcome {
+        add_tags(conn, user_id, id, &row.tags).await?;
+    }
+    Ok(outcome)
+}
+
+async fn insert_todo(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    row: &ImportRow,
+) -> Result<RowOutcome, sqlx::Error> {
+    let list_id = match &row.list {
+        Some(name) => Some(list_id(&mut *conn, user_id, name).await?),
+        None => None,
+    };
+    let Some(external_id) = &row.external_id else {
+        let inserted = sqlx::query_scalar::<_, uuid::Uuid>(
+            r#"insert into "todo"
+                (user_id, todo_text, is_done, completed_at, search_config, id, due_at, list_id)
+            values ($4, $1, $2, case when $2 then now() end, $3::regconfig, $5, $6, $7)
+            on conflict (user_id, todo_text) where deleted_at is null and recurred_at is null
+            do nothing
+            returning id"#,
+        )
+        .bind(&row.text)
+        .bind(row.is_done)
+        .bind(language::search_config(&row.text))
+        .bind(user_id)
+        .bind(Todo::new_id())
+        .bind(row.due_at)
+        .bind(list_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+        return Ok(match inserted {
+            Some(id) => RowOutcome::Inserted(id),
+            None => RowOutcome::Duplicate,
+        });
+    };
+    // xmax is only zero for rows this statement inserted
+    let inserted = sqlx::query!(
+        r#"insert into "todo"
+            (user_id, todo_text, is_done, completed_at, external_id, external_url, search_config, id)
+        values ($6, $1, $2, case when $2 then now() end, $3, $4, $5::text::regconfig, $7)
+        on conflict (user_id, external_id) do update
+            set todo_text = excluded.todo_text,
+                search_config = excluded.search_config,
+                external_url = excluded.external_url,
+                is_done = excluded.is_done,
+                completed_at = case when excluded.is_done
+                    then coalesce("todo".completed_at, excluded.completed_at) end
+        returning id, xmax = 0 as "inserted!""#,
+        &row.text,
+        row.is_done,
+        external_id,
+        row.external_url.as_deref(),
+        language::search_config(&row.text),
+        user_id,
+        Todo::new_id(),
+    )
+    .fetch_one(conn)
+    .await;
+    match inserted {
+        Ok(todo) if todo.inserted => Ok(RowOutcome::Inserted(todo.id)),
+        Ok(todo) => Ok(RowOutcome::Updated(todo.id)),
+        // an unrelated todo already has this text
+        Err(sqlx::Error::Database(err)) if err.code().as_deref() == Some("23505") => {
+            Ok(RowOutcome::Duplicate)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// The id of the user's list called `name`, creating it if there is none.
+async fn list_id(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    name: &str,
+) -> Result<uuid::Uuid, sqlx::Error> {
+    sqlx::query(
+        r#"insert into "list" (user_id, name) values ($1, $2)
+        on conflict (user_id, name) do nothing"#,
+    )
+    .bind(user_id)
+    .bind(name)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query_scalar::<_, uuid::Uuid>(r#"select id from "list" where user_id = $1 and name = $2"#)
+        .bind(user_id)
+        .bind(name)
+        .fetch_one(conn)
+        .await
+}
+
+/// Attaches the tags called `names` to the todo, creating the missing ones.
+async fn add_tags(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    todo_id: uuid::Uuid,
+    names: &[String],
+) -> Result<(), sqlx::Error> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    sqlx::query(
+        r#"insert into "tag" (user_id, name) select $1, unnest($2::text[])
+        on conflict (user_id, name) do nothing"#,
+    )
+    .bind(user_id)
+    .bind(names)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"insert into "todo_tag" (todo_id, tag_id)
+        select $1, id from "tag" where user_id = $2 and name = any($3)
+        on conflict do nothing"#,
+    )
+    .bind(todo_id)
+    .bind(user_id)
+    .bind(names)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/import/jobs/{id}",
+    tag = "import",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Import job id"),
+    ),
+    responses(
+        (status = 200, description = "The job's progress", body = ImportReport),
+        (status = 404, description = "No such job", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_job(
+    Extension(jobs): Extension<ImportJobs>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    // other users' jobs look the same as unknown ones
+    match jobs.get(id).filter(|report| report.user_id == user_id) {
+        Some(report) => (StatusCode::OK, Json(report)).into_response(),
+        None => ApiError::new(StatusCode::NOT_FOUND, "Not found").into_response(),
+    }
+}
+
+/// One line of a Todoist CSV project export.
+#[derive(Deserialize)]
+struct TodoistRow {
+    #[serde(rename = "TYPE")]
+    kind: String,
+    #[serde(rename = "CONTENT")]
+    content: String,
+    #[serde(rename = "DATE", default)]
+    date: String,
+    /// Only in exports of several projects; a single project's export is
+    /// named after it instead.
+    #[serde(rename = "PROJECT", default)]
+    project: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct TodoistQuery {
+    /// List for the todos of an export without a `PROJECT` column, usually
+    /// the project's name.
+    project: Option<String>,
+}
+
+/// Maps a `task` row onto a todo: `@label`s are taken out of the text and
+/// become tags, the project becomes the list and a `DATE` that names a day,
+/// like `tomorrow` or `2024-05-01`, the due date. Recurring dates such as
+/// `every day` are dropped.
+fn todoist_row(row: TodoistRow, project: Option<&str>) -> Result<Option<ImportRow>, String> {
+    if row.kind != "task" {
+        return Ok(None);
+    }
+    let mut tags = Vec::new();
+    let mut words = Vec::new();
+    for word in row.content.split_whitespace() {
+        match word.strip_prefix('@') {
+            Some(label) if !label.is_empty() && label.chars().count() <= MAX_TAG_CHARS => {
+                tags.push(label.to_lowercase())
+            }
+            _ => words.push(word),
+        }
+    }
+    if words.is_empty() {
+        return Ok(None);
+    }
+    let list = match row.project.trim() {
+        "" => project.map(str::to_owned),
+        name => Some(name.to_owned()),
+    };
+    if let Some(name) = &list {
+        if name.chars().count() > MAX_LIST_CHARS {
+            return Err(format!(
+                "project names must be at most {MAX_LIST_CHARS} characters"
+            ));
+        }
+    }
+    let date = quick_add::parse(&row.date, Utc::now());
+    Ok(Some(ImportRow {
+        text: words.join(" "),
+        is_done: false,
+        external_id: None,
+        external_url: None,
+        due_at: date.due_at.filter(|_| date.text.is_empty()),
+        list,
+        tags,
+    }))
+}
+
+/// Imports a Todoist CSV project export, see `todoist_row` for how a task
+/// maps onto a todo. Section and note rows are ignored.
+#[utoipa::path(
+    post,
+    path = "/import/todoist",
+    tag = "import",
+    params(TodoistQuery),
+    request_body(content = String, content_type = "text/csv", description = "Todoist CSV export"),
+    responses(
+        (status = 202, description = "The started job", body = ImportReport),
+        (status = 400, description = "Not a Todoist CSV export", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn todoist(
+    pg: Extension<PgPool>,
+    Extension(jobs): Extension<ImportJobs>,
+    Extension(events): Extension<Events>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<TodoistQuery>,
+    body: String,
+) -> axum::response::Response {
+    let project = query
+        .project
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(body.as_bytes());
+    if let Err(err) = reader.headers() {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid Todoist export: {err}"),
+        )
+        .into_response();
+    }
+    let rows = reader
+        .deserialize::<TodoistRow>()
+        .enumerate()
+        // header is line 1
+        .map(|(line, row)| {
+            row.map_err(|err| err.to_string())
+                .and_then(|row| todoist_row(row, project))
+                .map_err(|err| format!("line {}: {}", line + 2, err))
+        })
+        .collect();
+    spawn_import(pg.0, jobs, events, user_id, "todoist", async { Ok(rows) })
+}
+
+/// The parts of a Trello board JSON export that map onto todos.
+#[derive(Deserialize, ToSchema)]
+pub struct TrelloBoard {
+    #[schema(inline)]
+    cards: Vec<TrelloCard>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct TrelloCard {
+    id: String,
+    name: String,
+    #[serde(default)]
+    closed: bool,
+}
+
+/// Imports the cards of a Trello board JSON export. Archived cards are
+/// imported as done, and re-importing the same board updates the todos it
+/// created before. Lists and checklists are not imported, as there are no
+/// statuses or subtasks to map them to.
+#[utoipa::path(
+    post,
+    path = "/import/trello",
+    tag = "import",
+    request_body = TrelloBoard,
+    responses(
+        (status = 202, description = "The started job", body = ImportReport),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn trello(
+    pg: Extension<PgPool>,
+    Extension(jobs): Extension<ImportJobs>,
+    Extension(events): Extension<Events>,
+    AuthUser(user_id): AuthUser,
+    Json(board): Json<TrelloBoard>,
+) -> axum::response::Response {
+    let rows = board
+        .cards
+        .into_iter()
+        .map(|card| {
+            let text = card.name.trim();
+            if text.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(ImportRow {
+                text: text.to_owned(),
+                is_done: card.closed,
+                external_id: Some(format!("trello:{}", card.id)),
+                external_url: None,
+                due_at: None,
+                list: None,
+                tags: Vec::new(),
+            }))
+        })
+        .collect();
+    spawn_import(pg.0, jobs, events, user_id, "trello", async { Ok(rows) })
+}
+
+/// Imports every issue of the configured GitHub repository, closed issues as
+/// done. Re-importing refreshes the todos created before.
+#[utoipa::path(
+    post,
+    path = "/import/github",
+    tag = "import",
+    responses(
+        (status = 202, description = "The started job", body = ImportReport),
+        (status = 503, description = "No GitHub token is configured", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn github(
+    pg: Extension<PgPool>,
+    Extension(jobs): Extension<ImportJobs>,
+    Extension(events): Extension<Events>,
+    Extension(github): Extension<Option<Arc<GithubClient>>>,
+    AuthUser(user_id): AuthUser,
+) -> axum::response::Response {
+    let Some(github) = github else {
+        return ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "GitHub integration is not configured",
+        )
+        .into_response();
+    };
+    let rows = async move {
+        let issues = github.issues().await?;
+        Ok(issues
+            .into_iter()
+            .map(|issue| {
+                Ok(Some(ImportRow {
+                    text: issue.title,
+                    is_done: issue.state == "closed",
+                    external_id: Some(github.external_id(issue.number)),
+                    external_url: Some(issue.html_url),
+                    due_at: None,
+                    list: None,
+                    tags: Vec::new(),
+                }))
+            })
+            .collect())
+    };
+    spawn_import(pg.0, jobs, events, user_id, "github", rows)
+}
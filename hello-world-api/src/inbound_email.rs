@@ -0,0 +1,130 @@
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::{error::ApiError, events::Events, extract::Form, language, models::Todo, tx::Tx};
+
+/// Deliveries older than this are rejected as replays.
+const MAX_AGE_SECONDS: i64 = 300;
+
+/// Key Mailgun signs inbound webhooks with (`MAILGUN_SIGNING_KEY`).
+#[derive(Clone)]
+pub struct MailgunSigningKey(pub Option<String>);
+
+/// The fields of a Mailgun "forward" route delivery that we use.
+#[derive(Deserialize, ToSchema)]
+pub struct InboundMessage {
+    sender: String,
+    recipient: String,
+    subject: String,
+    timestamp: String,
+    token: String,
+    signature: String,
+}
+
+/// Turns an email forwarded by a Mailgun route into a todo named after its
+/// subject, owned by the user the recipient's `+tag` names
+/// (`todo+alice@example.com`). Todos have no description or attachments
+/// yet, so the body and any files are not kept.
+#[utoipa::path(
+    post,
+    path = "/inbound/email",
+    tag = "integrations",
+    request_body(content = InboundMessage, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The todo was created"),
+        (status = 401, description = "Invalid signature", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 406, description = "No subject, or no known user in the recipient's `+tag`; Mailgun doesn't retry", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Inbound email is not configured", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn mailgun(
+    mut tx: Tx,
+    Extension(MailgunSigningKey(key)): Extension<MailgunSigningKey>,
+    Extension(events): Extension<Events>,
+    Form(message): Form<InboundMessage>,
+) -> axum::response::Response {
+    let Some(key) = key else {
+        return ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Inbound email is not configured",
+        )
+        .into_response();
+    };
+    if !signature_matches(&key, &message) {
+        return ApiError::new(StatusCode::UNAUTHORIZED, "Invalid webhook signature")
+            .into_response();
+    }
+    let text = message.subject.trim();
+    if text.is_empty() {
+        // 406 tells Mailgun not to retry the delivery
+        return ApiError::new(StatusCode::NOT_ACCEPTABLE, "Email has no subject").into_response();
+    }
+    let Some(username) = recipient_tag(&message.recipient) else {
+        return ApiError::new(StatusCode::NOT_ACCEPTABLE, "Recipient has no +tag").into_response();
+    };
+    let user_id =
+        sqlx::query_scalar::<_, uuid::Uuid>(r#"select user_id from "user" where username = $1"#)
+            .bind(username)
+            .fetch_optional(&mut *tx)
+            .await;
+    let user_id = match user_id {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            return ApiError::new(StatusCode::NOT_ACCEPTABLE, "Unknown recipient").into_response()
+        }
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+
+    // a repeated subject maps to the existing todo rather than a conflict,
+    // otherwise Mailgun would keep retrying the delivery
+    let result = sqlx::query_scalar::<_, uuid::Uuid>(
+        r#"insert into "todo" (user_id, todo_text, search_config, id)
+        values ($1, $2, $3::regconfig, $4)
+        on conflict (user_id, todo_text) where deleted_at is null and recurred_at is null
+        do nothing returning id"#,
+    )
+    .bind(user_id)
+    .bind(text)
+    .bind(language::search_config(text))
+    .bind(Todo::new_id())
+    .fetch_optional(&mut *tx)
+    .await;
+    let id = match result {
+        Ok(id) => id,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    info!(sender = %message.sender, created = id.is_some(), "Received todo by email");
+    if let Some(id) = id {
+        if let Err(err) = events.created_id(&mut tx, user_id, id).await {
+            return ApiError::from(err).into_response();
+        }
+    }
+    StatusCode::OK.into_response()
+}
+
+/// `alice` of `todo+alice@example.com`.
+fn recipient_tag(recipient: &str) -> Option<&str> {
+    let (local, _domain) = recipient.trim().rsplit_once('@')?;
+    let (_, tag) = local.split_once('+')?;
+    (!tag.is_empty()).then_some(tag)
+}
+
+/// Mailgun signs `timestamp + token` with HMAC-SHA256.
+fn signature_matches(key: &str, message: &InboundMessage) -> bool {
+    let fresh = message.timestamp.parse::<i64>().is_ok_and(|timestamp| {
+        (chrono::Utc::now().timestamp() - timestamp).abs() <= MAX_AGE_SECONDS
+    });
+    let Ok(signature) = hex::decode(&message.signature) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(key.as_bytes()) else {
+        return false;
+    };
+    mac.update(message.timestamp.as_bytes());
+    mac.update(message.token.as_bytes());
+    fresh && mac.verify_slice(&signature).is_ok()
+}
@@ -0,0 +1,172 @@
+//! Periodic background work. Each [`Job`] runs every so often in its own
+//! task, its schedule and last outcome kept in the `job` table: a restarted
+//! server waits for a job's next run rather than running it again at once,
+//! and with several servers on one database each run happens on only one of
+//! them, the one that claimed it. A run that outlives [`LEASE`] is taken to
+//! have died with its server and may be claimed again.
+//!
+//! `GET /admin/jobs` shows every job's schedule and last outcome.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{response::IntoResponse, Extension};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+use crate::{error::ApiError, extract::Json};
+
+/// How often a job's task checks whether the job is due, if the job runs
+/// less often than that.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a claimed run may take before another server may claim the job.
+const LEASE: Duration = Duration::from_secs(600);
+
+#[async_trait]
+pub trait Job: Send + Sync + 'static {
+    /// Its row in the `job` table.
+    fn name(&self) -> &'static str;
+
+    /// Does one run's work, returning how many items, such as todos, it
+    /// handled.
+    async fn run(&self, pg: &PgPool) -> anyhow::Result<i64>;
+}
+
+/// Starts running `job` every `every`.
+pub fn spawn(pg: PgPool, every: Duration, job: impl Job) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every.min(POLL_INTERVAL));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut registered = false;
+        loop {
+            interval.tick().await;
+            if !registered {
+                match register(&pg, job.name(), every).await {
+                    Ok(()) => registered = true,
+                    Err(err) => {
+                        error!(job = job.name(), "Fail to register job {:?}", err);
+                        continue;
+                    }
+                }
+            }
+            match claim(&pg, job.name()).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => {
+                    error!(job = job.name(), "Fail to claim job {:?}", err);
+                    continue;
+                }
+            }
+            let outcome = job.run(&pg).await;
+            if let Err(err) = &outcome {
+                warn!(job = job.name(), "Job failed {:#}", err);
+            }
+            if let Err(err) = finish(&pg, job.name(), every, &outcome).await {
+                error!(job = job.name(), "Fail to record job outcome {:?}", err);
+            }
+        }
+    });
+}
+
+/// Adds the job's row, due at once, or updates its interval.
+async fn register(pg: &PgPool, name: &str, every: Duration) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"insert into "job" (name, every_secs) values ($1, $2)
+        on conflict (name) do update set every_secs = excluded.every_secs"#,
+        name,
+        every.as_secs() as i64,
+    )
+    .execute(pg)
+    .await?;
+    Ok(())
+}
+
+/// Whether this server got to do the job's due run.
+async fn claim(pg: &PgPool, name: &str) -> Result<bool, sqlx::Error> {
+    let claimed = sqlx::query!(
+        r#"update "job"
+        set running_until = now() + make_interval(secs => $2), last_started_at = now()
+        where name = $1 and next_run_at <= now()
+            and (running_until is null or running_until < now())"#,
+        name,
+        LEASE.as_secs_f64(),
+    )
+    .execute(pg)
+    .await?;
+    Ok(claimed.rows_affected() == 1)
+}
+
+async fn finish(
+    pg: &PgPool,
+    name: &str,
+    every: Duration,
+    outcome: &anyhow::Result<i64>,
+) -> Result<(), sqlx::Error> {
+    let (handled, error) = match outcome {
+        Ok(handled) => (Some(*handled), None),
+        Err(err) => (None, Some(format!("{err:#}"))),
+    };
+    sqlx::query!(
+        r#"update "job"
+        set running_until = null, last_finished_at = now(),
+            next_run_at = now() + make_interval(secs => $2),
+            last_error = $3, last_handled = coalesce($4, last_handled),
+            runs = runs + 1, failures = failures + ($3::text is not null)::int
+        where name = $1"#,
+        name,
+        every.as_secs_f64(),
+        error,
+        handled,
+    )
+    .execute(pg)
+    .await?;
+    Ok(())
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobStatus {
+    name: String,
+    /// Seconds between runs.
+    every_secs: i64,
+    next_run_at: DateTime<Utc>,
+    /// Whether a server is running it now.
+    running: bool,
+    last_started_at: Option<DateTime<Utc>>,
+    last_finished_at: Option<DateTime<Utc>>,
+    /// Why the last run failed, null if it succeeded.
+    last_error: Option<String>,
+    /// How many items, such as todos, the last successful run handled.
+    last_handled: Option<i64>,
+    runs: i64,
+    failures: i64,
+}
+
+/// The background jobs, by name, once a server has started them.
+#[utoipa::path(
+    get,
+    path = "/admin/jobs",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every job's schedule and last outcome", body = Vec<JobStatus>),
+    ),
+)]
+pub async fn list(pg: Extension<PgPool>) -> axum::response::Response {
+    let result = sqlx::query_as!(
+        JobStatus,
+        r#"select name, every_secs, next_run_at,
+            coalesce(running_until > now(), false) as "running!",
+            last_started_at, last_finished_at, last_error, last_handled, runs, failures
+        from "job"
+        order by name"#,
+    )
+    .fetch_all(&*pg)
+    .await;
+    match result {
+        Ok(jobs) => Json(jobs).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
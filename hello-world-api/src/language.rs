@@ -0,0 +1,41 @@
+//! Language detection of todo texts, so each todo is indexed and searched
+//! with the Postgres text-search configuration of its own language.
+
+use whatlang::Lang;
+
+/// Name of the text-search configuration for the language of `text`, or
+/// `simple` when detection isn't confident, as is common for short texts.
+pub fn search_config(text: &str) -> &'static str {
+    let Some(info) = whatlang::detect(text).filter(|info| info.is_reliable()) else {
+        return "simple";
+    };
+    match info.lang() {
+        Lang::Ara => "arabic",
+        Lang::Hye => "armenian",
+        Lang::Cat => "catalan",
+        Lang::Dan => "danish",
+        Lang::Nld => "dutch",
+        Lang::Eng => "english",
+        Lang::Fin => "finnish",
+        Lang::Fra => "french",
+        Lang::Deu => "german",
+        Lang::Ell => "greek",
+        Lang::Hin => "hindi",
+        Lang::Hun => "hungarian",
+        Lang::Ind => "indonesian",
+        Lang::Ita => "italian",
+        Lang::Lit => "lithuanian",
+        Lang::Nep => "nepali",
+        Lang::Nob => "norwegian",
+        Lang::Por => "portuguese",
+        Lang::Ron => "romanian",
+        Lang::Rus => "russian",
+        Lang::Srp => "serbian",
+        Lang::Spa => "spanish",
+        Lang::Swe => "swedish",
+        Lang::Tam => "tamil",
+        Lang::Tur => "turkish",
+        Lang::Yid => "yiddish",
+        _ => "simple",
+    }
+}
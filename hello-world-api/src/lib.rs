@@ -0,0 +1,103 @@
+//! The todo API as a library: [`app`] builds the router, so tests can send
+//! it requests without binding a socket, while the binary loads the
+//! [`config::Config`], migrates the database and serves [`routes::stack`].
+//!
+//! The core todo endpoints are split into [`models`] (request and response
+//! bodies), the `repository` (SQL) and the handlers; feature modules keep
+//! their queries and handlers together.
+
+pub mod access_log;
+mod admin_stats;
+mod admin_todos;
+mod analytics;
+mod api_keys;
+mod archive;
+mod assignment;
+mod assist;
+mod attachments;
+mod audit;
+mod auth;
+mod broker;
+mod caldav;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod checklist;
+mod compression;
+mod conditional;
+pub mod config;
+mod cors;
+mod counts;
+pub mod deadline;
+mod error;
+mod events;
+mod expiry;
+mod extract;
+pub mod fixtures;
+mod github;
+mod graphql;
+mod grpc;
+mod handlers;
+mod health;
+mod history;
+mod hooks;
+mod i18n;
+mod import;
+mod inbound_email;
+mod jobs;
+mod language;
+mod links;
+pub mod listen;
+mod lists;
+mod location;
+pub mod log_level;
+pub mod logs;
+mod maintenance;
+mod me;
+mod metrics;
+pub mod models;
+mod openapi;
+mod outbound;
+mod outbox;
+mod portable;
+mod purge;
+mod quick_add;
+mod quota;
+mod rate_limit;
+mod recording;
+mod recurrence;
+mod recurring;
+mod reminders;
+mod replica;
+pub mod repository;
+mod request_body;
+mod request_id;
+pub mod resilience;
+mod response_cache;
+pub mod routes;
+mod schedule;
+mod search;
+mod setup;
+mod share;
+mod stats;
+mod tags;
+pub mod telemetry;
+pub mod tls;
+mod todo_share;
+mod todo_stream;
+mod transfer;
+mod tx;
+mod versioning;
+mod webhooks;
+mod workspaces;
+
+pub use routes::app;
+
+/// The parsers of user input that the fuzz targets in `fuzz/` call, which
+/// are private to the crate otherwise.
+#[doc(hidden)]
+pub mod fuzz {
+    pub use crate::{
+        quick_add::{parse as quick_add, ParsedTodo},
+        recurrence::{parse as recurrence, Recurrence},
+    };
+}
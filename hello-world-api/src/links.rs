@@ -0,0 +1,211 @@
+//! Typed links between two of a user's todos: one relates to, duplicates or
+//! was caused by the other. A todo fetched by itself lists its links both
+//! ways, so the todo a link points at sees it too.
+//!
+//! Following `duplicates` or `caused_by` links of one kind never leads back
+//! to where it started: a link that would close such a cycle is refused.
+//! Links to deleted todos, or to todos merged into another, are left out
+//! rather than carried over.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use serde::Deserialize;
+use sqlx::PgExecutor;
+pub use todo_api_types::{LinkDirection, LinkKind, TodoLink};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{Json, Path},
+    tx::Tx,
+};
+
+/// Key of the advisory locks serializing a user's new links, so two racing
+/// requests can't each close half of a cycle.
+const LINK_LOCK: i32 = 0x6c69_6e6b;
+
+/// Whether links of `kind` may not form cycles.
+fn is_acyclic(kind: LinkKind) -> bool {
+    !matches!(kind, LinkKind::RelatesTo)
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateLink {
+    kind: LinkKind,
+    /// The todo linked to, another one of the user's.
+    todo_id: uuid::Uuid,
+}
+
+/// The links from and to the todo, oldest first.
+pub async fn list<'e>(
+    db: impl PgExecutor<'e>,
+    user_id: uuid::Uuid,
+    todo_id: uuid::Uuid,
+) -> Result<Vec<TodoLink>, sqlx::Error> {
+    sqlx::query_as!(
+        TodoLink,
+        r#"select l.id, l.kind as "kind: LinkKind",
+            case when l.from_id = $1 then 'outgoing' else 'incoming' end
+                as "direction!: LinkDirection",
+            t.id as todo_id, t.todo_text as text
+        from "todo_link" l
+        join "todo" t on t.id = case when l.from_id = $1 then l.to_id else l.from_id end
+        where (l.from_id = $1 or l.to_id = $1) and l.user_id = $2
+            and t.merged_into is null and t.deleted_at is null
+        order by l.created_at, l.id"#,
+        todo_id,
+        user_id,
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Links the todo to another one.
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/links",
+    tag = "links",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    request_body = CreateLink,
+    responses(
+        (status = 201, description = "The link, outgoing from the todo", body = TodoLink),
+        (status = 404, description = "No such todo, or no such todo to link to", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "The todos are linked so already, or the link would close a cycle", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "A link from the todo to itself, or an unknown kind or field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create(
+    AuthUser(user_id): AuthUser,
+    Extension(events): Extension<Events>,
+    Path(todo_id): Path<uuid::Uuid>,
+    mut tx: Tx,
+    Json(body): Json<CreateLink>,
+) -> axum::response::Response {
+    if body.todo_id == todo_id {
+        return ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "A todo can't be linked to itself",
+        )
+        .into_response();
+    }
+    let result = async {
+        sqlx::query("select pg_advisory_xact_lock($1, hashtext($2::text))")
+            .bind(LINK_LOCK)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        let found = sqlx::query_scalar::<_, i64>(
+            r#"select count(*) from "todo"
+            where id = any($1) and user_id = $2 and merged_into is null and deleted_at is null"#,
+        )
+        .bind(vec![todo_id, body.todo_id])
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if found != 2 {
+            return Err(ApiError::from(sqlx::Error::RowNotFound));
+        }
+        if is_acyclic(body.kind) && reaches(&mut *tx, body.kind, body.todo_id, todo_id).await? {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "The link would close a cycle",
+            ));
+        }
+        let id = sqlx::query_scalar::<_, uuid::Uuid>(
+            r#"insert into "todo_link" (user_id, from_id, to_id, kind)
+            values ($1, $2, $3, $4)
+            returning id"#,
+        )
+        .bind(user_id)
+        .bind(todo_id)
+        .bind(body.todo_id)
+        .bind(body.kind)
+        .fetch_one(&mut *tx)
+        .await?;
+        let links = list(&mut *tx, user_id, todo_id).await?;
+        let link = links
+            .into_iter()
+            .find(|link| link.id == id)
+            .ok_or_else(|| ApiError::from(sqlx::Error::RowNotFound))?;
+        events.changed(&mut *tx, user_id, todo_id).await?;
+        events.changed(&mut *tx, user_id, link.todo_id).await?;
+        Ok(link)
+    }
+    .await;
+    match result {
+        Ok(link) => (StatusCode::CREATED, Json(link)).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// Whether `kind` links lead from `from` to `to`, directly or not.
+async fn reaches<'e>(
+    db: impl PgExecutor<'e>,
+    kind: LinkKind,
+    from: uuid::Uuid,
+    to: uuid::Uuid,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"with recursive reached (id) as (
+            select to_id from "todo_link" where from_id = $1 and kind = $3
+            union
+            select l.to_id from "todo_link" l join reached r on l.from_id = r.id
+            where l.kind = $3
+        )
+        select exists(select 1 from reached where id = $2)"#,
+    )
+    .bind(from)
+    .bind(to)
+    .bind(kind)
+    .fetch_one(db)
+    .await
+}
+
+/// Removes a link from or to the todo.
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/links/{link_id}",
+    tag = "links",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("link_id" = uuid::Uuid, Path, description = "Link id"),
+    ),
+    responses(
+        (status = 204, description = "The link was removed"),
+        (status = 404, description = "The todo has no such link", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Path((todo_id, link_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> axum::response::Response {
+    let result = sqlx::query!(
+        r#"delete from "todo_link"
+        where id = $1 and user_id = $2 and (from_id = $3 or to_id = $3)
+        returning from_id, to_id"#,
+        link_id,
+        user_id,
+        todo_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await;
+    let link = match result {
+        Ok(Some(link)) => link,
+        Ok(None) => return ApiError::from(sqlx::Error::RowNotFound).into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    for id in [link.from_id, link.to_id] {
+        if let Err(err) = events.changed(&mut tx, user_id, id).await {
+            return ApiError::from(err).into_response();
+        }
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
@@ -0,0 +1,246 @@
+//! Where the server accepts connections, selected with `LISTEN`:
+//!
+//! - `host:port` binds a TCP socket (the default is `0.0.0.0:3000`),
+//! - `unix:/path/to.sock` binds a unix domain socket, replacing a stale one,
+//! - `systemd` takes over the socket passed by systemd socket activation
+//!   (`LISTEN_FDS`/`LISTEN_PID`), which may be either of the two.
+//!
+//! A TCP listener may serve HTTPS, see [`crate::tls`]. Every listener stops
+//! accepting connections on [`Shutdown`], then lets the requests in flight
+//! finish.
+
+use std::{
+    net::SocketAddr,
+    os::unix::io::{FromRawFd, IntoRawFd},
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use anyhow::Context as _;
+use axum::{body::Body, http::Request, response::Response, ServiceExt};
+use axum_server::{tls_rustls::RustlsConfig, HttpConfig};
+use hyper::server::accept::Accept;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tower::util::BoxCloneService;
+use tracing::info;
+
+/// First file descriptor passed by systemd.
+const SD_LISTEN_FDS_START: i32 = 3;
+
+pub enum Listen {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    Systemd,
+}
+
+pub enum Listener {
+    Tcp(std::net::TcpListener),
+    Unix(tokio::net::UnixListener),
+    Tls(std::net::TcpListener, RustlsConfig),
+}
+
+/// HTTP/2 support of a listener. Without TLS, HTTP/2 is only spoken in
+/// cleartext (h2c) with prior knowledge; upgrades from HTTP/1.1 aren't
+/// offered. With TLS, it is negotiated with ALPN.
+#[derive(Clone, Copy)]
+pub enum Http2 {
+    /// HTTP/1.1 only.
+    Off,
+    /// HTTP/1.1, and HTTP/2 for clients that open with the HTTP/2 preface
+    /// or pick it with ALPN.
+    H2c,
+    /// HTTP/2 only, for internal gRPC-style traffic.
+    Only,
+}
+
+impl std::str::FromStr for Http2 {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "off" => Ok(Http2::Off),
+            "h2c" => Ok(Http2::H2c),
+            "only" => Ok(Http2::Only),
+            other => anyhow::bail!("HTTP/2 mode must be off, h2c or only, got {other}"),
+        }
+    }
+}
+
+/// A fully layered application, ready to be served.
+pub type App = BoxCloneService<Request<Body>, Response, std::convert::Infallible>;
+
+impl std::str::FromStr for Listen {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "systemd" {
+            return Ok(Listen::Systemd);
+        }
+        if let Some(path) = value.strip_prefix("unix:") {
+            return Ok(Listen::Unix(PathBuf::from(path)));
+        }
+        value.parse().map(Listen::Tcp).with_context(|| {
+            format!("LISTEN must be host:port, unix:<path> or systemd, got {value}")
+        })
+    }
+}
+
+impl Listen {
+    pub fn bind(&self) -> anyhow::Result<Listener> {
+        match self {
+            Listen::Tcp(addr) => {
+                let listener = std::net::TcpListener::bind(addr)
+                    .with_context(|| format!("failed to bind {addr}"))?;
+                listener.set_nonblocking(true)?;
+                Ok(Listener::Tcp(listener))
+            }
+            Listen::Unix(path) => {
+                match std::fs::remove_file(path) {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => {
+                        return Err(err)
+                            .with_context(|| format!("failed to remove {}", path.display()))
+                    }
+                }
+                let listener = tokio::net::UnixListener::bind(path)
+                    .with_context(|| format!("failed to bind {}", path.display()))?;
+                Ok(Listener::Unix(listener))
+            }
+            Listen::Systemd => systemd_listener(),
+        }
+    }
+}
+
+impl Listener {
+    /// Makes a TCP listener serve HTTPS with `tls`, if there is one.
+    pub fn with_tls(self, tls: Option<RustlsConfig>) -> anyhow::Result<Listener> {
+        match (self, tls) {
+            (Listener::Tcp(listener), Some(tls)) => Ok(Listener::Tls(listener, tls)),
+            (Listener::Unix(_), Some(_)) => anyhow::bail!("TLS needs a TCP socket to listen on"),
+            (listener, _) => Ok(listener),
+        }
+    }
+
+    /// Serves `app` until `shutdown` is requested and the open requests are
+    /// answered.
+    pub async fn serve(self, app: App, http2: Http2, shutdown: Shutdown) -> anyhow::Result<()> {
+        match self {
+            Listener::Tcp(listener) => protocols(axum::Server::from_tcp(listener)?, http2)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown.requested())
+                .await
+                .context("Unable to start server"),
+            // unix peers have no address, the access log shows them as `-`
+            Listener::Unix(listener) => {
+                protocols(axum::Server::builder(UnixIncoming(listener)), http2)
+                    .serve(app.into_make_service())
+                    .with_graceful_shutdown(shutdown.requested())
+                    .await
+                    .context("Unable to start server")
+            }
+            Listener::Tls(listener, tls) => {
+                let handle = axum_server::Handle::new();
+                let graceful = handle.clone();
+                tokio::spawn(async move {
+                    shutdown.requested().await;
+                    graceful.graceful_shutdown(None);
+                });
+                let config = HttpConfig::new()
+                    .http1_only(matches!(http2, Http2::Off))
+                    .http2_only(matches!(http2, Http2::Only))
+                    .build();
+                axum_server::from_tcp_rustls(listener, tls)
+                    .handle(handle)
+                    .http_config(config)
+                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                    .await
+                    .context("Unable to start server")
+            }
+        }
+    }
+}
+
+/// Requested by the first SIGINT or SIGTERM the process receives.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn on_signal() -> anyhow::Result<Self> {
+        let mut terminate =
+            signal(SignalKind::terminate()).context("failed to listen for SIGTERM")?;
+        let (tx, rx) = watch::channel(false);
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down"),
+                _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+            }
+            let _ = tx.send(true);
+        });
+        Ok(Shutdown(rx))
+    }
+
+    /// Resolves once shutdown is requested.
+    pub async fn requested(mut self) {
+        // the sender only goes away after requesting it
+        let _ = self.0.wait_for(|requested| *requested).await;
+    }
+}
+
+fn protocols<I>(builder: hyper::server::Builder<I>, http2: Http2) -> hyper::server::Builder<I> {
+    match http2 {
+        Http2::Off => builder.http1_only(true),
+        Http2::H2c => builder,
+        Http2::Only => builder.http2_only(true),
+    }
+}
+
+fn systemd_listener() -> anyhow::Result<Listener> {
+    let pid = std::env::var("LISTEN_PID").context("LISTEN_PID is not set")?;
+    anyhow::ensure!(
+        pid == std::process::id().to_string(),
+        "LISTEN_PID {pid} is meant for another process"
+    );
+    let fds: u32 = std::env::var("LISTEN_FDS")
+        .context("LISTEN_FDS is not set")?
+        .parse()
+        .context("LISTEN_FDS must be a number")?;
+    anyhow::ensure!(
+        fds == 1,
+        "expected exactly one socket from systemd, got {fds}"
+    );
+
+    // SAFETY: systemd hands the socket to this process at SD_LISTEN_FDS_START
+    // and nothing else in the process owns that descriptor
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    // getsockname only yields an inet address for TCP sockets
+    if tcp.local_addr().is_ok() {
+        tcp.set_nonblocking(true)?;
+        return Ok(Listener::Tcp(tcp));
+    }
+    // SAFETY: ownership of the descriptor moves from `tcp` to the new listener
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+    unix.set_nonblocking(true)?;
+    Ok(Listener::Unix(tokio::net::UnixListener::from_std(unix)?))
+}
+
+/// Hyper's accept loop over a unix domain socket.
+struct UnixIncoming(tokio::net::UnixListener);
+
+impl Accept for UnixIncoming {
+    type Conn = tokio::net::UnixStream;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0
+            .poll_accept(cx)
+            .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+    }
+}
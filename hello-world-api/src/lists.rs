@@ -0,0 +1,330 @@
+//! Lists, or projects: the named groups each user sorts their todos into. A
+//! todo is in at most one of its user's lists, set with `list_id` when it is
+//! created or patched, and listings can be narrowed to one with `?list_id=`
+//! or `GET /lists/:id/todos`.
+
+use async_graphql::SimpleObject;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use utoipa::ToSchema;
+
+use crate::{
+    analytics::Analytics,
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{check_text, FieldError, Json, ListFormat, Path, Query, Valid, Validate},
+    handlers::todos::list_todos,
+    models::ListTodos,
+    quota::Quota,
+    repository::{todo_query::TodoQuery, Todos},
+    tx::Tx,
+};
+
+/// Longest list name accepted, in characters.
+pub const MAX_LIST_CHARS: usize = 100;
+
+#[derive(Serialize, ToSchema, SimpleObject)]
+pub struct List {
+    pub id: uuid::Uuid,
+    name: String,
+    /// How many of its todos are neither done nor expired.
+    open_todos: i64,
+}
+
+/// The name of a list to create or rename.
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ListName {
+    /// Stored trimmed, which must leave 1 to 100 characters.
+    #[schema(min_length = 1, max_length = 100)]
+    pub name: String,
+}
+
+impl Validate for ListName {
+    fn validate(&self) -> Vec<FieldError> {
+        check_text("name", &self.name, MAX_LIST_CHARS)
+            .into_iter()
+            .collect()
+    }
+}
+
+/// The user's lists, by name.
+#[utoipa::path(
+    get,
+    path = "/lists",
+    tag = "lists",
+    responses(
+        (status = 200, description = "Every list of the user", body = Vec<List>),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list(pg: Extension<PgPool>, AuthUser(user_id): AuthUser) -> axum::response::Response {
+    match user_lists(&pg, user_id).await {
+        Ok(lists) => Json(lists).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/lists",
+    tag = "lists",
+    request_body = ListName,
+    responses(
+        (status = 201, description = "The created list", body = List),
+        (status = 409, description = "The user has a list with that name", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Empty or too long name, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Valid(body): Valid<ListName>,
+) -> axum::response::Response {
+    match insert_list(&pg, user_id, &body).await {
+        Ok(list) => (StatusCode::CREATED, Json(list)).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/lists/{id}",
+    tag = "lists",
+    params(
+        ("id" = uuid::Uuid, Path, description = "List id"),
+    ),
+    responses(
+        (status = 200, description = "The list", body = List),
+        (status = 404, description = "No such list", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    match find_list(&pg, user_id, id).await {
+        Ok(list) => Json(list).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Renames a list.
+#[utoipa::path(
+    put,
+    path = "/lists/{id}",
+    tag = "lists",
+    params(
+        ("id" = uuid::Uuid, Path, description = "List id"),
+    ),
+    request_body = ListName,
+    responses(
+        (status = 200, description = "The renamed list", body = List),
+        (status = 404, description = "No such list", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "The user has another list with that name", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Empty or too long name, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn rename(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    Valid(body): Valid<ListName>,
+) -> axum::response::Response {
+    match rename_list(&pg, user_id, id, &body).await {
+        Ok(list) => Json(list).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Deletes a list. Its todos are kept, out of any list.
+#[utoipa::path(
+    delete,
+    path = "/lists/{id}",
+    tag = "lists",
+    params(
+        ("id" = uuid::Uuid, Path, description = "List id"),
+    ),
+    responses(
+        (status = 204, description = "The list is deleted"),
+        (status = 404, description = "No such list", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete(
+    AuthUser(user_id): AuthUser,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+    mut tx: Tx,
+) -> axum::response::Response {
+    let moved = match delete_list(&mut tx, user_id, id).await {
+        Ok(moved) => moved,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    for todo_id in moved {
+        if let Err(err) = events.changed(&mut tx, user_id, todo_id).await {
+            return ApiError::from(err).into_response();
+        }
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// One page of the list's todos, filtered, sorted and paged like
+/// `GET /todos`.
+#[utoipa::path(
+    get,
+    path = "/lists/{id}/todos",
+    tag = "lists",
+    params(
+        ("id" = uuid::Uuid, Path, description = "List id"),
+        ListTodos,
+    ),
+    responses(
+        (status = 200, description = "A page of the list's todos, a `TodoMetaListPage` with `?meta=true`, CSV rows with `Accept: text/csv`", body = TodoListPage, headers(("x-warning" = String, description = "One per entry of `warnings`"))),
+        (status = 400, description = "Invalid filter, sort or paging", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such list", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 406, description = "Accepts neither JSON nor CSV", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+#[allow(clippy::too_many_arguments)] // one per extractor
+pub async fn todos(
+    State(todos): State<Todos>,
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Extension(quota): Extension<Option<Quota>>,
+    Extension(analytics): Extension<Option<Analytics>>,
+    Path(id): Path<uuid::Uuid>,
+    format: ListFormat,
+    Query(params): Query<ListTodos>,
+) -> axum::response::Response {
+    let exists = sqlx::query_scalar!(
+        r#"select exists(select from "list" where id = $1 and user_id = $2) as "exists!""#,
+        id,
+        user_id,
+    )
+    .fetch_one(&*pg)
+    .await;
+    match exists {
+        Ok(true) => {}
+        Ok(false) => return ApiError::from(sqlx::Error::RowNotFound).into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    }
+    let meta = params.meta;
+    match TodoQuery::try_from(params) {
+        Ok(mut query) => {
+            query.list_id = Some(id);
+            list_todos(
+                &*todos,
+                quota,
+                analytics.as_ref(),
+                user_id,
+                query,
+                meta,
+                format,
+            )
+            .await
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+/// The user's lists, by name.
+pub async fn user_lists(pg: &PgPool, user_id: uuid::Uuid) -> Result<Vec<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"select id, name, list_open_todos(id) as "open_todos!"
+        from "list"
+        where user_id = $1
+        order by name"#,
+        user_id,
+    )
+    .fetch_all(pg)
+    .await
+}
+
+pub async fn find_list(
+    pg: &PgPool,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+) -> Result<List, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"select id, name, list_open_todos(id) as "open_todos!"
+        from "list"
+        where id = $1 and user_id = $2"#,
+        id,
+        user_id,
+    )
+    .fetch_one(pg)
+    .await
+}
+
+pub async fn insert_list(
+    pg: &PgPool,
+    user_id: uuid::Uuid,
+    list: &ListName,
+) -> Result<List, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"insert into "list" (user_id, name) values ($1, $2)
+        returning id, name, 0::bigint as "open_todos!""#,
+        user_id,
+        list.name.trim(),
+    )
+    .fetch_one(pg)
+    .await
+}
+
+pub async fn rename_list(
+    pg: &PgPool,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+    list: &ListName,
+) -> Result<List, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"update "list" set name = $1
+        where id = $2 and user_id = $3
+        returning id, name, list_open_todos(id) as "open_todos!""#,
+        list.name.trim(),
+        id,
+        user_id,
+    )
+    .fetch_one(pg)
+    .await
+}
+
+/// Deletes the list, taking its todos out of it first, in the transaction
+/// on `conn`. Returns the ids of those todos.
+pub async fn delete_list(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+) -> Result<Vec<uuid::Uuid>, sqlx::Error> {
+    let moved = sqlx::query_scalar!(
+        r#"update "todo" set list_id = null where list_id = $1 and user_id = $2
+        returning id"#,
+        id,
+        user_id,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let deleted = sqlx::query!(
+        r#"delete from "list" where id = $1 and user_id = $2"#,
+        id,
+        user_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    Ok(moved)
+}
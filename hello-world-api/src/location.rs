@@ -0,0 +1,257 @@
+//! Optional place attached to a todo, so mobile clients can surface the
+//! tasks relevant where the user currently is. Distances use the
+//! `earthdistance` extension's spherical earth model.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{Json, Path, Query},
+    models::{Priority, ToDoView, Todo},
+    tags::Tag,
+    tx::Tx,
+};
+
+/// Upper bound on the `km` of a nearby search.
+const MAX_SEARCH_KM: f64 = 500.0;
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct Location {
+    latitude: f64,
+    longitude: f64,
+    /// How close counts as "there", for clients that geofence the todo.
+    radius_m: Option<f64>,
+}
+
+impl Location {
+    fn validate(&self) -> Result<(), ApiError> {
+        validate_point(self.latitude, self.longitude)?;
+        if self
+            .radius_m
+            .is_some_and(|radius| !radius.is_finite() || radius <= 0.0)
+        {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "radius_m must be positive",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NearbyQuery {
+    lat: f64,
+    lon: f64,
+    km: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NearbyView {
+    #[serde(flatten)]
+    todo: ToDoView,
+    #[serde(flatten)]
+    location: Location,
+    distance_km: f64,
+}
+
+#[utoipa::path(
+    put,
+    path = "/todos/{id}/location",
+    tag = "location",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    request_body = Location,
+    responses(
+        (status = 200, description = "The stored location", body = Location),
+        (status = 400, description = "Coordinates or radius out of range", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn put_location(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+    Json(location): Json<Location>,
+) -> axum::response::Response {
+    if let Err(err) = location.validate() {
+        return err.into_response();
+    }
+    let result = sqlx::query_as!(
+        Location,
+        r#"update "todo" set latitude = $1, longitude = $2, radius_m = $3
+        where id = $4 and user_id = $5 and merged_into is null and deleted_at is null
+        returning latitude as "latitude!", longitude as "longitude!", radius_m"#,
+        location.latitude,
+        location.longitude,
+        location.radius_m,
+        id,
+        user_id,
+    )
+    .fetch_one(&mut *tx)
+    .await;
+    let location = match result {
+        Ok(location) => location,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    match events.changed(&mut tx, user_id, id).await {
+        Ok(()) => (StatusCode::OK, Json(location)).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/location",
+    tag = "location",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    responses(
+        (status = 204, description = "Location cleared"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_location(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    let result = sqlx::query(
+        r#"update "todo" set latitude = null, longitude = null, radius_m = null
+        where id = $1 and user_id = $2 and merged_into is null and deleted_at is null"#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await;
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            ApiError::from(sqlx::Error::RowNotFound).into_response()
+        }
+        Ok(_) => match events.changed(&mut tx, user_id, id).await {
+            Ok(()) => StatusCode::NO_CONTENT.into_response(),
+            Err(err) => ApiError::from(err).into_response(),
+        },
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Open todos within `km` (default 1) of `lat`/`lon`, closest first.
+#[utoipa::path(
+    get,
+    path = "/todos/nearby",
+    tag = "location",
+    params(
+        NearbyQuery,
+    ),
+    responses(
+        (status = 200, description = "Open todos within `km`, nearest first", body = Vec<NearbyView>),
+        (status = 400, description = "Coordinates or distance out of range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn nearby(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<NearbyQuery>,
+) -> axum::response::Response {
+    if let Err(err) = validate_point(params.lat, params.lon) {
+        return err.into_response();
+    }
+    let km = params.km.unwrap_or(1.0);
+    if !(km > 0.0 && km <= MAX_SEARCH_KM) {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("km must be between 0 and {MAX_SEARCH_KM}"),
+        )
+        .into_response();
+    }
+    // the earth_box test can use the gist index, the exact distance check
+    // then drops the corners of the box
+    let result = sqlx::query!(
+        r#"select id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+            todo_completion(id) as completion_percent,
+            latitude as "latitude!", longitude as "longitude!", radius_m,
+            earth_distance(ll_to_earth($1, $2), ll_to_earth(latitude, longitude))
+                as "distance_m!"
+        from "todo"
+        where user_id = $4 and latitude is not null
+            and merged_into is null and deleted_at is null
+            and not is_done and expired_at is null
+            and earth_box(ll_to_earth($1, $2), $3) @> ll_to_earth(latitude, longitude)
+            and earth_distance(ll_to_earth($1, $2), ll_to_earth(latitude, longitude)) <= $3
+        order by "distance_m!", id
+        limit 100"#,
+        params.lat,
+        params.lon,
+        km * 1000.0,
+        user_id,
+    )
+    .fetch_all(&*pg)
+    .await;
+    match result {
+        Ok(rows) => (
+            StatusCode::OK,
+            Json(
+                rows.into_iter()
+                    .map(|row| NearbyView {
+                        todo: ToDoView::from(Todo {
+                            id: row.id,
+                            todo_text: row.todo_text,
+                            is_done: row.is_done,
+                            start_at: row.start_at,
+                            due_at: row.due_at,
+                            expires_at: row.expires_at,
+                            expired_at: row.expired_at,
+                            version: row.version,
+                            list_id: row.list_id,
+                            assignee_id: row.assignee_id,
+                            priority: row.priority,
+                            recurrence: row.recurrence,
+                            created_at: row.created_at,
+                            updated_at: row.updated_at,
+                            position: row.position,
+                            deleted_at: None,
+                            field_modified: None,
+                            tags: row.tags,
+                            completion_percent: row.completion_percent,
+                            shared_by: None,
+                        }),
+                        location: Location {
+                            latitude: row.latitude,
+                            longitude: row.longitude,
+                            radius_m: row.radius_m,
+                        },
+                        distance_km: row.distance_m / 1000.0,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        )
+            .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+fn validate_point(latitude: f64, longitude: f64) -> Result<(), ApiError> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Latitude must be within ±90 and longitude within ±180",
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,71 @@
+//! Runtime control of the tracing filter, so verbosity can be raised for a
+//! target while investigating and lowered again without a restart.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+use utoipa::ToSchema;
+
+use crate::{error::ApiError, extract::Json};
+
+/// Filter used when `RUST_LOG` isn't set.
+pub const DEFAULT_FILTER: &str = "debug";
+
+#[derive(Clone)]
+pub struct LogLevel(pub reload::Handle<EnvFilter, Registry>);
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct LogFilter {
+    /// `RUST_LOG` syntax, such as `info,sqlx=warn,access_log=info`.
+    filter: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/log-level",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The current filter", body = LogFilter),
+    ),
+)]
+pub async fn get(Extension(LogLevel(handle)): Extension<LogLevel>) -> axum::response::Response {
+    match handle.with_current(|filter| filter.to_string()) {
+        Ok(filter) => Json(LogFilter { filter }).into_response(),
+        Err(err) => {
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/log-level",
+    tag = "admin",
+    request_body = LogFilter,
+    responses(
+        (status = 200, description = "The filter in effect", body = LogFilter),
+        (status = 400, description = "Invalid filter", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn put(
+    Extension(LogLevel(handle)): Extension<LogLevel>,
+    Json(body): Json<LogFilter>,
+) -> axum::response::Response {
+    let filter = match EnvFilter::try_new(&body.filter) {
+        Ok(filter) => filter,
+        Err(err) => {
+            return ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid filter: {err}"))
+                .into_response()
+        }
+    };
+    let filter_text = filter.to_string();
+    if let Err(err) = handle.reload(filter) {
+        return ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+    warn!(filter = %filter_text, "Log filter changed");
+    Json(LogFilter {
+        filter: filter_text,
+    })
+    .into_response()
+}
@@ -0,0 +1,65 @@
+//! How logs are written: as text or, for log shippers, as one JSON object
+//! per line (`LOG_FORMAT`), to stdout or to a file under `LOG_DIR` started
+//! anew every day. JSON lines carry the fields of the request's span, its
+//! `request_id` among them, and the access log's lines their route, status
+//! and latency as fields of their own.
+
+use std::path::PathBuf;
+
+use tracing::Subscriber;
+use tracing_appender::non_blocking::{NonBlockingBuilder, WorkerGuard};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, registry::LookupSpan, Layer};
+
+/// Name of the log files, suffixed with their day.
+const FILE_PREFIX: &str = "hello-world-api.log";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => anyhow::bail!("LOG_FORMAT must be text or json, got {other}"),
+        }
+    }
+}
+
+/// The layer writing the logs, and with `dir` the guard of the thread
+/// writing the files, which flushes them once dropped.
+pub fn layer<S>(
+    format: LogFormat,
+    dir: Option<&PathBuf>,
+) -> (Box<dyn Layer<S> + Send + Sync>, Option<WorkerGuard>)
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let (writer, guard) = match dir {
+        Some(dir) => {
+            let files = tracing_appender::rolling::daily(dir, FILE_PREFIX);
+            // blocks rather than dropping lines when the disk falls behind
+            let (writer, guard) = NonBlockingBuilder::default().lossy(false).finish(files);
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        None => (BoxMakeWriter::new(std::io::stdout), None),
+    };
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(dir.is_none());
+    let layer = match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+    (layer, guard)
+}
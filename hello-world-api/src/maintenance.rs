@@ -0,0 +1,110 @@
+//! Maintenance mode: while it is on, every request that could write answers
+//! 503 and reads keep being served, so migrations and failovers don't lose
+//! writes halfway through.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use axum::{
+    extract::State,
+    http::{header, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{
+    error::{ErrorCode, Problem},
+    extract::Json,
+};
+
+/// Switched with `PUT /admin/maintenance`, starts out as `MAINTENANCE_MODE`.
+#[derive(Clone)]
+pub struct Maintenance(Arc<AtomicBool>);
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct MaintenanceState {
+    enabled: bool,
+}
+
+impl Maintenance {
+    pub fn new(enabled: bool) -> Self {
+        Maintenance(Arc::new(AtomicBool::new(enabled)))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether requests with `method` only read, PROPFIND and REPORT being
+/// CalDAV's reads.
+pub fn is_read(method: &Method) -> bool {
+    matches!(
+        method.as_str(),
+        "GET" | "HEAD" | "OPTIONS" | "PROPFIND" | "REPORT"
+    )
+}
+
+pub async fn reject_writes<B>(
+    State(maintenance): State<Maintenance>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    // /admin/ has to stay reachable to switch maintenance off again
+    let read = is_read(req.method());
+    if read || !maintenance.enabled() || req.uri().path().starts_with("/admin/") {
+        return next.run(req).await;
+    }
+    (
+        [(header::RETRY_AFTER, "60")],
+        Problem {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            detail: "The service is undergoing maintenance and is read-only for now, \
+                     please try again in a few minutes"
+                .to_owned(),
+            code: ErrorCode::Maintenance,
+            details: None,
+        },
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/maintenance",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Whether writes are rejected", body = MaintenanceState),
+    ),
+)]
+pub async fn get(Extension(maintenance): Extension<Maintenance>) -> Json<MaintenanceState> {
+    Json(MaintenanceState {
+        enabled: maintenance.enabled(),
+    })
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/maintenance",
+    tag = "admin",
+    request_body = MaintenanceState,
+    responses(
+        (status = 200, description = "The new state", body = MaintenanceState),
+    ),
+)]
+pub async fn put(
+    Extension(maintenance): Extension<Maintenance>,
+    Json(state): Json<MaintenanceState>,
+) -> Json<MaintenanceState> {
+    let was = maintenance.0.swap(state.enabled, Ordering::Relaxed);
+    if was != state.enabled {
+        warn!(enabled = state.enabled, "Maintenance mode switched");
+    }
+    Json(state)
+}
@@ -0,0 +1,196 @@
+//! `GET /me/summary`: the calling user's account at a glance, and
+//! `/me/preferences`: a small JSON object of view settings clients keep for
+//! the user. Preferences are replaced as a whole, from the version whose
+//! `ETag` the client sends in `If-Match`, so two clients don't overwrite
+//! each other's changes unseen.
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{FieldError, IfMatch, Json, Valid, Validate},
+    tx::Tx,
+};
+
+/// Largest preferences stored, as JSON.
+pub const MAX_PREFERENCES_BYTES: usize = 16 * 1024;
+
+pub const MAX_KEY_CHARS: usize = 100;
+
+#[derive(Serialize, ToSchema)]
+pub struct UserSummary {
+    user_id: uuid::Uuid,
+    username: String,
+    /// Done todos, neither deleted nor merged, archived ones included. Kept
+    /// up to date by the
+    /// statements changing the todos rather than counted, so it is as cheap
+    /// to read however many todos there are.
+    completed_count: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/me/summary",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The calling user's summary", body = UserSummary),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn summary(
+    AuthUser(user_id): AuthUser,
+    pg: Extension<PgPool>,
+) -> axum::response::Response {
+    let summary = sqlx::query_as!(
+        UserSummary,
+        r#"select user_id, username, completed_count from "user" where user_id = $1"#,
+        user_id,
+    )
+    .fetch_one(&*pg)
+    .await;
+    match summary {
+        Ok(summary) => Json(summary).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// A user's preferences: any JSON values, by keys of 1 to 100 characters,
+/// at most 16 KiB in all.
+#[derive(Deserialize, Serialize, Default, ToSchema)]
+#[serde(transparent)]
+#[schema(example = json!({"theme": "dark", "list_view": {"sort": "due_at"}}))]
+pub struct Preferences(#[schema(value_type = Object)] serde_json::Map<String, serde_json::Value>);
+
+impl Validate for Preferences {
+    fn validate(&self) -> Vec<FieldError> {
+        let keys = self
+            .0
+            .keys()
+            .any(|key| !(1..=MAX_KEY_CHARS).contains(&key.chars().count()))
+            .then(|| FieldError {
+                field: "preferences",
+                reason: format!("keys must be 1 to {MAX_KEY_CHARS} characters"),
+            });
+        let size = serde_json::to_vec(&self.0).map_or(0, |json| json.len());
+        let size = (size > MAX_PREFERENCES_BYTES).then(|| FieldError {
+            field: "preferences",
+            reason: format!("must be at most {MAX_PREFERENCES_BYTES} bytes as JSON"),
+        });
+        keys.into_iter().chain(size).collect()
+    }
+}
+
+/// The user's preferences, `{}` until set, with an `ETag` of their version.
+#[utoipa::path(
+    get,
+    path = "/me/preferences",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The user's preferences", body = Preferences,
+            headers(("etag" = String, description = "The version of the preferences, for If-Match"))),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_preferences(AuthUser(user_id): AuthUser, mut tx: Tx) -> axum::response::Response {
+    match preferences(&mut tx, user_id).await {
+        Ok((preferences, version)) => respond_preferences(preferences, version),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Replaces the user's preferences, if they are still at the version in
+/// `If-Match`: the `ETag` of `GET /me/preferences`, or `*` for any.
+#[utoipa::path(
+    put,
+    path = "/me/preferences",
+    tag = "auth",
+    params(
+        ("If-Match" = String, Header, description = "The preferences' etag, or `*`"),
+    ),
+    request_body = Preferences,
+    responses(
+        (status = 200, description = "The user's preferences", body = Preferences,
+            headers(("etag" = String, description = "The version of the preferences, for If-Match"))),
+        (status = 412, description = "The preferences changed since the version in If-Match", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Not a JSON object, or too large", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 428, description = "No If-Match", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn put_preferences(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    headers: HeaderMap,
+    Valid(body): Valid<Preferences>,
+) -> axum::response::Response {
+    let Some(IfMatch(versions)) = IfMatch::from_headers(&headers) else {
+        return ApiError::new(
+            StatusCode::PRECONDITION_REQUIRED,
+            "If-Match is required, give the preferences' etag",
+        )
+        .into_response();
+    };
+    let current = match preferences(&mut tx, user_id).await {
+        Ok((_, version)) => version,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    if versions.is_some_and(|versions| !versions.contains(&current)) {
+        return changed().into_response();
+    }
+    // none are returned if another request stored the first preferences
+    // since they were read
+    let result = sqlx::query_as::<_, (sqlx::types::Json<Preferences>, i64)>(
+        r#"insert into "user_preferences" (user_id, preferences, version)
+        values ($1, $2, 1)
+        on conflict (user_id) do update
+            set preferences = excluded.preferences,
+                version = "user_preferences".version + 1,
+                updated_at = now()
+            where "user_preferences".version = $3
+        returning preferences, version"#,
+    )
+    .bind(user_id)
+    .bind(sqlx::types::Json(&body))
+    .bind(current)
+    .fetch_optional(&mut *tx)
+    .await;
+    match result {
+        Ok(Some((preferences, version))) => respond_preferences(preferences.0, version),
+        Ok(None) => changed().into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// The user's preferences and their version, locked until the transaction
+/// ends; version 0 for none yet.
+async fn preferences(tx: &mut Tx, user_id: uuid::Uuid) -> Result<(Preferences, i64), sqlx::Error> {
+    let row = sqlx::query_as::<_, (sqlx::types::Json<Preferences>, i64)>(
+        r#"select preferences, version from "user_preferences" where user_id = $1 for update"#,
+    )
+    .bind(user_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+    Ok(row.map_or_else(Default::default, |(preferences, version)| {
+        (preferences.0, version)
+    }))
+}
+
+fn respond_preferences(preferences: Preferences, version: i64) -> axum::response::Response {
+    let etag = HeaderValue::from_str(&format!("\"{version}\"")).expect("digits are a header value");
+    ([(header::ETAG, etag)], Json(preferences)).into_response()
+}
+
+fn changed() -> ApiError {
+    ApiError::new(
+        StatusCode::PRECONDITION_FAILED,
+        "The preferences changed since the version in If-Match",
+    )
+}
@@ -0,0 +1,255 @@
+//! Prometheus metrics, scraped from `GET /metrics` on the admin routes:
+//! request counts and latency histograms by method, route and status, the
+//! todo repository's query timings by statement, and the database pool's
+//! connections.
+//!
+//! Requests are labelled with the route they matched (`/todos/:id`), never
+//! their path, so the number of series stays bounded. Requests turned away
+//! by the outer middlewares before routing, such as load shedding, aren't
+//! counted here.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use sqlx::PgPool;
+
+use crate::{error, repository::PoolSettings};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// How long a scrape waits for a pool connection to time the acquire.
+const ACQUIRE_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Route of requests no route matched.
+const UNMATCHED: &str = "unmatched";
+
+/// How many of a statement's latest queries its p95 is taken from.
+const RECENT_QUERIES: usize = 1000;
+
+/// Query timings by statement. The repository runs them wherever it is
+/// used, not only in requests, so they are kept here rather than in
+/// [`Metrics`].
+static QUERIES: Mutex<BTreeMap<&'static str, QueryTimings>> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<BTreeMap<Labels, Histogram>>>);
+
+/// Method, matched route and status code.
+type Labels = (String, String, u16);
+
+#[derive(Default)]
+struct Histogram {
+    /// Requests at most as slow as each of [`BUCKETS`], not cumulative.
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+#[derive(Default)]
+struct QueryTimings {
+    count: u64,
+    sum: f64,
+    /// The latest durations in seconds, oldest first.
+    recent: VecDeque<f64>,
+}
+
+impl QueryTimings {
+    /// The 95th percentile of the latest durations, by nearest rank.
+    fn p95(&self) -> f64 {
+        let mut recent: Vec<f64> = self.recent.iter().copied().collect();
+        recent.sort_by(f64::total_cmp);
+        let rank = (recent.len() * 95).div_ceil(100);
+        recent.get(rank.saturating_sub(1)).copied().unwrap_or(0.0)
+    }
+}
+
+/// Records that a query of `statement` took `duration`.
+pub fn observe_query(statement: &'static str, duration: Duration) {
+    let seconds = duration.as_secs_f64();
+    let mut queries = QUERIES.lock().unwrap();
+    let timings = queries.entry(statement).or_default();
+    timings.count += 1;
+    timings.sum += seconds;
+    if timings.recent.len() == RECENT_QUERIES {
+        timings.recent.pop_front();
+    }
+    timings.recent.push_back(seconds);
+}
+
+pub async fn track<B>(State(metrics): State<Metrics>, req: Request<B>, next: Next<B>) -> Response {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED, MatchedPath::as_str)
+        .to_owned();
+
+    let response = next.run(req).await;
+
+    let labels = (method, route, response.status().as_u16());
+    metrics
+        .0
+        .lock()
+        .unwrap()
+        .entry(labels)
+        .or_default()
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+/// The metrics in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Prometheus text exposition format", body = String, content_type = "text/plain"),
+    ),
+)]
+pub async fn scrape(
+    Extension(metrics): Extension<Metrics>,
+    Extension(pg): Extension<PgPool>,
+    Extension(pool): Extension<PoolSettings>,
+) -> Response {
+    // read before the probe takes a connection
+    let size = pg.size();
+    let idle = pg.num_idle() as u32;
+    let started = Instant::now();
+    let acquire_seconds = match tokio::time::timeout(ACQUIRE_PROBE_TIMEOUT, pg.acquire()).await {
+        Ok(Ok(_connection)) => started.elapsed().as_secs_f64(),
+        _ => ACQUIRE_PROBE_TIMEOUT.as_secs_f64(),
+    };
+
+    let mut out = String::new();
+    out.push_str("# HELP http_requests_total Requests answered, by method, route and status.\n");
+    out.push_str("# TYPE http_requests_total counter\n");
+    let requests = metrics.0.lock().unwrap();
+    for ((method, route, status), histogram) in requests.iter() {
+        let _ = writeln!(
+            out,
+            r#"http_requests_total{{method="{method}",route="{route}",status="{status}"}} {}"#,
+            histogram.count
+        );
+    }
+    out.push_str("# HELP http_request_duration_seconds Time to answer requests.\n");
+    out.push_str("# TYPE http_request_duration_seconds histogram\n");
+    for ((method, route, status), histogram) in requests.iter() {
+        let labels = format!(r#"method="{method}",route="{route}",status="{status}""#);
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                r#"http_request_duration_seconds_bucket{{{labels},le="{bound}"}} {cumulative}"#
+            );
+        }
+        let _ = writeln!(
+            out,
+            r#"http_request_duration_seconds_bucket{{{labels},le="+Inf"}} {}"#,
+            histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "http_request_duration_seconds_sum{{{labels}}} {}",
+            histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "http_request_duration_seconds_count{{{labels}}} {}",
+            histogram.count
+        );
+    }
+    drop(requests);
+
+    out.push_str(
+        "# HELP db_query_duration_seconds Time the todo repository's queries took, by \
+         statement; the quantile is of the latest 1000.\n",
+    );
+    out.push_str("# TYPE db_query_duration_seconds summary\n");
+    for (statement, timings) in QUERIES.lock().unwrap().iter() {
+        let labels = format!(r#"statement="{statement}""#);
+        let _ = writeln!(
+            out,
+            r#"db_query_duration_seconds{{{labels},quantile="0.95"}} {}"#,
+            timings.p95()
+        );
+        let _ = writeln!(
+            out,
+            "db_query_duration_seconds_sum{{{labels}}} {}",
+            timings.sum
+        );
+        let _ = writeln!(
+            out,
+            "db_query_duration_seconds_count{{{labels}}} {}",
+            timings.count
+        );
+    }
+
+    let gauges = [
+        (
+            "db_pool_connections_active",
+            "Pool connections in use.",
+            size.saturating_sub(idle).to_string(),
+        ),
+        (
+            "db_pool_connections_idle",
+            "Pool connections waiting to be used.",
+            idle.to_string(),
+        ),
+        (
+            "db_pool_connections_min",
+            "Pool connections kept open while idle.",
+            pool.min_connections.to_string(),
+        ),
+        (
+            "db_pool_connections_max",
+            "Pool connections opened at most.",
+            pool.max_connections.to_string(),
+        ),
+        (
+            "db_pool_acquire_seconds",
+            "Time this scrape waited for a pool connection, capped at one second.",
+            acquire_seconds.to_string(),
+        ),
+    ];
+    for (name, help, value) in gauges {
+        let _ = writeln!(
+            out,
+            "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}"
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP db_pool_acquire_timeouts_total Requests failed waiting for a pool connection.\n\
+         # TYPE db_pool_acquire_timeouts_total counter\n\
+         db_pool_acquire_timeouts_total {}",
+        error::pool_acquire_timeouts()
+    );
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out).into_response()
+}
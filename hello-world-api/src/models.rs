@@ -0,0 +1,448 @@
+//! Todo rows as read from the database, the request bodies and query
+//! strings of the todo endpoints, and the views they answer with. The bodies
+//! and views are defined in `todo_api_types`, shared with clients; what the
+//! server makes of them is here.
+
+use axum::http::StatusCode;
+use serde::Serialize;
+use todo_api_types::TodoMeta;
+pub use todo_api_types::{
+    BulkComplete, BulkCreate, BulkResult, BulkResults, CreateTodo, GetTodo, ListTodos, ListTrash,
+    MergeTodo, MoveTodo, PatchTodo, Priority, PutTodo, Staleness, ToDoMetaView, ToDoView,
+    TodoListPage, TodoMetaListPage, TodoPage, TodoStatus, ValidateTodos,
+};
+
+use crate::{
+    error::ApiError,
+    extract::{check_text, FieldError, Validate},
+    recurrence,
+    repository::{
+        todo_query::{self, TodoQuery},
+        MoveTo, NewTodo, TodoChanges,
+    },
+    tags::Tag,
+};
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct Todo {
+    pub id: uuid::Uuid,
+    pub todo_text: String,
+    pub is_done: bool,
+    pub start_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub due_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the expiry job cancelled the todo.
+    pub expired_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Bumped by every update of the row.
+    pub version: i64,
+    pub list_id: Option<uuid::Uuid>,
+    /// The workspace member, or owner, the todo is assigned to.
+    pub assignee_id: Option<uuid::Uuid>,
+    pub priority: Option<Priority>,
+    pub recurrence: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Stamped by every update of the row, as `version` is bumped.
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// Where the todo is in its owner's order, which listings without `sort`
+    /// follow.
+    pub position: f64,
+    /// Only selected by listings, everything else never sees deleted todos.
+    #[sqlx(default)]
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only selected by the reads that can return `?meta=true`.
+    #[sqlx(default)]
+    pub field_modified: Option<serde_json::Value>,
+    /// `todo_tags(id)`, selected by the reads and updates answering with
+    /// the todo; a todo just inserted has none.
+    #[sqlx(default)]
+    pub tags: sqlx::types::Json<Vec<Tag>>,
+    /// `todo_completion(id)`, selected along with the tags: how much of the
+    /// checklist is done, in percent, `None` without one.
+    #[sqlx(default)]
+    pub completion_percent: Option<i32>,
+    /// `todo_shared_by(user_id, ...)` of the reads that also find todos
+    /// shared with the user: who shared it, `None` for the user's own.
+    #[sqlx(default)]
+    pub shared_by: Option<String>,
+}
+
+impl Todo {
+    /// Id for a new todo. Version 7 UUIDs start with their creation time, so
+    /// todos sort by id in the order they were created, and inserts land next
+    /// to each other at the end of the primary key index.
+    pub fn new_id() -> uuid::Uuid {
+        uuid::Uuid::now_v7()
+    }
+
+    /// Strong validator of the todo, its version quoted as in an `ETag`
+    /// header.
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.version)
+    }
+
+    pub fn status(&self) -> TodoStatus {
+        if self.is_done {
+            TodoStatus::Done
+        } else if self.expired_at.is_some() {
+            TodoStatus::Expired
+        } else {
+            TodoStatus::Open
+        }
+    }
+}
+
+impl TryFrom<ListTodos> for TodoQuery {
+    type Error = ApiError;
+
+    fn try_from(params: ListTodos) -> Result<Self, ApiError> {
+        let defaults = TodoQuery::default();
+        let sort = match params.sort {
+            Some(spec) => todo_query::parse_sort(&spec)
+                .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?,
+            None => defaults.sort,
+        };
+        if params.after.is_some() && !sort.is_empty() {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "after can only be used without sort, use offset instead",
+            ));
+        }
+        let after = match params.after {
+            Some(cursor) => Some(
+                cursor
+                    .parse()
+                    .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?,
+            ),
+            None => None,
+        };
+        let ids = match params.ids {
+            Some(ids) => Some(parse_ids(&ids)?),
+            None => None,
+        };
+        let fields = match params.fields {
+            Some(spec) => Some(
+                todo_query::parse_fields(&spec)
+                    .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?,
+            ),
+            None => None,
+        };
+        let default_limit = match ids {
+            Some(_) => MAX_LISTED_IDS as i64,
+            None => defaults.limit,
+        };
+        Ok(TodoQuery {
+            is_done: params.is_done,
+            started: params.started,
+            overdue: params.overdue,
+            due_before: params.due_before,
+            tag: params.tag,
+            list_id: params.list_id,
+            assignee_id: None,
+            priority: params.priority,
+            expired: params.expired,
+            include_deleted: params.include_deleted,
+            include_shared: false,
+            text_contains: params.q.filter(|q| !q.is_empty()),
+            search: params.search.filter(|search| !search.is_empty()),
+            since: params.since,
+            sort,
+            after,
+            ids,
+            fields,
+            limit: params.limit.unwrap_or(default_limit).clamp(1, 100),
+            offset: params.offset.unwrap_or(defaults.offset).max(0),
+        })
+    }
+}
+
+/// Most todos `GET /todos` fetches by `ids` at once, a full page.
+pub const MAX_LISTED_IDS: usize = 100;
+
+/// Parses the comma-separated `ids` of a listing.
+fn parse_ids(ids: &str) -> Result<Vec<uuid::Uuid>, ApiError> {
+    let ids = ids
+        .split(',')
+        .filter(|id| !id.is_empty())
+        .map(|id| id.trim().parse())
+        .collect::<Result<Vec<uuid::Uuid>, _>>()
+        .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "ids must be todo ids"))?;
+    if ids.len() > MAX_LISTED_IDS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("ids must have at most {MAX_LISTED_IDS} entries"),
+        ));
+    }
+    Ok(ids)
+}
+
+/// Most deleted todos `GET /todos/trash` lists at once.
+pub const MAX_TRASH_LIMIT: i64 = 1000;
+
+/// Longest todo text accepted, in characters.
+pub const MAX_TEXT_CHARS: usize = 1000;
+
+/// The todo to insert, its text and rule trimmed.
+impl<'a> From<&'a CreateTodo> for NewTodo<'a> {
+    fn from(body: &'a CreateTodo) -> Self {
+        NewTodo {
+            text: body.text.trim(),
+            start_at: body.start_at,
+            due_at: body.due_at,
+            expires_at: body.expires_at,
+            list_id: body.list_id,
+            priority: body.priority,
+            recurrence: body.recurrence.as_deref().map(str::trim),
+        }
+    }
+}
+
+impl Validate for CreateTodo {
+    fn validate(&self) -> Vec<FieldError> {
+        check_text("text", &self.text, MAX_TEXT_CHARS)
+            .into_iter()
+            .chain(self.recurrence.as_deref().and_then(recurrence::check))
+            .collect()
+    }
+}
+
+/// Most todos one `POST /todos/validate` checks.
+pub const MAX_VALIDATED_TODOS: usize = 1000;
+
+impl Validate for ValidateTodos {
+    fn validate(&self) -> Vec<FieldError> {
+        if self.etags.len() <= MAX_VALIDATED_TODOS {
+            return Vec::new();
+        }
+        vec![FieldError {
+            field: "etags",
+            reason: format!("must have at most {MAX_VALIDATED_TODOS} entries"),
+        }]
+    }
+}
+
+/// Most todos one bulk request creates or completes.
+pub const MAX_BULK_TODOS: usize = 100;
+
+impl Validate for BulkCreate {
+    fn validate(&self) -> Vec<FieldError> {
+        check_bulk_size("todos", self.todos.len())
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Validate for BulkComplete {
+    fn validate(&self) -> Vec<FieldError> {
+        check_bulk_size("ids", self.ids.len()).into_iter().collect()
+    }
+}
+
+fn check_bulk_size(field: &'static str, len: usize) -> Option<FieldError> {
+    let reason = if len == 0 {
+        "must not be empty".to_owned()
+    } else if len > MAX_BULK_TODOS {
+        format!("must have at most {MAX_BULK_TODOS} entries")
+    } else {
+        return None;
+    };
+    Some(FieldError { field, reason })
+}
+
+/// One item's outcome, `status` if it succeeded.
+pub fn bulk_result(status: StatusCode, result: Result<Todo, ApiError>) -> BulkResult {
+    match result {
+        Ok(todo) => BulkResult {
+            status: status.as_u16(),
+            todo: Some(ToDoView::from(todo)),
+            error: None,
+        },
+        Err(err) => BulkResult {
+            status: err.code.as_u16(),
+            todo: None,
+            error: Some(err.body()),
+        },
+    }
+}
+
+/// Where `body` moves the todo, `None` unless exactly one field is given.
+pub fn move_to(body: &MoveTodo) -> Option<MoveTo> {
+    match (body.index, body.before, body.after) {
+        (Some(index), None, None) => Some(MoveTo::Index(index)),
+        (None, Some(before), None) => Some(MoveTo::Before(before)),
+        (None, None, Some(after)) => Some(MoveTo::After(after)),
+        _ => None,
+    }
+}
+
+impl Validate for MoveTodo {
+    fn validate(&self) -> Vec<FieldError> {
+        match self.index {
+            Some(index) if index < 0 => vec![FieldError {
+                field: "index",
+                reason: "must not be negative".to_owned(),
+            }],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The changes to make, the text and rule trimmed.
+impl<'a> From<&'a PatchTodo> for TodoChanges<'a> {
+    fn from(body: &'a PatchTodo) -> Self {
+        TodoChanges {
+            text: body.text.as_deref().map(str::trim),
+            is_done: body.is_done,
+            due_at: body.due_at,
+            expires_at: body.expires_at,
+            list_id: body.list_id,
+            priority: body.priority,
+            recurrence: body
+                .recurrence
+                .as_ref()
+                .map(|rule| rule.as_deref().map(str::trim)),
+        }
+    }
+}
+
+impl Validate for PatchTodo {
+    fn validate(&self) -> Vec<FieldError> {
+        self.text
+            .as_deref()
+            .and_then(|text| check_text("text", text, MAX_TEXT_CHARS))
+            .into_iter()
+            .chain(
+                self.recurrence
+                    .as_ref()
+                    .and_then(|rule| recurrence::check(rule.as_deref()?)),
+            )
+            .collect()
+    }
+}
+
+/// A todo as a row of a listing answered as CSV, in the order of
+/// [`TODO_ROW_COLUMNS`], its tags' names separated by commas.
+#[derive(Serialize)]
+pub struct ToDoRow {
+    id: uuid::Uuid,
+    text: String,
+    is_done: bool,
+    status: TodoStatus,
+    start_at: Option<chrono::DateTime<chrono::Utc>>,
+    due_at: Option<chrono::DateTime<chrono::Utc>>,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    list_id: Option<uuid::Uuid>,
+    assignee_id: Option<uuid::Uuid>,
+    priority: Option<Priority>,
+    recurrence: Option<String>,
+    tags: String,
+    completion_percent: Option<i32>,
+    deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    etag: String,
+}
+
+/// The header of a CSV listing.
+pub const TODO_ROW_COLUMNS: [&str; 15] = [
+    "id",
+    "text",
+    "is_done",
+    "status",
+    "start_at",
+    "due_at",
+    "expires_at",
+    "list_id",
+    "assignee_id",
+    "priority",
+    "recurrence",
+    "tags",
+    "completion_percent",
+    "deleted_at",
+    "etag",
+];
+
+impl From<Todo> for ToDoRow {
+    fn from(todo: Todo) -> Self {
+        let tags: Vec<_> = todo.tags.0.iter().map(|tag| tag.name.as_str()).collect();
+        ToDoRow {
+            etag: todo.etag(),
+            status: todo.status(),
+            id: todo.id,
+            text: todo.todo_text,
+            is_done: todo.is_done,
+            start_at: todo.start_at,
+            due_at: todo.due_at,
+            expires_at: todo.expires_at,
+            list_id: todo.list_id,
+            assignee_id: todo.assignee_id,
+            priority: todo.priority,
+            recurrence: todo.recurrence,
+            tags: tags.join(", "),
+            completion_percent: todo.completion_percent,
+            deleted_at: todo.deleted_at,
+        }
+    }
+}
+
+impl From<Todo> for ToDoMetaView {
+    fn from(todo: Todo) -> Self {
+        ToDoMetaView {
+            meta: TodoMeta {
+                field_modified: todo.field_modified.clone().unwrap_or_default(),
+            },
+            todo: ToDoView::from(todo),
+        }
+    }
+}
+
+impl From<&Todo> for ToDoView {
+    fn from(todo: &Todo) -> Self {
+        ToDoView {
+            etag: todo.etag(),
+            id: todo.id,
+            text: todo.todo_text.clone(),
+            is_done: todo.is_done,
+            status: todo.status(),
+            start_at: todo.start_at,
+            due_at: todo.due_at,
+            expires_at: todo.expires_at,
+            list_id: todo.list_id,
+            assignee_id: todo.assignee_id,
+            priority: todo.priority,
+            recurrence: todo.recurrence.clone(),
+            tags: todo.tags.0.clone(),
+            completion_percent: todo.completion_percent,
+            shared_by: todo.shared_by.clone(),
+            created_at: todo.created_at,
+            updated_at: todo.updated_at,
+            position: todo.position,
+            deleted_at: todo.deleted_at,
+            links: None,
+        }
+    }
+}
+
+impl From<Todo> for ToDoView {
+    fn from(todo: Todo) -> Self {
+        ToDoView {
+            etag: todo.etag(),
+            status: todo.status(),
+            id: todo.id,
+            text: todo.todo_text,
+            is_done: todo.is_done,
+            start_at: todo.start_at,
+            due_at: todo.due_at,
+            expires_at: todo.expires_at,
+            list_id: todo.list_id,
+            assignee_id: todo.assignee_id,
+            priority: todo.priority,
+            recurrence: todo.recurrence,
+            tags: todo.tags.0,
+            completion_percent: todo.completion_percent,
+            shared_by: todo.shared_by,
+            created_at: todo.created_at,
+            updated_at: todo.updated_at,
+            position: todo.position,
+            deleted_at: todo.deleted_at,
+            links: None,
+        }
+    }
+}
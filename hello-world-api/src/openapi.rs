@@ -0,0 +1,377 @@
+//! OpenAPI document of the HTTP API, served at `/api-docs/openapi.json` and
+//! browsable at `/swagger-ui`. Paths and schemas are declared next to their
+//! handlers and bodies; this lists them. CalDAV is left out, as OpenAPI can't
+//! describe `PROPFIND` and `REPORT`.
+
+use utoipa::{
+    openapi::{
+        security::{
+            ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme,
+        },
+        Response,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    admin_stats, admin_todos, api_keys, archive, assignment, assist, attachments, audit, auth,
+    checklist, counts, error, events, github, handlers::todos, health, history, hooks, import,
+    inbound_email, jobs, links, lists, location, log_level, maintenance, me, metrics, models,
+    portable, rate_limit, recording, recurrence, schedule, search, setup, share, stats, tags,
+    todo_share, todo_stream, transfer, versioning, webhooks, workspaces,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Todo API"),
+    paths(
+        todos::get_todos,
+        todos::create_todo,
+        todos::quick_add_todo,
+        recurrence::preview,
+        todos::create_todos,
+        todos::complete_todos,
+        schedule::today,
+        search::search,
+        counts::get,
+        portable::export,
+        todo_stream::stream,
+        portable::import,
+        todos::get_todo,
+        todos::put_todo_done,
+        todos::toggle_todo,
+        todos::patch_todo,
+        todos::validate_todos,
+        todos::delete_todo,
+        todos::get_trash,
+        todos::restore_todo,
+        todos::move_todo,
+        todos::merge_todo,
+        archive::archive_completed,
+        archive::list,
+        history::list,
+        me::summary,
+        me::get_preferences,
+        me::put_preferences,
+        schedule::put_start,
+        schedule::delete_start,
+        location::put_location,
+        location::delete_location,
+        location::nearby,
+        assist::breakdown,
+        assignment::assign,
+        checklist::get_checklist,
+        checklist::add_item,
+        checklist::reorder,
+        checklist::put_item,
+        checklist::delete_item,
+        attachments::upload,
+        attachments::list,
+        attachments::download,
+        attachments::delete,
+        links::create,
+        links::delete,
+        lists::list,
+        lists::create,
+        lists::get,
+        lists::rename,
+        lists::delete,
+        lists::todos,
+        tags::list,
+        tags::create,
+        tags::stats,
+        tags::bulk,
+        tags::attach,
+        tags::detach,
+        share::create,
+        share::revoke,
+        share::view,
+        todo_share::todo_shares,
+        todo_share::share_todo,
+        todo_share::unshare_todo,
+        todo_share::list_shares,
+        todo_share::share_list,
+        todo_share::unshare_list,
+        webhooks::create,
+        webhooks::list,
+        webhooks::delete,
+        webhooks::rotate_secret,
+        webhooks::deliveries,
+        auth::register,
+        auth::login,
+        auth::introspect,
+        api_keys::create,
+        api_keys::list,
+        api_keys::revoke,
+        setup::get,
+        setup::post,
+        workspaces::list,
+        workspaces::create,
+        workspaces::members,
+        workspaces::remove_member,
+        workspaces::invite,
+        workspaces::accept,
+        workspaces::leaderboard,
+        workspaces::get_privacy,
+        workspaces::put_privacy,
+        workspaces::get_settings,
+        workspaces::put_settings,
+        events::stream,
+        events::sse,
+        import::todoist,
+        import::trello,
+        import::github,
+        import::get_job,
+        hooks::create,
+        hooks::delete,
+        hooks::deliver,
+        github::webhook,
+        inbound_email::mailgun,
+        stats::completions,
+        stats::heatmap,
+        audit::list,
+        admin_stats::get,
+        admin_todos::list,
+        transfer::export,
+        transfer::import,
+        jobs::list,
+        maintenance::get,
+        maintenance::put,
+        rate_limit::get_workspace,
+        rate_limit::put_workspace,
+        rate_limit::delete_workspace,
+        log_level::get,
+        log_level::put,
+        recording::list,
+        recording::get_settings,
+        recording::put_settings,
+        metrics::scrape,
+        health::healthz,
+        health::readyz,
+    ),
+    components(schemas(
+        error::ProblemDetails,
+        error::ErrorCode,
+        models::CreateTodo,
+        models::PutTodo,
+        models::PatchTodo,
+        models::TodoStatus,
+        events::TodoEvent,
+        events::EventKind,
+        models::BulkCreate,
+        models::BulkComplete,
+        models::BulkResults,
+        models::BulkResult,
+        models::ValidateTodos,
+        models::Staleness,
+        models::MergeTodo,
+        models::MoveTodo,
+        models::ToDoView,
+        models::ToDoMetaView,
+        models::TodoListPage,
+        models::TodoMetaListPage,
+        models::Priority,
+        recurrence::RecurrencePreview,
+        schedule::StartAt,
+        location::Location,
+        location::NearbyView,
+        assist::BreakdownView,
+        assignment::Assign,
+        checklist::ChecklistView,
+        checklist::AddItem,
+        checklist::PutItem,
+        checklist::Reorder,
+        attachments::Attachment,
+        attachments::UploadAttachment,
+        links::TodoLink,
+        links::LinkKind,
+        links::LinkDirection,
+        archive::ArchiveCompleted,
+        archive::Archived,
+        archive::ArchivedTodo,
+        archive::ArchivedTodoPage,
+        history::HistoryEntry,
+        me::UserSummary,
+        me::Preferences,
+        search::SearchHit,
+        search::SearchPage,
+        counts::TodoCounts,
+        portable::FileFormat,
+        portable::TodoRecord,
+        portable::TodoImportSummary,
+        portable::FailedRecord,
+        links::CreateLink,
+        lists::List,
+        lists::ListName,
+        tags::Tag,
+        tags::CreateTag,
+        tags::TagStats,
+        tags::BulkTags,
+        tags::BulkTagFilter,
+        tags::BulkTagged,
+        share::CreateShareLink,
+        share::ShareLinkView,
+        todo_share::SharePermission,
+        todo_share::ShareTodo,
+        todo_share::ShareView,
+        webhooks::CreateWebhook,
+        webhooks::WebhookView,
+        webhooks::CreatedWebhook,
+        webhooks::DeliveryStatus,
+        webhooks::DeliveryView,
+        auth::Credentials,
+        auth::UserView,
+        auth::TokenView,
+        auth::Introspect,
+        auth::Introspection,
+        api_keys::CreateApiKey,
+        api_keys::ApiKeyView,
+        api_keys::CreatedApiKey,
+        setup::SetupState,
+        workspaces::WorkspaceRole,
+        workspaces::WorkspaceView,
+        workspaces::NewWorkspace,
+        workspaces::Member,
+        workspaces::CreateInvitation,
+        workspaces::InvitationView,
+        workspaces::Period,
+        workspaces::Leaderboard,
+        workspaces::Ranked,
+        workspaces::Privacy,
+        workspaces::Weekday,
+        workspaces::WorkspaceSettings,
+        import::ImportReport,
+        import::JobStatus,
+        import::TrelloBoard,
+        hooks::HookMapping,
+        hooks::HookView,
+        inbound_email::InboundMessage,
+        stats::Bucket,
+        stats::CompletionsView,
+        stats::BucketCount,
+        stats::HeatmapView,
+        audit::AuditEntry,
+        admin_stats::AdminStats,
+        admin_stats::DayCount,
+        admin_stats::UserCounts,
+        admin_todos::AdminTodo,
+        admin_todos::AdminTodoPage,
+        transfer::Workspace,
+        transfer::ExportedUser,
+        transfer::ExportedList,
+        transfer::ExportedTag,
+        transfer::ExportedTodo,
+        transfer::ExportedItem,
+        transfer::ExportedLink,
+        transfer::ExportedWorkspace,
+        transfer::ExportedMember,
+        transfer::ImportSummary,
+        transfer::UserMapping,
+        jobs::JobStatus,
+        maintenance::MaintenanceState,
+        rate_limit::WorkspaceRateLimit,
+        log_level::LogFilter,
+        recording::Recording,
+        recording::RecordingSettings,
+        health::Liveness,
+        health::Readiness,
+        health::PoolHealth,
+    )),
+    modifiers(&SecuritySchemes, &AdminOnly, &Versioned),
+    tags(
+        (name = "todos"),
+        (name = "location", description = "Places todos are tied to"),
+        (name = "checklists", description = "Subtasks of a todo"),
+        (name = "attachments", description = "Files attached to a todo"),
+        (name = "links", description = "Typed relations between two todos"),
+        (name = "lists", description = "Projects a user sorts their todos into"),
+        (name = "tags", description = "Labels a user groups their todos by"),
+        (name = "sharing", description = "Todos and lists shared with other users, and read-only links to a todo"),
+        (name = "webhooks", description = "URLs the events of a user's todos are posted to"),
+        (name = "auth"),
+        (name = "workspaces", description = "Tenants whose data their members share, acted in with `X-Workspace`"),
+        (name = "import", description = "Bulk imports, run in the background"),
+        (name = "integrations", description = "Webhooks creating and updating todos"),
+        (name = "stats"),
+        (name = "admin", description = "Operator endpoints, possibly on their own listener"),
+        (name = "health", description = "Liveness and readiness probes"),
+    ),
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some("Token from `POST /auth/login`"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-Api-Key",
+                "Key from `POST /auth/api-keys`, accepted instead of a bearer token by the \
+                 REST endpoints other than those managing keys",
+            ))),
+        );
+    }
+}
+
+/// Marks the `/admin` endpoints as needing an admin's token, which
+/// [`auth::require_admin`] checks for all of them.
+struct AdminOnly;
+
+impl Modify for AdminOnly {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if !path.starts_with("/admin/") {
+                continue;
+            }
+            for operation in item.operations.values_mut() {
+                operation.security = Some(vec![SecurityRequirement::new("bearer", ["admin"])]);
+                let responses = &mut operation.responses.responses;
+                for (status, description) in [
+                    ("401", "Missing or invalid bearer token"),
+                    ("403", "Not an admin's token with the `admin` scope"),
+                ] {
+                    responses.insert(status.to_owned(), Response::new(description).into());
+                }
+            }
+        }
+    }
+}
+
+/// Lists the API's paths under `/api/v1`, where clients should call them;
+/// the operator endpoints stay unversioned.
+struct Versioned;
+
+impl Modify for Versioned {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let paths = std::mem::take(&mut openapi.paths.paths);
+        openapi.paths.paths = paths
+            .into_iter()
+            .map(|(path, item)| {
+                let operator = item.operations.values().any(|operation| {
+                    operation
+                        .tags
+                        .iter()
+                        .flatten()
+                        .any(|tag| tag == "admin" || tag == "health")
+                });
+                if operator {
+                    (path, item)
+                } else {
+                    (format!("{}{path}", versioning::V1), item)
+                }
+            })
+            .collect();
+    }
+}
@@ -0,0 +1,316 @@
+//! Outbound HTTP. Every call the server makes, to the LLM, GitHub, S3 and
+//! the analytics sinks, goes through one [`Outbound`] client, so connections are
+//! pooled across them and each [`Destination`] gets its own timeout and
+//! retries. Requests go through `OUTBOUND_PROXY` if set, otherwise through
+//! the usual `HTTPS_PROXY`/`NO_PROXY` variables.
+//!
+//! Destinations that may only be on the public internet refuse URLs that
+//! aren't `http(s)` or whose host is, or resolves to, a loopback, private,
+//! link-local or otherwise internal address. The addresses are checked again
+//! when connecting, so a name can't resolve to a public address for the check
+//! and to an internal one for the request. They don't follow redirects
+//! either, which could point at an internal address literal, connected to
+//! without resolving: a 3xx is their answer. Operator-configured endpoints,
+//! such as a self-hosted LLM or an in-cluster Kafka proxy, may be internal.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use hyper::client::connect::dns::Name;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    Method, RequestBuilder, Response, StatusCode, Url,
+};
+use tracing::warn;
+
+/// Where a request goes, and how patiently.
+pub struct Destination {
+    /// For the logs.
+    name: &'static str,
+    /// Of each attempt, connecting included.
+    timeout: Duration,
+    /// Attempts after the first one, for connection failures, timeouts,
+    /// 429s and 502 to 504s.
+    retries: u32,
+    /// Whether the URL may point into internal networks.
+    internal: bool,
+}
+
+/// The chat completions endpoint. Retried once, asking again has no effect
+/// besides the cost.
+pub const LLM: Destination = Destination {
+    name: "llm",
+    timeout: Duration::from_secs(30),
+    retries: 1,
+    internal: true,
+};
+
+pub const GITHUB: Destination = Destination {
+    name: "github",
+    timeout: Duration::from_secs(10),
+    retries: 2,
+    internal: false,
+};
+
+/// The Kafka proxy and PostHog. Not retried: a batch that reached the sink
+/// before timing out would be counted twice.
+pub const ANALYTICS: Destination = Destination {
+    name: "analytics",
+    timeout: Duration::from_secs(10),
+    retries: 0,
+    internal: true,
+};
+
+/// Users' webhooks, retried by the delivery queue rather than here, with a
+/// backoff.
+pub const WEBHOOKS: Destination = Destination {
+    name: "webhooks",
+    timeout: Duration::from_secs(10),
+    retries: 0,
+    internal: false,
+};
+
+/// The S3-compatible store of attachments, possibly an in-cluster MinIO.
+/// Reads, writes and deletes of an object by its key can safely be repeated.
+pub const STORAGE: Destination = Destination {
+    name: "storage",
+    timeout: Duration::from_secs(30),
+    retries: 2,
+    internal: true,
+};
+
+/// Connecting is given this long at most, whatever the destination's timeout.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait before the first retry, doubled for each further one.
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+#[derive(Clone)]
+pub struct Outbound {
+    /// For destinations that may be internal.
+    any: reqwest::Client,
+    /// Only connects to public addresses, through [`PublicOnly`] unless
+    /// behind a proxy, and doesn't follow redirects.
+    public: reqwest::Client,
+}
+
+impl Outbound {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let proxy = std::env::var("OUTBOUND_PROXY")
+            .ok()
+            .map(|url| reqwest::Proxy::all(&url).context("OUTBOUND_PROXY is not a valid URL"))
+            .transpose()?;
+        let builder = || {
+            let builder = reqwest::Client::builder()
+                .user_agent("hello-world-api")
+                .connect_timeout(CONNECT_TIMEOUT);
+            match &proxy {
+                Some(proxy) => builder.proxy(proxy.clone()),
+                None => builder,
+            }
+        };
+        // behind a proxy the resolver would only see the proxy's name
+        let public = match &proxy {
+            Some(_) => builder(),
+            None => builder().dns_resolver(Arc::new(PublicOnly)),
+        }
+        .redirect(reqwest::redirect::Policy::none());
+        Ok(Outbound {
+            any: builder()
+                .build()
+                .context("failed to build outbound HTTP client")?,
+            public: public
+                .build()
+                .context("failed to build outbound HTTP client")?,
+        })
+    }
+
+    /// Sends the request `build` makes of a `method` request to `url`, once
+    /// more for each retry the destination allows. The answer may still be
+    /// an error status, for the caller to check.
+    pub async fn send(
+        &self,
+        destination: &Destination,
+        method: Method,
+        url: &str,
+        build: impl Fn(RequestBuilder) -> RequestBuilder,
+    ) -> anyhow::Result<Response> {
+        let url = Url::parse(url).with_context(|| format!("invalid URL {url}"))?;
+        let client = if destination.internal {
+            &self.any
+        } else {
+            check_public(&url).await?;
+            &self.public
+        };
+        let mut attempt = 0;
+        loop {
+            let request = client
+                .request(method.clone(), url.clone())
+                .timeout(destination.timeout);
+            let result = build(request).send().await;
+            let reason = match &result {
+                Ok(response) if is_retryable(response.status()) => response.status().to_string(),
+                Err(err) if err.is_connect() || err.is_timeout() => err.to_string(),
+                _ => return Ok(result?),
+            };
+            if attempt == destination.retries {
+                return Ok(result?);
+            }
+            warn!(
+                destination = destination.name,
+                attempt, "Retrying outbound request after {reason}"
+            );
+            tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Fails unless `url` is `http(s)` without credentials, and its host only
+/// has public addresses.
+async fn check_public(url: &Url) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        matches!(url.scheme(), "http" | "https"),
+        "only http and https URLs may be requested, not {}",
+        url.scheme()
+    );
+    anyhow::ensure!(
+        url.username().is_empty() && url.password().is_none(),
+        "URLs with credentials may not be requested"
+    );
+    let host = url.host_str().context("URL has no host")?;
+    // IPv6 literals come bracketed
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or_default();
+    let addrs: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("failed to resolve {host}"))?
+        .collect();
+    anyhow::ensure!(
+        !addrs.is_empty() && addrs.iter().all(|addr| is_public(addr.ip())),
+        "{host} is not a public address"
+    );
+    Ok(())
+}
+
+/// Resolver only answering with public addresses, failing for names that
+/// have none.
+struct PublicOnly;
+
+impl Resolve for PublicOnly {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this network", carrier-grade NAT, IETF protocol assignments,
+        // benchmarking and reserved for future use
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local, link-local and documentation
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use axum::{
+        http::{header, StatusCode},
+        routing::get,
+        Router,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn public_requests_dont_follow_redirects() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reached = Arc::new(AtomicBool::new(false));
+        let internal = reached.clone();
+        let app = Router::new()
+            .route(
+                "/hook",
+                get(move || async move {
+                    let location = format!("http://127.0.0.1:{}/internal", addr.port());
+                    (StatusCode::FOUND, [(header::LOCATION, location)])
+                }),
+            )
+            .route(
+                "/internal",
+                get(move || async move { internal.store(true, Ordering::SeqCst) }),
+            );
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        // as if the hook's public name had passed the check, the redirect
+        // names a loopback literal
+        let outbound = Outbound::from_env().unwrap();
+        let response = outbound
+            .public
+            .get(format!("http://{addr}/hook"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert!(!reached.load(Ordering::SeqCst));
+        assert!(!is_public(addr.ip()));
+    }
+}
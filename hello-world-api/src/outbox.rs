@@ -0,0 +1,219 @@
+//! The outbox of todo events. [`Events`] records each event in the
+//! `todo_outbox` table, in the transaction making the change it tells of, so
+//! an event is only ever published for a change that was committed and is
+//! published even if the server dies right after committing. The [`relay`]
+//! then marks the recorded events published, queueing their webhooks'
+//! deliveries and sending them to the [`Broker`] in the same transaction,
+//! and publishes them on the process' bus to the live clients.
+//!
+//! The relay is woken by a notification sent when a transaction recording
+//! events commits, and looks for events left behind every
+//! [`POLL_INTERVAL`] otherwise. With several servers on one database each
+//! event is relayed by one of them, reaching the live clients connected to
+//! that one. An event is published again if its server dies while relaying
+//! it. Events relayed together are published in the order they were
+//! recorded in; a transaction committing after a later one may have its
+//! events published after that one's.
+//!
+//! Published events are kept for [`KEPT_FOR`].
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use sqlx::{postgres::PgListener, PgConnection, PgPool, Postgres, Transaction};
+use tracing::{error, warn};
+
+use crate::{broker::Broker, events::Events, tx::Tx, webhooks::Webhooks};
+
+/// The channel notified when events are recorded.
+const CHANNEL: &str = "todo_outbox";
+
+/// How often unpublished events are looked for without a notification,
+/// such as while the relay's connection is lost.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Events relayed in one transaction.
+const BATCH: i64 = 100;
+
+/// How long published events are kept.
+const KEPT_FOR: Duration = Duration::from_secs(86400);
+
+/// How often the published events older than [`KEPT_FOR`] are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// An event as recorded, for the relay.
+pub struct Recorded {
+    pub id: i64,
+    pub user_id: uuid::Uuid,
+    /// The event as live clients get it.
+    pub payload: String,
+}
+
+/// Where [`Events`] records an event: the connection of the transaction
+/// making the change, or nowhere with `STORAGE=memory`, which publishes it
+/// right away.
+pub struct Outbox<'c>(Option<&'c mut PgConnection>);
+
+impl<'c> From<&'c mut PgConnection> for Outbox<'c> {
+    fn from(conn: &'c mut PgConnection) -> Self {
+        Outbox(Some(conn))
+    }
+}
+
+impl<'c> From<&'c mut Transaction<'_, Postgres>> for Outbox<'c> {
+    fn from(tx: &'c mut Transaction<'_, Postgres>) -> Self {
+        Outbox(Some(&mut **tx))
+    }
+}
+
+/// The request's transaction, or nowhere if it is detached.
+impl<'c> From<&'c mut Tx> for Outbox<'c> {
+    fn from(tx: &'c mut Tx) -> Self {
+        Outbox(tx.connection())
+    }
+}
+
+impl Outbox<'_> {
+    /// Records `json`, an event of `user_id`'s todos; `false` if there is no
+    /// database to record it in.
+    pub async fn record(self, user_id: uuid::Uuid, json: &str) -> Result<bool, sqlx::Error> {
+        let Some(conn) = self.0 else {
+            return Ok(false);
+        };
+        sqlx::query!(
+            r#"insert into "todo_outbox" (user_id, payload) values ($1, $2::text::json)"#,
+            user_id,
+            json,
+        )
+        .execute(conn)
+        .await?;
+        Ok(true)
+    }
+}
+
+/// Starts publishing the recorded events on `events`, to `webhooks` and to
+/// the `broker` if any.
+pub fn spawn(pg: PgPool, events: Events, webhooks: Webhooks, broker: Option<Arc<dyn Broker>>) {
+    let relay = Relay {
+        events,
+        webhooks,
+        broker,
+    };
+    tokio::spawn(relay.run(pg));
+}
+
+/// Where the relay publishes the events.
+struct Relay {
+    events: Events,
+    webhooks: Webhooks,
+    broker: Option<Arc<dyn Broker>>,
+}
+
+impl Relay {
+    /// Publishes the events recorded, as they are and every [`POLL_INTERVAL`],
+    /// listening for the notification of new ones on a connection of its own.
+    async fn run(self, pg: PgPool) {
+        let mut listener = None;
+        let mut pruned_at: Option<Instant> = None;
+        loop {
+            if pruned_at.is_none_or(|at| at.elapsed() >= PRUNE_INTERVAL) {
+                if let Err(err) = prune(&pg).await {
+                    error!("Fail to delete published todo events {:?}", err);
+                }
+                pruned_at = Some(Instant::now());
+            }
+            if listener.is_none() {
+                listener = listen(&pg)
+                    .await
+                    .map_err(|err| warn!("Fail to listen for todo events {:?}", err))
+                    .ok();
+            }
+            loop {
+                match self.publish(&pg).await {
+                    Ok(published) if published < BATCH as usize => break,
+                    Ok(_) => {}
+                    Err(err) => {
+                        error!("Fail to relay todo events {:?}", err);
+                        break;
+                    }
+                }
+            }
+            let Some(notifications) = listener.as_mut() else {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            };
+            tokio::select! {
+                notification = notifications.recv() => {
+                    if let Err(err) = notification {
+                        warn!("Lost the todo events' notifications {:?}", err);
+                        listener = None;
+                    }
+                }
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Publishes up to [`BATCH`] unpublished events, returning how many. They
+    /// are marked published, and their webhooks' deliveries queued, in the
+    /// transaction locking them for it, which rolls back if the server dies
+    /// before committing, leaving them to be published again. With a broker,
+    /// they are only marked published once it has them.
+    async fn publish(&self, pg: &PgPool) -> anyhow::Result<usize> {
+        let mut tx = pg.begin().await?;
+        let rows = sqlx::query_as!(
+            Recorded,
+            r#"select id, user_id, payload::text as "payload!" from "todo_outbox"
+        where published_at is null
+        order by id
+        limit $1
+        for update skip locked"#,
+            BATCH,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        let ids: Vec<i64> = rows.iter().map(|row| row.id).collect();
+        sqlx::query!(
+            r#"update "todo_outbox" set published_at = now() where id = any($1)"#,
+            &ids,
+        )
+        .execute(&mut tx)
+        .await?;
+        let queued = self.webhooks.enqueue(&mut tx, &ids).await?;
+        if let Some(broker) = &self.broker {
+            broker.publish(&rows).await?;
+        }
+        tx.commit().await?;
+        if queued {
+            self.webhooks.wake();
+        }
+        for row in &rows {
+            if let Err(err) = self.events.relay(row.user_id, &row.payload) {
+                error!(id = row.id, "Fail to read recorded todo event {:?}", err);
+            }
+        }
+        Ok(rows.len())
+    }
+}
+
+async fn listen(pg: &PgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pg).await?;
+    listener.listen(CHANNEL).await?;
+    Ok(listener)
+}
+
+async fn prune(pg: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"delete from "todo_outbox"
+        where published_at < now() - make_interval(secs => $1)"#,
+        KEPT_FOR.as_secs_f64(),
+    )
+    .execute(pg)
+    .await?;
+    Ok(())
+}
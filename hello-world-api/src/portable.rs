@@ -0,0 +1,532 @@
+//! A user's todos as a file of their own. `GET /todos/export?format=` sends
+//! all of them as CSV or JSON, page by page as they are read, and
+//! `POST /todos/import` adds the todos of such a file, say one exported from
+//! another account or edited in a spreadsheet.
+//!
+//! Importing is idempotent: a todo whose text the user already has, or had
+//! earlier in the file, is skipped rather than duplicated. Each record is
+//! checked like the body of `POST /todos` and fails on its own; the answer
+//! counts the inserted, skipped and failed ones.
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::error;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{check_text, Json, Query, Validate},
+    models::{CreateTodo, Priority},
+    quota::Quota,
+    repository::{NewTodo, RepositoryError, Todos},
+    tags::MAX_TAG_CHARS,
+    tx::Tx,
+};
+
+/// Todos read from the database for each chunk of an export.
+const PAGE_SIZE: i64 = 500;
+
+/// Most todos one import adds.
+pub const MAX_IMPORT_TODOS: usize = 10_000;
+
+/// Largest file `POST /todos/import` accepts.
+pub const MAX_IMPORT_BYTES: usize = 16 * 1024 * 1024;
+
+/// The CSV columns, in the order of [`CsvRecord`]'s fields.
+const CSV_HEADER: [&str; 11] = [
+    "id",
+    "text",
+    "is_done",
+    "completed_at",
+    "start_at",
+    "due_at",
+    "expires_at",
+    "list_id",
+    "priority",
+    "recurrence",
+    "tags",
+];
+
+#[derive(Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    Csv,
+    #[default]
+    Json,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportTodos {
+    /// `json` (default) or `csv`.
+    #[serde(default)]
+    format: FileFormat,
+}
+
+/// A todo in an exported file. Importing one only needs its `text`, and
+/// ignores its `id` and `completed_at`: the todo gets a new id, and if done,
+/// is taken to be done at the time of the import.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TodoRecord {
+    id: Option<uuid::Uuid>,
+    text: String,
+    #[serde(default)]
+    is_done: bool,
+    completed_at: Option<DateTime<Utc>>,
+    start_at: Option<DateTime<Utc>>,
+    due_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    /// One of the user's lists; an import into another account has to leave
+    /// it out.
+    list_id: Option<uuid::Uuid>,
+    priority: Option<Priority>,
+    recurrence: Option<String>,
+    /// Names of its tags, created on import where the user lacks them.
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// A [`TodoRecord`] as a CSV row, its tags separated by commas. Every column
+/// but `text` may be left out or empty.
+#[derive(Serialize, Deserialize)]
+struct CsvRecord {
+    id: Option<uuid::Uuid>,
+    text: String,
+    is_done: Option<bool>,
+    completed_at: Option<DateTime<Utc>>,
+    start_at: Option<DateTime<Utc>>,
+    due_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    list_id: Option<uuid::Uuid>,
+    priority: Option<Priority>,
+    recurrence: Option<String>,
+    tags: Option<String>,
+}
+
+impl From<&TodoRecord> for CsvRecord {
+    fn from(todo: &TodoRecord) -> Self {
+        CsvRecord {
+            id: todo.id,
+            text: todo.text.clone(),
+            is_done: Some(todo.is_done),
+            completed_at: todo.completed_at,
+            start_at: todo.start_at,
+            due_at: todo.due_at,
+            expires_at: todo.expires_at,
+            list_id: todo.list_id,
+            priority: todo.priority,
+            recurrence: todo.recurrence.clone(),
+            tags: Some(todo.tags.join(", ")),
+        }
+    }
+}
+
+impl From<CsvRecord> for TodoRecord {
+    fn from(row: CsvRecord) -> Self {
+        TodoRecord {
+            id: row.id,
+            text: row.text,
+            is_done: row.is_done.unwrap_or_default(),
+            completed_at: row.completed_at,
+            start_at: row.start_at,
+            due_at: row.due_at,
+            expires_at: row.expires_at,
+            list_id: row.list_id,
+            priority: row.priority,
+            recurrence: row.recurrence,
+            // a tag with a comma in its name comes back as several
+            tags: row
+                .tags
+                .iter()
+                .flat_map(|tags| tags.split(','))
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        }
+    }
+}
+
+/// Downloads all of the user's todos, oldest first, without the deleted
+/// ones. The file is read page by page as it is sent, so a todo changed
+/// meanwhile may be exported as it was before or after the change.
+#[utoipa::path(
+    get,
+    path = "/todos/export",
+    tag = "todos",
+    params(
+        ExportTodos,
+    ),
+    responses(
+        (status = 200, description = "A `TodoRecord` per todo, or a CSV row with the same columns, its tags separated by commas", body = Vec<TodoRecord>),
+        (status = 400, description = "Unknown format", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn export(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<ExportTodos>,
+) -> axum::response::Response {
+    // the first page is read up front, to answer a failure with an error
+    let first = match page(&pg, user_id, None).await {
+        Ok(first) => first,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let (content_type, filename) = match params.format {
+        FileFormat::Csv => ("text/csv; charset=utf-8", "todos.csv"),
+        FileFormat::Json => ("application/json", "todos.json"),
+    };
+    let body = StreamBody::new(chunks(pg.0, user_id, params.format, first));
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// The file, a chunk per page of todos, starting with `first`.
+fn chunks(
+    pg: PgPool,
+    user_id: uuid::Uuid,
+    format: FileFormat,
+    first: Vec<TodoRecord>,
+) -> impl Stream<Item = Result<Bytes, sqlx::Error>> {
+    let encoder = Encoder {
+        format,
+        written: 0,
+        last_id: None,
+    };
+    let state = (pg, encoder, Some(first), false);
+    stream::unfold(state, move |(pg, mut encoder, next, finished)| async move {
+        if finished {
+            return None;
+        }
+        let todos = match next {
+            Some(todos) => todos,
+            None => match page(&pg, user_id, encoder.last_id).await {
+                Ok(todos) => todos,
+                Err(err) => {
+                    error!(%user_id, "Fail to export todos {:?}", err);
+                    return Some((Err(err), (pg, encoder, None, true)));
+                }
+            },
+        };
+        let last = (todos.len() as i64) < PAGE_SIZE;
+        let chunk = encoder.encode(&todos, last);
+        Some((Ok(chunk), (pg, encoder, None, last)))
+    })
+}
+
+/// Writes the todos of an export, a page at a time.
+struct Encoder {
+    format: FileFormat,
+    written: usize,
+    last_id: Option<uuid::Uuid>,
+}
+
+impl Encoder {
+    /// The page as part of the file, with the file's start if it is the
+    /// first written and its end if `last`.
+    fn encode(&mut self, todos: &[TodoRecord], last: bool) -> Bytes {
+        let mut chunk = Vec::new();
+        match self.format {
+            FileFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(&mut chunk);
+                if self.written == 0 {
+                    writer
+                        .write_record(CSV_HEADER)
+                        .expect("writing to a Vec succeeds");
+                }
+                for todo in todos {
+                    writer
+                        .serialize(CsvRecord::from(todo))
+                        .expect("a todo serializes to a CSV row");
+                }
+                writer.flush().expect("writing to a Vec succeeds");
+            }
+            FileFormat::Json => {
+                for (i, todo) in todos.iter().enumerate() {
+                    chunk.push(match self.written + i {
+                        0 => b'[',
+                        _ => b',',
+                    });
+                    serde_json::to_writer(&mut chunk, todo).expect("a todo serializes to JSON");
+                }
+                if last {
+                    chunk.extend_from_slice(match self.written + todos.len() {
+                        0 => b"[]\n",
+                        _ => b"]\n",
+                    });
+                }
+            }
+        }
+        self.written += todos.len();
+        self.last_id = todos.last().and_then(|todo| todo.id).or(self.last_id);
+        Bytes::from(chunk)
+    }
+}
+
+/// The user's next [`PAGE_SIZE`] live todos after the one with id `after`.
+async fn page(
+    pg: &PgPool,
+    user_id: uuid::Uuid,
+    after: Option<uuid::Uuid>,
+) -> Result<Vec<TodoRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        TodoRecord,
+        r#"select t.id as "id?", t.todo_text as text, t.is_done, t.completed_at, t.start_at,
+            t.due_at, t.expires_at, t.list_id, t.priority as "priority: Priority", t.recurrence,
+            array(
+                select g.name from "todo_tag" tt join "tag" g on g.id = tt.tag_id
+                where tt.todo_id = t.id
+                order by g.name
+            ) as "tags!"
+        from "todo" t
+        where t.user_id = $1 and t.merged_into is null and t.deleted_at is null
+            and ($2::uuid is null or t.id > $2)
+        order by t.id
+        limit $3"#,
+        user_id,
+        after,
+        PAGE_SIZE,
+    )
+    .fetch_all(pg)
+    .await
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TodoImportSummary {
+    inserted: usize,
+    /// Records whose text the user already had as a todo.
+    skipped: usize,
+    failed: usize,
+    /// Why each failed record did, in file order.
+    errors: Vec<FailedRecord>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FailedRecord {
+    /// Its position in the file, from 1, not counting the CSV header.
+    row: usize,
+    reason: String,
+}
+
+/// Adds the todos of a file as `GET /todos/export` sends, in one
+/// transaction. The format is told by the `Content-Type`, `text/csv` or
+/// `application/json`.
+#[utoipa::path(
+    post,
+    path = "/todos/import",
+    tag = "todos",
+    request_body(content = Vec<TodoRecord>, description = "An exported file, each record having at least a `text`; or the same as `text/csv`"),
+    responses(
+        (status = 200, description = "How many todos were inserted, skipped as duplicates and failed", body = TodoImportSummary),
+        (status = 400, description = "Not a CSV file or JSON array", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The todos would exceed the open-todo quota", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 413, description = "The file is larger than 16 MiB"),
+        (status = 415, description = "Neither CSV nor JSON", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "More than 10000 records", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn import(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(quota): Extension<Option<Quota>>,
+    Extension(events): Extension<Events>,
+    mut tx: Tx,
+    headers: HeaderMap,
+    body: Bytes,
+) -> axum::response::Response {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let records = if content_type.starts_with("text/csv") {
+        read_csv(&body)
+    } else if content_type.starts_with("application/json") {
+        read_json(&body)
+    } else {
+        return ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected a text/csv or application/json file",
+        )
+        .into_response();
+    };
+    let records = match records {
+        Ok(records) if records.len() > MAX_IMPORT_TODOS => {
+            return ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("A file can have at most {MAX_IMPORT_TODOS} todos"),
+            )
+            .into_response()
+        }
+        Ok(records) => records,
+        Err(err) => return err.into_response(),
+    };
+
+    let todos = tx.todos(&todos);
+    let checked: Vec<_> = records
+        .iter()
+        .map(|record| record.as_ref().map_err(Clone::clone).and_then(check))
+        .collect();
+    let new_todos: Vec<_> = checked
+        .iter()
+        .filter_map(|todo| todo.as_ref().ok().copied())
+        .collect();
+    if let Some(quota) = quota {
+        if let Err(err) = quota
+            .check_create_many(&*todos, user_id, new_todos.len())
+            .await
+        {
+            return err.into_response();
+        }
+    }
+    let mut inserted = match todos.insert_many(user_id, &new_todos).await {
+        Ok(inserted) => inserted.into_iter(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+
+    let mut summary = TodoImportSummary {
+        inserted: 0,
+        skipped: 0,
+        failed: 0,
+        errors: Vec::new(),
+    };
+    let mut created = Vec::new();
+    for (i, (record, todo)) in records.iter().zip(checked).enumerate() {
+        let result = match todo {
+            Ok(_) => inserted.next().expect("a result per valid todo"),
+            Err(reason) => {
+                summary.failed += 1;
+                summary.errors.push(FailedRecord { row: i + 1, reason });
+                continue;
+            }
+        };
+        let mut todo = match result {
+            Ok(todo) => todo,
+            Err(RepositoryError::Duplicate) => {
+                summary.skipped += 1;
+                continue;
+            }
+            Err(RepositoryError::NoSuchList) => {
+                summary.failed += 1;
+                summary.errors.push(FailedRecord {
+                    row: i + 1,
+                    reason: "list_id: is not one of your lists".to_owned(),
+                });
+                continue;
+            }
+            Err(err) => return ApiError::from(err).into_response(),
+        };
+        let record = record.as_ref().expect("only valid records are inserted");
+        if record.is_done {
+            todo = match todos.set_done(user_id, todo.id, true, None).await {
+                Ok(todo) => todo,
+                Err(err) => return ApiError::from(err).into_response(),
+            };
+        }
+        if !record.tags.is_empty() {
+            todo = match todos.add_tags(user_id, todo.id, &record.tags).await {
+                Ok(todo) => todo,
+                Err(err) => return ApiError::from(err).into_response(),
+            };
+        }
+        created.push(todo);
+        summary.inserted += 1;
+    }
+    drop(todos);
+    for todo in &created {
+        if let Err(err) = events.created(&mut tx, user_id, todo).await {
+            return ApiError::from(err).into_response();
+        }
+    }
+    (StatusCode::OK, Json(summary)).into_response()
+}
+
+type Records = Vec<Result<TodoRecord, String>>;
+
+/// Each row of a CSV file with a header, or why it isn't a todo.
+fn read_csv(body: &[u8]) -> Result<Records, ApiError> {
+    let mut reader = csv::Reader::from_reader(body);
+    if let Err(err) = reader.headers() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid CSV file: {err}"),
+        ));
+    }
+    Ok(reader
+        .deserialize::<CsvRecord>()
+        .map(|row| row.map(TodoRecord::from).map_err(|err| err.to_string()))
+        .collect())
+}
+
+/// Each element of a JSON array, or why it isn't a todo.
+fn read_json(body: &[u8]) -> Result<Records, ApiError> {
+    let values: Vec<serde_json::Value> = serde_json::from_slice(body).map_err(|err| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Expected a JSON array of todos: {err}"),
+        )
+    })?;
+    Ok(values
+        .into_iter()
+        .map(|value| serde_json::from_value(value).map_err(|err| err.to_string()))
+        .collect())
+}
+
+/// The todo to insert for `record`, or why it is invalid.
+fn check(record: &TodoRecord) -> Result<NewTodo<'_>, String> {
+    let create = CreateTodo {
+        text: record.text.clone(),
+        start_at: record.start_at,
+        due_at: record.due_at,
+        expires_at: record.expires_at,
+        list_id: record.list_id,
+        priority: record.priority,
+        recurrence: record.recurrence.clone(),
+    };
+    let fields: Vec<_> = create
+        .validate()
+        .into_iter()
+        .chain(
+            record
+                .tags
+                .iter()
+                .filter_map(|name| check_text("tags", name, MAX_TAG_CHARS)),
+        )
+        .map(|field| format!("{}: {}", field.field, field.reason))
+        .collect();
+    if !fields.is_empty() {
+        return Err(fields.join("; "));
+    }
+    Ok(NewTodo {
+        text: record.text.trim(),
+        start_at: record.start_at,
+        due_at: record.due_at,
+        expires_at: record.expires_at,
+        list_id: record.list_id,
+        priority: record.priority,
+        recurrence: record.recurrence.as_deref().map(str::trim),
+    })
+}
@@ -0,0 +1,107 @@
+//! Purging of deleted todos. A deleted todo stays in the table, out of
+//! sight, until it has been deleted for `PURGE_DELETED_AFTER_DAYS`; a job
+//! running every `PURGE_CHECK_SECS` then removes it for good, with its tags,
+//! links, share links, checklist, history and attachments, and the todos
+//! merged into it. Until then it is in the user's trash, from which it can
+//! be restored.
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+use crate::{attachments::storage::Storage, jobs::Job};
+
+/// Most deleted todos removed in one statement, besides those merged into
+/// them; the job goes on with the next ones right away.
+const BATCH_SIZE: i64 = 1000;
+
+/// How long deleted todos are kept, `PURGE_DELETED_AFTER_DAYS`: the trash
+/// only lists and restores the todos deleted since.
+#[derive(Clone, Copy)]
+pub struct Retention(pub Duration);
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention(Duration::from_secs(30 * 86400))
+    }
+}
+
+pub struct PurgeDeleted {
+    /// How long todos stay deleted before they are removed.
+    pub after: Duration,
+    /// Where the removed todos' attachments are.
+    pub attachments: Arc<dyn Storage>,
+}
+
+#[async_trait]
+impl Job for PurgeDeleted {
+    fn name(&self) -> &'static str {
+        "purge_deleted_todos"
+    }
+
+    async fn run(&self, pg: &PgPool) -> anyhow::Result<i64> {
+        let mut purged = 0;
+        loop {
+            let batch = purge(pg, self.after).await?;
+            purged += batch.removed;
+            // the rows went with their todos, a blob left behind is only
+            // wasted space
+            for key in &batch.storage_keys {
+                if let Err(err) = self.attachments.delete(key).await {
+                    warn!("Fail to delete attachment {key}: {:?}", err);
+                }
+            }
+            if batch.deleted < BATCH_SIZE {
+                break;
+            }
+        }
+        if purged > 0 {
+            info!("Purged {purged} deleted todos");
+        }
+        Ok(purged)
+    }
+}
+
+struct Batch {
+    /// Deleted todos past their time.
+    deleted: i64,
+    /// Those and the todos merged into them.
+    removed: i64,
+    /// Of the attachments of the removed todos.
+    storage_keys: Vec<String>,
+}
+
+/// Removes a batch of the todos deleted longer ago than `after`. Todos
+/// merged into them, which point at them, go in the same statement.
+async fn purge(pg: &PgPool, after: Duration) -> Result<Batch, sqlx::Error> {
+    let batch = sqlx::query!(
+        r#"with recursive purged as (
+            (
+                select id, true as deleted from "todo"
+                where deleted_at < now() - make_interval(secs => $1)
+                limit $2
+            )
+            union
+            select t.id, false from "todo" t join purged p on t.merged_into = p.id
+        ), removed as (
+            delete from "todo" where id in (select id from purged)
+            returning id
+        )
+        select (select count(*) from purged where deleted) as "deleted!",
+            (select count(*) from removed) as "removed!",
+            array(
+                select storage_key from "attachment" where todo_id in (select id from purged)
+            ) as "storage_keys!""#,
+        after.as_secs_f64(),
+        BATCH_SIZE,
+    )
+    .fetch_one(pg)
+    .await?;
+    Ok(Batch {
+        deleted: batch.deleted,
+        removed: batch.removed,
+        storage_keys: batch.storage_keys,
+    })
+}
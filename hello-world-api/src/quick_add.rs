@@ -0,0 +1,317 @@
+//! Parser for quick-add lines such as `pay rent tomorrow 5pm #finance !high`.
+//!
+//! Recognised tokens are removed from the text:
+//! - `#tag` adds a tag,
+//! - `!low`, `!medium`, `!high`, `!urgent` (or `!1`..`!4`) set the priority,
+//! - `today`, `tonight`, `tomorrow`, weekday names (optionally after `next`),
+//!   `in N days|weeks` and ISO dates (`2024-05-01`) set the due day,
+//! - `5pm`, `5:30pm`, `17:00`, optionally after `at`, set the due time.
+//!
+//! Times are interpreted in UTC; a due day without a time is due at 09:00.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use serde::Serialize;
+
+use crate::models::Priority;
+
+#[derive(Serialize, PartialEq, Eq, Debug)]
+pub struct ParsedTodo {
+    pub text: String,
+    pub due_at: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub priority: Option<Priority>,
+}
+
+/// Parses `input` relative to `now`.
+pub fn parse(input: &str, now: DateTime<Utc>) -> ParsedTodo {
+    let tokens: Vec<&str> = input.split_whitespace().collect();
+    let today = now.date_naive();
+    let mut words = Vec::new();
+    let mut tags = Vec::new();
+    let mut priority = None;
+    let mut day = None;
+    let mut time = None;
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        let lower = token.to_lowercase();
+        let next = tokens.get(i + 1).map(|t| t.to_lowercase());
+
+        if let Some(tag) = token.strip_prefix('#').filter(|tag| !tag.is_empty()) {
+            tags.push(tag.to_lowercase());
+        } else if let Some(p) = lower.strip_prefix('!').and_then(parse_priority) {
+            priority = Some(p);
+        } else if let Some(d) = parse_day(&lower, today) {
+            if lower == "tonight" && time.is_none() {
+                time = NaiveTime::from_hms_opt(20, 0, 0);
+            }
+            day = Some(d);
+        } else if let Some(weekday) = next
+            .as_deref()
+            .and_then(parse_weekday)
+            .filter(|_| lower == "next")
+        {
+            day = Some(next_weekday(today, weekday) + Duration::days(7));
+            i += 1;
+        } else if lower == "in" && tokens.len() > i + 2 {
+            match parse_offset(&tokens[i + 1..i + 3]) {
+                Some(offset) => {
+                    day = Some(today + offset);
+                    i += 2;
+                }
+                None => words.push(token),
+            }
+        } else if let Some(t) = next
+            .as_deref()
+            .and_then(parse_time)
+            .filter(|_| lower == "at")
+        {
+            time = Some(t);
+            i += 1;
+        } else if let Some(t) = parse_time(&lower) {
+            time = Some(t);
+        } else {
+            words.push(token);
+        }
+        i += 1;
+    }
+
+    let due_at = match (day, time) {
+        (None, None) => None,
+        (day, time) => {
+            let time = time.unwrap_or_else(|| NaiveTime::from_hms_opt(9, 0, 0).unwrap());
+            // a bare time that already passed today means tomorrow
+            let day = day.unwrap_or(if time <= now.time() {
+                today + Duration::days(1)
+            } else {
+                today
+            });
+            Some(Utc.from_utc_datetime(&day.and_time(time)))
+        }
+    };
+
+    ParsedTodo {
+        text: words.join(" "),
+        due_at,
+        tags,
+        priority,
+    }
+}
+
+fn parse_priority(value: &str) -> Option<Priority> {
+    match value {
+        "low" | "4" => Some(Priority::Low),
+        "medium" | "3" => Some(Priority::Medium),
+        "high" | "2" => Some(Priority::High),
+        "urgent" | "1" => Some(Priority::Urgent),
+        _ => None,
+    }
+}
+
+fn parse_day(value: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match value {
+        "today" | "tonight" => Some(today),
+        "tomorrow" => Some(today + Duration::days(1)),
+        _ => parse_weekday(value)
+            .map(|weekday| next_weekday(today, weekday))
+            .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()),
+    }
+}
+
+pub(crate) fn parse_weekday(value: &str) -> Option<Weekday> {
+    match value {
+        "mon" | "monday" => Some(Weekday::Mon),
+        "tue" | "tuesday" => Some(Weekday::Tue),
+        "wed" | "wednesday" => Some(Weekday::Wed),
+        "thu" | "thursday" => Some(Weekday::Thu),
+        "fri" | "friday" => Some(Weekday::Fri),
+        "sat" | "saturday" => Some(Weekday::Sat),
+        "sun" | "sunday" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// The first `weekday` strictly after `today`.
+fn next_weekday(today: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let ahead = (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+    today + Duration::days(if ahead == 0 { 7 } else { ahead.into() })
+}
+
+/// `["3", "days"]` style offsets following `in`.
+fn parse_offset(tokens: &[&str]) -> Option<Duration> {
+    let count: i64 = tokens[0]
+        .parse()
+        .ok()
+        .filter(|count| (1..=365).contains(count))?;
+    match tokens[1].to_lowercase().as_str() {
+        "day" | "days" => Some(Duration::days(count)),
+        "week" | "weeks" => Some(Duration::weeks(count)),
+        _ => None,
+    }
+}
+
+/// `5pm`, `5:30pm`, `12am` and 24-hour `17:00`.
+pub(crate) fn parse_time(value: &str) -> Option<NaiveTime> {
+    let (clock, meridiem) = match value.strip_suffix("am") {
+        Some(clock) => (clock, Some(false)),
+        None => match value.strip_suffix("pm") {
+            Some(clock) => (clock, Some(true)),
+            None => (value, None),
+        },
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) => (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?),
+        // a bare number is only a time with am/pm, "buy 2 apples" is not
+        None if meridiem.is_some() => (clock.parse::<u32>().ok()?, 0),
+        None => return None,
+    };
+    let hour = match meridiem {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some(pm) => hour % 12 + if pm { 12 } else { 0 },
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Wednesday at noon.
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 14, 12, 0, 0).unwrap()
+    }
+
+    fn due(input: &str) -> Option<DateTime<Utc>> {
+        parse(input, now()).due_at
+    }
+
+    fn at(month: u32, day: u32, hour: u32, minute: u32) -> Option<DateTime<Utc>> {
+        Some(
+            Utc.with_ymd_and_hms(2026, month, day, hour, minute, 0)
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn takes_every_kind_of_token_out_of_the_text() {
+        assert_eq!(
+            parse("pay rent tomorrow 5pm #finance !high", now()),
+            ParsedTodo {
+                text: "pay rent".to_owned(),
+                due_at: at(10, 15, 17, 0),
+                tags: vec!["finance".to_owned()],
+                priority: Some(Priority::High),
+            }
+        );
+        assert_eq!(
+            parse("#Home Buy #errands milk", now()),
+            ParsedTodo {
+                text: "Buy milk".to_owned(),
+                due_at: None,
+                tags: vec!["home".to_owned(), "errands".to_owned()],
+                priority: None,
+            }
+        );
+    }
+
+    #[test]
+    fn days() {
+        assert_eq!(due("today"), at(10, 14, 9, 0));
+        assert_eq!(due("tonight"), at(10, 14, 20, 0));
+        assert_eq!(due("tonight at 11pm"), at(10, 14, 23, 0));
+        assert_eq!(due("Tomorrow"), at(10, 15, 9, 0));
+        assert_eq!(due("fri"), at(10, 16, 9, 0));
+        // the next one, never today
+        assert_eq!(due("wednesday"), at(10, 21, 9, 0));
+        assert_eq!(due("next monday"), at(10, 26, 9, 0));
+        assert_eq!(due("in 3 days"), at(10, 17, 9, 0));
+        assert_eq!(due("in 1 week"), at(10, 21, 9, 0));
+        assert_eq!(
+            due("2027-01-05"),
+            Some(Utc.with_ymd_and_hms(2027, 1, 5, 9, 0, 0).unwrap())
+        );
+        // the last one wins
+        assert_eq!(due("today tomorrow"), at(10, 15, 9, 0));
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(due("friday at 5:30pm"), at(10, 16, 17, 30));
+        assert_eq!(due("friday 17:05"), at(10, 16, 17, 5));
+        assert_eq!(due("friday 12am"), at(10, 16, 0, 0));
+        // a bare time is today's if it is still ahead, else tomorrow's
+        assert_eq!(due("5pm"), at(10, 14, 17, 0));
+        assert_eq!(due("8am"), at(10, 15, 8, 0));
+        assert_eq!(due("12pm"), at(10, 15, 12, 0));
+    }
+
+    #[test]
+    fn priorities() {
+        for (token, priority) in [
+            ("!low", Priority::Low),
+            ("!4", Priority::Low),
+            ("!medium", Priority::Medium),
+            ("!3", Priority::Medium),
+            ("!HIGH", Priority::High),
+            ("!2", Priority::High),
+            ("!urgent", Priority::Urgent),
+            ("!1", Priority::Urgent),
+        ] {
+            let parsed = parse(&format!("call bank {token}"), now());
+            assert_eq!(parsed.priority, Some(priority), "{token}");
+            assert_eq!(parsed.text, "call bank", "{token}");
+        }
+        assert_eq!(parse("!1 !low", now()).priority, Some(Priority::Low));
+    }
+
+    #[test]
+    fn what_isnt_a_token_stays_in_the_text() {
+        for input in [
+            "buy 2 apples",
+            "in 400 days",
+            "in 0 days",
+            "in 3 months",
+            "in 3",
+            "meet at noon",
+            "at",
+            "13pm",
+            "0am",
+            "25:00",
+            "12:60",
+            "2026-02-30",
+            "# and !",
+            "!extreme",
+            "!5",
+            "next week",
+            "next",
+        ] {
+            let parsed = parse(input, now());
+            assert_eq!(
+                parsed,
+                ParsedTodo {
+                    text: input.to_owned(),
+                    due_at: None,
+                    tags: Vec::new(),
+                    priority: None,
+                },
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn leftover_words_are_joined_by_single_spaces() {
+        let parsed = parse("  water   the\tplants  tomorrow  ", now());
+        assert_eq!(parsed.text, "water the plants");
+        assert_eq!(parsed.due_at, at(10, 15, 9, 0));
+        let parsed = parse("#garden !low tomorrow", now());
+        assert_eq!(parsed.text, "");
+        assert_eq!(parse("", now()).text, "");
+        // what is after a token that failed is still read
+        let parsed = parse("in 3 months friday", now());
+        assert_eq!(parsed.text, "in 3 months");
+        assert_eq!(parsed.due_at, at(10, 16, 9, 0));
+    }
+}
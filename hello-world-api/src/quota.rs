@@ -0,0 +1,131 @@
+//! Per-user limit on open todos (`MAX_OPEN_TODOS`). Creating a todo past it
+//! fails with `403`. Once a user has `QUOTA_WARNING_PERCENT` of it open, the
+//! todo listings and creations answer with an `X-Warning` header and a
+//! `warnings` array, so clients can prompt for cleanup before that happens.
+//!
+//! Only `POST /todos`, `POST /todos/quick` and `POST /todos/bulk` are
+//! limited; imports and the integrations keep creating todos past the quota.
+
+use axum::{
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{
+    error::{ApiError, ErrorCode},
+    repository::{todo_query::TodoQuery, TodoRepository},
+};
+
+const X_WARNING: HeaderName = HeaderName::from_static("x-warning");
+
+#[derive(Clone, Copy)]
+pub struct Quota {
+    max_open: u32,
+    /// Open todos from which on responses carry a warning.
+    warn_at: u32,
+}
+
+#[derive(Serialize)]
+pub struct Warning {
+    code: &'static str,
+    message: String,
+}
+
+/// A response body with the warnings about it merged in.
+#[derive(Serialize)]
+struct Warned<T> {
+    #[serde(flatten)]
+    body: T,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<Warning>,
+}
+
+impl Quota {
+    pub fn new(max_open: u32, warning_percent: u8) -> Self {
+        Quota {
+            max_open,
+            warn_at: (max_open * u32::from(warning_percent)).div_ceil(100),
+        }
+    }
+
+    /// Fails with `403` if `user_id` can't open another todo.
+    pub async fn check_create(
+        self,
+        repository: &dyn TodoRepository,
+        user_id: uuid::Uuid,
+    ) -> Result<(), ApiError> {
+        self.check_create_many(repository, user_id, 1).await
+    }
+
+    /// Fails with `403` if `user_id` can't open `count` more todos.
+    pub async fn check_create_many(
+        self,
+        repository: &dyn TodoRepository,
+        user_id: uuid::Uuid,
+        count: usize,
+    ) -> Result<(), ApiError> {
+        let open = open_todos(repository, user_id).await?;
+        if open.saturating_add(count as i64) <= i64::from(self.max_open) {
+            return Ok(());
+        }
+        Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!(
+                "Quota of {} open todos reached, complete or delete some first",
+                self.max_open
+            ),
+        )
+        .with_code(ErrorCode::QuotaExceeded))
+    }
+
+    fn warnings(self, open: i64) -> Vec<Warning> {
+        if open < i64::from(self.warn_at) {
+            return Vec::new();
+        }
+        vec![Warning {
+            code: "open_todo_quota",
+            message: format!("{open} of {} allowed open todos are in use", self.max_open),
+        }]
+    }
+}
+
+/// Warnings for `user_id`'s current usage, none without a quota.
+pub async fn warnings(
+    quota: Option<Quota>,
+    repository: &dyn TodoRepository,
+    user_id: uuid::Uuid,
+) -> Result<Vec<Warning>, ApiError> {
+    let Some(quota) = quota else {
+        return Ok(Vec::new());
+    };
+    Ok(quota.warnings(open_todos(repository, user_id).await?))
+}
+
+/// `body` as JSON with `warnings` added to it, each also sent as an
+/// `X-Warning` header.
+pub fn respond(status: StatusCode, body: impl Serialize, warnings: Vec<Warning>) -> Response {
+    let mut headers = HeaderMap::new();
+    add_headers(&mut headers, &warnings);
+    (status, headers, Json(Warned { body, warnings })).into_response()
+}
+
+/// Sends each of `warnings` as an `X-Warning` header, for responses that
+/// can't carry them in their body.
+pub fn add_headers(headers: &mut HeaderMap, warnings: &[Warning]) {
+    for warning in warnings {
+        if let Ok(value) = HeaderValue::from_str(&warning.message) {
+            headers.append(X_WARNING, value);
+        }
+    }
+}
+
+async fn open_todos(repository: &dyn TodoRepository, user_id: uuid::Uuid) -> Result<i64, ApiError> {
+    let query = TodoQuery {
+        is_done: Some(false),
+        expired: Some(false),
+        ..TodoQuery::default()
+    };
+    Ok(repository.count(user_id, &query).await?)
+}
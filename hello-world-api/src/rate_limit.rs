@@ -0,0 +1,607 @@
+//! Per-client request rate limiting. With `RATE_LIMIT_PER_MINUTE` set, each
+//! client has a token bucket holding up to `RATE_LIMIT_BURST` requests and
+//! refilled at that rate; a request finding it empty is answered with a 429
+//! and a `Retry-After` of the seconds until the next token.
+//!
+//! Requests with a valid bearer token are counted against its user, wherever
+//! they come from, and those with an API key against the key once it has
+//! been found to be live: checking a key takes the database, which the
+//! limiter doesn't wait for, so a key's first request, and every request
+//! with a made-up key, counts against the client's address like the others. Behind reverse
+//! proxies that address is the peer's only when `TRUSTED_PROXY_HOPS` is 0:
+//! otherwise it is read from `X-Forwarded-For`, counting that many entries
+//! from the right, the ones appended by the trusted proxies. Entries further
+//! left were sent by the client and could be anything.
+//!
+//! Admins set other limits for a [workspace](crate::workspaces) with
+//! `PUT /admin/workspaces/{slug}/rate-limit`, which the users and API keys
+//! acting in it are counted against in buckets of their own. The limits are
+//! read from the database into [`WorkspaceLimits`] in the background, never
+//! while a request waits: until the first read, and for at most
+//! [`LIMITS_TTL`] after another instance changed them, the previous ones
+//! apply. The instance taking the change reads them again at once.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{self, Auth},
+    error::ApiError,
+    extract::{Json, Path},
+    workspaces,
+};
+
+/// How often buckets that have filled up again are forgotten.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the workspaces' limits are used before being read again.
+pub const LIMITS_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub struct RateLimiter(Arc<Limits>);
+
+struct Limits {
+    /// `RATE_LIMIT_PER_MINUTE` and `RATE_LIMIT_BURST`.
+    default: Limit,
+    workspaces: WorkspaceLimits,
+    proxy_hops: usize,
+    auth: Auth,
+    buckets: Mutex<Buckets>,
+}
+
+/// The size and refill rate of buckets.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Limit {
+    /// Tokens added to each bucket per second.
+    rate: f64,
+    /// Tokens a bucket holds at most, and starts with.
+    burst: f64,
+}
+
+impl Limit {
+    fn new(per_minute: u32, burst: u32) -> Self {
+        Limit {
+            rate: f64::from(per_minute) / 60.0,
+            burst: f64::from(burst),
+        }
+    }
+}
+
+struct Buckets {
+    /// By client and, for the workspaces with limits of their own, the
+    /// workspace the client acts in.
+    by_client: HashMap<(Client, Option<uuid::Uuid>), Bucket>,
+    /// Hashes of the API keys found live, forgotten with their bucket.
+    live_keys: HashSet<KeyHash>,
+    swept: Instant,
+}
+
+type KeyHash = [u8; 32];
+
+/// Who a request is counted against.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Client {
+    User(uuid::Uuid),
+    ApiKey(KeyHash),
+    /// `None` for peers without an address, such as on a Unix socket, which
+    /// share one bucket.
+    Ip(Option<IpAddr>),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    limit: Limit,
+}
+
+impl Bucket {
+    /// The tokens the bucket has by `now`.
+    fn refilled(&self, now: Instant) -> f64 {
+        let refilled = now.duration_since(self.updated).as_secs_f64() * self.limit.rate;
+        (self.tokens + refilled).min(self.limit.burst)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(
+        per_minute: u32,
+        burst: u32,
+        proxy_hops: usize,
+        auth: Auth,
+        workspaces: WorkspaceLimits,
+    ) -> Self {
+        RateLimiter(Arc::new(Limits {
+            default: Limit::new(per_minute, burst),
+            workspaces,
+            proxy_hops,
+            auth,
+            buckets: Mutex::new(Buckets {
+                by_client: HashMap::new(),
+                live_keys: HashSet::new(),
+                swept: Instant::now(),
+            }),
+        }))
+    }
+
+    /// Takes a token from the client's bucket, the one it has in
+    /// `workspace` if that has limits of its own, or answers how many
+    /// seconds until there is one.
+    fn take(&self, client: Client, workspace: Option<(uuid::Uuid, Limit)>) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.0.buckets.lock().unwrap();
+        if now.duration_since(buckets.swept) >= SWEEP_INTERVAL {
+            let Buckets {
+                by_client,
+                live_keys,
+                ..
+            } = &mut *buckets;
+            by_client.retain(|_, bucket| bucket.refilled(now) < bucket.limit.burst);
+            let kept: HashSet<KeyHash> = by_client
+                .keys()
+                .filter_map(|(client, _)| match client {
+                    Client::ApiKey(key) => Some(*key),
+                    _ => None,
+                })
+                .collect();
+            live_keys.retain(|key| kept.contains(key));
+            buckets.swept = now;
+        }
+        let (workspace_id, limit) = match workspace {
+            Some((id, limit)) => (Some(id), limit),
+            None => (None, self.0.default),
+        };
+        let bucket = buckets
+            .by_client
+            .entry((client, workspace_id))
+            .or_insert(Bucket {
+                tokens: limit.burst,
+                updated: now,
+                limit,
+            });
+        // changed by an admin since
+        if bucket.limit != limit {
+            bucket.tokens = bucket.tokens.min(limit.burst);
+            bucket.limit = limit;
+        }
+        bucket.tokens = bucket.refilled(now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / limit.rate).ceil() as u64)
+        }
+    }
+
+    fn client<B>(&self, req: &Request<B>) -> Client {
+        if let Some(user_id) = self.0.auth.bearer_user(req.headers()) {
+            return Client::User(user_id);
+        }
+        if let Some(key) = api_key_hash(req.headers()) {
+            if self.0.buckets.lock().unwrap().live_keys.contains(&key) {
+                return Client::ApiKey(key);
+            }
+        }
+        let peer = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Client::Ip(forwarded_for(req.headers(), self.0.proxy_hops).or(peer))
+    }
+
+    /// Remembers whether the API key a request was sent with is live, as
+    /// [`KeyCheck`] found, or forgets it.
+    fn learn(&self, key: KeyHash, live: bool) {
+        let mut buckets = self.0.buckets.lock().unwrap();
+        if live {
+            buckets.live_keys.insert(key);
+        } else {
+            buckets.live_keys.remove(&key);
+            buckets
+                .by_client
+                .retain(|(client, _), _| *client != Client::ApiKey(key));
+        }
+    }
+}
+
+/// Handed down with requests sent with an API key, for the authentication
+/// to tell the limiter whether the key is live.
+#[derive(Clone, Default)]
+pub struct KeyCheck(Arc<OnceLock<bool>>);
+
+impl KeyCheck {
+    pub fn found(&self, live: bool) {
+        let _ = self.0.set(live);
+    }
+}
+
+/// The hash of the request's API key, when it authenticates with one.
+fn api_key_hash(headers: &HeaderMap) -> Option<KeyHash> {
+    if !auth::uses_api_key(headers) {
+        return None;
+    }
+    let key = headers.get(auth::X_API_KEY)?.to_str().ok()?;
+    Some(Sha256::digest(key.trim().as_bytes()).into())
+}
+
+/// The address the outermost of `proxy_hops` trusted proxies saw the request
+/// coming from, `None` without proxies or if it isn't an address.
+fn forwarded_for(headers: &HeaderMap, proxy_hops: usize) -> Option<IpAddr> {
+    if proxy_hops == 0 {
+        return None;
+    }
+    // a header repeated by several proxies counts as one list, in order
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    // fewer entries than proxies means the leftmost came from one of them
+    let hop = hops.get(hops.len().saturating_sub(proxy_hops))?;
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+}
+
+/// Answers requests over the client's rate with a 429. The health probes and
+/// metrics scrapes, when served on the public listener, aren't counted.
+pub async fn limit<B>(
+    State(limiter): State<Option<RateLimiter>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(limiter) = limiter else {
+        return next.run(req).await;
+    };
+    if matches!(req.uri().path(), "/healthz" | "/readyz" | "/metrics") {
+        return next.run(req).await;
+    }
+    let key = api_key_hash(req.headers());
+    let client = limiter.client(&req);
+    // the address of anonymous requests is limited wherever they are sent
+    let workspace = match client {
+        Client::Ip(_) => None,
+        _ => limiter.0.workspaces.of(&req),
+    };
+    match limiter.take(client, workspace) {
+        Ok(()) => {
+            let Some(key) = key else {
+                return next.run(req).await;
+            };
+            let check = KeyCheck::default();
+            req.extensions_mut().insert(check.clone());
+            let response = next.run(req).await;
+            // routes that take no user don't check the key
+            if let Some(live) = check.0.get() {
+                limiter.learn(key, *live);
+            }
+            response
+        }
+        Err(retry_after) => ApiError {
+            retry_after: Some(retry_after),
+            ..ApiError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests, try again later",
+            )
+        }
+        .into_response(),
+    }
+}
+
+/// The limits of the workspaces that have their own, by slug, shared by
+/// the limiter and the admin endpoints changing them.
+#[derive(Clone, Default)]
+pub struct WorkspaceLimits(Arc<Cache>);
+
+#[derive(Default)]
+struct Cache {
+    /// `None` without a database, when no workspace has limits.
+    pool: Option<PgPool>,
+    /// `WORKSPACE_DOMAIN`.
+    domain: Option<String>,
+    state: Mutex<Cached>,
+}
+
+#[derive(Default)]
+struct Cached {
+    by_slug: HashMap<String, (uuid::Uuid, Limit)>,
+    /// When they were read, `None` until they are.
+    read_at: Option<Instant>,
+    reading: bool,
+    /// Bumped by every change, so a read started before one doesn't pass
+    /// for fresh.
+    generation: u64,
+}
+
+impl WorkspaceLimits {
+    pub fn new(pool: PgPool, domain: Option<String>) -> Self {
+        WorkspaceLimits(Arc::new(Cache {
+            pool: Some(pool),
+            domain,
+            state: Mutex::default(),
+        }))
+    }
+
+    /// The workspace `req` acts in and its limits, if it has its own.
+    fn of<B>(&self, req: &Request<B>) -> Option<(uuid::Uuid, Limit)> {
+        let slug = workspaces::named(req, self.0.domain.as_deref())?;
+        let mut state = self.0.state.lock().unwrap();
+        if state.read_at.is_none_or(|at| at.elapsed() >= LIMITS_TTL) {
+            self.read(&mut state);
+        }
+        state.by_slug.get(&slug).copied()
+    }
+
+    /// Has the limits read again after a change.
+    pub fn invalidate(&self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.generation += 1;
+        state.read_at = None;
+        self.read(&mut state);
+    }
+
+    /// Reads the limits in the background, unless they are being read.
+    fn read(&self, state: &mut Cached) {
+        let Some(pool) = self.0.pool.clone() else {
+            return;
+        };
+        if state.reading {
+            return;
+        }
+        state.reading = true;
+        let generation = state.generation;
+        let limits = self.clone();
+        tokio::spawn(async move {
+            let read = sqlx::query!(
+                r#"select w.slug, w.id, l.per_minute, l.burst
+                from "workspace_rate_limit" l join "workspace" w on w.id = l.workspace_id"#
+            )
+            .fetch_all(&pool)
+            .await;
+            let mut state = limits.0.state.lock().unwrap();
+            state.reading = false;
+            match read {
+                Ok(rows) => {
+                    state.by_slug = rows
+                        .into_iter()
+                        .map(|row| {
+                            let limit = Limit::new(row.per_minute as u32, row.burst as u32);
+                            (row.slug, (row.id, limit))
+                        })
+                        .collect();
+                    state.read_at = Some(Instant::now());
+                }
+                // the last ones read are kept until the next attempt
+                Err(err) => {
+                    warn!("Fail to read the workspaces' rate limits: {:?}", err);
+                    state.read_at = Some(Instant::now());
+                }
+            }
+            if state.generation != generation {
+                state.read_at = None;
+            }
+        });
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceRateLimit {
+    /// Requests a client may send per minute in the workspace.
+    per_minute: i32,
+    /// Requests a client may send at once, `per_minute` if left out.
+    burst: Option<i32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/workspaces/{slug}/rate-limit",
+    tag = "admin",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+    ),
+    responses(
+        (status = 200, description = "The workspace's limits", body = WorkspaceRateLimit),
+        (status = 404, description = "No such workspace, or it has the default limits", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn get_workspace(
+    pg: Extension<PgPool>,
+    Path(slug): Path<String>,
+) -> axum::response::Response {
+    let result = sqlx::query_as!(
+        WorkspaceRateLimit,
+        r#"select l.per_minute, l.burst as "burst?"
+        from "workspace_rate_limit" l join "workspace" w on w.id = l.workspace_id
+        where w.slug = $1"#,
+        slug,
+    )
+    .fetch_one(&*pg)
+    .await;
+    match result {
+        Ok(limit) => Json(limit).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Sets the limits of a workspace's clients, in place of the default ones.
+#[utoipa::path(
+    put,
+    path = "/admin/workspaces/{slug}/rate-limit",
+    tag = "admin",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+    ),
+    request_body = WorkspaceRateLimit,
+    responses(
+        (status = 200, description = "The workspace's new limits", body = WorkspaceRateLimit),
+        (status = 404, description = "No such workspace", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "A limit below 1, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn put_workspace(
+    pg: Extension<PgPool>,
+    Extension(limits): Extension<WorkspaceLimits>,
+    Path(slug): Path<String>,
+    Json(body): Json<WorkspaceRateLimit>,
+) -> axum::response::Response {
+    let burst = body.burst.unwrap_or(body.per_minute);
+    if body.per_minute < 1 || burst < 1 {
+        return ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "per_minute and burst must be at least 1",
+        )
+        .into_response();
+    }
+    let result = sqlx::query_as!(
+        WorkspaceRateLimit,
+        r#"insert into "workspace_rate_limit" (workspace_id, per_minute, burst)
+        select id, $2, $3 from "workspace" where slug = $1
+        on conflict (workspace_id)
+            do update set per_minute = excluded.per_minute, burst = excluded.burst,
+                updated_at = now()
+        returning per_minute, burst as "burst?""#,
+        slug,
+        body.per_minute,
+        burst,
+    )
+    .fetch_one(&*pg)
+    .await;
+    match result {
+        Ok(limit) => {
+            limits.invalidate();
+            info!(slug, limit.per_minute, burst, "Workspace rate limit set");
+            Json(limit).into_response()
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Has the workspace's clients limited as the others again.
+#[utoipa::path(
+    delete,
+    path = "/admin/workspaces/{slug}/rate-limit",
+    tag = "admin",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+    ),
+    responses(
+        (status = 204, description = "The default limits apply"),
+        (status = 404, description = "No such workspace, or it has the default limits", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn delete_workspace(
+    pg: Extension<PgPool>,
+    Extension(limits): Extension<WorkspaceLimits>,
+    Path(slug): Path<String>,
+) -> axum::response::Response {
+    let result = sqlx::query!(
+        r#"delete from "workspace_rate_limit" l using "workspace" w
+        where w.id = l.workspace_id and w.slug = $1"#,
+        slug,
+    )
+    .execute(&*pg)
+    .await;
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            ApiError::from(sqlx::Error::RowNotFound).into_response()
+        }
+        Ok(_) => {
+            limits.invalidate();
+            info!(slug, "Workspace rate limit removed");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One request a minute, from one address.
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(
+            1,
+            1,
+            0,
+            Auth::ephemeral(Duration::from_secs(60), false),
+            WorkspaceLimits::default(),
+        )
+    }
+
+    fn with_key(key: &str) -> Request<()> {
+        let mut req = Request::builder()
+            .header(auth::X_API_KEY, key)
+            .body(())
+            .unwrap();
+        let peer: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+        req.extensions_mut().insert(ConnectInfo(peer));
+        req
+    }
+
+    #[test]
+    fn live_api_keys_have_their_own_bucket() {
+        let limiter = limiter();
+        let (first, second) = (with_key("tdk_first"), with_key("tdk_second"));
+
+        // made up as far as the limiter knows, so counted against the address
+        assert!(limiter.take(limiter.client(&first), None).is_ok());
+        assert!(limiter.take(limiter.client(&second), None).is_err());
+
+        for req in [&first, &second] {
+            limiter.learn(api_key_hash(req.headers()).unwrap(), true);
+        }
+        assert!(matches!(limiter.client(&first), Client::ApiKey(_)));
+        assert!(limiter.take(limiter.client(&first), None).is_ok());
+        assert!(limiter.take(limiter.client(&second), None).is_ok());
+        assert!(limiter.take(limiter.client(&first), None).is_err());
+
+        // revoked
+        limiter.learn(api_key_hash(first.headers()).unwrap(), false);
+        assert!(matches!(limiter.client(&first), Client::Ip(Some(_))));
+    }
+
+    #[test]
+    fn workspaces_with_limits_have_buckets_of_their_own() {
+        let limiter = limiter();
+        let client = Client::Ip(None);
+        let workspace = (uuid::Uuid::from_u128(1), Limit::new(60, 2));
+        assert!(limiter.take(client, None).is_ok());
+        assert!(limiter.take(client, None).is_err());
+        assert!(limiter.take(client, Some(workspace)).is_ok());
+        assert!(limiter.take(client, Some(workspace)).is_ok());
+        assert_eq!(limiter.take(client, Some(workspace)), Err(1));
+
+        // lowered by an admin
+        let lowered = (workspace.0, Limit::new(1, 1));
+        assert_eq!(limiter.take(client, Some(lowered)), Err(60));
+    }
+
+    #[test]
+    fn bearer_tokens_come_before_api_keys() {
+        let mut req = with_key("tdk_first");
+        req.headers_mut()
+            .insert("authorization", "Bearer x".parse().unwrap());
+        assert_eq!(api_key_hash(req.headers()), None);
+    }
+}
@@ -0,0 +1,642 @@
+//! Opt-in capture of full request/response pairs for debugging a route or
+//! a user, kept in a fixed-size ring buffer and read back through
+//! `GET /debug/recordings`.
+//!
+//! Admins switch it on and off with `PUT /debug/recordings/settings`, for
+//! the requests to a path prefix, those acting as a user, or both; setting
+//! `RECORD_ROUTE` to a path prefix switches it on at startup. Every
+//! `sample`th matching request is recorded, and credentials in headers,
+//! query strings, JSON and form bodies are redacted before anything is
+//! stored.
+//!
+//! Bodies are only buffered when they are known to be at most
+//! [`MAX_BODY_BYTES`] long; longer and streamed ones, Server-Sent Events
+//! among them, are passed on as they come and recorded as
+//! [`NOT_RECORDED`]. WebSocket upgrades aren't recorded at all.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use anyhow::Context;
+use axum::{
+    body::{self, Body, Bytes, HttpBody},
+    extract::State,
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use http_body::Limited;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{error::ApiError, extract::Json};
+
+/// Bodies longer than this aren't buffered, nor stored.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// In place of the bodies that aren't buffered.
+const NOT_RECORDED: &str = "[not recorded]";
+
+const REDACTED: &str = "[redacted]";
+
+/// Header, query, JSON and form field names whose values are never stored,
+/// the new API keys' `key` among them.
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name == "key"
+        || [
+            "authorization",
+            "cookie",
+            "password",
+            "secret",
+            "signature",
+            "token",
+            "api_key",
+            "api-key",
+        ]
+        .iter()
+        .any(|secret| name.contains(secret))
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub struct Recording {
+    recorded_at: DateTime<Utc>,
+    method: String,
+    uri: String,
+    request_headers: Vec<(String, String)>,
+    request_body: String,
+    status: u16,
+    response_headers: Vec<(String, String)>,
+    response_body: String,
+    latency_ms: f64,
+}
+
+/// What is recorded.
+#[derive(Deserialize, Serialize, Clone, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RecordingSettings {
+    enabled: bool,
+    /// Only the requests to paths starting with this, all of them if null.
+    #[serde(default)]
+    route: Option<String>,
+    /// Only the requests acting as this user, anyone's if null.
+    #[serde(default)]
+    user_id: Option<uuid::Uuid>,
+    /// Every how manieth matching request is recorded, 1 by default.
+    #[serde(default = "every_request")]
+    sample: u64,
+}
+
+fn every_request() -> u64 {
+    1
+}
+
+struct Recorder {
+    settings: Mutex<RecordingSettings>,
+    capacity: usize,
+    seen: AtomicU64,
+    recordings: Mutex<VecDeque<Recording>>,
+}
+
+#[derive(Clone)]
+pub struct Recordings(Arc<Recorder>);
+
+/// Handed down with the requests recorded for a user, for the
+/// authentication to tell whose they are.
+#[derive(Clone, Default)]
+pub struct RecordedUser(Arc<OnceLock<uuid::Uuid>>);
+
+impl RecordedUser {
+    pub fn found(&self, user_id: uuid::Uuid) {
+        let _ = self.0.set(user_id);
+    }
+}
+
+/// Switched off, keeping the last 100 recordings once switched on.
+impl Default for Recordings {
+    fn default() -> Self {
+        let settings = RecordingSettings {
+            enabled: false,
+            route: None,
+            user_id: None,
+            sample: 1,
+        };
+        Recordings::new(settings, 100)
+    }
+}
+
+impl Recordings {
+    /// Switched on if `RECORD_ROUTE` is set, for the requests to that
+    /// prefix.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let route = std::env::var("RECORD_ROUTE").ok();
+        let sample = match std::env::var("RECORD_SAMPLE") {
+            Ok(sample) => sample
+                .parse::<u64>()
+                .ok()
+                .filter(|sample| *sample > 0)
+                .context("RECORD_SAMPLE must be a positive integer")?,
+            Err(_) => 1,
+        };
+        let capacity = match std::env::var("RECORD_CAPACITY") {
+            Ok(capacity) => capacity
+                .parse::<usize>()
+                .context("RECORD_CAPACITY must be a non-negative integer")?,
+            Err(_) => 100,
+        };
+        let settings = RecordingSettings {
+            enabled: route.is_some(),
+            route,
+            user_id: None,
+            sample,
+        };
+        Ok(Recordings::new(settings, capacity))
+    }
+
+    fn new(settings: RecordingSettings, capacity: usize) -> Self {
+        Recordings(Arc::new(Recorder {
+            settings: Mutex::new(settings),
+            capacity,
+            seen: AtomicU64::new(0),
+            recordings: Mutex::new(VecDeque::with_capacity(capacity)),
+        }))
+    }
+
+    /// The settings if `path` is to be recorded for them, sampled already
+    /// unless the request's user has yet to be known.
+    fn wants(&self, path: &str) -> Option<RecordingSettings> {
+        let settings = self.0.settings.lock().unwrap().clone();
+        let wanted = settings.enabled
+            && settings
+                .route
+                .as_ref()
+                .is_none_or(|route| path.starts_with(route.as_str()))
+            && !path.starts_with("/debug/");
+        (wanted && (settings.user_id.is_some() || self.sampled(&settings))).then_some(settings)
+    }
+
+    fn sampled(&self, settings: &RecordingSettings) -> bool {
+        self.0
+            .seen
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(settings.sample)
+    }
+
+    fn push(&self, recording: Recording) {
+        let mut recordings = self.0.recordings.lock().unwrap();
+        if recordings.len() >= self.0.capacity {
+            recordings.pop_front();
+        }
+        if self.0.capacity > 0 {
+            recordings.push_back(recording);
+        }
+    }
+}
+
+/// Whether `body` is known to be short enough to buffer.
+fn fits(body: &impl HttpBody) -> bool {
+    body.size_hint()
+        .upper()
+        .is_some_and(|upper| upper <= MAX_BODY_BYTES as u64)
+}
+
+/// Reads `body`, which [`fits`], failing past [`MAX_BODY_BYTES`] should it
+/// be longer than it said.
+async fn read<B>(body: B) -> Result<Bytes, String>
+where
+    B: HttpBody<Data = Bytes>,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    hyper::body::to_bytes(Limited::new(body, MAX_BODY_BYTES))
+        .await
+        .map_err(|err| err.to_string())
+}
+
+fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"))
+}
+
+/// Records the sampled requests matching the settings; everything else
+/// passes through untouched, as do the bodies that aren't buffered, so
+/// streaming responses keep streaming.
+pub async fn record(
+    State(recordings): State<Recordings>,
+    mut req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if req.headers().contains_key(header::UPGRADE) {
+        return next.run(req).await;
+    }
+    let Some(settings) = recordings.wants(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let user = RecordedUser::default();
+    if settings.user_id.is_some() {
+        req.extensions_mut().insert(user.clone());
+    }
+    let started = Instant::now();
+    let (parts, request_body) = req.into_parts();
+    let (request_body, request_text) = if fits(&request_body) {
+        match read(request_body).await {
+            Ok(bytes) => {
+                let text = redact_body(&parts.headers, &bytes);
+                (Body::from(bytes), text)
+            }
+            Err(err) => {
+                return ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("Failed to read request body: {err}"),
+                )
+                .into_response()
+            }
+        }
+    } else {
+        (request_body, NOT_RECORDED.to_owned())
+    };
+    let method = parts.method.to_string();
+    let uri = redact_uri(&parts.uri.to_string());
+    let request_headers = redact_headers(&parts.headers);
+
+    let response = next.run(Request::from_parts(parts, request_body)).await;
+
+    let (parts, response_body) = response.into_parts();
+    let streamed = parts.status == StatusCode::SWITCHING_PROTOCOLS
+        || is_event_stream(&parts.headers)
+        || !fits(&response_body);
+    let (response_body, response_text) = if streamed {
+        (response_body, NOT_RECORDED.to_owned())
+    } else {
+        match read(response_body).await {
+            Ok(bytes) => {
+                let text = redact_body(&parts.headers, &bytes);
+                (body::boxed(body::Full::new(bytes)), text)
+            }
+            Err(err) => {
+                warn!("Fail to read the recorded response: {err}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    };
+    let wanted = match settings.user_id {
+        Some(user_id) => user.0.get() == Some(&user_id) && recordings.sampled(&settings),
+        None => true,
+    };
+    if wanted {
+        recordings.push(Recording {
+            recorded_at: Utc::now(),
+            method,
+            uri,
+            request_headers,
+            request_body: request_text,
+            status: parts.status.as_u16(),
+            response_headers: redact_headers(&parts.headers),
+            response_body: response_text,
+            latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+        });
+    }
+    Response::from_parts(parts, response_body)
+}
+
+/// Recorded pairs, newest first.
+#[utoipa::path(
+    get,
+    path = "/debug/recordings",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Recorded requests, newest first", body = Vec<Recording>),
+    ),
+)]
+pub async fn list(Extension(recordings): Extension<Recordings>) -> Json<Vec<Recording>> {
+    let recorded: Vec<Recording> = recordings
+        .0
+        .recordings
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .cloned()
+        .collect();
+    Json(recorded)
+}
+
+#[utoipa::path(
+    get,
+    path = "/debug/recordings/settings",
+    tag = "admin",
+    responses(
+        (status = 200, description = "What is recorded", body = RecordingSettings),
+    ),
+)]
+pub async fn get_settings(Extension(recordings): Extension<Recordings>) -> Json<RecordingSettings> {
+    Json(recordings.0.settings.lock().unwrap().clone())
+}
+
+/// Switches recording on or off. The recordings made so far are kept.
+#[utoipa::path(
+    put,
+    path = "/debug/recordings/settings",
+    tag = "admin",
+    request_body = RecordingSettings,
+    responses(
+        (status = 200, description = "The new settings", body = RecordingSettings),
+        (status = 422, description = "A `sample` of 0, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn put_settings(
+    Extension(recordings): Extension<Recordings>,
+    Json(settings): Json<RecordingSettings>,
+) -> Response {
+    if settings.sample == 0 {
+        return ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "sample must be at least 1",
+        )
+        .into_response();
+    }
+    warn!(
+        enabled = settings.enabled,
+        route = ?settings.route,
+        user_id = ?settings.user_id,
+        "Request recording switched"
+    );
+    *recordings.0.settings.lock().unwrap() = settings.clone();
+    Json(settings).into_response()
+}
+
+fn redact_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_secret(name.as_str()) {
+                REDACTED.to_owned()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Redacts the query string and the tokens of share links, hooks and
+/// invitations, which are credentials themselves, under any API version.
+fn redact_uri(uri: &str) -> String {
+    let (path, query) = match uri.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (uri, None),
+    };
+    let path = ["/shared/", "/hooks/", "/invitations/"]
+        .iter()
+        .find_map(|segment| Some(path.find(segment)? + segment.len()))
+        .map_or_else(
+            || path.to_owned(),
+            |start| {
+                let end = path[start..]
+                    .find('/')
+                    .map_or(path.len(), |end| start + end);
+                format!("{}{REDACTED}{}", &path[..start], &path[end..])
+            },
+        );
+    match query {
+        Some(query) => format!("{path}?{}", redact_pairs(query)),
+        None => path,
+    }
+}
+
+/// Redacts `name=value` pairs of a query string or form body.
+fn redact_pairs(pairs: &str) -> String {
+    pairs
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if is_secret(name) => format!("{name}={REDACTED}"),
+            _ => pair.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn redact_body(headers: &HeaderMap, body: &Bytes) -> String {
+    let content_type = headers
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if content_type.contains("json") {
+        if let Ok(mut value) = serde_json::from_slice::<Value>(body) {
+            redact_json(&mut value);
+            return truncate(value.to_string().as_bytes());
+        }
+    }
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        return truncate(redact_pairs(&String::from_utf8_lossy(body)).as_bytes());
+    }
+    truncate(body)
+}
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if is_secret(name) {
+                    *field = Value::String(REDACTED.to_owned());
+                } else {
+                    redact_json(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        // the links handed out for new share links and hooks
+        Value::String(text) => *text = redact_uri(text),
+        _ => {}
+    }
+}
+
+fn truncate(body: &[u8]) -> String {
+    let kept = &body[..body.len().min(MAX_BODY_BYTES)];
+    let mut text = String::from_utf8_lossy(kept).into_owned();
+    if body.len() > MAX_BODY_BYTES {
+        text.push_str("…[truncated]");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, time::Duration};
+
+    use axum::{
+        middleware,
+        response::sse::{Event, Sse},
+        routing::{get, post},
+        Router,
+    };
+    use futures_util::stream::{self, StreamExt};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const USER: uuid::Uuid = uuid::Uuid::from_u128(1);
+
+    fn app(recordings: &Recordings) -> Router {
+        Router::new()
+            .route("/echo", post(|body: Bytes| async move { body }))
+            .route(
+                "/events",
+                get(|| async {
+                    let first = stream::once(async { Ok::<_, Infallible>(Event::default()) });
+                    Sse::new(first.chain(stream::pending()))
+                }),
+            )
+            .route(
+                "/me",
+                get(|user: Option<Extension<RecordedUser>>| async move {
+                    if let Some(Extension(user)) = user {
+                        user.found(USER);
+                    }
+                }),
+            )
+            .layer(middleware::from_fn_with_state(recordings.clone(), record))
+    }
+
+    fn recorded(recordings: &Recordings) -> Vec<Recording> {
+        recordings
+            .0
+            .recordings
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    fn every(route: Option<&str>, user_id: Option<uuid::Uuid>) -> Recordings {
+        let settings = RecordingSettings {
+            enabled: true,
+            route: route.map(str::to_owned),
+            user_id,
+            sample: 1,
+        };
+        Recordings::new(settings, 10)
+    }
+
+    #[tokio::test]
+    async fn long_bodies_pass_through_unrecorded() {
+        let recordings = every(None, None);
+        let long = vec![b'a'; MAX_BODY_BYTES + 1];
+        let response = app(&recordings)
+            .oneshot(
+                Request::post("/echo")
+                    .body(Body::from(long.clone()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let echoed = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(echoed.len(), long.len());
+        let long = &recorded(&recordings)[0];
+        assert_eq!(long.request_body, NOT_RECORDED);
+        assert_eq!(long.response_body, NOT_RECORDED);
+
+        let response = app(&recordings)
+            .oneshot(Request::post("/echo").body(Body::from("short")).unwrap())
+            .await
+            .unwrap();
+        let echoed = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(echoed, "short");
+        assert_eq!(recorded(&recordings)[1].response_body, "short");
+    }
+
+    #[tokio::test]
+    async fn event_streams_keep_streaming() {
+        let recordings = every(None, None);
+        let request = Request::get("/events").body(Body::empty()).unwrap();
+        let response =
+            tokio::time::timeout(Duration::from_secs(5), app(&recordings).oneshot(request))
+                .await
+                .expect("the stream was buffered")
+                .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(recorded(&recordings)[0].response_body, NOT_RECORDED);
+    }
+
+    #[tokio::test]
+    async fn only_the_users_requests_are_recorded() {
+        let recordings = every(Some("/me"), Some(USER));
+        for path in ["/me", "/echo"] {
+            let request = Request::get(path).body(Body::empty()).unwrap();
+            app(&recordings).oneshot(request).await.unwrap();
+        }
+        assert_eq!(recorded(&recordings).len(), 1);
+
+        let recordings = every(None, Some(uuid::Uuid::from_u128(2)));
+        let request = Request::get("/me").body(Body::empty()).unwrap();
+        app(&recordings).oneshot(request).await.unwrap();
+        assert!(recorded(&recordings).is_empty());
+    }
+
+    #[test]
+    fn tokens_in_paths_are_redacted() {
+        for (uri, redacted) in [
+            ("/shared/abc", "/shared/[redacted]"),
+            ("/v1/shared/abc", "/v1/shared/[redacted]"),
+            ("/hooks/abc", "/hooks/[redacted]"),
+            ("/invitations/abc/accept", "/invitations/[redacted]/accept"),
+            (
+                "/v1/invitations/abc/accept",
+                "/v1/invitations/[redacted]/accept",
+            ),
+            (
+                "/todos?token=abc&limit=5",
+                "/todos?token=[redacted]&limit=5",
+            ),
+            (
+                "/workspaces/home/invitations",
+                "/workspaces/home/invitations",
+            ),
+        ] {
+            assert_eq!(redact_uri(uri), redacted, "{uri}");
+        }
+    }
+
+    #[test]
+    fn new_api_keys_are_redacted() {
+        let mut created = json!({"key": "tk_secret", "id": "42", "name": "ci"});
+        redact_json(&mut created);
+        assert_eq!(
+            created,
+            json!({"key": "[redacted]", "id": "42", "name": "ci"})
+        );
+    }
+
+    #[test]
+    fn share_link_urls_are_redacted() {
+        let mut share = json!({"url": "/v1/shared/abc", "expires_at": null});
+        redact_json(&mut share);
+        assert_eq!(
+            share,
+            json!({"url": "/v1/shared/[redacted]", "expires_at": null})
+        );
+        let mut hook = json!({"url": "/v1/hooks/abc"});
+        redact_json(&mut hook);
+        assert_eq!(hook, json!({"url": "/v1/hooks/[redacted]"}));
+    }
+
+    #[test]
+    fn invitation_tokens_are_redacted() {
+        let mut invitation = json!({"token": "abc", "role": "member"});
+        redact_json(&mut invitation);
+        assert_eq!(invitation, json!({"token": "[redacted]", "role": "member"}));
+    }
+}
@@ -0,0 +1,559 @@
+//! Recurrence rules in plain words, such as `every 2 weeks on monday and
+//! friday at 5pm`, and the due dates they lead to:
+//! - `daily`, `weekly`, `monthly`, `yearly`, or `every [N|other]` followed by
+//!   `day`, `week`, `month` or `year` (or their plurals),
+//! - `every weekday`, or `every` followed by weekday names,
+//! - weekly rules may add `on` and weekday names, monthly ones
+//!   `on the 15th`; otherwise they repeat on the first occurrence's,
+//! - `5pm`, `5:30pm`, `17:00`, optionally after `at`, set the time.
+//!
+//! The same rules can be given as an iCalendar `RRULE`, such as
+//! `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR;BYHOUR=17`: `FREQ` with `INTERVAL`,
+//! `BYDAY` (weekly), `BYMONTHDAY` (monthly, one day), `BYHOUR` and
+//! `BYMINUTE`, but no `COUNT`, `UNTIL` or other parts.
+//!
+//! As with quick-add, times are in UTC and default to 09:00. Months without
+//! the day of a monthly rule, such as the 31st, are skipped, as are years
+//! without February 29th.
+
+use axum::{http::StatusCode, response::IntoResponse};
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{FieldError, Json, Query},
+    quick_add::{parse_time, parse_weekday},
+};
+
+const MAX_COUNT: usize = 100;
+const MAX_INTERVAL: u32 = 1000;
+/// How many periods to look through for occurrences, past which a rule
+/// like `every 12 months on the 30th` from February has none.
+const MAX_PERIODS: u32 = 10_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Unit {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct Recurrence {
+    interval: u32,
+    unit: Unit,
+    /// Of weekly rules, sorted, none for the first occurrence's.
+    weekdays: Vec<Weekday>,
+    /// Of monthly rules, none for the first occurrence's.
+    day: Option<u32>,
+    time: NaiveTime,
+}
+
+/// Longest rule accepted, in characters.
+pub const MAX_RULE_CHARS: usize = 200;
+
+/// The error of a rule given for `recurrence`, `None` if it is one.
+pub fn check(rule: &str) -> Option<FieldError> {
+    let reason = if rule.trim().chars().count() > MAX_RULE_CHARS {
+        format!("must be at most {MAX_RULE_CHARS} characters")
+    } else if parse(rule).is_none() {
+        "is not a recurrence rule, such as `every monday` or `FREQ=WEEKLY;BYDAY=MO`".to_owned()
+    } else {
+        return None;
+    };
+    Some(FieldError {
+        field: "recurrence",
+        reason,
+    })
+}
+
+/// Parses `input`, in words or as an `RRULE`, `None` if it isn't a rule.
+pub fn parse(input: &str) -> Option<Recurrence> {
+    let upper = input.trim().to_uppercase();
+    if upper.starts_with("FREQ=") || upper.starts_with("RRULE:") {
+        return parse_rrule(&upper);
+    }
+    let input = input.to_lowercase().replace(',', " ");
+    let mut tokens = input
+        .split_whitespace()
+        .filter(|token| *token != "and")
+        .peekable();
+    let mut recurrence = Recurrence {
+        interval: 1,
+        unit: Unit::Day,
+        weekdays: Vec::new(),
+        day: None,
+        time: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+    };
+
+    match tokens.next()? {
+        "daily" => recurrence.unit = Unit::Day,
+        "weekly" => recurrence.unit = Unit::Week,
+        "monthly" => recurrence.unit = Unit::Month,
+        "yearly" | "annually" => recurrence.unit = Unit::Year,
+        "every" => {
+            let token = tokens.next()?;
+            if token == "weekday" || token == "weekdays" {
+                recurrence.unit = Unit::Week;
+                recurrence.weekdays = vec![
+                    Weekday::Mon,
+                    Weekday::Tue,
+                    Weekday::Wed,
+                    Weekday::Thu,
+                    Weekday::Fri,
+                ];
+            } else if let Some(weekday) = parse_weekday(token) {
+                recurrence.unit = Unit::Week;
+                recurrence.weekdays.push(weekday);
+                while let Some(weekday) = tokens.peek().and_then(|token| parse_weekday(token)) {
+                    recurrence.weekdays.push(weekday);
+                    tokens.next();
+                }
+            } else {
+                let unit = match token {
+                    "other" => {
+                        recurrence.interval = 2;
+                        tokens.next()?
+                    }
+                    _ => match token.parse::<u32>() {
+                        Ok(interval) if (1..=MAX_INTERVAL).contains(&interval) => {
+                            recurrence.interval = interval;
+                            tokens.next()?
+                        }
+                        Ok(_) => return None,
+                        Err(_) => token,
+                    },
+                };
+                recurrence.unit = parse_unit(unit)?;
+            }
+        }
+        _ => return None,
+    }
+
+    while let Some(token) = tokens.next() {
+        if token == "on" && recurrence.unit == Unit::Week && recurrence.weekdays.is_empty() {
+            while let Some(weekday) = tokens.peek().and_then(|token| parse_weekday(token)) {
+                recurrence.weekdays.push(weekday);
+                tokens.next();
+            }
+            if recurrence.weekdays.is_empty() {
+                return None;
+            }
+        } else if token == "on" && recurrence.unit == Unit::Month && recurrence.day.is_none() {
+            if tokens.peek() == Some(&"the") {
+                tokens.next();
+            }
+            recurrence.day = Some(parse_day_of_month(tokens.next()?)?);
+        } else if let Some(time) = match token {
+            "at" => parse_time(tokens.next()?),
+            _ => parse_time(token),
+        } {
+            recurrence.time = time;
+        } else {
+            return None;
+        }
+    }
+
+    recurrence
+        .weekdays
+        .sort_by_key(|weekday| weekday.num_days_from_monday());
+    recurrence.weekdays.dedup();
+    Some(recurrence)
+}
+
+/// `FREQ=...;...` with an optional `RRULE:` prefix, each part at most once.
+fn parse_rrule(input: &str) -> Option<Recurrence> {
+    let mut unit = None;
+    let mut interval = None;
+    let mut weekdays = None;
+    let mut day = None;
+    let mut hour = None;
+    let mut minute = None;
+    for part in input.strip_prefix("RRULE:").unwrap_or(input).split(';') {
+        let (name, value) = part.split_once('=')?;
+        let seen = match name {
+            "FREQ" => unit
+                .replace(match value {
+                    "DAILY" => Unit::Day,
+                    "WEEKLY" => Unit::Week,
+                    "MONTHLY" => Unit::Month,
+                    "YEARLY" => Unit::Year,
+                    _ => return None,
+                })
+                .is_some(),
+            "INTERVAL" => interval
+                .replace(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|n| (1..=MAX_INTERVAL).contains(n))?,
+                )
+                .is_some(),
+            "BYDAY" => weekdays
+                .replace(
+                    value
+                        .split(',')
+                        .map(parse_rrule_weekday)
+                        .collect::<Option<Vec<_>>>()?,
+                )
+                .is_some(),
+            "BYMONTHDAY" => day.replace(parse_day_of_month(value)?).is_some(),
+            "BYHOUR" => hour
+                .replace(value.parse().ok().filter(|h| *h < 24)?)
+                .is_some(),
+            "BYMINUTE" => minute
+                .replace(value.parse().ok().filter(|m| *m < 60)?)
+                .is_some(),
+            _ => return None,
+        };
+        if seen {
+            return None;
+        }
+    }
+    let unit = unit?;
+    if (weekdays.is_some() && unit != Unit::Week) || (day.is_some() && unit != Unit::Month) {
+        return None;
+    }
+    let mut weekdays = weekdays.unwrap_or_default();
+    weekdays.sort_by_key(|weekday| weekday.num_days_from_monday());
+    weekdays.dedup();
+    Some(Recurrence {
+        interval: interval.unwrap_or(1),
+        unit,
+        weekdays,
+        day,
+        time: NaiveTime::from_hms_opt(hour.unwrap_or(9), minute.unwrap_or(0), 0)?,
+    })
+}
+
+/// `MO` to `SU`.
+fn parse_rrule_weekday(value: &str) -> Option<Weekday> {
+    match value {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// `day` or `days` and so on.
+fn parse_unit(value: &str) -> Option<Unit> {
+    match value.strip_suffix('s').unwrap_or(value) {
+        "day" => Some(Unit::Day),
+        "week" => Some(Unit::Week),
+        "month" => Some(Unit::Month),
+        "year" => Some(Unit::Year),
+        _ => None,
+    }
+}
+
+/// `15th`, `1st`, `2nd`, `3rd` or a bare `15`.
+fn parse_day_of_month(value: &str) -> Option<u32> {
+    let digits = ["st", "nd", "rd", "th"]
+        .iter()
+        .find_map(|suffix| value.strip_suffix(suffix))
+        .unwrap_or(value);
+    digits.parse().ok().filter(|day| (1..=31).contains(day))
+}
+
+impl Recurrence {
+    /// The first `count` occurrences after `from`, which also anchors the
+    /// periods: `every 2 weeks` repeats on its weekday, every other week
+    /// starting with its own.
+    pub fn occurrences(&self, from: DateTime<Utc>, count: usize) -> Vec<DateTime<Utc>> {
+        self.iter(from).take(count).collect()
+    }
+
+    /// The first occurrence after `after` of the rule anchored at `from`,
+    /// skipping those in between.
+    pub fn next_after(&self, from: DateTime<Utc>, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.iter(from).find(|occurrence| *occurrence > after)
+    }
+
+    fn iter(&self, from: DateTime<Utc>) -> impl Iterator<Item = DateTime<Utc>> + '_ {
+        let start = from.date_naive();
+        (0..MAX_PERIODS)
+            .map_while(|period| period.checked_mul(self.interval))
+            .map_while(move |offset| self.days_of_period(start, offset))
+            .flatten()
+            .map(|day| Utc.from_utc_datetime(&day.and_time(self.time)))
+            .filter(move |occurrence| *occurrence > from)
+    }
+
+    /// The days the rule falls on in the period `offset` units after the
+    /// one of `start`, `None` past the calendar's end.
+    fn days_of_period(&self, start: NaiveDate, offset: u32) -> Option<Vec<NaiveDate>> {
+        Some(match self.unit {
+            Unit::Day => vec![start.checked_add_signed(Duration::days(offset.into()))?],
+            Unit::Week if self.weekdays.is_empty() => {
+                vec![start.checked_add_signed(Duration::weeks(offset.into()))?]
+            }
+            Unit::Week => {
+                let monday = start.checked_add_signed(
+                    Duration::weeks(offset.into())
+                        - Duration::days(start.weekday().num_days_from_monday().into()),
+                )?;
+                self.weekdays
+                    .iter()
+                    .filter_map(|weekday| {
+                        monday.checked_add_signed(Duration::days(
+                            weekday.num_days_from_monday().into(),
+                        ))
+                    })
+                    .collect()
+            }
+            Unit::Month => {
+                let first = start.with_day(1)?.checked_add_months(Months::new(offset))?;
+                let day = self.day.unwrap_or(start.day());
+                first.with_day(day).into_iter().collect()
+            }
+            Unit::Year => {
+                let year = start.year().checked_add(offset.try_into().ok()?)?;
+                NaiveDate::from_ymd_opt(year, start.month(), start.day())
+                    .into_iter()
+                    .collect()
+            }
+        })
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PreviewRecurrence {
+    /// Such as `every 2 weeks on monday at 5pm` or `FREQ=WEEKLY;BYDAY=MO`.
+    rule: String,
+    /// How many occurrences, default 5.
+    count: Option<usize>,
+    /// When to list them from, default now.
+    from: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RecurrencePreview {
+    occurrences: Vec<DateTime<Utc>>,
+}
+
+/// `GET /recurrence/preview`: checks a rule and lists its next occurrences,
+/// for clients to show what it means before it is saved.
+#[utoipa::path(
+    get,
+    path = "/recurrence/preview",
+    tag = "todos",
+    params(
+        PreviewRecurrence,
+    ),
+    responses(
+        (status = 200, description = "The next occurrences, fewer if the rule has no more", body = RecurrencePreview),
+        (status = 400, description = "Not a recurrence rule, or `count` out of range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn preview(
+    _user: AuthUser,
+    Query(params): Query<PreviewRecurrence>,
+) -> axum::response::Response {
+    let count = params.count.unwrap_or(5);
+    if !(1..=MAX_COUNT).contains(&count) {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("count must be between 1 and {MAX_COUNT}"),
+        )
+        .into_response();
+    }
+    let Some(recurrence) = parse(&params.rule) else {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Not a recurrence rule, such as `every 2 weeks` or `every monday at 5pm`",
+        )
+        .into_response();
+    };
+    let occurrences = recurrence.occurrences(params.from.unwrap_or_else(Utc::now), count);
+    Json(RecurrencePreview { occurrences }).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap()
+    }
+
+    fn occurrences(rule: &str, from: DateTime<Utc>, count: usize) -> Vec<DateTime<Utc>> {
+        parse(rule)
+            .unwrap_or_else(|| panic!("{rule:?} is no rule"))
+            .occurrences(from, count)
+    }
+
+    #[test]
+    fn monthly_on_the_31st_skips_short_months() {
+        assert_eq!(
+            occurrences("monthly on the 31st", at(2026, 1, 15, 12), 5),
+            [
+                at(2026, 1, 31, 9),
+                at(2026, 3, 31, 9),
+                at(2026, 5, 31, 9),
+                at(2026, 7, 31, 9),
+                at(2026, 8, 31, 9),
+            ]
+        );
+        // the first occurrence's day when none is given
+        assert_eq!(
+            occurrences("monthly", at(2026, 1, 30, 8), 3),
+            [at(2026, 1, 30, 9), at(2026, 3, 30, 9), at(2026, 4, 30, 9)]
+        );
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYMONTHDAY=29", at(2027, 1, 30, 8), 2),
+            [at(2027, 3, 29, 9), at(2027, 4, 29, 9)]
+        );
+    }
+
+    #[test]
+    fn yearly_on_february_29th_skips_common_years() {
+        assert_eq!(
+            occurrences("yearly", at(2024, 2, 29, 8), 3),
+            [at(2024, 2, 29, 9), at(2028, 2, 29, 9), at(2032, 2, 29, 9)]
+        );
+        // 2100 isn't a leap year
+        assert_eq!(
+            occurrences("every 4 years", at(2096, 2, 29, 10), 1),
+            [at(2104, 2, 29, 9)]
+        );
+    }
+
+    #[test]
+    fn weekly_rules_fall_on_their_weekdays() {
+        // a Wednesday, so the week's Monday has passed
+        assert_eq!(
+            occurrences(
+                "every 2 weeks on monday and friday at 5pm",
+                at(2026, 10, 14, 8),
+                4
+            ),
+            [
+                Utc.with_ymd_and_hms(2026, 10, 16, 17, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2026, 10, 26, 17, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2026, 10, 30, 17, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2026, 11, 9, 17, 0, 0).unwrap(),
+            ]
+        );
+        assert_eq!(
+            occurrences("every weekday", at(2026, 10, 16, 10), 2),
+            [at(2026, 10, 19, 9), at(2026, 10, 20, 9)]
+        );
+    }
+
+    #[test]
+    fn words_and_rrules_agree() {
+        for (words, rrule) in [
+            ("daily", "FREQ=DAILY"),
+            ("every other week on fri", "FREQ=WEEKLY;INTERVAL=2;BYDAY=FR"),
+            (
+                "every friday, monday at 17:30",
+                "RRULE:FREQ=WEEKLY;BYDAY=MO,FR;BYHOUR=17;BYMINUTE=30",
+            ),
+            (
+                "every 3 months on the 2nd",
+                "freq=monthly;interval=3;bymonthday=2",
+            ),
+            ("annually at 12am", "FREQ=YEARLY;BYHOUR=0"),
+        ] {
+            assert_eq!(parse(words), parse(rrule), "{words}");
+            assert!(parse(words).is_some(), "{words}");
+        }
+    }
+
+    #[test]
+    fn next_after_skips_missed_occurrences() {
+        let daily = parse("daily at 5pm").unwrap();
+        assert_eq!(
+            daily.next_after(at(2026, 1, 1, 8), at(2026, 10, 15, 18)),
+            Some(Utc.with_ymd_and_hms(2026, 10, 16, 17, 0, 0).unwrap())
+        );
+        let monthly = parse("monthly on the 31st").unwrap();
+        assert_eq!(
+            monthly.next_after(at(2026, 1, 1, 8), at(2026, 4, 1, 0)),
+            Some(at(2026, 5, 31, 9))
+        );
+    }
+
+    #[test]
+    fn next_after_gives_up_past_its_period_cap() {
+        let daily = parse("daily").unwrap();
+        let anchor = at(1990, 1, 1, 8);
+        // within MAX_PERIODS days of the anchor
+        let last = anchor + Duration::days(i64::from(MAX_PERIODS) - 2);
+        assert!(daily.next_after(anchor, last).is_some());
+        assert_eq!(daily.next_after(anchor, at(2026, 10, 15, 0)), None);
+        // a year lasts longer, so a rule in years reaches further
+        assert_eq!(
+            parse("yearly")
+                .unwrap()
+                .next_after(anchor, at(2026, 10, 15, 0)),
+            Some(at(2027, 1, 1, 9))
+        );
+        // no month of every 12th from February has a 30th
+        let never = parse("every 12 months on the 30th").unwrap();
+        assert!(never.occurrences(at(2026, 2, 1, 8), 1).is_empty());
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_rule() {
+        for rule in [
+            "",
+            "sometimes",
+            "every",
+            "every 0 days",
+            "every 1001 days",
+            "every 2",
+            "every 2 fortnights",
+            "every other",
+            "weekly on",
+            "weekly on the 3rd",
+            "monthly on",
+            "monthly on the 32nd",
+            "monthly on the 0th",
+            "monthly on the 1st on the 2nd",
+            "daily on monday",
+            "every monday at",
+            "every monday at noon",
+            "every monday at 13pm",
+            "every monday at 25:00",
+            "daily please",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;FREQ=DAILY",
+            "FREQ=DAILY;BYDAY=MO",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=WEEKLY;BYDAY=XX",
+            "FREQ=WEEKLY;COUNT=3",
+            "FREQ=WEEKLY;UNTIL=20270101",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;BYHOUR=24",
+            "FREQ=DAILY;BYMINUTE=60",
+            "FREQ=DAILY;",
+            "RRULE:INTERVAL=2",
+        ] {
+            assert_eq!(parse(rule), None, "{rule:?}");
+            assert!(check(rule).is_some(), "{rule:?}");
+        }
+    }
+
+    #[test]
+    fn check_tells_why() {
+        assert!(check("every monday").is_none());
+        let long = format!("daily {}", "at 5pm ".repeat(40));
+        let error = check(&long).unwrap();
+        assert_eq!(error.field, "recurrence");
+        assert_eq!(error.reason, "must be at most 200 characters");
+        let error = check("whenever").unwrap();
+        assert!(error.reason.starts_with("is not a recurrence rule"));
+    }
+}
@@ -0,0 +1,137 @@
+//! Recurring todos. A todo with a `recurrence`, a rule as [`recurrence`]
+//! parses them, repeats: once it is done, a job running every
+//! `RECURRENCE_CHECK_SECS` creates its next occurrence, a copy with the same
+//! text, list, priority, location, tags, rule and unchecked checklist.
+//!
+//! The rule's periods are anchored at the done todo's due date, or at its
+//! completion if it had none, and the copy is due at the rule's first date
+//! after both that and the job's run, so occurrences missed by finishing
+//! late are skipped. Its start and expiry, if any, move by as much as the
+//! due date did. The done todo is stamped `recurred_at` and recurs only
+//! once, even if undone, handing its text over to the copy; no copy is made
+//! if the user has another todo with that text by then.
+
+use async_trait::async_trait;
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+use crate::{events::Events, jobs::Job, models::Todo, recurrence};
+
+/// Most done todos handled in one transaction; the job goes on with the
+/// next ones right away.
+const BATCH_SIZE: i64 = 100;
+
+pub struct RecurTodos {
+    pub events: Events,
+}
+
+#[async_trait]
+impl Job for RecurTodos {
+    fn name(&self) -> &'static str {
+        "recur_todos"
+    }
+
+    async fn run(&self, pg: &PgPool) -> anyhow::Result<i64> {
+        let mut created = 0;
+        loop {
+            let batch = recur(pg, &self.events).await?;
+            created += batch.created;
+            if batch.handled < BATCH_SIZE as usize {
+                break;
+            }
+        }
+        if created > 0 {
+            info!("Created {created} occurrences of recurring todos");
+        }
+        Ok(created as i64)
+    }
+}
+
+struct Batch {
+    /// Done todos stamped, whether they got a next occurrence or not.
+    handled: usize,
+    /// Next occurrences created.
+    created: usize,
+}
+
+/// Stamps a batch of the done recurring todos and creates their next
+/// occurrences, in one transaction recording their events. Rows another
+/// server's job has locked are left for it.
+async fn recur(pg: &PgPool, events: &Events) -> Result<Batch, sqlx::Error> {
+    let mut tx = pg.begin().await?;
+    let done = sqlx::query!(
+        r#"update "todo" set recurred_at = now()
+        where id in (
+            select id from "todo"
+            where recurrence is not null and is_done and recurred_at is null
+                and merged_into is null and deleted_at is null
+            order by completed_at
+            limit $1
+            for update skip locked
+        )
+        returning id, user_id as "user_id!", recurrence as "recurrence!", start_at, due_at,
+            expires_at, coalesce(completed_at, now()) as "completed_at!""#,
+        BATCH_SIZE,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let now = chrono::Utc::now();
+    let mut created = 0;
+    for todo in &done {
+        let Some(rule) = recurrence::parse(&todo.recurrence) else {
+            warn!(todo_id = %todo.id, "Recurring todo has no valid rule {:?}", todo.recurrence);
+            continue;
+        };
+        let anchor = todo.due_at.unwrap_or(todo.completed_at);
+        let Some(due_at) = rule.next_after(anchor, now) else {
+            continue;
+        };
+        let moved = due_at - anchor;
+        let next_id = sqlx::query_scalar!(
+            r#"insert into "todo" (id, user_id, todo_text, search_config, start_at, due_at,
+                expires_at, list_id, priority, recurrence, latitude, longitude, radius_m)
+            select $2, user_id, todo_text, search_config, $3, $4, $5, list_id, priority,
+                recurrence, latitude, longitude, radius_m
+            from "todo"
+            where id = $1
+            on conflict (user_id, todo_text) where deleted_at is null and recurred_at is null
+            do nothing
+            returning id"#,
+            todo.id,
+            Todo::new_id(),
+            todo.start_at.map(|start_at| start_at + moved),
+            due_at,
+            todo.expires_at.map(|expires_at| expires_at + moved),
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        let Some(next_id) = next_id else {
+            continue;
+        };
+        sqlx::query!(
+            r#"insert into "todo_tag" (todo_id, tag_id)
+            select $2, tag_id from "todo_tag" where todo_id = $1"#,
+            todo.id,
+            next_id,
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!(
+            r#"insert into "checklist_item" (todo_id, position, item_text)
+            select $2, position, item_text from "checklist_item" where todo_id = $1"#,
+            todo.id,
+            next_id,
+        )
+        .execute(&mut tx)
+        .await?;
+        events.changed(&mut tx, todo.user_id, todo.id).await?;
+        events.created_id(&mut tx, todo.user_id, next_id).await?;
+        created += 1;
+    }
+    tx.commit().await?;
+    Ok(Batch {
+        handled: done.len(),
+        created,
+    })
+}
@@ -0,0 +1,80 @@
+//! Due-date reminders. A job running every `REMINDER_CHECK_SECS` reminds
+//! users of their open todos falling due within `REMINDER_LEAD_SECS`, once
+//! per due date: it sends a `due` event to their live clients and a
+//! Postgres `NOTIFY` on the `todo_due` channel, with the todo's `id`,
+//! `user_id` and `due_at`, for other senders, such as of push
+//! notifications, to act on. A due date already past when the job first
+//! sees it gets no reminder. Workspaces' todos are reminded of the
+//! `reminder_offset_minutes` of their settings before instead, if set.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::{events::Events, jobs::Job};
+
+/// Channel of the `NOTIFY` sent for each reminder.
+pub const CHANNEL: &str = "todo_due";
+
+pub struct RemindDue {
+    pub events: Events,
+    /// How long before its due date a todo is reminded of.
+    pub lead: Duration,
+}
+
+#[async_trait]
+impl Job for RemindDue {
+    fn name(&self) -> &'static str {
+        "remind_due_todos"
+    }
+
+    async fn run(&self, pg: &PgPool) -> anyhow::Result<i64> {
+        let reminded = remind(pg, &self.events, self.lead).await?;
+        if reminded > 0 {
+            info!("Sent {reminded} due-date reminders");
+        }
+        Ok(reminded as i64)
+    }
+}
+
+/// Records a reminder of each open todo due within `lead`, or its
+/// workspace's reminder offset, that has none of its due date yet,
+/// notifying and recording its event in the same transaction. Reminders of past due dates can't be sent again and are
+/// dropped. Returns how many todos were reminded.
+async fn remind(pg: &PgPool, events: &Events, lead: Duration) -> Result<usize, sqlx::Error> {
+    let mut tx = pg.begin().await?;
+    sqlx::query!(r#"delete from "todo_reminder" where due_at <= now()"#)
+        .execute(&mut tx)
+        .await?;
+    let reminded = sqlx::query!(
+        r#"with reminded as (
+            insert into "todo_reminder" (todo_id, due_at)
+            select t.id, t.due_at from "todo" t
+            left join "workspace" w on w.id = t.user_id
+            where t.due_at > now()
+                and t.due_at <= now() + coalesce(
+                    (w.settings->>'reminder_offset_minutes')::integer * interval '1 minute',
+                    make_interval(secs => $2))
+                and not t.is_done and t.expired_at is null
+                and t.merged_into is null and t.deleted_at is null
+            on conflict do nothing
+            returning todo_id, due_at
+        )
+        select t.id, t.user_id as "user_id!",
+            pg_notify($1, json_build_object('id', t.id, 'user_id', t.user_id,
+                'due_at', r.due_at)::text)::text
+        from reminded r
+        join "todo" t on t.id = r.todo_id"#,
+        CHANNEL,
+        lead.as_secs_f64(),
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    for row in &reminded {
+        events.due(&mut tx, row.user_id, row.id).await?;
+    }
+    tx.commit().await?;
+    Ok(reminded.len())
+}
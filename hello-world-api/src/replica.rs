@@ -0,0 +1,41 @@
+//! Read-only replicas: with `READ_ONLY=true` the API only serves reads and
+//! answers every write with a 405, so a router can spread GET traffic over
+//! cheap replicas and send writes to the primary. Replicas neither migrate
+//! nor refresh the stats, which is left to the primary, so they can run
+//! against a read-only standby of its database.
+
+use axum::{
+    extract::State,
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    error::{ErrorCode, Problem},
+    maintenance,
+};
+
+/// The methods a replica answers, as listed in `Allow`.
+const READ_METHODS: &str = "GET, HEAD, OPTIONS, PROPFIND, REPORT";
+
+pub async fn reject_writes<B>(
+    State(read_only): State<bool>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    // the admin writes only change this process' state
+    if !read_only || maintenance::is_read(req.method()) || req.uri().path().starts_with("/admin/") {
+        return next.run(req).await;
+    }
+    (
+        [(header::ALLOW, READ_METHODS)],
+        Problem {
+            status: StatusCode::METHOD_NOT_ALLOWED,
+            detail: "This is a read-only replica, send writes to the primary".to_owned(),
+            code: ErrorCode::ReadOnly,
+            details: None,
+        },
+    )
+        .into_response()
+}
@@ -0,0 +1,496 @@
+//! Database access: the schema migrations, pool settings and warm-up, and the
+//! [`TodoRepository`] behind the core todo endpoints. Feature modules such
+//! as checklists and CalDAV still keep their own queries next to their
+//! handlers.
+
+pub mod memory;
+pub mod migrate;
+pub mod todo_query;
+pub(crate) mod todos;
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{
+    migrate::Migrator, postgres::PgPoolOptions, Executor, PgConnection, PgPool, Postgres, Type,
+};
+use tracing::info;
+
+use crate::{
+    links::TodoLink,
+    models::{Priority, Todo},
+};
+use todo_query::{Cursor, TodoQuery};
+
+pub use memory::MemoryTodoRepository;
+pub use todos::{PgTodoRepository, DEFAULT_EXACT_COUNT_LIMIT};
+
+/// The migrations in `migrations/`, embedded at build time, with the down
+/// scripts of the reversible ones.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// The repository the core todo handlers take as router state.
+pub type Todos = Arc<dyn TodoRepository>;
+
+/// Where the core todos are kept, from `STORAGE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Storage {
+    Postgres,
+    /// In a [`MemoryTodoRepository`], lost when the process exits. Nothing
+    /// is connected to, so only the core todo endpoints work.
+    Memory,
+}
+
+impl std::str::FromStr for Storage {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "postgres" => Ok(Storage::Postgres),
+            "memory" => Ok(Storage::Memory),
+            other => anyhow::bail!("STORAGE must be postgres or memory, got {other}"),
+        }
+    }
+}
+
+/// A repository running all its calls in one transaction, see
+/// [`TodoRepository::unit_of_work`].
+pub type UnitOfWork<'a> = Box<dyn TodoRepository + 'a>;
+
+/// Storage of the core todos, each owned by one user and only ever read or
+/// changed on their behalf. Merged tombstones and deleted todos are never
+/// returned, except by listings asking for deleted ones.
+#[async_trait]
+pub trait TodoRepository: Send + Sync {
+    /// This repository running its calls in the transaction on `conn`,
+    /// usually the request's [`Tx`](crate::tx::Tx), instead of each in its
+    /// own: a handler writing to several tables, through the repository or
+    /// not, keeps all of it or, answering with an error, none.
+    fn unit_of_work<'a>(&'a self, conn: &'a mut PgConnection) -> UnitOfWork<'a>;
+
+    /// This repository as a unit of work of its own, for requests served
+    /// without a database and so without a transaction to join. `None` for
+    /// repositories keeping the todos in one.
+    fn detached(&self) -> Option<UnitOfWork<'_>> {
+        None
+    }
+
+    async fn get(&self, user_id: uuid::Uuid, id: uuid::Uuid) -> Result<Todo, RepositoryError>;
+
+    /// The todos among `ids` that exist, in no particular order.
+    async fn get_many(
+        &self,
+        user_id: uuid::Uuid,
+        ids: &[uuid::Uuid],
+    ) -> Result<Vec<Todo>, RepositoryError>;
+
+    /// The todo a merged todo's tombstone points at, `None` for any other id.
+    async fn merged_into(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<Option<uuid::Uuid>, RepositoryError>;
+
+    /// One page of the todos matching `query`.
+    async fn list(
+        &self,
+        user_id: uuid::Uuid,
+        query: &TodoQuery,
+    ) -> Result<Vec<Todo>, RepositoryError>;
+
+    /// [`list`](Self::list) for paged listings, also returning the cursor
+    /// of the next page if there is one and the todos are listed in the
+    /// user's order.
+    async fn list_page(
+        &self,
+        user_id: uuid::Uuid,
+        query: &TodoQuery,
+    ) -> Result<(Vec<Todo>, Option<Cursor>), RepositoryError> {
+        // one row past the page tells whether there is a next one
+        let page_size = query.limit;
+        let query = TodoQuery {
+            limit: page_size + 1,
+            ..query.clone()
+        };
+        let mut todos = self.list(user_id, &query).await?;
+        let more = todos.len() as i64 > page_size;
+        todos.truncate(page_size as usize);
+        let next_cursor = more
+            .then(|| todos.last())
+            .flatten()
+            .filter(|_| query.sort.is_empty())
+            .map(|todo| Cursor {
+                position: todo.position,
+                id: todo.id,
+            });
+        Ok((todos, next_cursor))
+    }
+
+    /// All todos matching `query`, whatever page it is at.
+    async fn count(&self, user_id: uuid::Uuid, query: &TodoQuery) -> Result<i64, RepositoryError>;
+
+    /// [`count`](Self::count) for listings, where an estimate will do once
+    /// there are too many matches to count them quickly.
+    async fn count_for_listing(
+        &self,
+        user_id: uuid::Uuid,
+        query: &TodoQuery,
+    ) -> Result<Total, RepositoryError> {
+        Ok(Total {
+            count: self.count(user_id, query).await?,
+            estimated: false,
+        })
+    }
+
+    async fn insert(&self, user_id: uuid::Uuid, todo: NewTodo<'_>)
+        -> Result<Todo, RepositoryError>;
+
+    /// Inserts each of `todos`, failing on its own for those that can't be:
+    /// the others are still inserted. Only fails as a whole when storage
+    /// does. The default inserts them one by one.
+    async fn insert_many(
+        &self,
+        user_id: uuid::Uuid,
+        todos: &[NewTodo<'_>],
+    ) -> Result<Vec<Result<Todo, RepositoryError>>, RepositoryError> {
+        let mut results = Vec::with_capacity(todos.len());
+        for &todo in todos {
+            match self.insert(user_id, todo).await {
+                Err(err @ RepositoryError::Database(_)) => return Err(err),
+                result => results.push(result),
+            }
+        }
+        Ok(results)
+    }
+
+    /// Fails with `VersionMismatch` if `versions` is given and the todo's
+    /// version isn't among them, and with `NotPermitted` for a todo only
+    /// shared with the user as viewer; so does [`update`](Self::update).
+    async fn set_done(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        is_done: bool,
+        versions: Option<&[i64]>,
+    ) -> Result<Todo, RepositoryError>;
+
+    /// Marks the todo done if it isn't and not done if it is, in one
+    /// statement, so concurrent toggles each flip it. Fails with
+    /// `NotPermitted` as [`set_done`](Self::set_done) does.
+    async fn toggle_done(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<Todo, RepositoryError>;
+
+    /// Marks each of `ids` done, in order, `NotFound` for those that aren't
+    /// the user's live todos. The default completes them one by one.
+    async fn complete_many(
+        &self,
+        user_id: uuid::Uuid,
+        ids: &[uuid::Uuid],
+    ) -> Result<Vec<Result<Todo, RepositoryError>>, RepositoryError> {
+        let mut results = Vec::with_capacity(ids.len());
+        for &id in ids {
+            match self.set_done(user_id, id, true, None).await {
+                Err(err @ RepositoryError::Database(_)) => return Err(err),
+                result => results.push(result),
+            }
+        }
+        Ok(results)
+    }
+
+    async fn update(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        changes: TodoChanges<'_>,
+        versions: Option<&[i64]>,
+    ) -> Result<Todo, RepositoryError>;
+
+    /// Tags the todo with the user's tags of these names, creating the
+    /// missing ones.
+    async fn add_tags(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        names: &[String],
+    ) -> Result<Todo, RepositoryError>;
+
+    /// The links from and to the todo. The default has none, for storage
+    /// without links.
+    async fn links(
+        &self,
+        _user_id: uuid::Uuid,
+        _id: uuid::Uuid,
+    ) -> Result<Vec<TodoLink>, RepositoryError> {
+        Ok(Vec::new())
+    }
+
+    /// Fails with `NotFound` for unknown or already deleted todos, and with
+    /// `NotPermitted` for the ones only shared with the user.
+    async fn soft_delete(&self, user_id: uuid::Uuid, id: uuid::Uuid)
+        -> Result<(), RepositoryError>;
+
+    /// Up to `limit` of the user's own todos deleted within `kept_for`, the
+    /// last deleted first, with their `deleted_at`.
+    async fn trash(
+        &self,
+        user_id: uuid::Uuid,
+        kept_for: Duration,
+        limit: i64,
+    ) -> Result<Vec<Todo>, RepositoryError>;
+
+    /// Undeletes one of the todos [`trash`](Self::trash) lists, failing with
+    /// `NotFound` for any other, and with `Duplicate` if a live todo has
+    /// taken its text since.
+    async fn restore(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        kept_for: Duration,
+    ) -> Result<Todo, RepositoryError>;
+
+    /// Puts the user's own todo at `to` in their order, failing with
+    /// `NotFound` if it or the todo to move it next to isn't one of their
+    /// live todos, and with `NotPermitted` for a todo only shared with them.
+    async fn move_to(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        to: MoveTo,
+    ) -> Result<Todo, RepositoryError>;
+
+    /// Turns `source` into a tombstone pointing at `target`, handing its
+    /// checklist items, tags and external link over.
+    async fn merge(
+        &self,
+        user_id: uuid::Uuid,
+        target: uuid::Uuid,
+        source: uuid::Uuid,
+    ) -> Result<Todo, RepositoryError>;
+}
+
+/// A todo to insert with [`TodoRepository::insert`].
+#[derive(Clone, Copy)]
+pub struct NewTodo<'a> {
+    pub text: &'a str,
+    pub start_at: Option<DateTime<Utc>>,
+    pub due_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub list_id: Option<uuid::Uuid>,
+    pub priority: Option<Priority>,
+    pub recurrence: Option<&'a str>,
+}
+
+/// The fields [`TodoRepository::update`] changes, those that are given;
+/// `Some(None)` clears a date. Giving the expiry revives the todo if it had
+/// expired. `Some(None)` also takes the todo out of its list, clears its
+/// priority or stops it from recurring.
+#[derive(Clone, Copy, Default)]
+pub struct TodoChanges<'a> {
+    pub text: Option<&'a str>,
+    pub is_done: Option<bool>,
+    pub due_at: Option<Option<DateTime<Utc>>>,
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    pub list_id: Option<Option<uuid::Uuid>>,
+    pub priority: Option<Option<Priority>>,
+    pub recurrence: Option<Option<&'a str>>,
+}
+
+/// Where [`TodoRepository::move_to`] puts a todo among the user's others.
+#[derive(Clone, Copy, Debug)]
+pub enum MoveTo {
+    /// At this index of the user's live todos, counted without the moved
+    /// one; past the last it goes last.
+    Index(i64),
+    Before(uuid::Uuid),
+    After(uuid::Uuid),
+}
+
+/// How far apart a user's todos are placed: a new one goes this much after
+/// their last, and spreading them out again puts them this far apart.
+pub const POSITION_STEP: f64 = 1024.0;
+
+/// The position for a todo moved between `prev` and `next`, either of them
+/// `None` at an end of the order. `None` if there is no room left between
+/// them, and the user's todos have to be spread out first.
+pub fn position_between(prev: Option<f64>, next: Option<f64>) -> Option<f64> {
+    match (prev, next) {
+        (None, None) => Some(POSITION_STEP),
+        (Some(prev), None) => Some(prev + POSITION_STEP),
+        (None, Some(next)) => Some(next - POSITION_STEP),
+        (Some(prev), Some(next)) => {
+            let position = prev + (next - prev) / 2.0;
+            (prev < position && position < next).then_some(position)
+        }
+    }
+}
+
+/// How many todos a listing matches.
+#[derive(Clone, Copy, Debug)]
+pub struct Total {
+    pub count: i64,
+    /// Whether `count` is the planner's estimate rather than an exact count.
+    pub estimated: bool,
+}
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    NotFound,
+    /// Another live todo already has this text.
+    Duplicate,
+    /// The todo exists, at another version than the write expected.
+    VersionMismatch,
+    /// The todo's `list_id` isn't one of the user's lists.
+    NoSuchList,
+    /// The todo is shared with the user, but not for this.
+    NotPermitted,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RepositoryError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound,
+            sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some("23505") => {
+                RepositoryError::Duplicate
+            }
+            sqlx::Error::Database(db_err) if db_err.constraint() == Some("todo_list_id_fkey") => {
+                RepositoryError::NoSuchList
+            }
+            err => RepositoryError::Database(err),
+        }
+    }
+}
+
+impl std::fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepositoryError::NotFound => f.write_str("no such todo"),
+            RepositoryError::Duplicate => f.write_str("another live todo has this text"),
+            RepositoryError::VersionMismatch => f.write_str("the todo is at another version"),
+            RepositoryError::NoSuchList => f.write_str("no such list"),
+            RepositoryError::NotPermitted => f.write_str("the todo is shared, but not for this"),
+            RepositoryError::Database(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RepositoryError::Database(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// How the connection pool is sized, from the `DATABASE_*` settings.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    /// Kept open while idle, and opened and warmed up before serving.
+    pub min_connections: u32,
+    /// How long a request waits for a pool connection before failing.
+    pub acquire_timeout: Duration,
+    /// How long a connection over `min_connections` is kept idle, forever
+    /// if `None`.
+    pub idle_timeout: Option<Duration>,
+    /// How long a connection is used before it is replaced, forever if
+    /// `None`.
+    pub max_lifetime: Option<Duration>,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        PoolSettings {
+            max_connections: 20,
+            min_connections: 5,
+            acquire_timeout: Duration::from_millis(500),
+            idle_timeout: Some(Duration::from_secs(600)),
+            max_lifetime: Some(Duration::from_secs(1800)),
+        }
+    }
+}
+
+impl PoolSettings {
+    pub fn options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+            .max_lifetime(self.max_lifetime)
+    }
+}
+
+/// Opens `connections` pool connections up front and prepares the hot
+/// queries on each of them, so the first requests after a deploy don't pay
+/// for connection setup and statement parsing.
+pub async fn warm_up(db: &PgPool, connections: u32) -> anyhow::Result<()> {
+    // parameter types have to match what the handlers bind, otherwise the
+    // cached statement is unusable for them
+    // as `GET /todos` lists them, along with those shared with the user
+    let default_list = TodoQuery {
+        include_shared: true,
+        ..TodoQuery::default()
+    };
+    let default_list = default_list.build(uuid::Uuid::nil());
+    let hot_queries = [
+        (
+            default_list.sql(),
+            vec![
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+                <i64 as Type<Postgres>>::type_info(),
+                <i64 as Type<Postgres>>::type_info(),
+            ],
+        ),
+        (
+            todos::SELECT_TODO,
+            vec![
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+            ],
+        ),
+        (
+            todos::UPDATE_TODO_DONE,
+            vec![
+                <bool as Type<Postgres>>::type_info(),
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+                <Vec<i64> as Type<Postgres>>::type_info(),
+            ],
+        ),
+        (
+            todos::INSERT_TODO,
+            vec![
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+                <String as Type<Postgres>>::type_info(),
+                <chrono::DateTime<chrono::Utc> as Type<Postgres>>::type_info(),
+                <String as Type<Postgres>>::type_info(),
+                <chrono::DateTime<chrono::Utc> as Type<Postgres>>::type_info(),
+                <chrono::DateTime<chrono::Utc> as Type<Postgres>>::type_info(),
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+                <uuid::Uuid as Type<Postgres>>::type_info(),
+                <Priority as Type<Postgres>>::type_info(),
+                <String as Type<Postgres>>::type_info(),
+            ],
+        ),
+    ];
+    let mut acquired = Vec::with_capacity(connections as usize);
+    for _ in 0..connections {
+        let mut conn = db.acquire().await?;
+        for (sql, parameters) in &hot_queries {
+            (&mut *conn).prepare_with(sql, parameters).await?;
+        }
+        // hold on to the connection so the next acquire opens a new one
+        acquired.push(conn);
+    }
+    info!(connections, "Connection pool warmed up");
+    Ok(())
+}
@@ -0,0 +1,581 @@
+//! A [`TodoRepository`] kept in memory, for running the core todo handlers
+//! in tests, or with `STORAGE=memory` for local development, without a
+//! database.
+
+use std::{
+    cmp::Ordering,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, SubsecRound, Utc};
+use sqlx::PgConnection;
+
+use super::{
+    position_between,
+    todo_query::{SortDirection, TodoQuery, TodoSortField},
+    MoveTo, NewTodo, RepositoryError, TodoChanges, TodoRepository, UnitOfWork, POSITION_STEP,
+};
+use crate::{
+    models::{Priority, Todo},
+    tags::Tag,
+};
+
+struct Row {
+    id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    text: String,
+    is_done: bool,
+    start_at: Option<DateTime<Utc>>,
+    due_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    expired_at: Option<DateTime<Utc>>,
+    version: i64,
+    list_id: Option<uuid::Uuid>,
+    assignee_id: Option<uuid::Uuid>,
+    priority: Option<Priority>,
+    recurrence: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    position: f64,
+    deleted_at: Option<DateTime<Utc>>,
+    merged_into: Option<uuid::Uuid>,
+    /// By name.
+    tags: Vec<Tag>,
+}
+
+impl Row {
+    fn is_live(&self, user_id: uuid::Uuid) -> bool {
+        self.user_id == user_id && self.merged_into.is_none() && self.deleted_at.is_none()
+    }
+
+    /// Whether the row is one of the user's todos deleted after `since`.
+    fn is_trashed(&self, user_id: uuid::Uuid, since: DateTime<Utc>) -> bool {
+        self.user_id == user_id
+            && self.merged_into.is_none()
+            && self.deleted_at.is_some_and(|deleted_at| deleted_at > since)
+    }
+
+    fn check_version(&self, versions: Option<&[i64]>) -> Result<(), RepositoryError> {
+        match versions {
+            Some(versions) if !versions.contains(&self.version) => {
+                Err(RepositoryError::VersionMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// What the `todo_version` trigger does on every update in Postgres.
+    fn bump(&mut self) {
+        self.version += 1;
+        self.updated_at = stamp();
+    }
+
+    fn to_todo(&self) -> Todo {
+        Todo {
+            id: self.id,
+            todo_text: self.text.clone(),
+            is_done: self.is_done,
+            start_at: self.start_at,
+            due_at: self.due_at,
+            expires_at: self.expires_at,
+            expired_at: self.expired_at,
+            version: self.version,
+            list_id: self.list_id,
+            assignee_id: self.assignee_id,
+            priority: self.priority,
+            recurrence: self.recurrence.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            position: self.position,
+            deleted_at: self.deleted_at,
+            field_modified: None,
+            tags: sqlx::types::Json(self.tags.clone()),
+            // checklists only exist in the database
+            completion_percent: None,
+            shared_by: None,
+        }
+    }
+
+    fn matches(&self, user_id: uuid::Uuid, query: &TodoQuery, now: DateTime<Utc>) -> bool {
+        let started = self.start_at.is_none_or(|start_at| start_at <= now);
+        let overdue = !self.is_done && self.due_at.is_some_and(|due_at| due_at < now);
+        let text = self.text.to_lowercase();
+        self.user_id == user_id
+            && self.merged_into.is_none()
+            && (query.include_deleted || self.deleted_at.is_none())
+            && query.ids.as_ref().is_none_or(|ids| ids.contains(&self.id))
+            && query.is_done.is_none_or(|is_done| self.is_done == is_done)
+            && query.started.is_none_or(|wanted| started == wanted)
+            && query.overdue.is_none_or(|wanted| overdue == wanted)
+            && query
+                .expired
+                .is_none_or(|wanted| self.expired_at.is_some() == wanted)
+            && query
+                .tag
+                .as_ref()
+                .is_none_or(|name| self.tags.iter().any(|tag| tag.name == *name))
+            && query
+                .list_id
+                .is_none_or(|list_id| self.list_id == Some(list_id))
+            && query
+                .assignee_id
+                .is_none_or(|assignee_id| self.assignee_id == Some(assignee_id))
+            && query
+                .priority
+                .is_none_or(|priority| self.priority == Some(priority))
+            && query.since.is_none_or(|since| self.updated_at > since)
+            && query
+                .due_before
+                .is_none_or(|before| self.due_at.is_some_and(|due_at| due_at < before))
+            && query
+                .text_contains
+                .as_ref()
+                .is_none_or(|needle| text.contains(&needle.to_lowercase()))
+            // whole words without stemming, unlike Postgres' text search
+            && query.search.as_ref().is_none_or(|search| {
+                search.split_whitespace().all(|word| {
+                    text.split(|c: char| !c.is_alphanumeric())
+                        .any(|token| token == word.to_lowercase())
+                })
+            })
+    }
+
+    fn compare(&self, other: &Row, field: TodoSortField) -> Ordering {
+        match field {
+            TodoSortField::Id => self.id.cmp(&other.id),
+            TodoSortField::Text => self.text.cmp(&other.text),
+            TodoSortField::IsDone => self.is_done.cmp(&other.is_done),
+            TodoSortField::StartAt => nulls_last(self.start_at, other.start_at),
+            TodoSortField::DueAt => nulls_last(self.due_at, other.due_at),
+            TodoSortField::Priority => nulls_last(self.priority, other.priority),
+            TodoSortField::CreatedAt => self.created_at.cmp(&other.created_at),
+            TodoSortField::UpdatedAt => self.updated_at.cmp(&other.updated_at),
+            TodoSortField::Position => self.position.total_cmp(&other.position),
+        }
+    }
+
+    /// The order of unsorted listings, and what sorted ones fall back on.
+    fn compare_position(&self, other: &Row) -> Ordering {
+        self.position
+            .total_cmp(&other.position)
+            .then(self.id.cmp(&other.id))
+    }
+}
+
+/// The time to the microsecond, as Postgres keeps it, so a cursor made from a
+/// row's `created_at` doesn't list that row again.
+fn stamp() -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(6)
+}
+
+/// Orders dates or priorities with missing ones last, as Postgres does.
+fn nulls_last<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Todos in a `Vec`. Merging doesn't move checklist items, and any `list_id`
+/// is taken, as checklists and lists only exist in the database. Clones share
+/// the todos.
+#[derive(Clone, Default)]
+pub struct MemoryTodoRepository {
+    rows: Arc<Mutex<Vec<Row>>>,
+    /// Every user's tags, with the id of their owner.
+    tags: Arc<Mutex<Vec<(uuid::Uuid, Tag)>>>,
+}
+
+impl MemoryTodoRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl TodoRepository for MemoryTodoRepository {
+    /// Writes right away, as outside of one: rolling the transaction back
+    /// doesn't undo them.
+    fn unit_of_work<'a>(&'a self, _conn: &'a mut PgConnection) -> UnitOfWork<'a> {
+        Box::new(self.clone())
+    }
+
+    fn detached(&self) -> Option<UnitOfWork<'_>> {
+        Some(Box::new(self.clone()))
+    }
+
+    async fn get(&self, user_id: uuid::Uuid, id: uuid::Uuid) -> Result<Todo, RepositoryError> {
+        let rows = self.rows.lock().unwrap();
+        rows.iter()
+            .find(|row| row.id == id && row.is_live(user_id))
+            .map(Row::to_todo)
+            .ok_or(RepositoryError::NotFound)
+    }
+
+    async fn get_many(
+        &self,
+        user_id: uuid::Uuid,
+        ids: &[uuid::Uuid],
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let rows = self.rows.lock().unwrap();
+        Ok(rows
+            .iter()
+            .filter(|row| ids.contains(&row.id) && row.is_live(user_id))
+            .map(Row::to_todo)
+            .collect())
+    }
+
+    async fn merged_into(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<Option<uuid::Uuid>, RepositoryError> {
+        let rows = self.rows.lock().unwrap();
+        Ok(rows
+            .iter()
+            .find(|row| row.id == id && row.user_id == user_id)
+            .and_then(|row| row.merged_into))
+    }
+
+    async fn list(
+        &self,
+        user_id: uuid::Uuid,
+        query: &TodoQuery,
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let now = Utc::now();
+        let rows = self.rows.lock().unwrap();
+        let mut matching: Vec<&Row> = rows
+            .iter()
+            .filter(|row| row.matches(user_id, query, now))
+            .filter(|row| {
+                query.after.is_none_or(|after| {
+                    row.position
+                        .total_cmp(&after.position)
+                        .then(row.id.cmp(&after.id))
+                        .is_gt()
+                })
+            })
+            .collect();
+        matching.sort_by(|a, b| {
+            query
+                .sort
+                .iter()
+                .map(|&(field, direction)| match direction {
+                    SortDirection::Asc => a.compare(b, field),
+                    SortDirection::Desc => b.compare(a, field),
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a.compare_position(b))
+        });
+        Ok(matching
+            .into_iter()
+            .skip(query.offset as usize)
+            .take(query.limit as usize)
+            .map(Row::to_todo)
+            .collect())
+    }
+
+    async fn count(&self, user_id: uuid::Uuid, query: &TodoQuery) -> Result<i64, RepositoryError> {
+        let now = Utc::now();
+        let rows = self.rows.lock().unwrap();
+        Ok(rows
+            .iter()
+            .filter(|row| row.matches(user_id, query, now))
+            .count() as i64)
+    }
+
+    async fn insert(
+        &self,
+        user_id: uuid::Uuid,
+        todo: NewTodo<'_>,
+    ) -> Result<Todo, RepositoryError> {
+        let mut rows = self.rows.lock().unwrap();
+        if rows
+            .iter()
+            .any(|row| row.user_id == user_id && row.deleted_at.is_none() && row.text == todo.text)
+        {
+            return Err(RepositoryError::Duplicate);
+        }
+        let now = stamp();
+        let last = rows
+            .iter()
+            .filter(|row| row.user_id == user_id)
+            .map(|row| row.position)
+            .reduce(f64::max);
+        let row = Row {
+            id: Todo::new_id(),
+            user_id,
+            text: todo.text.to_owned(),
+            is_done: false,
+            start_at: todo.start_at,
+            due_at: todo.due_at,
+            expires_at: todo.expires_at,
+            expired_at: None,
+            version: 1,
+            list_id: todo.list_id,
+            assignee_id: None,
+            priority: todo.priority,
+            recurrence: todo.recurrence.map(str::to_owned),
+            created_at: now,
+            updated_at: now,
+            position: last.unwrap_or(0.0) + POSITION_STEP,
+            deleted_at: None,
+            merged_into: None,
+            tags: Vec::new(),
+        };
+        let todo = row.to_todo();
+        rows.push(row);
+        Ok(todo)
+    }
+
+    async fn set_done(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        is_done: bool,
+        versions: Option<&[i64]>,
+    ) -> Result<Todo, RepositoryError> {
+        let mut rows = self.rows.lock().unwrap();
+        let row = rows
+            .iter_mut()
+            .find(|row| row.id == id && row.is_live(user_id))
+            .ok_or(RepositoryError::NotFound)?;
+        row.check_version(versions)?;
+        row.is_done = is_done;
+        row.bump();
+        Ok(row.to_todo())
+    }
+
+    async fn toggle_done(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<Todo, RepositoryError> {
+        let mut rows = self.rows.lock().unwrap();
+        let row = rows
+            .iter_mut()
+            .find(|row| row.id == id && row.is_live(user_id))
+            .ok_or(RepositoryError::NotFound)?;
+        row.is_done = !row.is_done;
+        row.bump();
+        Ok(row.to_todo())
+    }
+
+    async fn update(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        changes: TodoChanges<'_>,
+        versions: Option<&[i64]>,
+    ) -> Result<Todo, RepositoryError> {
+        let mut rows = self.rows.lock().unwrap();
+        let row = rows
+            .iter_mut()
+            .find(|row| row.id == id && row.is_live(user_id))
+            .ok_or(RepositoryError::NotFound)?;
+        row.check_version(versions)?;
+        if let Some(text) = changes.text {
+            row.text = text.to_owned();
+        }
+        if let Some(is_done) = changes.is_done {
+            row.is_done = is_done;
+        }
+        if let Some(due_at) = changes.due_at {
+            row.due_at = due_at;
+        }
+        if let Some(expires_at) = changes.expires_at {
+            row.expires_at = expires_at;
+            row.expired_at = None;
+        }
+        if let Some(list_id) = changes.list_id {
+            row.list_id = list_id;
+        }
+        if let Some(priority) = changes.priority {
+            row.priority = priority;
+        }
+        if let Some(recurrence) = changes.recurrence {
+            row.recurrence = recurrence.map(str::to_owned);
+        }
+        row.bump();
+        Ok(row.to_todo())
+    }
+
+    async fn add_tags(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        names: &[String],
+    ) -> Result<Todo, RepositoryError> {
+        let mut rows = self.rows.lock().unwrap();
+        let row = rows
+            .iter_mut()
+            .find(|row| row.id == id && row.is_live(user_id))
+            .ok_or(RepositoryError::NotFound)?;
+        let mut tags = self.tags.lock().unwrap();
+        for name in names {
+            let tag = match tags
+                .iter()
+                .find(|(owner, tag)| *owner == user_id && tag.name == *name)
+            {
+                Some((_, tag)) => tag.clone(),
+                None => {
+                    let tag = Tag {
+                        id: uuid::Uuid::new_v4(),
+                        name: name.clone(),
+                    };
+                    tags.push((user_id, tag.clone()));
+                    tag
+                }
+            };
+            if row.tags.iter().all(|had| had.id != tag.id) {
+                row.tags.push(tag);
+            }
+        }
+        row.tags.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(row.to_todo())
+    }
+
+    async fn soft_delete(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<(), RepositoryError> {
+        let mut rows = self.rows.lock().unwrap();
+        let row = rows
+            .iter_mut()
+            .find(|row| row.id == id && row.is_live(user_id))
+            .ok_or(RepositoryError::NotFound)?;
+        row.deleted_at = Some(stamp());
+        row.bump();
+        Ok(())
+    }
+
+    async fn trash(
+        &self,
+        user_id: uuid::Uuid,
+        kept_for: Duration,
+        limit: i64,
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let since = Utc::now() - kept_for;
+        let rows = self.rows.lock().unwrap();
+        let mut deleted: Vec<_> = rows
+            .iter()
+            .filter(|row| row.is_trashed(user_id, since))
+            .collect();
+        deleted.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then(a.id.cmp(&b.id)));
+        Ok(deleted
+            .into_iter()
+            .take(limit as usize)
+            .map(Row::to_todo)
+            .collect())
+    }
+
+    async fn restore(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        kept_for: Duration,
+    ) -> Result<Todo, RepositoryError> {
+        let since = Utc::now() - kept_for;
+        let mut rows = self.rows.lock().unwrap();
+        let index = rows
+            .iter()
+            .position(|row| row.id == id && row.is_trashed(user_id, since))
+            .ok_or(RepositoryError::NotFound)?;
+        let text = &rows[index].text;
+        if rows
+            .iter()
+            .any(|row| row.user_id == user_id && row.deleted_at.is_none() && &row.text == text)
+        {
+            return Err(RepositoryError::Duplicate);
+        }
+        let row = &mut rows[index];
+        row.deleted_at = None;
+        row.bump();
+        Ok(row.to_todo())
+    }
+
+    async fn move_to(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        to: MoveTo,
+    ) -> Result<Todo, RepositoryError> {
+        let mut rows = self.rows.lock().unwrap();
+        let index = rows
+            .iter()
+            .position(|row| row.id == id && row.is_live(user_id))
+            .ok_or(RepositoryError::NotFound)?;
+        let neighbours = |rows: &[Row]| {
+            let mut others: Vec<&Row> = rows
+                .iter()
+                .filter(|row| row.id != id && row.is_live(user_id))
+                .collect();
+            others.sort_by(|a, b| a.compare_position(b));
+            let at = match to {
+                MoveTo::Index(index) => (index as usize).min(others.len()),
+                MoveTo::Before(anchor) | MoveTo::After(anchor) => {
+                    let at = others
+                        .iter()
+                        .position(|row| row.id == anchor)
+                        .ok_or(RepositoryError::NotFound)?;
+                    at + matches!(to, MoveTo::After(_)) as usize
+                }
+            };
+            let prev = at.checked_sub(1).map(|prev| others[prev].position);
+            Ok::<_, RepositoryError>((prev, others.get(at).map(|next| next.position)))
+        };
+        let (prev, next) = neighbours(&rows)?;
+        let position = match position_between(prev, next) {
+            Some(position) => position,
+            None => {
+                // spread out again, deleted todos included
+                let mut mine: Vec<&mut Row> = rows
+                    .iter_mut()
+                    .filter(|row| row.user_id == user_id && row.merged_into.is_none())
+                    .collect();
+                mine.sort_by(|a, b| a.compare_position(b));
+                for (rank, row) in mine.into_iter().enumerate() {
+                    row.position = (rank + 1) as f64 * POSITION_STEP;
+                    row.bump();
+                }
+                let (prev, next) = neighbours(&rows)?;
+                position_between(prev, next).unwrap_or(POSITION_STEP)
+            }
+        };
+        let row = &mut rows[index];
+        row.position = position;
+        row.bump();
+        Ok(row.to_todo())
+    }
+
+    async fn merge(
+        &self,
+        user_id: uuid::Uuid,
+        target: uuid::Uuid,
+        source: uuid::Uuid,
+    ) -> Result<Todo, RepositoryError> {
+        let mut rows = self.rows.lock().unwrap();
+        let live = |id| {
+            rows.iter()
+                .position(|row: &Row| row.id == id && row.is_live(user_id))
+        };
+        let (Some(target), Some(source)) = (live(target), live(source)) else {
+            return Err(RepositoryError::NotFound);
+        };
+        rows[source].merged_into = Some(rows[target].id);
+        rows[source].bump();
+        rows[target].bump();
+        for tag in std::mem::take(&mut rows[source].tags) {
+            if rows[target].tags.iter().all(|had| had.id != tag.id) {
+                rows[target].tags.push(tag);
+            }
+        }
+        rows[target].tags.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(rows[target].to_todo())
+    }
+}
@@ -0,0 +1,142 @@
+//! Running the [`MIGRATOR`] for `migrate up`, `down` and `status`. Most
+//! migrations are one-way; `down` only reverts those with a `.down.sql`
+//! next to their `.up.sql`, and stops before reverting any when one of the
+//! migrations to revert has none.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+
+use super::MIGRATOR;
+
+/// Where a migration is at in the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Applied(DateTime<Utc>),
+    Pending,
+    /// Started and failed halfway, which needs fixing by hand before
+    /// anything else is migrated.
+    Failed,
+    /// Applied, but the build's file has changed since.
+    Modified,
+    /// Applied by a newer build than this one.
+    Unknown,
+}
+
+#[derive(Debug)]
+pub struct Status {
+    pub version: i64,
+    pub description: String,
+    pub state: State,
+}
+
+/// Applies the pending migrations.
+pub async fn up(conn: &mut PgConnection) -> anyhow::Result<()> {
+    // migrations may take longer than any request
+    sqlx::query("set statement_timeout = 0")
+        .execute(&mut *conn)
+        .await?;
+    let migrated = MIGRATOR.run(&mut *conn).await;
+    // before failing, the connection may go back to a pool
+    sqlx::query("reset statement_timeout")
+        .execute(&mut *conn)
+        .await?;
+    migrated.context("failed to migrate")?;
+    Ok(())
+}
+
+/// Reverts the `steps` latest applied migrations, returning their versions,
+/// latest first.
+pub async fn down(conn: &mut PgConnection, steps: usize) -> anyhow::Result<Vec<i64>> {
+    let applied: Vec<i64> = applied(conn)
+        .await?
+        .into_iter()
+        .filter(|migration| migration.success)
+        .map(|migration| migration.version)
+        .rev()
+        .collect();
+    let reverted: Vec<i64> = applied.iter().copied().take(steps).collect();
+    for version in &reverted {
+        anyhow::ensure!(
+            MIGRATOR
+                .iter()
+                .any(|m| m.version == *version && m.migration_type.is_down_migration()),
+            "migration {version} can't be reverted, it has no down script"
+        );
+    }
+    // every migration past the one left latest, which are the ones checked
+    let target = applied.get(steps).copied().unwrap_or(0);
+    sqlx::query("set statement_timeout = 0")
+        .execute(&mut *conn)
+        .await?;
+    let reverted_all = MIGRATOR.undo(&mut *conn, target).await;
+    sqlx::query("reset statement_timeout")
+        .execute(&mut *conn)
+        .await?;
+    reverted_all.context("failed to revert")?;
+    Ok(reverted)
+}
+
+/// The build's migrations and those applied that it doesn't have, by
+/// version.
+pub async fn status(conn: &mut PgConnection) -> anyhow::Result<Vec<Status>> {
+    let applied = applied(conn).await?;
+    let mut statuses: Vec<Status> = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| {
+            let state = match applied.iter().find(|a| a.version == migration.version) {
+                None => State::Pending,
+                Some(applied) if !applied.success => State::Failed,
+                Some(applied) if *applied.checksum != *migration.checksum => State::Modified,
+                Some(applied) => State::Applied(applied.installed_on),
+            };
+            Status {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+    for applied in applied {
+        if !statuses
+            .iter()
+            .any(|status| status.version == applied.version)
+        {
+            statuses.push(Status {
+                version: applied.version,
+                description: applied.description,
+                state: State::Unknown,
+            });
+        }
+    }
+    statuses.sort_by_key(|status| status.version);
+    Ok(statuses)
+}
+
+#[derive(sqlx::FromRow)]
+struct Applied {
+    version: i64,
+    description: String,
+    installed_on: DateTime<Utc>,
+    success: bool,
+    checksum: Vec<u8>,
+}
+
+/// The migrations recorded as applied, by version; none before the first
+/// migration creates the table.
+async fn applied(conn: &mut PgConnection) -> anyhow::Result<Vec<Applied>> {
+    let migrated: bool = sqlx::query_scalar("select to_regclass('_sqlx_migrations') is not null")
+        .fetch_one(&mut *conn)
+        .await?;
+    if !migrated {
+        return Ok(Vec::new());
+    }
+    let applied = sqlx::query_as(
+        r#"select version, description, installed_on, success, checksum
+        from "_sqlx_migrations" order by version"#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(applied)
+}
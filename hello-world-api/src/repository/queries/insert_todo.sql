@@ -0,0 +1,11 @@
+insert into "todo" (user_id, todo_text, start_at, search_config, due_at, expires_at, id, list_id,
+    priority, recurrence)
+values ($1, $2, $3, $4::text::regconfig, $5, $6, $7, $8,
+    -- a workspace's todos get its default priority
+    coalesce($9, (select (settings->>'default_priority')::"priority" from "workspace" where id = $1)),
+    $10)
+returning id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+    list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+    position, null::timestamptz as deleted_at, null::jsonb as field_modified,
+    '[]'::json as "tags!: sqlx::types::Json<Vec<Tag>>",
+    null::integer as completion_percent, null::text as shared_by
@@ -0,0 +1,8 @@
+select id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+    list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+    position, null::timestamptz as deleted_at, field_modified as "field_modified?",
+    todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+    todo_completion(id) as completion_percent, todo_shared_by(user_id, $2) as shared_by
+from "todo"
+where id = $1 and (user_id = $2 or todo_permission(id, $2) is not null)
+    and merged_into is null and deleted_at is null
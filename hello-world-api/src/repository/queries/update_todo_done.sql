@@ -0,0 +1,10 @@
+update "todo"
+set is_done = $1, completed_at = case when $1 then coalesce(completed_at, now()) end
+where id = $2 and (user_id = $3 or todo_permission(id, $3) = 'editor')
+    and merged_into is null and deleted_at is null
+    and ($4::bigint[] is null or version = any($4))
+returning id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+    list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+    position, null::timestamptz as deleted_at, null::jsonb as field_modified,
+    todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+    todo_completion(id) as completion_percent, todo_shared_by(user_id, $3) as shared_by
@@ -0,0 +1,809 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::models::Priority;
+
+/// Keyset position in a listing in the user's order: the todos after the
+/// one at `position` with `id`. Clients get it as an opaque string, the
+/// base64 of both.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cursor {
+    pub position: f64,
+    pub id: uuid::Uuid,
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // the bits of the position, so it comes back exactly
+        let position = format!("{:x}.{}", self.position.to_bits(), self.id.simple());
+        f.write_str(&URL_SAFE_NO_PAD.encode(position))
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || "Not a cursor of this listing, give a next_cursor".to_owned();
+        let position = URL_SAFE_NO_PAD.decode(value).map_err(|_| invalid())?;
+        let position = String::from_utf8(position).map_err(|_| invalid())?;
+        let (bits, id) = position.split_once('.').ok_or_else(invalid)?;
+        Ok(Cursor {
+            position: u64::from_str_radix(bits, 16)
+                .map(f64::from_bits)
+                .map_err(|_| invalid())?,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Columns a todo listing can be ordered by. Only these ever reach the SQL
+/// text, so sort input from clients can't inject anything.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TodoSortField {
+    Id,
+    Text,
+    IsDone,
+    StartAt,
+    DueAt,
+    Priority,
+    CreatedAt,
+    UpdatedAt,
+    Position,
+}
+
+impl std::str::FromStr for TodoSortField {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "id" => Ok(TodoSortField::Id),
+            "text" => Ok(TodoSortField::Text),
+            "is_done" => Ok(TodoSortField::IsDone),
+            "start_at" => Ok(TodoSortField::StartAt),
+            "due_at" => Ok(TodoSortField::DueAt),
+            "priority" => Ok(TodoSortField::Priority),
+            "created_at" => Ok(TodoSortField::CreatedAt),
+            "updated_at" => Ok(TodoSortField::UpdatedAt),
+            "position" => Ok(TodoSortField::Position),
+            other => Err(format!("Cannot sort by {other}")),
+        }
+    }
+}
+
+impl TodoSortField {
+    fn column(self) -> &'static str {
+        match self {
+            TodoSortField::Id => "id",
+            TodoSortField::Text => "todo_text",
+            TodoSortField::IsDone => "is_done",
+            TodoSortField::StartAt => "start_at",
+            TodoSortField::DueAt => "due_at",
+            TodoSortField::Priority => "priority",
+            TodoSortField::CreatedAt => "created_at",
+            TodoSortField::UpdatedAt => "updated_at",
+            TodoSortField::Position => "position",
+        }
+    }
+}
+
+/// Fields a listing can be trimmed to with `fields`, by their names in
+/// the todo's view.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TodoField {
+    Id,
+    Text,
+    IsDone,
+    Status,
+    StartAt,
+    DueAt,
+    ExpiresAt,
+    ListId,
+    AssigneeId,
+    Priority,
+    Recurrence,
+    Tags,
+    CompletionPercent,
+    SharedBy,
+    CreatedAt,
+    UpdatedAt,
+    Position,
+    DeletedAt,
+    Etag,
+}
+
+impl std::str::FromStr for TodoField {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        TodoField::ALL
+            .into_iter()
+            .find(|field| field.name() == value)
+            .ok_or_else(|| format!("No field {value}"))
+    }
+}
+
+impl TodoField {
+    const ALL: [TodoField; 19] = [
+        TodoField::Id,
+        TodoField::Text,
+        TodoField::IsDone,
+        TodoField::Status,
+        TodoField::StartAt,
+        TodoField::DueAt,
+        TodoField::ExpiresAt,
+        TodoField::ListId,
+        TodoField::AssigneeId,
+        TodoField::Priority,
+        TodoField::Recurrence,
+        TodoField::Tags,
+        TodoField::CompletionPercent,
+        TodoField::SharedBy,
+        TodoField::CreatedAt,
+        TodoField::UpdatedAt,
+        TodoField::Position,
+        TodoField::DeletedAt,
+        TodoField::Etag,
+    ];
+
+    /// The field's key in the view.
+    pub fn name(self) -> &'static str {
+        match self {
+            TodoField::Id => "id",
+            TodoField::Text => "text",
+            TodoField::IsDone => "is_done",
+            TodoField::Status => "status",
+            TodoField::StartAt => "start_at",
+            TodoField::DueAt => "due_at",
+            TodoField::ExpiresAt => "expires_at",
+            TodoField::ListId => "list_id",
+            TodoField::AssigneeId => "assignee_id",
+            TodoField::Priority => "priority",
+            TodoField::Recurrence => "recurrence",
+            TodoField::Tags => "tags",
+            TodoField::CompletionPercent => "completion_percent",
+            TodoField::SharedBy => "shared_by",
+            TodoField::CreatedAt => "created_at",
+            TodoField::UpdatedAt => "updated_at",
+            TodoField::Position => "position",
+            TodoField::DeletedAt => "deleted_at",
+            TodoField::Etag => "etag",
+        }
+    }
+
+    /// The columns of [`COLUMNS`] the field is read from.
+    fn columns(self) -> &'static [&'static str] {
+        match self {
+            // always selected, the order and cursors need them
+            TodoField::Id | TodoField::Position => &[],
+            TodoField::Text => &["todo_text"],
+            TodoField::IsDone => &["is_done"],
+            TodoField::Status => &["is_done", "expired_at"],
+            TodoField::StartAt => &["start_at"],
+            TodoField::DueAt => &["due_at"],
+            TodoField::ExpiresAt => &["expires_at"],
+            TodoField::ListId => &["list_id"],
+            TodoField::AssigneeId => &["assignee_id"],
+            TodoField::Priority => &["priority"],
+            TodoField::Recurrence => &["recurrence"],
+            TodoField::Tags => &["tags"],
+            TodoField::CompletionPercent => &["completion_percent"],
+            TodoField::SharedBy => &["shared_by"],
+            TodoField::CreatedAt => &["created_at"],
+            TodoField::UpdatedAt => &["updated_at"],
+            TodoField::DeletedAt => &["deleted_at"],
+            TodoField::Etag => &["version"],
+        }
+    }
+}
+
+/// Parses `id,text` style field lists.
+pub fn parse_fields(spec: &str) -> Result<Vec<TodoField>, String> {
+    spec.split(',')
+        .filter(|name| !name.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// The columns a listing reads into a todo besides `id`, `position` and
+/// `field_modified`, with what is selected instead when no field asked for
+/// needs them: a placeholder of the column's type, or nothing for the ones
+/// the todo has a default for.
+const COLUMNS: [(&str, &str, Option<&str>); 16] = [
+    ("todo_text", "todo_text", Some("''::text as todo_text")),
+    ("is_done", "is_done", Some("false as is_done")),
+    (
+        "start_at",
+        "start_at",
+        Some("null::timestamptz as start_at"),
+    ),
+    ("due_at", "due_at", Some("null::timestamptz as due_at")),
+    (
+        "expires_at",
+        "expires_at",
+        Some("null::timestamptz as expires_at"),
+    ),
+    (
+        "expired_at",
+        "expired_at",
+        Some("null::timestamptz as expired_at"),
+    ),
+    ("version", "version", Some("0::bigint as version")),
+    ("list_id", "list_id", Some("null::uuid as list_id")),
+    (
+        "assignee_id",
+        "assignee_id",
+        Some("null::uuid as assignee_id"),
+    ),
+    ("priority", "priority", Some("null::priority as priority")),
+    ("recurrence", "recurrence", Some("null::text as recurrence")),
+    (
+        "created_at",
+        "created_at",
+        Some("'epoch'::timestamptz as created_at"),
+    ),
+    (
+        "updated_at",
+        "updated_at",
+        Some("'epoch'::timestamptz as updated_at"),
+    ),
+    ("deleted_at", "deleted_at", None),
+    ("tags", "todo_tags(id) as tags", None),
+    (
+        "completion_percent",
+        "todo_completion(id) as completion_percent",
+        None,
+    ),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    fn keyword(self) -> &'static str {
+        match self {
+            SortDirection::Asc => "asc",
+            SortDirection::Desc => "desc",
+        }
+    }
+}
+
+/// Filters, ordering and pagination for listing todos. Every value supplied
+/// by a client is sent as a bind parameter; only whitelisted identifiers and
+/// keywords are written into the SQL itself.
+#[derive(Clone, Debug)]
+pub struct TodoQuery {
+    pub is_done: Option<bool>,
+    /// Whether the start date has been reached; todos without one have
+    /// always started.
+    pub started: Option<bool>,
+    /// Whether the todo is open and past its due date.
+    pub overdue: Option<bool>,
+    pub due_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// Name of a tag the todos must have.
+    pub tag: Option<String>,
+    pub list_id: Option<uuid::Uuid>,
+    /// The user the todos are assigned to.
+    pub assignee_id: Option<uuid::Uuid>,
+    pub priority: Option<Priority>,
+    /// Whether the expiry job cancelled the todo.
+    pub expired: Option<bool>,
+    /// Also list soft-deleted todos.
+    pub include_deleted: bool,
+    /// Also list the todos shared with the user, with `shared_by` set.
+    pub include_shared: bool,
+    /// Case-insensitive substring match on the todo text.
+    pub text_contains: Option<String>,
+    /// Full-text search, parsed with each todo's own text-search
+    /// configuration so words match their stemmed forms in its language.
+    pub search: Option<String>,
+    /// Only todos changed after this time, for syncing incrementally; with
+    /// `include_deleted` that includes the ones deleted since.
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    pub sort: Vec<(TodoSortField, SortDirection)>,
+    /// Only todos after the cursor's, for listings in the user's order.
+    pub after: Option<Cursor>,
+    /// Only the todos with these ids.
+    pub ids: Option<Vec<uuid::Uuid>>,
+    /// Only select the columns of these fields, the others are left as
+    /// placeholders; all of them if `None`.
+    pub fields: Option<Vec<TodoField>>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl Default for TodoQuery {
+    fn default() -> Self {
+        TodoQuery {
+            is_done: None,
+            started: None,
+            overdue: None,
+            due_before: None,
+            tag: None,
+            list_id: None,
+            assignee_id: None,
+            priority: None,
+            expired: None,
+            include_deleted: false,
+            include_shared: false,
+            text_contains: None,
+            search: None,
+            since: None,
+            sort: Vec::new(),
+            after: None,
+            ids: None,
+            fields: None,
+            limit: 10,
+            offset: 0,
+        }
+    }
+}
+
+/// Parses `text,-is_done` style sort specs; a leading `-` sorts descending.
+pub fn parse_sort(spec: &str) -> Result<Vec<(TodoSortField, SortDirection)>, String> {
+    spec.split(',')
+        .filter(|key| !key.is_empty())
+        .map(|key| match key.strip_prefix('-') {
+            Some(field) => Ok((field.parse()?, SortDirection::Desc)),
+            None => Ok((key.parse()?, SortDirection::Asc)),
+        })
+        .collect()
+}
+
+impl TodoQuery {
+    /// `select` for one page of `user_id`'s todos matching the filters.
+    pub fn build(&self, user_id: uuid::Uuid) -> QueryBuilder<'_, Postgres> {
+        let mut builder = self.build_all(user_id);
+        builder.push(" limit ").push_bind(self.limit);
+        builder.push(" offset ").push_bind(self.offset);
+        builder
+    }
+
+    /// `select` for all of `user_id`'s todos matching the filters, in the
+    /// order of [`TodoQuery::build`]'s pages, for reading as a stream.
+    pub fn build_all(&self, user_id: uuid::Uuid) -> QueryBuilder<'_, Postgres> {
+        let mut builder = QueryBuilder::new("select id, position, field_modified");
+        for (column, selected, placeholder) in COLUMNS {
+            if self.selects(column) {
+                builder.push(", ").push(selected);
+            } else if let Some(placeholder) = placeholder {
+                builder.push(", ").push(placeholder);
+            }
+        }
+        if self.include_shared && self.selects("shared_by") {
+            builder
+                .push(", todo_shared_by(user_id, ")
+                .push_bind(user_id)
+                .push(") as shared_by");
+        }
+        builder.push(r#" from "todo""#);
+        self.push_filters(&mut builder, user_id);
+        if let Some(after) = self.after {
+            builder
+                .push(" and (position, id) > (")
+                .push_bind(after.position)
+                .push(", ")
+                .push_bind(after.id)
+                .push(")");
+        }
+
+        builder.push(" order by ");
+        for (field, direction) in &self.sort {
+            builder
+                .push(field.column())
+                .push(" ")
+                .push(direction.keyword())
+                .push(", ");
+        }
+        // id is unique, so ending on it keeps pages stable between requests;
+        // unsorted listings are in the user's order, along an index
+        builder.push("position, id");
+        builder
+    }
+
+    /// Whether a field asked for is read from `column`.
+    fn selects(&self, column: &str) -> bool {
+        self.fields
+            .as_ref()
+            .is_none_or(|fields| fields.iter().any(|field| field.columns().contains(&column)))
+    }
+
+    /// `select count(*)` of all todos matching the filters, whatever page
+    /// `build` is at.
+    pub fn build_count(&self, user_id: uuid::Uuid) -> QueryBuilder<'_, Postgres> {
+        let mut builder = QueryBuilder::new(r#"select count(*) from "todo""#);
+        self.push_filters(&mut builder, user_id);
+        builder
+    }
+
+    /// `select id` of all todos matching the filters, in no order, for
+    /// changing them all at once.
+    pub fn build_ids(&self, user_id: uuid::Uuid) -> QueryBuilder<'_, Postgres> {
+        let mut builder = QueryBuilder::new(r#"select id from "todo""#);
+        self.push_filters(&mut builder, user_id);
+        builder
+    }
+
+    /// `explain` of a scan for all todos matching the filters, whose plan
+    /// says how many rows the planner expects.
+    pub fn build_estimate(&self, user_id: uuid::Uuid) -> QueryBuilder<'_, Postgres> {
+        let mut builder = QueryBuilder::new(r#"explain (format json) select 1 from "todo""#);
+        self.push_filters(&mut builder, user_id);
+        builder
+    }
+
+    fn push_filters<'a>(&'a self, builder: &mut QueryBuilder<'a, Postgres>, user_id: uuid::Uuid) {
+        if self.include_shared {
+            // the arrays are computed once, so each side of the `or` can
+            // still be an index scan
+            builder
+                .push(" where (user_id = ")
+                .push_bind(user_id)
+                .push(r#" or id = any(array(select todo_id from "todo_share" where user_id = "#)
+                .push_bind(user_id)
+                .push(r#")) or list_id = any(array(select list_id from "todo_share" where user_id = "#)
+                .push_bind(user_id)
+                .push(")))");
+        } else {
+            builder.push(" where user_id = ").push_bind(user_id);
+        }
+        // tombstones of merged todos are never listed
+        builder.push(" and merged_into is null");
+        if let Some(ids) = &self.ids {
+            builder.push(" and id = any(").push_bind(ids).push(")");
+        }
+        if !self.include_deleted {
+            builder.push(" and deleted_at is null");
+        }
+        if let Some(is_done) = self.is_done {
+            builder.push(" and is_done = ").push_bind(is_done);
+        }
+        match self.started {
+            Some(true) => builder.push(" and (start_at is null or start_at <= now())"),
+            Some(false) => builder.push(" and start_at > now()"),
+            None => builder,
+        };
+        match self.overdue {
+            Some(true) => builder.push(" and not is_done and due_at < now()"),
+            Some(false) => builder.push(" and (is_done or due_at is null or due_at >= now())"),
+            None => builder,
+        };
+        match self.expired {
+            Some(true) => builder.push(" and expired_at is not null"),
+            Some(false) => builder.push(" and expired_at is null"),
+            None => builder,
+        };
+        if let Some(due_before) = self.due_before {
+            builder.push(" and due_at < ").push_bind(due_before);
+        }
+        if let Some(since) = self.since {
+            builder.push(" and updated_at > ").push_bind(since);
+        }
+        if let Some(tag) = &self.tag {
+            builder
+                .push(
+                    r#" and exists (select 1 from "todo_tag" tt join "tag" g on g.id = tt.tag_id
+                    where tt.todo_id = "todo".id and g.name = "#,
+                )
+                .push_bind(tag)
+                .push(")");
+        }
+        if let Some(list_id) = self.list_id {
+            builder.push(" and list_id = ").push_bind(list_id);
+        }
+        if let Some(assignee_id) = self.assignee_id {
+            builder.push(" and assignee_id = ").push_bind(assignee_id);
+        }
+        if let Some(priority) = self.priority {
+            builder.push(" and priority = ").push_bind(priority);
+        }
+        if let Some(text) = &self.text_contains {
+            builder
+                .push(" and ")
+                .push("todo_text ilike ")
+                .push_bind(format!("%{}%", escape_like(text)))
+                .push(r" escape '\'");
+        }
+        if let Some(search) = &self.search {
+            builder
+                .push(" and search_vector @@ websearch_to_tsquery(search_config, ")
+                .push_bind(search)
+                .push(")");
+        }
+    }
+}
+
+/// Escapes `LIKE` wildcards so user text only ever matches literally.
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: uuid::Uuid = uuid::Uuid::from_u128(1);
+
+    #[test]
+    fn unfiltered_page_in_the_users_order() {
+        let query = TodoQuery::default();
+        assert_eq!(
+            query.build(USER).sql(),
+            "select id, position, field_modified, todo_text, is_done, start_at, due_at, \
+             expires_at, expired_at, version, list_id, assignee_id, priority, recurrence, \
+             created_at, updated_at, deleted_at, todo_tags(id) as tags, \
+             todo_completion(id) as completion_percent from \"todo\" \
+             where user_id = $1 and merged_into is null and deleted_at is null \
+             order by position, id limit $2 offset $3"
+        );
+    }
+
+    #[test]
+    fn filters_are_bound_in_order() {
+        let query = TodoQuery {
+            ids: Some(vec![USER]),
+            is_done: Some(false),
+            started: Some(true),
+            overdue: Some(false),
+            expired: Some(true),
+            due_before: Some(chrono::Utc::now()),
+            since: Some(chrono::Utc::now()),
+            tag: Some("home".to_owned()),
+            list_id: Some(USER),
+            priority: Some(Priority::High),
+            text_contains: Some("milk".to_owned()),
+            search: Some("buy milk".to_owned()),
+            include_deleted: true,
+            ..TodoQuery::default()
+        };
+        let builder = query.build(USER);
+        let sql = builder.sql();
+        let filters = sql.split_once(" where ").unwrap().1;
+        let mut rest = filters;
+        for expected in [
+            "user_id = $1",
+            "and merged_into is null",
+            "and id = any($2)",
+            "and is_done = $3",
+            "and (start_at is null or start_at <= now())",
+            "and (is_done or due_at is null or due_at >= now())",
+            "and expired_at is not null",
+            "and due_at < $4",
+            "and updated_at > $5",
+            "g.name = $6)",
+            "and list_id = $7",
+            "and priority = $8",
+            r"and todo_text ilike $9 escape '\'",
+            "websearch_to_tsquery(search_config, $10)",
+            "order by position, id limit $11 offset $12",
+        ] {
+            let at = rest
+                .find(expected)
+                .unwrap_or_else(|| panic!("{expected:?} not in order in {filters}"));
+            rest = &rest[at + expected.len()..];
+        }
+        assert!(!filters.contains("deleted_at is null"));
+        assert_eq!(
+            query.build_count(USER).sql(),
+            format!("select count(*) from \"todo\" where {}", {
+                let (filters, _) = filters.split_once(" order by ").unwrap();
+                filters.to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn filters_left_out_bind_nothing() {
+        let query = TodoQuery {
+            started: Some(false),
+            overdue: Some(true),
+            expired: Some(false),
+            ..TodoQuery::default()
+        };
+        assert_eq!(
+            query.build_count(USER).sql(),
+            "select count(*) from \"todo\" where user_id = $1 and merged_into is null \
+             and deleted_at is null and start_at > now() \
+             and not is_done and due_at < now() and expired_at is null"
+        );
+        assert_eq!(
+            query.build_estimate(USER).sql(),
+            "explain (format json) select 1 from \"todo\" where user_id = $1 \
+             and merged_into is null and deleted_at is null and start_at > now() \
+             and not is_done and due_at < now() and expired_at is null"
+        );
+    }
+
+    #[test]
+    fn shared_todos_bind_the_user_for_each_side() {
+        let query = TodoQuery {
+            include_shared: true,
+            is_done: Some(true),
+            ..TodoQuery::default()
+        };
+        let builder = query.build(USER);
+        let sql = builder.sql();
+        assert!(sql.contains(
+            "todo_completion(id) as completion_percent, todo_shared_by(user_id, $1) as shared_by \
+             from \"todo\""
+        ));
+        assert!(sql.contains(
+            "where (user_id = $2 \
+             or id = any(array(select todo_id from \"todo_share\" where user_id = $3)) \
+             or list_id = any(array(select list_id from \"todo_share\" where user_id = $4))) \
+             and merged_into is null"
+        ));
+        assert!(sql.ends_with("and is_done = $5 order by position, id limit $6 offset $7"));
+
+        // the count selects no shared_by
+        let builder = query.build_count(USER);
+        assert!(builder.sql().starts_with(
+            "select count(*) from \"todo\" where (user_id = $1 or id = any(array(select todo_id \
+             from \"todo_share\" where user_id = $2))"
+        ));
+    }
+
+    #[test]
+    fn cursor_and_sort_come_after_the_filters() {
+        let query = TodoQuery {
+            is_done: Some(true),
+            after: Some(Cursor {
+                position: 1.5,
+                id: USER,
+            }),
+            sort: vec![
+                (TodoSortField::Priority, SortDirection::Desc),
+                (TodoSortField::Text, SortDirection::Asc),
+            ],
+            ..TodoQuery::default()
+        };
+        let builder = query.build(USER);
+        assert!(builder.sql().ends_with(
+            "and is_done = $2 and (position, id) > ($3, $4) \
+             order by priority desc, todo_text asc, position, id limit $5 offset $6"
+        ));
+        // a stream reads all of them
+        let builder = query.build_all(USER);
+        assert!(builder
+            .sql()
+            .ends_with("order by priority desc, todo_text asc, position, id"));
+    }
+
+    #[test]
+    fn fields_not_asked_for_are_placeholders() {
+        let query = TodoQuery {
+            fields: Some(vec![TodoField::Text, TodoField::Status]),
+            include_shared: true,
+            ..TodoQuery::default()
+        };
+        let builder = query.build(USER);
+        let (select, _) = builder.sql().split_once(" from ").unwrap();
+        assert_eq!(
+            select,
+            "select id, position, field_modified, todo_text, is_done, \
+             null::timestamptz as start_at, null::timestamptz as due_at, \
+             null::timestamptz as expires_at, expired_at, 0::bigint as version, \
+             null::uuid as list_id, null::uuid as assignee_id, null::priority as priority, \
+             null::text as recurrence, \
+             'epoch'::timestamptz as created_at, 'epoch'::timestamptz as updated_at"
+        );
+        // shared_by isn't selected, so the filters bind the user first
+        assert!(builder.sql().contains("where (user_id = $1 "));
+
+        let query = TodoQuery {
+            fields: Some(vec![TodoField::Tags, TodoField::Etag]),
+            ..TodoQuery::default()
+        };
+        let builder = query.build(USER);
+        let (select, _) = builder.sql().split_once(" from ").unwrap();
+        assert!(select.contains(", version, "));
+        assert!(select.ends_with(", todo_tags(id) as tags"));
+        assert!(!select.contains("deleted_at"));
+        assert!(!select.contains("todo_completion"));
+    }
+
+    #[test]
+    fn parses_sort_specs() {
+        assert_eq!(
+            parse_sort("text,-is_done,,position"),
+            Ok(vec![
+                (TodoSortField::Text, SortDirection::Asc),
+                (TodoSortField::IsDone, SortDirection::Desc),
+                (TodoSortField::Position, SortDirection::Asc),
+            ])
+        );
+        assert_eq!(parse_sort(""), Ok(Vec::new()));
+        assert_eq!(
+            parse_sort("text,-todo_text"),
+            Err("Cannot sort by todo_text".to_owned())
+        );
+        assert_eq!(parse_sort("--id"), Err("Cannot sort by -id".to_owned()));
+        assert_eq!(
+            parse_sort("id desc"),
+            Err("Cannot sort by id desc".to_owned())
+        );
+    }
+
+    #[test]
+    fn parses_field_lists() {
+        assert_eq!(
+            parse_fields("id,text,,etag"),
+            Ok(vec![TodoField::Id, TodoField::Text, TodoField::Etag])
+        );
+        for field in TodoField::ALL {
+            assert_eq!(field.name().parse(), Ok(field));
+        }
+        assert_eq!(
+            parse_fields("id,todo_text"),
+            Err("No field todo_text".to_owned())
+        );
+        assert_eq!(parse_fields("ID"), Err("No field ID".to_owned()));
+    }
+
+    #[test]
+    fn cursors_round_trip_exactly() {
+        for position in [
+            0.0,
+            -0.0,
+            1.5,
+            -3.25,
+            f64::MIN_POSITIVE,
+            f64::MAX,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ] {
+            let cursor = Cursor {
+                position,
+                id: uuid::Uuid::new_v4(),
+            };
+            let parsed: Cursor = cursor.to_string().parse().unwrap();
+            assert_eq!(parsed.position.to_bits(), position.to_bits());
+            assert_eq!(parsed.id, cursor.id);
+        }
+        let nan = Cursor {
+            position: f64::NAN,
+            id: USER,
+        };
+        let parsed: Cursor = nan.to_string().parse().unwrap();
+        assert!(parsed.position.is_nan());
+        assert!(!nan.to_string().contains(['+', '/', '=']));
+    }
+
+    #[test]
+    fn rejects_strings_that_arent_cursors() {
+        let encode = |text: &str| URL_SAFE_NO_PAD.encode(text);
+        for value in [
+            "",
+            "not base64!",
+            &encode("3ff8000000000000"),
+            &encode("zz.00000000000000000000000000000001"),
+            &encode("3ff8000000000000.not-a-uuid"),
+            &URL_SAFE_NO_PAD.encode([0xff, 0xfe]),
+        ] {
+            assert_eq!(
+                value.parse::<Cursor>(),
+                Err("Not a cursor of this listing, give a next_cursor".to_owned()),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn escapes_like_wildcards() {
+        assert_eq!(escape_like("milk"), "milk");
+        assert_eq!(escape_like("100%"), r"100\%");
+        assert_eq!(escape_like("a_b"), r"a\_b");
+        assert_eq!(escape_like(r"C:\temp"), r"C:\\temp");
+        assert_eq!(escape_like("ü_%"), r"ü\_\%");
+    }
+}
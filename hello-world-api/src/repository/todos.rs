@@ -0,0 +1,1100 @@
+//! Queries on the `todo` table, and the [`PgTodoRepository`] running them.
+//! Each only sees the todos of the user it is given, except that reads
+//! also find the todos shared with them and updates the ones shared with
+//! them as editor. Merged tombstones and deleted todos are never returned,
+//! except by listings asking for deleted ones.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    ops::{Deref, DerefMut},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use sqlx::{pool::PoolConnection, Connection, PgConnection, PgPool, Postgres};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{warn, Instrument};
+
+use super::{
+    position_between, todo_query::TodoQuery, MoveTo, NewTodo, RepositoryError, TodoChanges,
+    TodoRepository, Total, UnitOfWork, POSITION_STEP,
+};
+use crate::{
+    language,
+    links::{self, TodoLink},
+    metrics,
+    models::{Priority, Todo},
+    resilience,
+    tags::Tag,
+    telemetry,
+    todo_share::SharePermission,
+};
+
+/// Listings the planner expects to match more todos than this get its
+/// estimate as their total, unless configured otherwise.
+pub const DEFAULT_EXACT_COUNT_LIMIT: i64 = 10_000;
+
+/// Key of the advisory locks serializing a user's moves, so two racing ones
+/// don't pick their positions from the same neighbours.
+const MOVE_LOCK: i32 = 0x6d6f_7665;
+
+/// [`TodoRepository`] on the `todo` table. Runs each call on a pool
+/// connection of its own, except in a unit of work, where they all run on
+/// the unit's connection.
+pub struct PgTodoRepository<'c> {
+    pg: Pg<'c>,
+    exact_count_limit: i64,
+    slow_query_threshold: Option<Duration>,
+}
+
+/// Where a [`PgTodoRepository`] runs its queries.
+enum Pg<'c> {
+    Pool(PgPool),
+    /// A unit of work's, in its transaction.
+    Conn(Mutex<&'c mut PgConnection>),
+}
+
+impl<'c> Pg<'c> {
+    async fn acquire(&self) -> Result<Conn<'_, 'c>, sqlx::Error> {
+        Ok(match self {
+            Pg::Pool(pool) => Conn::Pooled(Box::new(pool.acquire().await?)),
+            Pg::Conn(conn) => Conn::Borrowed(conn.lock().await),
+        })
+    }
+
+    /// Runs `read`, again if it fails for a reason that may pass. In a unit
+    /// of work a failure has aborted the transaction, so it's run once.
+    async fn read<T, F, Fut>(&self, mut read: F) -> Result<T, sqlx::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        match self {
+            Pg::Pool(_) => resilience::retry(read).await,
+            Pg::Conn(_) => read().await,
+        }
+    }
+}
+
+/// The connection a call runs its queries on.
+enum Conn<'a, 'c> {
+    Pooled(Box<PoolConnection<Postgres>>),
+    Borrowed(MutexGuard<'a, &'c mut PgConnection>),
+}
+
+impl Deref for Conn<'_, '_> {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        match self {
+            Conn::Pooled(conn) => conn,
+            Conn::Borrowed(conn) => conn,
+        }
+    }
+}
+
+impl DerefMut for Conn<'_, '_> {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        match self {
+            Conn::Pooled(conn) => conn,
+            Conn::Borrowed(conn) => conn,
+        }
+    }
+}
+
+impl PgTodoRepository<'static> {
+    pub fn new(pg: PgPool) -> Self {
+        PgTodoRepository {
+            pg: Pg::Pool(pg),
+            exact_count_limit: DEFAULT_EXACT_COUNT_LIMIT,
+            slow_query_threshold: None,
+        }
+    }
+
+    /// Counts listings exactly only while the planner expects at most
+    /// `limit` matches; past it counting would take longer than the page.
+    pub fn exact_count_limit(self, limit: i64) -> Self {
+        PgTodoRepository {
+            exact_count_limit: limit,
+            ..self
+        }
+    }
+
+    /// Logs a warning for each query taking longer than `threshold`.
+    pub fn slow_query_threshold(self, threshold: Option<Duration>) -> Self {
+        PgTodoRepository {
+            slow_query_threshold: threshold,
+            ..self
+        }
+    }
+}
+
+impl PgTodoRepository<'_> {
+    /// Runs `query` in its span, recording how long it took in the query
+    /// metrics, and warns if that was past the slow query threshold.
+    async fn timed<T>(&self, statement: &'static str, query: impl Future<Output = T>) -> T {
+        let started = Instant::now();
+        let output = query.instrument(telemetry::query_span(statement)).await;
+        let elapsed = started.elapsed();
+        metrics::observe_query(statement, elapsed);
+        if matches!(self.slow_query_threshold, Some(threshold) if elapsed > threshold) {
+            warn!("Slow query {statement} took {}ms", elapsed.as_millis());
+        }
+        output
+    }
+
+    /// Tells a write that found no todo to change apart: one at other
+    /// versions than expected, or one the user may only view, from one they
+    /// don't have at all.
+    async fn check_version(
+        &self,
+        conn: &mut PgConnection,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        versions: Option<&[i64]>,
+        result: Result<Todo, sqlx::Error>,
+    ) -> Result<Todo, RepositoryError> {
+        match result {
+            Err(sqlx::Error::RowNotFound) => {
+                match self
+                    .timed("select_todo_access", access(conn, user_id, id))
+                    .await?
+                {
+                    Some(Access::Shared(SharePermission::Viewer)) => {
+                        Err(RepositoryError::NotPermitted)
+                    }
+                    Some(_) if versions.is_some() => Err(RepositoryError::VersionMismatch),
+                    _ => Err(RepositoryError::NotFound),
+                }
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+#[async_trait]
+impl<'c> TodoRepository for PgTodoRepository<'c> {
+    fn unit_of_work<'a>(&'a self, conn: &'a mut PgConnection) -> UnitOfWork<'a> {
+        Box::new(PgTodoRepository {
+            pg: Pg::Conn(Mutex::new(conn)),
+            exact_count_limit: self.exact_count_limit,
+            slow_query_threshold: self.slow_query_threshold,
+        })
+    }
+
+    async fn get(&self, user_id: uuid::Uuid, id: uuid::Uuid) -> Result<Todo, RepositoryError> {
+        let read = || async move {
+            let mut conn = self.pg.acquire().await?;
+            self.timed("select_todo", get(&mut conn, user_id, id)).await
+        };
+        Ok(self.pg.read(read).await?)
+    }
+
+    async fn merged_into(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<Option<uuid::Uuid>, RepositoryError> {
+        let read = || async move {
+            let mut conn = self.pg.acquire().await?;
+            self.timed("select_merged_into", merged_into(&mut conn, user_id, id))
+                .await
+        };
+        Ok(self.pg.read(read).await?)
+    }
+
+    async fn get_many(
+        &self,
+        user_id: uuid::Uuid,
+        ids: &[uuid::Uuid],
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let read = || async move {
+            let mut conn = self.pg.acquire().await?;
+            self.timed("select_todos", get_many(&mut conn, user_id, ids))
+                .await
+        };
+        Ok(self.pg.read(read).await?)
+    }
+
+    async fn list(
+        &self,
+        user_id: uuid::Uuid,
+        query: &TodoQuery,
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let read = || async move {
+            let mut conn = self.pg.acquire().await?;
+            self.timed("list_todos", list(&mut conn, user_id, query))
+                .await
+        };
+        Ok(self.pg.read(read).await?)
+    }
+
+    async fn count(&self, user_id: uuid::Uuid, query: &TodoQuery) -> Result<i64, RepositoryError> {
+        let read = || async move {
+            let mut conn = self.pg.acquire().await?;
+            self.timed("count_todos", count(&mut conn, user_id, query))
+                .await
+        };
+        Ok(self.pg.read(read).await?)
+    }
+
+    async fn count_for_listing(
+        &self,
+        user_id: uuid::Uuid,
+        query: &TodoQuery,
+    ) -> Result<Total, RepositoryError> {
+        let read = || async move {
+            let mut conn = self.pg.acquire().await?;
+            let estimate = self
+                .timed("estimate_todos", estimate(&mut conn, user_id, query))
+                .await?;
+            if estimate > self.exact_count_limit {
+                return Ok(Total {
+                    count: estimate,
+                    estimated: true,
+                });
+            }
+            Ok(Total {
+                count: self
+                    .timed("count_todos", count(&mut conn, user_id, query))
+                    .await?,
+                estimated: false,
+            })
+        };
+        Ok(self.pg.read(read).await?)
+    }
+
+    async fn insert(
+        &self,
+        user_id: uuid::Uuid,
+        todo: NewTodo<'_>,
+    ) -> Result<Todo, RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        Ok(self
+            .timed("insert_todo", insert(&mut conn, user_id, todo))
+            .await?)
+    }
+
+    async fn insert_many(
+        &self,
+        user_id: uuid::Uuid,
+        todos: &[NewTodo<'_>],
+    ) -> Result<Vec<Result<Todo, RepositoryError>>, RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        Ok(self
+            .timed("insert_todos", insert_many(&mut conn, user_id, todos))
+            .await?)
+    }
+
+    async fn set_done(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        is_done: bool,
+        versions: Option<&[i64]>,
+    ) -> Result<Todo, RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        let result = self
+            .timed(
+                "update_todo_done",
+                set_done(&mut conn, user_id, id, is_done, versions),
+            )
+            .await;
+        self.check_version(&mut conn, user_id, id, versions, result)
+            .await
+    }
+
+    async fn toggle_done(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<Todo, RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        let result = self
+            .timed("toggle_todo_done", toggle_done(&mut conn, user_id, id))
+            .await;
+        self.check_version(&mut conn, user_id, id, None, result)
+            .await
+    }
+
+    async fn complete_many(
+        &self,
+        user_id: uuid::Uuid,
+        ids: &[uuid::Uuid],
+    ) -> Result<Vec<Result<Todo, RepositoryError>>, RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        Ok(self
+            .timed("complete_todos", complete_many(&mut conn, user_id, ids))
+            .await?)
+    }
+
+    async fn update(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        changes: TodoChanges<'_>,
+        versions: Option<&[i64]>,
+    ) -> Result<Todo, RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        let result = self
+            .timed(
+                "update_todo",
+                update(&mut conn, user_id, id, changes, versions),
+            )
+            .await;
+        self.check_version(&mut conn, user_id, id, versions, result)
+            .await
+    }
+
+    async fn add_tags(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        names: &[String],
+    ) -> Result<Todo, RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        Ok(self
+            .timed("add_todo_tags", add_tags(&mut conn, user_id, id, names))
+            .await?)
+    }
+
+    async fn links(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<Vec<TodoLink>, RepositoryError> {
+        let read = || async move {
+            let mut conn = self.pg.acquire().await?;
+            self.timed("select_todo_links", links::list(&mut *conn, user_id, id))
+                .await
+        };
+        Ok(self.pg.read(read).await?)
+    }
+
+    async fn soft_delete(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<(), RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        let result = self
+            .timed("soft_delete_todo", soft_delete(&mut conn, user_id, id))
+            .await;
+        match result {
+            // only the owner deletes a todo, whoever else it is shared with
+            Err(sqlx::Error::RowNotFound) => match self
+                .timed("select_todo_access", access(&mut conn, user_id, id))
+                .await?
+            {
+                Some(Access::Shared(_)) => Err(RepositoryError::NotPermitted),
+                _ => Err(RepositoryError::NotFound),
+            },
+            result => Ok(result?),
+        }
+    }
+
+    async fn trash(
+        &self,
+        user_id: uuid::Uuid,
+        kept_for: Duration,
+        limit: i64,
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let read = || async move {
+            let mut conn = self.pg.acquire().await?;
+            self.timed(
+                "list_deleted_todos",
+                trash(&mut conn, user_id, kept_for, limit),
+            )
+            .await
+        };
+        Ok(self.pg.read(read).await?)
+    }
+
+    async fn restore(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        kept_for: Duration,
+    ) -> Result<Todo, RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        Ok(self
+            .timed("restore_todo", restore(&mut conn, user_id, id, kept_for))
+            .await?)
+    }
+
+    async fn move_to(
+        &self,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+        to: MoveTo,
+    ) -> Result<Todo, RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        let result = self
+            .timed("move_todo", move_to(&mut conn, user_id, id, to))
+            .await;
+        match result {
+            // todos are in their owner's order, whoever else they are shared with
+            Err(sqlx::Error::RowNotFound) => match self
+                .timed("select_todo_access", access(&mut conn, user_id, id))
+                .await?
+            {
+                Some(Access::Shared(_)) => Err(RepositoryError::NotPermitted),
+                _ => Err(RepositoryError::NotFound),
+            },
+            result => Ok(result?),
+        }
+    }
+
+    async fn merge(
+        &self,
+        user_id: uuid::Uuid,
+        target: uuid::Uuid,
+        source: uuid::Uuid,
+    ) -> Result<Todo, RepositoryError> {
+        let mut conn = self.pg.acquire().await?;
+        Ok(self
+            .timed("merge_todos", merge(&mut conn, user_id, target, source))
+            .await?)
+    }
+}
+
+// the hot queries, also prepared by `warm_up`
+pub(super) const SELECT_TODO: &str = include_str!("queries/select_todo.sql");
+pub(super) const UPDATE_TODO_DONE: &str = include_str!("queries/update_todo_done.sql");
+pub(super) const INSERT_TODO: &str = include_str!("queries/insert_todo.sql");
+
+async fn get(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+) -> Result<Todo, sqlx::Error> {
+    sqlx::query_file_as!(Todo, "src/repository/queries/select_todo.sql", id, user_id)
+        .fetch_one(conn)
+        .await
+}
+
+/// The todo a merged todo's tombstone points at, `None` for any other id.
+async fn merged_into(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+) -> Result<Option<uuid::Uuid>, sqlx::Error> {
+    let merged_into = sqlx::query_scalar::<_, Option<uuid::Uuid>>(
+        r#"select merged_into from "todo" where id = $1 and user_id = $2"#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await?;
+    Ok(merged_into.flatten())
+}
+
+async fn get_many(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    ids: &[uuid::Uuid],
+) -> Result<Vec<Todo>, sqlx::Error> {
+    sqlx::query_as!(
+        Todo,
+        r#"select id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, null::timestamptz as deleted_at, null::jsonb as field_modified,
+            '[]'::json as "tags!: sqlx::types::Json<Vec<Tag>>",
+            null::integer as completion_percent, todo_shared_by(user_id, $2) as shared_by
+        from "todo"
+        where id = any($1) and (user_id = $2 or todo_permission(id, $2) is not null)
+            and merged_into is null and deleted_at is null"#,
+        ids,
+        user_id,
+    )
+    .fetch_all(conn)
+    .await
+}
+
+/// One page of the todos matching `query`.
+async fn list(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    query: &TodoQuery,
+) -> Result<Vec<Todo>, sqlx::Error> {
+    query
+        .build(user_id)
+        .build_query_as::<Todo>()
+        .fetch_all(conn)
+        .await
+}
+
+/// All todos matching `query`'s filters, across pages.
+async fn count(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    query: &TodoQuery,
+) -> Result<i64, sqlx::Error> {
+    let (count,) = query
+        .build_count(user_id)
+        .build_query_as::<(i64,)>()
+        .fetch_one(conn)
+        .await?;
+    Ok(count)
+}
+
+/// The planner's guess at how many todos match `query`'s filters, read off
+/// the plan of its count without running it.
+async fn estimate(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    query: &TodoQuery,
+) -> Result<i64, sqlx::Error> {
+    let (plan,) = query
+        .build_estimate(user_id)
+        .build_query_as::<(sqlx::types::Json<serde_json::Value>,)>()
+        .fetch_one(conn)
+        .await?;
+    Ok(plan.0[0]["Plan"]["Plan Rows"].as_f64().unwrap_or(0.0) as i64)
+}
+
+async fn insert(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    todo: NewTodo<'_>,
+) -> Result<Todo, sqlx::Error> {
+    sqlx::query_file_as!(
+        Todo,
+        "src/repository/queries/insert_todo.sql",
+        user_id,
+        todo.text,
+        todo.start_at,
+        language::search_config(todo.text),
+        todo.due_at,
+        todo.expires_at,
+        Todo::new_id(),
+        todo.list_id,
+        todo.priority as Option<Priority>,
+        todo.recurrence,
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// All of `todos` in one transaction, with a savepoint around each so the
+/// ones the database refuses, such as duplicates, don't abort the others.
+async fn insert_many(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    todos: &[NewTodo<'_>],
+) -> Result<Vec<Result<Todo, RepositoryError>>, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    let mut results = Vec::with_capacity(todos.len());
+    for todo in todos {
+        let mut savepoint = tx.begin().await?;
+        let result = sqlx::query_file_as!(
+            Todo,
+            "src/repository/queries/insert_todo.sql",
+            user_id,
+            todo.text,
+            todo.start_at,
+            language::search_config(todo.text),
+            todo.due_at,
+            todo.expires_at,
+            Todo::new_id(),
+            todo.list_id,
+            todo.priority as Option<Priority>,
+            todo.recurrence,
+        )
+        .fetch_one(&mut savepoint)
+        .await;
+        match result {
+            Ok(todo) => {
+                savepoint.commit().await?;
+                results.push(Ok(todo));
+            }
+            Err(err @ sqlx::Error::Database(_)) => {
+                savepoint.rollback().await?;
+                results.push(Err(err.into()));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    tx.commit().await?;
+    Ok(results)
+}
+
+async fn set_done(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+    is_done: bool,
+    versions: Option<&[i64]>,
+) -> Result<Todo, sqlx::Error> {
+    sqlx::query_file_as!(
+        Todo,
+        "src/repository/queries/update_todo_done.sql",
+        is_done,
+        id,
+        user_id,
+        versions,
+    )
+    .fetch_one(conn)
+    .await
+}
+
+async fn toggle_done(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+) -> Result<Todo, sqlx::Error> {
+    sqlx::query_file_as!(
+        Todo,
+        "src/repository/queries/toggle_todo_done.sql",
+        id,
+        user_id,
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// Completes all of `ids` in one statement.
+async fn complete_many(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    ids: &[uuid::Uuid],
+) -> Result<Vec<Result<Todo, RepositoryError>>, sqlx::Error> {
+    let completed = sqlx::query_as!(
+        Todo,
+        r#"update "todo"
+        set is_done = true, completed_at = coalesce(completed_at, now())
+        where id = any($1) and user_id = $2 and merged_into is null and deleted_at is null
+        returning id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, null::timestamptz as deleted_at, null::jsonb as field_modified,
+            todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+            todo_completion(id) as completion_percent, null::text as shared_by"#,
+        ids,
+        user_id,
+    )
+    .fetch_all(conn)
+    .await?;
+    let completed: HashMap<_, _> = completed.into_iter().map(|todo| (todo.id, todo)).collect();
+    Ok(ids
+        .iter()
+        .map(|id| completed.get(id).cloned().ok_or(RepositoryError::NotFound))
+        .collect())
+}
+
+/// Fields bound as null keep their value, except the dates and the list: the
+/// due date is set to `$7` whenever `$6` is true, the expiry to `$9` whenever
+/// `$8` is, which also revives an expired todo, the list to `$12` whenever
+/// `$11` is, the priority to `$14` whenever `$13` is and the recurrence to
+/// `$16` whenever `$15` is. Only updates a todo at one of the versions `$10`,
+/// if bound.
+async fn update(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+    changes: TodoChanges<'_>,
+    versions: Option<&[i64]>,
+) -> Result<Todo, sqlx::Error> {
+    sqlx::query_as!(
+        Todo,
+        r#"update "todo"
+        set todo_text = coalesce($1, todo_text),
+            search_config = coalesce($2::text::regconfig, search_config),
+            is_done = coalesce($3, is_done),
+            completed_at = case when coalesce($3, is_done) then coalesce(completed_at, now()) end,
+            due_at = case when $6 then $7 else due_at end,
+            expires_at = case when $8 then $9 else expires_at end,
+            expired_at = case when $8 then null else expired_at end,
+            list_id = case when $11 then $12 else list_id end,
+            priority = case when $13 then $14 else priority end,
+            recurrence = case when $15 then $16 else recurrence end
+        where id = $4 and (user_id = $5 or todo_permission(id, $5) = 'editor')
+            and merged_into is null and deleted_at is null
+            and ($10::bigint[] is null or version = any($10))
+        returning id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, null::timestamptz as deleted_at, null::jsonb as field_modified,
+            todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+            todo_completion(id) as completion_percent, todo_shared_by(user_id, $5) as shared_by"#,
+        changes.text,
+        changes.text.map(language::search_config),
+        changes.is_done,
+        id,
+        user_id,
+        changes.due_at.is_some(),
+        changes.due_at.flatten(),
+        changes.expires_at.is_some(),
+        changes.expires_at.flatten(),
+        versions,
+        changes.list_id.is_some(),
+        changes.list_id.flatten(),
+        changes.priority.is_some(),
+        changes.priority.flatten() as Option<Priority>,
+        changes.recurrence.is_some(),
+        changes.recurrence.flatten(),
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// How a user reaches a todo.
+enum Access {
+    Owner,
+    Shared(SharePermission),
+}
+
+/// How `user_id` reaches the live todo `id`, `None` if they can't see it.
+async fn access(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+) -> Result<Option<Access>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"select user_id = $2 as "owner!",
+            todo_permission(id, $2) as "permission: SharePermission"
+        from "todo"
+        where id = $1 and merged_into is null and deleted_at is null"#,
+        id,
+        user_id,
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(row.and_then(|row| match (row.owner, row.permission) {
+        (true, _) => Some(Access::Owner),
+        (false, permission) => permission.map(Access::Shared),
+    }))
+}
+
+async fn add_tags(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+    names: &[String],
+) -> Result<Todo, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    // fails before any tag is created for a todo that isn't the user's,
+    // even one shared with them
+    sqlx::query(
+        r#"select 1 from "todo"
+        where id = $1 and user_id = $2 and merged_into is null and deleted_at is null"#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_one(&mut tx)
+    .await?;
+    sqlx::query(
+        r#"insert into "tag" (user_id, name) select $1, unnest($2::text[])
+        on conflict (user_id, name) do nothing"#,
+    )
+    .bind(user_id)
+    .bind(names)
+    .execute(&mut tx)
+    .await?;
+    sqlx::query(
+        r#"insert into "todo_tag" (todo_id, tag_id)
+        select $1, id from "tag" where user_id = $2 and name = any($3)
+        on conflict do nothing"#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(names)
+    .execute(&mut tx)
+    .await?;
+    let todo = sqlx::query_file_as!(Todo, "src/repository/queries/select_todo.sql", id, user_id)
+        .fetch_one(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(todo)
+}
+
+/// Sets `deleted_at`, failing with `RowNotFound` for unknown or already
+/// deleted todos.
+async fn soft_delete(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+) -> Result<(), sqlx::Error> {
+    let done = sqlx::query(
+        r#"update "todo" set deleted_at = now()
+        where id = $1 and user_id = $2 and merged_into is null and deleted_at is null"#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    if done.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    Ok(())
+}
+
+/// The user's own todos deleted within `kept_for`, the last deleted first.
+async fn trash(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    kept_for: Duration,
+    limit: i64,
+) -> Result<Vec<Todo>, sqlx::Error> {
+    sqlx::query_as!(
+        Todo,
+        r#"select id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, deleted_at, field_modified as "field_modified?",
+            todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+            todo_completion(id) as completion_percent, null::text as shared_by
+        from "todo"
+        where user_id = $1 and merged_into is null
+            and deleted_at > now() - make_interval(secs => $2)
+        order by deleted_at desc, id
+        limit $3"#,
+        user_id,
+        kept_for.as_secs_f64(),
+        limit,
+    )
+    .fetch_all(conn)
+    .await
+}
+
+/// Clears `deleted_at` of a todo deleted within `kept_for`, failing with
+/// `RowNotFound` for any other.
+async fn restore(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+    kept_for: Duration,
+) -> Result<Todo, sqlx::Error> {
+    sqlx::query_as!(
+        Todo,
+        r#"update "todo" set deleted_at = null
+        where id = $1 and user_id = $2 and merged_into is null
+            and deleted_at > now() - make_interval(secs => $3)
+        returning id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, null::timestamptz as deleted_at, field_modified as "field_modified?",
+            todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+            todo_completion(id) as completion_percent, null::text as shared_by"#,
+        id,
+        user_id,
+        kept_for.as_secs_f64(),
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// Sets the todo's position to one between its new neighbours, spreading
+/// the user's todos out again first if there is no room left between them.
+/// Fails with `RowNotFound` unless both the todo and the one `to` names are
+/// the user's live todos.
+async fn move_to(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+    to: MoveTo,
+) -> Result<Todo, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    sqlx::query("select pg_advisory_xact_lock($1, hashtext($2::text))")
+        .bind(MOVE_LOCK)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(
+        r#"select 1 from "todo"
+        where id = $1 and user_id = $2 and merged_into is null and deleted_at is null"#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_one(&mut tx)
+    .await?;
+    let (prev, next) = neighbours(&mut tx, user_id, id, to).await?;
+    let position = match position_between(prev, next) {
+        Some(position) => position,
+        None => {
+            spread_out(&mut tx, user_id).await?;
+            let (prev, next) = neighbours(&mut tx, user_id, id, to).await?;
+            position_between(prev, next).unwrap_or(POSITION_STEP)
+        }
+    };
+    let todo = sqlx::query_as!(
+        Todo,
+        r#"update "todo" set position = $3
+        where id = $1 and user_id = $2
+        returning id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, null::timestamptz as deleted_at, null::jsonb as field_modified,
+            todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+            todo_completion(id) as completion_percent, null::text as shared_by"#,
+        id,
+        user_id,
+        position,
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(todo)
+}
+
+/// The positions of the user's live todos the todo `id` lands between at
+/// `to`, leaving the todo itself out; `None` at either end.
+async fn neighbours(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+    to: MoveTo,
+) -> Result<(Option<f64>, Option<f64>), sqlx::Error> {
+    let (anchor, after) = match to {
+        MoveTo::Index(index) => {
+            // the todos at index - 1 and index, or the last one past the end
+            let positions = sqlx::query_scalar::<_, f64>(
+                r#"select position from "todo"
+                where user_id = $1 and id <> $2 and merged_into is null and deleted_at is null
+                order by position, id
+                offset greatest(least($3, (
+                    select count(*) from "todo"
+                    where user_id = $1 and id <> $2 and merged_into is null
+                        and deleted_at is null
+                )) - 1, 0)
+                limit 2"#,
+            )
+            .bind(user_id)
+            .bind(id)
+            .bind(index)
+            .fetch_all(&mut *conn)
+            .await?;
+            return Ok(match (index, positions.as_slice()) {
+                (0, [next, ..]) => (None, Some(*next)),
+                (_, [prev, next]) => (Some(*prev), Some(*next)),
+                (_, [prev]) => (Some(*prev), None),
+                _ => (None, None),
+            });
+        }
+        MoveTo::Before(anchor) => (anchor, false),
+        MoveTo::After(anchor) => (anchor, true),
+    };
+    let position = sqlx::query_scalar::<_, f64>(
+        r#"select position from "todo"
+        where id = $1 and user_id = $2 and id <> $3 and merged_into is null
+            and deleted_at is null"#,
+    )
+    .bind(anchor)
+    .bind(user_id)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+    let sql = if after {
+        r#"select position from "todo"
+        where user_id = $1 and id <> $2 and merged_into is null and deleted_at is null
+            and (position, id) > ($3, $4)
+        order by position, id
+        limit 1"#
+    } else {
+        r#"select position from "todo"
+        where user_id = $1 and id <> $2 and merged_into is null and deleted_at is null
+            and (position, id) < ($3, $4)
+        order by position desc, id desc
+        limit 1"#
+    };
+    let other = sqlx::query_scalar::<_, f64>(sql)
+        .bind(user_id)
+        .bind(id)
+        .bind(position)
+        .bind(anchor)
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(if after {
+        (Some(position), other)
+    } else {
+        (other, Some(position))
+    })
+}
+
+/// Puts the user's todos `POSITION_STEP` apart again, in the same order,
+/// deleted ones included so they come back where they were.
+async fn spread_out(conn: &mut PgConnection, user_id: uuid::Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"update "todo" set position = ranked.position
+        from (
+            select id, (row_number() over (order by position, id))::float8 * $2 as position
+            from "todo"
+            where user_id = $1 and merged_into is null
+        ) ranked
+        where "todo".id = ranked.id"#,
+    )
+    .bind(user_id)
+    .bind(POSITION_STEP)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Turns `source` into a tombstone pointing at `target`, moving its
+/// checklist items, tags and external link over.
+async fn merge(
+    conn: &mut PgConnection,
+    user_id: uuid::Uuid,
+    target: uuid::Uuid,
+    source: uuid::Uuid,
+) -> Result<Todo, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    // lock both rows in a fixed order so concurrent merges can't deadlock
+    let locked = sqlx::query!(
+        r#"select id, external_id, external_url from "todo"
+        where id = any($1) and user_id = $2 and merged_into is null and deleted_at is null
+        order by id
+        for update"#,
+        &[target, source][..],
+        user_id,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    if locked.len() != 2 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    let Some(source_row) = locked.into_iter().find(|row| row.id == source) else {
+        return Err(sqlx::Error::RowNotFound);
+    };
+    sqlx::query(
+        r#"update "todo" set merged_into = $1, external_id = null, external_url = null
+        where id = $2"#,
+    )
+    .bind(target)
+    .bind(source)
+    .execute(&mut tx)
+    .await?;
+    sqlx::query(
+        r#"update "checklist_item"
+        set todo_id = $1,
+            position = position + (select coalesce(max(position) + 1, 0) from "checklist_item" where todo_id = $1)
+        where todo_id = $2"#,
+    )
+    .bind(target)
+    .bind(source)
+    .execute(&mut tx)
+    .await?;
+    sqlx::query(
+        r#"with moved as (delete from "todo_tag" where todo_id = $2 returning tag_id)
+        insert into "todo_tag" (todo_id, tag_id) select $1, tag_id from moved
+        on conflict do nothing"#,
+    )
+    .bind(target)
+    .bind(source)
+    .execute(&mut tx)
+    .await?;
+    let todo = sqlx::query_as!(
+        Todo,
+        r#"update "todo"
+        set external_id = coalesce(external_id, $2), external_url = coalesce(external_url, $3)
+        where id = $1
+        returning id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, null::timestamptz as deleted_at, null::jsonb as field_modified,
+            todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+            todo_completion(id) as completion_percent, null::text as shared_by"#,
+        target,
+        source_row.external_id,
+        source_row.external_url,
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(todo)
+}
@@ -0,0 +1,77 @@
+//! Bounds on what a client may send. Bodies are read up to `MAX_BODY_BYTES`,
+//! anything larger is answered with a 413; the routes taking imports and
+//! attachments allow more. With `REQUEST_BODY_TIMEOUT_SECS` set, a client
+//! that goes that long without sending any of its body is answered with a
+//! 408, so a slow client doesn't hold a request slot for however long it
+//! likes. Large uploads may take longer as a whole, as long as they keep
+//! coming.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, HttpBody},
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::stream;
+use tracing::warn;
+
+use crate::error::{ApiError, ErrorCode};
+
+/// What a client too slow to send its body is answered with.
+fn timed_out() -> ApiError {
+    ApiError::new(
+        StatusCode::REQUEST_TIMEOUT,
+        "The request body took too long to arrive",
+    )
+    .with_code(ErrorCode::TimedOut)
+}
+
+/// Answers requests whose body stalls for `timeout` with a 408, whatever
+/// the handler made of the body failing to arrive.
+pub async fn enforce_timeout(
+    State(timeout): State<Duration>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if req.body().is_end_stream() {
+        return next.run(req).await;
+    }
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let stalled = Arc::new(AtomicBool::new(false));
+    let (parts, body) = req.into_parts();
+    let chunks = stream::unfold(Some(body), {
+        let stalled = stalled.clone();
+        move |body| {
+            let stalled = stalled.clone();
+            async move {
+                let mut body = body?;
+                match tokio::time::timeout(timeout, body.data()).await {
+                    Ok(Some(chunk)) => Some((chunk.map_err(tower::BoxError::from), Some(body))),
+                    Ok(None) => None,
+                    Err(_) => {
+                        stalled.store(true, Ordering::Relaxed);
+                        Some((Err("request body timed out".into()), None))
+                    }
+                }
+            }
+        }
+    });
+    let response = next
+        .run(Request::from_parts(parts, Body::wrap_stream(chunks)))
+        .await;
+    if !stalled.load(Ordering::Relaxed) {
+        return response;
+    }
+    warn!(%method, path, ?timeout, "Request body timed out");
+    timed_out().into_response()
+}
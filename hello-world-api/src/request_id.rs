@@ -0,0 +1,87 @@
+//! Request ids, for users to quote when reporting a problem and operators to
+//! find its logs. Every request gets one: the `X-Request-Id` it came with if
+//! that looks like an id, such as one set by a proxy in front, otherwise a
+//! new UUID. It is echoed as `X-Request-Id` on the response, included as
+//! `request_id` in problem details bodies and recorded on the request's
+//! span, so every event logged while handling it, SQL queries included,
+//! carries it.
+
+use axum::{
+    body::Body,
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Span;
+
+use crate::telemetry;
+
+pub static HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest id taken from a request, in bytes.
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    /// The id of the request being handled.
+    static CURRENT: RequestId;
+}
+
+/// A request's id, also set as its `X-Request-Id` header once assigned.
+#[derive(Clone)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    fn as_str(&self) -> &str {
+        // only ever made of printable ASCII
+        self.0.to_str().unwrap_or_default()
+    }
+}
+
+/// The id of the request being handled, `None` outside of [`assign`].
+pub fn current() -> Option<String> {
+    CURRENT.try_with(|id| id.as_str().to_owned()).ok()
+}
+
+/// Gives the request its id, and the response its `X-Request-Id`.
+pub async fn assign<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let id = req
+        .headers()
+        .get(&HEADER)
+        .filter(|value| is_valid(value.as_bytes()))
+        .cloned()
+        .unwrap_or_else(|| {
+            HeaderValue::try_from(uuid::Uuid::new_v4().to_string())
+                .expect("a UUID is a valid header value")
+        });
+    let id = RequestId(id);
+    req.headers_mut().insert(HEADER.clone(), id.0.clone());
+    req.extensions_mut().insert(id.clone());
+
+    let mut response = CURRENT.scope(id.clone(), next.run(req)).await;
+    response.headers_mut().insert(HEADER.clone(), id.0);
+    response
+}
+
+/// Short and made of letters, digits and `-_.:` only, so it can't forge log
+/// lines or blow them up.
+fn is_valid(id: &[u8]) -> bool {
+    (1..=MAX_LEN).contains(&id.len())
+        && id
+            .iter()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(byte))
+}
+
+/// The span of a request as `TraceLayer` makes it by default, with the
+/// request's id, in the trace of its `traceparent`.
+pub fn make_span(req: &Request<Body>) -> Span {
+    let request_id = req.extensions().get::<RequestId>().map(RequestId::as_str);
+    let span = tracing::debug_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        version = ?req.version(),
+        request_id,
+    );
+    telemetry::continue_trace(&span, req.headers());
+    span
+}
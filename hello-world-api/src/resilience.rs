@@ -0,0 +1,194 @@
+//! Riding out database hiccups. Reads that fail for a reason that may pass,
+//! such as a dropped connection or a failover in progress, are tried again
+//! with [`retry`] after a jittered backoff, rather than answered with a 500
+//! at once.
+//!
+//! Failures that still reach a client are counted by the [`DATABASE`]
+//! circuit breaker. After [`FAILURE_THRESHOLD`] of them within
+//! [`FAILURE_WINDOW`] the circuit opens: for [`OPEN_FOR`], the public API
+//! answers every request with a 503 and `Retry-After` instead of queueing
+//! them up against a database that isn't answering. The first failure after
+//! that opens it again right away, while a success closes it.
+
+use std::{
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::error::{self, ApiError};
+
+/// Attempts after the first one.
+pub const RETRIES: u32 = 2;
+
+/// Wait before the first retry, doubled for each further one, and then
+/// between half and all of it, so clients failing together don't all retry
+/// together.
+pub const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+pub const FAILURE_THRESHOLD: usize = 5;
+
+pub const FAILURE_WINDOW: Duration = Duration::from_secs(10);
+
+pub const OPEN_FOR: Duration = Duration::from_secs(5);
+
+/// The breaker of the pool's database, fed by every transient error turned
+/// into an [`ApiError`].
+pub static DATABASE: CircuitBreaker =
+    CircuitBreaker::new(FAILURE_THRESHOLD, FAILURE_WINDOW, OPEN_FOR);
+
+/// Whether `err` may not happen again if the query is simply sent again:
+/// lost connections and the errors of [`is_transient_code`]. Pool acquire
+/// timeouts aren't, retrying them would only add to the load.
+pub fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(db_err) => db_err.code().is_some_and(|code| is_transient_code(&code)),
+        _ => false,
+    }
+}
+
+/// Connection exceptions, the server shutting down or not taking
+/// connections yet, too many connections, and serialization failures and
+/// deadlocks.
+pub fn is_transient_code(code: &str) -> bool {
+    code.starts_with("08")
+        || matches!(
+            code,
+            "57P01" | "57P02" | "57P03" | "53300" | "40001" | "40P01"
+        )
+}
+
+/// Runs the idempotent `query` until it succeeds, fails for good or is out
+/// of retries. Nothing is retried while the circuit is open.
+pub async fn retry<T, F, Fut>(mut query: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        let result = query().await;
+        match &result {
+            Err(err) if is_transient(err) && attempt < RETRIES && !DATABASE.is_open() => {
+                warn!(attempt, "Retrying query after {err}");
+                tokio::time::sleep(jittered(RETRY_BACKOFF * 2u32.pow(attempt))).await;
+                attempt += 1;
+            }
+            Ok(_) => {
+                DATABASE.record_success();
+                return result;
+            }
+            Err(_) => return result,
+        }
+    }
+}
+
+/// Between half of `backoff` and all of it.
+fn jittered(backoff: Duration) -> Duration {
+    let random = uuid::Uuid::new_v4().as_u64_pair().0 % 1000;
+    backoff / 2 + backoff / 2 * random as u32 / 1000
+}
+
+/// Counts failures and opens once there are too many in a window of time,
+/// see the module docs.
+pub struct CircuitBreaker {
+    threshold: usize,
+    window: Duration,
+    open_for: Duration,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Of the failures in the window, oldest first.
+    failures: Vec<Instant>,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub const fn new(threshold: usize, window: Duration, open_for: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            window,
+            open_for,
+            state: Mutex::new(State {
+                failures: Vec::new(),
+                opened_at: None,
+            }),
+        }
+    }
+
+    /// How long the circuit stays open, `None` while it is closed.
+    pub fn retry_after(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        let opened_at = state.opened_at?;
+        self.open_for.checked_sub(opened_at.elapsed())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.retry_after().is_some()
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        // shortly after it was open, a single failure is enough to tell the
+        // database is still down
+        let reopen = state
+            .opened_at
+            .is_some_and(|opened| opened.elapsed() < self.open_for + self.window);
+        state
+            .failures
+            .retain(|&failed| now.duration_since(failed) < self.window);
+        state.failures.push(now);
+        if reopen || state.failures.len() >= self.threshold {
+            if state
+                .opened_at
+                .is_none_or(|opened| opened.elapsed() >= self.open_for)
+            {
+                warn!(
+                    failures = state.failures.len(),
+                    "Opening the database circuit for {:?}", self.open_for
+                );
+            }
+            state.opened_at = Some(now);
+            state.failures.clear();
+        }
+    }
+
+    /// Closes the circuit, once it is no longer open.
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .opened_at
+            .is_some_and(|opened| opened.elapsed() >= self.open_for)
+        {
+            state.opened_at = None;
+        }
+    }
+}
+
+/// Answers requests with a 503 while the [`DATABASE`] circuit is open,
+/// except those for the operator endpoints: the probes and the metrics
+/// should tell how things are, and admins may want to look into it.
+pub async fn reject_while_open<B>(request: Request<B>, next: Next<B>) -> Response {
+    let path = request.uri().path();
+    let operator =
+        path.starts_with("/admin/") || matches!(path, "/healthz" | "/readyz" | "/metrics");
+    match DATABASE.retry_after() {
+        Some(left) if !operator => ApiError {
+            // whole seconds, rounded up so clients don't come back early
+            retry_after: Some(left.as_secs() + 1),
+            ..error::unavailable()
+        }
+        .into_response(),
+        _ => next.run(request).await,
+    }
+}
@@ -0,0 +1,530 @@
+//! `Cache-Control` for idempotent reads, and optionally an in-process cache
+//! serving them.
+//!
+//! `RESPONSE_CACHE` lists path prefixes with the seconds their successful
+//! GET responses may be cached for, such as `/stats=300,/todos=5`. With
+//! `RESPONSE_CACHE_STORE=true` those responses are also kept in memory and
+//! served with an `Age` header until they expire. Any successful write
+//! through this instance empties the store; writes through other replicas
+//! are only picked up once entries expire.
+//!
+//! `RESPONSE_CACHE_REDIS_URL` keeps the stored responses in Redis instead,
+//! shared by every replica and emptied by a write through any of them. The
+//! entries are stored under a generation that writes bump, so they are
+//! dropped without being looked for, and a response read before a write is
+//! never stored for after it. Redis failing or taking longer than
+//! [`REDIS_TIMEOUT`] only turns the lookups into misses.
+//!
+//! Responses to requests with credentials are `private`, so only the
+//! client itself may keep them. Those to a bearer token are stored per
+//! `Authorization` value and workspace; those to an API key are never
+//! stored. Admins acting as another user are never served from the store
+//! either, so each of their requests reaches the audit log.
+//!
+//! A stored response is served as [`conditional`] would have answered: an
+//! empty 304 if the request's `If-None-Match` or `If-Modified-Since` says
+//! the client has it already.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use axum::{
+    body::{self, Body, Bytes, HttpBody},
+    extract::State,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use redis::{aio::ConnectionManager, AsyncCommands};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+use crate::{auth, conditional};
+
+/// Responses larger than this are never stored.
+const MAX_STORED_BYTES: usize = 1024 * 1024;
+const MAX_ENTRIES: usize = 1000;
+/// Longest a Redis call may take before it counts as a miss.
+const REDIS_TIMEOUT: Duration = Duration::from_millis(100);
+/// The counter stored entries' keys start with.
+const GENERATION_KEY: &str = "response_cache:generation";
+
+#[derive(Clone)]
+struct Cached {
+    stored_at: Instant,
+    ttl: Duration,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+#[derive(Clone)]
+pub struct ResponseCache {
+    /// Longest prefixes first, so the most specific rule wins.
+    rules: Arc<Vec<(String, Duration)>>,
+    store: Option<Store>,
+}
+
+#[derive(Clone)]
+enum Store {
+    Memory(Arc<Mutex<HashMap<String, Cached>>>),
+    Redis(RedisStore),
+}
+
+impl Store {
+    /// The generation entries are stored under now, `None` if that can't be
+    /// told and nothing should be stored.
+    async fn generation(&self) -> Option<u64> {
+        match self {
+            Store::Memory(_) => Some(0),
+            Store::Redis(redis) => redis.generation().await,
+        }
+    }
+
+    async fn get(&self, generation: u64, key: &str) -> Option<Cached> {
+        let cached = match self {
+            Store::Memory(store) => store.lock().unwrap().get(key).cloned(),
+            Store::Redis(redis) => redis.get(generation, key).await,
+        };
+        cached.filter(|cached| cached.stored_at.elapsed() < cached.ttl)
+    }
+
+    async fn put(&self, generation: u64, key: String, cached: Cached) {
+        match self {
+            Store::Memory(store) => {
+                let mut store = store.lock().unwrap();
+                if store.len() >= MAX_ENTRIES {
+                    store.retain(|_, cached| cached.stored_at.elapsed() < cached.ttl);
+                }
+                if store.len() < MAX_ENTRIES {
+                    store.insert(key, cached);
+                }
+            }
+            Store::Redis(redis) => redis.put(generation, &key, &cached).await,
+        }
+    }
+
+    async fn clear(&self) {
+        match self {
+            Store::Memory(store) => store.lock().unwrap().clear(),
+            Store::Redis(redis) => redis.clear().await,
+        }
+    }
+}
+
+/// Entries as hashes of their status, headers, body and when they were
+/// stored, which Redis expires along with them.
+#[derive(Clone)]
+struct RedisStore {
+    client: redis::Client,
+    /// Connected on first use, and again after failing to.
+    connection: Arc<OnceCell<ConnectionManager>>,
+}
+
+impl RedisStore {
+    /// Runs `command` on the connection, `None` if it fails or times out.
+    async fn run<T, F>(&self, command: impl FnOnce(ConnectionManager) -> F) -> Option<T>
+    where
+        F: std::future::Future<Output = redis::RedisResult<T>>,
+    {
+        let result = tokio::time::timeout(REDIS_TIMEOUT, async {
+            let connection = self
+                .connection
+                .get_or_try_init(|| self.client.get_connection_manager())
+                .await?
+                .clone();
+            command(connection).await
+        })
+        .await;
+        match result {
+            Ok(Ok(value)) => Some(value),
+            Ok(Err(err)) => {
+                warn!(%err, "Response cache Redis call failed");
+                None
+            }
+            Err(_) => {
+                warn!("Response cache Redis call timed out");
+                None
+            }
+        }
+    }
+
+    async fn generation(&self) -> Option<u64> {
+        self.run(|mut redis| async move {
+            let generation: Option<u64> = redis.get(GENERATION_KEY).await?;
+            Ok(generation.unwrap_or_default())
+        })
+        .await
+    }
+
+    async fn get(&self, generation: u64, key: &str) -> Option<Cached> {
+        let key = redis_key(generation, key);
+        let mut fields: HashMap<String, Vec<u8>> = self
+            .run(|mut redis| async move { redis.hgetall(key).await })
+            .await?;
+        let number = |fields: &HashMap<String, Vec<u8>>, name: &str| -> Option<u64> {
+            std::str::from_utf8(fields.get(name)?).ok()?.parse().ok()
+        };
+        let age = unix_millis().saturating_sub(number(&fields, "stored_at")?);
+        let headers: Vec<(String, String)> = serde_json::from_slice(fields.get("headers")?).ok()?;
+        Some(Cached {
+            stored_at: Instant::now().checked_sub(Duration::from_millis(age))?,
+            ttl: Duration::from_secs(number(&fields, "ttl")?),
+            status: StatusCode::from_u16(u16::try_from(number(&fields, "status")?).ok()?).ok()?,
+            headers: headers
+                .into_iter()
+                .filter_map(|(name, value)| {
+                    Some((
+                        HeaderName::try_from(name).ok()?,
+                        HeaderValue::try_from(value).ok()?,
+                    ))
+                })
+                .collect(),
+            body: Bytes::from(fields.remove("body")?),
+        })
+    }
+
+    async fn put(&self, generation: u64, key: &str, cached: &Cached) {
+        // headers that aren't text are left out
+        let headers: Vec<(&str, &str)> = cached
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+            .collect();
+        let Ok(headers) = serde_json::to_vec(&headers) else {
+            return;
+        };
+        let fields: [(&str, Vec<u8>); 5] = [
+            ("stored_at", unix_millis().to_string().into_bytes()),
+            ("ttl", cached.ttl.as_secs().to_string().into_bytes()),
+            ("status", cached.status.as_u16().to_string().into_bytes()),
+            ("headers", headers),
+            ("body", cached.body.to_vec()),
+        ];
+        let key = redis_key(generation, key);
+        let ttl = cached.ttl.as_secs() as i64;
+        self.run(|mut redis| async move {
+            redis::pipe()
+                .atomic()
+                .hset_multiple(&key, &fields)
+                .ignore()
+                .expire(&key, ttl)
+                .ignore()
+                .query_async::<_, ()>(&mut redis)
+                .await
+        })
+        .await;
+    }
+
+    async fn clear(&self) {
+        self.run(|mut redis| async move { redis.incr::<_, _, u64>(GENERATION_KEY, 1).await })
+            .await;
+    }
+}
+
+/// Hashed, as the key has the request's `Authorization` in it.
+fn redis_key(generation: u64, key: &str) -> String {
+    format!(
+        "response_cache:{generation}:{}",
+        hex::encode(Sha256::digest(key))
+    )
+}
+
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+impl ResponseCache {
+    /// `None` unless `RESPONSE_CACHE` is set.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(spec) = std::env::var("RESPONSE_CACHE") else {
+            return Ok(None);
+        };
+        let mut rules = spec
+            .split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(|rule| {
+                let (prefix, seconds) = rule.trim().split_once('=').with_context(|| {
+                    format!("RESPONSE_CACHE rule {rule:?} is not prefix=seconds")
+                })?;
+                let seconds: u64 = seconds.parse().with_context(|| {
+                    format!("RESPONSE_CACHE rule {rule:?} has no valid seconds")
+                })?;
+                Ok((prefix.to_owned(), Duration::from_secs(seconds)))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        rules.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        let store = match std::env::var("RESPONSE_CACHE_REDIS_URL") {
+            Ok(url) => Some(Store::Redis(RedisStore {
+                client: redis::Client::open(url)
+                    .context("RESPONSE_CACHE_REDIS_URL is not a Redis URL")?,
+                connection: Default::default(),
+            })),
+            Err(_) => std::env::var("RESPONSE_CACHE_STORE")
+                .is_ok_and(|v| v == "true")
+                .then(|| Store::Memory(Default::default())),
+        };
+        Ok(Some(ResponseCache {
+            rules: Arc::new(rules),
+            store,
+        }))
+    }
+
+    fn ttl(&self, path: &str) -> Option<Duration> {
+        self.rules
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .map(|(_, ttl)| *ttl)
+    }
+}
+
+pub async fn cache(
+    State(cache): State<Option<ResponseCache>>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let Some(cache) = cache else {
+        return next.run(req).await;
+    };
+    let read = matches!(
+        req.method().as_str(),
+        "GET" | "HEAD" | "OPTIONS" | "PROPFIND" | "REPORT"
+    );
+    if !read {
+        let response = next.run(req).await;
+        if response.status().is_success() {
+            if let Some(store) = &cache.store {
+                store.clear().await;
+            }
+        }
+        return response;
+    }
+    let Some(ttl) = cache
+        .ttl(req.uri().path())
+        .filter(|_| req.method() == Method::GET)
+    else {
+        return next.run(req).await;
+    };
+    let private = req.headers().contains_key(header::AUTHORIZATION)
+        || req.headers().contains_key(auth::X_API_KEY);
+    // the key only has the `Authorization` in it
+    let Some(store) = cache
+        .store
+        .as_ref()
+        .filter(|_| !req.headers().contains_key("x-act-as") && !auth::uses_api_key(req.headers()))
+    else {
+        let mut response = next.run(req).await;
+        set_cache_control(&mut response, ttl, private);
+        return response;
+    };
+
+    // the share link view depends on Accept and everything else on the
+    // user and the workspace, named by a header or the host, so all of them
+    // are part of the key
+    let value_of = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+    let key = format!(
+        "{} {} {} {} {}",
+        req.uri(),
+        value_of(header::ACCEPT),
+        value_of(header::AUTHORIZATION),
+        value_of(header::HOST),
+        value_of(HeaderName::from_static("x-workspace")),
+    );
+    let Some(generation) = store.generation().await else {
+        let mut response = next.run(req).await;
+        set_cache_control(&mut response, ttl, private);
+        return response;
+    };
+    let bypass = req
+        .headers()
+        .get(header::CACHE_CONTROL)
+        .is_some_and(|value| value.as_bytes().starts_with(b"no-cache"));
+    if !bypass {
+        if let Some(cached) = store.get(generation, &key).await {
+            let age = cached.stored_at.elapsed().as_secs();
+            let mut response = (cached.status, cached.headers, cached.body).into_response();
+            response
+                .headers_mut()
+                .insert(header::AGE, HeaderValue::from(age));
+            set_cache_control(
+                &mut response,
+                Duration::from_secs(cached.ttl.as_secs().saturating_sub(age)),
+                private,
+            );
+            if conditional::not_modified(req.headers(), response.headers()) {
+                return conditional::unchanged(std::mem::take(response.headers_mut()));
+            }
+            return response;
+        }
+    }
+
+    let response = next.run(req).await;
+    let no_store = response
+        .headers()
+        .get(header::CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("no-store") || value.contains("private"));
+    if no_store
+        || response.status() != StatusCode::OK
+        || response
+            .body()
+            .size_hint()
+            .upper()
+            .is_none_or(|size| size as usize > MAX_STORED_BYTES)
+    {
+        return response;
+    }
+    let (parts, response_body) = response.into_parts();
+    let Ok(bytes) = hyper::body::to_bytes(response_body).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let cached = Cached {
+        stored_at: Instant::now(),
+        ttl,
+        status: parts.status,
+        headers: parts.headers.clone(),
+        body: bytes.clone(),
+    };
+    store.put(generation, key, cached).await;
+    let mut response = Response::from_parts(parts, body::boxed(body::Full::new(bytes)));
+    set_cache_control(&mut response, ttl, private);
+    response
+}
+
+/// Handlers that already decided on caching (such as share links'
+/// `no-store`) keep their header.
+fn set_cache_control(response: &mut Response, ttl: Duration, private: bool) {
+    if !response.status().is_success() || response.headers().contains_key(header::CACHE_CONTROL) {
+        return;
+    }
+    let scope = if private { "private, " } else { "" };
+    if let Ok(value) = HeaderValue::from_str(&format!("{scope}max-age={}", ttl.as_secs())) {
+        response.headers_mut().insert(header::CACHE_CONTROL, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Caches `/todos` for a minute in memory, in front of a handler
+    /// answering with the credentials it was called with.
+    fn app() -> Router {
+        let state = ResponseCache {
+            rules: Arc::new(vec![("/todos".to_owned(), Duration::from_secs(60))]),
+            store: Some(Store::Memory(Default::default())),
+        };
+        let credentials = |headers: HeaderMap| async move {
+            [header::AUTHORIZATION.as_str(), auth::X_API_KEY]
+                .into_iter()
+                .filter_map(|name| headers.get(name)?.to_str().ok().map(str::to_owned))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        Router::new()
+            .route("/todos", get(credentials))
+            .layer(middleware::from_fn_with_state(Some(state), cache))
+    }
+
+    async fn get_with(app: &Router, name: &str, value: &str) -> Response {
+        let request = Request::get("/todos")
+            .header(name, value)
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn text(response: Response) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn api_keys_get_their_own_responses() {
+        let app = app();
+        for key in ["first key", "second key", "first key"] {
+            let response = get_with(&app, auth::X_API_KEY, key).await;
+            assert_eq!(
+                response.headers()[header::CACHE_CONTROL],
+                "private, max-age=60"
+            );
+            assert!(!response.headers().contains_key(header::AGE));
+            assert_eq!(text(response).await, key);
+        }
+    }
+
+    #[tokio::test]
+    async fn bearer_tokens_are_served_from_the_store_per_token() {
+        let app = app();
+        let first = get_with(&app, "authorization", "Bearer first").await;
+        assert!(!first.headers().contains_key(header::AGE));
+        let second = get_with(&app, "authorization", "Bearer second").await;
+        assert_eq!(text(second).await, "Bearer second");
+        let again = get_with(&app, "authorization", "Bearer first").await;
+        assert!(again.headers().contains_key(header::AGE));
+        assert_eq!(
+            again.headers()[header::CACHE_CONTROL],
+            "private, max-age=60"
+        );
+        assert_eq!(text(again).await, "Bearer first");
+    }
+
+    #[tokio::test]
+    async fn stored_responses_are_not_modified_for_their_etag() {
+        let state = ResponseCache {
+            rules: Arc::new(vec![("/todos".to_owned(), Duration::from_secs(60))]),
+            store: Some(Store::Memory(Default::default())),
+        };
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let handler = {
+            let calls = calls.clone();
+            move || async move {
+                calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                "Buy milk"
+            }
+        };
+        // as the routes are layered: the cache in front of the conditional
+        // responses
+        let app = Router::new()
+            .route("/todos", get(handler))
+            .route_layer(middleware::from_fn(conditional::respond))
+            .layer(middleware::from_fn_with_state(Some(state), cache));
+
+        let first = get_with(&app, "authorization", "Bearer token").await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[header::ETAG].to_str().unwrap().to_owned();
+        let request = |if_none_match: &str| {
+            Request::get("/todos")
+                .header(header::AUTHORIZATION, "Bearer token")
+                .header(header::IF_NONE_MATCH, if_none_match)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let hit = app.clone().oneshot(request(&etag)).await.unwrap();
+        assert_eq!(hit.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(hit.headers()[header::ETAG], etag.as_str());
+        assert!(hit.headers().contains_key(header::AGE));
+        assert!(!hit.headers().contains_key(header::CONTENT_TYPE));
+        assert_eq!(text(hit).await, "");
+        let other = app.clone().oneshot(request("\"other\"")).await.unwrap();
+        assert_eq!(other.status(), StatusCode::OK);
+        assert_eq!(text(other).await, "Buy milk");
+        // both were answered from the store
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
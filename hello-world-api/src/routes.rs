@@ -0,0 +1,544 @@
+//! Assembly of the application: which handler serves which route, the
+//! shared services handlers take as extensions, and the middleware stacks
+//! wrapped around the routers before they are served.
+
+pub mod layers;
+pub mod rewrite;
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{any, delete, get, post, put},
+    Extension, Router,
+};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tracing::warn;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+#[cfg(feature = "chaos")]
+use crate::chaos;
+use crate::{
+    access_log, admin_stats, admin_todos,
+    analytics::Analytics,
+    api_keys, archive, assignment, assist,
+    attachments::{self, storage::LocalDisk},
+    audit,
+    auth::{self, Auth},
+    broker, caldav, checklist, conditional,
+    config::Config,
+    counts,
+    events::{self, Events},
+    expiry,
+    github::{self, GithubClient, GithubSync},
+    graphql, grpc,
+    handlers::{fallback, todos},
+    health, history, hooks, import, inbound_email, jobs, links, listen, lists, location,
+    log_level::{self, LogLevel},
+    maintenance::{self, Maintenance},
+    me,
+    metrics::{self, Metrics},
+    openapi::ApiDoc,
+    outbound::Outbound,
+    outbox, portable, purge,
+    quota::Quota,
+    rate_limit::{self, RateLimiter, WorkspaceLimits},
+    recording::{self, Recordings},
+    recurrence, recurring, reminders,
+    repository::{PgTodoRepository, PoolSettings, Storage, Todos},
+    request_id,
+    response_cache::ResponseCache,
+    schedule, search, setup, share, stats, tags, todo_share, todo_stream, transfer, tx, versioning,
+    webhooks,
+    workspaces::{self, WorkspaceDomain},
+};
+
+/// Everything the handlers and middlewares share besides the pool. The
+/// default has every integration and diagnostic switched off.
+#[derive(Clone)]
+pub struct Services {
+    storage: Storage,
+    pool: PoolSettings,
+    auth: Auth,
+    github: Option<Arc<GithubClient>>,
+    github_sync: Option<GithubSync>,
+    assistant: Option<Arc<dyn assist::TaskAssistant>>,
+    attachments: Arc<dyn attachments::storage::Storage>,
+    mailgun_signing_key: Option<String>,
+    recordings: Recordings,
+    response_cache: Option<ResponseCache>,
+    analytics: Option<Analytics>,
+    quota: Option<Quota>,
+    rate_limit: Option<RateLimiter>,
+    workspace_limits: WorkspaceLimits,
+    events: Events,
+    retention: purge::Retention,
+    maintenance: Maintenance,
+    metrics: Metrics,
+    workspace_domain: WorkspaceDomain,
+    /// `None` when this process doesn't own the tracing subscriber.
+    log_level: Option<LogLevel>,
+    #[cfg(feature = "chaos")]
+    chaos: chaos::Chaos,
+}
+
+impl Default for Services {
+    fn default() -> Self {
+        Services {
+            storage: Storage::Postgres,
+            pool: PoolSettings::default(),
+            auth: Auth::ephemeral(Duration::from_secs(86400), false),
+            github: None,
+            github_sync: None,
+            assistant: None,
+            attachments: Arc::new(LocalDisk::new(
+                std::env::temp_dir().join("hello-world-api-attachments"),
+            )),
+            mailgun_signing_key: None,
+            recordings: Recordings::default(),
+            response_cache: None,
+            analytics: None,
+            quota: None,
+            rate_limit: None,
+            workspace_limits: WorkspaceLimits::default(),
+            events: Events::default(),
+            retention: purge::Retention::default(),
+            maintenance: Maintenance::new(false),
+            metrics: Metrics::default(),
+            workspace_domain: WorkspaceDomain::default(),
+            log_level: None,
+            #[cfg(feature = "chaos")]
+            chaos: chaos::Chaos::default(),
+        }
+    }
+}
+
+impl Services {
+    /// The integrations configured by their environment variables; starting
+    /// the GitHub sync needs the pool. Also starts the background jobs, see
+    /// [`jobs`], except on read-only replicas and without a database.
+    pub fn from_env(db: &PgPool, config: &Config, log_level: LogLevel) -> anyhow::Result<Self> {
+        let events = Events::default();
+        let outbound = Outbound::from_env()?;
+        let attachments = attachments::storage::from_env(outbound.clone())?;
+        if !config.read_only && config.storage == Storage::Postgres {
+            jobs::spawn(
+                db.clone(),
+                config.stats_refresh_interval,
+                stats::RefreshStats,
+            );
+            let expire = expiry::ExpireTodos {
+                events: events.clone(),
+            };
+            jobs::spawn(db.clone(), config.expiry_check_interval, expire);
+            let recur = recurring::RecurTodos {
+                events: events.clone(),
+            };
+            jobs::spawn(db.clone(), config.recurrence_check_interval, recur);
+            let purge = purge::PurgeDeleted {
+                after: config.purge_deleted_after,
+                attachments: attachments.clone(),
+            };
+            jobs::spawn(db.clone(), config.purge_check_interval, purge);
+            let remind = reminders::RemindDue {
+                events: events.clone(),
+                lead: config.reminder_lead,
+            };
+            jobs::spawn(db.clone(), config.reminder_check_interval, remind);
+            let webhooks = webhooks::Webhooks::spawn(db.clone(), outbound.clone());
+            outbox::spawn(db.clone(), events.clone(), webhooks, broker::from_env()?);
+        }
+        let github = GithubClient::from_env(outbound.clone()).map(Arc::new);
+        let github_sync = github
+            .clone()
+            .filter(|github| github.sync_issues)
+            .map(|github| GithubSync::spawn(github, db.clone()));
+        let assistant = assist::ChatCompletionsAssistant::from_env(outbound.clone())?
+            .map(|assistant| Arc::new(assistant) as Arc<dyn assist::TaskAssistant>);
+        let auth = match &config.jwt_secret {
+            Some(secret) => Auth::new(
+                secret.as_bytes(),
+                config.token_lifetime,
+                config.admin_impersonation,
+            ),
+            None => {
+                warn!("JWT_SECRET is not set, tokens are only valid until the server stops");
+                Auth::ephemeral(config.token_lifetime, config.admin_impersonation)
+            }
+        };
+        let workspace_limits = WorkspaceLimits::new(db.clone(), config.workspace_domain.clone());
+        let rate_limit = config.rate_limit_per_minute.map(|per_minute| {
+            RateLimiter::new(
+                per_minute,
+                config.rate_limit_burst.unwrap_or(per_minute),
+                config.trusted_proxy_hops,
+                auth.clone(),
+                workspace_limits.clone(),
+            )
+        });
+        Ok(Services {
+            storage: config.storage,
+            pool: config.pool,
+            auth,
+            github,
+            github_sync,
+            assistant,
+            attachments,
+            mailgun_signing_key: std::env::var("MAILGUN_SIGNING_KEY").ok(),
+            recordings: Recordings::from_env()?,
+            response_cache: ResponseCache::from_env()?,
+            analytics: Analytics::from_env(outbound)?,
+            quota: config
+                .max_open_todos
+                .map(|max_open| Quota::new(max_open, config.quota_warning_percent)),
+            rate_limit,
+            workspace_limits,
+            events,
+            retention: purge::Retention(config.purge_deleted_after),
+            maintenance: Maintenance::new(config.maintenance_mode),
+            metrics: Metrics::default(),
+            workspace_domain: WorkspaceDomain(config.workspace_domain.clone()),
+            log_level: Some(log_level),
+            #[cfg(feature = "chaos")]
+            chaos: chaos::Chaos::from_env()?,
+        })
+    }
+
+    /// A token with the todo scopes for a user of its own, for trying out
+    /// [`memory_api`], which has no accounts to log in to.
+    pub fn demo_token(&self) -> anyhow::Result<String> {
+        let token = self
+            .auth
+            .issue(
+                uuid::Uuid::new_v4(),
+                &[auth::Scope::TodosRead, auth::Scope::TodosWrite],
+            )
+            .map_err(|err| anyhow::anyhow!(err.error))?;
+        Ok(token.access_token)
+    }
+}
+
+/// Every route, with no integrations configured and without the outer
+/// middlewares of [`stack`], so tests can call it without binding a socket.
+pub fn app(pool: PgPool) -> Router {
+    let services = Services::default();
+    let todos = Arc::new(PgTodoRepository::new(pool.clone()));
+    with_services(api(todos, None).merge(admin(&services)), pool, &services)
+}
+
+/// The core todo endpoints, which read and write through `todos` rather
+/// than the pool. They still take the auth, quota, analytics and GitHub
+/// sync extensions added by [`with_services`]. Their `GET`s are
+/// conditional, see [`conditional`].
+pub fn todo_routes(todos: Todos) -> Router {
+    Router::new()
+        .route("/todos", get(todos::get_todos).post(todos::create_todo))
+        .route("/todos/quick", post(todos::quick_add_todo))
+        .route("/todos/bulk", post(todos::create_todos))
+        .route("/todos/bulk-complete", post(todos::complete_todos))
+        .route("/todos/validate", post(todos::validate_todos))
+        .route("/todos/trash", get(todos::get_trash))
+        .route("/todos/today", get(schedule::today))
+        .route("/todos/counts", get(counts::get))
+        .route(
+            "/todos/import",
+            post(portable::import).layer(DefaultBodyLimit::max(portable::MAX_IMPORT_BYTES)),
+        )
+        .route(
+            "/todos/:id",
+            get(todos::get_todo)
+                .put(todos::put_todo_done)
+                .patch(todos::patch_todo)
+                .delete(todos::delete_todo),
+        )
+        .route("/todos/:id/toggle", post(todos::toggle_todo))
+        .route("/todos/:id/merge", post(todos::merge_todo))
+        .route("/todos/:id/restore", post(todos::restore_todo))
+        .route("/todos/:id/move", post(todos::move_todo))
+        .route("/lists/:id/todos", get(lists::todos))
+        .route_layer(middleware::from_fn(conditional::respond))
+        .with_state(todos)
+}
+
+/// The routes served with `STORAGE=memory`: the core todo endpoints and
+/// their events, which need no more than `todos`, a detached repository.
+/// They are versioned as in [`api`].
+pub fn memory_api(todos: Todos, sunset: Option<DateTime<Utc>>) -> Router {
+    let v1 = todo_routes(todos)
+        .route("/ws/todos", get(events::stream))
+        .route("/todos/events", get(events::sse));
+    versioning::mount(v1, sunset).merge(swagger_ui())
+}
+
+/// The public API routes, under `/api/v1` and at their deprecated
+/// unversioned paths until `sunset`, with their OpenAPI document and Swagger
+/// UI. CalDAV stays unversioned, as its clients are set up with its paths.
+pub fn api(todos: Todos, sunset: Option<DateTime<Utc>>) -> Router {
+    versioning::mount(v1(todos), sunset)
+        .merge(swagger_ui())
+        .route("/.well-known/caldav", any(caldav::well_known))
+        .route("/caldav", any(caldav::collection))
+        .route("/caldav/:name", any(caldav::resource))
+}
+
+fn swagger_ui() -> SwaggerUi {
+    SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi())
+}
+
+fn v1(todos: Todos) -> Router {
+    let graphql = Router::new()
+        .route("/graphql", get(graphql::execute).post(graphql::execute))
+        .route("/graphql/ws", get(graphql::subscribe))
+        .with_state(todos.clone());
+    // the routes of the data a workspace has, which its members act on
+    let data = todo_routes(todos)
+        .merge(graphql)
+        .route("/todos/nearby", get(location::nearby))
+        .route("/todos/search", get(search::search))
+        .route("/todos/export", get(portable::export))
+        .route("/todos/stream", get(todo_stream::stream))
+        .route("/ws/todos", get(events::stream))
+        .route("/todos/events", get(events::sse))
+        .route("/todos/:id/breakdown", post(assist::breakdown))
+        .route("/todos/:id/assign", post(assignment::assign))
+        .route(
+            "/todos/:id/checklist",
+            get(checklist::get_checklist)
+                .post(checklist::add_item)
+                .put(checklist::reorder),
+        )
+        .route(
+            "/todos/:id/checklist/:item_id",
+            put(checklist::put_item).delete(checklist::delete_item),
+        )
+        .route(
+            "/todos/:id/attachments",
+            get(attachments::list)
+                .post(attachments::upload)
+                .layer(DefaultBodyLimit::max(attachments::MAX_UPLOAD_BYTES)),
+        )
+        .route(
+            "/todos/:id/attachments/:attachment_id",
+            get(attachments::download).delete(attachments::delete),
+        )
+        .route("/todos/:id/links", post(links::create))
+        .route("/todos/:id/links/:link_id", delete(links::delete))
+        .route("/todos/:id/history", get(history::list))
+        .route("/todos/archive-completed", post(archive::archive_completed))
+        .route("/todos/archived", get(archive::list))
+        .route("/me/summary", get(me::summary))
+        .route("/recurrence/preview", get(recurrence::preview))
+        .route(
+            "/todos/:id/start",
+            put(schedule::put_start).delete(schedule::delete_start),
+        )
+        .route(
+            "/todos/:id/location",
+            put(location::put_location).delete(location::delete_location),
+        )
+        .route("/lists", get(lists::list).post(lists::create))
+        .route(
+            "/lists/:id",
+            get(lists::get).put(lists::rename).delete(lists::delete),
+        )
+        .route("/tags", get(tags::list).post(tags::create))
+        .route("/tags/stats", get(tags::stats))
+        .route("/todos/bulk/tags", post(tags::bulk))
+        .route(
+            "/todos/:id/tags/:tag_id",
+            put(tags::attach).delete(tags::detach),
+        )
+        .route("/todos/:id/share-link", post(share::create))
+        .route("/todos/:id/share-link/:link_id", delete(share::revoke))
+        .route("/todos/:id/shares", get(todo_share::todo_shares))
+        .route(
+            "/todos/:id/shares/:username",
+            put(todo_share::share_todo).delete(todo_share::unshare_todo),
+        )
+        .route("/lists/:id/shares", get(todo_share::list_shares))
+        .route(
+            "/lists/:id/shares/:username",
+            put(todo_share::share_list).delete(todo_share::unshare_list),
+        )
+        .route("/webhooks", get(webhooks::list).post(webhooks::create))
+        .route("/webhooks/:id", delete(webhooks::delete))
+        .route("/webhooks/:id/secret", post(webhooks::rotate_secret))
+        .route("/webhooks/:id/deliveries", get(webhooks::deliveries))
+        .route("/import/todoist", post(import::todoist))
+        .route("/import/trello", post(import::trello))
+        .route("/import/github", post(import::github))
+        .route("/integrations/hooks", post(hooks::create))
+        .route("/integrations/hooks/:id", delete(hooks::delete))
+        .route("/import/jobs/:id", get(import::get_job))
+        .route("/stats/completions", get(stats::completions))
+        .route("/stats/heatmap", get(stats::heatmap))
+        .route_layer(middleware::from_fn(workspaces::resolve));
+    data.route("/auth/register", post(auth::register))
+        .route("/auth/login", post(auth::login))
+        .route("/auth/introspect", post(auth::introspect))
+        .route("/auth/api-keys", get(api_keys::list).post(api_keys::create))
+        .route("/auth/api-keys/:id", delete(api_keys::revoke))
+        .route(
+            "/me/preferences",
+            get(me::get_preferences).put(me::put_preferences),
+        )
+        .route(
+            "/workspaces",
+            get(workspaces::list).post(workspaces::create),
+        )
+        .route("/workspaces/:slug/members", get(workspaces::members))
+        .route(
+            "/workspaces/:slug/members/:user_id",
+            delete(workspaces::remove_member),
+        )
+        .route("/workspaces/:slug/invitations", post(workspaces::invite))
+        .route(
+            "/workspaces/:slug/leaderboard",
+            get(workspaces::leaderboard),
+        )
+        .route(
+            "/workspaces/:slug/privacy",
+            get(workspaces::get_privacy).put(workspaces::put_privacy),
+        )
+        .route(
+            "/workspaces/:slug/settings",
+            get(workspaces::get_settings).put(workspaces::put_settings),
+        )
+        .route("/invitations/:token/accept", post(workspaces::accept))
+        .route("/setup", get(setup::get).post(setup::post))
+        .route("/shared/:token", get(share::view))
+        .route("/integrations/github", post(github::webhook))
+        .route("/hooks/:token", post(hooks::deliver))
+        .route("/inbound/email", post(inbound_email::mailgun))
+}
+
+/// Operator endpoints, merged into [`api`] or served on their own listener
+/// so they can be bound to localhost only. The health probes are among them,
+/// so orchestrators probe a port that isn't behind the public middlewares.
+/// The `/admin` ones and the recordings take an admin's token.
+pub fn admin(services: &Services) -> Router {
+    Router::new()
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route("/metrics", get(metrics::scrape))
+        .merge(admin_only(services))
+}
+
+/// The `/admin` endpoints and the recordings, which hold other users'
+/// requests, behind [`auth::require_admin`].
+fn admin_only(services: &Services) -> Router {
+    let admin = Router::new()
+        .route("/debug/recordings", get(recording::list))
+        .route(
+            "/debug/recordings/settings",
+            get(recording::get_settings).put(recording::put_settings),
+        )
+        .route("/admin/todos", get(admin_todos::list))
+        .route("/admin/audit-log", get(audit::list))
+        .route("/admin/stats", get(admin_stats::get))
+        .route("/admin/export", get(transfer::export))
+        .route("/admin/jobs", get(jobs::list))
+        .route(
+            "/admin/import",
+            post(transfer::import).layer(DefaultBodyLimit::max(transfer::MAX_IMPORT_BYTES)),
+        )
+        .route(
+            "/admin/maintenance",
+            get(maintenance::get).put(maintenance::put),
+        )
+        .route(
+            "/admin/workspaces/:slug/rate-limit",
+            get(rate_limit::get_workspace)
+                .put(rate_limit::put_workspace)
+                .delete(rate_limit::delete_workspace),
+        );
+    let admin = match services.log_level {
+        Some(_) => admin.route("/admin/log-level", get(log_level::get).put(log_level::put)),
+        None => admin,
+    };
+    admin.route_layer(middleware::from_fn(auth::require_admin))
+}
+
+/// Adds the fallbacks and the extensions the handlers of `routes` take.
+pub fn with_services(routes: Router, pool: PgPool, services: &Services) -> Router {
+    let mut app = routes
+        .fallback(fallback::not_found)
+        .layer(middleware::map_response(fallback::method_not_allowed))
+        // inside the metrics, which count a failed commit's 500
+        .layer(middleware::from_fn(tx::scope))
+        .layer(middleware::from_fn(api_keys::quota_headers))
+        // inside the router, where the matched route is known
+        .layer(middleware::from_fn_with_state(
+            services.metrics.clone(),
+            metrics::track,
+        ))
+        .layer(middleware::from_fn(access_log::matched_route))
+        .layer(Extension(services.metrics.clone()))
+        .layer(Extension(services.auth.clone()))
+        .layer(Extension(services.quota))
+        .layer(Extension(services.events.clone()))
+        .layer(Extension(services.retention))
+        .layer(Extension(services.analytics.clone()))
+        .layer(Extension(stats::HeatmapCache::default()))
+        .layer(Extension(counts::CountsCache::default()))
+        .layer(Extension(import::ImportJobs::default()))
+        .layer(Extension(services.github.clone()))
+        .layer(Extension(services.assistant.clone()))
+        .layer(Extension(services.attachments.clone()))
+        .layer(Extension(services.github_sync.clone()))
+        .layer(Extension(inbound_email::MailgunSigningKey(
+            services.mailgun_signing_key.clone(),
+        )))
+        .layer(Extension(services.recordings.clone()))
+        .layer(Extension(services.maintenance.clone()))
+        .layer(Extension(services.workspace_limits.clone()))
+        .layer(Extension(services.workspace_domain.clone()))
+        .layer(Extension(graphql::schema()))
+        .layer(Extension(services.storage))
+        .layer(Extension(services.pool));
+    if let Some(log_level) = &services.log_level {
+        app = app.layer(Extension(log_level.clone()));
+    }
+    let app = app
+        .layer(Extension(pool))
+        .layer(tower_http::trace::TraceLayer::new_for_http().make_span_with(request_id::make_span));
+    #[cfg(feature = "chaos")]
+    let app = app.layer(middleware::from_fn_with_state(
+        services.chaos,
+        chaos::inject,
+    ));
+    app
+}
+
+/// The middlewares around the public listener's router.
+pub fn stack(app: Router, config: &Config, services: &Services) -> listen::App {
+    layers::assemble(app, &layers::PUBLIC, config, services)
+}
+
+/// The gRPC service on `GRPC_LISTEN`, over the same `todos` as the REST
+/// API.
+pub fn grpc(todos: Todos, pool: PgPool, config: &Config, services: &Services) -> Router {
+    grpc::router(grpc::TodoService {
+        todos,
+        pool,
+        auth: services.auth.clone(),
+        events: services.events.clone(),
+        quota: services.quota,
+        github_sync: services.github_sync.clone(),
+        maintenance: services.maintenance.clone(),
+        read_only: config.read_only,
+    })
+}
+
+/// The middlewares around the gRPC listener's router.
+pub fn grpc_stack(grpc: Router, config: &Config, services: &Services) -> listen::App {
+    layers::assemble(grpc, &layers::GRPC, config, services)
+}
+
+/// The middlewares around a separate admin listener's router.
+pub fn admin_stack(admin: Router, config: &Config, services: &Services) -> listen::App {
+    layers::assemble(admin, &layers::ADMIN, config, services)
+}
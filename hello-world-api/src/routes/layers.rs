@@ -0,0 +1,282 @@
+//! The middlewares wrapped around a router before it is served, kept in one
+//! registry so their order is decided in a single place. Each listener's
+//! stack lists its middlewares outermost first; [`assemble`] leaves out the
+//! ones the configuration switches off and wraps the rest in that order.
+//!
+//! The constraints between them are checked at compile time below, so
+//! reordering a stack into a subtly broken one doesn't build.
+
+use axum::{error_handling::HandleErrorLayer, extract::DefaultBodyLimit, middleware, Router};
+use tower::{util::BoxCloneService, Layer, ServiceBuilder};
+use tracing::debug;
+
+use super::{rewrite, Services};
+use crate::{
+    access_log, compression, config::Config, cors, deadline, handlers::fallback, listen,
+    maintenance, rate_limit, recording, replica, repository::Storage, request_body, request_id,
+    resilience, response_cache,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Middleware {
+    /// Gives every request an `X-Request-Id`, echoed on the response.
+    RequestId,
+    /// One line per request, including those shed or rejected further in.
+    AccessLog,
+    /// `CORS_ALLOWED_ORIGINS`.
+    Cors,
+    /// Sheds requests over `MAX_CONCURRENT_REQUESTS` right away instead of
+    /// letting them queue up until the pool acquire timeout fails them.
+    LoadShed,
+    /// `RATE_LIMIT_PER_MINUTE`.
+    RateLimit,
+    /// `REQUEST_TIMEOUT_SECS`.
+    Deadline,
+    /// `MAX_BODY_BYTES` and `REQUEST_BODY_TIMEOUT_SECS`.
+    RequestBody,
+    /// `RESPONSE_COMPRESSION`.
+    Compression,
+    /// `PATH_NORMALIZATION`.
+    NormalizePath,
+    /// `HTTP_METHOD_OVERRIDE`.
+    MethodOverride,
+    /// Rejects writes while maintenance mode is on.
+    Maintenance,
+    /// Rejects writes on a `READ_ONLY` replica.
+    ReadOnly,
+    /// `RESPONSE_CACHE`.
+    ResponseCache,
+    /// Rejects requests while the database circuit is open.
+    CircuitBreaker,
+    /// `RECORD_ROUTE`.
+    Recording,
+}
+
+/// Around the public listener's router, outermost first.
+pub const PUBLIC: [Middleware; 15] = [
+    Middleware::RequestId,
+    Middleware::AccessLog,
+    Middleware::Cors,
+    Middleware::LoadShed,
+    Middleware::RateLimit,
+    Middleware::Deadline,
+    Middleware::RequestBody,
+    Middleware::Compression,
+    Middleware::NormalizePath,
+    Middleware::MethodOverride,
+    Middleware::Maintenance,
+    Middleware::ReadOnly,
+    Middleware::ResponseCache,
+    Middleware::CircuitBreaker,
+    Middleware::Recording,
+];
+
+/// Around a separate admin listener's router.
+pub const ADMIN: [Middleware; 3] = [
+    Middleware::RequestId,
+    Middleware::AccessLog,
+    Middleware::Compression,
+];
+
+/// Around the gRPC listener's router. Its calls are all `POST`s, so the
+/// service itself rejects writes in maintenance mode and on replicas.
+pub const GRPC: [Middleware; 3] = [
+    Middleware::RequestId,
+    Middleware::AccessLog,
+    Middleware::LoadShed,
+];
+
+/// Position of `middleware` in `stack`, `usize::MAX` if it isn't there.
+const fn position(stack: &[Middleware], middleware: Middleware) -> usize {
+    let mut i = 0;
+    while i < stack.len() {
+        if stack[i] as usize == middleware as usize {
+            return i;
+        }
+        i += 1;
+    }
+    usize::MAX
+}
+
+/// Whether `outer` runs before `inner` in `stack`.
+const fn outside(stack: &[Middleware], outer: Middleware, inner: Middleware) -> bool {
+    position(stack, outer) < position(stack, inner)
+}
+
+const _: () = {
+    use Middleware::*;
+    // the access log and every response, rejections included, carry the id
+    assert!(position(&PUBLIC, RequestId) == 0);
+    // requests turned away by any other middleware are still logged
+    assert!(position(&PUBLIC, AccessLog) == 1);
+    // browsers only let pages read responses with the CORS headers, the
+    // rejections included
+    assert!(position(&PUBLIC, Cors) == 2);
+    // shedding is only cheap before anything else has been done
+    assert!(position(&PUBLIC, LoadShed) == 3);
+    // nothing is done for a limited client but turning it away
+    assert!(position(&PUBLIC, RateLimit) == 4);
+    // a request's time starts once it is let in, and covers everything done
+    // for it then
+    assert!(position(&PUBLIC, Deadline) == 5);
+    // a stalled body is timed out however deep in it is first read,
+    // recordings buffering it included
+    assert!(outside(&PUBLIC, RequestBody, Recording));
+    // the router picks a route by the rewritten path and method
+    assert!(outside(&PUBLIC, NormalizePath, Maintenance));
+    assert!(outside(&PUBLIC, NormalizePath, ResponseCache));
+    assert!(outside(&PUBLIC, NormalizePath, Recording));
+    // writes tunnelled through POST have to be seen as writes
+    assert!(outside(&PUBLIC, MethodOverride, Maintenance));
+    assert!(outside(&PUBLIC, MethodOverride, ReadOnly));
+    assert!(outside(&PUBLIC, MethodOverride, ResponseCache));
+    // a rejected write must neither be cached nor recorded as handled
+    assert!(outside(&PUBLIC, Maintenance, ResponseCache));
+    assert!(outside(&PUBLIC, ReadOnly, ResponseCache));
+    // recordings show what the handlers answered, not a cached copy
+    assert!(outside(&PUBLIC, ResponseCache, Recording));
+    // cached responses are still served while the database is unavailable
+    assert!(outside(&PUBLIC, ResponseCache, CircuitBreaker));
+    // the store keeps responses as the handlers answered them, compressed
+    // for each client by the encodings it accepts, and recordings show them
+    // readable
+    assert!(outside(&PUBLIC, Compression, ResponseCache));
+    assert!(outside(&PUBLIC, Compression, Recording));
+    assert!(position(&ADMIN, RequestId) == 0);
+    assert!(position(&ADMIN, AccessLog) == 1);
+    assert!(position(&GRPC, RequestId) == 0);
+    assert!(position(&GRPC, AccessLog) == 1);
+};
+
+impl Middleware {
+    /// Whether the configuration switches it on. Maintenance mode and
+    /// recording can be switched on at runtime, so their middlewares are
+    /// always there.
+    fn enabled(self, config: &Config, services: &Services) -> bool {
+        match self {
+            Middleware::RequestId
+            | Middleware::AccessLog
+            | Middleware::LoadShed
+            | Middleware::RequestBody
+            | Middleware::Maintenance
+            | Middleware::Recording => true,
+            Middleware::NormalizePath => {
+                !matches!(config.path_normalization, rewrite::PathNormalization::Off)
+            }
+            Middleware::MethodOverride => config.method_override,
+            Middleware::Cors => config.cors_allowed_origins.is_some(),
+            Middleware::RateLimit => services.rate_limit.is_some(),
+            Middleware::Deadline => config.request_timeout.is_some(),
+            Middleware::CircuitBreaker => config.storage == Storage::Postgres,
+            Middleware::Compression => config.compression,
+            Middleware::ReadOnly => config.read_only,
+            Middleware::ResponseCache => services.response_cache.is_some(),
+        }
+    }
+
+    fn wrap(self, app: listen::App, config: &Config, services: &Services) -> listen::App {
+        match self {
+            Middleware::RequestId => {
+                BoxCloneService::new(middleware::from_fn(request_id::assign).layer(app))
+            }
+            Middleware::AccessLog => BoxCloneService::new(
+                middleware::from_fn_with_state(
+                    (config.access_log_format, config.log_format),
+                    access_log::access_log,
+                )
+                .layer(app),
+            ),
+            Middleware::Cors => match &config.cors_allowed_origins {
+                Some(origins) => BoxCloneService::new(cors::layer(origins, config).layer(app)),
+                None => app,
+            },
+            Middleware::LoadShed => BoxCloneService::new(
+                ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(fallback::overloaded))
+                    .load_shed()
+                    .concurrency_limit(config.max_concurrent_requests)
+                    .service(app),
+            ),
+            Middleware::RateLimit => BoxCloneService::new(
+                middleware::from_fn_with_state(services.rate_limit.clone(), rate_limit::limit)
+                    .layer(app),
+            ),
+            Middleware::Deadline => match config.request_timeout {
+                Some(timeout) => BoxCloneService::new(
+                    middleware::from_fn_with_state(timeout, deadline::enforce).layer(app),
+                ),
+                None => app,
+            },
+            Middleware::RequestBody => {
+                let app = match config.request_body_timeout {
+                    Some(timeout) => BoxCloneService::new(
+                        middleware::from_fn_with_state(timeout, request_body::enforce_timeout)
+                            .layer(app),
+                    ),
+                    None => app,
+                };
+                BoxCloneService::new(DefaultBodyLimit::max(config.max_body_bytes).layer(app))
+            }
+            Middleware::Compression => BoxCloneService::new(
+                ServiceBuilder::new()
+                    .map_response(compression::vary)
+                    .layer(compression::layer())
+                    .service(app),
+            ),
+            Middleware::NormalizePath => BoxCloneService::new(
+                middleware::from_fn_with_state(config.path_normalization, rewrite::normalize_path)
+                    .layer(app),
+            ),
+            Middleware::MethodOverride => BoxCloneService::new(
+                middleware::from_fn_with_state(config.method_override, rewrite::override_method)
+                    .layer(app),
+            ),
+            Middleware::Maintenance => BoxCloneService::new(
+                middleware::from_fn_with_state(
+                    services.maintenance.clone(),
+                    maintenance::reject_writes,
+                )
+                .layer(app),
+            ),
+            Middleware::ReadOnly => BoxCloneService::new(
+                middleware::from_fn_with_state(config.read_only, replica::reject_writes).layer(app),
+            ),
+            Middleware::CircuitBreaker => {
+                BoxCloneService::new(middleware::from_fn(resilience::reject_while_open).layer(app))
+            }
+            Middleware::ResponseCache => BoxCloneService::new(
+                middleware::from_fn_with_state(
+                    services.response_cache.clone(),
+                    response_cache::cache,
+                )
+                .layer(app),
+            ),
+            Middleware::Recording => BoxCloneService::new(
+                middleware::from_fn_with_state(services.recordings.clone(), recording::record)
+                    .layer(app),
+            ),
+        }
+    }
+}
+
+/// Wraps `router` in the enabled middlewares of `stack`, the first one
+/// outermost.
+pub fn assemble(
+    router: Router,
+    stack: &[Middleware],
+    config: &Config,
+    services: &Services,
+) -> listen::App {
+    let enabled: Vec<_> = stack
+        .iter()
+        .copied()
+        .filter(|middleware| middleware.enabled(config, services))
+        .collect();
+    debug!(middlewares = ?enabled, "Assembled middleware stack");
+    enabled
+        .iter()
+        .rev()
+        .fold(BoxCloneService::new(router), |app, middleware| {
+            middleware.wrap(app, config, services)
+        })
+}
@@ -0,0 +1,115 @@
+//! Rewrites of the path and method, which have to run before the router
+//! picks a route.
+
+use axum::{
+    extract::State,
+    http::{HeaderName, Method, Request, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+
+use crate::error::{ErrorCode, Problem};
+
+#[derive(Clone, Copy)]
+pub enum PathNormalization {
+    /// Serve `/todos/` and `//todos` as if `/todos` was requested.
+    Rewrite,
+    /// Answer with a 308 pointing at the canonical path.
+    Redirect,
+    Off,
+}
+
+impl std::str::FromStr for PathNormalization {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "rewrite" => Ok(PathNormalization::Rewrite),
+            "redirect" => Ok(PathNormalization::Redirect),
+            "off" => Ok(PathNormalization::Off),
+            other => {
+                anyhow::bail!("PATH_NORMALIZATION must be rewrite, redirect or off, got {other}")
+            }
+        }
+    }
+}
+
+/// Swagger UI's page links its assets relative to itself, so it keeps its
+/// trailing slash.
+const SWAGGER_UI: &str = "/swagger-ui/";
+
+/// Collapses duplicate slashes and drops a trailing one, returning `None`
+/// when the path is already canonical.
+fn canonical_path(path: &str) -> Option<String> {
+    if path == SWAGGER_UI {
+        return None;
+    }
+    if !path.contains("//") && (path == "/" || !path.ends_with('/')) {
+        return None;
+    }
+    let mut canonical = String::with_capacity(path.len());
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        canonical.push('/');
+        canonical.push_str(segment);
+    }
+    if canonical.is_empty() {
+        canonical.push('/');
+    }
+    Some(canonical)
+}
+
+pub async fn normalize_path<B>(
+    State(mode): State<PathNormalization>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(path) = canonical_path(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+    match mode {
+        PathNormalization::Off => next.run(req).await,
+        PathNormalization::Redirect => Redirect::permanent(&path_and_query).into_response(),
+        PathNormalization::Rewrite => {
+            let mut parts = req.uri().clone().into_parts();
+            parts.path_and_query = path_and_query.parse().ok();
+            if let Ok(uri) = Uri::from_parts(parts) {
+                *req.uri_mut() = uri;
+            }
+            next.run(req).await
+        }
+    }
+}
+
+static X_HTTP_METHOD_OVERRIDE: HeaderName = HeaderName::from_static("x-http-method-override");
+
+/// Lets clients stuck behind GET/POST-only proxies tunnel PUT, PATCH and
+/// DELETE through a POST carrying `X-HTTP-Method-Override`.
+pub async fn override_method<B>(
+    State(enabled): State<bool>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if enabled && req.method() == Method::POST {
+        if let Some(value) = req.headers_mut().remove(&X_HTTP_METHOD_OVERRIDE) {
+            match value.to_str().map(str::to_ascii_uppercase).as_deref() {
+                Ok("PUT") => *req.method_mut() = Method::PUT,
+                Ok("PATCH") => *req.method_mut() = Method::PATCH,
+                Ok("DELETE") => *req.method_mut() = Method::DELETE,
+                _ => {
+                    return Problem {
+                        status: StatusCode::BAD_REQUEST,
+                        detail: "X-HTTP-Method-Override must be PUT, PATCH or DELETE".to_owned(),
+                        code: ErrorCode::InvalidRequest,
+                        details: None,
+                    }
+                    .into_response()
+                }
+            }
+        }
+    }
+    next.run(req).await
+}
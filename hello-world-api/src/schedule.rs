@@ -0,0 +1,156 @@
+//! Soft scheduling: a todo can have a start date before which it isn't
+//! worth looking at yet. It stays in the regular listings, but the today
+//! view only shows open todos that have started.
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::{
+    analytics::Analytics,
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{Json, ListFormat, Path, Query},
+    handlers::todos::list_todos,
+    models::{ListTodos, Priority, ToDoView, Todo},
+    quota::Quota,
+    repository::{todo_query::TodoQuery, Todos},
+    tags::Tag,
+    tx::Tx,
+};
+
+#[derive(Deserialize, ToSchema)]
+pub struct StartAt {
+    start_at: DateTime<Utc>,
+}
+
+/// `GET /todos/today`: open todos whose start date has been reached or that
+/// have none, with the same search, sort and paging as `GET /todos`.
+#[utoipa::path(
+    get,
+    path = "/todos/today",
+    tag = "todos",
+    params(
+        ListTodos,
+    ),
+    responses(
+        (status = 200, description = "A page of todos, a `TodoMetaListPage` with `?meta=true`, CSV rows with `Accept: text/csv`; carries quota `warnings` once near the limit", body = TodoListPage, headers(("x-warning" = String, description = "One per entry of `warnings`"))),
+        (status = 400, description = "Invalid filter, sort or paging", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 406, description = "Accepts neither JSON nor CSV", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn today(
+    State(todos): State<Todos>,
+    AuthUser(user_id): AuthUser,
+    Extension(quota): Extension<Option<Quota>>,
+    Extension(analytics): Extension<Option<Analytics>>,
+    format: ListFormat,
+    Query(params): Query<ListTodos>,
+) -> axum::response::Response {
+    let meta = params.meta;
+    let mut query = match TodoQuery::try_from(params) {
+        Ok(query) => query,
+        Err(err) => return err.into_response(),
+    };
+    query.is_done = Some(false);
+    query.started = Some(true);
+    query.expired = Some(false);
+    list_todos(
+        &*todos,
+        quota,
+        analytics.as_ref(),
+        user_id,
+        query,
+        meta,
+        format,
+    )
+    .await
+}
+
+#[utoipa::path(
+    put,
+    path = "/todos/{id}/start",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    request_body = StartAt,
+    responses(
+        (status = 200, description = "The updated todo", body = ToDoView),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn put_start(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+    Json(body): Json<StartAt>,
+) -> axum::response::Response {
+    let result = sqlx::query_as!(
+        Todo,
+        r#"update "todo" set start_at = $1
+        where id = $2 and user_id = $3 and merged_into is null and deleted_at is null
+        returning id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, null::timestamptz as deleted_at, null::jsonb as field_modified,
+            todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+            todo_completion(id) as completion_percent, null::text as shared_by"#,
+        body.start_at,
+        id,
+        user_id,
+    )
+    .fetch_one(&mut *tx)
+    .await;
+    let todo = match result {
+        Ok(todo) => todo,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    match events.updated(&mut tx, user_id, &todo).await {
+        Ok(()) => (StatusCode::OK, Json(ToDoView::from(todo))).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/start",
+    tag = "todos",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    responses(
+        (status = 204, description = "Start date cleared"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_start(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    let result = sqlx::query(
+        r#"update "todo" set start_at = null
+        where id = $1 and user_id = $2 and merged_into is null and deleted_at is null"#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await;
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            ApiError::from(sqlx::Error::RowNotFound).into_response()
+        }
+        Ok(_) => match events.changed(&mut tx, user_id, id).await {
+            Ok(()) => StatusCode::NO_CONTENT.into_response(),
+            Err(err) => ApiError::from(err).into_response(),
+        },
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
@@ -0,0 +1,165 @@
+//! Ranked full-text search of todos, `GET /todos/search?q=`. A todo matches
+//! if it has a word starting with each word of `q`, so results come as the
+//! user types, or has each of them once stemmed in the language `q` is
+//! written in. Matches are ranked by how closely and how often they match,
+//! words stemmed in the todo's own language counting most.
+//!
+//! Unlike the `?search=` filter of `GET /todos`, which parses the query in
+//! each todo's own language, the query is the same for every todo and can
+//! use the index on `search_document`.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    analytics::Analytics,
+    auth::AuthUser,
+    error::ApiError,
+    extract::{Json, Query},
+    language,
+    models::{Priority, ToDoView, Todo},
+    tags::Tag,
+};
+
+const MAX_LIMIT: i64 = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchTodos {
+    /// Words to look for, each also as the start of a longer word.
+    q: String,
+    /// Page size, 1 to 100 (default 20).
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    todo: ToDoView,
+    /// How well the todo matches, higher first.
+    rank: f32,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchPage {
+    /// Best match first.
+    items: Vec<SearchHit>,
+    /// All todos matching, across pages; 0 past the last one.
+    total: i64,
+}
+
+/// `word1:* & word2:*` of the words of `q`, only ever letters and digits, so
+/// `to_tsquery` can't fail on it; `None` if `q` has no words.
+fn prefix_query(q: &str) -> Option<String> {
+    let words: Vec<_> = q
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("{}:*", word.to_lowercase()))
+        .collect();
+    (!words.is_empty()).then(|| words.join(" & "))
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/search",
+    tag = "todos",
+    params(
+        SearchTodos,
+    ),
+    responses(
+        (status = 200, description = "A page of the matching todos, best match first", body = SearchPage),
+        (status = 400, description = "No word in `q`, or `limit` out of range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn search(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Extension(analytics): Extension<Option<Analytics>>,
+    Query(params): Query<SearchTodos>,
+) -> axum::response::Response {
+    let limit = params.limit.unwrap_or(20);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {MAX_LIMIT}"),
+        )
+        .into_response();
+    }
+    let Some(prefixes) = prefix_query(&params.q) else {
+        return ApiError::new(StatusCode::BAD_REQUEST, "q must have a word to search for")
+            .into_response();
+    };
+    let result = sqlx::query!(
+        r#"with query as (
+            select to_tsquery('simple', $1)
+                || plainto_tsquery($2::text::regconfig, $3) as query
+        )
+        select id, todo_text, is_done, start_at, due_at, expires_at, expired_at, version,
+            list_id, assignee_id, priority as "priority: Priority", recurrence, created_at, updated_at,
+            position, todo_tags(id) as "tags!: sqlx::types::Json<Vec<Tag>>",
+            todo_completion(id) as completion_percent,
+            ts_rank_cd(search_document, query.query) as "rank!",
+            count(*) over () as "total!"
+        from "todo", query
+        where user_id = $4 and merged_into is null and deleted_at is null
+            and search_document @@ query.query
+        order by "rank!" desc, id
+        limit $5
+        offset $6"#,
+        prefixes,
+        language::search_config(&params.q),
+        params.q,
+        user_id,
+        limit,
+        params.offset.unwrap_or(0).max(0),
+    )
+    .fetch_all(&*pg)
+    .await;
+    let rows = match result {
+        Ok(rows) => rows,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let total = rows.first().map_or(0, |row| row.total);
+    if let Some(analytics) = analytics {
+        let properties = serde_json::json!({
+            "kind": "ranked",
+            "filtered": false,
+            "sorted": false,
+            "results": total,
+        });
+        analytics.emit(user_id, "search_performed", properties);
+    }
+    let items = rows
+        .into_iter()
+        .map(|row| SearchHit {
+            todo: ToDoView::from(Todo {
+                id: row.id,
+                todo_text: row.todo_text,
+                is_done: row.is_done,
+                start_at: row.start_at,
+                due_at: row.due_at,
+                expires_at: row.expires_at,
+                expired_at: row.expired_at,
+                version: row.version,
+                list_id: row.list_id,
+                assignee_id: row.assignee_id,
+                priority: row.priority,
+                recurrence: row.recurrence,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+                position: row.position,
+                deleted_at: None,
+                field_modified: None,
+                tags: row.tags,
+                completion_percent: row.completion_percent,
+                shared_by: None,
+            }),
+            rank: row.rank,
+        })
+        .collect();
+    Json(SearchPage { items, total }).into_response()
+}
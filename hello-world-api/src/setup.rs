@@ -0,0 +1,118 @@
+//! First-run setup. Until the database has a user, `POST /setup` creates
+//! the first one as an admin and logs them in, so a fresh install needs no
+//! SQL to get an admin. Once any user exists both setup endpoints answer 404.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{self, Auth, Credentials, Scope},
+    error::ApiError,
+    extract::Json,
+};
+
+/// Key of the advisory lock serializing setups, so two racing requests
+/// can't both create an admin.
+const SETUP_LOCK: i64 = 0x0073_6574_7570;
+
+#[derive(Serialize, ToSchema)]
+pub struct SetupState {
+    /// Always `true`: once setup is done the endpoint is gone.
+    needed: bool,
+}
+
+/// Whether the first admin still has to be created.
+#[utoipa::path(
+    get,
+    path = "/setup",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Setup is needed", body = SetupState),
+        (status = 404, description = "Setup is done", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn get(pg: Extension<PgPool>) -> axum::response::Response {
+    match has_users(&pg).await {
+        Ok(false) => Json(SetupState { needed: true }).into_response(),
+        Ok(true) => done().into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Creates the first user, an admin, and answers with a token carrying
+/// every scope.
+#[utoipa::path(
+    post,
+    path = "/setup",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 201, description = "The admin was created, with a bearer token for them", body = TokenView),
+        (status = 400, description = "Username or password too short or long", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Setup is done", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn post(
+    pg: Extension<PgPool>,
+    Extension(auth): Extension<Auth>,
+    Json(credentials): Json<Credentials>,
+) -> axum::response::Response {
+    // cheap check first, so a finished setup doesn't hash passwords
+    match has_users(&pg).await {
+        Ok(false) => {}
+        Ok(true) => return done().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    }
+    let (username, hash) = match auth::new_account(credentials).await {
+        Ok(account) => account,
+        Err(err) => return err.into_response(),
+    };
+    let user_id = match create_admin(&pg, &username, &hash).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return done().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    info!(%user_id, username, "Created the first admin");
+    match auth.issue(user_id, &Scope::ALL) {
+        Ok(token) => (StatusCode::CREATED, Json(token)).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+async fn has_users(pg: &PgPool) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(r#"select exists(select 1 from "user")"#)
+        .fetch_one(pg)
+        .await
+}
+
+/// The new admin's id, `None` if another user was created in the meantime.
+async fn create_admin(
+    pg: &PgPool,
+    username: &str,
+    hash: &str,
+) -> Result<Option<uuid::Uuid>, sqlx::Error> {
+    let mut tx = pg.begin().await?;
+    sqlx::query("select pg_advisory_xact_lock($1)")
+        .bind(SETUP_LOCK)
+        .execute(&mut tx)
+        .await?;
+    let user_id = sqlx::query_scalar::<_, uuid::Uuid>(
+        r#"insert into "user" (username, password_hash, role)
+        select $1, $2, 'admin'
+        where not exists (select 1 from "user")
+        returning user_id"#,
+    )
+    .bind(username)
+    .bind(hash)
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(user_id)
+}
+
+fn done() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "Setup is done")
+}
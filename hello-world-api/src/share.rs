@@ -0,0 +1,272 @@
+//! Unauthenticated read-only links to a single todo, created and revoked by
+//! the todo's owner. Only a hash of the
+//! token is stored, so the link can't be recovered from the database: it is
+//! shown once when created and can afterwards only be revoked.
+
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+    Extension,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{Json, Path, Query},
+    i18n::{Locale, Phrase},
+    models::{Priority, ToDoView, Todo, TodoStatus},
+    tags::Tag,
+    versioning,
+};
+
+/// Lifetime of a link created without an explicit `expires_at`.
+const DEFAULT_LIFETIME_DAYS: i64 = 30;
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateShareLink {
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ShareLinkView {
+    id: uuid::Uuid,
+    /// Path of the public view, relative to the API's base URL.
+    url: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ViewShareLink {
+    /// IANA timezone the HTML view shows dates in, UTC by default.
+    tz: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/share-link",
+    tag = "sharing",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    request_body(content = Option<CreateShareLink>),
+    responses(
+        (status = 201, description = "The new link", body = ShareLinkView),
+        (status = 400, description = "`expires_at` is in the past", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such todo", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Path(todo_id): Path<uuid::Uuid>,
+    body: Option<Json<CreateShareLink>>,
+) -> axum::response::Response {
+    let now = Utc::now();
+    let expires_at = body
+        .and_then(|Json(body)| body.expires_at)
+        .unwrap_or_else(|| now + Duration::days(DEFAULT_LIFETIME_DAYS));
+    if expires_at <= now {
+        return ApiError::new(StatusCode::BAD_REQUEST, "expires_at must be in the future")
+            .into_response();
+    }
+    // two v4 uuids give 244 random bits
+    let token = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let result = sqlx::query_scalar::<_, uuid::Uuid>(
+        r#"insert into "share_link" (todo_id, token_hash, expires_at)
+        select id, $2, $3 from "todo"
+        where id = $1 and user_id = $4 and merged_into is null and deleted_at is null
+        returning id"#,
+    )
+    .bind(todo_id)
+    .bind(hash(&token))
+    .bind(expires_at)
+    .bind(user_id)
+    .fetch_one(&*pg)
+    .await;
+    match result {
+        Ok(id) => (
+            StatusCode::CREATED,
+            Json(ShareLinkView {
+                id,
+                url: format!("{}/shared/{token}", versioning::V1),
+                expires_at,
+            }),
+        )
+            .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/share-link/{link_id}",
+    tag = "sharing",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("link_id" = uuid::Uuid, Path, description = "Share link id"),
+    ),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 404, description = "No such todo or link", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn revoke(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Path((todo_id, link_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> axum::response::Response {
+    let result = sqlx::query(
+        r#"update "share_link" set revoked_at = coalesce(revoked_at, now())
+        where id = $1 and todo_id = (select id from "todo" where id = $2 and user_id = $3)"#,
+    )
+    .bind(link_id)
+    .bind(todo_id)
+    .bind(user_id)
+    .execute(&*pg)
+    .await;
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            ApiError::from(sqlx::Error::RowNotFound).into_response()
+        }
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// `GET /shared/:token`, rendered as HTML for browsers and JSON otherwise.
+/// Expired, revoked and unknown tokens all look the same. The HTML is in
+/// the language of `Accept-Language` and the timezone of `?tz=`.
+#[utoipa::path(
+    get,
+    path = "/shared/{token}",
+    tag = "sharing",
+    params(
+        ("token" = String, Path, description = "Token from the link's `url`"),
+        ViewShareLink,
+    ),
+    responses(
+        (status = 200, description = "The todo as an HTML page", body = String, content_type = "text/html"),
+        (status = 400, description = "`tz` is not a timezone", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Unknown, expired or revoked link", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn view(
+    pg: Extension<PgPool>,
+    Path(token): Path<String>,
+    Query(params): Query<ViewShareLink>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    let tz = match params.tz.as_deref().map(str::parse::<chrono_tz::Tz>) {
+        None => chrono_tz::UTC,
+        Some(Ok(tz)) => tz,
+        Some(Err(_)) => {
+            return ApiError::new(StatusCode::BAD_REQUEST, "tz must be an IANA timezone name")
+                .into_response()
+        }
+    };
+    let result = sqlx::query_as!(
+        Todo,
+        r#"select t.id, t.todo_text, t.is_done, t.start_at, t.due_at, t.expires_at, t.expired_at,
+            t.version, null::uuid as list_id, null::uuid as assignee_id,
+            t.priority as "priority: Priority", t.recurrence,
+            t.created_at, t.updated_at, t.position, null::timestamptz as deleted_at,
+            null::jsonb as field_modified, '[]'::json as "tags!: sqlx::types::Json<Vec<Tag>>",
+            null::integer as completion_percent, null::text as shared_by
+        from "share_link" l
+        join "todo" t on t.id = l.todo_id
+        where l.token_hash = $1
+            and l.revoked_at is null
+            and l.expires_at > now()
+            and t.merged_into is null and t.deleted_at is null"#,
+        hash(&token),
+    )
+    .fetch_one(&*pg)
+    .await;
+    let todo = match result {
+        Ok(todo) => todo,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    // the link can be revoked at any time, so nobody may keep a copy
+    let cache = [
+        (header::CACHE_CONTROL, "no-store"),
+        (header::HeaderName::from_static("x-robots-tag"), "noindex"),
+    ];
+    let wants_html = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    if wants_html {
+        let locale = Locale::from_accept_language(
+            headers
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok()),
+        );
+        (
+            cache,
+            [
+                (header::CONTENT_LANGUAGE, locale.tag()),
+                (header::VARY, "Accept, Accept-Language"),
+            ],
+            Html(render(&todo, locale, tz)),
+        )
+            .into_response()
+    } else {
+        (
+            cache,
+            // tags are the owner's own, not part of what is shared, and
+            // aren't selected
+            Json(ToDoView::from(todo)),
+        )
+            .into_response()
+    }
+}
+
+fn hash(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+fn render(todo: &Todo, locale: Locale, tz: chrono_tz::Tz) -> String {
+    let (status, class) = match todo.status() {
+        TodoStatus::Done => (locale.text(Phrase::Done), "done"),
+        TodoStatus::Expired => (locale.text(Phrase::Expired), "expired"),
+        TodoStatus::Open => (locale.text(Phrase::Open), "open"),
+    };
+    let start = match todo.start_at {
+        Some(start_at) => format!(
+            "<p class=\"start\">{starts} <time datetime=\"{datetime}\">{date}</time> ({relative})</p>",
+            starts = locale.text(Phrase::Starts),
+            datetime = start_at.to_rfc3339(),
+            date = locale.format_date_time(start_at.with_timezone(&tz)),
+            relative = locale.format_relative(start_at, Utc::now()),
+        ),
+        None => String::new(),
+    };
+    format!(
+        "<!doctype html>\n<html lang=\"{lang}\"><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width\">\
+         <title>{text}</title></head>\
+         <body><main><h1>{text}</h1><p class=\"{class}\">{status}</p>{start}</main></body></html>\n",
+        lang = locale.tag(),
+        text = escape_html(&todo.todo_text),
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,264 @@
+//! Completion statistics. They are read from the `todo_daily_completions`
+//! materialized view rather than the todo table, so they lag behind by up to
+//! `STATS_REFRESH_SECS` and say as of when in `refreshed_at`.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{Json, Query},
+    jobs::Job,
+};
+
+/// Upper bound on `to - from`, so one request can't generate millions of
+/// daily buckets.
+const MAX_RANGE_DAYS: i64 = 3660;
+
+#[derive(Deserialize, Serialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Day,
+    Week,
+    Month,
+}
+
+impl Bucket {
+    /// Field name understood by Postgres' `date_trunc`.
+    fn field(self) -> &'static str {
+        match self {
+            Bucket::Day => "day",
+            Bucket::Week => "week",
+            Bucket::Month => "month",
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CompletionsQuery {
+    bucket: Option<Bucket>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CompletionsView {
+    bucket: Bucket,
+    from: NaiveDate,
+    to: NaiveDate,
+    /// When the counts were last brought up to date.
+    refreshed_at: DateTime<Utc>,
+    counts: Vec<BucketCount>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BucketCount {
+    start: NaiveDate,
+    completed: i64,
+}
+
+/// The user's todos completed per day, week or month between `from` and `to`
+/// (inclusive, UTC), with empty buckets reported as zero. Defaults to daily
+/// counts for the last 30 days.
+#[utoipa::path(
+    get,
+    path = "/stats/completions",
+    tag = "stats",
+    params(
+        CompletionsQuery,
+    ),
+    responses(
+        (status = 200, description = "Completed todos per bucket", body = CompletionsView),
+        (status = 400, description = "Invalid or too long range", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn completions(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<CompletionsQuery>,
+) -> axum::response::Response {
+    let bucket = query.bucket.unwrap_or(Bucket::Day);
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query.from.unwrap_or(to - Duration::days(29));
+    if from > to {
+        return ApiError::new(StatusCode::BAD_REQUEST, "from must not be after to").into_response();
+    }
+    if (to - from).num_days() > MAX_RANGE_DAYS {
+        return ApiError::new(StatusCode::BAD_REQUEST, "Range must not exceed ten years")
+            .into_response();
+    }
+
+    let refreshed_at = match refreshed_at(&pg).await {
+        Ok(refreshed_at) => refreshed_at,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let result = completion_counts(&pg, user_id, bucket, from, to).await;
+    match result {
+        Ok(counts) => (
+            StatusCode::OK,
+            Json(CompletionsView {
+                bucket,
+                from,
+                to,
+                refreshed_at,
+                counts,
+            }),
+        )
+            .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Refreshes the counts the stats endpoints read, without blocking them
+/// while it does.
+pub struct RefreshStats;
+
+#[async_trait]
+impl Job for RefreshStats {
+    fn name(&self) -> &'static str {
+        "refresh_stats"
+    }
+
+    /// Handles the one view.
+    async fn run(&self, pg: &PgPool) -> anyhow::Result<i64> {
+        refresh(pg).await?;
+        Ok(1)
+    }
+}
+
+async fn refresh(pg: &PgPool) -> Result<(), sqlx::Error> {
+    let mut tx = pg.begin().await?;
+    // refreshing may take longer than the pool's statement_timeout allows
+    sqlx::query("set local statement_timeout = 0")
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(r#"refresh materialized view concurrently "todo_daily_completions""#)
+        .execute(&mut tx)
+        .await?;
+    // now() is when the transaction began, before the view's snapshot
+    sqlx::query(
+        r#"update "view_refresh" set refreshed_at = now()
+        where view_name = 'todo_daily_completions'"#,
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await
+}
+
+async fn refreshed_at(pg: &PgPool) -> Result<DateTime<Utc>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"select refreshed_at from "view_refresh" where view_name = 'todo_daily_completions'"#,
+    )
+    .fetch_one(pg)
+    .await
+}
+
+async fn completion_counts(
+    pg: &PgPool,
+    user_id: uuid::Uuid,
+    bucket: Bucket,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<BucketCount>, sqlx::Error> {
+    sqlx::query_as!(
+        BucketCount,
+        r#"select b.start::date as "start!", coalesce(sum(c.completed), 0)::bigint as "completed!"
+        from generate_series(
+            date_trunc($1, $2::date::timestamp), $3::date::timestamp, ('1 ' || $1)::interval
+        ) as b(start)
+        left join "todo_daily_completions" c
+            on date_trunc($1, c.day::timestamp) = b.start
+            and c.user_id = $4
+            and c.day between $2::date and $3::date
+        group by b.start
+        order by b.start"#,
+        bucket.field(),
+        from,
+        to,
+        user_id,
+    )
+    .fetch_all(pg)
+    .await
+}
+
+/// How long a computed heatmap is served before it is recomputed.
+const HEATMAP_TTL: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Last computed heatmap of each user, shared by all requests.
+#[derive(Clone, Default)]
+pub struct HeatmapCache(Arc<Mutex<HashMap<uuid::Uuid, Arc<HeatmapView>>>>);
+
+#[derive(Serialize, ToSchema)]
+pub struct HeatmapView {
+    #[serde(skip)]
+    computed_at: Instant,
+    from: NaiveDate,
+    to: NaiveDate,
+    /// When the counts were last brought up to date.
+    refreshed_at: DateTime<Utc>,
+    /// Highest daily count in the range, for scaling colours.
+    max: i64,
+    days: Vec<BucketCount>,
+}
+
+/// The user's daily completion counts for the last 365 days. The grid is
+/// cached for `HEATMAP_TTL` and recomputed once the day rolls over.
+#[utoipa::path(
+    get,
+    path = "/stats/heatmap",
+    tag = "stats",
+    responses(
+        (status = 200, description = "Completed todos per day of the last year", body = HeatmapView),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn heatmap(
+    pg: Extension<PgPool>,
+    Extension(cache): Extension<HeatmapCache>,
+    AuthUser(user_id): AuthUser,
+) -> axum::response::Response {
+    let to = Utc::now().date_naive();
+    let cached = cache.0.lock().unwrap().get(&user_id).cloned();
+    if let Some(view) = cached {
+        if view.computed_at.elapsed() < HEATMAP_TTL && view.to == to {
+            return (StatusCode::OK, Json(&*view)).into_response();
+        }
+    }
+
+    let from = to - Duration::days(364);
+    let refreshed_at = match refreshed_at(&pg).await {
+        Ok(refreshed_at) => refreshed_at,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    match completion_counts(&pg, user_id, Bucket::Day, from, to).await {
+        Ok(days) => {
+            let view = Arc::new(HeatmapView {
+                computed_at: Instant::now(),
+                from,
+                to,
+                refreshed_at,
+                max: days.iter().map(|day| day.completed).max().unwrap_or(0),
+                days,
+            });
+            let mut cache = cache.0.lock().unwrap();
+            // stale grids of other users go whenever one is recomputed
+            cache.retain(|_, view| view.computed_at.elapsed() < HEATMAP_TTL);
+            cache.insert(user_id, view.clone());
+            (StatusCode::OK, Json(&*view)).into_response()
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
@@ -0,0 +1,480 @@
+//! Tags: labels each user keeps for their own todos, any number per todo.
+//! Todos embed their tags wherever they are answered with, and listings can
+//! be filtered by one with `?tag=`. `GET /tags/stats` counts each tag's
+//! todos, for the sidebars of clients, and `POST /todos/bulk/tags` adds and
+//! removes tags across many todos at once.
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool};
+pub use todo_api_types::Tag;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::Events,
+    extract::{check_text, FieldError, Json, Path, Valid, Validate},
+    models::{Priority, MAX_BULK_TODOS},
+    repository::todo_query::TodoQuery,
+    tx::Tx,
+};
+
+/// Longest tag name accepted, in characters.
+pub const MAX_TAG_CHARS: usize = 50;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateTag {
+    /// Stored trimmed, which must leave 1 to 50 characters.
+    #[schema(min_length = 1, max_length = 50)]
+    pub name: String,
+}
+
+impl Validate for CreateTag {
+    fn validate(&self) -> Vec<FieldError> {
+        check_text("name", &self.name, MAX_TAG_CHARS)
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Tags to add to and remove from the todos given by `ids` or by `filter`,
+/// one of which is required.
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BulkTags {
+    /// Names of the tags to add, created if the user has none by that name.
+    #[serde(default)]
+    pub add: Vec<String>,
+    /// Names of the tags to remove.
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Up to 100 of the user's todos.
+    pub ids: Option<Vec<uuid::Uuid>>,
+    /// All the user's todos matching it, however many.
+    pub filter: Option<BulkTagFilter>,
+}
+
+/// The filters of `GET /todos` that select todos to tag.
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BulkTagFilter {
+    pub is_done: Option<bool>,
+    pub overdue: Option<bool>,
+    pub due_before: Option<DateTime<Utc>>,
+    /// Name of a tag the todos must have.
+    pub tag: Option<String>,
+    pub list_id: Option<uuid::Uuid>,
+    pub priority: Option<Priority>,
+    /// Case-insensitive substring of the text.
+    pub q: Option<String>,
+}
+
+impl Validate for BulkTags {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.add.is_empty() && self.remove.is_empty() {
+            errors.push(FieldError {
+                field: "add",
+                reason: "must not be empty without remove".to_owned(),
+            });
+        }
+        for (field, names) in [("add", &self.add), ("remove", &self.remove)] {
+            errors.extend(
+                names
+                    .iter()
+                    .find_map(|name| check_text(field, name, MAX_TAG_CHARS)),
+            );
+        }
+        if self.add.iter().any(|name| self.remove.contains(name)) {
+            errors.push(FieldError {
+                field: "remove",
+                reason: "must not have the tags of add".to_owned(),
+            });
+        }
+        match (&self.ids, &self.filter) {
+            (Some(ids), None) if ids.is_empty() || ids.len() > MAX_BULK_TODOS => {
+                errors.push(FieldError {
+                    field: "ids",
+                    reason: format!("must have 1 to {MAX_BULK_TODOS} entries"),
+                })
+            }
+            (Some(_), None) | (None, Some(_)) => {}
+            _ => errors.push(FieldError {
+                field: "ids",
+                reason: "must be given, or filter instead".to_owned(),
+            }),
+        }
+        errors
+    }
+}
+
+impl BulkTags {
+    /// The query for the todos to tag, the user's live ones.
+    fn query(&self) -> TodoQuery {
+        let filter = self.filter.as_ref();
+        TodoQuery {
+            ids: self.ids.clone(),
+            is_done: filter.and_then(|filter| filter.is_done),
+            overdue: filter.and_then(|filter| filter.overdue),
+            due_before: filter.and_then(|filter| filter.due_before),
+            tag: filter.and_then(|filter| filter.tag.clone()),
+            list_id: filter.and_then(|filter| filter.list_id),
+            priority: filter.and_then(|filter| filter.priority),
+            text_contains: filter
+                .and_then(|filter| filter.q.clone())
+                .filter(|q| !q.is_empty()),
+            ..TodoQuery::default()
+        }
+    }
+}
+
+/// How many todos a bulk tag operation changed.
+#[derive(Serialize, ToSchema)]
+pub struct BulkTagged {
+    /// The todos given, or matching the filter.
+    pub matched: usize,
+    /// Those that gained or lost a tag.
+    pub affected: usize,
+}
+
+/// A tag with how many of its todos are open and done.
+#[derive(Serialize, ToSchema)]
+pub struct TagStats {
+    pub id: uuid::Uuid,
+    pub name: String,
+    /// How many of its todos are neither done nor expired.
+    pub open: i64,
+    pub done: i64,
+    /// When one of its todos last changed; null for a tag without todos.
+    pub last_activity_at: Option<DateTime<Utc>>,
+}
+
+/// The user's tags, by name.
+#[utoipa::path(
+    get,
+    path = "/tags",
+    tag = "tags",
+    responses(
+        (status = 200, description = "Every tag of the user", body = Vec<Tag>),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list(pg: Extension<PgPool>, AuthUser(user_id): AuthUser) -> axum::response::Response {
+    match user_tags(&pg, user_id).await {
+        Ok(tags) => Json(tags).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// The user's tags, by name, with the counts of their todos.
+#[utoipa::path(
+    get,
+    path = "/tags/stats",
+    tag = "tags",
+    responses(
+        (status = 200, description = "Every tag of the user, tags without todos included", body = Vec<TagStats>),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn stats(pg: Extension<PgPool>, AuthUser(user_id): AuthUser) -> axum::response::Response {
+    match tag_stats(&pg, user_id).await {
+        Ok(stats) => Json(stats).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/tags",
+    tag = "tags",
+    request_body = CreateTag,
+    responses(
+        (status = 201, description = "The created tag", body = Tag),
+        (status = 409, description = "The user has a tag with that name", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Empty or too long name, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Valid(body): Valid<CreateTag>,
+) -> axum::response::Response {
+    match insert_tag(&pg, user_id, &body).await {
+        Ok(tag) => (StatusCode::CREATED, Json(tag)).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Tags a todo; tagging it again changes nothing.
+#[utoipa::path(
+    put,
+    path = "/todos/{id}/tags/{tag_id}",
+    tag = "tags",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("tag_id" = uuid::Uuid, Path, description = "Tag id"),
+    ),
+    responses(
+        (status = 204, description = "The todo has the tag"),
+        (status = 404, description = "No such todo or tag", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn attach(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Path((todo_id, tag_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> axum::response::Response {
+    let result = tag_todo(&mut *tx, user_id, todo_id, tag_id).await;
+    if let Ok(true) = result {
+        if let Err(err) = events.changed(&mut tx, user_id, todo_id).await {
+            return ApiError::from(err).into_response();
+        }
+    }
+    respond(result)
+}
+
+/// Untags a todo; untagging one without the tag changes nothing.
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/tags/{tag_id}",
+    tag = "tags",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("tag_id" = uuid::Uuid, Path, description = "Tag id"),
+    ),
+    responses(
+        (status = 204, description = "The todo doesn't have the tag"),
+        (status = 404, description = "No such todo or tag", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn detach(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Path((todo_id, tag_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> axum::response::Response {
+    let result = untag_todo(&mut *tx, user_id, todo_id, tag_id).await;
+    if let Ok(true) = result {
+        if let Err(err) = events.changed(&mut tx, user_id, todo_id).await {
+            return ApiError::from(err).into_response();
+        }
+    }
+    respond(result)
+}
+
+/// Adds and removes tags across the todos given by id or matching a filter,
+/// all or, failing, none of them. Todos already tagged as asked are left
+/// alone and not counted as affected; ids that aren't the user's todos are
+/// skipped.
+#[utoipa::path(
+    post,
+    path = "/todos/bulk/tags",
+    tag = "tags",
+    request_body = BulkTags,
+    responses(
+        (status = 200, description = "How many todos matched and changed", body = BulkTagged),
+        (status = 422, description = "No tags, too long names, a tag both added and removed, not one of ids and filter, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn bulk(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Extension(events): Extension<Events>,
+    Valid(body): Valid<BulkTags>,
+) -> axum::response::Response {
+    let add: Vec<String> = body.add.iter().map(|name| name.trim().to_owned()).collect();
+    let remove: Vec<String> = body
+        .remove
+        .iter()
+        .map(|name| name.trim().to_owned())
+        .collect();
+    let query = body.query();
+    let mut ids = query.build_ids(user_id);
+    let matched: Vec<uuid::Uuid> = match ids
+        .build_query_as::<(uuid::Uuid,)>()
+        .fetch_all(&mut *tx)
+        .await
+    {
+        Ok(rows) => rows.into_iter().map(|(id,)| id).collect(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let affected = match retag(&mut tx, user_id, &matched, &add, &remove).await {
+        Ok(affected) => affected,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    for &todo_id in &affected {
+        if let Err(err) = events.changed(&mut tx, user_id, todo_id).await {
+            return ApiError::from(err).into_response();
+        }
+    }
+    let tagged = BulkTagged {
+        matched: matched.len(),
+        affected: affected.len(),
+    };
+    (StatusCode::OK, Json(tagged)).into_response()
+}
+
+/// Tags `todo_ids` with the user's tags named `add`, creating the missing
+/// ones, and untags them from those named `remove`, returning the todos
+/// that changed.
+async fn retag(
+    tx: &mut Tx,
+    user_id: uuid::Uuid,
+    todo_ids: &[uuid::Uuid],
+    add: &[String],
+    remove: &[String],
+) -> Result<Vec<uuid::Uuid>, sqlx::Error> {
+    if todo_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    sqlx::query(
+        r#"insert into "tag" (user_id, name) select $1, unnest($2::text[])
+        on conflict (user_id, name) do nothing"#,
+    )
+    .bind(user_id)
+    .bind(add)
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query_scalar::<_, uuid::Uuid>(
+        r#"with added as (
+            insert into "todo_tag" (todo_id, tag_id)
+            select t.id, g.id from unnest($2::uuid[]) t(id)
+            join "tag" g on g.user_id = $1 and g.name = any($3)
+            on conflict do nothing
+            returning todo_id
+        ), removed as (
+            delete from "todo_tag" tt
+            using "tag" g
+            where g.id = tt.tag_id and g.user_id = $1 and g.name = any($4)
+                and tt.todo_id = any($2)
+            returning tt.todo_id
+        )
+        select todo_id from added union select todo_id from removed"#,
+    )
+    .bind(user_id)
+    .bind(todo_ids)
+    .bind(add)
+    .bind(remove)
+    .fetch_all(&mut **tx)
+    .await
+}
+
+/// 204 if the todo and tag were found, 404 otherwise.
+fn respond(found: Result<bool, sqlx::Error>) -> axum::response::Response {
+    match found {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => ApiError::from(sqlx::Error::RowNotFound).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// The user's tags, by name.
+pub async fn user_tags(pg: &PgPool, user_id: uuid::Uuid) -> Result<Vec<Tag>, sqlx::Error> {
+    sqlx::query_as!(
+        Tag,
+        r#"select id, name from "tag" where user_id = $1 order by name"#,
+        user_id,
+    )
+    .fetch_all(pg)
+    .await
+}
+
+/// The user's tags, by name, counting their todos in one pass.
+pub async fn tag_stats(pg: &PgPool, user_id: uuid::Uuid) -> Result<Vec<TagStats>, sqlx::Error> {
+    sqlx::query_as!(
+        TagStats,
+        r#"select g.id, g.name,
+            count(t.id) filter (where not t.is_done and t.expired_at is null) as "open!",
+            count(t.id) filter (where t.is_done) as "done!",
+            max(t.updated_at) as last_activity_at
+        from "tag" g
+        left join "todo_tag" tt on tt.tag_id = g.id
+        left join "todo" t on t.id = tt.todo_id
+            and t.merged_into is null and t.deleted_at is null
+        where g.user_id = $1
+        group by g.id
+        order by g.name"#,
+        user_id,
+    )
+    .fetch_all(pg)
+    .await
+}
+
+pub async fn insert_tag(
+    pg: &PgPool,
+    user_id: uuid::Uuid,
+    tag: &CreateTag,
+) -> Result<Tag, sqlx::Error> {
+    sqlx::query_as!(
+        Tag,
+        r#"insert into "tag" (user_id, name) values ($1, $2) returning id, name"#,
+        user_id,
+        tag.name.trim(),
+    )
+    .fetch_one(pg)
+    .await
+}
+
+/// Tags the todo, false if the user has no such todo or tag.
+pub async fn tag_todo(
+    db: impl PgExecutor<'_>,
+    user_id: uuid::Uuid,
+    todo_id: uuid::Uuid,
+    tag_id: uuid::Uuid,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        r#"with target as (
+            select t.id as todo_id, g.id as tag_id
+            from "todo" t
+            join "tag" g on g.user_id = t.user_id
+            where t.id = $1 and g.id = $2 and t.user_id = $3
+                and t.merged_into is null and t.deleted_at is null
+        ), attached as (
+            insert into "todo_tag" (todo_id, tag_id)
+            select todo_id, tag_id from target
+            on conflict do nothing
+        )
+        select exists(select 1 from target)"#,
+    )
+    .bind(todo_id)
+    .bind(tag_id)
+    .bind(user_id)
+    .fetch_one(db)
+    .await
+}
+
+/// Untags the todo, false if the user has no such todo or tag.
+pub async fn untag_todo(
+    db: impl PgExecutor<'_>,
+    user_id: uuid::Uuid,
+    todo_id: uuid::Uuid,
+    tag_id: uuid::Uuid,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        r#"with target as (
+            select t.id as todo_id, g.id as tag_id
+            from "todo" t
+            join "tag" g on g.user_id = t.user_id
+            where t.id = $1 and g.id = $2 and t.user_id = $3
+                and t.merged_into is null and t.deleted_at is null
+        ), detached as (
+            delete from "todo_tag" tt
+            using target
+            where tt.todo_id = target.todo_id and tt.tag_id = target.tag_id
+        )
+        select exists(select 1 from target)"#,
+    )
+    .bind(todo_id)
+    .bind(tag_id)
+    .bind(user_id)
+    .fetch_one(db)
+    .await
+}
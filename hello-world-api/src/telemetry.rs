@@ -0,0 +1,99 @@
+//! Distributed tracing. With `OTEL_EXPORTER_OTLP_ENDPOINT` set, spans are
+//! exported over OTLP/HTTP to a collector: one per request, continuing the
+//! trace of its W3C `traceparent` header if it has one, and one per query of
+//! the core todo endpoints, named after its statement. Which spans are
+//! exported is filtered by `TRACE_FILTER`, apart from the logs' `RUST_LOG`.
+
+use anyhow::Context;
+use axum::http::{HeaderMap, HeaderName};
+use opentelemetry::{propagation::Extractor, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{self, Sampler},
+    Resource,
+};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{registry::LookupSpan, EnvFilter, Layer};
+
+use crate::config::Config;
+
+/// The layer exporting spans, `None` without an endpoint. Also makes
+/// [`continue_trace`] read `traceparent` headers.
+pub fn layer<S>(config: &Config) -> anyhow::Result<Option<impl Layer<S>>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return Ok(None);
+    };
+    let filter =
+        EnvFilter::try_new(&config.trace_filter).context("TRACE_FILTER is not a valid filter")?;
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    // traces started upstream are kept or dropped as they were there
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+        config.trace_sample_ratio,
+    )));
+    let resource = Resource::new([KeyValue::new(
+        "service.name",
+        config.otel_service_name.clone(),
+    )]);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_sampler(sampler)
+                .with_resource(resource),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .context("failed to set up the OTLP exporter")?;
+    Ok(Some(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(filter),
+    ))
+}
+
+/// Exports the spans not exported yet, before the process exits.
+pub async fn shutdown() {
+    // waits for the exporter, which runs on the runtime
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+}
+
+/// Makes `span` a child of the span that sent `headers`, if they have a
+/// valid `traceparent`.
+pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+    let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&Headers(headers))
+    });
+    span.set_parent(parent);
+}
+
+/// The span of a query, by the name of its statement.
+pub fn query_span(statement: &'static str) -> Span {
+    tracing::info_span!(
+        "query",
+        otel.name = statement,
+        otel.kind = "client",
+        db.system = "postgresql",
+        db.statement.name = statement,
+    )
+}
+
+struct Headers<'a>(&'a HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)?.to_str().ok()
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
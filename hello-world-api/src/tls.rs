@@ -0,0 +1,88 @@
+//! TLS termination on the public listener, for deployments without a proxy
+//! in front: with `TLS_CERT_FILE` and `TLS_KEY_FILE` set, `LISTEN` serves
+//! HTTPS only. Both files are read again on SIGHUP, so a renewed
+//! certificate is picked up by new connections without a restart; if they
+//! can't be read, the certificate in use is kept.
+
+use std::{
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+
+use crate::listen::Http2;
+
+/// The PEM files of the certificate chain and of its private key.
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// The configuration of the listener's handshakes, reloaded from `files` on
+/// SIGHUP. Protocols are negotiated with ALPN as `http2` allows.
+pub fn config(files: TlsFiles, http2: Http2) -> anyhow::Result<RustlsConfig> {
+    let config = RustlsConfig::from_config(Arc::new(server_config(&files, http2)?));
+    let mut hangup = signal(SignalKind::hangup()).context("failed to listen for SIGHUP")?;
+    let reloaded = config.clone();
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match server_config(&files, http2) {
+                Ok(server_config) => {
+                    reloaded.reload_from_config(Arc::new(server_config));
+                    info!("Reloaded the TLS certificate");
+                }
+                Err(err) => warn!("Keeping the TLS certificate in use: {err:#}"),
+            }
+        }
+    });
+    Ok(config)
+}
+
+fn server_config(files: &TlsFiles, http2: Http2) -> anyhow::Result<ServerConfig> {
+    let certs = read_pem(&files.cert, rustls_pemfile::certs)?;
+    anyhow::ensure!(
+        !certs.is_empty(),
+        "no certificate in {}",
+        files.cert.display()
+    );
+    let keys = read_pem(&files.key, rustls_pemfile::read_all)?;
+    let key = keys
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(key),
+            _ => None,
+        })
+        .with_context(|| format!("no private key in {}", files.key.display()))?;
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            certs.into_iter().map(Certificate).collect(),
+            PrivateKey(key),
+        )
+        .with_context(|| format!("{} doesn't fit its private key", files.cert.display()))?;
+    config.alpn_protocols = match http2 {
+        Http2::Off => vec![b"http/1.1".to_vec()],
+        Http2::H2c => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        Http2::Only => vec![b"h2".to_vec()],
+    };
+    Ok(config)
+}
+
+fn read_pem<T>(
+    path: &Path,
+    parse: impl FnOnce(&mut dyn std::io::BufRead) -> std::io::Result<T>,
+) -> anyhow::Result<T> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    parse(&mut BufReader::new(file)).with_context(|| format!("{} is not PEM", path.display()))
+}
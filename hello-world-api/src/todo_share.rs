@@ -0,0 +1,365 @@
+//! Todos and lists shared by their owner with other users, by username. The
+//! recipients find them in their `GET /todos`, with `shared_by` telling
+//! whose they are, and fetch them by id; editors also change them like
+//! their own, though only the owner deletes them, tags them or shares them
+//! further. Sharing a list shares the todos in it, as they come and go.
+//!
+//! What a share allows is checked where the todos are read and written, in
+//! the repository, so every API sees the same.
+
+use axum::{http::StatusCode, response::IntoResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgExecutor};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{Json, Path},
+    tx::Tx,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "share_permission", rename_all = "lowercase")]
+pub enum SharePermission {
+    /// Reads the todo.
+    Viewer,
+    /// Also changes it.
+    Editor,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ShareTodo {
+    permission: SharePermission,
+}
+
+#[derive(Serialize, ToSchema, sqlx::FromRow)]
+pub struct ShareView {
+    /// Whom it is shared with.
+    username: String,
+    permission: SharePermission,
+    created_at: DateTime<Utc>,
+}
+
+/// What is shared: a todo by itself, or a list and the todos in it.
+#[derive(Clone, Copy)]
+enum Shared {
+    Todo,
+    List,
+}
+
+impl Shared {
+    fn column(self) -> &'static str {
+        match self {
+            Shared::Todo => "todo_id",
+            Shared::List => "list_id",
+        }
+    }
+
+    fn not_found(self) -> ApiError {
+        match self {
+            Shared::Todo => ApiError::new(StatusCode::NOT_FOUND, "No such todo"),
+            Shared::List => ApiError::new(StatusCode::NOT_FOUND, "No such list"),
+        }
+    }
+
+    /// Whether `id` is `user_id`'s to share; shared with them isn't enough.
+    async fn owned(
+        self,
+        db: impl PgExecutor<'_>,
+        user_id: uuid::Uuid,
+        id: uuid::Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let sql = match self {
+            Shared::Todo => {
+                r#"select exists (select 1 from "todo"
+                where id = $1 and user_id = $2 and merged_into is null and deleted_at is null)"#
+            }
+            Shared::List => {
+                r#"select exists (select 1 from "list" where id = $1 and user_id = $2)"#
+            }
+        };
+        sqlx::query_scalar::<_, bool>(sql)
+            .bind(id)
+            .bind(user_id)
+            .fetch_one(db)
+            .await
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/shares",
+    tag = "sharing",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+    ),
+    responses(
+        (status = 200, description = "Whom the todo is shared with by itself, by username", body = Vec<ShareView>),
+        (status = 404, description = "No such todo of the user's", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn todo_shares(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    list(&mut tx, Shared::Todo, user_id, id).await
+}
+
+/// Shares the todo with a user, or changes what they may do with it.
+#[utoipa::path(
+    put,
+    path = "/todos/{id}/shares/{username}",
+    tag = "sharing",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("username" = String, Path, description = "Whom to share it with"),
+    ),
+    request_body = ShareTodo,
+    responses(
+        (status = 200, description = "The share", body = ShareView),
+        (status = 404, description = "No such todo of the user's, or no such user", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Sharing with the owner, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn share_todo(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path((id, username)): Path<(uuid::Uuid, String)>,
+    Json(body): Json<ShareTodo>,
+) -> axum::response::Response {
+    share(
+        &mut tx,
+        Shared::Todo,
+        user_id,
+        id,
+        &username,
+        body.permission,
+    )
+    .await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/shares/{username}",
+    tag = "sharing",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Todo id"),
+        ("username" = String, Path, description = "Whom it is shared with"),
+    ),
+    responses(
+        (status = 204, description = "No longer shared with the user"),
+        (status = 404, description = "No such todo of the user's, or not shared with that user", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn unshare_todo(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path((id, username)): Path<(uuid::Uuid, String)>,
+) -> axum::response::Response {
+    unshare(&mut tx, Shared::Todo, user_id, id, &username).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/lists/{id}/shares",
+    tag = "sharing",
+    params(
+        ("id" = uuid::Uuid, Path, description = "List id"),
+    ),
+    responses(
+        (status = 200, description = "Whom the list is shared with, by username", body = Vec<ShareView>),
+        (status = 404, description = "No such list of the user's", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list_shares(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    list(&mut tx, Shared::List, user_id, id).await
+}
+
+/// Shares the list's todos with a user, or changes what they may do with
+/// them.
+#[utoipa::path(
+    put,
+    path = "/lists/{id}/shares/{username}",
+    tag = "sharing",
+    params(
+        ("id" = uuid::Uuid, Path, description = "List id"),
+        ("username" = String, Path, description = "Whom to share it with"),
+    ),
+    request_body = ShareTodo,
+    responses(
+        (status = 200, description = "The share", body = ShareView),
+        (status = 404, description = "No such list of the user's, or no such user", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Sharing with the owner, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn share_list(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path((id, username)): Path<(uuid::Uuid, String)>,
+    Json(body): Json<ShareTodo>,
+) -> axum::response::Response {
+    share(
+        &mut tx,
+        Shared::List,
+        user_id,
+        id,
+        &username,
+        body.permission,
+    )
+    .await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/lists/{id}/shares/{username}",
+    tag = "sharing",
+    params(
+        ("id" = uuid::Uuid, Path, description = "List id"),
+        ("username" = String, Path, description = "Whom it is shared with"),
+    ),
+    responses(
+        (status = 204, description = "No longer shared with the user"),
+        (status = 404, description = "No such list of the user's, or not shared with that user", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn unshare_list(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path((id, username)): Path<(uuid::Uuid, String)>,
+) -> axum::response::Response {
+    unshare(&mut tx, Shared::List, user_id, id, &username).await
+}
+
+async fn list(
+    conn: &mut PgConnection,
+    shared: Shared,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+) -> axum::response::Response {
+    match shared.owned(&mut *conn, user_id, id).await {
+        Ok(true) => {}
+        Ok(false) => return shared.not_found().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    }
+    let result = sqlx::query_as::<_, ShareView>(&format!(
+        r#"select u.username, s.permission, s.created_at
+        from "todo_share" s join "user" u on u.user_id = s.user_id
+        where s.{} = $1
+        order by u.username"#,
+        shared.column()
+    ))
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await;
+    match result {
+        Ok(shares) => Json(shares).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+async fn share(
+    conn: &mut PgConnection,
+    shared: Shared,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+    username: &str,
+    permission: SharePermission,
+) -> axum::response::Response {
+    match shared.owned(&mut *conn, user_id, id).await {
+        Ok(true) => {}
+        Ok(false) => return shared.not_found().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    }
+    let recipient = match recipient(&mut *conn, username).await {
+        Ok(Some(recipient)) if recipient == user_id => {
+            return ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Cannot share with the owner",
+            )
+            .into_response()
+        }
+        Ok(Some(recipient)) => recipient,
+        Ok(None) => return ApiError::new(StatusCode::NOT_FOUND, "No such user").into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let column = shared.column();
+    let result = sqlx::query_scalar::<_, DateTime<Utc>>(&format!(
+        r#"insert into "todo_share" ({column}, user_id, permission) values ($1, $2, $3)
+        on conflict ({column}, user_id) do update set permission = excluded.permission
+        returning created_at"#
+    ))
+    .bind(id)
+    .bind(recipient)
+    .bind(permission)
+    .fetch_one(&mut *conn)
+    .await;
+    match result {
+        Ok(created_at) => Json(ShareView {
+            username: username.to_owned(),
+            permission,
+            created_at,
+        })
+        .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+async fn unshare(
+    conn: &mut PgConnection,
+    shared: Shared,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+    username: &str,
+) -> axum::response::Response {
+    match shared.owned(&mut *conn, user_id, id).await {
+        Ok(true) => {}
+        Ok(false) => return shared.not_found().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    }
+    let result = sqlx::query(&format!(
+        r#"delete from "todo_share" s using "user" u
+        where s.{} = $1 and u.user_id = s.user_id and u.username = $2"#,
+        shared.column()
+    ))
+    .bind(id)
+    .bind(username)
+    .execute(&mut *conn)
+    .await;
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            ApiError::new(StatusCode::NOT_FOUND, "Not shared with that user").into_response()
+        }
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// The user called `username`, if any; the accounts holding workspaces'
+/// data aren't anyone to share with.
+async fn recipient(
+    db: impl PgExecutor<'_>,
+    username: &str,
+) -> Result<Option<uuid::Uuid>, sqlx::Error> {
+    sqlx::query_scalar::<_, uuid::Uuid>(
+        r#"select user_id from "user" u
+        where username = $1 and not exists (select 1 from "workspace" w where w.id = u.user_id)"#,
+    )
+    .bind(username)
+    .fetch_optional(db)
+    .await
+}
@@ -0,0 +1,128 @@
+//! `GET /todos/stream`: all of a user's todos matching the filters as
+//! newline-delimited JSON, for clients syncing more todos than they would
+//! want to page through. Rows are read off a cursor as the client takes
+//! them, so the server holds a few of them at a time however many match.
+
+use axum::{
+    body::{Bytes, StreamBody},
+    http::header,
+    response::IntoResponse,
+    Extension,
+};
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::warn;
+use utoipa::IntoParams;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::Query,
+    models::{Priority, ToDoMetaView, ToDoView, Todo},
+    repository::todo_query::TodoQuery,
+};
+
+/// Lines encoded ahead of the client. Once they are, reading from the
+/// database waits for it.
+const BUFFERED_LINES: usize = 64;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamTodos {
+    is_done: Option<bool>,
+    /// Only todos with the tag of this name.
+    tag: Option<String>,
+    /// Only todos in this list.
+    list_id: Option<uuid::Uuid>,
+    /// Only todos of this priority.
+    priority: Option<Priority>,
+    /// Also stream soft-deleted todos.
+    #[serde(default)]
+    include_deleted: bool,
+    /// Only todos changed after this time; with `include_deleted=true` that
+    /// includes the ones deleted since.
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Stream each todo's sync metadata, as `GET /todos?meta=true` lists it.
+    #[serde(default)]
+    meta: bool,
+}
+
+/// A line per todo, in the user's order. A failure before the first line is
+/// answered with an error; one later on cuts the body short, so clients
+/// should treat a body not ending in a newline as incomplete.
+#[utoipa::path(
+    get,
+    path = "/todos/stream",
+    tag = "todos",
+    params(
+        StreamTodos,
+    ),
+    responses(
+        (status = 200, description = "A `ToDoView` per line, or a `ToDoMetaView` with `?meta=true`", body = ToDoView, content_type = "application/x-ndjson"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn stream(
+    pg: Extension<PgPool>,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<StreamTodos>,
+) -> axum::response::Response {
+    let query = TodoQuery {
+        is_done: params.is_done,
+        tag: params.tag,
+        list_id: params.list_id,
+        priority: params.priority,
+        include_deleted: params.include_deleted,
+        since: params.since,
+        ..TodoQuery::default()
+    };
+    let (lines, mut received) = mpsc::channel(BUFFERED_LINES);
+    // the rows borrow the query and the pool, which the task owns for as
+    // long as the body is read
+    let pg = pg.0;
+    tokio::spawn(async move {
+        let mut builder = query.build_all(user_id);
+        let mut rows = builder.build_query_as::<Todo>().fetch(&pg);
+        while let Some(row) = rows.next().await {
+            let line = row.map(|todo| encode(todo, params.meta));
+            let failed = line.is_err();
+            // the client is gone once nobody receives
+            if lines.send(line).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    // the first line is waited for, to answer a failure with an error
+    let first = match received.recv().await {
+        Some(Err(err)) => return ApiError::from(err).into_response(),
+        first => first,
+    };
+    let body = stream::iter(first)
+        .chain(stream::unfold(received, |mut received| async move {
+            received.recv().await.map(|line| (line, received))
+        }))
+        .map(|line| {
+            line.map_err(|err| {
+                warn!("Fail to stream todos: {:?}", err);
+                std::io::Error::other(err)
+            })
+        });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(body),
+    )
+        .into_response()
+}
+
+fn encode(todo: Todo, meta: bool) -> Bytes {
+    let mut line = match meta {
+        true => serde_json::to_vec(&ToDoMetaView::from(todo)),
+        false => serde_json::to_vec(&ToDoView::from(todo)),
+    }
+    .expect("a todo serializes to JSON");
+    line.push(b'\n');
+    line.into()
+}
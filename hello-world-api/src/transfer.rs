@@ -0,0 +1,658 @@
+//! Moving an instance's data to another one, such as from a self-hosted
+//! install to a hosted one. `GET /admin/export` answers with a [`Workspace`]
+//! document of every user with their lists, tags, todos, checklists and
+//! links, and of the [workspaces](crate::workspaces) with their members and
+//! settings, and
+//! `POST /admin/import` on the other instance adds it to its own data.
+//!
+//! Imported todos, lists and tags get new ids there, so ids can't clash with the
+//! ones it has; the answer tells which user each exported one became. Users
+//! whose username is taken on the importing instance are taken to be the
+//! same person and get the todos, keeping their own password. So are
+//! workspaces whose slug is taken: their data and members join the one
+//! there, whose name and settings stay. Deleted, merged and archived todos,
+//! shares and share links, attachments, webhooks and hooks, API keys,
+//! preferences, invitations, workspaces' rate limits and the audit log aren't
+//! moved.
+//!
+//! The document has every user's password hash, so both endpoints check for
+//! an [`AdminUser`] themselves, wherever they are routed.
+
+use std::collections::{HashMap, HashSet};
+
+use axum::{http::StatusCode, response::IntoResponse, Extension};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AdminUser,
+    error::ApiError,
+    extract::{Json, Validate},
+    language,
+    links::LinkKind,
+    models::{Priority, Todo},
+    workspaces::{WorkspaceRole, WorkspaceSettings},
+};
+
+/// Version of the [`Workspace`] format, bumped when it changes.
+const VERSION: u32 = 1;
+
+/// Largest document `POST /admin/import` accepts.
+pub const MAX_IMPORT_BYTES: usize = 256 * 1024 * 1024;
+
+/// Everything exported from an instance.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Workspace {
+    /// `1`.
+    version: u32,
+    exported_at: DateTime<Utc>,
+    users: Vec<ExportedUser>,
+    /// Absent from exports made before lists.
+    #[serde(default)]
+    lists: Vec<ExportedList>,
+    tags: Vec<ExportedTag>,
+    todos: Vec<ExportedTodo>,
+    links: Vec<ExportedLink>,
+    /// Absent from exports made before workspaces.
+    #[serde(default)]
+    workspaces: Vec<ExportedWorkspace>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ExportedUser {
+    id: uuid::Uuid,
+    username: String,
+    /// The Argon2 hash of their password, so they can log in with it after
+    /// the move.
+    password_hash: Option<String>,
+    /// Whether their role is admin.
+    is_admin: bool,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ExportedList {
+    id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ExportedTag {
+    id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ExportedTodo {
+    id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    text: String,
+    is_done: bool,
+    completed_at: Option<DateTime<Utc>>,
+    start_at: Option<DateTime<Utc>>,
+    due_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    expired_at: Option<DateTime<Utc>>,
+    external_id: Option<String>,
+    external_url: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    radius_m: Option<f64>,
+    /// Id of an [`ExportedList`] of the same user.
+    #[serde(default)]
+    list_id: Option<uuid::Uuid>,
+    #[serde(default)]
+    priority: Option<Priority>,
+    #[serde(default)]
+    recurrence: Option<String>,
+    /// When its next occurrence was created, if it recurs and is done.
+    #[serde(default)]
+    recurred_at: Option<DateTime<Utc>>,
+    /// Ids of [`ExportedTag`]s of the same user.
+    tag_ids: Vec<uuid::Uuid>,
+    /// In order.
+    #[schema(value_type = Vec<ExportedItem>)]
+    checklist: sqlx::types::Json<Vec<ExportedItem>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ExportedItem {
+    text: String,
+    is_done: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ExportedLink {
+    from_id: uuid::Uuid,
+    to_id: uuid::Uuid,
+    kind: LinkKind,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ExportedWorkspace {
+    /// Id of the [`ExportedUser`] its data belongs to, which has no
+    /// password.
+    id: uuid::Uuid,
+    slug: String,
+    name: String,
+    #[schema(value_type = WorkspaceSettings)]
+    settings: sqlx::types::Json<WorkspaceSettings>,
+    created_at: DateTime<Utc>,
+    #[schema(value_type = Vec<ExportedMember>)]
+    members: sqlx::types::Json<Vec<ExportedMember>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ExportedMember {
+    /// Id of an [`ExportedUser`].
+    user_id: uuid::Uuid,
+    role: WorkspaceRole,
+    on_leaderboard: bool,
+    joined_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportSummary {
+    /// Which user each exported one is now.
+    users: Vec<UserMapping>,
+    /// Todos added.
+    todos: usize,
+    /// Ids of the exported todos left out because their user already has a
+    /// todo with the same text or external id.
+    skipped_todos: Vec<uuid::Uuid>,
+    /// Links added, leaving out those of skipped todos.
+    links: usize,
+    /// Workspaces added, leaving out those whose slug was taken.
+    workspaces: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserMapping {
+    /// The exported id.
+    exported_id: uuid::Uuid,
+    id: uuid::Uuid,
+    username: String,
+    /// Whether the user was created rather than found by username.
+    created: bool,
+}
+
+/// Exports every user's data, as of one snapshot of the database.
+#[utoipa::path(
+    get,
+    path = "/admin/export",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The instance's data, for `POST /admin/import` elsewhere", body = Workspace),
+    ),
+)]
+pub async fn export(pg: Extension<PgPool>, _: AdminUser) -> axum::response::Response {
+    match read_workspace(&pg).await {
+        Ok(workspace) => {
+            info!(
+                users = workspace.users.len(),
+                todos = workspace.todos.len(),
+                "Exported the workspace"
+            );
+            Json(workspace).into_response()
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+async fn read_workspace(pg: &PgPool) -> Result<Workspace, sqlx::Error> {
+    let mut tx = pg.begin().await?;
+    sqlx::query("set transaction isolation level repeatable read, read only")
+        .execute(&mut tx)
+        .await?;
+    let users = sqlx::query_as!(
+        ExportedUser,
+        r#"select user_id as id, username, password_hash, role = 'admin' as "is_admin!",
+            created_at
+        from "user" order by created_at, user_id"#,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let lists = sqlx::query_as!(
+        ExportedList,
+        r#"select id, user_id, name from "list" order by user_id, name"#,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let tags = sqlx::query_as!(
+        ExportedTag,
+        r#"select id, user_id, name from "tag" order by user_id, name"#,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let todos = sqlx::query_as!(
+        ExportedTodo,
+        r#"select t.id, t.user_id as "user_id!", t.todo_text as text, t.is_done, t.completed_at, t.start_at,
+            t.due_at, t.expires_at, t.expired_at, t.external_id, t.external_url,
+            t.latitude, t.longitude, t.radius_m, t.list_id, t.priority as "priority: Priority",
+            t.recurrence, t.recurred_at,
+            array(select tag_id from "todo_tag" where todo_id = t.id) as "tag_ids!",
+            coalesce((
+                select json_agg(json_build_object('text', item_text, 'is_done', is_done) order by position)
+                from "checklist_item" where todo_id = t.id
+            ), '[]') as "checklist!: sqlx::types::Json<Vec<ExportedItem>>"
+        from "todo" t
+        where t.user_id is not null and t.merged_into is null and t.deleted_at is null
+        order by t.id"#,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let links = sqlx::query_as!(
+        ExportedLink,
+        r#"select l.from_id, l.to_id, l.kind as "kind: LinkKind"
+        from "todo_link" l
+        join "todo" f on f.id = l.from_id
+        join "todo" t on t.id = l.to_id
+        where f.merged_into is null and f.deleted_at is null
+            and t.merged_into is null and t.deleted_at is null
+        order by l.created_at, l.id"#,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let workspaces = sqlx::query_as!(
+        ExportedWorkspace,
+        r#"select w.id, w.slug, w.name,
+            w.settings as "settings: sqlx::types::Json<WorkspaceSettings>", w.created_at,
+            coalesce((
+                select json_agg(json_build_object('user_id', m.user_id, 'role', m.role,
+                    'on_leaderboard', m.on_leaderboard, 'joined_at', m.joined_at)
+                    order by m.joined_at, m.user_id)
+                from "workspace_member" m where m.workspace_id = w.id
+            ), '[]') as "members!: sqlx::types::Json<Vec<ExportedMember>>"
+        from "workspace" w
+        order by w.created_at, w.id"#,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Workspace {
+        version: VERSION,
+        exported_at: Utc::now(),
+        users,
+        lists,
+        tags,
+        todos,
+        links,
+        workspaces,
+    })
+}
+
+/// Adds an exported workspace to this instance's data, all of it or, if
+/// anything fails, none.
+#[utoipa::path(
+    post,
+    path = "/admin/import",
+    tag = "admin",
+    request_body = Workspace,
+    responses(
+        (status = 200, description = "What was imported", body = ImportSummary),
+        (status = 413, description = "The document is larger than 256 MiB"),
+        (status = 422, description = "Another format version, a list, tag, todo, link or workspace referring to something the document lacks, or invalid workspace settings", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn import(
+    pg: Extension<PgPool>,
+    _: AdminUser,
+    Json(workspace): Json<Workspace>,
+) -> axum::response::Response {
+    if let Err(err) = check(&workspace) {
+        return err.into_response();
+    }
+    match write_workspace(&pg, workspace).await {
+        Ok(summary) => {
+            info!(
+                users = summary.users.len(),
+                todos = summary.todos,
+                skipped = summary.skipped_todos.len(),
+                "Imported a workspace"
+            );
+            Json(summary).into_response()
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Fails unless the document is of this version and refers only to users,
+/// lists, tags and todos it has, and its workspaces' settings are valid.
+fn check(workspace: &Workspace) -> Result<(), ApiError> {
+    let invalid = |detail: String| Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, detail));
+    if workspace.version != VERSION {
+        return invalid(format!(
+            "Can't import version {} exports, only version {VERSION}",
+            workspace.version
+        ));
+    }
+    let users: HashSet<_> = workspace.users.iter().map(|user| user.id).collect();
+    // lists and tags only go on todos of the same user
+    let lists: HashSet<_> = workspace
+        .lists
+        .iter()
+        .map(|list| (list.user_id, list.id))
+        .collect();
+    let tags: HashSet<_> = workspace
+        .tags
+        .iter()
+        .map(|tag| (tag.user_id, tag.id))
+        .collect();
+    let todos: HashMap<_, _> = workspace
+        .todos
+        .iter()
+        .map(|todo| (todo.id, todo.user_id))
+        .collect();
+    if let Some(tag) = workspace
+        .tags
+        .iter()
+        .find(|tag| !users.contains(&tag.user_id))
+    {
+        return invalid(format!("Tag {} belongs to an unknown user", tag.id));
+    }
+    if let Some(list) = workspace
+        .lists
+        .iter()
+        .find(|list| !users.contains(&list.user_id))
+    {
+        return invalid(format!("List {} belongs to an unknown user", list.id));
+    }
+    for todo in &workspace.todos {
+        if !users.contains(&todo.user_id) {
+            return invalid(format!("Todo {} belongs to an unknown user", todo.id));
+        }
+        if let Some(tag_id) = todo
+            .tag_ids
+            .iter()
+            .find(|tag_id| !tags.contains(&(todo.user_id, **tag_id)))
+        {
+            return invalid(format!("Todo {} has unknown tag {tag_id}", todo.id));
+        }
+        if let Some(list_id) = todo
+            .list_id
+            .filter(|list_id| !lists.contains(&(todo.user_id, *list_id)))
+        {
+            return invalid(format!("Todo {} is in unknown list {list_id}", todo.id));
+        }
+    }
+    for link in &workspace.links {
+        let (Some(from), Some(to)) = (todos.get(&link.from_id), todos.get(&link.to_id)) else {
+            return invalid(format!(
+                "Link from {} to {} refers to an unknown todo",
+                link.from_id, link.to_id
+            ));
+        };
+        if from != to || link.from_id == link.to_id {
+            return invalid(format!(
+                "Link from {} to {} isn't between two todos of one user",
+                link.from_id, link.to_id
+            ));
+        }
+    }
+    for exported in &workspace.workspaces {
+        if !users.contains(&exported.id) {
+            return invalid(format!(
+                "Workspace {} belongs to an unknown user",
+                exported.slug
+            ));
+        }
+        if let Some(member) = exported
+            .members
+            .iter()
+            .find(|member| !users.contains(&member.user_id) || member.user_id == exported.id)
+        {
+            return invalid(format!(
+                "Workspace {} has unknown member {}",
+                exported.slug, member.user_id
+            ));
+        }
+        if let Some(field) = exported.settings.validate().first() {
+            return invalid(format!(
+                "Workspace {} has an invalid {}: {}",
+                exported.slug, field.field, field.reason
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn write_workspace(pg: &PgPool, workspace: Workspace) -> Result<ImportSummary, sqlx::Error> {
+    // not begun before the document is read, which may take a while
+    let mut tx = pg.begin().await?;
+    let mut users = Vec::with_capacity(workspace.users.len());
+    let mut user_ids = HashMap::new();
+    let accounts: HashMap<_, _> = workspace
+        .workspaces
+        .iter()
+        .map(|exported| (exported.id, exported))
+        .collect();
+    let mut created_workspaces = 0;
+    for user in workspace.users {
+        if let Some(exported) = accounts.get(&user.id) {
+            let (id, username, created) = write_account(&mut tx, exported).await?;
+            created_workspaces += usize::from(created);
+            user_ids.insert(user.id, id);
+            users.push(UserMapping {
+                exported_id: user.id,
+                id,
+                username,
+                created,
+            });
+            continue;
+        }
+        let created = sqlx::query_scalar::<_, uuid::Uuid>(
+            r#"insert into "user" (username, password_hash, role, created_at)
+            values ($1, $2, case when $3 then 'admin'::"role" else 'user'::"role" end, $4)
+            on conflict (username) do nothing
+            returning user_id"#,
+        )
+        .bind(&user.username)
+        .bind(&user.password_hash)
+        .bind(user.is_admin)
+        .bind(user.created_at)
+        .fetch_optional(&mut tx)
+        .await?;
+        let id = match created {
+            Some(id) => id,
+            None => {
+                sqlx::query_scalar(r#"select user_id from "user" where username = $1"#)
+                    .bind(&user.username)
+                    .fetch_one(&mut tx)
+                    .await?
+            }
+        };
+        user_ids.insert(user.id, id);
+        users.push(UserMapping {
+            exported_id: user.id,
+            id,
+            username: user.username,
+            created: created.is_some(),
+        });
+    }
+
+    let mut list_ids = HashMap::new();
+    for list in workspace.lists {
+        // a user found by username may have the list already
+        let id = sqlx::query_scalar::<_, uuid::Uuid>(
+            r#"insert into "list" (user_id, name) values ($1, $2)
+            on conflict (user_id, name) do update set name = excluded.name
+            returning id"#,
+        )
+        .bind(user_ids[&list.user_id])
+        .bind(&list.name)
+        .fetch_one(&mut tx)
+        .await?;
+        list_ids.insert(list.id, id);
+    }
+
+    let mut tag_ids = HashMap::new();
+    for tag in workspace.tags {
+        // a user found by username may have the tag already
+        let id = sqlx::query_scalar::<_, uuid::Uuid>(
+            r#"insert into "tag" (user_id, name) values ($1, $2)
+            on conflict (user_id, name) do update set name = excluded.name
+            returning id"#,
+        )
+        .bind(user_ids[&tag.user_id])
+        .bind(&tag.name)
+        .fetch_one(&mut tx)
+        .await?;
+        tag_ids.insert(tag.id, id);
+    }
+
+    let mut todo_ids = HashMap::new();
+    let mut skipped_todos = Vec::new();
+    for todo in workspace.todos {
+        let inserted = sqlx::query_scalar::<_, uuid::Uuid>(
+            r#"insert into "todo" (id, user_id, todo_text, search_config, is_done, completed_at,
+                start_at, due_at, expires_at, expired_at, external_id, external_url,
+                latitude, longitude, radius_m, list_id, priority, recurrence, recurred_at)
+            values ($1, $2, $3, $4::regconfig, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15,
+                $16, $17, $18, $19)
+            on conflict do nothing
+            returning id"#,
+        )
+        .bind(Todo::new_id())
+        .bind(user_ids[&todo.user_id])
+        .bind(&todo.text)
+        .bind(language::search_config(&todo.text))
+        .bind(todo.is_done)
+        .bind(todo.completed_at)
+        .bind(todo.start_at)
+        .bind(todo.due_at)
+        .bind(todo.expires_at)
+        .bind(todo.expired_at)
+        .bind(&todo.external_id)
+        .bind(&todo.external_url)
+        .bind(todo.latitude)
+        .bind(todo.longitude)
+        .bind(todo.radius_m)
+        .bind(todo.list_id.map(|list_id| list_ids[&list_id]))
+        .bind(todo.priority)
+        .bind(&todo.recurrence)
+        .bind(todo.recurred_at)
+        .fetch_optional(&mut tx)
+        .await?;
+        let Some(id) = inserted else {
+            skipped_todos.push(todo.id);
+            continue;
+        };
+        todo_ids.insert(todo.id, (id, user_ids[&todo.user_id]));
+        let tags: Vec<_> = todo.tag_ids.iter().map(|tag_id| tag_ids[tag_id]).collect();
+        sqlx::query(r#"insert into "todo_tag" (todo_id, tag_id) select $1, unnest($2::uuid[])"#)
+            .bind(id)
+            .bind(tags)
+            .execute(&mut tx)
+            .await?;
+        let (texts, done): (Vec<_>, Vec<_>) = todo
+            .checklist
+            .0
+            .into_iter()
+            .map(|item| (item.text, item.is_done))
+            .unzip();
+        sqlx::query(
+            r#"insert into "checklist_item" (todo_id, position, item_text, is_done)
+            select $1, position - 1, item_text, is_done
+            from unnest($2::text[], $3::bool[]) with ordinality as item (item_text, is_done, position)"#,
+        )
+        .bind(id)
+        .bind(texts)
+        .bind(done)
+        .execute(&mut tx)
+        .await?;
+    }
+
+    let mut links = 0;
+    for link in workspace.links {
+        let (Some(&(from_id, user_id)), Some(&(to_id, _))) =
+            (todo_ids.get(&link.from_id), todo_ids.get(&link.to_id))
+        else {
+            continue;
+        };
+        let inserted = sqlx::query(
+            r#"insert into "todo_link" (user_id, from_id, to_id, kind) values ($1, $2, $3, $4)
+            on conflict do nothing"#,
+        )
+        .bind(user_id)
+        .bind(from_id)
+        .bind(to_id)
+        .bind(link.kind)
+        .execute(&mut tx)
+        .await?;
+        links += inserted.rows_affected() as usize;
+    }
+
+    for exported in &workspace.workspaces {
+        for member in exported.members.iter() {
+            // a user found by username may be a member already
+            sqlx::query(
+                r#"insert into "workspace_member"
+                    (workspace_id, user_id, role, on_leaderboard, joined_at)
+                values ($1, $2, $3, $4, $5)
+                on conflict do nothing"#,
+            )
+            .bind(user_ids[&exported.id])
+            .bind(user_ids[&member.user_id])
+            .bind(member.role)
+            .bind(member.on_leaderboard)
+            .bind(member.joined_at)
+            .execute(&mut tx)
+            .await?;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(ImportSummary {
+        users,
+        todos: todo_ids.len(),
+        skipped_todos,
+        links,
+        workspaces: created_workspaces,
+    })
+}
+
+/// The account of an exported workspace: the one of the workspace with its
+/// slug here, or else a new one, named as
+/// [`workspaces`](crate::workspaces) names them, with the workspace. Its id,
+/// username and whether it was created.
+async fn write_account(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    exported: &ExportedWorkspace,
+) -> Result<(uuid::Uuid, String, bool), sqlx::Error> {
+    let found = sqlx::query_as::<_, (uuid::Uuid, String)>(
+        r#"select u.user_id, u.username
+        from "workspace" w join "user" u on u.user_id = w.id
+        where w.slug = $1"#,
+    )
+    .bind(&exported.slug)
+    .fetch_optional(&mut **tx)
+    .await?;
+    if let Some((id, username)) = found {
+        return Ok((id, username, false));
+    }
+    let id = uuid::Uuid::new_v4();
+    let username = format!("workspace:{id}");
+    sqlx::query(r#"insert into "user" (user_id, username, created_at) values ($1, $2, $3)"#)
+        .bind(id)
+        .bind(&username)
+        .bind(exported.created_at)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query(
+        r#"insert into "workspace" (id, slug, name, settings, created_at)
+        values ($1, $2, $3, jsonb_strip_nulls($4), $5)"#,
+    )
+    .bind(id)
+    .bind(&exported.slug)
+    .bind(&exported.name)
+    .bind(&exported.settings)
+    .bind(exported.created_at)
+    .execute(&mut **tx)
+    .await?;
+    Ok((id, username, true))
+}
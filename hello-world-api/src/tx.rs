@@ -0,0 +1,165 @@
+//! Request-scoped transactions. A handler taking [`Tx`] runs its queries in
+//! one transaction, which [`scope`] commits once the handler answers with a
+//! success or redirect and rolls back otherwise, so a handler making several
+//! queries neither begins nor commits by hand and returning an error undoes
+//! everything it wrote. The transaction's queries are bounded by the
+//! request's deadline, if it has one. The todo repository's calls join it
+//! through [`TodoRepository::unit_of_work`]. Taken after [`AuthUser`], the
+//! changes it makes to todos are recorded as the request's user's in their
+//! [`history`](crate::history).
+//!
+//! With `STORAGE=memory` there is no database to begin one on: the handlers
+//! get a detached [`Tx`] whose [`todos`](Tx::todos) write to the repository
+//! right away, and which mustn't be queried on.
+//!
+//! [`TodoRepository::unit_of_work`]: crate::repository::TodoRepository::unit_of_work
+//! [`AuthUser`]: crate::auth::AuthUser
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use tracing::error;
+
+use crate::{
+    auth::Actor,
+    deadline,
+    error::ApiError,
+    history,
+    repository::{Storage, Todos, UnitOfWork},
+};
+
+/// Where the request's transaction waits for [`scope`] once the handler
+/// dropped its [`Tx`].
+#[derive(Clone, Default)]
+struct Slot(Arc<Mutex<Option<Transaction<'static, Postgres>>>>);
+
+impl Slot {
+    fn take(&self) -> Option<Transaction<'static, Postgres>> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// The request's transaction, begun when the handler is called. Derefs to
+/// sqlx's transaction, so queries run on `&mut *tx`.
+pub struct Tx {
+    /// Always `Some` until dropped or committed, unless detached.
+    tx: Option<Transaction<'static, Postgres>>,
+    slot: Slot,
+}
+
+impl Tx {
+    /// Commits before the handler answers, for one telling others, who read
+    /// the database on their own, about what it wrote.
+    pub async fn commit(mut self) -> Result<(), sqlx::Error> {
+        match self.tx.take() {
+            Some(tx) => tx.commit().await,
+            None => Ok(()),
+        }
+    }
+
+    /// The transaction's connection, `None` if detached.
+    pub fn connection(&mut self) -> Option<&mut PgConnection> {
+        self.tx.as_deref_mut()
+    }
+
+    /// `todos` joining this transaction, see
+    /// [`TodoRepository::unit_of_work`].
+    ///
+    /// [`TodoRepository::unit_of_work`]: crate::repository::TodoRepository::unit_of_work
+    pub fn todos<'a>(&'a mut self, todos: &'a Todos) -> UnitOfWork<'a> {
+        match &mut self.tx {
+            Some(tx) => todos.unit_of_work(tx),
+            None => todos
+                .detached()
+                .expect("a detached Tx is only taken with a detached repository"),
+        }
+    }
+}
+
+impl Deref for Tx {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        self.tx.as_ref().expect("a detached Tx can't be queried on")
+    }
+}
+
+impl DerefMut for Tx {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.tx.as_mut().expect("a detached Tx can't be queried on")
+    }
+}
+
+impl Drop for Tx {
+    fn drop(&mut self) {
+        // handed back for scope to settle once the response is known
+        *self.slot.0.lock().unwrap() = self.tx.take();
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Tx {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, ApiError> {
+        let (Some(slot), Some(pg)) = (
+            parts.extensions.get::<Slot>().cloned(),
+            parts.extensions.get::<PgPool>(),
+        ) else {
+            error!("Tx is taken by a handler outside the transaction scope");
+            return Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error",
+            ));
+        };
+        if let Some(Storage::Memory) = parts.extensions.get::<Storage>() {
+            return Ok(Tx { tx: None, slot });
+        }
+        let tx = match slot.take() {
+            Some(tx) => tx,
+            None => {
+                let mut tx = pg.begin().await?;
+                deadline::apply(&mut tx).await?;
+                if let Some(&Actor(actor_id)) = parts.extensions.get::<Actor>() {
+                    history::set_actor(&mut tx, actor_id).await?;
+                }
+                tx
+            }
+        };
+        Ok(Tx { tx: Some(tx), slot })
+    }
+}
+
+/// Lets the handlers take a [`Tx`], and commits or rolls back the one a
+/// handler took by the status it answers with. Answers a failed commit with
+/// a 500 instead of the handler's response.
+pub async fn scope<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let slot = Slot::default();
+    req.extensions_mut().insert(slot.clone());
+
+    let response = next.run(req).await;
+
+    let Some(tx) = slot.take() else {
+        return response;
+    };
+    let status = response.status();
+    if status.is_success() || status.is_redirection() {
+        if let Err(err) = tx.commit().await {
+            error!("Fail to commit the request's transaction {:?}", err);
+            return ApiError::from(err).into_response();
+        }
+    } else if let Err(err) = tx.rollback().await {
+        error!("Fail to roll back the request's transaction {:?}", err);
+    }
+    response
+}
@@ -0,0 +1,56 @@
+//! Versions of the HTTP API. Its routes are served under [`V1`]; the
+//! unversioned paths they had before stay as aliases, answering as before
+//! but with `Deprecation` and `Sunset` headers and a `Link` to their
+//! successor, so clients can move over before the aliases are removed. An
+//! incompatible next version gets a prefix of its own, served beside v1.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderName, HeaderValue, Request},
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use chrono::{DateTime, Utc};
+
+pub const V1: &str = "/api/v1";
+
+static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+static SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// When the unversioned paths were deprecated, 2026-10-14, as an RFC 9745
+/// date.
+const DEPRECATED_AT: &str = "@1791936000";
+
+/// Serves `api` under [`V1`] and, deprecated, at its unversioned paths; from
+/// `sunset` on, if set, the aliases may be gone.
+pub fn mount(api: Router, sunset: Option<DateTime<Utc>>) -> Router {
+    Router::new().nest(V1, api.clone()).merge(
+        // unknown paths are answered by the fallback, without the headers
+        api.route_layer(middleware::from_fn_with_state(sunset, deprecated)),
+    )
+}
+
+async fn deprecated<B>(
+    State(sunset): State<Option<DateTime<Utc>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let successor = match req.uri().query() {
+        Some(query) => format!("<{V1}{}?{query}>", req.uri().path()),
+        None => format!("<{V1}{}>", req.uri().path()),
+    };
+    let mut response = next.run(req).await;
+    let headers = response.headers_mut();
+    headers.insert(&DEPRECATION, HeaderValue::from_static(DEPRECATED_AT));
+    if let Some(sunset) = sunset {
+        let date = sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        if let Ok(date) = HeaderValue::from_str(&date) {
+            headers.insert(&SUNSET, date);
+        }
+    }
+    if let Ok(link) = HeaderValue::from_str(&format!("{successor}; rel=\"successor-version\"")) {
+        headers.append(header::LINK, link);
+    }
+    response
+}
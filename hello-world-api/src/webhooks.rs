@@ -0,0 +1,607 @@
+//! Webhooks: URLs a user has the events of their todos posted to, as
+//! [`TodoEvent`](crate::events::TodoEvent) JSON, the same as live clients get. Each event relayed from
+//! the [outbox](crate::outbox) is queued in `webhook_delivery` for every
+//! webhook of its user that takes its kind, in the transaction marking it
+//! published, and posted from there in the background:
+//! a failed attempt, an error status, a redirect, which isn't followed, or
+//! no answer within the timeout, is made again with an exponential backoff,
+//! [`MAX_ATTEMPTS`] times at most.
+//! With several servers on one database each delivery is attempted by one
+//! of them at a time.
+//!
+//! Deliveries are signed like GitHub's: `X-Webhook-Signature` is `sha256=`
+//! and the hex HMAC-SHA256 of the body, keyed with the webhook's secret,
+//! which is shown once when the webhook is created and once more each time
+//! `POST /webhooks/{id}/secret` replaces it; deliveries still to be
+//! attempted are signed with the new one. `X-Webhook-Delivery` is
+//! the same for every attempt of a delivery, for receivers to drop
+//! repeats. Webhooks only post to public addresses.
+//!
+//! `GET /webhooks/{id}/deliveries` shows the latest deliveries with the
+//! outcome of their last attempt; they are kept for [`KEPT_FOR`].
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{http::StatusCode, response::IntoResponse};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use reqwest::{header, Method, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{PgConnection, PgPool};
+use tokio::sync::Notify;
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    events::EventKind,
+    extract::{FieldError, Json, Path, Valid, Validate},
+    outbound::{self, Outbound},
+    tx::Tx,
+};
+
+pub const MAX_URL_CHARS: usize = 2000;
+
+/// Attempts of a delivery before it is given up on.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+/// Wait before the second attempt, doubled for each further one up to
+/// [`MAX_RETRY_DELAY`]; the last attempt is made about an hour after the
+/// first.
+const RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+/// How often the queue is checked for deliveries due, besides when one is
+/// queued.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long an attempt may take before another server may make it again.
+const LEASE: Duration = Duration::from_secs(60);
+
+/// Deliveries claimed at once, and attempted concurrently.
+const BATCH: i64 = 16;
+
+/// How long deliveries are kept once delivered or given up on.
+const KEPT_FOR: Duration = Duration::from_secs(30 * 86400);
+
+/// How often the deliveries older than [`KEPT_FOR`] are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Deliveries listed by `GET /webhooks/{id}/deliveries`.
+const LOGGED: i64 = 100;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateWebhook {
+    /// An `http(s)` URL, without credentials, of at most 2000 characters.
+    #[schema(example = "https://example.com/hooks/todos")]
+    url: String,
+    /// The kinds of events to deliver, all of them if empty or left out.
+    #[serde(default)]
+    events: Vec<EventKind>,
+}
+
+impl Validate for CreateWebhook {
+    fn validate(&self) -> Vec<FieldError> {
+        check_url(&self.url)
+            .map(|reason| FieldError {
+                field: "url",
+                reason: reason.to_owned(),
+            })
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Why `url` can't be a webhook's.
+fn check_url(url: &str) -> Option<&'static str> {
+    if url.chars().count() > MAX_URL_CHARS {
+        return Some("must be at most 2000 characters");
+    }
+    match Url::parse(url) {
+        Ok(url) if !matches!(url.scheme(), "http" | "https") => Some("must be http or https"),
+        Ok(url) if !url.username().is_empty() || url.password().is_some() => {
+            Some("must not have credentials")
+        }
+        Ok(url) if url.host_str().is_none() => Some("must have a host"),
+        Ok(_) => None,
+        Err(_) => Some("is not a URL"),
+    }
+}
+
+#[derive(Serialize, ToSchema, sqlx::FromRow)]
+pub struct WebhookView {
+    id: uuid::Uuid,
+    url: String,
+    /// Delivered kinds of events, all of them if empty.
+    events: Vec<EventKind>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedWebhook {
+    /// Keys the deliveries' `X-Webhook-Signature`. Only returned here, it
+    /// can't be looked up later.
+    secret: String,
+    #[serde(flatten)]
+    view: WebhookView,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// To be attempted (again).
+    Pending,
+    Delivered,
+    /// Given up on after `MAX_ATTEMPTS` attempts.
+    Failed,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeliveryView {
+    /// Sent as `X-Webhook-Delivery`.
+    id: uuid::Uuid,
+    event: EventKind,
+    status: DeliveryStatus,
+    /// Made so far.
+    attempts: i32,
+    created_at: DateTime<Utc>,
+    /// When it is attempted next, if it is still pending.
+    next_attempt_at: Option<DateTime<Utc>>,
+    delivered_at: Option<DateTime<Utc>>,
+    /// Of the answer to the last attempt, if there was one.
+    response_status: Option<i32>,
+    /// Why the last attempt failed.
+    last_error: Option<String>,
+    /// The body posted.
+    #[schema(value_type = Object)]
+    payload: serde_json::Value,
+}
+
+#[derive(sqlx::FromRow)]
+struct DeliveryRow {
+    id: uuid::Uuid,
+    event: EventKind,
+    attempts: i32,
+    created_at: DateTime<Utc>,
+    next_attempt_at: Option<DateTime<Utc>>,
+    delivered_at: Option<DateTime<Utc>>,
+    response_status: Option<i32>,
+    last_error: Option<String>,
+    payload: serde_json::Value,
+}
+
+impl From<DeliveryRow> for DeliveryView {
+    fn from(row: DeliveryRow) -> Self {
+        let status = match (row.delivered_at, row.next_attempt_at) {
+            (Some(_), _) => DeliveryStatus::Delivered,
+            (None, Some(_)) => DeliveryStatus::Pending,
+            (None, None) => DeliveryStatus::Failed,
+        };
+        DeliveryView {
+            id: row.id,
+            event: row.event,
+            status,
+            attempts: row.attempts,
+            created_at: row.created_at,
+            next_attempt_at: row.next_attempt_at,
+            delivered_at: row.delivered_at,
+            response_status: row.response_status,
+            last_error: row.last_error,
+            payload: row.payload,
+        }
+    }
+}
+
+/// Has the events of the user's todos posted to a URL.
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    request_body = CreateWebhook,
+    responses(
+        (status = 201, description = "The webhook, with its secret shown this once", body = CreatedWebhook),
+        (status = 422, description = "An invalid URL, an unknown kind of event or field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Valid(body): Valid<CreateWebhook>,
+) -> axum::response::Response {
+    let secret = new_secret();
+    let result = sqlx::query_as::<_, WebhookView>(
+        r#"insert into "webhook" (user_id, url, secret, events) values ($1, $2, $3, $4)
+        returning id, url, events, created_at"#,
+    )
+    .bind(user_id)
+    .bind(&body.url)
+    .bind(&secret)
+    .bind(&body.events)
+    .fetch_one(&mut *tx)
+    .await;
+    match result {
+        Ok(view) => (StatusCode::CREATED, Json(CreatedWebhook { secret, view })).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "The user's webhooks, oldest first", body = Vec<WebhookView>),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list(AuthUser(user_id): AuthUser, mut tx: Tx) -> axum::response::Response {
+    let result = sqlx::query_as::<_, WebhookView>(
+        r#"select id, url, events, created_at from "webhook"
+        where user_id = $1
+        order by created_at, id"#,
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await;
+    match result {
+        Ok(webhooks) => Json(webhooks).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Stops posting events to the webhook, dropping its pending deliveries
+/// and its delivery log.
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Webhook id"),
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "No such webhook", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    let result = sqlx::query(r#"delete from "webhook" where id = $1 and user_id = $2"#)
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await;
+    match result {
+        Ok(done) if done.rows_affected() == 0 => no_such_webhook().into_response(),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Replaces the webhook's secret, such as once it leaked. Receivers must
+/// take the new one straight away: every delivery from now on, including
+/// those of earlier events still to be attempted, is signed with it.
+#[utoipa::path(
+    post,
+    path = "/webhooks/{id}/secret",
+    tag = "webhooks",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Webhook id"),
+    ),
+    responses(
+        (status = 200, description = "The webhook, with its new secret shown this once", body = CreatedWebhook),
+        (status = 404, description = "No such webhook", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn rotate_secret(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    let secret = new_secret();
+    let result = sqlx::query_as::<_, WebhookView>(
+        r#"update "webhook" set secret = $3
+        where id = $1 and user_id = $2
+        returning id, url, events, created_at"#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(&secret)
+    .fetch_optional(&mut *tx)
+    .await;
+    match result {
+        Ok(Some(view)) => Json(CreatedWebhook { secret, view }).into_response(),
+        Ok(None) => no_such_webhook().into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+fn new_secret() -> String {
+    // two v4 uuids give 244 random bits
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// The webhook's latest deliveries, for debugging a receiver.
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Webhook id"),
+    ),
+    responses(
+        (status = 200, description = "The last 100 deliveries, newest first", body = Vec<DeliveryView>),
+        (status = 404, description = "No such webhook", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn deliveries(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(id): Path<uuid::Uuid>,
+) -> axum::response::Response {
+    let result = sqlx::query_scalar::<_, bool>(
+        r#"select exists (select 1 from "webhook" where id = $1 and user_id = $2)"#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await;
+    match result {
+        Ok(true) => {}
+        Ok(false) => return no_such_webhook().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    }
+    let result = sqlx::query_as::<_, DeliveryRow>(
+        r#"select id, event, attempts, created_at, next_attempt_at, delivered_at,
+            response_status, last_error, payload
+        from "webhook_delivery"
+        where webhook_id = $1
+        order by created_at desc, id desc
+        limit $2"#,
+    )
+    .bind(id)
+    .bind(LOGGED)
+    .fetch_all(&mut *tx)
+    .await;
+    match result {
+        Ok(rows) => {
+            let deliveries: Vec<_> = rows.into_iter().map(DeliveryView::from).collect();
+            Json(deliveries).into_response()
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+fn no_such_webhook() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "No such webhook")
+}
+
+/// Queues the deliveries of events, and makes them in the background.
+#[derive(Clone)]
+pub struct Webhooks {
+    queued: Arc<Notify>,
+}
+
+impl Webhooks {
+    /// Starts making the deliveries queued.
+    pub fn spawn(pg: PgPool, outbound: Outbound) -> Self {
+        let queued = Arc::new(Notify::new());
+        tokio::spawn(deliver(pg, outbound, queued.clone()));
+        Webhooks { queued }
+    }
+
+    /// Queues a delivery of each of the outbox's events in `ids` for every
+    /// webhook of its user taking its kind. Returns whether any was queued,
+    /// for [`Webhooks::wake`] once they are committed.
+    pub async fn enqueue(&self, conn: &mut PgConnection, ids: &[i64]) -> Result<bool, sqlx::Error> {
+        let done = sqlx::query!(
+            r#"insert into "webhook_delivery" (webhook_id, event, payload)
+            select w.id, e.kind, o.payload::jsonb
+            from "todo_outbox" o
+            cross join lateral (select (o.payload->>'type')::todo_event_kind as kind) e
+            join "webhook" w on w.user_id = o.user_id
+                and (w.events = '{}' or e.kind = any(w.events))
+            where o.id = any($1)
+            order by o.id"#,
+            ids,
+        )
+        .execute(conn)
+        .await?;
+        Ok(done.rows_affected() > 0)
+    }
+
+    /// Has the deliveries queued made right away.
+    pub fn wake(&self) {
+        self.queued.notify_one();
+    }
+}
+
+/// Makes the deliveries due, as they are queued and as their retries come
+/// up.
+async fn deliver(pg: PgPool, outbound: Outbound, queued: Arc<Notify>) {
+    let mut pruned_at: Option<Instant> = None;
+    loop {
+        if pruned_at.is_none_or(|at| at.elapsed() >= PRUNE_INTERVAL) {
+            if let Err(err) = prune(&pg).await {
+                error!("Fail to delete old webhook deliveries {:?}", err);
+            }
+            pruned_at = Some(Instant::now());
+        }
+        loop {
+            let claimed = match claim(&pg).await {
+                Ok(claimed) => claimed,
+                Err(err) => {
+                    error!("Fail to claim webhook deliveries {:?}", err);
+                    break;
+                }
+            };
+            if claimed.is_empty() {
+                break;
+            }
+            futures_util::stream::iter(claimed)
+                .for_each_concurrent(None, |delivery| attempt(&pg, &outbound, delivery))
+                .await;
+        }
+        tokio::select! {
+            _ = queued.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// A delivery claimed for an attempt.
+struct Claimed {
+    id: uuid::Uuid,
+    event: EventKind,
+    payload: String,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// Takes up to [`BATCH`] deliveries due, counting the attempts about to be
+/// made and leasing them for [`LEASE`].
+async fn claim(pg: &PgPool) -> Result<Vec<Claimed>, sqlx::Error> {
+    sqlx::query_as!(
+        Claimed,
+        r#"update "webhook_delivery" d
+        set next_attempt_at = now() + make_interval(secs => $1), attempts = d.attempts + 1
+        from "webhook" w
+        where w.id = d.webhook_id and d.id in (
+            select id from "webhook_delivery"
+            where next_attempt_at <= now()
+            order by next_attempt_at
+            limit $2
+            for update skip locked
+        )
+        returning d.id, d.event as "event: EventKind", d.payload::text as "payload!",
+            d.attempts, w.url, w.secret"#,
+        LEASE.as_secs_f64(),
+        BATCH,
+    )
+    .fetch_all(pg)
+    .await
+}
+
+async fn attempt(pg: &PgPool, outbound: &Outbound, delivery: Claimed) {
+    let signature = format!("sha256={}", sign(&delivery.secret, &delivery.payload));
+    let event = serde_json::to_value(delivery.event).unwrap_or_default();
+    let event = event.as_str().unwrap_or_default();
+    let result = outbound
+        .send(
+            &outbound::WEBHOOKS,
+            Method::POST,
+            &delivery.url,
+            |request| {
+                request
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("x-webhook-event", event)
+                    .header("x-webhook-delivery", delivery.id.to_string())
+                    .header("x-webhook-signature", &signature)
+                    .body(delivery.payload.clone())
+            },
+        )
+        .await;
+    let (status, error) = match result {
+        Ok(response) => (Some(response.status()), failure(response.status())),
+        Err(err) => (None, Some(format!("{err:#}"))),
+    };
+    if let Some(error) = &error {
+        warn!(delivery = %delivery.id, attempt = delivery.attempts, "Webhook delivery failed: {error}");
+    }
+    let retry_in = RETRY_BACKOFF
+        .saturating_mul(2u32.saturating_pow(delivery.attempts as u32 - 1))
+        .min(MAX_RETRY_DELAY);
+    let result = sqlx::query!(
+        r#"update "webhook_delivery"
+        set response_status = $2, last_error = $3,
+            delivered_at = case when $3::text is null then now() end,
+            next_attempt_at = case
+                when $3::text is null or attempts >= $4 then null
+                else now() + make_interval(secs => $5)
+            end
+        where id = $1"#,
+        delivery.id,
+        status.map(|status| i32::from(status.as_u16())),
+        error,
+        MAX_ATTEMPTS,
+        retry_in.as_secs_f64(),
+    )
+    .execute(pg)
+    .await;
+    if let Err(err) = result {
+        error!(delivery = %delivery.id, "Fail to record webhook delivery {:?}", err);
+    }
+}
+
+/// Why an answer with `status` isn't a delivery, `None` for a 2xx. Public
+/// outbound requests don't follow redirects, so a 3xx is a failure too.
+fn failure(status: reqwest::StatusCode) -> Option<String> {
+    match status {
+        status if status.is_success() => None,
+        status if status.is_redirection() => {
+            Some(format!("answered {status}, redirects aren't followed"))
+        }
+        status => Some(format!("answered {status}")),
+    }
+}
+
+/// The hex HMAC-SHA256 of `payload` keyed with `secret`.
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+async fn prune(pg: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"delete from "webhook_delivery"
+        where next_attempt_at is null and created_at < now() - make_interval(secs => $1)"#,
+        KEPT_FOR.as_secs_f64(),
+    )
+    .execute(pg)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+
+    #[test]
+    fn only_success_statuses_are_deliveries() {
+        assert_eq!(failure(StatusCode::OK), None);
+        assert_eq!(failure(StatusCode::NO_CONTENT), None);
+        for status in [
+            StatusCode::MOVED_PERMANENTLY,
+            StatusCode::FOUND,
+            StatusCode::NOT_MODIFIED,
+            StatusCode::PERMANENT_REDIRECT,
+            StatusCode::NOT_FOUND,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ] {
+            assert!(failure(status).is_some(), "{status}");
+        }
+    }
+}
@@ -0,0 +1,989 @@
+//! Workspaces: tenants whose todos, lists, tags and everything else are
+//! shared by their members. A workspace's data belongs to an account of its
+//! own, which has no password to log in with, so every query scoped to a
+//! user is scoped to a workspace once the request acts in one: [`resolve`]
+//! reads the workspace's slug from `X-Workspace` or, with `WORKSPACE_DOMAIN`
+//! set, from the subdomain the request was sent to, and [`AuthUser`] is then
+//! the workspace for its members. Everyone else gets a 404, as for a
+//! workspace that doesn't exist.
+//!
+//! Owners invite users with single-use tokens, which only a hash of is
+//! stored; the accounts and the workspaces themselves aren't scoped.
+//!
+//! The leaderboard ranks the members by the todos they completed in the
+//! workspace this week or month, as the audit log records who did; members
+//! opt out of it in their privacy settings.
+//!
+//! Owners set the workspace's [settings](WorkspaceSettings): the priority
+//! todos created without one get, how long before their due date its todos
+//! are reminded of, and the day and timezone its leaderboard's periods
+//! start on.
+
+use axum::{
+    http::{header, request::Parts, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use tracing::error;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::ApiError,
+    extract::{check_text, FieldError, Json, Path, Query, Valid, Validate},
+    models::Priority,
+    tx::Tx,
+};
+
+/// Header naming the workspace a request acts in.
+pub const X_WORKSPACE: &str = "x-workspace";
+
+/// Longest slug accepted: the longest DNS label, so any slug can be a
+/// subdomain.
+pub const MAX_SLUG_CHARS: usize = 63;
+
+pub const MAX_NAME_CHARS: usize = 100;
+
+/// Lifetime of an invitation created without an explicit `expires_at`.
+const DEFAULT_INVITATION_DAYS: i64 = 7;
+
+/// Longest reminder offset a workspace sets: a week.
+pub const MAX_REMINDER_OFFSET_MINUTES: i32 = 7 * 24 * 60;
+
+/// `WORKSPACE_DOMAIN`: the domain whose subdomains are workspaces' slugs.
+#[derive(Clone, Default)]
+pub struct WorkspaceDomain(pub Option<String>);
+
+/// The slug of the workspace the request acts in, set by [`resolve`].
+#[derive(Clone)]
+struct Tenant(String);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "workspace_role", rename_all = "lowercase")]
+pub enum WorkspaceRole {
+    /// Invites and removes members, besides what members do.
+    Owner,
+    /// Reads and writes the workspace's data.
+    Member,
+}
+
+#[derive(Serialize, ToSchema, sqlx::FromRow)]
+pub struct WorkspaceView {
+    /// Also the id of the account the workspace's data belongs to.
+    id: uuid::Uuid,
+    slug: String,
+    name: String,
+    /// The requesting user's role in it.
+    role: WorkspaceRole,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NewWorkspace {
+    /// 1 to 63 lowercase letters, digits and hyphens, not starting or
+    /// ending with a hyphen.
+    #[schema(example = "acme")]
+    slug: String,
+    /// Stored trimmed, which must leave 1 to 100 characters.
+    #[schema(example = "Acme Corp")]
+    name: String,
+}
+
+impl Validate for NewWorkspace {
+    fn validate(&self) -> Vec<FieldError> {
+        let slug = (!is_slug(&self.slug)).then(|| FieldError {
+            field: "slug",
+            reason: format!(
+                "must be 1 to {MAX_SLUG_CHARS} lowercase letters, digits and hyphens, not starting or ending with a hyphen"
+            ),
+        });
+        slug.into_iter()
+            .chain(check_text("name", &self.name, MAX_NAME_CHARS))
+            .collect()
+    }
+}
+
+#[derive(Serialize, ToSchema, sqlx::FromRow)]
+pub struct Member {
+    user_id: uuid::Uuid,
+    username: String,
+    role: WorkspaceRole,
+    joined_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateInvitation {
+    /// The only user who may accept it; anyone with the token by default.
+    username: Option<String>,
+    /// `member` by default.
+    role: Option<WorkspaceRole>,
+    /// In a week by default.
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InvitationView {
+    id: uuid::Uuid,
+    /// To `POST /invitations/{token}/accept` with. Only returned here, it
+    /// can't be looked up later.
+    token: String,
+    username: Option<String>,
+    role: WorkspaceRole,
+    expires_at: DateTime<Utc>,
+}
+
+/// What a leaderboard counts the completions of: since the workspace's
+/// week start or since the 1st, midnight in its timezone.
+#[derive(Deserialize, Serialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Week,
+    Month,
+}
+
+impl Period {
+    /// When the period around `now` started, in `settings`' timezone.
+    fn start(self, settings: &WorkspaceSettings, now: DateTime<Utc>) -> DateTime<Utc> {
+        let tz = settings.tz();
+        let today = now.with_timezone(&tz).date_naive();
+        let first = match self {
+            Period::Week => {
+                let week_start = settings.week_start.unwrap_or(Weekday::Monday);
+                let days = (today.weekday().num_days_from_monday() + 7
+                    - week_start.to_chrono().num_days_from_monday())
+                    % 7;
+                today - Duration::days(days.into())
+            }
+            Period::Month => {
+                NaiveDate::from_ymd_opt(today.year(), today.month(), 1).expect("the 1st is a date")
+            }
+        };
+        let midnight = first.and_hms_opt(0, 0, 0).expect("midnight is a time");
+        // a change to daylight saving time may skip midnight, the period
+        // then starts as the day does, an hour later
+        tz.from_local_datetime(&midnight)
+            .earliest()
+            .or_else(|| {
+                tz.from_local_datetime(&(midnight + Duration::hours(1)))
+                    .earliest()
+            })
+            .map_or_else(|| midnight.and_utc(), |start| start.with_timezone(&Utc))
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LeaderboardQuery {
+    /// `week` by default.
+    period: Option<Period>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Leaderboard {
+    period: Period,
+    /// When the period started.
+    since: DateTime<Utc>,
+    /// The members who haven't opted out, most completions first.
+    members: Vec<Ranked>,
+}
+
+#[derive(Serialize, ToSchema, sqlx::FromRow)]
+pub struct Ranked {
+    /// From 1; members with as many completions share a rank.
+    rank: i64,
+    user_id: uuid::Uuid,
+    username: String,
+    /// The workspace's todos the member marked done in the period.
+    completed: i64,
+}
+
+/// A member's settings of what the other members see of them.
+#[derive(Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Privacy {
+    /// Whether the member is ranked on the leaderboard; true by default.
+    on_leaderboard: bool,
+}
+
+/// A day of the week, as a workspace's week start.
+#[derive(Deserialize, Serialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    fn to_chrono(self) -> chrono::Weekday {
+        match self {
+            Weekday::Monday => chrono::Weekday::Mon,
+            Weekday::Tuesday => chrono::Weekday::Tue,
+            Weekday::Wednesday => chrono::Weekday::Wed,
+            Weekday::Thursday => chrono::Weekday::Thu,
+            Weekday::Friday => chrono::Weekday::Fri,
+            Weekday::Saturday => chrono::Weekday::Sat,
+            Weekday::Sunday => chrono::Weekday::Sun,
+        }
+    }
+}
+
+/// A workspace's settings, stored as the JSON object of those set; each is
+/// null for its default.
+#[derive(Deserialize, Serialize, Default, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceSettings {
+    /// Given to the todos created in the workspace without a priority; none
+    /// by default.
+    default_priority: Option<Priority>,
+    /// How long before their due date the workspace's todos are reminded
+    /// of, 1 to 10080; `REMINDER_LEAD_SECS` by default.
+    reminder_offset_minutes: Option<i32>,
+    /// The day the leaderboard's weeks start on; monday by default.
+    week_start: Option<Weekday>,
+    /// The IANA timezone the leaderboard's periods start at midnight in;
+    /// UTC by default.
+    #[schema(example = "Europe/Berlin")]
+    timezone: Option<String>,
+}
+
+impl WorkspaceSettings {
+    fn tz(&self) -> chrono_tz::Tz {
+        self.timezone
+            .as_deref()
+            .and_then(|timezone| timezone.parse().ok())
+            .unwrap_or(chrono_tz::UTC)
+    }
+}
+
+impl Validate for WorkspaceSettings {
+    fn validate(&self) -> Vec<FieldError> {
+        let offset = self
+            .reminder_offset_minutes
+            .filter(|minutes| !(1..=MAX_REMINDER_OFFSET_MINUTES).contains(minutes))
+            .map(|_| FieldError {
+                field: "reminder_offset_minutes",
+                reason: format!("must be 1 to {MAX_REMINDER_OFFSET_MINUTES}"),
+            });
+        let timezone = self
+            .timezone
+            .as_deref()
+            .filter(|timezone| timezone.parse::<chrono_tz::Tz>().is_err())
+            .map(|_| FieldError {
+                field: "timezone",
+                reason: "must be an IANA timezone name".to_owned(),
+            });
+        offset.into_iter().chain(timezone).collect()
+    }
+}
+
+/// Reads the workspace the request names, in `X-Workspace` or else in the
+/// subdomain of [`WorkspaceDomain`] its host is, for [`AuthUser`] to check
+/// and act in. Layered on the routes of the data workspaces have.
+pub async fn resolve<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let domain = match request.extensions().get::<WorkspaceDomain>() {
+        Some(WorkspaceDomain(domain)) => domain.as_deref(),
+        None => None,
+    };
+    if let Some(slug) = named(&request, domain) {
+        request.extensions_mut().insert(Tenant(slug));
+    }
+    next.run(request).await
+}
+
+/// The slug of the workspace `request` names, in `X-Workspace` or else in
+/// the subdomain of `domain` its host is.
+pub(crate) fn named<B>(request: &Request<B>, domain: Option<&str>) -> Option<String> {
+    let named = request
+        .headers()
+        .get(X_WORKSPACE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).trim().to_owned());
+    named.or_else(|| {
+        let domain = domain?;
+        // HTTP/2 requests have an authority rather than a Host header
+        let host = match request.uri().host() {
+            Some(host) => host,
+            None => request.headers().get(header::HOST)?.to_str().ok()?,
+        };
+        let host = host.split(':').next().unwrap_or(host);
+        subdomain(host, domain).map(str::to_owned)
+    })
+}
+
+/// The single label `host` has in front of `domain`, if it is a subdomain
+/// of it.
+fn subdomain<'a>(host: &'a str, domain: &str) -> Option<&'a str> {
+    let label = host
+        .len()
+        .checked_sub(domain.len())
+        .filter(|&start| host[start..].eq_ignore_ascii_case(domain))
+        .and_then(|start| host[..start].strip_suffix('.'))?;
+    (!label.is_empty() && !label.contains('.')).then_some(label)
+}
+
+/// `user_id` or, for a request acting in a workspace, the workspace if they
+/// are one of its members. Called by [`AuthUser`] once it knows the user.
+pub(crate) async fn scope(parts: &Parts, user_id: uuid::Uuid) -> Result<uuid::Uuid, ApiError> {
+    let Some(Tenant(slug)) = parts.extensions.get::<Tenant>() else {
+        return Ok(user_id);
+    };
+    let Some(pg) = parts.extensions.get::<PgPool>() else {
+        error!("PgPool extension is missing");
+        return Err(ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal error",
+        ));
+    };
+    match membership(pg, slug, user_id).await? {
+        Some((workspace_id, _)) => Ok(workspace_id),
+        None => Err(no_such_workspace()),
+    }
+}
+
+/// The workspaces the user is a member of, by slug.
+#[utoipa::path(
+    get,
+    path = "/workspaces",
+    tag = "workspaces",
+    responses(
+        (status = 200, description = "The user's workspaces", body = Vec<WorkspaceView>),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list(pg: Extension<PgPool>, AuthUser(user_id): AuthUser) -> axum::response::Response {
+    let result = sqlx::query_as::<_, WorkspaceView>(
+        r#"select w.id, w.slug, w.name, m.role, w.created_at
+        from "workspace" w join "workspace_member" m on m.workspace_id = w.id
+        where m.user_id = $1
+        order by w.slug"#,
+    )
+    .bind(user_id)
+    .fetch_all(&*pg)
+    .await;
+    match result {
+        Ok(workspaces) => Json(workspaces).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Creates a workspace, with the user as its owner.
+#[utoipa::path(
+    post,
+    path = "/workspaces",
+    tag = "workspaces",
+    request_body = NewWorkspace,
+    responses(
+        (status = 201, description = "The new workspace", body = WorkspaceView),
+        (status = 409, description = "Slug is taken", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid slug or name, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Valid(body): Valid<NewWorkspace>,
+) -> axum::response::Response {
+    match insert_workspace(&mut tx, user_id, &body).await {
+        Ok(workspace) => (StatusCode::CREATED, Json(workspace)).into_response(),
+        Err(sqlx::Error::Database(err)) if err.code().as_deref() == Some("23505") => {
+            ApiError::new(StatusCode::CONFLICT, "Slug is taken").into_response()
+        }
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+async fn insert_workspace(
+    conn: &mut PgConnection,
+    owner_id: uuid::Uuid,
+    body: &NewWorkspace,
+) -> Result<WorkspaceView, sqlx::Error> {
+    let id = uuid::Uuid::new_v4();
+    // the account the workspace's data belongs to, named so no user can
+    // take the name first
+    sqlx::query(r#"insert into "user" (user_id, username) values ($1, $2)"#)
+        .bind(id)
+        .bind(format!("workspace:{id}"))
+        .execute(&mut *conn)
+        .await?;
+    let created_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+        r#"insert into "workspace" (id, slug, name) values ($1, $2, $3) returning created_at"#,
+    )
+    .bind(id)
+    .bind(&body.slug)
+    .bind(body.name.trim())
+    .fetch_one(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"insert into "workspace_member" (workspace_id, user_id, role) values ($1, $2, 'owner')"#,
+    )
+    .bind(id)
+    .bind(owner_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(WorkspaceView {
+        id,
+        slug: body.slug.clone(),
+        name: body.name.trim().to_owned(),
+        role: WorkspaceRole::Owner,
+        created_at,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/workspaces/{slug}/members",
+    tag = "workspaces",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+    ),
+    responses(
+        (status = 200, description = "The workspace's members, by username", body = Vec<Member>),
+        (status = 404, description = "No such workspace, or the user isn't a member", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn members(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(slug): Path<String>,
+) -> axum::response::Response {
+    let workspace_id = match membership(&mut *tx, &slug, user_id).await {
+        Ok(Some((workspace_id, _))) => workspace_id,
+        Ok(None) => return no_such_workspace().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let result = sqlx::query_as::<_, Member>(
+        r#"select m.user_id, u.username, m.role, m.joined_at
+        from "workspace_member" m join "user" u on u.user_id = m.user_id
+        where m.workspace_id = $1
+        order by u.username"#,
+    )
+    .bind(workspace_id)
+    .fetch_all(&mut *tx)
+    .await;
+    match result {
+        Ok(members) => Json(members).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Removes a member: owners remove anyone, members only themselves. A
+/// workspace keeps at least one owner.
+#[utoipa::path(
+    delete,
+    path = "/workspaces/{slug}/members/{user_id}",
+    tag = "workspaces",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+        ("user_id" = uuid::Uuid, Path, description = "The member's user id"),
+    ),
+    responses(
+        (status = 204, description = "No longer a member"),
+        (status = 403, description = "A member removing someone else", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such workspace or member", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "The last owner", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn remove_member(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path((slug, member_id)): Path<(String, uuid::Uuid)>,
+) -> axum::response::Response {
+    let workspace_id = match membership(&mut *tx, &slug, user_id).await {
+        Ok(Some((_, WorkspaceRole::Member))) if member_id != user_id => {
+            return ApiError::new(StatusCode::FORBIDDEN, "Only owners remove other members")
+                .into_response()
+        }
+        Ok(Some((workspace_id, _))) => workspace_id,
+        Ok(None) => return no_such_workspace().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    // the owners are locked, so two of them can't leave at once
+    let result = sqlx::query_scalar::<_, i64>(
+        r#"select count(*) from (
+            select 1 from "workspace_member"
+            where workspace_id = $1 and role = 'owner' and user_id <> $2
+            for update
+        ) others"#,
+    )
+    .bind(workspace_id)
+    .bind(member_id)
+    .fetch_one(&mut *tx)
+    .await;
+    let other_owners = match result {
+        Ok(others) => others,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let result = sqlx::query_scalar::<_, WorkspaceRole>(
+        r#"delete from "workspace_member" where workspace_id = $1 and user_id = $2 returning role"#,
+    )
+    .bind(workspace_id)
+    .bind(member_id)
+    .fetch_optional(&mut *tx)
+    .await;
+    match result {
+        Ok(Some(WorkspaceRole::Owner)) if other_owners == 0 => {
+            ApiError::new(StatusCode::CONFLICT, "A workspace keeps at least one owner")
+                .into_response()
+        }
+        Ok(Some(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(None) => ApiError::new(StatusCode::NOT_FOUND, "No such member").into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Invites a user to the workspace, as its owners may.
+#[utoipa::path(
+    post,
+    path = "/workspaces/{slug}/invitations",
+    tag = "workspaces",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+    ),
+    request_body(content = Option<CreateInvitation>),
+    responses(
+        (status = 201, description = "The invitation, with its token shown this once", body = InvitationView),
+        (status = 400, description = "`expires_at` is in the past", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The user isn't an owner", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such workspace, or the user isn't a member", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn invite(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(slug): Path<String>,
+    body: Option<Json<CreateInvitation>>,
+) -> axum::response::Response {
+    let workspace_id = match membership(&mut *tx, &slug, user_id).await {
+        Ok(Some((workspace_id, WorkspaceRole::Owner))) => workspace_id,
+        Ok(Some(_)) => {
+            return ApiError::new(StatusCode::FORBIDDEN, "Only owners invite members")
+                .into_response()
+        }
+        Ok(None) => return no_such_workspace().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let body = body.map(|Json(body)| body).unwrap_or(CreateInvitation {
+        username: None,
+        role: None,
+        expires_at: None,
+    });
+    let now = Utc::now();
+    let expires_at = body
+        .expires_at
+        .unwrap_or_else(|| now + Duration::days(DEFAULT_INVITATION_DAYS));
+    if expires_at <= now {
+        return ApiError::new(StatusCode::BAD_REQUEST, "expires_at must be in the future")
+            .into_response();
+    }
+    let username = body.username.map(|username| username.trim().to_owned());
+    let role = body.role.unwrap_or(WorkspaceRole::Member);
+    // two v4 uuids give 244 random bits
+    let token = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let result = sqlx::query_scalar::<_, uuid::Uuid>(
+        r#"insert into "workspace_invitation"
+            (workspace_id, token_hash, username, role, invited_by, expires_at)
+        values ($1, $2, $3, $4, $5, $6)
+        returning id"#,
+    )
+    .bind(workspace_id)
+    .bind(hash(&token))
+    .bind(&username)
+    .bind(role)
+    .bind(user_id)
+    .bind(expires_at)
+    .fetch_one(&mut *tx)
+    .await;
+    match result {
+        Ok(id) => (
+            StatusCode::CREATED,
+            Json(InvitationView {
+                id,
+                token,
+                username,
+                role,
+                expires_at,
+            }),
+        )
+            .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Joins the workspace of an invitation. Expired, used and unknown tokens,
+/// and those for another user, all look the same.
+#[utoipa::path(
+    post,
+    path = "/invitations/{token}/accept",
+    tag = "workspaces",
+    params(
+        ("token" = String, Path, description = "The invitation's token"),
+    ),
+    responses(
+        (status = 200, description = "The workspace joined", body = WorkspaceView),
+        (status = 404, description = "No such invitation", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Already a member", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn accept(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(token): Path<String>,
+) -> axum::response::Response {
+    let result = sqlx::query_as::<_, (uuid::Uuid, WorkspaceRole)>(
+        r#"update "workspace_invitation" set accepted_by = $2, accepted_at = now()
+        where token_hash = $1 and accepted_at is null and expires_at > now()
+            and (username is null
+                or username = (select username from "user" where user_id = $2))
+        returning workspace_id, role"#,
+    )
+    .bind(hash(&token))
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await;
+    let (workspace_id, role) = match result {
+        Ok(Some(invitation)) => invitation,
+        Ok(None) => {
+            return ApiError::new(StatusCode::NOT_FOUND, "No such invitation").into_response()
+        }
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    // answering with an error rolls the acceptance back, the invitation
+    // stays usable by someone else
+    let result = sqlx::query_as::<_, WorkspaceView>(
+        r#"with joined as (
+            insert into "workspace_member" (workspace_id, user_id, role)
+            values ($1, $2, $3)
+            on conflict do nothing
+            returning workspace_id, role
+        )
+        select w.id, w.slug, w.name, j.role, w.created_at
+        from joined j join "workspace" w on w.id = j.workspace_id"#,
+    )
+    .bind(workspace_id)
+    .bind(user_id)
+    .bind(role)
+    .fetch_optional(&mut *tx)
+    .await;
+    match result {
+        Ok(Some(workspace)) => Json(workspace).into_response(),
+        Ok(None) => ApiError::new(StatusCode::CONFLICT, "Already a member").into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Ranks the members by the todos they completed in the workspace this
+/// week or month, leaving out those who opted out. The periods start on the
+/// week start and in the timezone of the workspace's settings.
+#[utoipa::path(
+    get,
+    path = "/workspaces/{slug}/leaderboard",
+    tag = "workspaces",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+        LeaderboardQuery,
+    ),
+    responses(
+        (status = 200, description = "The ranked members", body = Leaderboard),
+        (status = 404, description = "No such workspace, or the user isn't a member", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn leaderboard(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(slug): Path<String>,
+    Query(query): Query<LeaderboardQuery>,
+) -> axum::response::Response {
+    let workspace_id = match membership(&mut *tx, &slug, user_id).await {
+        Ok(Some((workspace_id, _))) => workspace_id,
+        Ok(None) => return no_such_workspace().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let settings = match settings(&mut *tx, workspace_id).await {
+        Ok(settings) => settings,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    let period = query.period.unwrap_or(Period::Week);
+    let since = period.start(&settings, Utc::now());
+    // a completion is a change of one of the workspace's todos to done
+    let result = sqlx::query_as::<_, Ranked>(
+        r#"select rank() over (order by count(l.id) desc) as rank,
+            m.user_id, u.username, count(l.id) as completed
+        from "workspace_member" m
+        join "user" u on u.user_id = m.user_id
+        left join ("todo_audit_log" l join "todo" t on t.id = l.todo_id and t.user_id = $1)
+            on l.actor_id = m.user_id and l.at >= $2
+                and (l.after->>'is_done')::boolean
+                and not coalesce((l.before->>'is_done')::boolean, false)
+        where m.workspace_id = $1 and m.on_leaderboard
+        group by m.user_id, u.username
+        order by rank, u.username"#,
+    )
+    .bind(workspace_id)
+    .bind(since)
+    .fetch_all(&mut *tx)
+    .await;
+    match result {
+        Ok(members) => Json(Leaderboard {
+            period,
+            since,
+            members,
+        })
+        .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// The user's privacy settings in the workspace.
+#[utoipa::path(
+    get,
+    path = "/workspaces/{slug}/privacy",
+    tag = "workspaces",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+    ),
+    responses(
+        (status = 200, description = "The user's settings", body = Privacy),
+        (status = 404, description = "No such workspace, or the user isn't a member", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_privacy(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(slug): Path<String>,
+) -> axum::response::Response {
+    let result = sqlx::query_scalar::<_, bool>(
+        r#"select m.on_leaderboard
+        from "workspace" w join "workspace_member" m on m.workspace_id = w.id
+        where w.slug = $1 and m.user_id = $2"#,
+    )
+    .bind(&slug)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await;
+    respond_privacy(result)
+}
+
+/// Sets the user's privacy settings in the workspace, opting in or out of
+/// the leaderboard.
+#[utoipa::path(
+    put,
+    path = "/workspaces/{slug}/privacy",
+    tag = "workspaces",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+    ),
+    request_body = Privacy,
+    responses(
+        (status = 200, description = "The user's settings", body = Privacy),
+        (status = 404, description = "No such workspace, or the user isn't a member", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn put_privacy(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(slug): Path<String>,
+    Json(body): Json<Privacy>,
+) -> axum::response::Response {
+    let result = sqlx::query_scalar::<_, bool>(
+        r#"update "workspace_member" m set on_leaderboard = $3
+        from "workspace" w
+        where w.id = m.workspace_id and w.slug = $1 and m.user_id = $2
+        returning m.on_leaderboard"#,
+    )
+    .bind(&slug)
+    .bind(user_id)
+    .bind(body.on_leaderboard)
+    .fetch_optional(&mut *tx)
+    .await;
+    respond_privacy(result)
+}
+
+fn respond_privacy(result: Result<Option<bool>, sqlx::Error>) -> axum::response::Response {
+    match result {
+        Ok(Some(on_leaderboard)) => Json(Privacy { on_leaderboard }).into_response(),
+        Ok(None) => no_such_workspace().into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// The workspace's settings.
+#[utoipa::path(
+    get,
+    path = "/workspaces/{slug}/settings",
+    tag = "workspaces",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+    ),
+    responses(
+        (status = 200, description = "The workspace's settings", body = WorkspaceSettings),
+        (status = 404, description = "No such workspace, or the user isn't a member", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn get_settings(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(slug): Path<String>,
+) -> axum::response::Response {
+    let workspace_id = match membership(&mut *tx, &slug, user_id).await {
+        Ok(Some((workspace_id, _))) => workspace_id,
+        Ok(None) => return no_such_workspace().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    match settings(&mut *tx, workspace_id).await {
+        Ok(settings) => Json(settings).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Replaces the workspace's settings, those left out going back to their
+/// defaults.
+#[utoipa::path(
+    put,
+    path = "/workspaces/{slug}/settings",
+    tag = "workspaces",
+    params(
+        ("slug" = String, Path, description = "Workspace slug"),
+    ),
+    request_body = WorkspaceSettings,
+    responses(
+        (status = 200, description = "The workspace's settings", body = WorkspaceSettings),
+        (status = 403, description = "The user isn't an owner", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such workspace, or the user isn't a member", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "An invalid setting, or an unknown field", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn put_settings(
+    AuthUser(user_id): AuthUser,
+    mut tx: Tx,
+    Path(slug): Path<String>,
+    Valid(body): Valid<WorkspaceSettings>,
+) -> axum::response::Response {
+    let workspace_id = match membership(&mut *tx, &slug, user_id).await {
+        Ok(Some((workspace_id, WorkspaceRole::Owner))) => workspace_id,
+        Ok(Some(_)) => {
+            return ApiError::new(StatusCode::FORBIDDEN, "Only owners change the settings")
+                .into_response()
+        }
+        Ok(None) => return no_such_workspace().into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+    // nulls are stripped, an absent setting is the default
+    let result = sqlx::query_scalar::<_, sqlx::types::Json<WorkspaceSettings>>(
+        r#"update "workspace" set settings = jsonb_strip_nulls($2)
+        where id = $1
+        returning settings"#,
+    )
+    .bind(workspace_id)
+    .bind(sqlx::types::Json(&body))
+    .fetch_one(&mut *tx)
+    .await;
+    match result {
+        Ok(settings) => Json(settings.0).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+async fn settings(
+    db: impl PgExecutor<'_>,
+    workspace_id: uuid::Uuid,
+) -> Result<WorkspaceSettings, sqlx::Error> {
+    let settings = sqlx::query_scalar::<_, sqlx::types::Json<WorkspaceSettings>>(
+        r#"select settings from "workspace" where id = $1"#,
+    )
+    .bind(workspace_id)
+    .fetch_one(db)
+    .await?;
+    Ok(settings.0)
+}
+
+/// The workspace of `slug` and the user's role in it, if they are a member.
+async fn membership(
+    db: impl PgExecutor<'_>,
+    slug: &str,
+    user_id: uuid::Uuid,
+) -> Result<Option<(uuid::Uuid, WorkspaceRole)>, sqlx::Error> {
+    sqlx::query_as(
+        r#"select w.id, m.role
+        from "workspace" w join "workspace_member" m on m.workspace_id = w.id
+        where w.slug = $1 and m.user_id = $2"#,
+    )
+    .bind(slug)
+    .bind(user_id)
+    .fetch_optional(db)
+    .await
+}
+
+/// Whether a workspace isn't there or the user isn't a member is all that
+/// non-members learn.
+fn no_such_workspace() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "No such workspace")
+}
+
+fn is_slug(slug: &str) -> bool {
+    (1..=MAX_SLUG_CHARS).contains(&slug.len())
+        && slug
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+}
+
+fn hash(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        rfc3339.parse().unwrap()
+    }
+
+    #[test]
+    fn periods_start_on_the_week_start_in_the_timezone() {
+        // a Monday, which is already Tuesday in Tokyo
+        let now = at("2030-01-07T20:00:00Z");
+        let utc = WorkspaceSettings::default();
+        assert_eq!(Period::Week.start(&utc, now), at("2030-01-07T00:00:00Z"));
+        assert_eq!(Period::Month.start(&utc, now), at("2030-01-01T00:00:00Z"));
+        let tokyo = WorkspaceSettings {
+            week_start: Some(Weekday::Tuesday),
+            timezone: Some("Asia/Tokyo".to_owned()),
+            ..WorkspaceSettings::default()
+        };
+        assert_eq!(Period::Week.start(&tokyo, now), at("2030-01-07T15:00:00Z"));
+        assert_eq!(Period::Month.start(&tokyo, now), at("2029-12-31T15:00:00Z"));
+        let sunday = WorkspaceSettings {
+            week_start: Some(Weekday::Sunday),
+            ..WorkspaceSettings::default()
+        };
+        assert_eq!(Period::Week.start(&sunday, now), at("2030-01-06T00:00:00Z"));
+    }
+}
@@ -0,0 +1,432 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::json;
+
+#[tokio::test]
+async fn health_probes() {
+    let app = TestApp::new().await;
+    let response = app.request(Method::GET, "/healthz", None, None, &[]).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["status"], "ok");
+
+    let response = app.request(Method::GET, "/readyz", None, None, &[]).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let newest = hello_world_api::repository::MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .max();
+    assert_eq!(response.json()["migration"].as_i64(), newest);
+    let pool = &response.json()["pool"];
+    assert!(pool["size"].as_u64().unwrap() >= 1, "{pool}");
+    assert_eq!(pool["max"], 20);
+    assert_eq!(pool["saturated"], false);
+
+    // operator endpoints aren't versioned
+    let response = app
+        .request(Method::GET, "/api/v1/healthz", None, None, &[])
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn metrics_count_requests() {
+    let app = TestApp::new().await;
+    app.request(Method::GET, "/healthz", None, None, &[]).await;
+    let response = app.request(Method::GET, "/metrics", None, None, &[]).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.text().contains("/healthz"), "{}", response.text());
+    assert!(response.text().contains("db_pool_connections_max 20"));
+}
+
+#[tokio::test]
+async fn metrics_time_queries() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    app.get("/api/v1/todos", &token).await;
+    let response = app.request(Method::GET, "/metrics", None, None, &[]).await;
+    assert!(
+        response
+            .text()
+            .contains(r#"db_query_duration_seconds{statement="list_todos",quantile="0.95"}"#),
+        "{}",
+        response.text()
+    );
+}
+
+#[tokio::test]
+async fn audit_log_and_jobs() {
+    let app = TestApp::new().await;
+    let admin = app.admin().await;
+
+    // nobody acted as anybody else
+    let response = app.get("/admin/audit-log", &admin).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json(), json!([]));
+    let response = app.get("/admin/audit-log?limit=0", &admin).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    // no server started the background jobs
+    let response = app.get("/admin/jobs", &admin).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json(), json!([]));
+}
+
+#[tokio::test]
+async fn maintenance_mode() {
+    let app = TestApp::new().await;
+    let admin = app.admin().await;
+    let response = app
+        .put("/admin/maintenance", &admin, json!({"enabled": true}))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["enabled"], true);
+    let response = app.get("/admin/maintenance", &admin).await;
+    assert_eq!(response.json()["enabled"], true);
+}
+
+#[tokio::test]
+async fn recording_is_switched_by_admins() {
+    let app = TestApp::new().await;
+    let admin = app.admin().await;
+    let path = "/debug/recordings/settings";
+    let response = app.get(path, &admin).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["enabled"], false);
+
+    let settings = json!({"enabled": true, "route": "/api/v1/todos", "user_id": null, "sample": 2});
+    let response = app.put(path, &admin, settings.clone()).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(app.get(path, &admin).await.json(), settings);
+    let response = app
+        .put(path, &admin, json!({"enabled": true, "sample": 0}))
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let response = app.get("/debug/recordings", &admin).await;
+    assert_eq!(response.json(), json!([]));
+}
+
+#[tokio::test]
+async fn workspace_rate_limits() {
+    let app = TestApp::new().await;
+    let admin = app.admin().await;
+    let alice = app.user("alice").await;
+    let response = app
+        .post(
+            "/api/v1/workspaces",
+            &alice,
+            json!({"slug": "acme", "name": "Acme Corp"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let path = "/admin/workspaces/acme/rate-limit";
+    assert_eq!(app.get(path, &admin).await.status, StatusCode::NOT_FOUND);
+
+    let response = app.put(path, &admin, json!({"per_minute": 600})).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json(), json!({"per_minute": 600, "burst": 600}));
+    let response = app
+        .put(path, &admin, json!({"per_minute": 600, "burst": 50}))
+        .await;
+    assert_eq!(response.json(), json!({"per_minute": 600, "burst": 50}));
+    assert_eq!(
+        app.get(path, &admin).await.json(),
+        json!({"per_minute": 600, "burst": 50})
+    );
+
+    let response = app.put(path, &admin, json!({"per_minute": 0})).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let response = app
+        .put(
+            "/admin/workspaces/nope/rate-limit",
+            &admin,
+            json!({"per_minute": 60}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.put(path, &alice, json!({"per_minute": 6000})).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    assert_eq!(
+        app.delete(path, &admin).await.status,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(app.delete(path, &admin).await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn export_and_import_everything() {
+    let app = TestApp::new().await;
+    let admin = app.admin().await;
+    let alice = app.user("alice").await;
+    app.todo(&alice, "Buy milk").await;
+
+    let response = app.get("/admin/export", &admin).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let export = response.json();
+
+    assert_eq!(export["users"][0]["is_admin"], true);
+    assert_eq!(export["users"][1]["is_admin"], false);
+
+    let copy = TestApp::new().await;
+    let copy_admin = copy.admin().await;
+    let response = copy
+        .post("/admin/import", &copy_admin, export.clone())
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let response = copy
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(json!({"username": "alice", "password": "a password"})),
+            &[],
+        )
+        .await;
+    let token = response.json()["access_token"].as_str().unwrap().to_owned();
+    let page = copy.get("/api/v1/todos", &token).await.json();
+    assert_eq!(page["items"][0]["text"], "Buy milk");
+
+    // importing again skips what is already there
+    let response = copy.post("/admin/import", &copy_admin, export).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let report = response.json();
+    assert_eq!(report["todos"], 0);
+    assert_eq!(report["users"][1]["created"], false);
+    let page = copy.get("/api/v1/todos", &token).await.json();
+    assert_eq!(page["total"], 1);
+}
+
+#[tokio::test]
+async fn export_and_import_workspaces() {
+    let app = TestApp::new().await;
+    let admin = app.admin().await;
+    let alice = app.user("alice").await;
+    let response = app
+        .post(
+            "/api/v1/workspaces",
+            &alice,
+            json!({"slug": "acme", "name": "Acme Corp"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let response = app
+        .put(
+            "/api/v1/workspaces/acme/settings",
+            &alice,
+            json!({"default_priority": "high"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/todos",
+            Some(&alice),
+            Some(json!({"text": "Ship it"})),
+            &[("x-workspace", "acme")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+
+    let export = app.get("/admin/export", &admin).await.json();
+    let workspaces = export["workspaces"].as_array().unwrap();
+    assert_eq!(workspaces.len(), 1);
+    assert_eq!(workspaces[0]["slug"], "acme");
+    assert_eq!(workspaces[0]["members"][0]["role"], "owner");
+
+    let copy = TestApp::new().await;
+    let copy_admin = copy.admin().await;
+    let response = copy
+        .post("/admin/import", &copy_admin, export.clone())
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["workspaces"], 1);
+    let response = copy
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(json!({"username": "alice", "password": "a password"})),
+            &[],
+        )
+        .await;
+    let token = response.json()["access_token"].as_str().unwrap().to_owned();
+    let page = copy
+        .request(
+            Method::GET,
+            "/api/v1/todos",
+            Some(&token),
+            None,
+            &[("x-workspace", "acme")],
+        )
+        .await
+        .json();
+    assert_eq!(page["items"][0]["text"], "Ship it");
+    assert_eq!(page["items"][0]["priority"], "high");
+    let settings = copy
+        .get("/api/v1/workspaces/acme/settings", &token)
+        .await
+        .json();
+    assert_eq!(settings["default_priority"], "high");
+    // alice's own todos aren't the workspace's
+    let page = copy.get("/api/v1/todos", &token).await.json();
+    assert_eq!(page["total"], 0);
+
+    // importing again finds the workspace by its slug
+    let response = copy.post("/admin/import", &copy_admin, export).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["workspaces"], 0);
+    assert_eq!(response.json()["todos"], 0);
+}
+
+#[tokio::test]
+async fn stats_across_users() {
+    let app = TestApp::new().await;
+    let admin = app.admin().await;
+    let alice = app.user("alice").await;
+    let done = app.todo(&alice, "Buy milk").await;
+    app.todo(&alice, "Pay rent").await;
+    app.todo(&admin, "Rotate the keys").await;
+    let path = format!("/api/v1/todos/{}", done["id"].as_str().unwrap());
+    app.request(
+        Method::PATCH,
+        &path,
+        Some(&alice),
+        Some(json!({"is_done": true})),
+        &[("if-match", "*")],
+    )
+    .await;
+
+    let response = app.get("/admin/stats", &admin).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let stats = response.json();
+    assert_eq!(stats["total"], 3);
+    assert_eq!(stats["completed"], 1);
+    assert_eq!(stats["open"], 2);
+    let days = stats["created_per_day"].as_array().unwrap();
+    assert_eq!(days.len(), 30);
+    assert_eq!(days[29]["created"], 3);
+    assert_eq!(days[0]["created"], 0);
+    assert_eq!(stats["users"][0]["username"], "alice");
+    assert_eq!(stats["users"][0]["completed"], 1);
+    assert_eq!(stats["users"][1]["total"], 1);
+
+    let response = app.get("/admin/stats?users=1", &admin).await;
+    assert_eq!(response.json()["users"].as_array().unwrap().len(), 1);
+    let response = app.get("/admin/stats?users=0", &admin).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    // users' data is for admins only
+    let response = app.get("/admin/stats", &alice).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app
+        .request(Method::GET, "/admin/stats", None, None, &[])
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn admin_endpoints_need_the_admin_role() {
+    let app = TestApp::new().await;
+    let admin = app.admin().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    app.todo(&alice, "Buy milk").await;
+    app.todo(&bob, "Pay rent").await;
+
+    let response = app.get("/admin/todos", &admin).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let page = response.json();
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["items"][0]["username"], "bob");
+    let page = app.get("/admin/todos?user=alice", &admin).await.json();
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["items"][0]["text"], "Buy milk");
+    let response = app.get("/admin/todos?limit=0", &admin).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    for path in [
+        "/admin/todos",
+        "/admin/jobs",
+        "/admin/export",
+        "/admin/audit-log",
+        "/admin/stats",
+        "/admin/maintenance",
+        "/debug/recordings",
+        "/debug/recordings/settings",
+    ] {
+        let response = app.get(path, &alice).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN, "{path}");
+        assert_eq!(
+            response.header("content-type"),
+            Some("application/problem+json"),
+            "{path}"
+        );
+        assert_eq!(response.json()["status"], 403, "{path}");
+        let response = app.request(Method::GET, path, None, None, &[]).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{path}");
+    }
+    let response = app
+        .put("/admin/maintenance", &alice, json!({"enabled": true}))
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app.post("/admin/import", &alice, json!({})).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    // the role is checked on every request, not only when logging in
+    sqlx::query(r#"update "user" set role = 'user' where username = 'admin'"#)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let response = app.get("/admin/todos", &admin).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn circuit_breaker_opens_after_repeated_failures() {
+    use hello_world_api::resilience::{self, CircuitBreaker};
+    use std::time::Duration;
+
+    let breaker = CircuitBreaker::new(3, Duration::from_secs(10), Duration::from_millis(200));
+    breaker.record_failure();
+    breaker.record_failure();
+    assert!(!breaker.is_open());
+    breaker.record_failure();
+    let retry_after = breaker.retry_after().expect("open after 3 failures");
+    assert!(retry_after <= Duration::from_millis(200));
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert!(!breaker.is_open());
+    // just after it closed, a single failure opens it again
+    breaker.record_failure();
+    assert!(breaker.is_open());
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    breaker.record_success();
+    breaker.record_failure();
+    assert!(!breaker.is_open());
+
+    // lost connections are retried, other errors aren't
+    let mut attempts = 0;
+    let result = resilience::retry(|| {
+        attempts += 1;
+        let failed = attempts < 3;
+        async move {
+            match failed {
+                true => Err(sqlx::Error::Io(std::io::ErrorKind::ConnectionReset.into())),
+                false => Ok(attempts),
+            }
+        }
+    })
+    .await;
+    assert_eq!(result.unwrap(), 3);
+    let mut attempts = 0;
+    let result = resilience::retry(|| {
+        attempts += 1;
+        async { Err::<(), _>(sqlx::Error::RowNotFound) }
+    })
+    .await;
+    assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+    assert_eq!(attempts, 1);
+}
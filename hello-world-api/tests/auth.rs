@@ -0,0 +1,410 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::json;
+
+#[tokio::test]
+async fn setup_creates_the_first_admin_once() {
+    let app = TestApp::new().await;
+    let response = app
+        .request(Method::GET, "/api/v1/setup", None, None, &[])
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["needed"], true);
+
+    let token = app.admin().await;
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/introspect",
+            None,
+            Some(json!({"token": token})),
+            &[],
+        )
+        .await;
+    assert_eq!(response.json()["scope"], "todos:read todos:write admin");
+
+    let response = app
+        .request(Method::GET, "/api/v1/setup", None, None, &[])
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/setup",
+            None,
+            Some(json!({"username": "second", "password": "a password"})),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn register_rejects_taken_and_invalid_usernames() {
+    let app = TestApp::new().await;
+    app.user("alice").await;
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/register",
+            None,
+            Some(json!({"username": "alice", "password": "another password"})),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/problem+json")
+    );
+
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/register",
+            None,
+            Some(json!({"username": "bob", "password": "x"})),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn login_checks_the_password_and_scopes() {
+    let app = TestApp::new().await;
+    app.user("alice").await;
+    let login = |password: &str, scope: Option<&str>| json!({"username": "alice", "password": password, "scope": scope});
+
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(login("wrong password", None)),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(login("a password", Some("admin"))),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(login("a password", Some("todos:read"))),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let token = response.json()["access_token"].as_str().unwrap().to_owned();
+    assert_eq!(response.json()["scope"], "todos:read");
+
+    // reading is allowed, writing is not
+    assert_eq!(
+        app.get("/api/v1/todos", &token).await.status,
+        StatusCode::OK
+    );
+    let response = app
+        .post("/api/v1/todos", &token, json!({"text": "Buy milk"}))
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn introspect_tells_valid_tokens_apart() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/introspect",
+            None,
+            Some(json!({"token": token})),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["active"], true);
+    assert_eq!(response.json()["scope"], "todos:read todos:write");
+
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/introspect",
+            None,
+            Some(json!({"token": "not a token"})),
+            &[],
+        )
+        .await;
+    assert_eq!(response.json(), json!({"active": false}));
+}
+
+#[tokio::test]
+async fn endpoints_need_a_valid_token() {
+    let app = TestApp::new().await;
+    let response = app
+        .request(Method::GET, "/api/v1/todos", None, None, &[])
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = app.get("/api/v1/todos", "not a token").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn api_keys_act_as_their_user_with_their_scopes() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    app.todo(&token, "Buy milk").await;
+
+    let response = app
+        .post(
+            "/api/v1/auth/api-keys",
+            &token,
+            json!({"name": "backup", "scope": "todos:read"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let created = response.json();
+    let key = created["key"].as_str().unwrap().to_owned();
+    assert!(key.starts_with(created["prefix"].as_str().unwrap()));
+    assert_eq!(created["scope"], "todos:read");
+
+    let with_key = |method: Method, path: &'static str| {
+        let key = key.clone();
+        let app = &app;
+        async move {
+            app.request(method, path, None, None, &[("x-api-key", key.as_str())])
+                .await
+        }
+    };
+    let response = with_key(Method::GET, "/api/v1/todos").await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["items"][0]["text"], "Buy milk");
+    let response = with_key(
+        Method::DELETE,
+        "/api/v1/todos/00000000-0000-0000-0000-000000000000",
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    // keys can't manage keys
+    let response = with_key(Method::GET, "/api/v1/auth/api-keys").await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    let keys = app.get("/api/v1/auth/api-keys", &token).await.json();
+    assert_eq!(keys[0]["name"], "backup");
+    assert!(keys[0].get("key").is_none());
+
+    let path = format!("/api/v1/auth/api-keys/{}", created["id"].as_str().unwrap());
+    assert_eq!(
+        app.delete(&path, &token).await.status,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        app.delete(&path, &token).await.status,
+        StatusCode::NOT_FOUND
+    );
+    let response = with_key(Method::GET, "/api/v1/todos").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn api_keys_have_daily_quotas() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let response = app
+        .post(
+            "/api/v1/auth/api-keys",
+            &token,
+            json!({"name": "ci", "daily_quota": 2}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    assert_eq!(response.json()["daily_quota"], 2);
+    let key = response.json()["key"].as_str().unwrap().to_owned();
+
+    let mut remaining = Vec::new();
+    for _ in 0..2 {
+        let response = app
+            .request(
+                Method::GET,
+                "/api/v1/todos",
+                None,
+                None,
+                &[("x-api-key", &key)],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        remaining.push(response.header("x-quota-remaining").unwrap().to_owned());
+        let reset: i64 = response.header("x-quota-reset").unwrap().parse().unwrap();
+        assert!(reset > chrono::Utc::now().timestamp());
+    }
+    assert_eq!(remaining, ["1", "0"]);
+    let response = app
+        .request(
+            Method::GET,
+            "/api/v1/todos",
+            None,
+            None,
+            &[("x-api-key", &key)],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.header("x-quota-remaining"), Some("0"));
+    assert!(response.header("retry-after").is_some());
+
+    // bearer tokens aren't counted
+    let response = app.get("/api/v1/todos", &token).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("x-quota-remaining"), None);
+}
+
+#[tokio::test]
+async fn api_keys_are_checked_when_created() {
+    let app = TestApp::new().await;
+    let token = app.admin().await;
+    for body in [
+        json!({"name": " "}),
+        json!({"name": "ci", "scope": "admin"}),
+        json!({"name": "ci", "scope": "todos:delete"}),
+        json!({"name": "ci", "expires_at": "2000-01-01T00:00:00Z"}),
+        json!({"name": "ci", "daily_quota": 0}),
+    ] {
+        let response = app.post("/api/v1/auth/api-keys", &token, body).await;
+        assert_eq!(
+            response.status,
+            StatusCode::BAD_REQUEST,
+            "{}",
+            response.text()
+        );
+    }
+
+    let response = app
+        .post("/api/v1/auth/api-keys", &token, json!({"name": "ci"}))
+        .await;
+    assert_eq!(response.json()["scope"], "todos:read todos:write");
+    let key = response.json()["key"].as_str().unwrap().to_owned();
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/todos",
+            None,
+            Some(json!({"text": "Ship it"})),
+            &[("x-api-key", &key)],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    // an admin's key still isn't an admin
+    let response = app
+        .request(
+            Method::GET,
+            "/api/v1/todos",
+            None,
+            None,
+            &[("x-api-key", &key), ("x-act-as", "admin")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app
+        .request(
+            Method::GET,
+            "/api/v1/todos",
+            None,
+            None,
+            &[("x-api-key", "tdk_guess")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn preferences_are_replaced_from_the_version_read() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let path = "/api/v1/me/preferences";
+    let response = app.get(path, &alice).await;
+    assert_eq!(response.json(), json!({}));
+    let etag = response.header("etag").unwrap().to_owned();
+    assert_eq!(etag, "\"0\"");
+
+    let preferences = json!({"theme": "dark", "list_view": {"sort": "due_at"}});
+    let response = app
+        .request(
+            Method::PUT,
+            path,
+            Some(&alice),
+            Some(preferences.clone()),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::PRECONDITION_REQUIRED);
+    let response = app
+        .request(
+            Method::PUT,
+            path,
+            Some(&alice),
+            Some(preferences.clone()),
+            &[("if-match", &etag)],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json(), preferences);
+    assert_eq!(response.header("etag"), Some("\"1\""));
+    // a client still at the first version doesn't overwrite the change
+    let response = app
+        .request(
+            Method::PUT,
+            path,
+            Some(&alice),
+            Some(json!({"theme": "light"})),
+            &[("if-match", &etag)],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+    let response = app
+        .request(
+            Method::PUT,
+            path,
+            Some(&alice),
+            Some(json!({"theme": "light"})),
+            &[("if-match", "*")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.header("etag"), Some("\"2\""));
+    let response = app.get(path, &alice).await;
+    assert_eq!(response.json(), json!({"theme": "light"}));
+    // each user has their own
+    assert_eq!(app.get(path, &bob).await.json(), json!({}));
+
+    for invalid in [
+        json!(["dark"]),
+        json!({"": 1}),
+        json!({"notes": "x".repeat(16 * 1024)}),
+    ] {
+        let response = app
+            .request(
+                Method::PUT,
+                path,
+                Some(&alice),
+                Some(invalid),
+                &[("if-match", "*")],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
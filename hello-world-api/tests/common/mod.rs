@@ -0,0 +1,274 @@
+//! Harness of the integration tests: every test gets a database of its own,
+//! migrated, and sends requests to [`hello_world_api::app`] without binding
+//! a socket.
+//!
+//! The databases are created on the server at `TEST_DATABASE_URL` if it is
+//! set, e.g. the one of `docker-compose up db`, and dropped after the test.
+//! Otherwise every test starts a Postgres container of its own, which needs
+//! Docker.
+
+#![allow(dead_code)]
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use sqlx::{postgres::PgPoolOptions, Connection, PgConnection, PgPool};
+use testcontainers_modules::{
+    postgres::Postgres,
+    testcontainers::{runners::AsyncRunner, ContainerAsync, ImageExt},
+};
+use tower::ServiceExt;
+
+pub struct TestApp {
+    router: Router,
+    pub pool: PgPool,
+    server_url: String,
+    database: String,
+    _container: Option<ContainerAsync<Postgres>>,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// The body as JSON, `null` if it is empty.
+    pub fn json(&self) -> Value {
+        if self.body.is_empty() {
+            return Value::Null;
+        }
+        serde_json::from_slice(&self.body).unwrap_or_else(|err| {
+            panic!("{} is not JSON: {err}", String::from_utf8_lossy(&self.body))
+        })
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(name)
+            .map(|value| value.to_str().expect("header is not text"))
+    }
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        let (server_url, container) = match std::env::var("TEST_DATABASE_URL") {
+            Ok(url) => (url, None),
+            Err(_) => {
+                let container = Postgres::default()
+                    .with_tag("14-alpine")
+                    .start()
+                    .await
+                    .expect("failed to start Postgres, set TEST_DATABASE_URL without Docker");
+                let host = container.get_host().await.unwrap();
+                let port = container.get_host_port_ipv4(5432).await.unwrap();
+                let url = format!("postgres://postgres:postgres@{host}:{port}/postgres");
+                (url, Some(container))
+            }
+        };
+        let database = format!("test_{}", uuid::Uuid::new_v4().simple());
+        let mut conn = PgConnection::connect(&server_url)
+            .await
+            .expect("failed to connect to the test server");
+        sqlx::query(&format!(r#"create database "{database}""#))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        conn.close().await.unwrap();
+
+        let (base, _) = server_url.rsplit_once('/').expect("URL without database");
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(&format!("{base}/{database}"))
+            .await
+            .unwrap();
+        hello_world_api::repository::MIGRATOR
+            .run(&pool)
+            .await
+            .expect("failed to migrate");
+        TestApp {
+            router: hello_world_api::app(pool.clone()),
+            pool,
+            server_url,
+            database,
+            _container: container,
+        }
+    }
+
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+        headers: &[(&str, &str)],
+    ) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let body = match body {
+            Some(body) => {
+                request = request.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        self.send(request.body(body).unwrap()).await
+    }
+
+    /// Sends `body` as it is, for the endpoints that don't take JSON.
+    pub async fn request_body(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        content_type: &str,
+        body: &str,
+    ) -> TestResponse {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::CONTENT_TYPE, content_type);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        self.send(request.body(Body::from(body.to_owned())).unwrap())
+            .await
+    }
+
+    async fn send(&self, request: Request<Body>) -> TestResponse {
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .to_vec();
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    pub async fn get(&self, path: &str, token: &str) -> TestResponse {
+        self.request(Method::GET, path, Some(token), None, &[])
+            .await
+    }
+
+    pub async fn post(&self, path: &str, token: &str, body: Value) -> TestResponse {
+        self.request(Method::POST, path, Some(token), Some(body), &[])
+            .await
+    }
+
+    pub async fn put(&self, path: &str, token: &str, body: Value) -> TestResponse {
+        self.request(Method::PUT, path, Some(token), Some(body), &[])
+            .await
+    }
+
+    pub async fn delete(&self, path: &str, token: &str) -> TestResponse {
+        self.request(Method::DELETE, path, Some(token), None, &[])
+            .await
+    }
+
+    /// Creates the first user, an admin, through `POST /setup` and returns
+    /// their token.
+    pub async fn admin(&self) -> String {
+        let response = self
+            .request(
+                Method::POST,
+                "/api/v1/setup",
+                None,
+                Some(serde_json::json!({"username": "admin", "password": "admin password"})),
+                &[],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        token_of(&response)
+    }
+
+    /// Registers `username` and logs them in, returning their token.
+    pub async fn user(&self, username: &str) -> String {
+        let credentials = serde_json::json!({"username": username, "password": "a password"});
+        let response = self
+            .request(
+                Method::POST,
+                "/api/v1/auth/register",
+                None,
+                Some(credentials.clone()),
+                &[],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        let response = self
+            .request(
+                Method::POST,
+                "/api/v1/auth/login",
+                None,
+                Some(credentials),
+                &[],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        token_of(&response)
+    }
+
+    /// Creates a todo with `text` for the owner of `token`.
+    pub async fn todo(&self, token: &str, text: &str) -> Value {
+        let response = self
+            .post("/api/v1/todos", token, serde_json::json!({"text": text}))
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        response.json()
+    }
+}
+
+fn token_of(response: &TestResponse) -> String {
+    response.json()["access_token"]
+        .as_str()
+        .expect("no access_token")
+        .to_owned()
+}
+
+impl Drop for TestApp {
+    /// Drops the test's database, which a container is removed with anyway.
+    fn drop(&mut self) {
+        if self._container.is_some() {
+            return;
+        }
+        let server_url = self.server_url.clone();
+        let database = self.database.clone();
+        // dropping can't wait for the test's runtime, so it gets one of its own
+        let dropped = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let mut conn = PgConnection::connect(&server_url).await?;
+                sqlx::query(&format!(
+                    r#"drop database if exists "{database}" with (force)"#
+                ))
+                .execute(&mut conn)
+                .await?;
+                conn.close().await
+            })
+        })
+        .join();
+        if let Ok(Err(err)) = dropped {
+            eprintln!("failed to drop {}: {err}", self.database);
+        }
+    }
+}
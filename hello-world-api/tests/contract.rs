@@ -0,0 +1,242 @@
+//! Golden files of the JSON contract: the OpenAPI document, which describes
+//! every endpoint, and the responses of a run through the endpoints, are
+//! compared with the snapshots in `tests/snapshots`. A change to any of them
+//! fails the test until the new snapshot is approved, see the README.
+
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestResponse};
+use insta::{assert_json_snapshot, dynamic_redaction, internals::Content, Settings};
+use serde_json::{json, Value};
+
+/// The status, content type and body of `response`, which is what a client
+/// relies on.
+fn contract(response: &TestResponse) -> Value {
+    json!({
+        "status": response.status.as_u16(),
+        "content_type": response.header("content-type"),
+        "body": response.json(),
+    })
+}
+
+/// Replaces what changes from one run to the next: ids, times and tokens.
+/// Those that are null are kept, being null is part of the contract.
+fn redactions() -> Settings {
+    let mut settings = Settings::clone_current();
+    settings.set_sort_maps(true);
+    for field in [
+        "id",
+        "user_id",
+        "list_id",
+        "todo_id",
+        "tag_id",
+        "owner_id",
+        "access_token",
+        "token",
+        "key",
+        "prefix",
+        "etag",
+        "url",
+        "instance",
+        "request_id",
+        "sub",
+        "iat",
+        "exp",
+        "created_at",
+        "updated_at",
+        "completed_at",
+        "started_at",
+        "expires_at",
+        "last_used_at",
+        "deleted_at",
+        "archived_at",
+        "next_cursor",
+    ] {
+        settings.add_redaction(
+            &format!(".**.{field}"),
+            dynamic_redaction(move |value, _| match value {
+                Content::None | Content::Unit => value,
+                _ => Content::from(format!("[{field}]")),
+            }),
+        );
+    }
+    settings
+}
+
+#[tokio::test]
+async fn openapi_document() {
+    let app = TestApp::new().await;
+    let response = app
+        .request(Method::GET, "/api-docs/openapi.json", None, None, &[])
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    // the version is bumped on every release, not with the contract
+    assert_json_snapshot!(response.json(), {".info.version" => "[version]"});
+}
+
+#[tokio::test]
+async fn auth_responses() {
+    let app = TestApp::new().await;
+    let _settings = redactions().bind_to_scope();
+    let credentials = json!({"username": "alice", "password": "a password"});
+    let register = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/register",
+            None,
+            Some(credentials.clone()),
+            &[],
+        )
+        .await;
+    assert_json_snapshot!("register", contract(&register));
+    let login = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(credentials),
+            &[],
+        )
+        .await;
+    assert_json_snapshot!("login", contract(&login));
+    let wrong = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(json!({"username": "alice", "password": "wrong"})),
+            &[],
+        )
+        .await;
+    assert_json_snapshot!("login_wrong_password", contract(&wrong));
+
+    let token = login.json()["access_token"].as_str().unwrap().to_owned();
+    let introspect = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/introspect",
+            Some(&token),
+            Some(json!({"token": token})),
+            &[],
+        )
+        .await;
+    assert_json_snapshot!("introspect", contract(&introspect));
+    let key = app
+        .post("/api/v1/auth/api-keys", &token, json!({"name": "ci"}))
+        .await;
+    assert_json_snapshot!("api_key_created", contract(&key));
+    let keys = app.get("/api/v1/auth/api-keys", &token).await;
+    assert_json_snapshot!("api_keys", contract(&keys));
+    let anonymous = app
+        .request(Method::GET, "/api/v1/todos", None, None, &[])
+        .await;
+    assert_json_snapshot!("unauthorized", contract(&anonymous));
+}
+
+#[tokio::test]
+async fn todo_responses() {
+    let app = TestApp::new().await;
+    let _settings = redactions().bind_to_scope();
+    let token = app.user("alice").await;
+    let created = app
+        .post(
+            "/api/v1/todos",
+            &token,
+            json!({"text": "Buy milk", "priority": "high", "due_at": "2030-01-01T09:00:00Z"}),
+        )
+        .await;
+    assert_json_snapshot!("todo_created", contract(&created));
+    let path = format!("/api/v1/todos/{}", created.json()["id"].as_str().unwrap());
+    assert_json_snapshot!("todo", contract(&app.get(&path, &token).await));
+    assert_json_snapshot!("todos", contract(&app.get("/api/v1/todos", &token).await));
+    let patch = |if_match: &'static [(&'static str, &'static str)]| {
+        app.request(
+            Method::PATCH,
+            &path,
+            Some(&token),
+            Some(json!({"is_done": true})),
+            if_match,
+        )
+    };
+    assert_json_snapshot!("todo_patch_without_etag", contract(&patch(&[]).await));
+    let patched = patch(&[("if-match", "\"1\"")]).await;
+    assert_json_snapshot!("todo_patched", contract(&patched));
+    let invalid = app.post("/api/v1/todos", &token, json!({"text": ""})).await;
+    assert_json_snapshot!("todo_invalid", contract(&invalid));
+    let quick = app
+        .post(
+            "/api/v1/todos/quick",
+            &token,
+            json!({"text": "Call mom !high #family"}),
+        )
+        .await;
+    assert_json_snapshot!("todo_quick", contract(&quick));
+    let checklist = app
+        .post(
+            &format!("{path}/checklist"),
+            &token,
+            json!({"text": "Oat milk"}),
+        )
+        .await;
+    assert_json_snapshot!("checklist_item", contract(&checklist));
+
+    let deleted = app.delete(&path, &token).await;
+    assert_eq!(deleted.status, StatusCode::NO_CONTENT);
+    assert_json_snapshot!("todo_not_found", contract(&app.get(&path, &token).await));
+    assert_json_snapshot!(
+        "trash",
+        contract(&app.get("/api/v1/todos/trash", &token).await)
+    );
+}
+
+#[tokio::test]
+async fn account_responses() {
+    let app = TestApp::new().await;
+    let _settings = redactions().bind_to_scope();
+    let admin = app.admin().await;
+    let token = app.user("alice").await;
+    app.todo(&token, "Water the plants").await;
+    let list = app
+        .post("/api/v1/lists", &token, json!({"name": "Home"}))
+        .await;
+    assert_json_snapshot!("list_created", contract(&list));
+    assert_json_snapshot!("lists", contract(&app.get("/api/v1/lists", &token).await));
+    assert_json_snapshot!("tags", contract(&app.get("/api/v1/tags", &token).await));
+    assert_json_snapshot!(
+        "me_summary",
+        contract(&app.get("/api/v1/me/summary", &token).await)
+    );
+    assert_json_snapshot!(
+        "archived",
+        contract(&app.get("/api/v1/todos/archived", &token).await)
+    );
+    assert_json_snapshot!(
+        "recurrence_preview",
+        contract(
+            &app.get(
+                "/api/v1/recurrence/preview?rule=FREQ%3DWEEKLY&count=2&from=2030-01-07T09:00:00Z",
+                &token,
+            )
+            .await
+        )
+    );
+    assert_json_snapshot!(
+        "forbidden",
+        contract(&app.get("/admin/stats", &token).await)
+    );
+    // the days are the last 30 up to today
+    assert_json_snapshot!(
+        "admin_stats",
+        contract(&app.get("/admin/stats", &admin).await),
+        {".body.created_per_day[].day" => "[day]"}
+    );
+    let workspace = app
+        .post(
+            "/api/v1/workspaces",
+            &token,
+            json!({"name": "Family", "slug": "family"}),
+        )
+        .await;
+    assert_json_snapshot!("workspace_created", contract(&workspace));
+}
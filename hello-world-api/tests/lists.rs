@@ -0,0 +1,221 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::json;
+
+#[tokio::test]
+async fn lists_crud() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let response = app
+        .post("/api/v1/lists", &token, json!({"name": "Groceries"}))
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let list = response.json();
+    assert_eq!(list["open_todos"], 0);
+    let path = format!("/api/v1/lists/{}", list["id"].as_str().unwrap());
+
+    let response = app
+        .post("/api/v1/lists", &token, json!({"name": "Groceries"}))
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    let response = app.post("/api/v1/lists", &token, json!({"name": ""})).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = app.put(&path, &token, json!({"name": "Food"})).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["name"], "Food");
+    let response = app.get("/api/v1/lists", &token).await;
+    assert_eq!(response.json()[0]["name"], "Food");
+
+    // lists are their owner's alone
+    let other = app.user("bob").await;
+    assert_eq!(app.get(&path, &other).await.status, StatusCode::NOT_FOUND);
+
+    assert_eq!(
+        app.delete(&path, &token).await.status,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(app.get(&path, &token).await.status, StatusCode::NOT_FOUND);
+    let response = app.put(&path, &token, json!({"name": "Food"})).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn list_todos() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let list = app
+        .post("/api/v1/lists", &token, json!({"name": "Groceries"}))
+        .await
+        .json();
+    let list_id = list["id"].as_str().unwrap();
+    app.post(
+        "/api/v1/todos",
+        &token,
+        json!({"text": "Buy milk", "list_id": list_id}),
+    )
+    .await;
+    app.todo(&token, "Pay rent").await;
+
+    let response = app
+        .get(&format!("/api/v1/lists/{list_id}/todos"), &token)
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["total"], 1);
+    assert_eq!(response.json()["items"][0]["text"], "Buy milk");
+    let response = app.get(&format!("/api/v1/lists/{list_id}"), &token).await;
+    assert_eq!(response.json()["open_todos"], 1);
+
+    let response = app
+        .get(
+            &format!("/api/v1/lists/{}/todos", uuid::Uuid::new_v4()),
+            &token,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn tags() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let todo = app.todo(&token, "Buy milk").await;
+    let todo_id = todo["id"].as_str().unwrap();
+    let response = app
+        .post("/api/v1/tags", &token, json!({"name": "errands"}))
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let tag_id = response.json()["id"].as_str().unwrap().to_owned();
+    let response = app
+        .post("/api/v1/tags", &token, json!({"name": "errands"}))
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(
+        app.get("/api/v1/tags", &token).await.json()[0]["name"],
+        "errands"
+    );
+
+    let tagging = format!("/api/v1/todos/{todo_id}/tags/{tag_id}");
+    assert_eq!(
+        app.put(&tagging, &token, json!(null)).await.status,
+        StatusCode::NO_CONTENT
+    );
+    let page = app.get("/api/v1/todos?tag=errands", &token).await.json();
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["items"][0]["tags"][0]["name"], "errands");
+
+    assert_eq!(
+        app.delete(&tagging, &token).await.status,
+        StatusCode::NO_CONTENT
+    );
+    let page = app.get("/api/v1/todos?tag=errands", &token).await.json();
+    assert_eq!(page["total"], 0);
+
+    let response = app
+        .put(
+            &format!("/api/v1/todos/{todo_id}/tags/{}", uuid::Uuid::new_v4()),
+            &token,
+            json!(null),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn tag_stats() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let mut tag_ids = Vec::new();
+    for name in ["home", "errands"] {
+        let tag = app
+            .post("/api/v1/tags", &token, json!({"name": name}))
+            .await;
+        tag_ids.push(tag.json()["id"].as_str().unwrap().to_owned());
+    }
+    let mut todo_paths = Vec::new();
+    for text in ["Buy milk", "Post a letter", "Walk the dog"] {
+        let todo = app.todo(&token, text).await;
+        let path = format!("/api/v1/todos/{}", todo["id"].as_str().unwrap());
+        let tagging = format!("{path}/tags/{}", tag_ids[1]);
+        app.put(&tagging, &token, json!(null)).await;
+        todo_paths.push(path);
+    }
+    let done = app
+        .request(
+            Method::PATCH,
+            &todo_paths[1],
+            Some(&token),
+            Some(json!({"is_done": true})),
+            &[("if-match", "*")],
+        )
+        .await;
+    assert_eq!(done.status, StatusCode::OK, "{}", done.text());
+    // deleted todos aren't counted
+    app.delete(&todo_paths[2], &token).await;
+
+    let response = app.get("/api/v1/tags/stats", &token).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let stats = response.json();
+    assert_eq!(stats[0]["name"], "errands");
+    assert_eq!(stats[0]["open"], 1);
+    assert_eq!(stats[0]["done"], 1);
+    assert!(stats[0]["last_activity_at"].is_string());
+    assert_eq!(stats[1]["name"], "home");
+    assert_eq!(stats[1]["open"], 0);
+    assert_eq!(stats[1]["done"], 0);
+    assert_eq!(stats[1]["last_activity_at"], json!(null));
+}
+
+#[tokio::test]
+async fn bulk_tags() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let mut ids = Vec::new();
+    for text in ["Buy milk", "Buy bread", "Walk the dog"] {
+        ids.push(app.todo(&token, text).await["id"].clone());
+    }
+    let response = app
+        .post(
+            "/api/v1/todos/bulk/tags",
+            &token,
+            json!({"add": ["errands", "home"], "ids": [ids[0], ids[2]]}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json(), json!({"matched": 2, "affected": 2}));
+
+    // only the todos that had the tag change
+    let response = app
+        .post(
+            "/api/v1/todos/bulk/tags",
+            &token,
+            json!({"add": ["shopping"], "remove": ["errands"], "filter": {"q": "buy"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json(), json!({"matched": 2, "affected": 2}));
+    let page = app.get("/api/v1/todos?tag=errands", &token).await.json();
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["items"][0]["text"], "Walk the dog");
+    let page = app.get("/api/v1/todos?tag=shopping", &token).await.json();
+    assert_eq!(page["total"], 2);
+
+    let response = app
+        .post(
+            "/api/v1/todos/bulk/tags",
+            &token,
+            json!({"add": ["home"], "ids": [ids[0]], "filter": {}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let response = app
+        .post(
+            "/api/v1/todos/bulk/tags",
+            &token,
+            json!({"add": ["home"], "remove": ["home"], "ids": [ids[0]]}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use common::TestApp;
+use hello_world_api::repository::migrate::{self, State};
+
+async fn state(app: &TestApp, version: i64) -> State {
+    let mut conn = app.pool.acquire().await.unwrap();
+    migrate::status(&mut conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|status| status.version == version)
+        .expect("no such migration")
+        .state
+}
+
+#[tokio::test]
+async fn the_latest_migration_is_reverted_and_applied_again() {
+    let app = TestApp::new().await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let latest = migrate::status(&mut conn)
+        .await
+        .unwrap()
+        .last()
+        .unwrap()
+        .version;
+    assert!(matches!(state(&app, latest).await, State::Applied(_)));
+
+    let reverted = migrate::down(&mut conn, 1).await.unwrap();
+    assert_eq!(reverted, [latest]);
+    assert_eq!(state(&app, latest).await, State::Pending);
+
+    migrate::up(&mut conn).await.unwrap();
+    assert!(matches!(state(&app, latest).await, State::Applied(_)));
+}
+
+#[tokio::test]
+async fn nothing_is_reverted_past_a_one_way_migration() {
+    let app = TestApp::new().await;
+    let mut conn = app.pool.acquire().await.unwrap();
+    let statuses = migrate::status(&mut conn).await.unwrap();
+
+    let err = migrate::down(&mut conn, statuses.len()).await.unwrap_err();
+    assert!(err.to_string().contains("no down script"), "{err}");
+    let latest = statuses.last().unwrap().version;
+    assert!(matches!(state(&app, latest).await, State::Applied(_)));
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::{json, Value};
+
+/// The events recorded for the todos of `username`, oldest first.
+async fn recorded(app: &TestApp, username: &str) -> Vec<Value> {
+    sqlx::query_scalar::<_, Value>(
+        r#"select o.payload::jsonb from "todo_outbox" o join "user" u using (user_id)
+        where u.username = $1
+        order by o.id"#,
+    )
+    .bind(username)
+    .fetch_all(&app.pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn changes_record_their_events() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    app.user("bob").await;
+    let todo = app.todo(&alice, "Buy milk").await;
+    let id = todo["id"].as_str().unwrap();
+    let path = format!("/api/v1/todos/{id}");
+
+    let response = app
+        .request(
+            Method::PATCH,
+            &path,
+            Some(&alice),
+            Some(json!({"text": "Buy oat milk"})),
+            &[("if-match", "*")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(
+        app.delete(&path, &alice).await.status,
+        StatusCode::NO_CONTENT
+    );
+
+    let events = recorded(&app, "alice").await;
+    let kinds: Vec<_> = events.iter().map(|event| event["type"].clone()).collect();
+    assert_eq!(
+        kinds,
+        [json!("created"), json!("updated"), json!("deleted")]
+    );
+    assert!(events.iter().all(|event| event["id"] == id));
+    assert_eq!(events[0]["todo"]["text"], "Buy milk");
+    assert_eq!(events[1]["todo"]["text"], "Buy oat milk");
+    assert!(events[2].get("todo").is_none());
+    assert!(recorded(&app, "bob").await.is_empty());
+}
+
+#[tokio::test]
+async fn failed_changes_record_nothing() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let todo = app.todo(&alice, "Buy milk").await;
+    let path = format!("/api/v1/todos/{}", todo["id"].as_str().unwrap());
+
+    assert_eq!(app.delete(&path, &bob).await.status, StatusCode::NOT_FOUND);
+    let response = app.post("/api/v1/todos", &alice, json!({"text": ""})).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(recorded(&app, "alice").await.len(), 1);
+    assert!(recorded(&app, "bob").await.is_empty());
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use hello_world_api::fixtures::{self, Seed};
+use serde_json::json;
+
+const SEED: Seed = Seed {
+    users: 2,
+    todos_per_user: 30,
+    rng_seed: 7,
+};
+
+async fn texts(app: &TestApp) -> Vec<String> {
+    sqlx::query_scalar("select todo_text from todo order by todo_text")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn seeded_users_can_log_in() {
+    let app = TestApp::new().await;
+    let seeded = fixtures::seed(&app.pool, SEED).await.unwrap();
+    assert_eq!((seeded.users, seeded.todos), (2, 60));
+
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(json!({"username": "bob", "password": fixtures::PASSWORD})),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let token = response.json()["access_token"].as_str().unwrap().to_owned();
+    let page = app.get("/api/v1/todos?limit=1", &token).await.json();
+    assert_eq!(page["total"], 30);
+    assert!(!app.get("/api/v1/lists", &token).await.json()[0]["name"].is_null());
+}
+
+#[tokio::test]
+async fn seeding_again_skips_existing_users() {
+    let app = TestApp::new().await;
+    fixtures::seed(&app.pool, SEED).await.unwrap();
+    let seeded = fixtures::seed(&app.pool, Seed { users: 3, ..SEED })
+        .await
+        .unwrap();
+    assert_eq!(seeded.existing_users, 2);
+    assert_eq!((seeded.users, seeded.todos), (1, 30));
+}
+
+#[tokio::test]
+async fn the_same_seed_gives_the_same_todos() {
+    let first = TestApp::new().await;
+    let second = TestApp::new().await;
+    fixtures::seed(&first.pool, SEED).await.unwrap();
+    fixtures::seed(&second.pool, SEED).await.unwrap();
+    assert_eq!(texts(&first).await, texts(&second).await);
+}
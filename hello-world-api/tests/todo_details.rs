@@ -0,0 +1,389 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::{json, Value};
+
+async fn todo_id(app: &TestApp, token: &str, text: &str) -> String {
+    app.todo(token, text).await["id"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+/// Uploads `content` to `path` as the form's `file` part.
+async fn upload(
+    app: &TestApp,
+    token: &str,
+    path: &str,
+    filename: &str,
+    content_type: &str,
+    content: &str,
+) -> common::TestResponse {
+    let body = format!(
+        "--XYZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\
+        Content-Type: {content_type}\r\n\r\n{content}\r\n--XYZ--\r\n"
+    );
+    app.request_body(
+        Method::POST,
+        path,
+        Some(token),
+        "multipart/form-data; boundary=XYZ",
+        &body,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn checklist() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let id = todo_id(&app, &token, "Pack for the trip").await;
+    let checklist = format!("/api/v1/todos/{id}/checklist");
+
+    let mut items = Vec::new();
+    for text in ["Passport", "Charger"] {
+        let response = app.post(&checklist, &token, json!({"text": text})).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        let view = response.json();
+        let added = view["items"].as_array().unwrap().last().unwrap();
+        items.push(added["id"].as_str().unwrap().to_owned());
+    }
+    let response = app
+        .put(
+            &format!("{checklist}/{}", items[0]),
+            &token,
+            json!({"is_done": true}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+
+    let view = app.get(&checklist, &token).await.json();
+    assert_eq!(view["completed_count"], 1);
+    assert_eq!(view["items"][0]["text"], "Passport");
+    let todo = app.get(&format!("/api/v1/todos/{id}"), &token).await.json();
+    assert_eq!(todo["completion_percent"], 50);
+
+    let response = app
+        .put(&checklist, &token, json!({"order": [items[1], items[0]]}))
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let view = app.get(&checklist, &token).await.json();
+    assert_eq!(view["items"][0]["text"], "Charger");
+
+    let response = app
+        .delete(&format!("{checklist}/{}", items[1]), &token)
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["items"].as_array().unwrap().len(), 1);
+    let response = app
+        .delete(&format!("{checklist}/{}", items[1]), &token)
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app
+        .get(
+            &format!("/api/v1/todos/{}/checklist", uuid::Uuid::new_v4()),
+            &token,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn links() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let cause = todo_id(&app, &token, "Fix the leak").await;
+    let effect = todo_id(&app, &token, "Dry the floor").await;
+
+    let response = app
+        .post(
+            &format!("/api/v1/todos/{effect}/links"),
+            &token,
+            json!({"kind": "caused_by", "todo_id": cause}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let link = response.json();
+    assert_eq!(link["direction"], "outgoing");
+
+    let todo = app
+        .get(&format!("/api/v1/todos/{cause}"), &token)
+        .await
+        .json();
+    assert_eq!(todo["links"][0]["todo_id"], effect);
+    assert_eq!(todo["links"][0]["direction"], "incoming");
+
+    // the same link again, and one closing a cycle
+    let response = app
+        .post(
+            &format!("/api/v1/todos/{effect}/links"),
+            &token,
+            json!({"kind": "caused_by", "todo_id": cause}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    let response = app
+        .post(
+            &format!("/api/v1/todos/{cause}/links"),
+            &token,
+            json!({"kind": "caused_by", "todo_id": effect}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    let response = app
+        .post(
+            &format!("/api/v1/todos/{cause}/links"),
+            &token,
+            json!({"kind": "relates_to", "todo_id": cause}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let path = format!(
+        "/api/v1/todos/{effect}/links/{}",
+        link["id"].as_str().unwrap()
+    );
+    assert_eq!(
+        app.delete(&path, &token).await.status,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        app.delete(&path, &token).await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn history() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let id = todo_id(&app, &token, "Buy milk").await;
+    app.request(
+        Method::PATCH,
+        &format!("/api/v1/todos/{id}"),
+        Some(&token),
+        Some(json!({"text": "Buy oat milk"})),
+        &[("if-match", "*")],
+    )
+    .await;
+
+    let response = app
+        .get(&format!("/api/v1/todos/{id}/history"), &token)
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let actions: Vec<_> = response
+        .json()
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["action"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(actions, ["updated", "created"]);
+
+    let response = app
+        .get(&format!("/api/v1/todos/{id}/history?limit=0"), &token)
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn start_date() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let id = todo_id(&app, &token, "Plan the trip").await;
+    let path = format!("/api/v1/todos/{id}/start");
+    let later = (chrono::Utc::now() + chrono::Duration::days(7)).to_rfc3339();
+
+    let response = app.put(&path, &token, json!({"start_at": later})).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let page = app.get("/api/v1/todos?started=false", &token).await.json();
+    assert_eq!(page["total"], 1);
+
+    assert_eq!(
+        app.delete(&path, &token).await.status,
+        StatusCode::NO_CONTENT
+    );
+    let page = app.get("/api/v1/todos?started=true", &token).await.json();
+    assert_eq!(page["total"], 1);
+}
+
+#[tokio::test]
+async fn location_and_nearby() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let id = todo_id(&app, &token, "Return the books").await;
+    let path = format!("/api/v1/todos/{id}/location");
+
+    let response = app
+        .put(&path, &token, json!({"latitude": 100.0, "longitude": 0.0}))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = app
+        .put(
+            &path,
+            &token,
+            json!({"latitude": 52.52, "longitude": 13.405, "radius_m": 50.0}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+
+    let nearby = app
+        .get("/api/v1/todos/nearby?lat=52.53&lon=13.41&km=5", &token)
+        .await;
+    assert_eq!(nearby.status, StatusCode::OK, "{}", nearby.text());
+    assert_eq!(nearby.json()[0]["id"], id.as_str());
+    let far = app
+        .get("/api/v1/todos/nearby?lat=48.85&lon=2.35&km=5", &token)
+        .await
+        .json();
+    assert_eq!(far, json!([]));
+
+    assert_eq!(
+        app.delete(&path, &token).await.status,
+        StatusCode::NO_CONTENT
+    );
+}
+
+#[tokio::test]
+async fn share_links() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let id = todo_id(&app, &token, "Buy milk").await;
+    let response = app
+        .post(&format!("/api/v1/todos/{id}/share-link"), &token, json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let link = response.json();
+    let url = link["url"].as_str().unwrap();
+    assert!(url.starts_with("/api/v1/shared/"), "{url}");
+
+    // no token needed to view
+    let response = app.request(Method::GET, url, None, None, &[]).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.text().contains("Buy milk"));
+
+    let response = app
+        .post(
+            &format!("/api/v1/todos/{id}/share-link"),
+            &token,
+            json!({"expires_at": "2000-01-01T00:00:00Z"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    let revoke = format!(
+        "/api/v1/todos/{id}/share-link/{}",
+        link["id"].as_str().unwrap()
+    );
+    assert_eq!(
+        app.delete(&revoke, &token).await.status,
+        StatusCode::NO_CONTENT
+    );
+    let response = app.request(Method::GET, url, None, None, &[]).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let unknown = format!("/api/v1/todos/{id}/share-link/{}", uuid::Uuid::new_v4());
+    assert_eq!(
+        app.delete(&unknown, &token).await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn breakdown_needs_an_assistant() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let id = todo_id(&app, &token, "Plan the trip").await;
+    let response = app
+        .post(
+            &format!("/api/v1/todos/{id}/breakdown"),
+            &token,
+            Value::Null,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn attachments() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let id = todo_id(&app, &token, "File the expenses").await;
+    let attachments = format!("/api/v1/todos/{id}/attachments");
+
+    let response = upload(
+        &app,
+        &token,
+        &attachments,
+        "../receipt.pdf",
+        "application/pdf",
+        "%PDF-1.4 a receipt",
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let attachment = response.json();
+    assert_eq!(attachment["filename"], "receipt.pdf");
+    assert_eq!(attachment["content_type"], "application/pdf");
+    assert_eq!(attachment["size"], 18);
+    let path = format!("{attachments}/{}", attachment["id"].as_str().unwrap());
+
+    let response = app.get(&path, &token).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "%PDF-1.4 a receipt");
+    assert_eq!(response.header("content-type"), Some("application/pdf"));
+    assert!(response
+        .header("content-disposition")
+        .unwrap()
+        .starts_with("attachment; filename=\"receipt.pdf\""));
+    let list = app.get(&attachments, &token).await.json();
+    assert_eq!(list.as_array().unwrap().len(), 1);
+
+    // only some types, and only content of the declared type
+    let response = upload(&app, &token, &attachments, "page.html", "text/html", "<p>").await;
+    assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let response = upload(&app, &token, &attachments, "cat.png", "image/png", "<p>").await;
+    assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let response = upload(&app, &token, &attachments, "empty.txt", "text/plain", "").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    // other users' todos have no attachments to them
+    let other = app.user("bob").await;
+    assert_eq!(app.get(&path, &other).await.status, StatusCode::NOT_FOUND);
+    let response = upload(&app, &other, &attachments, "a.txt", "text/plain", "hi").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    assert_eq!(
+        app.delete(&path, &token).await.status,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(app.get(&path, &token).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(app.get(&attachments, &token).await.json(), json!([]));
+}
+
+#[tokio::test]
+async fn concurrent_uploads_keep_to_the_limit() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let id = todo_id(&app, &token, "File the expenses").await;
+    let attachments = format!("/api/v1/todos/{id}/attachments");
+    let filenames: Vec<_> = (0..25).map(|i| format!("receipt-{i}.txt")).collect();
+    let uploads = filenames
+        .iter()
+        .map(|filename| upload(&app, &token, &attachments, filename, "text/plain", "paid"));
+    let statuses: Vec<_> = futures_util::future::join_all(uploads)
+        .await
+        .into_iter()
+        .map(|response| response.status)
+        .collect();
+    let created = statuses
+        .iter()
+        .filter(|&&status| status == StatusCode::CREATED)
+        .count();
+    let refused = statuses
+        .iter()
+        .filter(|&&status| status == StatusCode::CONFLICT)
+        .count();
+    assert_eq!((created, refused), (20, 5), "{statuses:?}");
+    let list = app.get(&attachments, &token).await.json();
+    assert_eq!(list.as_array().unwrap().len(), 20);
+}
@@ -0,0 +1,211 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestResponse};
+use serde_json::{json, Value};
+
+/// Writes to the todo at `path` whatever its version.
+async fn write(
+    app: &TestApp,
+    method: Method,
+    path: &str,
+    token: &str,
+    body: Value,
+) -> TestResponse {
+    app.request(method, path, Some(token), Some(body), &[("if-match", "*")])
+        .await
+}
+
+async fn patch(app: &TestApp, path: &str, token: &str, body: Value) -> TestResponse {
+    write(app, Method::PATCH, path, token, body).await
+}
+
+fn texts(page: &Value) -> Vec<&str> {
+    page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|todo| todo["text"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn viewers_read_and_editors_also_change() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let carol = app.user("carol").await;
+    let todo = app.todo(&alice, "Plan the offsite").await;
+    let path = format!("/api/v1/todos/{}", todo["id"].as_str().unwrap());
+    app.todo(&bob, "Bob's own").await;
+
+    let response = app
+        .put(
+            &format!("{path}/shares/bob"),
+            &alice,
+            json!({"permission": "viewer"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["permission"], "viewer");
+
+    // listed among the recipient's own, telling whose it is
+    let page = app.get("/api/v1/todos", &bob).await.json();
+    assert_eq!(texts(&page), ["Plan the offsite", "Bob's own"]);
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["items"][0]["shared_by"], "alice");
+    assert!(page["items"][1].get("shared_by").is_none());
+    let response = app.get(&path, &bob).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["shared_by"], "alice");
+    assert!(app
+        .get(&path, &alice)
+        .await
+        .json()
+        .get("shared_by")
+        .is_none());
+
+    let response = patch(&app, &path, &bob, json!({"text": "Cancel the offsite"})).await;
+    assert_eq!(
+        response.status,
+        StatusCode::FORBIDDEN,
+        "{}",
+        response.text()
+    );
+    let response = write(&app, Method::PUT, &path, &bob, json!({"is_done": true})).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    // others still can't tell it exists
+    assert_eq!(app.get(&path, &carol).await.status, StatusCode::NOT_FOUND);
+    let response = patch(&app, &path, &carol, json!({"text": "Mine"})).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(app.get("/api/v1/todos", &carol).await.json()["total"], 0);
+
+    app.put(
+        &format!("{path}/shares/bob"),
+        &alice,
+        json!({"permission": "editor"}),
+    )
+    .await;
+    let response = patch(&app, &path, &bob, json!({"text": "Plan the retreat"})).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["shared_by"], "alice");
+    assert_eq!(
+        app.get(&path, &alice).await.json()["text"],
+        "Plan the retreat"
+    );
+
+    // but only the owner deletes it or shares it further
+    let response = app.delete(&path, &bob).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app
+        .put(
+            &format!("{path}/shares/carol"),
+            &bob,
+            json!({"permission": "viewer"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    let shares = app.get(&format!("{path}/shares"), &alice).await.json();
+    assert_eq!(shares.as_array().unwrap().len(), 1);
+    assert_eq!(shares[0]["username"], "bob");
+    assert_eq!(shares[0]["permission"], "editor");
+
+    // revoked, it's gone for the recipient
+    let response = app.delete(&format!("{path}/shares/bob"), &alice).await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    assert_eq!(app.get(&path, &bob).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        texts(&app.get("/api/v1/todos", &bob).await.json()),
+        ["Bob's own"]
+    );
+    let response = app.delete(&format!("{path}/shares/bob"), &alice).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn sharing_a_list_shares_its_todos() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let list = app
+        .post("/api/v1/lists", &alice, json!({"name": "Groceries"}))
+        .await
+        .json();
+    let list_id = list["id"].as_str().unwrap();
+    let response = app
+        .post(
+            "/api/v1/todos",
+            &alice,
+            json!({"text": "Milk", "list_id": list_id}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    app.todo(&alice, "Not on the list").await;
+
+    let response = app
+        .put(
+            &format!("/api/v1/lists/{list_id}/shares/bob"),
+            &alice,
+            json!({"permission": "editor"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let page = app.get("/api/v1/todos", &bob).await.json();
+    assert_eq!(texts(&page), ["Milk"]);
+    let milk = format!("/api/v1/todos/{}", page["items"][0]["id"].as_str().unwrap());
+    let response = write(&app, Method::PUT, &milk, &bob, json!({"is_done": true})).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+
+    // todos moved out of the list aren't shared any longer
+    let response = patch(&app, &milk, &alice, json!({"list_id": null})).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(app.get(&milk, &bob).await.status, StatusCode::NOT_FOUND);
+
+    let shares = app
+        .get(&format!("/api/v1/lists/{list_id}/shares"), &alice)
+        .await
+        .json();
+    assert_eq!(shares[0]["username"], "bob");
+    assert_eq!(
+        app.get(&format!("/api/v1/lists/{list_id}/shares"), &bob)
+            .await
+            .status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn shares_need_another_existing_user() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let todo = app.todo(&alice, "Plan the offsite").await;
+    let shares = format!("/api/v1/todos/{}/shares", todo["id"].as_str().unwrap());
+
+    let response = app
+        .put(
+            &format!("{shares}/alice"),
+            &alice,
+            json!({"permission": "viewer"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let response = app
+        .put(
+            &format!("{shares}/nobody"),
+            &alice,
+            json!({"permission": "viewer"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app
+        .put(
+            &format!("{shares}/nobody"),
+            &alice,
+            json!({"permission": "owner"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(app.get(&shares, &alice).await.json(), json!([]));
+}
@@ -0,0 +1,980 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::{json, Value};
+
+fn id(todo: &Value) -> &str {
+    todo["id"].as_str().expect("todo without id")
+}
+
+fn texts(page: &Value) -> Vec<&str> {
+    page["items"]
+        .as_array()
+        .expect("page without items")
+        .iter()
+        .map(|todo| todo["text"].as_str().unwrap())
+        .collect()
+}
+
+async fn completed_count(app: &TestApp, token: &str) -> i64 {
+    let summary = app.get("/api/v1/me/summary", token).await.json();
+    summary["completed_count"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn create_and_get_a_todo() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let response = app
+        .post(
+            "/api/v1/todos",
+            &token,
+            json!({"text": "Buy milk", "priority": "high"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    let todo = response.json();
+    assert_eq!(todo["text"], "Buy milk");
+    assert_eq!(todo["is_done"], false);
+    assert_eq!(todo["status"], "open");
+    assert_eq!(todo["priority"], "high");
+    assert_eq!(todo["etag"], "\"1\"");
+    assert_eq!(todo["created_at"], todo["updated_at"]);
+
+    let response = app
+        .get(&format!("/api/v1/todos/{}", id(&todo)), &token)
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("etag"), Some("\"1\""));
+    assert_eq!(response.json()["text"], "Buy milk");
+}
+
+#[tokio::test]
+async fn unknown_and_other_users_todos_are_not_found() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let todo = app.todo(&alice, "Buy milk").await;
+
+    let response = app
+        .get(&format!("/api/v1/todos/{}", uuid::Uuid::new_v4()), &alice)
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.json()["status"], 404);
+    let response = app.get(&format!("/api/v1/todos/{}", id(&todo)), &bob).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app
+        .delete(&format!("/api/v1/todos/{}", id(&todo)), &bob)
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.get("/api/v1/todos/not-an-id", &alice).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn open_todos_have_unique_texts() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    app.todo(&token, "Buy milk").await;
+    let response = app
+        .post("/api/v1/todos", &token, json!({"text": "Buy milk"}))
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/problem+json")
+    );
+
+    // other users have todos of their own
+    let other = app.user("bob").await;
+    app.todo(&other, "Buy milk").await;
+}
+
+#[tokio::test]
+async fn invalid_todos_are_rejected() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    for body in [
+        json!({"text": ""}),
+        json!({"text": "x".repeat(1001)}),
+        json!({"text": "Buy milk", "colour": "red"}),
+        json!({"text": "Buy milk", "list_id": uuid::Uuid::new_v4()}),
+        json!({"text": "Buy milk", "recurrence": "every blue moon"}),
+    ] {
+        let response = app.post("/api/v1/todos", &token, body.clone()).await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+    }
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_rejected() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let body = json!({"text": "x".repeat(3 * 1024 * 1024)});
+    let response = app.post("/api/v1/todos", &token, body).await;
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.json()["code"], "payload_too_large");
+}
+
+#[tokio::test]
+async fn updates_need_the_current_etag() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let todo = app.todo(&token, "Buy milk").await;
+    let path = format!("/api/v1/todos/{}", id(&todo));
+    let patch = Some(json!({"text": "Buy oat milk"}));
+
+    let response = app
+        .request(Method::PATCH, &path, Some(&token), patch.clone(), &[])
+        .await;
+    assert_eq!(response.status, StatusCode::PRECONDITION_REQUIRED);
+
+    let response = app
+        .request(
+            Method::PATCH,
+            &path,
+            Some(&token),
+            patch.clone(),
+            &[("if-match", "\"1\"")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["text"], "Buy oat milk");
+    assert_eq!(response.header("etag"), Some("\"2\""));
+
+    let response = app
+        .request(
+            Method::PATCH,
+            &path,
+            Some(&token),
+            patch,
+            &[("if-match", "\"1\"")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+
+    let response = app
+        .request(
+            Method::PUT,
+            &path,
+            Some(&token),
+            Some(json!({"is_done": true})),
+            &[("if-match", "\"2\"")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["is_done"], true);
+    assert_eq!(response.json()["status"], "done");
+}
+
+#[tokio::test]
+async fn unchanged_todos_and_pages_are_not_modified() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let todo = app.todo(&token, "Buy milk").await;
+    let path = format!("/api/v1/todos/{}", id(&todo));
+
+    let response = app.get(&path, &token).await;
+    assert_eq!(response.header("etag"), Some("\"1\""));
+    let last_modified = response.header("last-modified").unwrap().to_owned();
+    for condition in [
+        ("if-none-match", "\"1\""),
+        ("if-modified-since", last_modified.as_str()),
+    ] {
+        let response = app
+            .request(Method::GET, &path, Some(&token), None, &[condition])
+            .await;
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED, "{condition:?}");
+        assert!(response.body.is_empty());
+        assert_eq!(response.header("etag"), Some("\"1\""));
+    }
+
+    let response = app.get("/api/v1/todos", &token).await;
+    let etag = response.header("etag").unwrap().to_owned();
+    assert!(etag.starts_with("W/"), "{etag}");
+    assert_eq!(
+        response.header("last-modified"),
+        Some(last_modified.as_str())
+    );
+    let response = app
+        .request(
+            Method::GET,
+            "/api/v1/todos",
+            Some(&token),
+            None,
+            &[("if-none-match", etag.as_str())],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+
+    app.todo(&token, "Pay rent").await;
+    let response = app
+        .request(
+            Method::GET,
+            "/api/v1/todos",
+            Some(&token),
+            None,
+            &[("if-none-match", etag.as_str())],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(texts(&response.json()), ["Buy milk", "Pay rent"]);
+    let response = app
+        .request(
+            Method::GET,
+            &path,
+            Some(&token),
+            None,
+            &[("if-modified-since", "Sat, 01 Jan 2000 00:00:00 GMT")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn patch_needs_a_field_to_change() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let todo = app.todo(&token, "Buy milk").await;
+    let response = app
+        .request(
+            Method::PATCH,
+            &format!("/api/v1/todos/{}", id(&todo)),
+            Some(&token),
+            Some(json!({})),
+            &[("if-match", "*")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn deleted_todos_are_gone_unless_asked_for() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let todo = app.todo(&token, "Buy milk").await;
+    let path = format!("/api/v1/todos/{}", id(&todo));
+
+    assert_eq!(
+        app.delete(&path, &token).await.status,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(app.get(&path, &token).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        app.delete(&path, &token).await.status,
+        StatusCode::NOT_FOUND
+    );
+
+    let page = app.get("/api/v1/todos", &token).await.json();
+    assert_eq!(page["total"], 0);
+    let page = app
+        .get("/api/v1/todos?include_deleted=true", &token)
+        .await
+        .json();
+    assert_eq!(page["total"], 1);
+    assert!(page["items"][0]["deleted_at"].is_string());
+
+    // the text is free again
+    app.todo(&token, "Buy milk").await;
+}
+
+#[tokio::test]
+async fn deleted_todos_are_restored_from_the_trash() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let milk = app.todo(&alice, "Buy milk").await;
+    let bread = app.todo(&alice, "Buy bread").await;
+    let eggs = app.todo(&alice, "Buy eggs").await;
+    for todo in [&milk, &bread, &eggs] {
+        app.delete(&format!("/api/v1/todos/{}", id(todo)), &alice)
+            .await;
+    }
+    // deleted longer ago than todos are kept, as if about to be purged
+    sqlx::query(r#"update "todo" set deleted_at = now() - interval '31 days' where id = $1"#)
+        .bind(id(&eggs).parse::<uuid::Uuid>().unwrap())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let trash = app.get("/api/v1/todos/trash", &alice).await.json();
+    let texts: Vec<_> = trash
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|todo| todo["text"].as_str().unwrap())
+        .collect();
+    assert_eq!(texts, ["Buy bread", "Buy milk"]);
+    assert!(trash[0]["deleted_at"].is_string());
+    assert_eq!(app.get("/api/v1/todos/trash", &bob).await.json(), json!([]));
+    let response = app.get("/api/v1/todos/trash?limit=0", &alice).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    let restore = |todo: &Value| format!("/api/v1/todos/{}/restore", id(todo));
+    let response = app.post(&restore(&milk), &bob, json!({})).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.post(&restore(&eggs), &alice, json!({})).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.post(&restore(&milk), &alice, json!({})).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["text"], "Buy milk");
+    assert!(response.json()["deleted_at"].is_null());
+    let response = app.post(&restore(&milk), &alice, json!({})).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    let path = format!("/api/v1/todos/{}", id(&milk));
+    assert_eq!(app.get(&path, &alice).await.status, StatusCode::OK);
+    let history = app.get(&format!("{path}/history"), &alice).await.json();
+    assert_eq!(history[0]["action"], "restored");
+    let trash = app.get("/api/v1/todos/trash", &alice).await.json();
+    assert_eq!(trash.as_array().unwrap().len(), 1);
+
+    // a todo with the text was created meanwhile
+    app.todo(&alice, "Buy bread").await;
+    let response = app.post(&restore(&bread), &alice, json!({})).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn toggling_flips_done_and_keeps_the_completed_count() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let milk = app.todo(&alice, "Buy milk").await;
+    let bread = app.todo(&alice, "Buy bread").await;
+    let toggle = |todo: &Value| format!("/api/v1/todos/{}/toggle", id(todo));
+
+    let response = app.post(&toggle(&milk), &alice, json!({})).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["is_done"], true);
+    assert!(response.header("etag").is_some());
+    assert_eq!(completed_count(&app, &alice).await, 1);
+    let response = app.post(&toggle(&milk), &alice, json!({})).await;
+    assert_eq!(response.json()["is_done"], false);
+    assert_eq!(completed_count(&app, &alice).await, 0);
+
+    // whichever way todos are done or deleted
+    let ids = json!({"ids": [id(&milk), id(&bread)]});
+    app.post("/api/v1/todos/bulk-complete", &alice, ids).await;
+    assert_eq!(completed_count(&app, &alice).await, 2);
+    app.delete(&format!("/api/v1/todos/{}", id(&bread)), &alice)
+        .await;
+    assert_eq!(completed_count(&app, &alice).await, 1);
+
+    let bob = app.user("bob").await;
+    let response = app.post(&toggle(&milk), &bob, json!({})).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let summary = app.get("/api/v1/me/summary", &bob).await.json();
+    assert_eq!(summary["username"], "bob");
+    assert_eq!(summary["completed_count"], 0);
+}
+
+#[tokio::test]
+async fn done_todos_are_archived_once_old_enough() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let milk = app.todo(&alice, "Buy milk").await;
+    let rent = app.todo(&alice, "Pay rent").await;
+    let bread = app.todo(&alice, "Buy bread").await;
+    for todo in [&milk, &rent] {
+        app.request(
+            Method::PUT,
+            &format!("/api/v1/todos/{}", id(todo)),
+            Some(&alice),
+            Some(json!({"is_done": true})),
+            &[("if-match", "*")],
+        )
+        .await;
+    }
+    sqlx::query(r#"update "todo" set completed_at = now() - interval '40 days' where id = $1"#)
+        .bind(id(&rent).parse::<uuid::Uuid>().unwrap())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    // done for more than 30 days by default
+    let response = app
+        .post("/api/v1/todos/archive-completed", &alice, json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json(), json!({"archived": 1}));
+    let response = app
+        .post(
+            "/api/v1/todos/archive-completed",
+            &alice,
+            json!({"older_than_days": 0}),
+        )
+        .await;
+    assert_eq!(response.json(), json!({"archived": 1}));
+
+    let archived = app.get("/api/v1/todos/archived", &alice).await.json();
+    assert_eq!(archived["total"], 2);
+    assert_eq!(archived["items"][0]["text"], "Buy milk");
+    assert_eq!(archived["items"][1]["text"], "Pay rent");
+    let path = |todo: &Value| format!("/api/v1/todos/{}", id(todo));
+    assert_eq!(
+        app.get(&path(&rent), &alice).await.status,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(app.get(&path(&bread), &alice).await.status, StatusCode::OK);
+    let page = app
+        .get("/api/v1/todos/archived?limit=1&offset=1", &alice)
+        .await
+        .json();
+    assert_eq!(page["items"][0]["text"], "Pay rent");
+    let response = app.get("/api/v1/todos/archived?limit=0", &alice).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn archived_todos_stay_in_the_completed_count() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let toggle = |todo: &Value| format!("/api/v1/todos/{}/toggle", id(todo));
+    for text in ["Buy milk", "Pay rent", "Buy bread"] {
+        let todo = app.todo(&alice, text).await;
+        let response = app.post(&toggle(&todo), &alice, json!({})).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    }
+    assert_eq!(completed_count(&app, &alice).await, 3);
+
+    let response = app
+        .post(
+            "/api/v1/todos/archive-completed",
+            &alice,
+            json!({"older_than_days": 0}),
+        )
+        .await;
+    assert_eq!(response.json(), json!({"archived": 3}));
+    assert_eq!(completed_count(&app, &alice).await, 3);
+}
+
+#[tokio::test]
+async fn list_filters_sorts_and_pages() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    for (text, priority) in [
+        ("Buy milk", "low"),
+        ("Walk the dog", "urgent"),
+        ("Pay rent", "high"),
+    ] {
+        app.post(
+            "/api/v1/todos",
+            &token,
+            json!({"text": text, "priority": priority}),
+        )
+        .await;
+    }
+
+    let page = app.get("/api/v1/todos", &token).await.json();
+    assert_eq!(texts(&page), ["Buy milk", "Walk the dog", "Pay rent"]);
+    assert_eq!(page["total"], 3);
+
+    let page = app.get("/api/v1/todos?sort=-priority", &token).await.json();
+    assert_eq!(texts(&page), ["Walk the dog", "Pay rent", "Buy milk"]);
+    let page = app.get("/api/v1/todos?sort=text", &token).await.json();
+    assert_eq!(texts(&page), ["Buy milk", "Pay rent", "Walk the dog"]);
+    let page = app.get("/api/v1/todos?priority=high", &token).await.json();
+    assert_eq!(texts(&page), ["Pay rent"]);
+    let page = app.get("/api/v1/todos?q=MILK", &token).await.json();
+    assert_eq!(texts(&page), ["Buy milk"]);
+
+    let first = app.get("/api/v1/todos?limit=2", &token).await.json();
+    assert_eq!(texts(&first), ["Buy milk", "Walk the dog"]);
+    let cursor = first["next_cursor"].as_str().expect("no next_cursor");
+    let second = app
+        .get(&format!("/api/v1/todos?limit=2&after={cursor}"), &token)
+        .await
+        .json();
+    assert_eq!(texts(&second), ["Pay rent"]);
+    assert_eq!(second["next_cursor"], Value::Null);
+
+    for query in [
+        "sort=colour",
+        "after=nonsense",
+        "sort=text&after=MTIz",
+        "due_before=soon",
+    ] {
+        let response = app.get(&format!("/api/v1/todos?{query}"), &token).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn listings_fetch_ids_and_select_fields() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let milk = app.todo(&alice, "Buy milk").await;
+    app.todo(&alice, "Walk the dog").await;
+    let rent = app.todo(&alice, "Pay rent").await;
+    let bobs = app.todo(&app.user("bob").await, "Not alice's").await;
+
+    let ids = [id(&milk), id(&rent), id(&bobs)];
+    let page = app
+        .get(&format!("/api/v1/todos?ids={}", ids.join(",")), &alice)
+        .await
+        .json();
+    assert_eq!(texts(&page), ["Buy milk", "Pay rent"]);
+    assert_eq!(page["total"], 2);
+
+    let page = app
+        .get(
+            &format!("/api/v1/todos?ids={}&fields=id,text", ids[0]),
+            &alice,
+        )
+        .await
+        .json();
+    assert_eq!(
+        page["items"],
+        json!([{"id": milk["id"], "text": "Buy milk"}])
+    );
+    let page = app
+        .get("/api/v1/todos?fields=status,etag&limit=1", &alice)
+        .await
+        .json();
+    assert_eq!(
+        page["items"],
+        json!([{"status": "open", "etag": milk["etag"]}])
+    );
+
+    for query in ["ids=nonsense", "fields=colour"] {
+        let response = app.get(&format!("/api/v1/todos?{query}"), &alice).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{query}");
+    }
+    let response = app
+        .request(
+            Method::GET,
+            "/api/v1/todos?fields=id",
+            Some(&alice),
+            None,
+            &[("accept", "text/csv")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn todos_are_moved_within_the_users_order() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let mut todos = Vec::new();
+    for text in ["Buy milk", "Walk the dog", "Pay rent", "Call mum"] {
+        todos.push(app.todo(&alice, text).await);
+    }
+    let [milk, dog, rent, mum] = &todos[..] else {
+        unreachable!()
+    };
+    let move_to = |todo: &Value| format!("/api/v1/todos/{}/move", id(todo));
+
+    let response = app
+        .post(&move_to(mum), &alice, json!({"before": id(milk)}))
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["text"], "Call mum");
+    app.post(&move_to(milk), &alice, json!({"index": 2})).await;
+    app.post(&move_to(dog), &alice, json!({"after": id(rent)}))
+        .await;
+    let page = app.get("/api/v1/todos", &alice).await.json();
+    assert_eq!(
+        texts(&page),
+        ["Call mum", "Buy milk", "Pay rent", "Walk the dog"]
+    );
+    app.post(&move_to(mum), &alice, json!({"index": 10})).await;
+    let first = app.get("/api/v1/todos?limit=2", &alice).await.json();
+    assert_eq!(texts(&first), ["Buy milk", "Pay rent"]);
+    let cursor = first["next_cursor"].as_str().expect("no next_cursor");
+    let second = app
+        .get(&format!("/api/v1/todos?limit=2&after={cursor}"), &alice)
+        .await
+        .json();
+    assert_eq!(texts(&second), ["Walk the dog", "Call mum"]);
+
+    // each move halves the room between the rent and the todo after it,
+    // until the todos are spread out again
+    for round in 0..60 {
+        let todo = if round % 2 == 0 { milk } else { dog };
+        let response = app
+            .post(&move_to(todo), &alice, json!({"after": id(rent)}))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    }
+    let page = app.get("/api/v1/todos", &alice).await.json();
+    assert_eq!(
+        texts(&page),
+        ["Pay rent", "Walk the dog", "Buy milk", "Call mum"]
+    );
+    let positions: Vec<_> = page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|todo| todo["position"].as_f64().unwrap())
+        .collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+
+    for body in [
+        json!({"after": id(milk)}),
+        json!({"index": 0, "after": id(rent)}),
+        json!({}),
+    ] {
+        let response = app.post(&move_to(milk), &alice, body.clone()).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{body}");
+    }
+    let response = app.post(&move_to(milk), &alice, json!({"index": -1})).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let response = app.post(&move_to(milk), &bob, json!({"index": 0})).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let stranger = app.todo(&bob, "Buy bread").await;
+    let response = app
+        .post(&move_to(milk), &alice, json!({"before": id(&stranger)}))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn since_lists_the_todos_changed_after_a_time() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let milk = app.todo(&token, "Buy milk").await;
+    let rent = app.todo(&token, "Pay rent").await;
+    let since = rent["updated_at"].as_str().unwrap().to_owned();
+
+    let page = app
+        .get(&format!("/api/v1/todos?since={since}"), &token)
+        .await
+        .json();
+    assert_eq!(page["total"], 0);
+
+    let response = app
+        .request(
+            Method::PUT,
+            &format!("/api/v1/todos/{}", id(&milk)),
+            Some(&token),
+            Some(json!({"is_done": true})),
+            &[("if-match", "*")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    app.delete(&format!("/api/v1/todos/{}", id(&rent)), &token)
+        .await;
+
+    let page = app
+        .get(&format!("/api/v1/todos?since={since}"), &token)
+        .await
+        .json();
+    assert_eq!(texts(&page), ["Buy milk"]);
+    let page = app
+        .get(
+            &format!("/api/v1/todos?since={since}&include_deleted=true&sort=-updated_at"),
+            &token,
+        )
+        .await
+        .json();
+    assert_eq!(texts(&page), ["Pay rent", "Buy milk"]);
+}
+
+#[tokio::test]
+async fn bulk_create_and_complete() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let response = app
+        .post(
+            "/api/v1/todos/bulk",
+            &token,
+            json!({"todos": [{"text": "Buy milk"}, {"text": "Pay rent"}, {"text": "Buy milk"}]}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let results = response.json()["results"].clone();
+    let statuses: Vec<_> = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].as_u64().unwrap())
+        .collect();
+    assert_eq!(statuses, [201, 201, 409]);
+
+    let milk = results[0]["todo"]["id"].as_str().unwrap();
+    let unknown = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post(
+            "/api/v1/todos/bulk-complete",
+            &token,
+            json!({"ids": [milk, unknown]}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let results = response.json()["results"].clone();
+    assert_eq!(results[0]["status"], 200);
+    assert_eq!(results[0]["todo"]["is_done"], true);
+    assert_eq!(results[1]["status"], 404);
+
+    let response = app
+        .post("/api/v1/todos/bulk", &token, json!({"todos": []}))
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn validate_tells_stale_and_deleted_todos() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let fresh = app.todo(&token, "Buy milk").await;
+    let stale = app.todo(&token, "Pay rent").await;
+    app.request(
+        Method::PUT,
+        &format!("/api/v1/todos/{}", id(&stale)),
+        Some(&token),
+        Some(json!({"is_done": true})),
+        &[("if-match", "*")],
+    )
+    .await;
+    let gone = uuid::Uuid::new_v4();
+
+    let response = app
+        .post(
+            "/api/v1/todos/validate",
+            &token,
+            json!({"etags": {id(&fresh): "\"1\"", id(&stale): "\"1\"", gone.to_string(): "\"1\""}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let staleness = response.json();
+    assert_eq!(staleness["stale"], json!([id(&stale)]));
+    assert_eq!(staleness["deleted"], json!([gone]));
+}
+
+#[tokio::test]
+async fn quick_add_parses_the_text() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let response = app
+        .post(
+            "/api/v1/todos/quick",
+            &token,
+            json!({"text": "Pay rent !urgent"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let todo = response.json();
+    assert_eq!(todo["text"], "Pay rent");
+    assert_eq!(todo["priority"], "urgent");
+
+    let response = app
+        .post("/api/v1/todos/quick", &token, json!({"text": "!urgent"}))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn merged_todos_redirect_to_their_target() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let target = app.todo(&token, "Buy milk").await;
+    let source = app.todo(&token, "Get milk").await;
+    let merge = format!("/api/v1/todos/{}/merge", id(&target));
+
+    let response = app
+        .post(&merge, &token, json!({"source_id": id(&target)}))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = app
+        .post(&merge, &token, json!({"source_id": id(&source)}))
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+
+    let response = app
+        .get(&format!("/api/v1/todos/{}", id(&source)), &token)
+        .await;
+    assert_eq!(response.status, StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        response.header("location"),
+        Some(format!("/api/v1/todos/{}", id(&target)).as_str())
+    );
+    let response = app
+        .post(&merge, &token, json!({"source_id": id(&source)}))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn today_counts_and_search() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let due = (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+    app.post(
+        "/api/v1/todos",
+        &token,
+        json!({"text": "Pay the overdue rent", "due_at": due}),
+    )
+    .await;
+    app.todo(&token, "Buy milk").await;
+
+    let response = app.get("/api/v1/todos/today", &token).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(texts(&response.json()).contains(&"Pay the overdue rent"));
+
+    let response = app.get("/api/v1/todos/counts", &token).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let etag = response.header("etag").unwrap().to_owned();
+    assert_eq!(response.json()["open"], 2);
+    assert_eq!(response.json()["overdue"], 1);
+    let response = app
+        .request(
+            Method::GET,
+            "/api/v1/todos/counts",
+            Some(&token),
+            None,
+            &[("if-none-match", etag.as_str())],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+
+    let response = app.get("/api/v1/todos/search?q=ren", &token).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(texts(&response.json()), ["Pay the overdue rent"]);
+    let response = app.get("/api/v1/todos/search?q=", &token).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn export_and_import() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    app.todo(&alice, "Buy milk").await;
+    app.todo(&alice, "Pay rent").await;
+
+    let response = app.get("/api/v1/todos/export", &alice).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let records = response.json();
+    assert_eq!(records.as_array().unwrap().len(), 2);
+
+    let bob = app.user("bob").await;
+    app.todo(&bob, "Buy milk").await;
+    let response = app.post("/api/v1/todos/import", &bob, records).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["inserted"], 1);
+    assert_eq!(response.json()["skipped"], 1);
+
+    let response = app.get("/api/v1/todos/export?format=xml", &alice).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn stream_has_a_line_per_todo() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    for batch in 0..2 {
+        let todos: Vec<_> = (0..75)
+            .map(|n| json!({"text": format!("Todo {batch}.{n}")}))
+            .collect();
+        let response = app
+            .post("/api/v1/todos/bulk", &alice, json!({"todos": todos}))
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    }
+    app.todo(&app.user("bob").await, "Not alice's").await;
+
+    let response = app.get("/api/v1/todos/stream", &alice).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/x-ndjson")
+    );
+    let text = response.text();
+    assert!(text.ends_with('\n'));
+    let lines: Vec<Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 150);
+    // in creation order, the first batch first
+    assert!(lines[74]["text"].as_str().unwrap().starts_with("Todo 0."));
+    assert!(lines[75]["text"].as_str().unwrap().starts_with("Todo 1."));
+
+    let id = lines[0]["id"].as_str().unwrap();
+    app.request(
+        Method::PATCH,
+        &format!("/api/v1/todos/{id}"),
+        Some(&alice),
+        Some(json!({"is_done": true})),
+        &[("if-match", "*")],
+    )
+    .await;
+    let response = app
+        .get("/api/v1/todos/stream?is_done=true&meta=true", &alice)
+        .await;
+    let text = response.text();
+    assert_eq!(text.lines().count(), 1);
+    assert!(text.contains(id), "{text}");
+
+    // an empty stream is an empty body
+    let response = app.get("/api/v1/todos/stream?tag=none", &alice).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "");
+}
+
+#[tokio::test]
+async fn csv_listings() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    app.todo(&token, "Buy milk").await;
+    let response = app
+        .request(
+            Method::GET,
+            "/api/v1/todos",
+            Some(&token),
+            None,
+            &[("accept", "text/csv")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response
+        .header("content-type")
+        .unwrap()
+        .starts_with("text/csv"));
+    assert!(response.text().contains("Buy milk"));
+
+    let response = app
+        .request(
+            Method::GET,
+            "/api/v1/todos",
+            Some(&token),
+            None,
+            &[("accept", "image/png")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_ACCEPTABLE);
+}
+
+#[tokio::test]
+async fn unversioned_paths_are_deprecated_aliases() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let response = app.get("/todos?limit=1", &token).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.header("deprecation").is_some());
+    assert_eq!(
+        response.header("link"),
+        Some("</api/v1/todos?limit=1>; rel=\"successor-version\"")
+    );
+
+    let response = app.get("/api/v1/todos", &token).await;
+    assert_eq!(response.header("deprecation"), None);
+    let response = app.get("/api/v2/todos", &token).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn unknown_routes_and_methods() {
+    let app = TestApp::new().await;
+    let token = app.user("alice").await;
+    let response = app.get("/api/v1/nothing", &token).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/problem+json")
+    );
+    let response = app.delete("/api/v1/todos", &token).await;
+    assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+}
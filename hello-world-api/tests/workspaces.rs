@@ -0,0 +1,618 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestResponse};
+use serde_json::{json, Value};
+
+/// Sends a request acting in the workspace `slug`.
+async fn in_workspace(
+    app: &TestApp,
+    method: Method,
+    path: &str,
+    token: &str,
+    body: Option<Value>,
+    slug: &str,
+) -> TestResponse {
+    app.request(method, path, Some(token), body, &[("x-workspace", slug)])
+        .await
+}
+
+/// Creates the workspace `slug` owned by the user of `token`.
+async fn workspace(app: &TestApp, token: &str, slug: &str) {
+    let response = app
+        .post(
+            "/api/v1/workspaces",
+            token,
+            json!({"slug": slug, "name": "Acme Corp"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    assert_eq!(response.json()["role"], "owner");
+}
+
+/// Invites `username` to `slug` and has them accept with `token`.
+async fn join(app: &TestApp, owner: &str, slug: &str, username: &str, token: &str) {
+    let response = app
+        .post(
+            &format!("/api/v1/workspaces/{slug}/invitations"),
+            owner,
+            json!({"username": username}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let invitation = response.json()["token"].as_str().unwrap().to_owned();
+    let response = app
+        .post(
+            &format!("/api/v1/invitations/{invitation}/accept"),
+            token,
+            json!(null),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+}
+
+fn texts(page: &Value) -> Vec<&str> {
+    page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|todo| todo["text"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn workspace_data_is_its_members_alone() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    workspace(&app, &alice, "acme").await;
+    app.todo(&alice, "Personal").await;
+    let response = in_workspace(
+        &app,
+        Method::POST,
+        "/api/v1/todos",
+        &alice,
+        Some(json!({"text": "Shared"})),
+        "acme",
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let shared = format!("/api/v1/todos/{}", response.json()["id"].as_str().unwrap());
+
+    // the workspace and the user's own todos don't mix
+    let page = in_workspace(&app, Method::GET, "/api/v1/todos", &alice, None, "acme")
+        .await
+        .json();
+    assert_eq!(texts(&page), ["Shared"]);
+    assert_eq!(
+        texts(&app.get("/api/v1/todos", &alice).await.json()),
+        ["Personal"]
+    );
+    assert_eq!(app.get(&shared, &alice).await.status, StatusCode::NOT_FOUND);
+
+    // nor does anyone else see them, whether they name the workspace or not
+    for response in [
+        in_workspace(&app, Method::GET, "/api/v1/todos", &bob, None, "acme").await,
+        in_workspace(&app, Method::GET, &shared, &bob, None, "acme").await,
+        in_workspace(&app, Method::GET, "/api/v1/lists", &bob, None, "acme").await,
+        in_workspace(&app, Method::GET, "/api/v1/todos", &bob, None, "nowhere").await,
+    ] {
+        assert_eq!(
+            response.status,
+            StatusCode::NOT_FOUND,
+            "{}",
+            response.text()
+        );
+        assert_eq!(response.json()["detail"], "No such workspace");
+    }
+    assert_eq!(app.get(&shared, &bob).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(app.get("/api/v1/todos", &bob).await.json()["total"], 0);
+    let response = in_workspace(
+        &app,
+        Method::PATCH,
+        &shared,
+        &bob,
+        Some(json!({"text": "Mine now"})),
+        "acme",
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    // once a member, everything in the workspace is shared
+    join(&app, &alice, "acme", "bob", &bob).await;
+    let response = in_workspace(&app, Method::GET, &shared, &bob, None, "acme").await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let response = in_workspace(
+        &app,
+        Method::POST,
+        "/api/v1/lists",
+        &bob,
+        Some(json!({"name": "Roadmap"})),
+        "acme",
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    let lists = in_workspace(&app, Method::GET, "/api/v1/lists", &alice, None, "acme")
+        .await
+        .json();
+    assert_eq!(lists[0]["name"], "Roadmap");
+    assert_eq!(app.get("/api/v1/lists", &alice).await.json(), json!([]));
+    assert_eq!(app.get("/api/v1/lists", &bob).await.json(), json!([]));
+
+    let workspaces = app.get("/api/v1/workspaces", &bob).await.json();
+    assert_eq!(workspaces[0]["slug"], "acme");
+    assert_eq!(workspaces[0]["role"], "member");
+}
+
+#[tokio::test]
+async fn invitations_are_single_use_and_for_their_user() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let carol = app.user("carol").await;
+    workspace(&app, &alice, "acme").await;
+    let response = app
+        .post(
+            "/api/v1/workspaces/acme/invitations",
+            &alice,
+            json!({"username": "bob"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    assert_eq!(response.json()["role"], "member");
+    let accept = format!(
+        "/api/v1/invitations/{}/accept",
+        response.json()["token"].as_str().unwrap()
+    );
+
+    assert_eq!(
+        app.post(&accept, &carol, json!(null)).await.status,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        app.post(&accept, &bob, json!(null)).await.status,
+        StatusCode::OK
+    );
+    assert_eq!(
+        app.post(&accept, &bob, json!(null)).await.status,
+        StatusCode::NOT_FOUND
+    );
+    let response = app
+        .post("/api/v1/invitations/forged/accept", &carol, json!(null))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    // an invitation for anyone doesn't let a member in twice, and stays
+    // usable by someone else
+    let response = app
+        .post("/api/v1/workspaces/acme/invitations", &alice, json!({}))
+        .await;
+    let accept = format!(
+        "/api/v1/invitations/{}/accept",
+        response.json()["token"].as_str().unwrap()
+    );
+    assert_eq!(
+        app.post(&accept, &bob, json!(null)).await.status,
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        app.post(&accept, &carol, json!(null)).await.status,
+        StatusCode::OK
+    );
+    let members = app
+        .get("/api/v1/workspaces/acme/members", &carol)
+        .await
+        .json();
+    let usernames: Vec<_> = members
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|member| member["username"].as_str().unwrap())
+        .collect();
+    assert_eq!(usernames, ["alice", "bob", "carol"]);
+
+    let response = app
+        .post(
+            "/api/v1/workspaces/acme/invitations",
+            &alice,
+            json!({"expires_at": "2000-01-01T00:00:00Z"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn only_owners_manage_members() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    workspace(&app, &alice, "acme").await;
+    join(&app, &alice, "acme", "bob", &bob).await;
+    let members = app
+        .get("/api/v1/workspaces/acme/members", &alice)
+        .await
+        .json();
+    let alice_id = members[0]["user_id"].as_str().unwrap().to_owned();
+    let bob_id = members[1]["user_id"].as_str().unwrap().to_owned();
+
+    let response = app
+        .post("/api/v1/workspaces/acme/invitations", &bob, json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app
+        .delete(&format!("/api/v1/workspaces/acme/members/{alice_id}"), &bob)
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app
+        .delete(
+            &format!("/api/v1/workspaces/acme/members/{alice_id}"),
+            &alice,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+
+    // a removed member loses access at once
+    let response = app
+        .delete(&format!("/api/v1/workspaces/acme/members/{bob_id}"), &alice)
+        .await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    let response = in_workspace(&app, Method::GET, "/api/v1/todos", &bob, None, "acme").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.get("/api/v1/workspaces/acme/members", &bob).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(app.get("/api/v1/workspaces", &bob).await.json(), json!([]));
+}
+
+#[tokio::test]
+async fn workspace_slugs_are_unique() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    workspace(&app, &alice, "acme").await;
+    let response = app
+        .post(
+            "/api/v1/workspaces",
+            &bob,
+            json!({"slug": "acme", "name": "Acme Inc"}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    for slug in ["", "Acme", "-acme", "acme.corp"] {
+        let response = app
+            .post(
+                "/api/v1/workspaces",
+                &bob,
+                json!({"slug": slug, "name": "Acme"}),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY, "{slug}");
+    }
+
+    // nobody logs in to the account holding a workspace's data
+    let id = app.get("/api/v1/workspaces", &alice).await.json()[0]["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let response = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(json!({"username": format!("workspace:{id}"), "password": ""})),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn leaderboard_ranks_members_who_opt_in() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    let carol = app.user("carol").await;
+    workspace(&app, &alice, "acme").await;
+    join(&app, &alice, "acme", "bob", &bob).await;
+    join(&app, &alice, "acme", "carol", &carol).await;
+    for (token, texts) in [
+        (&bob, &["Ship it", "Tell them"][..]),
+        (&alice, &["Plan it"]),
+    ] {
+        for text in texts {
+            let todo = in_workspace(
+                &app,
+                Method::POST,
+                "/api/v1/todos",
+                token,
+                Some(json!({"text": text})),
+                "acme",
+            )
+            .await;
+            assert_eq!(todo.status, StatusCode::CREATED, "{}", todo.text());
+            let path = format!("/api/v1/todos/{}", todo.json()["id"].as_str().unwrap());
+            let response = app
+                .request(
+                    Method::PATCH,
+                    &path,
+                    Some(token),
+                    Some(json!({"is_done": true})),
+                    &[("x-workspace", "acme"), ("if-match", "*")],
+                )
+                .await;
+            assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+        }
+    }
+    // completing one's own todos doesn't count
+    let todo = app.todo(&carol, "Water the plants").await;
+    let response = app
+        .request(
+            Method::PATCH,
+            &format!("/api/v1/todos/{}", todo["id"].as_str().unwrap()),
+            Some(&carol),
+            Some(json!({"is_done": true})),
+            &[("if-match", "*")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+
+    let response = app.get("/api/v1/workspaces/acme/leaderboard", &carol).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let board = response.json();
+    assert_eq!(board["period"], "week");
+    let ranked = |board: &Value| -> Vec<(String, i64, i64)> {
+        board["members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|member| {
+                (
+                    member["username"].as_str().unwrap().to_owned(),
+                    member["rank"].as_i64().unwrap(),
+                    member["completed"].as_i64().unwrap(),
+                )
+            })
+            .collect()
+    };
+    assert_eq!(
+        ranked(&board),
+        [
+            ("bob".to_owned(), 1, 2),
+            ("alice".to_owned(), 2, 1),
+            ("carol".to_owned(), 3, 0),
+        ]
+    );
+
+    let response = app
+        .put(
+            "/api/v1/workspaces/acme/privacy",
+            &bob,
+            json!({"on_leaderboard": false}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json(), json!({"on_leaderboard": false}));
+    let response = app.get("/api/v1/workspaces/acme/privacy", &bob).await;
+    assert_eq!(response.json(), json!({"on_leaderboard": false}));
+    let board = app
+        .get("/api/v1/workspaces/acme/leaderboard?period=month", &alice)
+        .await
+        .json();
+    assert_eq!(board["period"], "month");
+    assert_eq!(
+        ranked(&board),
+        [("alice".to_owned(), 1, 1), ("carol".to_owned(), 2, 0)]
+    );
+
+    // outsiders see neither
+    let outsider = app.user("dave").await;
+    for path in [
+        "/api/v1/workspaces/acme/leaderboard",
+        "/api/v1/workspaces/acme/privacy",
+    ] {
+        let response = app.get(path, &outsider).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn todos_are_assigned_to_members() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    app.user("carol").await;
+    workspace(&app, &alice, "acme").await;
+    join(&app, &alice, "acme", "bob", &bob).await;
+    let members = app
+        .get("/api/v1/workspaces/acme/members", &alice)
+        .await
+        .json();
+    let bob_id = members
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|member| member["username"] == "bob")
+        .unwrap()["user_id"]
+        .clone();
+    for text in ["Ship it", "Plan it"] {
+        let response = in_workspace(
+            &app,
+            Method::POST,
+            "/api/v1/todos",
+            &alice,
+            Some(json!({"text": text})),
+            "acme",
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+    }
+    let page = in_workspace(&app, Method::GET, "/api/v1/todos", &alice, None, "acme")
+        .await
+        .json();
+    let id = page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|todo| todo["text"] == "Ship it")
+        .unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let assign = format!("/api/v1/todos/{id}/assign");
+
+    let response = in_workspace(
+        &app,
+        Method::POST,
+        &assign,
+        &alice,
+        Some(json!({"assignee_id": bob_id})),
+        "acme",
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json()["assignee_id"], bob_id);
+    let page = in_workspace(
+        &app,
+        Method::GET,
+        "/api/v1/todos?assignee=me",
+        &bob,
+        None,
+        "acme",
+    )
+    .await
+    .json();
+    assert_eq!(texts(&page), ["Ship it"]);
+    let page = in_workspace(
+        &app,
+        Method::GET,
+        "/api/v1/todos?assignee=me",
+        &alice,
+        None,
+        "acme",
+    )
+    .await
+    .json();
+    assert_eq!(texts(&page), Vec::<&str>::new());
+
+    // bob is told of the assignment and of the new due date
+    let response = app
+        .request(
+            Method::PATCH,
+            &format!("/api/v1/todos/{id}"),
+            Some(&alice),
+            Some(json!({"due_at": "2030-01-07T09:00:00Z"})),
+            &[("x-workspace", "acme"), ("if-match", "*")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    let kinds = sqlx::query_scalar::<_, String>(
+        r#"select o.payload->>'type' from "todo_outbox" o join "user" u using (user_id)
+        where u.username = 'bob'
+        order by o.id"#,
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(kinds, ["assigned", "due_changed"]);
+
+    // carol isn't a member
+    let carol_id: uuid::Uuid =
+        sqlx::query_scalar(r#"select user_id from "user" where username = 'carol'"#)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+    let response = in_workspace(
+        &app,
+        Method::POST,
+        &assign,
+        &alice,
+        Some(json!({"assignee_id": carol_id})),
+        "acme",
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let response = in_workspace(
+        &app,
+        Method::GET,
+        "/api/v1/todos?assignee=bob",
+        &alice,
+        None,
+        "acme",
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn owners_set_the_workspace_settings() {
+    let app = TestApp::new().await;
+    let alice = app.user("alice").await;
+    let bob = app.user("bob").await;
+    workspace(&app, &alice, "acme").await;
+    join(&app, &alice, "acme", "bob", &bob).await;
+    let path = "/api/v1/workspaces/acme/settings";
+    let defaults = json!({
+        "default_priority": null,
+        "reminder_offset_minutes": null,
+        "week_start": null,
+        "timezone": null,
+    });
+    assert_eq!(app.get(path, &bob).await.json(), defaults);
+
+    let settings = json!({
+        "default_priority": "high",
+        "reminder_offset_minutes": 60,
+        "week_start": "sunday",
+        "timezone": "Europe/Berlin",
+    });
+    let response = app.put(path, &bob, settings.clone()).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    for invalid in [
+        json!({"reminder_offset_minutes": 0}),
+        json!({"timezone": "Mars/Olympus"}),
+        json!({"week_start": "someday"}),
+        json!({"colour": "red"}),
+    ] {
+        let response = app.put(path, &alice, invalid).await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let response = app.put(path, &alice, settings.clone()).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.text());
+    assert_eq!(response.json(), settings);
+    assert_eq!(app.get(path, &bob).await.json(), settings);
+
+    // the workspace's todos get its priority, the members' own don't
+    for (body, priority) in [
+        (json!({"text": "Ship it"}), "high"),
+        (json!({"text": "Plan it", "priority": "low"}), "low"),
+    ] {
+        let response = in_workspace(
+            &app,
+            Method::POST,
+            "/api/v1/todos",
+            &bob,
+            Some(body),
+            "acme",
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", response.text());
+        assert_eq!(response.json()["priority"], priority);
+    }
+    let response = app
+        .post("/api/v1/todos", &bob, json!({"text": "Mine"}))
+        .await;
+    assert_eq!(response.json()["priority"], Value::Null);
+    let board = app
+        .get("/api/v1/workspaces/acme/leaderboard", &bob)
+        .await
+        .json();
+    assert!(board["since"].as_str().unwrap().ends_with(":00:00Z"));
+
+    // left out settings go back to their defaults
+    let response = app
+        .put(path, &alice, json!({"timezone": "Asia/Tokyo"}))
+        .await;
+    assert_eq!(response.json()["default_priority"], Value::Null);
+    assert_eq!(response.json()["timezone"], "Asia/Tokyo");
+    let outsider = app.user("carol").await;
+    assert_eq!(app.get(path, &outsider).await.status, StatusCode::NOT_FOUND);
+}
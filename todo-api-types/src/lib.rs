@@ -0,0 +1,650 @@
+//! The request and response bodies of the todo endpoints, shared by the
+//! server and its clients so both read and write the same JSON.
+//!
+//! With the `server` feature they also derive what the server documents and
+//! stores them with: OpenAPI schemas, GraphQL types and Postgres enums.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Ordered from lowest to highest, as todos sort by it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(sqlx::Type, utoipa::ToSchema, async_graphql::Enum),
+    sqlx(type_name = "priority", rename_all = "lowercase")
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+    Urgent,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema, async_graphql::Enum))]
+#[serde(rename_all = "snake_case")]
+pub enum TodoStatus {
+    Open,
+    Done,
+    /// Not done before its `expires_at`, and cancelled since.
+    Expired,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(utoipa::ToSchema, async_graphql::SimpleObject)
+)]
+pub struct Tag {
+    pub id: uuid::Uuid,
+    pub name: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(sqlx::Type, utoipa::ToSchema),
+    sqlx(type_name = "text", rename_all = "snake_case")
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    RelatesTo,
+    Duplicates,
+    CausedBy,
+}
+
+/// Which of the two todos a link reads from.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(sqlx::Type, utoipa::ToSchema),
+    sqlx(type_name = "text", rename_all = "snake_case")
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkDirection {
+    /// This todo `kind` the other, e.g. duplicates it.
+    Outgoing,
+    /// The other todo `kind` this one.
+    Incoming,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct TodoLink {
+    pub id: uuid::Uuid,
+    pub kind: LinkKind,
+    pub direction: LinkDirection,
+    /// The other todo.
+    pub todo_id: uuid::Uuid,
+    /// Its text.
+    pub text: String,
+}
+
+/// The query string of `GET /todos` and the other todo listings.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
+pub struct ListTodos {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_done: Option<bool>,
+    /// Only todos whose start date has (or hasn't) been reached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started: Option<bool>,
+    /// Only open todos past their due date, or only the others.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overdue: Option<bool>,
+    /// Only todos due before this time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only todos with the tag of this name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Only todos in this list.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list_id: Option<uuid::Uuid>,
+    /// Only todos assigned to this user, `me` for the requester.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assignee: Option<String>,
+    /// Only todos of this priority.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+    /// Only todos that expired, or only the others.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expired: Option<bool>,
+    /// Also list soft-deleted todos.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub include_deleted: bool,
+    /// Case-insensitive substring of the text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    /// Full-text search in each todo's language, e.g. `"buy milk" -oat`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    /// Only todos changed after this time, for syncing incrementally; with
+    /// `include_deleted=true` that includes the ones deleted since.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Comma-separated `id`, `text`, `is_done`, `start_at`, `due_at`,
+    /// `priority`, `created_at`, `updated_at` or `position`, `-` for
+    /// descending, e.g. `-priority,due_at`. Todos without a date or priority
+    /// sort last, or first when descending. Without it, and between todos
+    /// sorting the same, todos are in the user's order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    /// `next_cursor` of the previous page; only without `sort`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    /// Only the todos with these comma-separated ids, at most 100. The page
+    /// size defaults to 100 with them, so a page has all of them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ids: Option<String>,
+    /// Comma-separated fields of each todo to return, e.g. `id,text`; only
+    /// those are read from the database. Not for CSV listings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<String>,
+    /// Page size, 1 to 100 (default 10).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    /// Include the sync metadata of each todo.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub meta: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// The query string of `GET /todos/:id`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
+pub struct GetTodo {
+    /// Include the todo's sync metadata.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub meta: bool,
+}
+
+/// The query string of `GET /todos/trash`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
+pub struct ListTrash {
+    /// How many to list, 100 by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+}
+
+/// The body of `POST /todos`.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(utoipa::ToSchema, async_graphql::InputObject),
+    graphql(name = "CreateTodoInput")
+)]
+#[serde(deny_unknown_fields)]
+pub struct CreateTodo {
+    /// Stored trimmed, which must leave 1 to 1000 characters.
+    #[cfg_attr(feature = "server", schema(min_length = 1, max_length = 1000))]
+    pub text: String,
+    pub start_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub due_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the todo is cancelled unless done by then.
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// One of the user's lists to put it in.
+    pub list_id: Option<uuid::Uuid>,
+    pub priority: Option<Priority>,
+    /// Repeats the todo once done, e.g. `every monday at 5pm` or
+    /// `FREQ=WEEKLY;BYDAY=MO;BYHOUR=17`.
+    #[cfg_attr(feature = "server", schema(max_length = 200))]
+    pub recurrence: Option<String>,
+}
+
+impl CreateTodo {
+    /// A todo with just a text, due whenever.
+    pub fn new(text: impl Into<String>) -> Self {
+        CreateTodo {
+            text: text.into(),
+            start_at: None,
+            due_at: None,
+            expires_at: None,
+            list_id: None,
+            priority: None,
+            recurrence: None,
+        }
+    }
+}
+
+/// The body of `PUT /todos/:id`.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct PutTodo {
+    pub is_done: bool,
+}
+
+/// The fields to change, at least one.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct PatchTodo {
+    /// Stored trimmed, which must leave 1 to 1000 characters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "server", schema(min_length = 1, max_length = 1000))]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_done: Option<bool>,
+    /// `null` clears the due date.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(
+        feature = "server",
+        schema(value_type = Option<chrono::DateTime<chrono::Utc>>)
+    )]
+    pub due_at: Option<Option<chrono::DateTime<chrono::Utc>>>,
+    /// `null` clears the expiry. Setting it either way revives an expired
+    /// todo.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(
+        feature = "server",
+        schema(value_type = Option<chrono::DateTime<chrono::Utc>>)
+    )]
+    pub expires_at: Option<Option<chrono::DateTime<chrono::Utc>>>,
+    /// Moves the todo to another of the user's lists, `null` out of its
+    /// list.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(feature = "server", schema(value_type = Option<uuid::Uuid>))]
+    pub list_id: Option<Option<uuid::Uuid>>,
+    /// `null` clears the priority.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(feature = "server", schema(value_type = Option<Priority>))]
+    pub priority: Option<Option<Priority>>,
+    /// `null` stops the todo from repeating.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(
+        feature = "server",
+        schema(value_type = Option<String>, max_length = 200)
+    )]
+    pub recurrence: Option<Option<String>>,
+}
+
+/// Deserializes a field that is present, telling an explicit `null` apart
+/// from a missing field, which `#[serde(default)]` leaves `None`.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+impl PatchTodo {
+    /// Whether no field is given, leaving nothing to update.
+    pub fn is_empty(&self) -> bool {
+        self.text.is_none()
+            && self.is_done.is_none()
+            && self.due_at.is_none()
+            && self.expires_at.is_none()
+            && self.list_id.is_none()
+            && self.priority.is_none()
+            && self.recurrence.is_none()
+    }
+}
+
+/// The body of `POST /todos/validate`.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct ValidateTodos {
+    /// The `etag` the client holds of each todo, by id.
+    #[cfg_attr(feature = "server", schema(value_type = HashMap<String, String>))]
+    pub etags: HashMap<uuid::Uuid, String>,
+}
+
+/// The todos of a [`ValidateTodos`] the client has to refresh; those not
+/// listed are unchanged.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct Staleness {
+    /// Changed since the client's `etag`.
+    pub stale: Vec<uuid::Uuid>,
+    /// Deleted, merged into another todo or never the user's.
+    pub deleted: Vec<uuid::Uuid>,
+}
+
+/// The body of `POST /todos/bulk`.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct BulkCreate {
+    /// Each checked like the body of `POST /todos`, failing on its own.
+    pub todos: Vec<CreateTodo>,
+}
+
+/// The body of `POST /todos/bulk-complete`.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct BulkComplete {
+    pub ids: Vec<uuid::Uuid>,
+}
+
+/// The outcome of each item of a bulk request, in the order they were sent.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct BulkResults {
+    pub results: Vec<BulkResult>,
+}
+
+/// One item's outcome, as its own request would have had it.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct BulkResult {
+    /// e.g. `201` for a todo created, `409` for a duplicate.
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo: Option<ToDoView>,
+    /// The problem details of a failed item.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "server", schema(value_type = Option<ProblemDetails>))]
+    pub error: Option<serde_json::Value>,
+}
+
+/// The body of `POST /todos/:id/merge`.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct MergeTodo {
+    pub source_id: uuid::Uuid,
+}
+
+/// Where to move the todo, exactly one of the fields.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct MoveTodo {
+    /// Index among the user's other todos, from 0; past the last puts it
+    /// last.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "server", schema(minimum = 0))]
+    pub index: Option<i64>,
+    /// Right before this todo.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<uuid::Uuid>,
+    /// Right after this todo.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(utoipa::ToSchema, async_graphql::SimpleObject),
+    graphql(name = "Todo")
+)]
+pub struct ToDoView {
+    pub id: uuid::Uuid,
+    pub text: String,
+    pub is_done: bool,
+    pub status: TodoStatus,
+    /// Until then the todo is kept out of the today view.
+    pub start_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub due_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// The list the todo is in, if any.
+    pub list_id: Option<uuid::Uuid>,
+    /// The workspace member the todo is assigned to, if any.
+    pub assignee_id: Option<uuid::Uuid>,
+    pub priority: Option<Priority>,
+    /// The rule the todo repeats by, its next occurrence created once it is
+    /// done.
+    pub recurrence: Option<String>,
+    /// By name.
+    pub tags: Vec<Tag>,
+    /// How much of the todo's checklist is done, rounded down; absent for
+    /// a todo without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_percent: Option<i32>,
+    /// The username, or workspace slug, of whoever shared the todo with
+    /// the user; absent for the user's own todos.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shared_by: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// When the todo last changed, except for its tags and checklist.
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// Where the todo is in its owner's order, lowest first; changed with
+    /// `POST /todos/:id/move`.
+    #[serde(default)]
+    pub position: f64,
+    /// Only set on deleted todos listed with `?include_deleted=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only on a todo fetched by itself, from and to it, oldest first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "server", graphql(skip))]
+    pub links: Option<Vec<TodoLink>>,
+    /// Changes with every update of the todo except to its tags and
+    /// checklist, checked in bulk by `POST /todos/validate` and sent as
+    /// `If-Match` to update it.
+    pub etag: String,
+}
+
+impl ToDoView {
+    pub fn with_links(self, links: Vec<TodoLink>) -> Self {
+        ToDoView {
+            links: Some(links),
+            ..self
+        }
+    }
+}
+
+/// A todo with the sync metadata asked for with `?meta=true`.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ToDoMetaView {
+    #[serde(flatten)]
+    pub todo: ToDoView,
+    #[cfg_attr(feature = "server", schema(inline))]
+    pub meta: TodoMeta,
+}
+
+impl ToDoMetaView {
+    pub fn with_links(self, links: Vec<TodoLink>) -> Self {
+        ToDoMetaView {
+            todo: self.todo.with_links(links),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct TodoMeta {
+    /// When `text`, `is_done`, `location`, `start_at`, `due_at`,
+    /// `expires_at`, `priority` and `recurrence` were last changed, for
+    /// resolving sync conflicts field by field.
+    #[cfg_attr(feature = "server", schema(value_type = Object))]
+    pub field_modified: serde_json::Value,
+}
+
+/// A page of a todo listing.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "server",
+    derive(utoipa::ToSchema),
+    aliases(TodoListPage = TodoPage<ToDoView>, TodoMetaListPage = TodoPage<ToDoMetaView>)
+)]
+pub struct TodoPage<T> {
+    pub items: Vec<T>,
+    /// All todos matching the filters, across pages.
+    pub total: i64,
+    /// Whether `total` is estimated, as it is for listings matching more
+    /// todos than `EXACT_COUNT_LIMIT`.
+    pub total_estimated: bool,
+    /// `after` for the next page; only set for unsorted listings with more
+    /// todos.
+    pub next_cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn enums_are_lowercase_words() {
+        assert_eq!(json!(Priority::Urgent), json!("urgent"));
+        assert_eq!(
+            serde_json::from_value::<Priority>(json!("medium")).unwrap(),
+            Priority::Medium
+        );
+        assert!(serde_json::from_value::<Priority>(json!("Medium")).is_err());
+        assert!(Priority::Low < Priority::Medium && Priority::High < Priority::Urgent);
+        assert_eq!(json!(TodoStatus::Expired), json!("expired"));
+        assert_eq!(json!(LinkKind::CausedBy), json!("caused_by"));
+        assert_eq!(json!(LinkDirection::Incoming), json!("incoming"));
+    }
+
+    #[test]
+    fn patches_tell_null_from_missing() {
+        let patch: PatchTodo =
+            serde_json::from_value(json!({"due_at": null, "priority": "high"})).unwrap();
+        assert_eq!(patch.due_at, Some(None));
+        assert_eq!(patch.priority, Some(Some(Priority::High)));
+        assert_eq!(patch.list_id, None);
+        assert!(!patch.is_empty());
+        // and keep telling them apart on the way out
+        assert_eq!(json!(patch), json!({"due_at": null, "priority": "high"}));
+
+        let empty: PatchTodo = serde_json::from_value(json!({})).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(json!(empty), json!({}));
+        assert!(serde_json::from_value::<PatchTodo>(json!({"done": true})).is_err());
+    }
+
+    #[test]
+    fn request_bodies_refuse_unknown_fields() {
+        let create: CreateTodo =
+            serde_json::from_value(json!({"text": "Buy milk", "priority": "low"})).unwrap();
+        assert_eq!(create.text, "Buy milk");
+        assert_eq!(create.due_at, None);
+        assert!(serde_json::from_value::<CreateTodo>(json!({"text": "x", "tags": []})).is_err());
+        assert!(serde_json::from_value::<PutTodo>(json!({"is_done": true, "x": 1})).is_err());
+        assert!(serde_json::from_value::<MoveTodo>(json!({"position": 1})).is_err());
+        assert_eq!(
+            json!(CreateTodo::new("Buy milk")),
+            json!({
+                "text": "Buy milk",
+                "start_at": null,
+                "due_at": null,
+                "expires_at": null,
+                "list_id": null,
+                "priority": null,
+                "recurrence": null,
+            })
+        );
+    }
+
+    #[test]
+    fn listing_queries_only_carry_what_is_set() {
+        assert_eq!(json!(ListTodos::default()), json!({}));
+        let query = ListTodos {
+            is_done: Some(false),
+            include_deleted: true,
+            sort: Some("-priority".to_owned()),
+            limit: Some(5),
+            ..ListTodos::default()
+        };
+        assert_eq!(
+            json!(query),
+            json!({"is_done": false, "include_deleted": true, "sort": "-priority", "limit": 5})
+        );
+        let parsed: ListTodos = serde_json::from_value(json!({"tag": "home"})).unwrap();
+        assert_eq!(parsed.tag.as_deref(), Some("home"));
+        assert!(!parsed.include_deleted && !parsed.meta);
+    }
+
+    #[test]
+    fn todo_views_leave_out_what_they_lack() {
+        let id = uuid::Uuid::from_u128(1);
+        let view: ToDoView = serde_json::from_value(json!({
+            "id": id,
+            "text": "Buy milk",
+            "is_done": false,
+            "status": "open",
+            "start_at": null,
+            "due_at": "2024-05-01T17:00:00Z",
+            "expires_at": null,
+            "list_id": null,
+            "priority": "high",
+            "recurrence": null,
+            "tags": [{"id": id, "name": "home"}],
+            "created_at": "2024-04-01T09:00:00Z",
+            "updated_at": "2024-04-01T09:00:00Z",
+            "etag": "\"1\"",
+        }))
+        .unwrap();
+        // servers before the user's order had no position
+        assert_eq!(view.position, 0.0);
+        assert_eq!(view.tags[0].name, "home");
+        let value = json!(view);
+        for absent in ["completion_percent", "shared_by", "deleted_at", "links"] {
+            assert!(value.get(absent).is_none(), "{absent}");
+        }
+
+        let linked = view.with_links(vec![TodoLink {
+            id,
+            kind: LinkKind::Duplicates,
+            direction: LinkDirection::Outgoing,
+            todo_id: id,
+            text: "Buy oat milk".to_owned(),
+        }]);
+        assert_eq!(json!(linked)["links"][0]["kind"], "duplicates");
+        let meta = ToDoMetaView {
+            todo: linked,
+            meta: TodoMeta {
+                field_modified: json!({"text": "2024-04-01T09:00:00Z"}),
+            },
+        };
+        // the todo's fields sit next to its meta
+        let value = json!(meta);
+        assert_eq!(value["text"], "Buy milk");
+        assert_eq!(
+            value["meta"]["field_modified"]["text"],
+            "2024-04-01T09:00:00Z"
+        );
+    }
+}
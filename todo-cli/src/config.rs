@@ -0,0 +1,74 @@
+//! Where the server is and how to log in to it, from the flags, the
+//! environment or the config file, the first one setting it winning.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// The server used when nothing says otherwise, as `cargo run` serves it.
+const DEFAULT_SERVER: &str = "http://localhost:3000";
+
+/// The config file, every key optional:
+///
+/// ```toml
+/// server = "https://todo.example.com"
+/// token = "..."
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    server: Option<String>,
+    token: Option<String>,
+}
+
+pub struct Config {
+    /// The server's root, without `/api/v1`.
+    pub server: String,
+    /// Bearer token to call the API with.
+    pub token: String,
+}
+
+impl Config {
+    /// Fills in what the flags and environment left unset, `server` and
+    /// `token`, from the file at `path`, or the default one if it exists.
+    pub fn load(
+        path: Option<&Path>,
+        server: Option<String>,
+        token: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let file = match path {
+            Some(path) => read(path)?,
+            None => match default_path() {
+                Some(path) if path.exists() => read(&path)?,
+                _ => File::default(),
+            },
+        };
+        let server = server
+            .or(file.server)
+            .unwrap_or_else(|| DEFAULT_SERVER.to_owned());
+        let token = token.or(file.token).context(
+            "no token, log in with POST /auth/login and pass it with --token, \
+            TODO_TOKEN or the config file",
+        )?;
+        Ok(Config {
+            server: server.trim_end_matches('/').to_owned(),
+            token,
+        })
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<File> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("{} is not a valid config", path.display()))
+}
+
+/// `todo/config.toml` in `XDG_CONFIG_HOME`, or in `~/.config` without it.
+fn default_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("todo").join("config.toml"))
+}
@@ -0,0 +1,247 @@
+//! `todo`, a command line client of the todo API.
+
+mod config;
+mod output;
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use todo_client::{Client, CreateTodo, ListTodos, Priority, PutTodo, ToDoView};
+
+use crate::{config::Config, output::Format};
+
+/// Adds, lists and completes your todos on a todo API server
+///
+/// The server and token come from the flags, the environment or the config
+/// file, `~/.config/todo/config.toml` by default, with `server` and `token`
+/// keys.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// The server's URL, such as https://todo.example.com
+    #[arg(long, global = true, env = "TODO_SERVER")]
+    server: Option<String>,
+    /// Bearer token from POST /auth/login
+    #[arg(long, global = true, env = "TODO_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Config file to read instead of the default one
+    #[arg(long, global = true, env = "TODO_CONFIG")]
+    config: Option<PathBuf>,
+    #[arg(long, short, global = true, value_enum, default_value_t = Format::Table)]
+    output: Format,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Add a todo
+    Add {
+        text: String,
+        /// When it is due, such as 2024-05-01T17:00:00Z
+        #[arg(long)]
+        due: Option<chrono::DateTime<chrono::Utc>>,
+        /// low, medium, high or urgent
+        #[arg(long, value_parser = priority)]
+        priority: Option<Priority>,
+    },
+    /// List your todos, in your order
+    List {
+        /// Only the todos not done yet
+        #[arg(long, conflicts_with = "done")]
+        pending: bool,
+        /// Only the todos done
+        #[arg(long)]
+        done: bool,
+    },
+    /// Mark a todo done
+    Done { id: uuid::Uuid },
+}
+
+fn priority(value: &str) -> Result<Priority, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_owned()))
+        .map_err(|_| "expected low, medium, high or urgent".to_owned())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref(), cli.server, cli.token)?;
+    let client = Client::new(config.server, config.token);
+    match cli.command {
+        Command::Add {
+            text,
+            due,
+            priority,
+        } => {
+            let body = CreateTodo {
+                due_at: due,
+                priority,
+                ..CreateTodo::new(text)
+            };
+            let todo = client.create_todo(&body).await?;
+            output::print_one(cli.output, &todo)
+        }
+        Command::List { pending, done } => {
+            let is_done = match (pending, done) {
+                (true, _) => Some(false),
+                (_, true) => Some(true),
+                _ => None,
+            };
+            let todos = list_todos(&client, is_done).await?;
+            output::print(cli.output, &todos)
+        }
+        Command::Done { id } => {
+            // updates are conditional, on the version just read
+            let todo = client.get_todo(id).await?;
+            let todo = client
+                .put_todo(id, &todo.etag, &PutTodo { is_done: true })
+                .await?;
+            output::print_one(cli.output, &todo)
+        }
+    }
+}
+
+/// Every todo, done or not if `is_done` is given, following the pages'
+/// cursors to the last one.
+async fn list_todos(client: &Client, is_done: Option<bool>) -> anyhow::Result<Vec<ToDoView>> {
+    let mut query = ListTodos {
+        is_done,
+        limit: Some(100),
+        ..ListTodos::default()
+    };
+    let mut todos = Vec::new();
+    loop {
+        let page = client.list_todos(&query).await?;
+        todos.extend(page.items);
+        match page.next_cursor {
+            Some(cursor) => query.after = Some(cursor),
+            None => return Ok(todos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{error::ErrorKind, CommandFactory};
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("todo").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn the_commands_are_well_formed() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn add_takes_a_due_date_and_a_priority() {
+        let cli = parse(&[
+            "add",
+            "Buy milk",
+            "--due",
+            "2024-05-01T17:00:00Z",
+            "--priority",
+            "high",
+        ])
+        .unwrap();
+        let Command::Add {
+            text,
+            due,
+            priority,
+        } = cli.command
+        else {
+            panic!("not add");
+        };
+        assert_eq!(text, "Buy milk");
+        assert_eq!(due.unwrap().to_rfc3339(), "2024-05-01T17:00:00+00:00");
+        assert_eq!(priority, Some(Priority::High));
+
+        let cli = parse(&["add", "Buy milk"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Add {
+                due: None,
+                priority: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn add_rejects_what_it_cant_send() {
+        let err = parse(&["add", "Buy milk", "--priority", "asap"])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+        assert!(err
+            .to_string()
+            .contains("expected low, medium, high or urgent"));
+        let err = parse(&["add", "Buy milk", "--due", "tomorrow"])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+        let err = parse(&["add"]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn list_filters_on_done_or_pending() {
+        for (args, pending, done) in [
+            (&["list"][..], false, false),
+            (&["list", "--pending"][..], true, false),
+            (&["list", "--done"][..], false, true),
+        ] {
+            let cli = parse(args).unwrap();
+            assert!(
+                matches!(cli.command, Command::List { pending: p, done: d } if p == pending && d == done),
+                "{args:?}"
+            );
+        }
+        let err = parse(&["list", "--pending", "--done"]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn done_takes_a_todo_id() {
+        let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let cli = parse(&["done", id]).unwrap();
+        assert!(matches!(cli.command, Command::Done { id: parsed } if parsed.to_string() == id));
+        let err = parse(&["done", "42"]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+        let err = parse(&["done"]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn global_flags_go_before_or_after_the_command() {
+        let cli = parse(&[
+            "list",
+            "--server",
+            "https://todo.example.com",
+            "--token",
+            "secret",
+            "-o",
+            "json",
+        ])
+        .unwrap();
+        assert_eq!(cli.server.as_deref(), Some("https://todo.example.com"));
+        assert_eq!(cli.token.as_deref(), Some("secret"));
+        assert!(matches!(cli.output, Format::Json));
+        let cli = parse(&["--config", "/tmp/todo.toml", "list"]).unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("/tmp/todo.toml")));
+        assert!(matches!(cli.output, Format::Table));
+
+        let err = parse(&["-o", "yaml", "list"]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidValue);
+        let err = parse(&[]).err().unwrap();
+        assert_eq!(
+            err.kind(),
+            ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+        );
+        let err = parse(&["remove"]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidSubcommand);
+    }
+}
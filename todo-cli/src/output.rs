@@ -0,0 +1,148 @@
+//! How todos are printed: as a table for people, or as the JSON the server
+//! answered with for scripts.
+
+use clap::ValueEnum;
+use todo_client::{Priority, ToDoView};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+pub fn print(format: Format, todos: &[ToDoView]) -> anyhow::Result<()> {
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(todos)?),
+        Format::Table => print!("{}", table(todos)),
+    }
+    Ok(())
+}
+
+pub fn print_one(format: Format, todo: &ToDoView) -> anyhow::Result<()> {
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(todo)?),
+        Format::Table => print!("{}", table(std::slice::from_ref(todo))),
+    }
+    Ok(())
+}
+
+const HEADER: [&str; 5] = ["ID", "DONE", "DUE", "PRIORITY", "TEXT"];
+
+/// The todos one per line under a header, their columns aligned; the text
+/// comes last, so it isn't padded.
+fn table(todos: &[ToDoView]) -> String {
+    let rows: Vec<[String; 5]> = todos
+        .iter()
+        .map(|todo| {
+            [
+                todo.id.to_string(),
+                if todo.is_done { "x" } else { "" }.to_owned(),
+                todo.due_at
+                    .map(|due_at| due_at.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default(),
+                todo.priority.map(priority).unwrap_or_default().to_owned(),
+                todo.text.clone(),
+            ]
+        })
+        .collect();
+    let mut widths = HEADER.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    let header = HEADER.map(str::to_owned);
+    for row in std::iter::once(&header).chain(&rows) {
+        let (text, padded) = row.split_last().expect("rows have columns");
+        for (cell, width) in padded.iter().zip(widths) {
+            table.push_str(&format!("{cell:width$}  "));
+        }
+        table.push_str(text);
+        table.push('\n');
+    }
+    table
+}
+
+fn priority(priority: Priority) -> &'static str {
+    match priority {
+        Priority::Low => "low",
+        Priority::Medium => "medium",
+        Priority::High => "high",
+        Priority::Urgent => "urgent",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn todo(id: u128, text: &str, is_done: bool, priority: Option<&str>) -> ToDoView {
+        serde_json::from_value(json!({
+            "id": uuid::Uuid::from_u128(id),
+            "text": text,
+            "is_done": is_done,
+            "status": if is_done { "done" } else { "open" },
+            "start_at": null,
+            "due_at": if id == 1 { json!("2024-05-01T17:00:00Z") } else { json!(null) },
+            "expires_at": null,
+            "list_id": null,
+            "assignee_id": null,
+            "priority": priority,
+            "recurrence": null,
+            "tags": [],
+            "created_at": "2024-04-01T09:00:00Z",
+            "updated_at": "2024-04-01T09:00:00Z",
+            "etag": "\"1\"",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn no_todos_is_a_header() {
+        assert_eq!(table(&[]), "ID  DONE  DUE  PRIORITY  TEXT\n");
+    }
+
+    #[test]
+    fn columns_are_aligned() {
+        let todos = [
+            todo(1, "Pay rent", false, Some("urgent")),
+            todo(2, "Buy milk", true, None),
+        ];
+        assert_eq!(
+            table(&todos),
+            "\
+ID                                    DONE  DUE               PRIORITY  TEXT
+00000000-0000-0000-0000-000000000001        2024-05-01 17:00  urgent    Pay rent
+00000000-0000-0000-0000-000000000002  x                                 Buy milk
+"
+        );
+    }
+
+    #[test]
+    fn text_is_last_and_not_padded() {
+        let long = "Renew the passport before the summer holidays ".repeat(3);
+        let texts = [
+            "牛乳を買う",
+            "🥛 + 🍞",
+            "Café crème, déjà vu",
+            long.trim_end(),
+            "",
+        ];
+        let todos: Vec<_> = texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| todo(i as u128 + 2, text, false, Some("low")))
+            .collect();
+        let table = table(&todos);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), texts.len() + 1);
+        let text_at = lines[0].find("TEXT").unwrap();
+        for (line, text) in lines[1..].iter().zip(texts) {
+            assert_eq!(&line[text_at..], text.trim_end());
+            assert!(line[..text_at].is_ascii());
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! A typed client of the todo API, so Rust programs call it with the same
+//! request and response bodies the server has, re-exported from
+//! `todo_api_types`, rather than hand-rolled JSON.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), todo_client::Error> {
+//! use todo_client::{Client, CreateTodo, ListTodos};
+//!
+//! let client = Client::new("http://localhost:3000", "<token from POST /auth/login>");
+//! client.create_todo(&CreateTodo::new("Buy milk")).await?;
+//! let pending = ListTodos {
+//!     is_done: Some(false),
+//!     ..ListTodos::default()
+//! };
+//! for todo in client.list_todos(&pending).await?.items {
+//!     println!("{} {}", todo.id, todo.text);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use reqwest::{header, Method, RequestBuilder, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+pub use todo_api_types::*;
+
+/// Calls one server's `/api/v1` as one user.
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    /// The server's root, without `/api/v1`.
+    server: String,
+    token: String,
+}
+
+impl Client {
+    /// A client of the server at `server`, such as `https://todo.example.com`,
+    /// sending `token` as its bearer token.
+    pub fn new(server: impl Into<String>, token: impl Into<String>) -> Self {
+        Client::with_http(reqwest::Client::new(), server, token)
+    }
+
+    /// Like [`Client::new`], sending the requests with `http`, e.g. to set
+    /// timeouts or a proxy.
+    pub fn with_http(
+        http: reqwest::Client,
+        server: impl Into<String>,
+        token: impl Into<String>,
+    ) -> Self {
+        let server = server.into().trim_end_matches('/').to_owned();
+        Client {
+            http,
+            server,
+            token: token.into(),
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}/api/v1{path}", self.server))
+            .bearer_auth(&self.token)
+    }
+
+    /// `POST /todos`
+    pub async fn create_todo(&self, body: &CreateTodo) -> Result<ToDoView, Error> {
+        json(self.request(Method::POST, "/todos").json(body)).await
+    }
+
+    /// `POST /todos/bulk`, each todo failing on its own.
+    pub async fn create_todos(&self, body: &BulkCreate) -> Result<BulkResults, Error> {
+        json(self.request(Method::POST, "/todos/bulk").json(body)).await
+    }
+
+    /// `GET /todos`, one page of them; the next one is listed with the
+    /// page's `next_cursor` as `after`.
+    pub async fn list_todos(&self, query: &ListTodos) -> Result<TodoPage<ToDoView>, Error> {
+        json(self.request(Method::GET, "/todos").query(query)).await
+    }
+
+    /// `GET /todos/:id`
+    pub async fn get_todo(&self, id: uuid::Uuid) -> Result<ToDoView, Error> {
+        json(self.request(Method::GET, &format!("/todos/{id}"))).await
+    }
+
+    /// `PATCH /todos/:id`, if the todo is still at `etag`, the one it was
+    /// last read with.
+    pub async fn update_todo(
+        &self,
+        id: uuid::Uuid,
+        etag: &str,
+        body: &PatchTodo,
+    ) -> Result<ToDoView, Error> {
+        let request = self
+            .request(Method::PATCH, &format!("/todos/{id}"))
+            .header(header::IF_MATCH, etag)
+            .json(body);
+        json(request).await
+    }
+
+    /// `PUT /todos/:id`, marking the todo done or not, if it is still at
+    /// `etag`.
+    pub async fn put_todo(
+        &self,
+        id: uuid::Uuid,
+        etag: &str,
+        body: &PutTodo,
+    ) -> Result<ToDoView, Error> {
+        let request = self
+            .request(Method::PUT, &format!("/todos/{id}"))
+            .header(header::IF_MATCH, etag)
+            .json(body);
+        json(request).await
+    }
+
+    /// `POST /todos/bulk-complete`
+    pub async fn complete_todos(&self, body: &BulkComplete) -> Result<BulkResults, Error> {
+        json(
+            self.request(Method::POST, "/todos/bulk-complete")
+                .json(body),
+        )
+        .await
+    }
+
+    /// `DELETE /todos/:id`, into the trash.
+    pub async fn delete_todo(&self, id: uuid::Uuid) -> Result<(), Error> {
+        send(self.request(Method::DELETE, &format!("/todos/{id}"))).await?;
+        Ok(())
+    }
+}
+
+/// Why a call failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The server couldn't be reached, or answered with something that
+    /// isn't the API's.
+    Http(reqwest::Error),
+    /// The server refused the request, as its problem details say.
+    Api(Problem),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Http(err) => write!(f, "{err}"),
+            Error::Api(problem) => write!(f, "{} {}", problem.status, problem.detail),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(err) => Some(err),
+            Error::Api(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+/// The RFC 7807 problem details the server answers errors with.
+#[derive(Debug, Deserialize)]
+pub struct Problem {
+    pub status: u16,
+    pub detail: String,
+    /// Stable kind of the error to branch on, e.g. `precondition_failed`
+    /// when the todo changed since its `etag`.
+    pub code: String,
+    /// Where and why a malformed request failed.
+    #[serde(default)]
+    pub details: Option<serde_json::Value>,
+    /// To quote when reporting the problem.
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+/// Sends `request`, failing with the problem details of an error response.
+async fn send(request: RequestBuilder) -> Result<Response, Error> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let bytes = response.bytes().await?;
+    let problem = serde_json::from_slice(&bytes).unwrap_or_else(|_| Problem {
+        status: status.as_u16(),
+        detail: status.canonical_reason().unwrap_or_default().to_owned(),
+        code: code_for(status).to_owned(),
+        details: None,
+        request_id: None,
+    });
+    Err(Error::Api(problem))
+}
+
+async fn json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, Error> {
+    Ok(send(request).await?.json().await?)
+}
+
+/// The code of an error answered without problem details, e.g. by a proxy
+/// in front of the server.
+fn code_for(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        status if status.is_client_error() => "invalid_request",
+        _ => "internal",
+    }
+}
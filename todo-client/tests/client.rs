@@ -0,0 +1,245 @@
+//! The client against a stand-in server answering the way the API does.
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use serde_json::{json, Value};
+use todo_client::{Client, CreateTodo, Error, ListTodos, PutTodo};
+
+/// Serves `app` on a free port, answering the address to call it at.
+async fn serve(app: Router) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(app.into_make_service());
+    tokio::spawn(server);
+    format!("http://{addr}")
+}
+
+/// A todo as the server answers it, with `text`.
+fn todo(id: &str, text: &str) -> Value {
+    json!({
+        "id": id,
+        "text": text,
+        "is_done": false,
+        "status": "open",
+        "start_at": null,
+        "due_at": null,
+        "expires_at": null,
+        "list_id": null,
+        "priority": null,
+        "recurrence": null,
+        "tags": [],
+        "created_at": "2024-04-01T09:00:00Z",
+        "updated_at": "2024-04-01T09:00:00Z",
+        "position": 1.0,
+        "etag": "\"1\"",
+    })
+}
+
+const ID: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+fn api_error(err: Error) -> todo_client::Problem {
+    match err {
+        Error::Api(problem) => problem,
+        Error::Http(err) => panic!("not an API error: {err}"),
+    }
+}
+
+#[tokio::test]
+async fn calls_api_v1_with_the_bearer_token() {
+    // answers with the Authorization it got as the text
+    let app = Router::new().route(
+        "/api/v1/todos/:id",
+        get(|Path(id): Path<String>, headers: HeaderMap| async move {
+            let authorization = headers[header::AUTHORIZATION].to_str().unwrap();
+            Json(todo(&id, authorization))
+        }),
+    );
+    let server = serve(app).await;
+    for server in [server.clone(), format!("{server}/")] {
+        let client = Client::new(server, "secret");
+        let todo = client.get_todo(ID.parse().unwrap()).await.unwrap();
+        assert_eq!(todo.id.to_string(), ID);
+        assert_eq!(todo.text, "Bearer secret");
+    }
+}
+
+#[tokio::test]
+async fn sends_bodies_queries_and_if_match() {
+    let app = Router::new()
+        .route(
+            "/api/v1/todos",
+            get(|Query(query): Query<HashMap<String, String>>| async move {
+                let mut query: Vec<_> = query.into_iter().collect();
+                query.sort();
+                let text = format!("{query:?}");
+                Json(json!({
+                    "items": [todo(ID, &text)],
+                    "total": 1,
+                    "total_estimated": false,
+                    "next_cursor": "next",
+                }))
+            })
+            .post(|Json(body): Json<Value>| async move {
+                (StatusCode::CREATED, Json(todo(ID, &body.to_string())))
+            }),
+        )
+        .route(
+            "/api/v1/todos/:id",
+            axum::routing::put(|headers: HeaderMap, Json(body): Json<Value>| async move {
+                let if_match = headers[header::IF_MATCH].to_str().unwrap();
+                Json(todo(ID, &format!("{if_match} {body}")))
+            })
+            .delete(|| async { StatusCode::NO_CONTENT }),
+        );
+    let client = Client::new(serve(app).await, "secret");
+
+    let page = client
+        .list_todos(&ListTodos {
+            is_done: Some(false),
+            limit: Some(100),
+            after: Some("abc".to_owned()),
+            ..ListTodos::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        page.items[0].text,
+        r#"[("after", "abc"), ("is_done", "false"), ("limit", "100")]"#
+    );
+    assert_eq!(page.next_cursor.as_deref(), Some("next"));
+
+    let created = client
+        .create_todo(&CreateTodo::new("Buy milk"))
+        .await
+        .unwrap();
+    let body: Value = serde_json::from_str(&created.text).unwrap();
+    assert_eq!(body["text"], "Buy milk");
+    assert_eq!(body["due_at"], Value::Null);
+
+    let id = ID.parse().unwrap();
+    let put = client
+        .put_todo(id, "\"7\"", &PutTodo { is_done: true })
+        .await
+        .unwrap();
+    assert_eq!(put.text, r#""7" {"is_done":true}"#);
+    client.delete_todo(id).await.unwrap();
+}
+
+#[tokio::test]
+async fn maps_problem_details_to_api_errors() {
+    let app = Router::new()
+        .route(
+            "/api/v1/todos/:id",
+            get(|| async {
+                (
+                    StatusCode::PRECONDITION_FAILED,
+                    [(header::CONTENT_TYPE, "application/problem+json")],
+                    Json(json!({
+                        "type": "about:blank",
+                        "title": "Precondition Failed",
+                        "status": 412,
+                        "detail": "The todo changed since it was read",
+                        "code": "precondition_failed",
+                        "request_id": "req-1",
+                    })),
+                )
+            }),
+        )
+        .route(
+            "/api/v1/todos",
+            get(|| async {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "status": 400,
+                        "detail": "Invalid query",
+                        "code": "invalid_request",
+                        "details": [{"field": "limit", "reason": "must be between 1 and 100"}],
+                    })),
+                )
+            }),
+        );
+    let client = Client::new(serve(app).await, "secret");
+
+    let err = client.get_todo(ID.parse().unwrap()).await.unwrap_err();
+    assert_eq!(err.to_string(), "412 The todo changed since it was read");
+    let problem = api_error(err);
+    assert_eq!(problem.status, 412);
+    assert_eq!(problem.code, "precondition_failed");
+    assert_eq!(problem.request_id.as_deref(), Some("req-1"));
+    assert_eq!(problem.details, None);
+
+    let err = client.list_todos(&ListTodos::default()).await.unwrap_err();
+    let problem = api_error(err);
+    assert_eq!(problem.code, "invalid_request");
+    assert_eq!(problem.details.unwrap()[0]["field"], "limit");
+    assert_eq!(problem.request_id, None);
+}
+
+#[tokio::test]
+async fn errors_without_problem_details_get_a_code_from_their_status() {
+    let app = Router::new().route(
+        "/api/v1/todos/:id",
+        get(|Path(id): Path<String>| async move {
+            let status = match id.as_str() {
+                "00000000-0000-0000-0000-000000000001" => StatusCode::UNAUTHORIZED,
+                "00000000-0000-0000-0000-000000000002" => StatusCode::TOO_MANY_REQUESTS,
+                "00000000-0000-0000-0000-000000000003" => StatusCode::BAD_GATEWAY,
+                "00000000-0000-0000-0000-000000000004" => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (status, "<html>proxy error</html>").into_response()
+        }),
+    );
+    let client = Client::new(serve(app).await, "secret");
+    for (id, status, code) in [
+        (1, 401, "unauthorized"),
+        (2, 429, "rate_limited"),
+        (3, 502, "unavailable"),
+        (4, 409, "invalid_request"),
+        (5, 500, "internal"),
+    ] {
+        let err = client
+            .get_todo(uuid::Uuid::from_u128(id))
+            .await
+            .unwrap_err();
+        let problem = api_error(err);
+        assert_eq!(problem.status, status);
+        assert_eq!(problem.code, code);
+    }
+    // no route at all
+    let client = Client::new(serve(Router::new()).await, "secret");
+    let err = client
+        .complete_todos(&todo_client::BulkComplete { ids: Vec::new() })
+        .await
+        .unwrap_err();
+    let problem = api_error(err);
+    assert_eq!((problem.status, problem.code.as_str()), (404, "not_found"));
+    assert_eq!(problem.detail, "Not Found");
+}
+
+#[tokio::test]
+async fn unreachable_servers_and_other_bodies_are_http_errors() {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    // nothing listens there any more
+    let client = Client::new(format!("http://{addr}"), "secret");
+    let err = client.get_todo(ID.parse().unwrap()).await.unwrap_err();
+    assert!(matches!(err, Error::Http(_)), "{err}");
+
+    let app = Router::new().route("/api/v1/todos/:id", get(|| async { "not a todo" }));
+    let client = Client::new(serve(app).await, "secret");
+    let err = client.get_todo(ID.parse().unwrap()).await.unwrap_err();
+    assert!(matches!(err, Error::Http(_)), "{err}");
+}